@@ -0,0 +1,26 @@
+#![feature(test)]
+
+extern crate roll_api;
+extern crate test;
+
+use roll_api::die::DieType;
+use roll_api::parser::parse;
+use roll_api::roll::Roll;
+use roll_api::roller::Roller;
+use test::Bencher;
+
+#[bench]
+fn bench_roll_cup(b: &mut Bencher) {
+    let mut roller = Roller::seeded(7);
+    b.iter(|| roller.roll_cup(&[(DieType::D6, 2), (DieType::D8, 1), (DieType::D4, 3)]));
+}
+
+#[bench]
+fn bench_parse_and_roll(b: &mut Bencher) {
+    let mut roller = Roller::seeded(7);
+    b.iter(|| {
+        ["2d6", "1d8", "3d4"].iter()
+            .map(|notation| Roll::with_roller(parse(notation).unwrap().pop().unwrap(), &mut roller).value)
+            .sum::<i32>()
+    });
+}
@@ -0,0 +1,188 @@
+#![feature(test)]
+
+extern crate roll_api;
+extern crate test;
+
+use roll_api::format::{RollFormatter, SummaryFormatter, VerboseFormatter};
+use roll_api::locale::English;
+use roll_api::parser::parse;
+use roll_api::roll::Roll;
+use roll_api::roller::Roller;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use test::Bencher;
+
+/// Counts allocations, so the benches can compare the formatters before and after `format_into`
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The formatters as they were before `format_into`, building a string for every piece of the text
+mod before {
+    use roll_api::die::{Die, DieType};
+    use roll_api::locale::{Clause, English, Locale, Message};
+    use roll_api::roll::{ExplodeSuccessPolicy, Roll};
+
+    pub fn summary(roll: &Roll) -> String {
+        let dice: Vec<String> = roll.dice_in_notation_order().into_iter().map(|die| {
+            if die.taken.is_some() {
+                format!("took {}", die.value)
+            } else if die.is_dropped {
+                format!("~~{}~~", die.value)
+            } else {
+                die.value.to_string()
+            }
+        }).collect();
+        format!("{}: [{}] = {}", roll.equation, dice.join(", "), roll.value)
+    }
+
+    pub fn verbose(roll: &Roll) -> String {
+        let dice = roll.dice_in_notation_order().into_iter().map(|die| English.text(&Message::Die {
+            clauses: clauses(die),
+            value: die.value,
+        })).collect();
+        let (die, sides) = roll.dice.first().map_or((DieType::Other, 0), |die| (die.die, die.max));
+        let mut sentences = match roll.dice.first() {
+            Some(taken) if taken.taken.is_some() => vec![English.text(&Message::Took { value: taken.value })],
+            _ => vec![English.text(&Message::Rolled {
+                count: rolled_count(roll),
+                dice,
+                die,
+                sides,
+            })],
+        };
+
+        let modifier = roll.modifiers.iter().fold(0, |sum, modifier| sum + *modifier as i32);
+        if modifier != 0 {
+            sentences.push(English.text(&Message::Modifier(modifier)));
+        }
+        sentences.push(English.text(&Message::Total {
+            is_after_dropping: roll.dice.iter().any(|die| die.is_dropped && !die.is_rerolled),
+            value: roll.value,
+        }));
+
+        let kept_d20s: Vec<&Die> = roll.dice.iter()
+            .filter(|die| die.die == DieType::D20 && !die.is_dropped && die.taken.is_none())
+            .collect();
+        if kept_d20s.iter().any(|die| die.value == 20) {
+            sentences.push(English.text(&Message::CriticalHit));
+        } else if kept_d20s.iter().any(|die| die.value == 1) {
+            sentences.push(English.text(&Message::CriticalMiss));
+        }
+        sentences.join(" ")
+    }
+
+    fn clauses(die: &Die) -> Vec<Clause> {
+        let mut clauses = vec![];
+        if die.is_exploded {
+            clauses.push(Clause::Exploded);
+        }
+        if die.is_rerolled {
+            clauses.push(Clause::Rerolled);
+        } else if die.is_dropped {
+            clauses.push(Clause::Dropped);
+        }
+        clauses
+    }
+
+    fn rolled_count(roll: &Roll) -> usize {
+        let rerolls = roll.dice.iter().filter(|die| die.child.is_some()).count();
+        let explosions = match roll.explode_policy {
+            ExplodeSuccessPolicy::CompareEachDie => roll.dice.iter().filter(|die| die.is_exploded).count(),
+            ExplodeSuccessPolicy::CompoundThenCompare => 0,
+        };
+        roll.dice.len().saturating_sub(rerolls + explosions)
+    }
+}
+
+/// 1000 rolls of a typical attack, the kind of thing a bot formats all day
+fn rolls() -> Vec<Roll> {
+    let flags = parse("4d6kh3+2").unwrap().pop().unwrap();
+    let mut roller = Roller::seeded(3);
+    (0..1000).map(|_| Roll::with_roller(flags.clone(), &mut roller)).collect()
+}
+
+fn allocations<F: FnMut()>(mut format: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    format();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+#[bench]
+fn bench_summary_before(b: &mut Bencher) {
+    let rolls = rolls();
+    b.iter(|| for roll in &rolls {
+        test::black_box(before::summary(roll));
+    });
+}
+
+#[bench]
+fn bench_summary_into_one_buffer(b: &mut Bencher) {
+    let rolls = rolls();
+    let mut buffer = String::with_capacity(256);
+    let mut format = || for roll in &rolls {
+        buffer.clear();
+        SummaryFormatter.format_into(roll, &mut buffer).unwrap();
+        test::black_box(&buffer);
+    };
+
+    // Every roll allocated a string for each die, a list of them and the text they were joined into
+    let was = allocations(|| for roll in &rolls {
+        test::black_box(before::summary(roll));
+    });
+    let now = allocations(&mut format);
+    assert!(was >= 5 * rolls.len(), "formatting the old way allocated {} times", was);
+    assert_eq!(now, 0, "formatting into one buffer allocated {} times, down from {}", now, was);
+    b.iter(format);
+}
+
+#[bench]
+fn bench_verbose_before(b: &mut Bencher) {
+    let rolls = rolls();
+    b.iter(|| for roll in &rolls {
+        test::black_box(before::verbose(roll));
+    });
+}
+
+#[bench]
+fn bench_verbose_into_one_buffer(b: &mut Bencher) {
+    let rolls = rolls();
+    let verbose = VerboseFormatter::new(English);
+    let mut buffer = String::with_capacity(256);
+    let mut format = || for roll in &rolls {
+        buffer.clear();
+        verbose.format_into(roll, &mut buffer).unwrap();
+        test::black_box(&buffer);
+    };
+
+    // The same text, but without a string for each sentence or the list they were joined from
+    for roll in &rolls {
+        assert_eq!(verbose.format(roll), before::verbose(roll));
+        assert_eq!(SummaryFormatter.format(roll), before::summary(roll));
+    }
+    let was = allocations(|| for roll in &rolls {
+        test::black_box(before::verbose(roll));
+    });
+    let now = allocations(&mut format);
+    assert!(now < was, "formatting into one buffer allocated {} times, up from {}", now, was);
+    b.iter(format);
+}
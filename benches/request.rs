@@ -0,0 +1,65 @@
+#![feature(test)]
+
+extern crate roll_api;
+extern crate serde_json;
+extern crate test;
+
+use roll_api::request::{OwnedRollRequest, RollRequest};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use test::Bencher;
+
+/// Counts allocations, so the benches can check how many reading a batch makes
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A batch of 10k requests, each with an ID, notation, participant and comment
+fn batch() -> String {
+    let requests: Vec<String> = (0..10000).map(|i| {
+        format!(r#"{{"id": "r-{}", "notation": "4d6kh3+{}", "participant": "alice", "comment": "attack {}"}}"#, i, i % 5, i)
+    }).collect();
+    format!("[{}]", requests.join(","))
+}
+
+fn allocations<T, F: FnOnce() -> T>(read: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    test::black_box(read());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+#[bench]
+fn bench_read_borrowed_batch(b: &mut Bencher) {
+    let json = batch();
+    // Only the vector of requests is allocated, every string is borrowed
+    let borrowed = allocations(|| serde_json::from_str::<Vec<RollRequest>>(&json).unwrap());
+    assert!(borrowed < 100, "reading borrowed requests allocated {} times", borrowed);
+    b.iter(|| serde_json::from_str::<Vec<RollRequest>>(&json).unwrap().len());
+}
+
+#[bench]
+fn bench_read_owned_batch(b: &mut Bencher) {
+    let json = batch();
+    let read = || serde_json::from_str::<Vec<RollRequest>>(&json).unwrap()
+        .into_iter()
+        .map(RollRequest::into_owned)
+        .collect::<Vec<OwnedRollRequest>>();
+    // Every ID, notation, participant and comment is copied
+    let owned = allocations(&read);
+    assert!(owned >= 40000, "reading owned requests allocated {} times", owned);
+    b.iter(|| read().len());
+}
@@ -0,0 +1,50 @@
+#![feature(test)]
+
+extern crate roll_api;
+extern crate test;
+
+use roll_api::parser::parse;
+use roll_api::roll::Roll;
+use roll_api::roller::{DiceRoller, RollOptions, Roller, ThreadRoller};
+use std::time::Duration;
+use test::Bencher;
+
+#[bench]
+fn bench_roll_directly(b: &mut Bencher) {
+    let flags = parse("4d6kh3+2").unwrap().pop().unwrap();
+    b.iter(|| Roll::with_roller(flags.clone(), &mut Roller::new()).value);
+}
+
+#[bench]
+fn bench_roll_through_the_engine_trait(b: &mut Bencher) {
+    let flags = parse("4d6kh3+2").unwrap().pop().unwrap();
+    let engine: Box<dyn DiceRoller> = Box::new(ThreadRoller);
+    b.iter(|| engine.roll(&flags).unwrap().value);
+}
+
+#[bench]
+fn bench_roll_with_a_deadline(b: &mut Bencher) {
+    let flags = parse("4d6kh3+2").unwrap().pop().unwrap();
+    let options = RollOptions::new().deadline(Duration::from_secs(60));
+    b.iter(|| Roll::try_with_roller(flags.clone(), &mut Roller::new().with_options(&options)).unwrap().value);
+}
+
+#[bench]
+fn bench_roll_untimed(b: &mut Bencher) {
+    let flags = parse("8d6!rr1kh3").unwrap().pop().unwrap();
+    b.iter(|| Roll::with_roller(flags.clone(), &mut Roller::seeded(7)).value);
+}
+
+#[bench]
+fn bench_roll_timed(b: &mut Bencher) {
+    let flags = parse("8d6!rr1kh3").unwrap().pop().unwrap();
+    let options = RollOptions::new().capture_timing();
+    b.iter(|| Roll::with_roller(flags.clone(), &mut Roller::seeded(7).with_options(&options)).value);
+}
+
+#[bench]
+fn bench_roll_custom_sides(b: &mut Bencher) {
+    let flags = parse("20d[1,1,2,3,5,8]dh2dl2").unwrap().pop().unwrap();
+    let mut roller = Roller::seeded(7);
+    b.iter(|| Roll::with_roller(flags.clone(), &mut roller).value);
+}
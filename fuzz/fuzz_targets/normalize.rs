@@ -0,0 +1,15 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate roll_api;
+
+use roll_api::parser::{normalize_notation, ParseOptions};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(notation) = ::std::str::from_utf8(data) {
+        // Reading lookalikes as ASCII twice changes nothing the second time
+        if let Ok(normalized) = normalize_notation(notation, &ParseOptions::new()) {
+            assert_eq!(normalize_notation(&normalized, &ParseOptions::new()).ok(), Some(normalized.clone()));
+        }
+        let _ = normalize_notation(notation, &ParseOptions::new().strict());
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate roll_api;
+
+use roll_api::parser::{parse_notation_with, ParseOptions};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(notation) = ::std::str::from_utf8(data) {
+        let _ = parse_notation_with(notation, &ParseOptions::new());
+        let _ = parse_notation_with(notation, &ParseOptions::new().strict());
+    }
+});
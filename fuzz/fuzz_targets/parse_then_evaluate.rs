@@ -0,0 +1,26 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate roll_api;
+
+use roll_api::limits::Limits;
+use roll_api::request::RollRequest;
+use roll_api::roller::Roller;
+
+fuzz_target!(|data: &[u8]| {
+    // The first bytes are the dice, as if rolled by hand, and the rest after a newline is notation
+    let split = data.iter().position(|&b| b == b'\n').unwrap_or(0);
+    let (values, notation) = data.split_at(split);
+    let notation = match ::std::str::from_utf8(notation) {
+        Ok(notation) => notation.trim_start_matches('\n'),
+        Err(_) => return,
+    };
+
+    // The same limits the server rolls with, so the fuzzer reaches whatever a request can
+    let request = RollRequest::new(notation);
+    let seed = values.iter().fold(0u64, |seed, &b| seed.wrapping_mul(31).wrapping_add(b as u64));
+    let values = values.iter().map(|&b| b as i8 as i16).collect();
+    let _ = request.roll(&Limits::default(), &mut Roller::manual(values));
+
+    // Hand-rolled dice never draw from a range, so the same roll is made again drawing its dice
+    let _ = request.roll(&Limits::default(), &mut Roller::seeded(seed));
+});
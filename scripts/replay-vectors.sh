@@ -0,0 +1,23 @@
+#!/bin/sh
+# Check seeds roll the same dice on every target, see tests/replay_vectors.rs
+#
+# Needs the i686 target (`rustup target add i686-unknown-linux-gnu`, and gcc-multilib to link) and
+# wasm-pack. The library is built without the `server` feature, as Rocket doesn't build for WASM,
+# so every test is run that way first to catch anything that still leans on Rocket.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+features="--no-default-features --features std"
+
+echo "library without the server"
+cargo test $features
+
+echo "replay vectors: native"
+cargo test $features --test replay_vectors
+
+echo "replay vectors: i686-unknown-linux-gnu"
+cargo test $features --test replay_vectors --target i686-unknown-linux-gnu
+
+echo "replay vectors: wasm32-unknown-unknown"
+wasm-pack test --node -- $features --test replay_vectors
@@ -0,0 +1,243 @@
+use limits::Limits;
+use request::OwnedRollRequest;
+use roll::{Roll, RollError};
+use roller::{CancellationToken, RollOptions, Roller};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinHandle};
+#[cfg(test)]
+use error::{ApiError, ErrorCode};
+#[cfg(test)]
+use std::sync::atomic::AtomicBool;
+#[cfg(test)]
+use std::thread;
+#[cfg(test)]
+use std::time::{Duration, Instant};
+#[cfg(test)]
+use tokio::runtime::{Builder, Runtime};
+
+type Job<T> = Box<dyn FnOnce(&mut Roller) -> Result<T, RollError> + Send>;
+type Permit = Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+/// Rolls on tokio's blocking threads, so big rolls don't hold up the threads serving requests
+///
+/// At most `max_concurrent` rolls are made at once, with up to `max_queued` more waiting their
+/// turn. Past that, rolls fail straight away with `RollError::Saturated`, which servers answer with
+/// a 503. Dropping a roll's future cancels it, so a roll nobody is waiting for stops at its next
+/// check of the roller instead of running to the end.
+#[derive(Clone)]
+pub struct AsyncRoller {
+    in_flight: Arc<AtomicUsize>,
+    limits: Limits,
+    max_in_flight: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl AsyncRoller {
+    pub fn new(limits: Limits, max_concurrent: usize, max_queued: usize) -> AsyncRoller {
+        AsyncRoller {
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            limits,
+            max_in_flight: max_concurrent + max_queued,
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+        }
+    }
+
+    /// Number of rolls being made or waiting to be
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Roll a request checked against the limits, the same as `RollRequest::roll`
+    pub fn roll_async(&self, request: OwnedRollRequest) -> RollFuture<Roll> {
+        let limits = self.limits.clone();
+        self.run(move |roller| request.roll(&limits, roller))
+    }
+
+    /// Run anything that rolls, e.g. a simulation, with a roller that stops once the future is
+    /// dropped
+    pub fn run<T, F>(&self, job: F) -> RollFuture<T>
+        where T: Send + 'static, F: FnOnce(&mut Roller) -> Result<T, RollError> + Send + 'static {
+        let token = CancellationToken::new();
+        if self.in_flight.fetch_add(1, Ordering::SeqCst) >= self.max_in_flight {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            return RollFuture {
+                state: State::Failed(Some(RollError::Saturated)),
+                token,
+            };
+        }
+
+        let slot = Slot(self.in_flight.clone());
+        RollFuture {
+            state: State::Queued(Box::pin(self.permits.clone().acquire_owned()), Some((Box::new(job), slot))),
+            token,
+        }
+    }
+}
+
+/// A place among the rolls in flight, given up when the roll is done or abandoned
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+enum State<T> {
+    Done,
+    Failed(Option<RollError>),
+    Queued(Permit, Option<(Job<T>, Slot)>),
+    Running(JoinHandle<Result<T, RollError>>),
+}
+
+/// A roll made by an `AsyncRoller`, cancelled when dropped before it's done
+pub struct RollFuture<T> {
+    state: State<T>,
+    token: CancellationToken,
+}
+
+impl<T: Send + 'static> Future for RollFuture<T> {
+    type Output = Result<T, RollError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, RollError>> {
+        let this = self.get_mut();
+        loop {
+            let next = match this.state {
+                State::Done => panic!("a roll future was polled after it was done"),
+                State::Failed(ref mut error) => {
+                    let error = error.take().expect("a failed roll is only handed out once");
+                    this.state = State::Done;
+                    return Poll::Ready(Err(error));
+                },
+                State::Queued(ref mut permit, ref mut job) => {
+                    let permit = match permit.as_mut().poll(cx) {
+                        Poll::Ready(Ok(permit)) => permit,
+                        Poll::Ready(Err(_)) => {
+                            this.state = State::Done;
+                            return Poll::Ready(Err(RollError::Cancelled));
+                        },
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    let (job, slot) = job.take().expect("a queued roll is only started once");
+                    let options = RollOptions::new().cancel_with(this.token.clone());
+                    State::Running(task::spawn_blocking(move || {
+                        let _permit = permit;
+                        let _slot = slot;
+                        job(&mut Roller::new().with_options(&options))
+                    }))
+                },
+                State::Running(ref mut handle) => {
+                    let result = match Pin::new(handle).poll(cx) {
+                        Poll::Ready(Ok(result)) => result,
+                        Poll::Ready(Err(error)) => {
+                            if error.is_panic() {
+                                ::std::panic::resume_unwind(error.into_panic());
+                            }
+                            Err(RollError::Cancelled)
+                        },
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    this.state = State::Done;
+                    return Poll::Ready(result);
+                },
+            };
+            this.state = next;
+        }
+    }
+}
+
+impl<T> Drop for RollFuture<T> {
+    fn drop(&mut self) {
+        match self.state {
+            State::Done | State::Failed(_) => {},
+            _ => self.token.cancel(),
+        }
+    }
+}
+
+#[cfg(test)]
+fn runtime() -> Runtime {
+    Builder::new_multi_thread().worker_threads(4).enable_all().build().unwrap()
+}
+
+#[test]
+fn it_rolls_requests_concurrently() {
+    let runtime = runtime();
+    let roller = AsyncRoller::new(Limits::default(), 4, 16);
+    let request = |seed: u64| OwnedRollRequest {
+        seed: Some(seed),
+        ..OwnedRollRequest::new("4d6kh3")
+    };
+
+    let handles: Vec<_> = (0..10).map(|seed| runtime.spawn(roller.roll_async(request(seed)))).collect();
+    for (seed, handle) in handles.into_iter().enumerate() {
+        let roll = runtime.block_on(handle).unwrap().unwrap();
+        let expected = request(seed as u64).roll(&Limits::default(), &mut Roller::new()).unwrap();
+        assert_eq!(roll.value, expected.value);
+    }
+    assert_eq!(roller.in_flight(), 0);
+
+    // Rolls are checked against the limits the same as anywhere else
+    let too_many = runtime.block_on(roller.roll_async(OwnedRollRequest::new("1000d6")));
+    assert_eq!(too_many.err(), Some(RollError::TooManyDice(1000, 100)));
+}
+
+#[test]
+fn it_turns_rolls_away_when_saturated() {
+    let runtime = runtime();
+    let roller = AsyncRoller::new(Limits::default(), 1, 1);
+    let running = roller.run(|roller: &mut Roller| Ok(roller.between(1, 20)));
+    let queued = roller.run(|roller: &mut Roller| Ok(roller.between(1, 20)));
+    assert_eq!(roller.in_flight(), 2);
+
+    let error = runtime.block_on(roller.roll_async(OwnedRollRequest::new("1d20"))).unwrap_err();
+    assert_eq!(error, RollError::Saturated);
+    assert_eq!(ApiError::from(error).code, ErrorCode::Unavailable);
+
+    // Once the others are done or given up on, there's room again
+    assert!(runtime.block_on(running).is_ok());
+    drop(queued);
+    assert_eq!(roller.in_flight(), 0);
+    assert!(runtime.block_on(roller.roll_async(OwnedRollRequest::new("1d20"))).is_ok());
+}
+
+#[test]
+fn it_cancels_rolls_when_their_future_is_dropped() {
+    let runtime = runtime();
+    let roller = AsyncRoller::new(Limits::default(), 1, 0);
+    let stopped = Arc::new(AtomicBool::new(false));
+    let stopped_in_job = stopped.clone();
+
+    // A slow simulation that only stops when its roller is cancelled
+    let slow = roller.run(move |roller: &mut Roller| {
+        while !roller.should_stop() {
+            roller.between(1, 6);
+            thread::sleep(Duration::from_millis(1));
+        }
+        stopped_in_job.store(true, Ordering::SeqCst);
+        roller.stopped()
+    });
+    let timed_out = runtime.block_on(async_timeout(slow));
+    assert!(timed_out.is_err());
+
+    let started = Instant::now();
+    while !stopped.load(Ordering::SeqCst) && started.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert!(stopped.load(Ordering::SeqCst));
+    while roller.in_flight() > 0 && started.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(roller.in_flight(), 0);
+}
+
+/// Give up on the roll after 50ms, dropping its future
+#[cfg(test)]
+fn async_timeout(roll: RollFuture<()>) -> ::tokio::time::Timeout<RollFuture<()>> {
+    ::tokio::time::timeout(Duration::from_millis(50), roll)
+}
@@ -0,0 +1,509 @@
+use drand_verify::{G1Pubkey, G2PubkeyRfc, Pubkey};
+use hmac::{Hmac, Mac};
+use http::{self, HttpError, HttpOptions, Response, Scheme, Url};
+use roll::{BeaconDraw, Roll, RollError, RollFlags};
+use roller::Roller;
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+#[cfg(test)]
+use parser::parse;
+
+/// A round of randomness published by a beacon
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BeaconValue {
+    /// The previous round's signature as lowercase hex, which chained drand rounds sign along with
+    /// their own, empty for unchained ones
+    #[serde(default)]
+    pub previous_signature: String,
+
+    /// The round's 32 bytes of randomness as lowercase hex
+    pub randomness: String,
+
+    pub round: u64,
+
+    /// The beacon's signature over the round as lowercase hex, see `DrandBeacon`
+    #[serde(default)]
+    pub signature: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BeaconError {
+    /// The round was published before the roll was announced, so its dice could already be known
+    AlreadyPublished(u64),
+
+    /// The beacon answered with something other than a round, with why
+    InvalidResponse(String),
+
+    /// The public key isn't hex, or isn't a drand chain's 48 or 96 bytes
+    InvalidPublicKey,
+
+    /// The round's signature isn't the chain's, or its randomness isn't the hash of it
+    InvalidSignature(u64),
+
+    /// The beacon's URL couldn't be understood, or isn't an `https://` one
+    InvalidUrl,
+
+    /// The request couldn't be sent or the response couldn't be read
+    Io(String),
+
+    /// The flags or participant aren't the ones the roll was announced with
+    NotAnnounced,
+
+    /// The round hasn't been published yet, so nothing can be rolled with it
+    NotPublished(u64),
+
+    /// The round was fetched, but the roll couldn't be made
+    Roll(RollError),
+
+    /// The beacon answered with a status other than 2xx
+    Status(u16),
+}
+
+impl From<RollError> for BeaconError {
+    fn from(error: RollError) -> BeaconError {
+        BeaconError::Roll(error)
+    }
+}
+
+impl From<HttpError> for BeaconError {
+    fn from(error: HttpError) -> BeaconError {
+        match error {
+            HttpError::InvalidUrl | HttpError::UnsupportedScheme => BeaconError::InvalidUrl,
+            HttpError::InvalidResponse(why) => BeaconError::InvalidResponse(why),
+            HttpError::Io(why) => BeaconError::Io(why),
+            HttpError::PrivateHost(host) => BeaconError::Io(format!("{} can't be reached", host)),
+        }
+    }
+}
+
+/// A public source of randomness published in numbered rounds, e.g. drand
+pub trait EntropyBeacon {
+    /// The round's randomness, failing with `BeaconError::NotPublished` until the beacon has published it
+    fn fetch_round(&self, round: u64) -> Result<BeaconValue, BeaconError>;
+}
+
+/// Rounds of a drand chain, fetched from a relay's HTTP API and checked against the chain's key
+///
+/// The URL is the chain's, e.g. `https://api.drand.sh/<chain hash>`, with rounds fetched from
+/// `/public/<round>` under it. Only `https://` relays are supported, and only public addresses are
+/// connected to. The public key is the chain's, from its `/info`: 48 bytes for a chained chain like
+/// drand's default one, or 96 for an unchained one like quicknet. Every round fetched has its
+/// signature checked against the key, and its randomness checked to be the hash of the signature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrandBeacon {
+    options: HttpOptions,
+    public_key: Vec<u8>,
+    url: Url,
+}
+
+impl DrandBeacon {
+    pub fn new(url: &str, public_key: &str) -> Result<DrandBeacon, BeaconError> {
+        let url = Url::parse(url)?;
+        if url.scheme != Scheme::Https {
+            return Err(BeaconError::InvalidUrl);
+        }
+        let public_key = from_hex(public_key)
+            .filter(|key| key.len() == 48 || key.len() == 96)
+            .ok_or(BeaconError::InvalidPublicKey)?;
+        Ok(DrandBeacon {
+            options: HttpOptions {
+                public_only: true,
+                ..HttpOptions::default()
+            },
+            public_key,
+            url,
+        })
+    }
+
+    /// Connect and wait the way the options say, e.g. with longer timeouts
+    pub fn with_options(mut self, options: HttpOptions) -> DrandBeacon {
+        self.options = options;
+        self
+    }
+}
+
+impl EntropyBeacon for DrandBeacon {
+    fn fetch_round(&self, round: u64) -> Result<BeaconValue, BeaconError> {
+        let target = format!("{}/public/{}", self.url.path.trim_end_matches('/'), round);
+        let response = http::request(&self.url, "GET", &target, &[("Accept", "application/json")], b"", &self.options)?;
+        let value = read_round(round, &response)?;
+        verify_signature(&self.public_key, &value)?;
+        Ok(value)
+    }
+}
+
+/// The round in a drand relay's response
+///
+/// Relays answer rounds still to come with a 404, or a 425 Too Early.
+fn read_round(round: u64, response: &Response) -> Result<BeaconValue, BeaconError> {
+    match response.status {
+        404 | 425 => return Err(BeaconError::NotPublished(round)),
+        _ if !response.is_success() => return Err(BeaconError::Status(response.status)),
+        _ => {},
+    }
+
+    let value: BeaconValue = serde_json::from_str(response.text()?)
+        .map_err(|error| BeaconError::InvalidResponse(error.to_string()))?;
+    if value.round != round {
+        return Err(BeaconError::InvalidResponse(format!("asked for round {} but got round {}", round, value.round)));
+    }
+    randomness(&value)?;
+    Ok(value)
+}
+
+/// Check the round was signed with the chain's key, and its randomness is the SHA-256 of the signature
+///
+/// Chained rounds sign the previous round's signature along with their number, and unchained ones
+/// only their number, which `drand_verify` hashes the way each kind of chain does.
+fn verify_signature(public_key: &[u8], value: &BeaconValue) -> Result<(), BeaconError> {
+    let invalid = BeaconError::InvalidSignature(value.round);
+    let signature = match from_hex(&value.signature) {
+        Some(signature) => signature,
+        None => return Err(invalid),
+    };
+    if Sha256::digest(&signature).as_slice() != randomness(value)?.as_slice() {
+        return Err(invalid);
+    }
+
+    let is_valid = match public_key.len() {
+        48 => {
+            let previous = from_hex(&value.previous_signature).ok_or(BeaconError::InvalidSignature(value.round))?;
+            G1Pubkey::from_variable(public_key).map_err(|_| BeaconError::InvalidPublicKey)?
+                .verify(value.round, &previous, &signature)
+        },
+        96 => {
+            G2PubkeyRfc::from_variable(public_key).map_err(|_| BeaconError::InvalidPublicKey)?
+                .verify(value.round, b"", &signature)
+        },
+        _ => return Err(BeaconError::InvalidPublicKey),
+    };
+    match is_valid {
+        Ok(true) => Ok(()),
+        _ => Err(invalid),
+    }
+}
+
+/// A beacon whose rounds are published by hand, for tests of code that rolls with a beacon
+///
+/// Its rounds aren't signed, as nothing checks them.
+#[derive(Clone, Debug, Default)]
+pub struct MockBeacon {
+    rounds: BTreeMap<u64, BeaconValue>,
+}
+
+impl MockBeacon {
+    pub fn new() -> MockBeacon {
+        MockBeacon::default()
+    }
+
+    /// Publish the round with the randomness, given as lowercase hex
+    pub fn publish(&mut self, round: u64, randomness: &str) {
+        self.rounds.insert(round, BeaconValue {
+            previous_signature: String::new(),
+            randomness: randomness.to_string(),
+            round,
+            signature: String::new(),
+        });
+    }
+}
+
+impl EntropyBeacon for MockBeacon {
+    fn fetch_round(&self, round: u64) -> Result<BeaconValue, BeaconError> {
+        self.rounds.get(&round).cloned().ok_or(BeaconError::NotPublished(round))
+    }
+}
+
+/// A roll promised to a round before the beacon published it, see `announce`
+///
+/// Show it to everyone at the table when it's made, e.g. by posting it to the room, so they know
+/// the round and request were picked before anyone could know the dice.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Announcement {
+    pub participant: String,
+
+    /// Hash of the flags, participant and round, see `request_hash`
+    pub request_hash: String,
+
+    pub round: u64,
+}
+
+/// Hash a request for a round, as lowercase hex
+///
+/// The hash is SHA-256 over the flags' `canonical` form, the participant and the round, each on a
+/// line of its own, so rolls that behave the same hash the same. Changing any of this breaks
+/// verification of every roll made with a beacon before the change.
+pub fn request_hash(flags: &RollFlags, participant: &str, round: u64) -> String {
+    let request = format!("{}\n{}\n{}", flags.canonical(), participant, round);
+    Sha256::digest(request.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Promise the participant a roll of the flags from a round the beacon hasn't published yet
+///
+/// This is the first of the two steps, made when the request comes in, and the second is
+/// `roll_with_beacon` once the round is out. Pick a round that's still to come, e.g. the next
+/// one. A round that's already published is refused with `BeaconError::AlreadyPublished`, as
+/// its dice could be known before the announcement was made.
+pub fn announce(beacon: &dyn EntropyBeacon, round: u64, flags: &RollFlags, participant: &str)
+                -> Result<Announcement, BeaconError> {
+    flags.validate()?;
+    match beacon.fetch_round(round) {
+        Err(BeaconError::NotPublished(_)) => {},
+        Ok(_) => return Err(BeaconError::AlreadyPublished(round)),
+        Err(error) => return Err(error),
+    }
+    Ok(Announcement {
+        participant: participant.to_string(),
+        request_hash: request_hash(flags, participant, round),
+        round,
+    })
+}
+
+/// Derive the seed for a request from a round
+///
+/// The seed is HMAC-SHA256 keyed with the round's 32 bytes of randomness over the request hash as
+/// it's written, and the roller reads it the same as a derived seed, see `Roller::keyed`. Changing
+/// any of this breaks verification of every roll made with a beacon before the change.
+pub fn seed(value: &BeaconValue, request_hash: &str) -> Result<[u8; 32], BeaconError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&randomness(value)?).expect("HMAC can take a key of any size");
+    mac.update(request_hash.as_bytes());
+
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&mac.finalize().into_bytes());
+    Ok(seed)
+}
+
+/// Roll the announced request with the seed of its round, once the beacon has published it
+///
+/// The flags have to be the ones announced, or the roll is refused with
+/// `BeaconError::NotAnnounced`, and until the beacon publishes the round it's refused with
+/// `BeaconError::NotPublished`. The round, its randomness and the request are kept on the roll as
+/// its `beacon`, so anyone can roll it again with `verify_roll`.
+pub fn roll_with_beacon(beacon: &dyn EntropyBeacon, announcement: &Announcement, flags: RollFlags)
+                        -> Result<Roll, BeaconError> {
+    if request_hash(&flags, &announcement.participant, announcement.round) != announcement.request_hash {
+        return Err(BeaconError::NotAnnounced);
+    }
+    let value = beacon.fetch_round(announcement.round)?;
+    let seed = seed(&value, &announcement.request_hash)?;
+    flags.validate()?;
+
+    let mut roll = Roll::try_with_roller(flags, &mut Roller::keyed(seed))?;
+    roll.beacon = Some(BeaconDraw {
+        participant: announcement.participant.clone(),
+        randomness: value.randomness,
+        request_hash: announcement.request_hash.clone(),
+        round: announcement.round,
+    });
+    Ok(roll)
+}
+
+/// Roll again from the round as the beacon published it, checking the roll came out the same
+///
+/// Fetch the round from the beacon itself rather than trusting the randomness kept on the roll.
+/// The request hash is worked out again from the flags, so the roll has to have been made from
+/// them, and every die and every total has to match.
+pub fn verify_roll(value: &BeaconValue, flags: RollFlags, roll: &Roll) -> bool {
+    let draw = match roll.beacon {
+        Some(ref draw) if draw.round == value.round && draw.randomness == value.randomness => draw,
+        _ => return false,
+    };
+    if request_hash(&flags, &draw.participant, draw.round) != draw.request_hash {
+        return false;
+    }
+    let seed = match seed(value, &draw.request_hash) {
+        Ok(seed) => seed,
+        Err(_) => return false,
+    };
+    let replayed = Roll::with_roller(flags, &mut Roller::keyed(seed));
+
+    replayed.dice.len() == roll.dice.len() &&
+        replayed.dice.iter().zip(roll.dice.iter()).all(|(a, b)| a.value == b.value && a.is_dropped == b.is_dropped) &&
+        (replayed.natural_value, replayed.raw_value, replayed.value) == (roll.natural_value, roll.raw_value, roll.value)
+}
+
+/// The round's randomness as bytes, which has to be 32 of them
+fn randomness(value: &BeaconValue) -> Result<Vec<u8>, BeaconError> {
+    from_hex(&value.randomness)
+        .filter(|bytes| bytes.len() == 32)
+        .ok_or_else(|| BeaconError::InvalidResponse(format!("round {} has no 32 bytes of randomness", value.round)))
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+const RANDOMNESS: &str = "101297f1ca7dc44ef6088d94ad5fb7ba03455dc33d53ddb412bbc4564ed986ec";
+
+/// Public key of drand's quicknet chain, which is unchained
+#[cfg(test)]
+const QUICKNET_KEY: &str = "83cf0f2896adee7eb8b5f01fcad3912212c437e0073e911fb90022d3e760183c8c4b450b6a0a6c3ac6a5776a2d1064510d1fec758c921cc22b0e17e63aaf4bcb5ed66304de9cf809bd274ca73bab4af5a6e9c76a4bc09e76eae8991ef5ece45a";
+
+#[cfg(test)]
+fn flags() -> RollFlags {
+    parse("4d20+2").unwrap().pop().unwrap()
+}
+
+#[test]
+fn it_refuses_to_roll_before_the_round_is_published() {
+    let mut beacon = MockBeacon::new();
+    let announcement = announce(&beacon, 1000, &flags(), "ana").unwrap();
+    assert_eq!(announcement.request_hash, request_hash(&flags(), "ana", 1000));
+    let rolled = roll_with_beacon(&beacon, &announcement, flags());
+    assert_eq!(rolled.err(), Some(BeaconError::NotPublished(1000)));
+
+    beacon.publish(1000, RANDOMNESS);
+    let roll = roll_with_beacon(&beacon, &announcement, flags()).unwrap();
+    assert_eq!(roll.beacon, Some(BeaconDraw {
+        participant: "ana".to_string(),
+        randomness: RANDOMNESS.to_string(),
+        request_hash: announcement.request_hash.clone(),
+        round: 1000,
+    }));
+
+    let announcement = announce(&beacon, 1001, &flags(), "ana").unwrap();
+    beacon.publish(1001, "not hex");
+    assert!(match roll_with_beacon(&beacon, &announcement, flags()) {
+        Err(BeaconError::InvalidResponse(_)) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn it_refuses_to_announce_a_published_round() {
+    let mut beacon = MockBeacon::new();
+    beacon.publish(7, RANDOMNESS);
+    assert_eq!(announce(&beacon, 7, &flags(), "ana").err(), Some(BeaconError::AlreadyPublished(7)));
+    assert!(announce(&beacon, 8, &flags(), "ana").is_ok());
+}
+
+#[test]
+fn it_only_rolls_what_was_announced() {
+    let mut beacon = MockBeacon::new();
+    let announcement = announce(&beacon, 7, &flags(), "ana").unwrap();
+    beacon.publish(7, RANDOMNESS);
+
+    let other = parse("4d20+3").unwrap().pop().unwrap();
+    assert_eq!(roll_with_beacon(&beacon, &announcement, other).err(), Some(BeaconError::NotAnnounced));
+    let claimed = Announcement {
+        participant: "bo".to_string(),
+        ..announcement.clone()
+    };
+    assert_eq!(roll_with_beacon(&beacon, &claimed, flags()).err(), Some(BeaconError::NotAnnounced));
+
+    // Notation written another way that rolls the same is the same request
+    let spelled = parse("4d20 + 1 + 1").unwrap().pop().unwrap();
+    assert!(roll_with_beacon(&beacon, &announcement, spelled).is_ok());
+}
+
+#[test]
+fn it_derives_the_same_dice_from_the_same_round_and_request() {
+    let mut beacon = MockBeacon::new();
+    beacon.publish(7, RANDOMNESS);
+    let dice = |participant: &str| -> Vec<i16> {
+        let announcement = Announcement {
+            participant: participant.to_string(),
+            request_hash: request_hash(&flags(), participant, 7),
+            round: 7,
+        };
+        roll_with_beacon(&beacon, &announcement, flags()).unwrap().dice.iter().map(|die| die.value).collect()
+    };
+    assert_eq!(dice("ana"), dice("ana"));
+    assert_ne!(dice("ana"), dice("bo"));
+
+    // The seed is pinned, so rolls made before a change can still be verified
+    let value = beacon.fetch_round(7).unwrap();
+    let hex: String = seed(&value, "first").unwrap().iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(hex, "f9287781e6fdcd4be9c20779cdfccf15b4c3bab0510554505c48ae0883b718b2");
+    let keyed: Vec<i16> = Roll::with_roller(flags(), &mut Roller::keyed(seed(&value, &request_hash(&flags(), "ana", 7)).unwrap()))
+        .dice.iter().map(|die| die.value).collect();
+    assert_eq!(keyed, dice("ana"));
+}
+
+#[test]
+fn it_verifies_rolls_from_the_published_round() {
+    let mut beacon = MockBeacon::new();
+    let announcement = announce(&beacon, 7, &flags(), "ana").unwrap();
+    beacon.publish(7, RANDOMNESS);
+    let roll = roll_with_beacon(&beacon, &announcement, flags()).unwrap();
+    let value = beacon.fetch_round(7).unwrap();
+    assert!(verify_roll(&value, flags(), &roll));
+
+    let mut edited = roll.clone();
+    edited.dice[0].value = if edited.dice[0].value == 20 { 19 } else { 20 };
+    assert!(!verify_roll(&value, flags(), &edited));
+
+    // The dice are the same, but the total isn't what they come to
+    let mut edited = roll.clone();
+    edited.value += 1;
+    assert!(!verify_roll(&value, flags(), &edited));
+
+    // Other flags would have been another request, with dice of their own
+    assert!(!verify_roll(&value, parse("4d20+3").unwrap().pop().unwrap(), &roll));
+
+    let mut other = MockBeacon::new();
+    other.publish(7, &RANDOMNESS.replace("1", "2"));
+    assert!(!verify_roll(&other.fetch_round(7).unwrap(), flags(), &roll));
+    assert!(!verify_roll(&value, flags(), &Roll::with_roller(flags(), &mut Roller::seeded(7))));
+}
+
+#[test]
+fn it_reads_rounds_from_a_drand_relay() {
+    let body = format!(r#"{{"round":367,"randomness":"{}","signature":"b0c4","previous_signature":"a1"}}"#, RANDOMNESS);
+    let response = |status: &str, body: &str| {
+        http::parse_response(format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\n\r\n{}", status, body).as_bytes()).unwrap()
+    };
+    let value = read_round(367, &response("200 OK", &body)).unwrap();
+    assert_eq!((value.round, value.randomness.as_str(), value.signature.as_str()), (367, RANDOMNESS, "b0c4"));
+    assert_eq!(value.previous_signature, "a1");
+
+    assert_eq!(read_round(368, &response("425 Too Early", "")).err(), Some(BeaconError::NotPublished(368)));
+    assert_eq!(read_round(368, &response("404 Not Found", "")).err(), Some(BeaconError::NotPublished(368)));
+    assert_eq!(read_round(368, &response("503 Service Unavailable", "")).err(), Some(BeaconError::Status(503)));
+    assert!(read_round(368, &response("200 OK", &body)).is_err());
+}
+
+#[test]
+fn it_only_fetches_from_https_relays_with_a_chain_key() {
+    assert_eq!(DrandBeacon::new("http://api.drand.sh/chain", QUICKNET_KEY).err(), Some(BeaconError::InvalidUrl));
+    assert_eq!(DrandBeacon::new("ftp://api.drand.sh/chain", QUICKNET_KEY).err(), Some(BeaconError::InvalidUrl));
+    assert_eq!(DrandBeacon::new("https://api.drand.sh/chain", "b0c4").err(), Some(BeaconError::InvalidPublicKey));
+    assert_eq!(DrandBeacon::new("https://api.drand.sh/chain", "not hex").err(), Some(BeaconError::InvalidPublicKey));
+
+    // Relays on private addresses aren't connected to
+    let beacon = DrandBeacon::new("https://127.0.0.1:1/chain", QUICKNET_KEY).unwrap();
+    assert!(match beacon.fetch_round(1) {
+        Err(BeaconError::Io(_)) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn it_refuses_rounds_the_chain_didnt_sign() {
+    let key = from_hex(QUICKNET_KEY).unwrap();
+    let signature = "a1".repeat(48);
+    let signed = Sha256::digest(&from_hex(&signature).unwrap()).iter().map(|byte| format!("{:02x}", byte)).collect();
+    let value = BeaconValue {
+        previous_signature: String::new(),
+        randomness: signed,
+        round: 367,
+        signature,
+    };
+    assert_eq!(verify_signature(&key, &value), Err(BeaconError::InvalidSignature(367)));
+
+    // Randomness that isn't the hash of the signature is refused before the signature is checked
+    let value = BeaconValue {
+        randomness: RANDOMNESS.to_string(),
+        ..value
+    };
+    assert_eq!(verify_signature(&key, &value), Err(BeaconError::InvalidSignature(367)));
+    let value = BeaconValue {
+        signature: "not hex".to_string(),
+        ..value
+    };
+    assert_eq!(verify_signature(&key, &value), Err(BeaconError::InvalidSignature(367)));
+}
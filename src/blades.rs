@@ -0,0 +1,142 @@
+use die::DieType;
+use roll::{Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::ScriptedRoller;
+#[cfg(test)]
+use ttml::arg::ComparisonArg;
+
+/// How a Blades in the Dark roll went, from worst to best
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+pub enum BladesOutcome {
+    Failure,
+    Partial,
+    Full,
+
+    /// Two or more dice showed the critical face
+    Critical,
+}
+
+/// The lowest highest die reaching each outcome, anything below the partial is a failure
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BladesBands {
+    pub partial: i16,
+    pub full: i16,
+}
+
+impl Default for BladesBands {
+    fn default() -> BladesBands {
+        BladesBands {
+            partial: 4,
+            full: 6,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BladesResult {
+    /// The highest kept die, none when no die was kept
+    pub highest: Option<i16>,
+
+    /// If two or more kept dice showed the critical face
+    pub is_critical: bool,
+
+    pub outcome: BladesOutcome,
+
+    /// The dice rolled, with only the highest kept
+    pub roll: Roll,
+}
+
+impl BladesBands {
+    /// The outcome of the highest die, none being a failure
+    pub fn outcome(&self, highest: Option<i16>, is_critical: bool) -> BladesOutcome {
+        match highest {
+            Some(_) if is_critical => BladesOutcome::Critical,
+            Some(highest) if highest >= self.full => BladesOutcome::Full,
+            Some(highest) if highest >= self.partial => BladesOutcome::Partial,
+            _ => BladesOutcome::Failure,
+        }
+    }
+}
+
+/// Take the highest kept die of a roll, critical when two or more kept dice show the face
+///
+/// The roll's other kept dice are dropped, leaving the highest. A roll with every die dropped, e.g.
+/// by `keep_where`, is a failure with no highest die.
+pub fn highest_with_crit_on_pairs(mut roll: Roll, face: i16, bands: &BladesBands) -> BladesResult {
+    let (highest, showing_face) = {
+        let kept: Vec<i16> = roll.dice.iter().filter(|die| !die.is_dropped).map(|die| die.value).collect();
+        (kept.iter().cloned().max(), kept.iter().filter(|value| **value == face).count())
+    };
+    roll.keep_high(1);
+
+    let is_critical = showing_face >= 2;
+    BladesResult {
+        highest,
+        is_critical,
+        outcome: bands.outcome(highest, is_critical),
+        roll,
+    }
+}
+
+/// Make a Blades in the Dark roll of a number of d6s, with the usual bands
+pub fn blades_roll(dice: u8) -> BladesResult {
+    blades_roll_with(dice, &BladesBands::default(), &mut Roller::new())
+}
+
+/// Make a roll on the given bands, using the given source of randomness
+///
+/// With no dice, two are rolled and the lowest is kept, which can't be a critical.
+pub fn blades_roll_with(dice: u8, bands: &BladesBands, roller: &mut Roller) -> BladesResult {
+    let builder = RollFlags::builder().die(DieType::D6);
+    let builder = match dice {
+        0 => builder.n(2).kl(1),
+        _ => builder.n(dice as i16),
+    };
+    let mut flags = builder.build().expect("a pool of d6s is always a valid roll");
+    flags.equation = flags.canonical();
+    highest_with_crit_on_pairs(Roll::with_roller(flags, roller), 6, bands)
+}
+
+#[test]
+fn it_crits_on_two_sixes() {
+    let result = blades_roll_with(4, &BladesBands::default(), &mut ScriptedRoller::new(vec![6, 2, 6, 5]));
+    assert_eq!((result.highest, result.is_critical, result.outcome), (Some(6), true, BladesOutcome::Critical));
+    assert_eq!(result.roll.dice.iter().filter(|die| !die.is_dropped).count(), 1);
+
+    let outcome = |values: Vec<i16>| blades_roll_with(3, &BladesBands::default(), &mut ScriptedRoller::new(values)).outcome;
+    assert_eq!(outcome(vec![6, 2, 1]), BladesOutcome::Full);
+    assert_eq!(outcome(vec![3, 5, 4]), BladesOutcome::Partial);
+    assert_eq!(outcome(vec![1, 3, 2]), BladesOutcome::Failure);
+
+    // The bands can move, e.g. for a harsher position
+    let harsh = BladesBands {
+        partial: 5,
+        full: 6,
+    };
+    assert_eq!(blades_roll_with(2, &harsh, &mut ScriptedRoller::new(vec![4, 2])).outcome, BladesOutcome::Failure);
+}
+
+#[test]
+fn it_keeps_the_lowest_of_two_without_dice() {
+    let result = blades_roll_with(0, &BladesBands::default(), &mut ScriptedRoller::new(vec![6, 3]));
+    assert_eq!(result.highest, Some(3));
+    assert_eq!(result.outcome, BladesOutcome::Failure);
+
+    // Two sixes are only one kept six
+    let result = blades_roll_with(0, &BladesBands::default(), &mut ScriptedRoller::new(vec![6, 6]));
+    assert_eq!((result.highest, result.is_critical, result.outcome), (Some(6), false, BladesOutcome::Full));
+}
+
+#[test]
+fn it_fails_when_every_die_is_dropped() {
+    let flags = RollFlags::builder().n(3).die(DieType::D6).build().unwrap();
+    let mut roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 2, 6]));
+    roll.keep_where(&ComparisonArg::EqualTo, 6);
+    assert_eq!(roll.dice.iter().filter(|die| !die.is_dropped).count(), 2);
+
+    roll.keep_where(&ComparisonArg::LessThan, 2);
+    assert!(roll.dice.iter().all(|die| die.is_dropped));
+    let result = highest_with_crit_on_pairs(roll, 6, &BladesBands::default());
+    assert_eq!((result.highest, result.is_critical, result.outcome), (None, false, BladesOutcome::Failure));
+}
@@ -0,0 +1,129 @@
+use die::DieType;
+use roll::{ExplodeSuccessPolicy, Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::{assert_die_flags, FlagSet, ScriptedRoller};
+
+/// How skilled a Burning Wheel pool is, which sets the lowest face counted as a success
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Shade {
+    /// Successes on 4 or more
+    Black,
+
+    /// Successes on 3 or more
+    Gray,
+
+    /// Successes on 2 or more
+    White,
+}
+
+impl Shade {
+    /// The lowest face counted as a success
+    pub fn threshold(&self) -> i16 {
+        match self {
+            &Shade::Black => 4,
+            &Shade::Gray => 3,
+            &Shade::White => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BurningWheelResult {
+    /// Successes over the obstacle, negative when short of it
+    pub margin: i32,
+
+    /// If the successes met the obstacle
+    pub passed: bool,
+
+    /// The pool with dice below the shade dropped, open-ended 6s marked as exploded
+    pub roll: Roll,
+
+    /// Number of dice that came up as successes, including those rolled by 6s
+    pub successes: usize,
+}
+
+/// Roll a Burning Wheel pool of d6s against an obstacle
+pub fn burning_wheel_roll(pool: u8, shade: Shade, open_ended: bool, obstacle: u8) -> BurningWheelResult {
+    burning_wheel_roll_with(pool, shade, open_ended, obstacle, &mut Roller::new())
+}
+
+/// Roll a pool against an obstacle using the given source of randomness
+///
+/// Open-ended 6s each roll another die, which is a success or not on its own and explodes again on
+/// a 6. Meeting the obstacle exactly passes.
+pub fn burning_wheel_roll_with(pool: u8, shade: Shade, open_ended: bool, obstacle: u8, roller: &mut Roller)
+                               -> BurningWheelResult {
+    let builder = RollFlags::builder().n(pool as i16).die(DieType::D6).gte(shade.threshold());
+    let builder = if open_ended {
+        builder.explode(ExplodeSuccessPolicy::CompareEachDie)
+    } else {
+        builder
+    };
+    let mut flags = builder.build().expect("a pool of d6s is always a valid roll");
+    flags.equation = flags.canonical();
+
+    let roll = Roll::with_roller(flags, roller);
+    let successes = roll.successes();
+    let margin = successes as i32 - obstacle as i32;
+    BurningWheelResult {
+        margin,
+        passed: margin >= 0,
+        roll,
+        successes,
+    }
+}
+
+#[cfg(test)]
+fn roll_with(values: Vec<i16>, pool: u8, shade: Shade, open_ended: bool, obstacle: u8) -> BurningWheelResult {
+    burning_wheel_roll_with(pool, shade, open_ended, obstacle, &mut ScriptedRoller::new(values))
+}
+
+#[test]
+fn it_counts_successes_by_shade() {
+    let values = vec![1, 2, 3, 4, 5];
+    assert_eq!(roll_with(values.clone(), 5, Shade::Black, false, 0).successes, 2);
+    assert_eq!(roll_with(values.clone(), 5, Shade::Gray, false, 0).successes, 3);
+    assert_eq!(roll_with(values.clone(), 5, Shade::White, false, 0).successes, 4);
+
+    let result = roll_with(values, 5, Shade::Black, false, 0);
+    assert_eq!(result.roll.equation, "5d6gte4");
+    assert!(result.roll.dice[2].is_dropped && !result.roll.dice[3].is_dropped);
+}
+
+#[test]
+fn it_adds_successes_from_open_ended_sixes() {
+    // The first 6 rolls another 6, which rolls a 4
+    let result = roll_with(vec![6, 2, 3, 6, 4], 3, Shade::Black, true, 3);
+    assert_eq!(result.roll.dice.len(), 5);
+    assert_eq!(result.successes, 3);
+    assert!(result.passed);
+    assert_die_flags(&result.roll.dice[0], FlagSet {
+        is_exploded: true,
+        is_successful: true,
+        ..FlagSet::default()
+    });
+    assert_die_flags(&result.roll.dice[3], FlagSet {
+        is_exploded: true,
+        is_successful: true,
+        ..FlagSet::default()
+    });
+    assert!(!result.roll.dice[4].is_exploded);
+
+    // Without open-ended, the same 6 is only one success
+    let result = roll_with(vec![6, 2, 3], 3, Shade::Black, false, 3);
+    assert_eq!(result.roll.dice.len(), 3);
+    assert_eq!((result.successes, result.margin, result.passed), (1, -2, false));
+}
+
+#[test]
+fn it_passes_on_meeting_the_obstacle() {
+    let result = roll_with(vec![4, 5, 1], 3, Shade::Black, false, 2);
+    assert_eq!((result.successes, result.margin, result.passed), (2, 0, true));
+
+    let result = roll_with(vec![4, 3, 1], 3, Shade::Black, false, 2);
+    assert_eq!((result.margin, result.passed), (-1, false));
+
+    let result = roll_with(vec![6, 6, 6], 3, Shade::Black, false, 1);
+    assert_eq!((result.margin, result.passed), (2, true));
+}
@@ -0,0 +1,250 @@
+use config::Config;
+use dialect::Dialect;
+use limits::Limits;
+use parser::{parse_notation_with, ParseOptions, MAX_NOTATION_LENGTH, MODIFIERS};
+use rocket::State;
+use rocket_contrib::Json;
+#[cfg(test)]
+use parser::parse_notation;
+#[cfg(test)]
+use rocket::http::{ContentType, Status};
+#[cfg(test)]
+use rocket::local::Client;
+#[cfg(test)]
+use serde_json::{self, Value};
+
+/// Version of the shape of `Capabilities`, raised whenever a field is removed or changes meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How a roll can be evaluated, other than rolling it once at random
+const EVAL_MODES: &[(&str, &str)] = &[
+    ("random", "Rolled once with the thread's random number generator"),
+    ("seeded", "Rolled from a seed, the same seed always rolling the same dice"),
+    ("derived", "Rolled from a server secret and a client seed, so players can check it afterwards"),
+    ("exact", "Every total's probability worked out exactly, for rolls with few enough outcomes"),
+    ("simulated", "Every total's probability estimated by rolling many times"),
+];
+
+/// Modules with helpers for a game's own rolls
+const GAME_SYSTEMS: &[(&str, &str)] = &[
+    ("blades", "Blades in the Dark action rolls"),
+    ("burning_wheel", "Burning Wheel tests with shaded dice"),
+    ("hero", "HERO System damage with stun and body"),
+    ("layers", "D&D 5e advantage and disadvantage from layered options"),
+    ("pbta", "Powered by the Apocalypse moves"),
+    ("percentile", "Dark Heresy 2e and WFRP 4e percentile tests"),
+    ("pf2", "Pathfinder 2e checks with degrees of success"),
+    ("sotdl", "Shadow of the Demon Lord boons and banes"),
+];
+
+/// A modifier notation can be written with, and the dialects that read its example
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ModifierCapability {
+    pub description: String,
+
+    /// Names of the dialects its example can be parsed in
+    pub dialects: Vec<String>,
+
+    pub example: String,
+    pub token: String,
+}
+
+/// Something this build can do, with what it's for
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NamedCapability {
+    pub description: String,
+    pub name: String,
+}
+
+/// What this build of the library or server supports, so clients can leave out what it doesn't
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// Names of the dialects notation can be written in, standard notation first
+    pub dialects: Vec<String>,
+
+    pub eval_modes: Vec<NamedCapability>,
+
+    /// Cargo features the build was made with
+    pub features: Vec<String>,
+
+    pub game_systems: Vec<NamedCapability>,
+
+    /// Limits a single roll is checked against
+    pub limits: Limits,
+
+    /// Most dice a request to the server can roll, none outside of a server
+    pub max_dice_rolled: Option<usize>,
+
+    /// Most characters notation can have
+    pub max_notation_length: usize,
+
+    pub modifiers: Vec<ModifierCapability>,
+    pub schema_version: u32,
+
+    /// Version of the crate
+    pub version: String,
+}
+
+impl Capabilities {
+    /// Add the server's own limits
+    pub fn with_config(mut self, config: &Config) -> Capabilities {
+        self.max_dice_rolled = Some(config.max_dice_rolled);
+        self
+    }
+}
+
+/// What this build supports, read from the parser's modifiers, the dialects and the features it was
+/// built with
+pub fn capabilities() -> Capabilities {
+    let modifiers = MODIFIERS.iter()
+        .map(|modifier| ModifierCapability {
+            description: modifier.description.to_string(),
+            dialects: Dialect::ALL.iter()
+                .filter(|&&dialect| parse_notation_with(modifier.example, &ParseOptions::new().dialect(dialect)).is_ok())
+                .map(|dialect| dialect.name().to_string())
+                .collect(),
+            example: modifier.example.to_string(),
+            token: modifier.token.to_string(),
+        })
+        .collect();
+    let named = |list: &[(&str, &str)]| -> Vec<NamedCapability> {
+        list.iter()
+            .map(|&(name, description)| NamedCapability {
+                description: description.to_string(),
+                name: name.to_string(),
+            })
+            .collect()
+    };
+
+    Capabilities {
+        dialects: Dialect::ALL.iter().map(|dialect| dialect.name().to_string()).collect(),
+        eval_modes: named(EVAL_MODES),
+        features: features(),
+        game_systems: named(GAME_SYSTEMS),
+        limits: Limits::default(),
+        max_dice_rolled: None,
+        max_notation_length: MAX_NOTATION_LENGTH,
+        modifiers,
+        schema_version: SCHEMA_VERSION,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
+
+fn features() -> Vec<String> {
+    let features = [
+        ("std", true),
+        ("core-nostd", cfg!(feature = "core-nostd")),
+        ("async", cfg!(feature = "async")),
+        ("beacon", cfg!(feature = "beacon")),
+        ("client", cfg!(feature = "client")),
+        ("export-parquet", cfg!(feature = "export-parquet")),
+        ("metrics", cfg!(feature = "metrics")),
+        ("receipts", cfg!(feature = "receipts")),
+        ("storage-redis", cfg!(feature = "storage-redis")),
+        ("test-util", cfg!(feature = "test-util")),
+        ("tracing", cfg!(feature = "tracing")),
+    ];
+    features.iter().filter(|&&(_, is_enabled)| is_enabled).map(|&(name, _)| name.to_string()).collect()
+}
+
+#[get("/capabilities")]
+pub fn get_capabilities(config: Option<State<Config>>) -> Json<Capabilities> {
+    match config {
+        Some(config) => Json(capabilities().with_config(&config)),
+        None => Json(capabilities()),
+    }
+}
+
+/// The tokens written in an equation, e.g. `d`, `kh` and `!` for `4d6kh3!`, with anything in
+/// brackets written as `[]` and comments left out
+#[cfg(test)]
+fn tokens(equation: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = vec![];
+    let mut chars = equation.chars().peekable();
+    while let Some(c) = chars.next() {
+        let closing = match c {
+            '[' => Some(']'),
+            '"' => Some('"'),
+            _ => None,
+        };
+        if let Some(closing) = closing {
+            while chars.next().map_or(false, |next| next != closing) {}
+            if c == '[' {
+                tokens.push("[]".to_string());
+            }
+            continue;
+        }
+
+        let is_part = |first: char, next: char| if first == '!' { next == '!' } else { next.is_alphabetic() };
+        if c == '!' || c.is_alphabetic() {
+            let mut token = c.to_string();
+            while let Some(&next) = chars.peek() {
+                if !is_part(c, next) {
+                    break;
+                }
+                token.push(next);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+    tokens
+}
+
+/// The shape of JSON, with every value replaced by the name of its type and arrays by the shape of
+/// their first element
+#[cfg(test)]
+fn shape(value: &Value) -> Value {
+    match *value {
+        Value::Null => json!("null"),
+        Value::Bool(_) => json!("boolean"),
+        Value::Number(_) => json!("number"),
+        Value::String(_) => json!("string"),
+        Value::Array(ref values) => Value::Array(values.iter().take(1).map(shape).collect()),
+        Value::Object(ref fields) => Value::Object(fields.iter().map(|(key, value)| (key.clone(), shape(value))).collect()),
+    }
+}
+
+#[test]
+fn it_lists_every_modifier_the_parser_writes() {
+    let listed: Vec<String> = capabilities().modifiers.iter().map(|modifier| modifier.token.clone()).collect();
+    let examples: Vec<&str> = MODIFIERS.iter().map(|modifier| modifier.example).collect();
+    let others = ["1d20+5", "5d20dh1dl1", "2d6rr<2", "2d20ro<3", "3d10!!>=9+2", "4dF+2", "df", "3.5d6",
+                  "2d[1,3,5][odd]", "1d8[fire,magic]", "2d6[fire] + 1d6[cold]", "1d20floor5ceiling15+2",
+                  "1d20 \"to hit\""];
+
+    // Notation that stops parsing would have nothing to check, so it fails rather than being skipped
+    for notation in examples.iter().chain(others.iter()) {
+        let rolls = parse_notation(notation).unwrap_or_else(|error| panic!("{} doesn't parse: {:?}", notation, error));
+        for flags in rolls {
+            for token in tokens(&flags.equation) {
+                assert!(listed.contains(&token), "{} from {} isn't in the capabilities", token, notation);
+            }
+        }
+    }
+
+    assert_eq!(tokens("4d6kh3!"), vec!["d", "kh", "!"]);
+    assert_eq!(tokens("3d10!!>=9 + 2d6[fire] \"kh\""), vec!["d", "!!", "d", "[]"]);
+
+    // Every dialect reads plain dice
+    let capabilities = capabilities();
+    assert_eq!(capabilities.modifiers[0].dialects, capabilities.dialects);
+    assert!(capabilities.features.contains(&"std".to_string()));
+}
+
+#[test]
+fn it_keeps_the_shape_of_its_json() {
+    let path = format!("{}/tests/golden/capabilities-shape.json", env!("CARGO_MANIFEST_DIR"));
+    let golden: Value = serde_json::from_str(&::std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(shape(&serde_json::to_value(&capabilities()).unwrap()), golden);
+}
+
+#[test]
+fn it_serves_the_capabilities_with_the_server_limits() {
+    let client = Client::new(::rocket()).expect("the API should launch");
+    let mut response = client.get("/capabilities").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let served: Capabilities = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+    assert_eq!(served.max_dice_rolled, Some(10000));
+    assert_eq!(served.modifiers, capabilities().modifiers);
+}
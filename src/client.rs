@@ -0,0 +1,363 @@
+use error::ApiError;
+use history::{HistoryPage, HistoryQuery};
+use http::{self, HttpError, HttpOptions, Response, Url};
+use roll::Roll;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+use stats::Distribution;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+use tokio::task::{self, JoinHandle};
+use v1::{RollsResponse, StatsResponse, ValidateRequest};
+use validate::ValidationReport;
+#[cfg(test)]
+use error::ErrorCode;
+
+#[derive(Debug, PartialEq)]
+pub enum ClientError {
+    /// The server answered with an error, decoded from its body
+    Api { status: u16, error: ApiError },
+
+    /// The base URL couldn't be understood, or isn't `http://` or `https://`, which needs the `tls`
+    /// feature
+    InvalidUrl,
+
+    /// The server answered with something other than what the endpoint returns, with why
+    InvalidResponse(String),
+
+    /// The request couldn't be sent or the response couldn't be read
+    Io(String),
+}
+
+impl From<HttpError> for ClientError {
+    fn from(error: HttpError) -> ClientError {
+        match error {
+            HttpError::InvalidUrl | HttpError::UnsupportedScheme => ClientError::InvalidUrl,
+            HttpError::InvalidResponse(why) => ClientError::InvalidResponse(why),
+            HttpError::Io(why) => ClientError::Io(why),
+            HttpError::PrivateHost(host) => ClientError::Io(format!("{} can't be reached", host)),
+        }
+    }
+}
+
+/// How long to wait before trying a request the server turned away with a 429 again
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backoff {
+    /// Tries after the first before the 429 is handed back
+    pub max_retries: u32,
+
+    /// Wait before the first retry when the server doesn't say, doubling before every retry after it
+    pub base: Duration,
+
+    /// Longest the client waits for one retry, so a 429 asking for longer is handed back straight
+    /// away, and the most the doubled wait grows to
+    pub max_wait: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            max_retries: 3,
+            base: Duration::from_millis(500),
+            max_wait: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Endpoint {
+    backoff: Backoff,
+    options: HttpOptions,
+    token: String,
+
+    /// The base URL, with its path's trailing `/` taken off so routes can go after it
+    url: Url,
+}
+
+/// Make requests to the HTTP API from a bot or another server, with the same types it answers with
+///
+/// Requests are made over `http::request` on tokio's blocking threads, so each method hands back a
+/// future to await on a tokio runtime. Nothing is sent until the future is first polled, so a
+/// future made outside a runtime or dropped without being awaited does nothing. Requests turned
+/// away by the rate limit are tried again after the `Retry-After` the server gives, see `Backoff`.
+#[derive(Clone)]
+pub struct RollApiClient {
+    endpoint: Arc<Endpoint>,
+}
+
+/// A request made by a `RollApiClient`, sent once it's first polled
+pub struct ClientFuture<T> {
+    state: Request<T>,
+}
+
+enum Request<T> {
+    Waiting(Option<Box<dyn FnOnce() -> Result<T, ClientError> + Send>>),
+    Sent(JoinHandle<Result<T, ClientError>>),
+}
+
+impl<T: Send + 'static> Future for ClientFuture<T> {
+    type Output = Result<T, ClientError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, ClientError>> {
+        let future = self.get_mut();
+        if let Request::Waiting(ref mut request) = future.state {
+            let request = request.take().expect("a request is only sent once");
+            future.state = Request::Sent(task::spawn_blocking(request));
+        }
+        let handle = match future.state {
+            Request::Sent(ref mut handle) => handle,
+            Request::Waiting(_) => unreachable!("the request was just sent"),
+        };
+        match Pin::new(handle).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(error)) => {
+                if error.is_panic() {
+                    ::std::panic::resume_unwind(error.into_panic());
+                }
+                Poll::Ready(Err(ClientError::Io("the request was cancelled".to_string())))
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl RollApiClient {
+    /// A client for the API at the base URL, e.g. `https://rolls.example`, sending the token as a
+    /// bearer token so requests count against the token's rate limit
+    pub fn new(base_url: &str, token: &str) -> Result<RollApiClient, ClientError> {
+        let mut url = Url::parse(base_url)?;
+        url.path = url.path.trim_end_matches('/').to_string();
+        Ok(RollApiClient {
+            endpoint: Arc::new(Endpoint {
+                backoff: Backoff::default(),
+                options: HttpOptions::default(),
+                token: token.to_string(),
+                url,
+            }),
+        })
+    }
+
+    /// Retry requests turned away by the rate limit this way instead of the default
+    pub fn with_backoff(self, backoff: Backoff) -> RollApiClient {
+        let mut endpoint = (*self.endpoint).clone();
+        endpoint.backoff = backoff;
+        RollApiClient {
+            endpoint: Arc::new(endpoint),
+        }
+    }
+
+    /// Connect, write and read with these timeouts instead of the defaults
+    pub fn with_options(self, options: HttpOptions) -> RollApiClient {
+        let mut endpoint = (*self.endpoint).clone();
+        endpoint.options = options;
+        RollApiClient {
+            endpoint: Arc::new(endpoint),
+        }
+    }
+
+    /// Roll notation, the same as `GET /v1/<command>`
+    pub fn roll(&self, notation: &str) -> ClientFuture<Roll> {
+        let route = "/v1/".to_string() + &encode(notation);
+        self.spawn(move |endpoint| {
+            let response: RollsResponse = endpoint.request("GET", &route, None)?;
+            Ok(response.roll)
+        })
+    }
+
+    /// Roll each notation in turn, handing back the rolls in the same order
+    ///
+    /// The API rolls one command a request, so this makes a request for each and stops at the first
+    /// that fails. Each counts against the rate limit, and is retried on its own when turned away.
+    pub fn roll_batch(&self, notations: &[&str]) -> ClientFuture<Vec<Roll>> {
+        let routes: Vec<String> = notations.iter().map(|notation| "/v1/".to_string() + &encode(notation)).collect();
+        self.spawn(move |endpoint| {
+            routes.iter()
+                .map(|route| endpoint.request("GET", route, None).map(|response: RollsResponse| response.roll))
+                .collect()
+        })
+    }
+
+    /// Check notation without rolling it, the same as `POST /v1/validate`
+    pub fn validate(&self, notation: &str) -> ClientFuture<ValidationReport> {
+        let request = ValidateRequest {
+            notation: notation.to_string(),
+            variables: None,
+            lint: None,
+        };
+        let body = to_json(&request);
+        self.spawn(move |endpoint| endpoint.request("POST", "/v1/validate", Some(body?)))
+    }
+
+    /// A page of a room's roll history, the same as `GET /v1/rooms/<room>/rolls`
+    pub fn history(&self, room: &str, query: &HistoryQuery) -> ClientFuture<HistoryPage> {
+        let route = format!("/v1/rooms/{}/rolls", encode(room)) + &query_string(query);
+        self.spawn(move |endpoint| endpoint.request("GET", &route, None))
+    }
+
+    /// Chance of each total of notation, the same as `GET /v1/stats/<command>`
+    pub fn stats(&self, notation: &str) -> ClientFuture<Distribution> {
+        let route = "/v1/stats/".to_string() + &encode(notation);
+        self.spawn(move |endpoint| {
+            let response: StatsResponse = endpoint.request("GET", &route, None)?;
+            Ok(response.distribution)
+        })
+    }
+
+    fn spawn<T, F>(&self, request: F) -> ClientFuture<T>
+        where T: Send + 'static, F: FnOnce(&Endpoint) -> Result<T, ClientError> + Send + 'static {
+        let endpoint = self.endpoint.clone();
+        ClientFuture {
+            state: Request::Waiting(Some(Box::new(move || request(&endpoint)))),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Make a request, waiting and trying again while it's turned away by the rate limit
+    fn request<T: DeserializeOwned>(&self, method: &str, route: &str, body: Option<String>) -> Result<T, ClientError> {
+        let mut retries = 0;
+        let mut wait = self.backoff.base;
+        let response = loop {
+            let response = self.send(method, route, body.as_ref().map(|body| body.as_str()))?;
+            if response.status != 429 || retries >= self.backoff.max_retries {
+                break response;
+            }
+
+            let asked = response.header("Retry-After").and_then(|seconds| seconds.parse().ok())
+                .map_or(wait, Duration::from_secs);
+            if asked > self.backoff.max_wait {
+                break response;
+            }
+            thread::sleep(asked);
+            wait = wait.checked_mul(2).map_or(self.backoff.max_wait, |doubled| doubled.min(self.backoff.max_wait));
+            retries += 1;
+        };
+        decode(&response)
+    }
+
+    /// Make a request once
+    fn send(&self, method: &str, route: &str, body: Option<&str>) -> Result<Response, ClientError> {
+        let authorization = "Bearer ".to_string() + &self.token;
+        let headers = [("Authorization", authorization.as_str()), ("Accept", "application/json"),
+                       ("Content-Type", "application/json")];
+        let target = self.url.path.clone() + route;
+        let body = body.unwrap_or("").as_bytes();
+        Ok(http::request(&self.url, method, &target, &headers, body, &self.options)?)
+    }
+}
+
+/// The body of a 2xx response as what the endpoint returns, or the error any other response has
+fn decode<T: DeserializeOwned>(response: &Response) -> Result<T, ClientError> {
+    if response.is_success() {
+        let body = response.text()?;
+        return serde_json::from_str(body).map_err(|error| ClientError::InvalidResponse(error.to_string()));
+    }
+    match response.text().ok().and_then(|body| serde_json::from_str(body).ok()) {
+        Some(error) => Err(ClientError::Api {
+            status: response.status,
+            error,
+        }),
+        None => Err(ClientError::InvalidResponse(format!("the server answered {} without an error", response.status))),
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, ClientError> {
+    serde_json::to_string(value).map_err(|error| ClientError::InvalidResponse(error.to_string()))
+}
+
+/// Percent-encode everything but the characters a path segment or query value can hold as they are
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            (byte as char).to_string()
+        } else {
+            format!("%{:02X}", byte)
+        })
+        .collect()
+}
+
+/// The query's filters as a query string, empty when it has none
+fn query_string(query: &HistoryQuery) -> String {
+    let limit = query.limit.map(|limit| limit.to_string());
+    let fields = [("before", query.before.as_ref()), ("cursor", query.cursor.as_ref()), ("limit", limit.as_ref()),
+                  ("notation_contains", query.notation_contains.as_ref()), ("participant", query.participant.as_ref())];
+    let pairs: Vec<String> = fields.iter()
+        .filter_map(|&(name, value)| value.map(|value| format!("{}={}", name, encode(value))))
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        "?".to_string() + &pairs.join("&")
+    }
+}
+
+#[test]
+fn it_decodes_the_error_of_a_response() {
+    let response = http::parse_response(b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 2\r\nContent-Type: application/json\r\n\r\n\
+                                         {\"code\":\"RATE_LIMITED\",\"details\":{\"retry_after\":2},\"message\":\"Too many requests, slow down.\"}").unwrap();
+    assert_eq!(response.header("Retry-After"), Some("2"));
+    match decode::<Roll>(&response) {
+        Err(ClientError::Api { status, error }) => {
+            assert_eq!((status, error.code), (429, ErrorCode::RateLimited));
+            assert_eq!(error.details["retry_after"], 2);
+        },
+        _ => panic!("a 429 should decode to its error"),
+    }
+}
+
+#[test]
+fn it_decodes_chunked_bodies() {
+    let response = http::parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                                         6\r\n{\"code\r\n4\r\n\":\"N\r\n0\r\n\r\n").unwrap();
+    assert_eq!(response.text(), Ok("{\"code\":\"N"));
+    let response = http::parse_response(b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n\
+                                         1a\r\n{\"code\":\"NOT_FOUND\",\"detai\r\n1d\r\nls\":{},\"message\":\"Not found\"}\r\n0\r\n\r\n").unwrap();
+    match decode::<Roll>(&response) {
+        Err(ClientError::Api { status, error }) => assert_eq!((status, error.code), (404, ErrorCode::NotFound)),
+        _ => panic!("a chunked 404 should decode to its error"),
+    }
+}
+
+#[test]
+fn it_turns_away_bodies_that_arent_what_it_asked_for() {
+    let response = http::parse_response(b"HTTP/1.1 502 Bad Gateway\r\n\r\n<html></html>").unwrap();
+    assert!(match decode::<Roll>(&response) {
+        Err(ClientError::InvalidResponse(_)) => true,
+        _ => false,
+    });
+
+    let response = http::parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n\xff\xfe").unwrap();
+    assert!(match decode::<Roll>(&response) {
+        Err(ClientError::InvalidResponse(_)) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn it_sends_nothing_until_awaited() {
+    // Spawning needs a runtime, so a request sent straight away would panic here
+    let client = RollApiClient::new("http://127.0.0.1:9", "token").unwrap();
+    drop(client.roll("1d20"));
+    drop(client.roll_batch(&["1d20", "1d6"]));
+}
+
+#[test]
+fn it_encodes_notation_for_the_path() {
+    assert_eq!(encode("1d20+5 \"to hit\""), "1d20%2B5%20%22to%20hit%22");
+    assert_eq!(encode("4d6kh3"), "4d6kh3");
+
+    let query = HistoryQuery {
+        limit: Some(10),
+        participant: Some("Sam & Max".to_string()),
+        ..HistoryQuery::default()
+    };
+    assert_eq!(query_string(&query), "?limit=10&participant=Sam%20%26%20Max");
+    assert_eq!(query_string(&HistoryQuery::default()), "");
+    assert_eq!(RollApiClient::new("ftp://rolls.example", "token").err(), Some(ClientError::InvalidUrl));
+    assert_eq!(RollApiClient::new("https://rolls.example", "token").is_ok(), cfg!(feature = "tls"));
+}
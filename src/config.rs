@@ -1,9 +1,33 @@
+use history::{RollLog, RollStore};
+use rate_limit::{MemoryLimiter, RateLimiter, RateLimits, TokenBucket};
+#[cfg(feature = "storage-redis")]
+use redis_store::{FailurePolicy, RedisLimiter, RedisStore};
 use rocket::Rocket;
 use rocket::fairing::{Fairing, Info, Kind};
+use stats::DistributionCache;
+use std::time::Duration;
+
+/// Where room history is kept and, when requests are rate limited, where they're counted
+type Storage = (Box<dyn RollStore>, Option<Box<dyn RateLimiter>>);
 
 #[derive(Debug)]
 pub struct Config {
     pub access_control_allow_origin: String,
+
+    /// Most dice a single request can roll, checked against the worst case before rolling
+    pub max_dice_rolled: usize,
+
+    /// Most preset slots each participant can fill, see `Session::with_max_preset_slots`
+    pub max_preset_slots: usize,
+
+    /// How long rolling a request can take before it's given up on, none to let it finish
+    pub roll_deadline: Option<Duration>,
+
+    /// Rolls taking longer than this are logged with where their time went, none to not time them
+    pub slow_roll_warning: Option<Duration>,
+
+    /// Reject notation with lookalike characters, like full-width digits, instead of reading them as ASCII
+    pub strict_notation: bool,
 }
 
 pub struct ConfigMiddleware;
@@ -18,9 +42,84 @@ impl Fairing for ConfigMiddleware {
 
     fn on_attach(&self, rocket: Rocket) -> Result<Rocket, Rocket> {
         let access_control_allow_origin = rocket.config().get_str("access_control_allow_origin").unwrap_or("http://localhost:3000").to_string();
-        Ok(rocket.manage(Config {
-            access_control_allow_origin,
-        }))
+        let max_dice_rolled = rocket.config().get_int("max_dice_rolled").unwrap_or(10000) as usize;
+        let max_preset_slots = rocket.config().get_int("max_preset_slots").unwrap_or(10) as usize;
+        let roll_deadline = Some(rocket.config().get_int("roll_deadline_ms").unwrap_or(1000))
+            .filter(|&milliseconds| milliseconds > 0)
+            .map(|milliseconds| Duration::from_millis(milliseconds as u64));
+        let slow_roll_warning = rocket.config().get_int("slow_roll_warning_ms").ok()
+            .filter(|&milliseconds| milliseconds > 0)
+            .map(|milliseconds| Duration::from_millis(milliseconds as u64));
+        let strict_notation = rocket.config().get_bool("strict_notation").unwrap_or(false);
+        let stats_cache_capacity = rocket.config().get_int("stats_cache_capacity").unwrap_or(256) as usize;
+        let rate_limit = rocket.config().get_int("rate_limit_capacity").ok()
+            .filter(|&capacity| capacity > 0)
+            .map(|capacity| TokenBucket {
+                capacity: capacity as u32,
+                refill_per_second: rocket.config().get_int("rate_limit_refill_per_second").unwrap_or(1) as u32,
+            });
+        let (store, limiter) = match storage(&rocket, rate_limit) {
+            Ok(storage) => storage,
+            Err(_error) => {
+                #[cfg(feature = "tracing")]
+                error!(error = %_error, "storage couldn't be set up");
+                return Err(rocket);
+            },
+        };
+
+        let rocket = rocket
+            .manage(Config {
+                access_control_allow_origin,
+                max_dice_rolled,
+                max_preset_slots,
+                roll_deadline,
+                slow_roll_warning,
+                strict_notation,
+            })
+            .manage(DistributionCache::new(stats_cache_capacity))
+            .manage(store);
+        Ok(match limiter {
+            Some(limiter) => rocket.manage(RateLimits::new(limiter)),
+            None => rocket,
+        })
     }
 
 }
+
+fn memory_storage(rate_limit: Option<TokenBucket>) -> Storage {
+    let limiter = rate_limit.map(|bucket| Box::new(MemoryLimiter::new(bucket)) as Box<dyn RateLimiter>);
+    (Box::new(RollLog::new()), limiter)
+}
+
+/// Share history and rate limits through Redis when `redis_url` is set, otherwise keep them in memory
+#[cfg(feature = "storage-redis")]
+fn storage(rocket: &Rocket, rate_limit: Option<TokenBucket>) -> Result<Storage, String> {
+    let url = match rocket.config().get_str("redis_url") {
+        Ok(url) => url,
+        Err(_) => return Ok(memory_storage(rate_limit)),
+    };
+    let prefix = rocket.config().get_str("redis_prefix").unwrap_or("roll-api");
+    let failure = if rocket.config().get_bool("redis_fail_open").unwrap_or(false) {
+        FailurePolicy::Open
+    } else {
+        FailurePolicy::Closed
+    };
+
+    let mut store = RedisStore::open(url, prefix, failure).map_err(|error| error.to_string())?;
+    if let Ok(ttl) = rocket.config().get_int("history_ttl") {
+        store = store.with_ttl(ttl as usize);
+    }
+    let limiter = match rate_limit {
+        Some(bucket) => {
+            let limiter = RedisLimiter::open(url, prefix, bucket, failure).map_err(|error| error.to_string())?;
+            Some(Box::new(limiter) as Box<dyn RateLimiter>)
+        },
+        None => None,
+    };
+    Ok((Box::new(store), limiter))
+}
+
+#[cfg(not(feature = "storage-redis"))]
+fn storage(_rocket: &Rocket, rate_limit: Option<TokenBucket>) -> Result<Storage, String> {
+    Ok(memory_storage(rate_limit))
+}
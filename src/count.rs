@@ -0,0 +1,308 @@
+use limits::Limits;
+use parser::parse_notation;
+use roll::{Roll, RollError};
+use roller::Roller;
+use rounding::Rounding;
+use std::collections::HashMap;
+#[cfg(test)]
+use testing::{assert_roll_total, ScriptedRoller};
+
+#[derive(Debug, PartialEq)]
+pub enum CountError {
+    /// The notation or one of its sub-expressions couldn't be understood
+    InvalidNotation,
+
+    /// The count or sides can't be rolled, or the roll is past the limits
+    Roll(RollError),
+
+    /// A variable was used that wasn't supplied
+    UnknownVariable(String),
+}
+
+impl From<RollError> for CountError {
+    fn from(error: RollError) -> CountError {
+        CountError::Roll(error)
+    }
+}
+
+/// A roll whose number of dice, and possibly sides, was worked out before rolling
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CountedRoll {
+    /// Number of dice the count worked out to
+    pub count: i16,
+
+    /// The count roll, made to work out the number of dice when the count has dice in it
+    pub count_roll: Option<Roll>,
+
+    /// The roll made with the worked out count and sides
+    pub roll: Roll,
+
+    /// The sides roll, made to work out the sides when they have dice in them
+    pub sides_roll: Option<Roll>,
+}
+
+/// Roll notation whose count or sides are worked out first, e.g. `(1d4)d6` or `(1+@level/2)d8`
+///
+/// The count at the start of the notation, and the sides after its `d`, can be written in
+/// parentheses as arithmetic on whole numbers and `@` variables, or as dice. Dice in a count or
+/// sides are rolled first, and don't add to the total. Division rounds down. A count of zero or
+/// less rolls no dice, leaving only the modifiers, and the worked out roll has to stay within the
+/// limits.
+pub fn roll_counted(notation: &str, variables: &HashMap<String, i32>, limits: &Limits, roller: &mut Roller)
+                    -> Result<CountedRoll, CountError> {
+    let (count, rest) = if notation.starts_with('(') {
+        take_group(notation)?
+    } else {
+        let digits = notation.find(|c: char| !c.is_digit(10)).unwrap_or(notation.len());
+        (notation[..digits].to_string(), &notation[digits..])
+    };
+    if !rest.starts_with('d') {
+        return Err(CountError::InvalidNotation);
+    }
+
+    let (sides, rest) = if rest[1..].starts_with('(') {
+        let (sides, rest) = take_group(&rest[1..])?;
+        (Some(sides), rest)
+    } else {
+        (None, &rest[1..])
+    };
+
+    let (count, count_roll) = evaluate(&count, variables, limits, roller)?;
+    let count = count.max(0);
+    let (sides, sides_roll) = match sides {
+        Some(sides) => {
+            let (sides, sides_roll) = evaluate(&sides, variables, limits, roller)?;
+            (Some(sides), sides_roll)
+        },
+        None => (None, None),
+    };
+
+    let counted = match sides {
+        Some(sides) => format!("{}d{}{}", count, sides.max(0), rest),
+        None => format!("{}d{}", count, rest),
+    };
+    let flags = parse_notation(&counted).ok().and_then(|mut rolls| rolls.pop()).ok_or(CountError::InvalidNotation)?;
+    flags.validate()?;
+    limits.check(&flags)?;
+
+    Ok(CountedRoll {
+        count: flags.n,
+        count_roll,
+        roll: Roll::with_roller(flags, roller),
+        sides_roll,
+    })
+}
+
+/// Split a parenthesized group off the start of the notation, returning what's inside it
+fn take_group(notation: &str) -> Result<(String, &str), CountError> {
+    let mut depth = 0;
+    for (i, c) in notation.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {},
+        }
+        if depth == 0 {
+            return Ok((notation[1..i].to_string(), &notation[i + 1..]));
+        }
+    }
+    Err(CountError::InvalidNotation)
+}
+
+/// Work out a count or sides, rolling them when they have dice in them
+fn evaluate(expression: &str, variables: &HashMap<String, i32>, limits: &Limits, roller: &mut Roller)
+            -> Result<(i16, Option<Roll>), CountError> {
+    let expression = substitute(expression, variables)?;
+    if !expression.contains('d') {
+        let value = Arithmetic::new(&expression).parse()?;
+        return Ok((clamp(value), None));
+    }
+
+    let flags = parse_notation(&expression).ok().and_then(|mut rolls| rolls.pop()).ok_or(CountError::InvalidNotation)?;
+    flags.validate()?;
+    limits.check(&flags)?;
+    let roll = Roll::with_roller(flags, roller);
+    Ok((clamp(roll.value as i64), Some(roll)))
+}
+
+/// Write every `@` variable in the expression as its value
+fn substitute(expression: &str, variables: &HashMap<String, i32>) -> Result<String, CountError> {
+    let mut substituted = String::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '@' {
+            substituted.push(c);
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(&next) = chars.peek() {
+            if !next.is_alphanumeric() && next != '_' {
+                break;
+            }
+            name.push(next);
+            chars.next();
+        }
+        match variables.get(&name) {
+            Some(value) => substituted = substituted + &value.to_string(),
+            None => return Err(CountError::UnknownVariable(name)),
+        }
+    }
+    Ok(substituted)
+}
+
+fn clamp(value: i64) -> i16 {
+    value.max(i16::min_value() as i64).min(i16::max_value() as i64) as i16
+}
+
+/// Whole number arithmetic with `+`, `-`, `*`, `/` and parentheses
+struct Arithmetic {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Arithmetic {
+    fn new(expression: &str) -> Arithmetic {
+        Arithmetic {
+            chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+            position: 0,
+        }
+    }
+
+    fn parse(&mut self) -> Result<i64, CountError> {
+        let value = self.sum()?;
+        if self.position != self.chars.len() {
+            return Err(CountError::InvalidNotation);
+        }
+        Ok(value)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).cloned()
+    }
+
+    fn sum(&mut self) -> Result<i64, CountError> {
+        let mut value = self.product()?;
+        while let Some(op) = self.peek() {
+            match op {
+                '+' => {
+                    self.position += 1;
+                    value = clamp(value + self.product()?) as i64;
+                },
+                '-' => {
+                    self.position += 1;
+                    value = clamp(value - self.product()?) as i64;
+                },
+                _ => break,
+            }
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, CountError> {
+        let mut value = self.factor()?;
+        while let Some(op) = self.peek() {
+            match op {
+                '*' => {
+                    self.position += 1;
+                    value = clamp(value * self.factor()?) as i64;
+                },
+                '/' => {
+                    self.position += 1;
+                    let divisor = self.factor()?;
+                    if divisor == 0 {
+                        return Err(CountError::Roll(RollError::DivideByZero));
+                    }
+                    value = Rounding::Floor.divide(value as i32, divisor as i32) as i64;
+                },
+                _ => break,
+            }
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<i64, CountError> {
+        match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                Ok(-self.factor()?)
+            },
+            Some('(') => {
+                self.position += 1;
+                let value = self.sum()?;
+                if self.peek() != Some(')') {
+                    return Err(CountError::InvalidNotation);
+                }
+                self.position += 1;
+                Ok(value)
+            },
+            _ => {
+                let start = self.position;
+                while self.peek().map_or(false, |c| c.is_digit(10)) {
+                    self.position += 1;
+                }
+                let digits: String = self.chars[start..self.position].iter().collect();
+                digits.parse().map(clamp).map(|value| value as i64).map_err(|_| CountError::InvalidNotation)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+fn level(level: i32) -> HashMap<String, i32> {
+    let mut variables = HashMap::new();
+    variables.insert("level".to_string(), level);
+    variables
+}
+
+#[test]
+fn it_rolls_the_count_first() {
+    let mut roller = ScriptedRoller::new(vec![3, 2, 5, 6]);
+    let counted = roll_counted("(1d4)d6", &HashMap::new(), &Limits::default(), &mut roller).unwrap();
+    assert_eq!(counted.count, 3);
+    assert_roll_total(counted.count_roll.as_ref().unwrap(), 3);
+    assert_eq!(counted.roll.dice.len(), 3);
+
+    // The count roll is kept apart and doesn't add to the total
+    assert_roll_total(&counted.roll, 13);
+    assert!(counted.sides_roll.is_none());
+}
+
+#[test]
+fn it_works_out_the_count_from_variables() {
+    let mut roller = ScriptedRoller::new(vec![4, 4, 1, 7]);
+    let counted = roll_counted("(1+@level/2)d8+2", &level(7), &Limits::default(), &mut roller).unwrap();
+    assert_eq!(counted.count, 4);
+    assert!(counted.count_roll.is_none());
+    assert_roll_total(&counted.roll, 18);
+
+    let result = roll_counted("(@level)d8", &HashMap::new(), &Limits::default(), &mut Roller::new());
+    assert_eq!(result.err(), Some(CountError::UnknownVariable("level".to_string())));
+}
+
+#[test]
+fn it_works_out_the_sides() {
+    let mut roller = ScriptedRoller::new(vec![2, 9]);
+    let counted = roll_counted("1d(@level*2)", &level(5), &Limits::default(), &mut roller).unwrap();
+    assert_eq!(counted.roll.dice[0].max, 10);
+    assert_roll_total(&counted.roll, 2);
+}
+
+#[test]
+fn it_rolls_no_dice_for_counts_below_one() {
+    let counted = roll_counted("(@level-3)d6+1", &level(1), &Limits::default(), &mut Roller::new()).unwrap();
+    assert_eq!(counted.count, 0);
+    assert!(counted.roll.dice.is_empty());
+    assert_eq!(counted.roll.value, 1);
+}
+
+#[test]
+fn it_keeps_counts_within_the_limits() {
+    let result = roll_counted("(@level*10)d6", &level(20), &Limits::default(), &mut Roller::new());
+    assert_eq!(result.err(), Some(CountError::Roll(RollError::TooManyDice(200, 100))));
+
+    let result = roll_counted("(1d4)d6", &HashMap::new(), &Limits::default(), &mut ScriptedRoller::new(vec![4, 1, 1, 1, 1]));
+    assert!(result.is_ok());
+    assert_eq!(roll_counted("(2/0)d6", &HashMap::new(), &Limits::default(), &mut Roller::new()).err(),
+               Some(CountError::Roll(RollError::DivideByZero)));
+}
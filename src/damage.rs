@@ -0,0 +1,240 @@
+use die::Die;
+use roll::Roll;
+use rounding::Rounding;
+use std::collections::BTreeSet;
+#[cfg(test)]
+use die::DieType;
+#[cfg(test)]
+use roll::RollFlags;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// How a target takes each type of damage, e.g. a fire elemental's
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DamageProfile {
+    /// Type given to damage from untagged terms
+    pub default_tag: String,
+
+    /// Types of damage that do nothing
+    pub immunities: BTreeSet<String>,
+
+    /// Types of damage that are halved
+    pub resistances: BTreeSet<String>,
+
+    /// How halved damage is rounded
+    pub rounding: Rounding,
+
+    /// Types of damage that are doubled
+    pub vulnerabilities: BTreeSet<String>,
+}
+
+impl Default for DamageProfile {
+    fn default() -> DamageProfile {
+        DamageProfile {
+            default_tag: "untyped".to_string(),
+            immunities: BTreeSet::new(),
+            resistances: BTreeSet::new(),
+            rounding: Rounding::Floor,
+            vulnerabilities: BTreeSet::new(),
+        }
+    }
+}
+
+/// Damage of one type, before and after the target's profile
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DamageAmount {
+    /// Damage the target takes
+    pub adjusted: i32,
+
+    /// Damage rolled
+    pub raw: i32,
+
+    /// The type of damage, e.g. `fire`
+    pub tag: String,
+}
+
+/// Damage rolled against a target, by type in the order the types first appear
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DamageReport {
+    pub amounts: Vec<DamageAmount>,
+
+    /// Damage the target takes in all
+    pub total: i32,
+}
+
+impl DamageReport {
+    /// The amount of a type of damage, if any was rolled
+    pub fn get(&self, tag: &str) -> Option<&DamageAmount> {
+        self.amounts.iter().find(|amount| amount.tag == tag)
+    }
+}
+
+impl DamageProfile {
+    pub fn new() -> DamageProfile {
+        DamageProfile::default()
+    }
+
+    pub fn resist(mut self, tag: &str) -> DamageProfile {
+        self.resistances.insert(tag.to_string());
+        self
+    }
+
+    pub fn immune(mut self, tag: &str) -> DamageProfile {
+        self.immunities.insert(tag.to_string());
+        self
+    }
+
+    pub fn vulnerable(mut self, tag: &str) -> DamageProfile {
+        self.vulnerabilities.insert(tag.to_string());
+        self
+    }
+
+    /// Give damage from untagged terms this type
+    pub fn default_tag(mut self, tag: &str) -> DamageProfile {
+        self.default_tag = tag.to_string();
+        self
+    }
+
+    pub fn rounding(mut self, rounding: Rounding) -> DamageProfile {
+        self.rounding = rounding;
+        self
+    }
+
+    /// The damage the target takes from a type, given how much was rolled
+    ///
+    /// Immunity wins over everything. Resistance and vulnerability together halve and then double,
+    /// so the rounding of the half still counts.
+    pub fn adjust(&self, tag: &str, raw: i32) -> i32 {
+        if self.immunities.contains(tag) {
+            return 0;
+        }
+
+        let mut adjusted = raw;
+        if self.resistances.contains(tag) {
+            adjusted = self.rounding.divide(adjusted, 2);
+        }
+        if self.vulnerabilities.contains(tag) {
+            adjusted *= 2;
+        }
+        adjusted
+    }
+
+    /// Add up the damage of several rolls by type, e.g. each term of `2d6[fire] + 1d4[piercing]`
+    ///
+    /// Each roll's whole value, modifiers included, is damage of the first tag on its dice. A roll
+    /// whose dice have different tags, e.g. one imported from Foundry, counts each kept die as
+    /// damage of its own tag, and the rest of its value as damage of its first term's, which its
+    /// modifiers were written with. The profile is applied to the subtotal of each type rather than
+    /// to each roll, so two resisted odd rolls of the same type round once. The rolls aren't
+    /// changed.
+    pub fn apply<'a, I: IntoIterator<Item = &'a Roll>>(&self, rolls: I) -> DamageReport {
+        let mut amounts: Vec<DamageAmount> = vec![];
+        for roll in rolls {
+            let dice = roll.dice_in_notation_order();
+            let own = dice.first().map_or(&self.default_tag, |&die| self.tag_of(die));
+            if dice.iter().all(|die| self.tag_of(die) == own) {
+                credit(&mut amounts, own, roll.value);
+                continue;
+            }
+
+            let mut rest = roll.value;
+            for die in dice.into_iter().filter(|die| !die.is_dropped) {
+                credit(&mut amounts, self.tag_of(die), die.counted() as i32);
+                rest -= die.counted() as i32;
+            }
+            credit(&mut amounts, own, rest);
+        }
+
+        for amount in amounts.iter_mut() {
+            amount.adjusted = self.adjust(&amount.tag, amount.raw);
+        }
+        DamageReport {
+            total: amounts.iter().map(|amount| amount.adjusted).sum(),
+            amounts,
+        }
+    }
+
+    /// The type of damage a die does, its first tag or the default
+    fn tag_of<'a>(&'a self, die: &'a Die) -> &'a String {
+        die.tags.first().unwrap_or(&self.default_tag)
+    }
+}
+
+/// Add damage of a type to the amounts, after the others when it's the first of its type
+fn credit(amounts: &mut Vec<DamageAmount>, tag: &str, raw: i32) {
+    match amounts.iter().position(|amount| amount.tag == tag) {
+        Some(idx) => amounts[idx].raw += raw,
+        None => amounts.push(DamageAmount {
+            adjusted: 0,
+            raw,
+            tag: tag.to_string(),
+        }),
+    }
+}
+
+#[test]
+fn it_resists_and_doubles_damage_by_type() {
+    let flags = RollFlags::builder().n(2).die(DieType::D6).modifier(3).tag("fire").build().unwrap();
+    let fire = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![4, 5]));
+    let flags = RollFlags::builder().n(1).die(DieType::D4).tag("piercing").build().unwrap();
+    let piercing = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3]));
+    let profile = DamageProfile::new().resist("fire").vulnerable("piercing");
+
+    let report = profile.apply(vec![&fire, &piercing]);
+    assert_eq!(report.get("fire"), Some(&DamageAmount {
+        adjusted: 6,
+        raw: 12,
+        tag: "fire".to_string(),
+    }));
+    assert_eq!(report.get("piercing").map(|amount| (amount.raw, amount.adjusted)), Some((3, 6)));
+    assert_eq!(report.total, 12);
+
+    // The rolls are left as they were
+    assert_eq!((fire.value, piercing.value), (12, 3));
+}
+
+#[test]
+fn it_zeroes_immune_damage_with_its_modifier() {
+    let flags = RollFlags::builder().n(1).die(DieType::D8).modifier(4).tag("poison").build().unwrap();
+    let poison = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6]));
+    let flags = RollFlags::builder().n(1).die(DieType::D6).modifier(2).build().unwrap();
+    let untagged = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3]));
+    let profile = DamageProfile::new().immune("poison").default_tag("bludgeoning");
+
+    let report = profile.apply(vec![&poison, &untagged]);
+    assert_eq!(report.get("poison").map(|amount| (amount.raw, amount.adjusted)), Some((10, 0)));
+    assert_eq!(report.get("bludgeoning").map(|amount| amount.adjusted), Some(5));
+    assert_eq!(report.total, 5);
+    assert_eq!(poison.apply_damage_profile(&profile).total, 0);
+}
+
+#[test]
+fn it_rounds_odd_resisted_damage() {
+    let flags = RollFlags::builder().n(3).die(DieType::D6).tag("fire").build().unwrap();
+    let fire = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![4, 5, 6]));
+    let resisted = DamageProfile::new().resist("fire");
+    assert_eq!(fire.apply_damage_profile(&resisted.clone().rounding(Rounding::Floor)).total, 7);
+    assert_eq!(fire.apply_damage_profile(&resisted.clone().rounding(Rounding::Ceil)).total, 8);
+
+    // Resisted and vulnerable halves first, keeping the rounding
+    let both = resisted.vulnerable("fire").rounding(Rounding::Floor);
+    assert_eq!(fire.apply_damage_profile(&both).total, 14);
+}
+
+#[test]
+fn it_credits_the_modifier_to_its_own_terms_type() {
+    // `1d8[slashing]+3` with a `1d6[fire]` term after it, all in one roll the way an import has it
+    let flags = RollFlags::builder().n(1).die(DieType::D8).modifier(3).tag("slashing").build().unwrap();
+    let mut roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![5]));
+    let mut fire = roll.dice[0].clone();
+    fire.tags = vec!["fire".to_string()];
+    fire.term_index = 1;
+    fire.value = 4;
+    roll.dice.push(fire);
+    roll.value += 4;
+
+    let report = DamageProfile::new().resist("fire").apply(vec![&roll]);
+    assert_eq!(report.get("slashing").map(|amount| (amount.raw, amount.adjusted)), Some((8, 8)));
+    assert_eq!(report.get("fire").map(|amount| (amount.raw, amount.adjusted)), Some((4, 2)));
+    assert_eq!(report.total, 10);
+}
@@ -0,0 +1,204 @@
+use fairness::{commit_seed, shuffled_deck, SeedRecipe};
+use std::fmt;
+#[cfg(test)]
+use fairness::{verify_deck, verify_seed};
+
+/// Suits from lowest to highest, the order Savage Worlds breaks ties in
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Suit {
+    Clubs,
+    Diamonds,
+    Hearts,
+    Spades,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Joker {
+    Black,
+    Red,
+}
+
+/// A playing card, ordered the way Savage Worlds deals initiative: by rank, then by suit, with the
+/// jokers above every other card
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Card {
+    /// Rank from 2 to 14, with 11 to 14 for jack, queen, king and ace
+    Standard { rank: u8, suit: Suit },
+    Joker(Joker),
+}
+
+impl Card {
+    /// Every card of a 54 card deck in a fixed order, the order shuffles start from
+    ///
+    /// Clubs from 2 to ace, then diamonds, hearts and spades the same way, then the black joker
+    /// and the red joker. Shuffles of decks that have already been dealt rely on this order, so it
+    /// never changes.
+    pub fn canonical_deck() -> Vec<Card> {
+        let mut cards = vec![];
+        for &suit in [Suit::Clubs, Suit::Diamonds, Suit::Hearts, Suit::Spades].iter() {
+            for rank in 2..15 {
+                cards.push(Card::Standard {
+                    rank,
+                    suit,
+                });
+            }
+        }
+        cards.push(Card::Joker(Joker::Black));
+        cards.push(Card::Joker(Joker::Red));
+        cards
+    }
+}
+
+/// Written short, e.g. `10D`, `AS` or `RJ`
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Card::Standard { rank, suit } => {
+                let suit = match suit {
+                    Suit::Clubs => "C",
+                    Suit::Diamonds => "D",
+                    Suit::Hearts => "H",
+                    Suit::Spades => "S",
+                };
+                match rank {
+                    11 => write!(f, "J{}", suit),
+                    12 => write!(f, "Q{}", suit),
+                    13 => write!(f, "K{}", suit),
+                    14 => write!(f, "A{}", suit),
+                    rank => write!(f, "{}{}", rank, suit),
+                }
+            },
+            Card::Joker(Joker::Black) => write!(f, "BJ"),
+            Card::Joker(Joker::Red) => write!(f, "RJ"),
+        }
+    }
+}
+
+/// A deck shuffled from a server secret and a client seed, so players can check every draw once
+/// the secret is revealed
+///
+/// Hand out the `commitment` before drawing. Each shuffle uses the next nonce of the recipe, so
+/// the draws of each shuffle are verified on their own, see `fairness::verify_deck`.
+pub struct Deck {
+    cards: Vec<Card>,
+    drawn: usize,
+    recipe: SeedRecipe,
+}
+
+impl Deck {
+    /// Shuffle a full deck with the recipe at its nonce
+    pub fn new(recipe: SeedRecipe) -> Deck {
+        Deck {
+            cards: shuffled_deck(&recipe),
+            drawn: 0,
+            recipe,
+        }
+    }
+
+    /// Commitment to the server secret, see `fairness::commit_seed`
+    pub fn commitment(&self) -> String {
+        commit_seed(&self.recipe.server)
+    }
+
+    /// Nonce of the current shuffle
+    pub fn nonce(&self) -> u64 {
+        self.recipe.nonce
+    }
+
+    /// Take the top card, none once the deck is empty
+    pub fn draw(&mut self) -> Option<Card> {
+        let card = self.cards.get(self.drawn).cloned();
+        if card.is_some() {
+            self.drawn += 1;
+        }
+        card
+    }
+
+    /// Cards drawn since the last shuffle, in the order they were drawn
+    pub fn drawn(&self) -> &[Card] {
+        &self.cards[..self.drawn]
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.cards.len() - self.drawn
+    }
+
+    /// Put every card back and shuffle again with the next nonce, e.g. after a joker is dealt
+    pub fn reshuffle(&mut self) {
+        self.recipe.nonce += 1;
+        self.cards = shuffled_deck(&self.recipe);
+        self.drawn = 0;
+    }
+
+    /// The recipe of the current shuffle, giving away the server secret
+    ///
+    /// Only reveal it once the shuffle is done with, players can work out every card left from it.
+    pub fn reveal(&self) -> SeedRecipe {
+        self.recipe.clone()
+    }
+}
+
+#[cfg(test)]
+fn recipe() -> SeedRecipe {
+    let mut server = [0u8; 32];
+    for (i, byte) in server.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    SeedRecipe {
+        server,
+        client: "client-seed".to_string(),
+        nonce: 0,
+    }
+}
+
+#[cfg(test)]
+fn codes(cards: &[Card]) -> String {
+    cards.iter().map(|card| card.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+#[test]
+fn it_shuffles_known_permutations() {
+    let deck = Card::canonical_deck();
+    assert_eq!(deck.len(), 54);
+    assert_eq!(codes(&deck[..3]), "2C 3C 4C");
+    assert!(deck[1] < deck[13] && deck[12] < deck[52]);
+
+    assert_eq!(codes(&shuffled_deck(&recipe())), "4S 5C BJ 3D QD 2H 10C RJ 2S 5S 9C 4D KD 5D JD 8D AC 7S \
+                                                   QH AH 5H 3S KH 6S 4C 7C QC 6H 3C AS 3H 2D 9H 8S AD 10D \
+                                                   QS KS 6C 9D 7D 8H KC 9S 4H 10S 10H 2C JH JS 6D 8C JC 7H");
+
+    // A reshuffle uses the next nonce
+    let mut deck = Deck::new(recipe());
+    deck.reshuffle();
+    assert_eq!(deck.nonce(), 1);
+    let drawn: Vec<Card> = (0..10).filter_map(|_| deck.draw()).collect();
+    assert_eq!(codes(&drawn), "8C 10S 5H 6S 2H AC 9H QH 7S JS");
+}
+
+#[test]
+fn it_verifies_the_draws_of_each_shuffle() {
+    let mut deck = Deck::new(recipe());
+    let commitment = deck.commitment();
+    for _ in 0..5 {
+        deck.draw();
+    }
+    let first: Vec<Card> = deck.drawn().to_vec();
+    let first_recipe = deck.reveal();
+    deck.reshuffle();
+    while deck.draw().is_some() {}
+    assert_eq!(deck.remaining(), 0);
+
+    let revealed = deck.reveal();
+    assert!(verify_seed(&commitment, &revealed.server));
+    assert!(verify_deck(&first_recipe, &first));
+    assert!(verify_deck(&revealed, deck.drawn()));
+
+    // The first shuffle's draws don't verify under the second's nonce
+    assert!(!verify_deck(&revealed, &first));
+
+    // Swapping two draws fails
+    let mut swapped = first.clone();
+    swapped.swap(1, 2);
+    assert!(!verify_deck(&first_recipe, &swapped));
+    assert!(!verify_seed(&commitment, &[7; 32]));
+}
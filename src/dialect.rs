@@ -0,0 +1,235 @@
+use parser::{parse_notation_with, ParseError, ParseOptions};
+#[cfg(test)]
+use parser::parse_notation;
+
+/// Whose notation to read, as virtual tabletops write some modifiers differently
+///
+/// Only how rolls are written differs, a roll reads as the same flags whichever dialect it was
+/// written in:
+///
+/// | Roll                     | Standard  | Roll20   | Foundry     |
+/// |--------------------------|-----------|----------|-------------|
+/// | Keep the highest         | `4d6kh3`  | `4d6k3`  | `4d6k3`     |
+/// | Reroll until it's not 1  | `2d6rr1`  | `2d6r1`  | `2d6rr1`    |
+/// | Reroll a 1 once          | `2d6ro1`  | `2d6ro1` | `2d6r1`     |
+/// | Count 5s and up          | `8d6gte5` | `8d6>5`  | `8d6cs>=5`  |
+/// | Explode                  | `3d6!`    | `3d6!`   | `3d6x`      |
+///
+/// Writing a modifier from one dialect while reading another fails with
+/// `ParseError::UnsupportedInDialect` rather than rolling something else.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dialect {
+    Foundry,
+    Roll20,
+    Standard,
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect::Standard
+    }
+}
+
+impl Dialect {
+    /// Every dialect, standard notation first
+    pub const ALL: [Dialect; 3] = [Dialect::Standard, Dialect::Roll20, Dialect::Foundry];
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Dialect::Foundry => "Foundry",
+            Dialect::Roll20 => "Roll20",
+            Dialect::Standard => "Standard",
+        }
+    }
+}
+
+/// How likely notation is to have been written in a dialect
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Confidence {
+    /// The notation can't be read in the dialect
+    Unlikely,
+
+    /// The notation reads the same in the dialect as in standard notation
+    Possible,
+
+    /// The notation uses modifiers written the dialect's way
+    Likely,
+}
+
+/// Guess which dialects notation was written in, most likely first, e.g. to pick one for a macro
+/// pasted in
+///
+/// Notation only written the standard way is possibly any dialect that reads it. Some notation
+/// can't be told apart, e.g. `2d6r1` rerolls forever in Roll20 and once in Foundry, so both are
+/// likely.
+pub fn detect_dialect(input: &str) -> Vec<(Dialect, Confidence)> {
+    let mut detected: Vec<(Dialect, Confidence)> = Dialect::ALL.iter()
+        .map(|&dialect| {
+            let confidence = match parse_notation_with(input, &ParseOptions::new().dialect(dialect)) {
+                Err(_) => Confidence::Unlikely,
+                Ok(_) if translate(input, dialect).ok().map_or(false, |translated| translated != input) => Confidence::Likely,
+                Ok(_) => Confidence::Possible,
+            };
+            (dialect, confidence)
+        })
+        .collect();
+
+    // The sort is stable, which keeps the standard dialect first of those as likely
+    detected.sort_by(|a, b| b.1.cmp(&a.1));
+    detected
+}
+
+/// Write notation in a dialect as standard notation, leaving comments, sides and tags as they were
+pub fn translate(command: &str, dialect: Dialect) -> Result<String, ParseError> {
+    let chars: Vec<char> = command.chars().collect();
+    let mut translated = String::with_capacity(command.len());
+    let mut in_comment = false;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' => in_comment = !in_comment,
+            '[' if !in_comment => depth += 1,
+            ']' if !in_comment && depth > 0 => depth -= 1,
+            _ => {},
+        }
+
+        // Modifiers follow the sides of the dice, or the number or brackets of another modifier
+        let follows_dice = !in_comment && depth == 0 && i > 0 && (chars[i - 1].is_digit(10) || chars[i - 1] == ']');
+        let rewritten = if follows_dice { modifier(&chars[i..], dialect)? } else { None };
+        match rewritten {
+            Some((standard, len)) => {
+                translated += standard;
+                i += len;
+            },
+            None => {
+                translated.push(c);
+                i += 1;
+            },
+        }
+    }
+    Ok(translated)
+}
+
+/// The standard way of writing the modifier at the start of the characters, and how many
+/// characters it was written with, none when it's already written the standard way
+fn modifier(chars: &[char], dialect: Dialect) -> Result<Option<(&'static str, usize)>, ParseError> {
+    let starts = |text: &str| text.chars().enumerate().all(|(i, c)| chars.get(i) == Some(&c));
+    let before_number = |len: usize| chars.get(len).map_or(false, |c| c.is_digit(10));
+    let unsupported = |name: &'static str| Err(ParseError::UnsupportedInDialect(name, dialect));
+
+    match dialect {
+        Dialect::Standard => {
+            if starts("r") && !starts("rr") && !starts("ro") {
+                return unsupported("r");
+            }
+            if starts("k") && before_number(1) {
+                return unsupported("k");
+            }
+            for name in &["x", "cs", ">", "<"] {
+                if starts(name) {
+                    return unsupported(name);
+                }
+            }
+            Ok(None)
+        },
+        Dialect::Roll20 => {
+            if starts("rr") {
+                return unsupported("rr");
+            }
+            if starts("ro") {
+                return Ok(None);
+            }
+            for name in &["x", "cs"] {
+                if starts(name) {
+                    return unsupported(name);
+                }
+            }
+
+            // Roll20's `>` counts dice landing on the number too
+            let rewrites = [("r", "rr"), (">", "gte"), ("<", "lte")];
+            if let Some(&(written, standard)) = rewrites.iter().find(|&&(written, _)| starts(written)) {
+                return Ok(Some((standard, written.len())));
+            }
+            if starts("k") && before_number(1) {
+                return Ok(Some(("kh", 1)));
+            }
+            Ok(None)
+        },
+        Dialect::Foundry => {
+            // Foundry's minimum and maximum raise or lower each die rather than changing its faces
+            for name in &["min", "max", "ro", ">", "<"] {
+                if starts(name) {
+                    return unsupported(name);
+                }
+            }
+
+            // Longer spellings go first, so `rr=` isn't read as `r`
+            let rewrites = [("rr=", "rr"), ("rr", "rr"), ("r=", "ro"), ("r", "ro"), ("cs>=", "gte"),
+                            ("cs<=", "lte"), ("cs>", "gt"), ("cs<", "lt")];
+            if let Some(&(written, standard)) = rewrites.iter().find(|&&(written, _)| starts(written)) {
+                return Ok(Some((standard, written.len())));
+            }
+            if starts("x") && !starts("xo") {
+                return Ok(Some(("!", 1)));
+            }
+            if starts("k") && before_number(1) {
+                return Ok(Some(("kh", 1)));
+            }
+            Ok(None)
+        },
+    }
+}
+
+#[test]
+fn it_reads_every_dialect_as_the_same_roll() {
+    let rolls = [
+        ("4d6kh3", "4d6k3", "4d6k3"),
+        ("2d6rr1", "2d6r1", "2d6rr1"),
+        ("2d6rr<2", "2d6r<2", "2d6rr=1"),
+        ("2d20ro1", "2d20ro1", "2d20r1"),
+        ("2d20ro<3", "2d20ro<3", "2d20r<3"),
+        ("8d6gte5", "8d6>5", "8d6cs>=5"),
+        ("8d10lte2", "8d10<2", "8d10cs<=2"),
+        ("3d6!", "3d6!", "3d6x"),
+        ("1d10!>8", "1d10!>8", "1d10x>8"),
+        ("2d6[fire]!+3", "2d6[fire]!+3", "2d6[fire]x+3"),
+    ];
+    for &(standard, roll20, foundry) in rolls.iter() {
+        let expected = parse_notation(standard).unwrap()[0].canonical();
+        for &(notation, dialect) in [(standard, Dialect::Standard), (roll20, Dialect::Roll20), (foundry, Dialect::Foundry)].iter() {
+            let flags = parse_notation_with(notation, &ParseOptions::new().dialect(dialect));
+            assert_eq!(flags.map(|flags| flags[0].canonical()), Ok(expected.clone()),
+                       "{} in {} isn't {}", notation, dialect.name(), standard);
+        }
+    }
+
+    // Comments are left as they were written
+    let flags = parse_notation_with("1d20x \"x marks the spot\"", &ParseOptions::new().dialect(Dialect::Foundry)).unwrap();
+    assert!(flags[0].equation.contains("x marks the spot"));
+}
+
+#[test]
+fn it_refuses_modifiers_from_another_dialect() {
+    let parse_in = |notation: &str, dialect: Dialect| parse_notation_with(notation, &ParseOptions::new().dialect(dialect)).err();
+    assert_eq!(parse_in("2d6r1", Dialect::Standard), Some(ParseError::UnsupportedInDialect("r", Dialect::Standard)));
+    assert_eq!(parse_in("4d6k3", Dialect::Standard), Some(ParseError::UnsupportedInDialect("k", Dialect::Standard)));
+    assert_eq!(parse_in("3d6x", Dialect::Roll20), Some(ParseError::UnsupportedInDialect("x", Dialect::Roll20)));
+    assert_eq!(parse_in("2d6rr1", Dialect::Roll20), Some(ParseError::UnsupportedInDialect("rr", Dialect::Roll20)));
+    assert_eq!(parse_in("8d6>5", Dialect::Foundry), Some(ParseError::UnsupportedInDialect(">", Dialect::Foundry)));
+    assert_eq!(parse_in("1d20min10", Dialect::Foundry), Some(ParseError::UnsupportedInDialect("min", Dialect::Foundry)));
+}
+
+#[test]
+fn it_guesses_the_dialect() {
+    assert_eq!(detect_dialect("8d6>5"), vec![(Dialect::Roll20, Confidence::Likely),
+                                           (Dialect::Standard, Confidence::Unlikely),
+                                           (Dialect::Foundry, Confidence::Unlikely)]);
+    assert_eq!(detect_dialect("3d6x")[0], (Dialect::Foundry, Confidence::Likely));
+
+    // Standard notation could be from anywhere that reads it
+    assert_eq!(detect_dialect("2d6rr1"), vec![(Dialect::Standard, Confidence::Possible),
+                                            (Dialect::Foundry, Confidence::Possible),
+                                            (Dialect::Roll20, Confidence::Unlikely)]);
+}
@@ -1,53 +1,77 @@
 use chrono::DateTime;
 use chrono::prelude::Utc;
-use rand::distributions::{IndependentSample, Range};
-use rand;
+pub use eval::DieType;
+use roll::RollError;
+use roller::Roller;
+use std::cmp::Ordering;
+use std::fmt::Write;
+#[cfg(test)]
+use testing::ScriptedRoller;
 use uuid::Uuid;
 
+/// How many faces of a Fate die show a plus, a blank and a minus
 #[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
-pub enum DieType {
-    D4,
-    D6,
-    D8,
-    D10,
-    D12,
-    D20,
-    D100,
-    Fate,
-    Other,
-}
-
-/// Determine the minimum number to roll based on the die type
-fn get_die_min(die: &DieType) -> i16 {
-    match die {
-        &DieType::D4 => 1,
-        &DieType::D6 => 1,
-        &DieType::D8 => 1,
-        &DieType::D10 => 1,
-        &DieType::D12 => 1,
-        &DieType::D20 => 1,
-        &DieType::D100 => 1,
-        &DieType::Fate => -1,
-        &DieType::Other => 0,
-    }
-}
-
-/// Determine the minimum number to roll based on the die type
-fn get_die_max(die: &DieType) -> i16 {
-    match die {
-        &DieType::D4 => 4,
-        &DieType::D6 => 6,
-        &DieType::D8 => 8,
-        &DieType::D10 => 10,
-        &DieType::D12 => 12,
-        &DieType::D20 => 20,
-        &DieType::D100 => 100,
-        &DieType::Fate => 1,
-        &DieType::Other => 0,
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize)]
+pub struct FateFaces {
+    pub blank: u8,
+    pub minus: u8,
+    pub plus: u8,
+}
+
+impl FateFaces {
+    /// Count the faces of a Fate die from its sides, none if a side isn't -1, 0 or 1
+    pub fn from_sides(sides: &[i16]) -> Option<FateFaces> {
+        if sides.iter().any(|side| *side < -1 || *side > 1) {
+            return None;
+        }
+        let count = |face: i16| sides.iter().filter(|side| **side == face).count() as u8;
+        Some(FateFaces {
+            blank: count(0),
+            minus: count(-1),
+            plus: count(1),
+        })
+    }
+
+    /// The sides of a die with these faces, each face repeated by its count
+    pub fn sides(&self) -> Vec<i16> {
+        let mut sides = vec![1; self.plus as usize];
+        sides.extend(vec![0; self.blank as usize]);
+        sides.extend(vec![-1; self.minus as usize]);
+        sides
+    }
+}
+
+/// A face a d20 is set to instead of being rolled, e.g. taking 10 on a check with time to spare
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TakeKind {
+    Ten,
+    Twenty,
+
+    /// Any other face, e.g. one a feat lets a character take
+    Value(i16),
+}
+
+impl TakeKind {
+    /// The face taken
+    pub fn face(&self) -> i16 {
+        match *self {
+            TakeKind::Ten => 10,
+            TakeKind::Twenty => 20,
+            TakeKind::Value(face) => face,
+        }
+    }
+}
+
+/// One time a die was rolled
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RollRecord {
+    /// When the die was rolled
+    pub timestamp: DateTime<Utc>,
+
+    /// The value the die landed on
+    pub value: i16,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Die {
     /// Unique identifier of the die
     pub id: String,
@@ -58,9 +82,29 @@ pub struct Die {
     /// The type of die (e.g. d20, d100)
     pub die: DieType,
 
+    /// Faces of a Fate die with a custom number of plus, blank and minus faces
+    pub fate_faces: Option<FateFaces>,
+
+    /// The face of a half die halved and rounded up, which it counts for in place of its value, none
+    /// for whole dice
+    #[serde(default)]
+    pub halved: Option<i16>,
+
     /// If the die is dropped in the final roll
     pub is_dropped: bool,
 
+    /// If the die landed on its highest face and exploded into another roll
+    #[serde(default)]
+    pub is_exploded: bool,
+
+    /// If the die refuses to be rolled again once it has been rolled
+    #[serde(default)]
+    pub is_immutable: bool,
+
+    /// If the value was entered by hand rather than rolled
+    #[serde(default)]
+    pub is_manual: bool,
+
     /// If the die is dropped in the final roll
     pub is_rerolled: bool,
 
@@ -73,9 +117,39 @@ pub struct Die {
     /// Minimum number to roll
     pub min: i16,
 
+    /// The face the die landed on, when a blessing or curse moved its value, see `Roll::adjust`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub natural: Option<i16>,
+
+    /// Where the die comes in its term, counting from 0 in the order the term's dice were rolled
+    ///
+    /// Dice the term started with come first, then those added by rerolls and explosions.
+    #[serde(default)]
+    pub position_in_term: u16,
+
+    /// Every value the die has landed on, oldest first, the last being its value
+    ///
+    /// A die made by rerolling another starts with the other die's history.
+    #[serde(default)]
+    pub roll_history: Vec<RollRecord>,
+
     /// Custom sides
     pub sides: Option<Vec<i16>>,
 
+    /// The face the die was set to without rolling, e.g. when taking 10, none for dice that were
+    /// rolled or entered, see `Roller::take`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken: Option<TakeKind>,
+
+    /// Tags given to the die's term in notation, e.g. `fire` in `2d6[fire]`
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Which term of the notation rolled the die, counting from 0, e.g. 1 for the `1d6` of
+    /// `2d6 + 1d6`
+    #[serde(default)]
+    pub term_index: u16,
+
     /// Timestamp of the roll
     pub timestamp: DateTime<Utc>,
 
@@ -86,66 +160,252 @@ pub struct Die {
 impl Die {
     pub fn new (die: DieType) -> Die {
         Die {
-            id: Uuid::new_v4().to_string(),
+            id: new_id(),
             child: None,
             die,
+            fate_faces: None,
+            halved: None,
             is_dropped: false,
+            is_exploded: false,
+            is_immutable: false,
+            is_manual: false,
             is_rerolled: false,
             is_successful: false,
-            max: get_die_max(&die),
-            min: get_die_min(&die),
+            max: die.max_face(),
+            min: die.min_face(),
+            natural: None,
+            position_in_term: 0,
+            roll_history: vec![],
             sides: None,
+            taken: None,
+            tags: vec![],
+            term_index: 0,
             timestamp: Utc::now(),
             value: 0,
         }
     }
 
+    /// Create a die landing between a min and max, inclusive, e.g. `Die::with_range(DieType::Other, -8, -5)`
+    pub fn with_range(die: DieType, min: i16, max: i16) -> Die {
+        Die {
+            max,
+            min,
+            ..Die::new(die)
+        }
+    }
+
     /// Drop the die from the final roll
     pub fn drop(&mut self) {
+        #[cfg(feature = "tracing")]
+        debug!(id = %self.id, value = self.value, "drop");
         self.is_dropped = true
     }
 
+    /// If the die landed on its highest face, whatever a blessing moved it to
+    pub fn is_critical(&self) -> bool {
+        self.landed_on() == self.max
+    }
+
+    /// If the die landed on its lowest face, whatever a curse moved it to
+    pub fn is_fumble(&self) -> bool {
+        self.landed_on() == self.min
+    }
+
+    /// The face the die landed on, its value unless a blessing or curse moved it
+    pub fn landed_on(&self) -> i16 {
+        self.natural.unwrap_or(self.value)
+    }
+
+    /// If the die is able to land on the value
+    pub fn has_face(&self, value: i16) -> bool {
+        match self.sides {
+            Some(ref sides) => sides.contains(&value),
+            None => value >= self.min && value <= self.max,
+        }
+    }
+
+    /// Every face the die can land on
+    pub fn faces(&self) -> Vec<i16> {
+        match self.sides {
+            Some(ref sides) => sides.clone(),
+            None => (self.min as i32..self.max as i32 + 1).map(|face| face as i16).collect(),
+        }
+    }
+
+    /// What the die counts for in a total, its value or the half die's halved value
+    pub fn counted(&self) -> i16 {
+        self.halved.unwrap_or(self.value)
+    }
+
+    /// If the die's term was given the tag
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
     /// Mark the die as successful to the a comparison
     pub fn success(&mut self) {
         self.is_successful = true
     }
 
     pub fn rerolled (&mut self, die: &Die) {
+        #[cfg(feature = "tracing")]
+        debug!(id = %self.id, value = self.value, child = %die.id, "reroll");
         self.is_rerolled = true;
         let id = &die.id;
         self.child = Some(id.to_owned());
     }
 
+    /// Create a Fate die weighted by its number of plus, blank and minus faces
+    pub fn fate_with_faces(plus: u8, blank: u8, minus: u8) -> Result<Die, RollError> {
+        let faces = FateFaces {
+            blank,
+            minus,
+            plus,
+        };
+        let mut die = Die::new(DieType::Fate);
+        die.set_sides(faces.sides())?;
+        die.fate_faces = Some(faces);
+        Ok(die)
+    }
+
+    /// A new, unrolled die with the same faces as this one
+    pub fn unrolled_copy(&self) -> Die {
+        let mut die = Die::new(self.die);
+        die.fate_faces = self.fate_faces;
+        die.max = self.max;
+        die.min = self.min;
+        die.sides = self.sides.clone();
+        die.tags = self.tags.clone();
+        die.term_index = self.term_index;
+        die
+    }
+
+    /// A new die with the same faces as this one, carrying on its history, to roll in its place
+    pub fn rerolled_copy(&self) -> Die {
+        let mut die = self.unrolled_copy();
+        die.roll_history = self.roll_history.clone();
+        die
+    }
+
+    /// Number of times the die has been rolled
+    pub fn roll_count(&self) -> usize {
+        self.roll_history.len()
+    }
+
+    /// Create a die and roll it
+    pub fn roll_new(die: DieType) -> Die {
+        Die::new(die).rolled()
+    }
+
     /// Roll the die, generating a random number and calculating any modifiers
+    #[deprecated(since = "0.1.0", note = "use `roll_value`, `rolled` or `Die::roll_new` instead")]
     pub fn roll(&mut self) -> &Die {
-        // generate a random number
-        match &self.sides {
-            &Some(ref sides) => {
-                let between = Range::new(0, sides.len());
-                let mut rng = rand::thread_rng();
-                let idx = between.ind_sample(&mut rng);
-                let roll = sides[idx];
-                self.value = roll;
-                self.is_successful = true;
-            },
-            &None => {
-                let between = Range::new(self.min, self.max);
-                let mut rng = rand::thread_rng();
-                let roll = between.ind_sample(&mut rng);
-                self.value = roll;
-                self.is_successful = true;
-            }
-        }
+        self.roll_with(&mut Roller::new());
+        self
+    }
+
+    /// Roll the die, returning the rolled value
+    pub fn roll_value(&mut self) -> i16 {
+        self.roll_with(&mut Roller::new())
+    }
+
+    /// Roll the die and hand it back, e.g. `Die::new(DieType::D20).rolled()`
+    pub fn rolled(mut self) -> Die {
+        self.roll_value();
         self
     }
 
+    /// Roll the die using the given source of randomness, returning the rolled value
+    ///
+    /// An immutable die that has already been rolled keeps its value, use `try_roll_with` to find
+    /// out when that happens.
+    pub fn roll_with(&mut self, roller: &mut Roller) -> i16 {
+        match self.try_roll_with(roller) {
+            Ok(value) => value,
+            Err(_) => self.value,
+        }
+    }
+
+    /// Roll the die using the given source of randomness, unless it's immutable and already rolled
+    pub fn try_roll_with(&mut self, roller: &mut Roller) -> Result<i16, RollError> {
+        if self.is_immutable && self.roll_count() > 0 {
+            return Err(RollError::AlreadyRolled);
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = trace_span!("die", id = %self.id, die = ?self.die).entered();
+
+        // generate a random number, keeping the min and max in line with any custom sides
+        let value = match &self.sides {
+            &Some(ref sides) if !sides.is_empty() => {
+                self.min = *sides.iter().min().unwrap();
+                self.max = *sides.iter().max().unwrap();
+                roller.side(sides)
+            },
+            _ => roller.between(self.min, self.max),
+        };
+        Ok(self.land_on(value))
+    }
+
+    /// Roll the die landing only on one of the given faces, e.g. those no other die landed on
+    pub fn roll_among(&mut self, faces: &[i16], roller: &mut Roller) -> i16 {
+        let value = roller.side(faces);
+        self.land_on(value)
+    }
+
+    fn land_on(&mut self, value: i16) -> i16 {
+        self.natural = None;
+        self.value = value;
+        self.is_successful = true;
+        self.roll_history.push(RollRecord {
+            timestamp: Utc::now(),
+            value: self.value,
+        });
+
+        #[cfg(feature = "tracing")]
+        trace!(id = %self.id, value = self.value, "roll");
+        self.value
+    }
+
+    #[deprecated(since = "0.1.0", note = "use `Die::with_range` or `set_sides` instead")]
     pub fn set_min(&mut self, min: i16) {
         self.min = min;
     }
 
+    #[deprecated(since = "0.1.0", note = "use `Die::with_range` or `set_sides` instead")]
     pub fn set_max(&mut self, max: i16) {
         self.max = max;
     }
+
+    /// Use custom sides, setting the min and max to the lowest and highest of them
+    ///
+    /// The sides keep their order, so seeded rolls land on the same faces as before.
+    pub fn set_sides(&mut self, sides: Vec<i16>) -> Result<(), RollError> {
+        if sides.is_empty() {
+            return Err(RollError::NoSides);
+        }
+        self.min = *sides.iter().min().unwrap();
+        self.max = *sides.iter().max().unwrap();
+        self.sides = Some(sides);
+        Ok(())
+    }
+
+    /// Compare dice as they come in notation, by term and then by position in the term, with IDs
+    /// breaking ties between dice of different rolls
+    ///
+    /// Use it with `sort_by`, e.g. `Roll::dice_in_notation_order`. Dice the same by this order can
+    /// still differ in their values, so it isn't what `==` means for a die.
+    pub fn cmp_in_notation(&self, other: &Die) -> Ordering {
+        (self.term_index, self.position_in_term).cmp(&(other.term_index, other.position_in_term))
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+/// A new random ID, written into a string sized for it up front rather than grown as it's written
+pub fn new_id() -> String {
+    let mut id = String::with_capacity(36);
+    write!(id, "{}", Uuid::new_v4()).expect("writing to a string can't fail");
+    id
 }
 
 #[test]
@@ -168,6 +428,7 @@ fn it_can_create_dice() {
 }
 
 #[test]
+#[allow(deprecated)]
 fn it_can_set_die_min() {
     let mut custom = Die::new(DieType::Other);
     custom.set_min(-5);
@@ -176,6 +437,7 @@ fn it_can_set_die_min() {
 }
 
 #[test]
+#[allow(deprecated)]
 fn it_can_set_die_max() {
     let mut custom = Die::new(DieType::Other);
     custom.set_max(-50);
@@ -184,25 +446,185 @@ fn it_can_set_die_max() {
 }
 
 #[test]
+#[allow(deprecated)]
 fn it_can_roll_die() {
     let mut die = Die::new(DieType::D20);
-    die.roll();
-    assert!(die.value >= 1);
-    assert!(die.value <= 20);
+    let value = die.roll_value();
+    assert!(value >= 1);
+    assert!(value <= 20);
+    assert_eq!(die.value, value);
 
     let mut custom = Die::new(DieType::Other);
     custom.set_max(-5);
     custom.set_min(-8);
-    custom.roll();
-    assert!(custom.value >= -8);
-    assert!(custom.value <= -5);
+    let value = custom.roll_value();
+    assert!(value >= -8);
+    assert!(value <= -5);
+}
+
+#[test]
+fn it_can_create_dice_with_a_range() {
+    let mut custom = Die::with_range(DieType::Other, -8, -5);
+    assert_eq!((custom.min, custom.max), (-8, -5));
+    assert_eq!(custom.roll_with(&mut ScriptedRoller::new(vec![-5])), -5);
+}
+
+#[test]
+fn it_can_roll_a_new_die() {
+    let die = Die::new(DieType::D6).rolled();
+    assert!(die.value >= 1 && die.value <= 6);
+
+    let die = Die::roll_new(DieType::D4);
+    assert_eq!(die.die, DieType::D4);
+    assert!(die.value >= 1 && die.value <= 4);
+    assert!(die.is_successful);
+}
+
+#[test]
+#[allow(deprecated)]
+fn it_can_still_roll_by_reference() {
+    let mut die = Die::new(DieType::D8);
+    let value = die.roll().value;
+    assert!(value >= 1 && value <= 8);
+}
+
+#[test]
+fn it_knows_its_faces() {
+    let d6 = Die::new(DieType::D6);
+    assert!(d6.has_face(1));
+    assert!(d6.has_face(6));
+    assert!(!d6.has_face(0));
+    assert!(!d6.has_face(7));
+
+    let mut custom = Die::new(DieType::Other);
+    custom.sides = Some(vec![2, 4]);
+    assert!(custom.has_face(4));
+    assert!(!custom.has_face(3));
 }
 
 #[test]
 fn it_can_roll_custom_sides() {
     let mut die = Die::new(DieType::Other);
     die.sides = Some(vec![2, 4, 6, 8, 10]);
-    die.roll();
-    assert_ne!(die.value, 0);
-    assert_eq!(die.value % 2, 0);
+    let value = die.roll_value();
+    assert_ne!(value, 0);
+    assert_eq!(value % 2, 0);
+}
+
+#[test]
+fn it_can_set_custom_sides() {
+    let mut die = Die::new(DieType::Other);
+    assert_eq!(die.set_sides(vec![6, 2, 2, 9, 4]), Ok(()));
+    assert_eq!(die.min, 2);
+    assert_eq!(die.max, 9);
+    assert_eq!(die.sides, Some(vec![6, 2, 2, 9, 4]));
+
+    assert_eq!(die.set_sides(vec![]), Err(RollError::NoSides));
+    assert_eq!(die.max, 9);
+}
+
+#[test]
+fn it_can_detect_criticals_on_custom_sides() {
+    let mut die = Die::new(DieType::Other);
+    die.set_sides(vec![3, 1, 7]).unwrap();
+    die.roll_with(&mut ScriptedRoller::new(vec![7]));
+    assert!(die.is_critical());
+    assert!(!die.is_fumble());
+
+    // Sides written directly are picked up when rolling
+    let mut die = Die::new(DieType::Other);
+    die.sides = Some(vec![-2, 5]);
+    die.roll_with(&mut ScriptedRoller::new(vec![-2]));
+    assert!(die.is_fumble());
+    assert_eq!(die.max, 5);
+}
+
+#[cfg(test)]
+fn fate_frequencies(die: &mut Die, rolls: usize) -> (f64, f64, f64) {
+    let mut roller = Roller::seeded(7);
+    let mut counts = [0; 3];
+    for _ in 0..rolls {
+        counts[(die.roll_with(&mut roller) + 1) as usize] += 1;
+    }
+    let share = |count: usize| count as f64 / rolls as f64;
+    (share(counts[2]), share(counts[1]), share(counts[0]))
+}
+
+#[test]
+fn it_can_weight_fate_faces() {
+    let mut skewed = Die::fate_with_faces(3, 2, 1).unwrap();
+    assert_eq!(skewed.fate_faces, Some(FateFaces { blank: 2, minus: 1, plus: 3 }));
+    assert_eq!((skewed.min, skewed.max), (-1, 1));
+
+    let (plus, blank, minus) = fate_frequencies(&mut skewed, 6000);
+    assert!((plus - 3.0 / 6.0).abs() < 0.03);
+    assert!((blank - 2.0 / 6.0).abs() < 0.03);
+    assert!((minus - 1.0 / 6.0).abs() < 0.03);
+
+    assert_eq!(Die::fate_with_faces(0, 0, 0).err(), Some(RollError::NoSides));
+}
+
+#[test]
+fn it_rolls_even_fate_faces_like_a_plain_fate_die() {
+    let (plus, blank, minus) = fate_frequencies(&mut Die::fate_with_faces(1, 1, 1).unwrap(), 6000);
+    let (plain_plus, plain_blank, plain_minus) = fate_frequencies(&mut Die::new(DieType::Fate), 6000);
+    for &share in [plus, blank, minus, plain_plus, plain_blank, plain_minus].iter() {
+        assert!((share - 1.0 / 3.0).abs() < 0.03);
+    }
+}
+
+#[test]
+fn it_keeps_a_history_of_rolls() {
+    let mut die = Die::new(DieType::D20);
+    assert_eq!(die.roll_count(), 0);
+    die.roll_with(&mut ScriptedRoller::new(vec![4]));
+    die.roll_with(&mut ScriptedRoller::new(vec![15]));
+
+    assert_eq!(die.roll_count(), 2);
+    let values: Vec<i16> = die.roll_history.iter().map(|record| record.value).collect();
+    assert_eq!(values, vec![4, 15]);
+    assert_eq!(die.value, 15);
+}
+
+#[test]
+fn it_refuses_to_roll_an_immutable_die_again() {
+    let mut die = Die::new(DieType::D20);
+    die.is_immutable = true;
+    assert_eq!(die.try_roll_with(&mut ScriptedRoller::new(vec![4])), Ok(4));
+    assert_eq!(die.try_roll_with(&mut ScriptedRoller::new(vec![15])), Err(RollError::AlreadyRolled));
+
+    // Rolling without checking leaves the first value in place
+    assert_eq!(die.roll_with(&mut ScriptedRoller::new(vec![15])), 4);
+    assert_eq!(die.roll_count(), 1);
+}
+
+#[test]
+fn it_serializes_the_roll_history() {
+    use serde_json;
+
+    let mut die = Die::new(DieType::D6);
+    die.roll_with(&mut ScriptedRoller::new(vec![2]));
+    die.roll_with(&mut ScriptedRoller::new(vec![5]));
+    let json: serde_json::Value = serde_json::to_value(&die).unwrap();
+    assert_eq!(json["roll_history"].as_array().map(|history| history.len()), Some(2));
+    assert_eq!(json["roll_history"][0]["value"], 2);
+}
+
+#[test]
+fn it_orders_dice_as_they_come_in_notation() {
+    let mut dice: Vec<Die> = (0..6).map(|i| {
+        let mut die = Die::new(DieType::D6);
+        die.term_index = (i % 2) as u16;
+        die.position_in_term = (5 - i) as u16;
+        die
+    }).collect();
+    dice.sort_by(|a, b| a.cmp_in_notation(b));
+    let places: Vec<(u16, u16)> = dice.iter().map(|die| (die.term_index, die.position_in_term)).collect();
+    assert_eq!(places, vec![(0, 1), (0, 3), (0, 5), (1, 0), (1, 2), (1, 4)]);
+
+    // Dice in the same place of different rolls are told apart by their IDs
+    let mut other = dice[0].clone();
+    assert_eq!(other.cmp_in_notation(&dice[0]), Ordering::Equal);
+    other.id = new_id();
+    assert!(other.cmp_in_notation(&dice[0]) != Ordering::Equal);
 }
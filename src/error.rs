@@ -0,0 +1,350 @@
+use history::HistoryError;
+use parser::ParseError;
+use roll::RollError;
+use serde_json::Value;
+use stats::StatsError;
+use webhook::WebhookError;
+#[cfg(test)]
+use dialect::Dialect;
+
+/// What went wrong with a request, as a stable code clients can match on
+///
+/// Codes are part of the API, so a variant is never renamed and its `as_str` never changes.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ErrorCode {
+    /// Two flags were set that can't be used together
+    #[serde(rename = "CONFLICTING_FLAGS")]
+    ConflictingFlags,
+
+    /// A history query parameter isn't valid
+    #[serde(rename = "INVALID_QUERY")]
+    InvalidQuery,
+
+    /// The roll parsed, but can't be rolled
+    #[serde(rename = "INVALID_ROLL")]
+    InvalidRoll,
+
+    /// The webhook can't be registered
+    #[serde(rename = "INVALID_WEBHOOK")]
+    InvalidWebhook,
+
+    /// The roll is bigger than the server allows
+    #[serde(rename = "LIMIT_EXCEEDED")]
+    LimitExceeded,
+
+    /// The route, room, participant or roll doesn't exist
+    #[serde(rename = "NOT_FOUND")]
+    NotFound,
+
+    /// The notation couldn't be parsed
+    #[serde(rename = "PARSE_ERROR")]
+    ParseError,
+
+    /// Too many requests were made with the same API token or from the same address
+    #[serde(rename = "RATE_LIMITED")]
+    RateLimited,
+
+    /// The request needs an API token it didn't have, e.g. the one that owns the room
+    #[serde(rename = "UNAUTHORIZED")]
+    Unauthorized,
+
+    /// Something the request needs, like shared roll history, can't be reached right now
+    #[serde(rename = "UNAVAILABLE")]
+    Unavailable,
+
+    /// Notation used an `@` variable that isn't known
+    #[serde(rename = "UNKNOWN_VARIABLE")]
+    UnknownVariable,
+
+    /// A flag was used on dice that don't support it
+    #[serde(rename = "UNSUPPORTED_MODIFIER")]
+    UnsupportedModifier,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ErrorCode::ConflictingFlags => "CONFLICTING_FLAGS",
+            ErrorCode::InvalidQuery => "INVALID_QUERY",
+            ErrorCode::InvalidRoll => "INVALID_ROLL",
+            ErrorCode::InvalidWebhook => "INVALID_WEBHOOK",
+            ErrorCode::LimitExceeded => "LIMIT_EXCEEDED",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::ParseError => "PARSE_ERROR",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Unavailable => "UNAVAILABLE",
+            ErrorCode::UnknownVariable => "UNKNOWN_VARIABLE",
+            ErrorCode::UnsupportedModifier => "UNSUPPORTED_MODIFIER",
+        }
+    }
+}
+
+/// The body of every error the API returns
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+
+    /// Structured facts about the error, e.g. the limit and the value past it, or null
+    pub details: Value,
+
+    /// A description of the error for people
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: &str, details: Value) -> ApiError {
+        ApiError {
+            code,
+            details,
+            message: message.to_string(),
+        }
+    }
+
+    /// A route or resource that doesn't exist
+    pub fn not_found() -> ApiError {
+        ApiError::new(ErrorCode::NotFound, "Resource not found.", Value::Null)
+    }
+
+    /// Too many requests were made, and another can be made after a number of seconds
+    pub fn rate_limited(retry_after: u64) -> ApiError {
+        ApiError::new(ErrorCode::RateLimited, "Too many requests, slow down.", json!({ "retry_after": retry_after }))
+    }
+
+    /// A roll that could roll more dice (first) than a request is allowed (second)
+    pub fn too_many_dice_rolled(dice: usize, max: usize) -> ApiError {
+        ApiError::new(ErrorCode::LimitExceeded, "Roll could roll too many dice.", json!({
+            "limit": "max_dice_rolled",
+            "max": max,
+            "value": dice
+        }))
+    }
+
+    /// The request didn't have the API token that owns the room
+    pub fn not_room_owner() -> ApiError {
+        ApiError::new(ErrorCode::Unauthorized, "Only the room's owner can do that.", json!({ "resource": "room" }))
+    }
+
+    /// Notation used a variable that isn't known, named without its `@`
+    pub fn unknown_variable(name: &str) -> ApiError {
+        ApiError::new(ErrorCode::UnknownVariable, &format!("The variable @{} isn't known.", name), json!({
+            "variable": name
+        }))
+    }
+
+    /// Add the expression the error came from to its details
+    pub fn in_expression(mut self, expression: &str) -> ApiError {
+        let mut details = match self.details {
+            Value::Object(details) => details,
+            _ => Default::default(),
+        };
+        details.insert("expression".to_string(), Value::String(expression.to_string()));
+        self.details = Value::Object(details);
+        self
+    }
+
+    /// Say where in a block of notation the error came from, in its message and details
+    pub fn at_line(mut self, line: usize, column: usize) -> ApiError {
+        let mut details = match self.details {
+            Value::Object(details) => details,
+            _ => Default::default(),
+        };
+        details.insert("column".to_string(), json!(column));
+        details.insert("line".to_string(), json!(line));
+        self.details = Value::Object(details);
+        self.message = format!("Line {}, column {}: {}", line, column, self.message);
+        self
+    }
+}
+
+impl From<RollError> for ApiError {
+    fn from(error: RollError) -> ApiError {
+        let message = error.to_string();
+        let (code, details) = match error {
+            RollError::AlreadyRolled | RollError::DivideByZero | RollError::EndlessExplosion |
+            RollError::EndlessReroll | RollError::NoRepeats | RollError::NoSides => (ErrorCode::InvalidRoll, Value::Null),
+            RollError::Cancelled | RollError::Saturated => (ErrorCode::Unavailable, Value::Null),
+            RollError::CompoundOverflow(most) => {
+                (ErrorCode::LimitExceeded, json!({ "limit": "compound_total", "max": i16::max_value(), "value": most }))
+            },
+            RollError::ConflictingFlags(first, second) => (ErrorCode::ConflictingFlags, json!({ "flags": [first, second] })),
+            RollError::DeadlineExceeded => (ErrorCode::LimitExceeded, json!({ "limit": "roll_deadline_ms" })),
+            RollError::AssignedTwice(id) | RollError::DroppedDie(id) => (ErrorCode::InvalidRoll, json!({ "die": id })),
+            RollError::FloorAboveCeiling(floor, ceiling) => {
+                (ErrorCode::InvalidRoll, json!({ "ceiling": ceiling, "floor": floor }))
+            },
+            RollError::InvalidNotation => (ErrorCode::ParseError, Value::Null),
+            RollError::MinAboveMax(min, max) => (ErrorCode::InvalidRoll, json!({ "max": max, "min": min })),
+            RollError::NotEnoughFaces(n, faces) => (ErrorCode::InvalidRoll, json!({ "dice": n, "faces": faces })),
+            RollError::TooManyDice(n, max) => {
+                (ErrorCode::LimitExceeded, json!({ "limit": "max_dice", "max": max, "value": n }))
+            },
+            RollError::TooManySides(sides, max) => {
+                (ErrorCode::LimitExceeded, json!({ "limit": "max_sides", "max": max, "value": sides }))
+            },
+            RollError::TooManyTerms(terms) => (ErrorCode::InvalidRoll, json!({ "terms": terms })),
+            RollError::UnknownDie(id) => (ErrorCode::NotFound, json!({ "id": id, "resource": "die" })),
+            RollError::ValueOutOfRange(index, value) => {
+                (ErrorCode::InvalidRoll, json!({ "index": index, "value": value }))
+            },
+            RollError::WrongValueCount(expected, found) => {
+                (ErrorCode::InvalidRoll, json!({ "expected": expected, "found": found }))
+            },
+        };
+        ApiError::new(code, &message, details)
+    }
+}
+
+impl From<ParseError> for ApiError {
+    fn from(error: ParseError) -> ApiError {
+        match error {
+            // TTML doesn't say where parsing stopped, so there's no span to give
+            ParseError::InvalidNotation => ApiError::new(ErrorCode::ParseError, "The roll couldn't be understood.", Value::Null),
+            ParseError::LookalikeCharacter(character, ascii) => {
+                let message = format!("The roll has a non-ASCII lookalike character {}, write it as {}.", character, ascii);
+                ApiError::new(ErrorCode::ParseError, &message, json!({
+                    "ascii": ascii.to_string(),
+                    "character": character.to_string()
+                }))
+            },
+            ParseError::TooLong(length, max) => {
+                ApiError::new(ErrorCode::LimitExceeded, &format!("The roll is {} characters long, the most is {}.", length, max), json!({
+                    "limit": "max_notation_length",
+                    "max": max,
+                    "value": length
+                }))
+            },
+            ParseError::TooManyExpressions(max) => {
+                ApiError::new(ErrorCode::LimitExceeded, &format!("The block has more than {} rolls.", max), json!({
+                    "limit": "max_expressions",
+                    "max": max
+                }))
+            },
+            ParseError::UnsupportedInDialect(name, dialect) => {
+                ApiError::new(ErrorCode::UnsupportedModifier, &format!("The {} modifier can't be read as {} notation.", name, dialect.name()), json!({
+                    "dialect": dialect.name(),
+                    "modifier": name
+                }))
+            },
+            ParseError::UnsupportedModifier(name) => {
+                ApiError::new(ErrorCode::UnsupportedModifier, &format!("The {} flag can't be used on Fate dice.", name), json!({
+                    "modifier": name
+                }))
+            },
+            ParseError::AtLine(line, column, error) => ApiError::from(*error).at_line(line, column),
+        }
+    }
+}
+
+impl From<HistoryError> for ApiError {
+    fn from(error: HistoryError) -> ApiError {
+        let (code, message, details) = match error {
+            HistoryError::InvalidBefore => {
+                (ErrorCode::InvalidQuery, "Before must be a roll ID or an RFC 3339 timestamp.", json!({ "parameter": "before" }))
+            },
+            HistoryError::InvalidCursor => (ErrorCode::InvalidQuery, "Cursor is not valid.", json!({ "parameter": "cursor" })),
+            HistoryError::UnknownRoom => (ErrorCode::NotFound, "Room not found.", json!({ "resource": "room" })),
+            HistoryError::UnknownRoll => (ErrorCode::NotFound, "Roll not found.", json!({ "resource": "roll" })),
+            HistoryError::UnknownParticipant => {
+                (ErrorCode::NotFound, "Participant not found.", json!({ "resource": "participant" }))
+            },
+            HistoryError::Unavailable => (ErrorCode::Unavailable, "Roll history is unavailable, try again.", Value::Null),
+        };
+        ApiError::new(code, message, details)
+    }
+}
+
+impl From<StatsError> for ApiError {
+    fn from(error: StatsError) -> ApiError {
+        match error {
+            StatsError::InvalidNotation(expression) => {
+                ApiError::new(ErrorCode::ParseError, &format!("{} is not a valid roll.", expression), Value::Null)
+                    .in_expression(&expression)
+            },
+            StatsError::OverLimits(expression, error) => ApiError::from(error).in_expression(&expression),
+            StatsError::Stopped(error) => ApiError::from(error),
+            StatsError::TooLarge(steps, max) => {
+                ApiError::new(ErrorCode::LimitExceeded, "The rolls have too many totals to add together.", json!({
+                    "limit": "max_sum_steps",
+                    "max": max,
+                    "value": steps
+                }))
+            },
+            StatsError::TooManyExpressions(count, max) => {
+                ApiError::new(ErrorCode::LimitExceeded, &format!("Can't add {} rolls together, the most is {}.", count, max), json!({
+                    "limit": "max_expressions",
+                    "max": max,
+                    "value": count
+                }))
+            },
+        }
+    }
+}
+
+impl From<WebhookError> for ApiError {
+    fn from(error: WebhookError) -> ApiError {
+        let (message, reason) = match error {
+            WebhookError::PrivateHost => ("Webhook URLs must be on a public host.", "private_host"),
+            WebhookError::UnsupportedScheme => ("Webhook URLs must start with http:// or https://.", "unsupported_scheme"),
+            _ => ("Webhook URL is not valid.", "invalid_url"),
+        };
+        ApiError::new(ErrorCode::InvalidWebhook, message, json!({ "reason": reason }))
+    }
+}
+
+#[test]
+fn it_keeps_error_codes_stable() {
+    let codes = [
+        (ErrorCode::ConflictingFlags, "CONFLICTING_FLAGS"),
+        (ErrorCode::InvalidQuery, "INVALID_QUERY"),
+        (ErrorCode::InvalidRoll, "INVALID_ROLL"),
+        (ErrorCode::InvalidWebhook, "INVALID_WEBHOOK"),
+        (ErrorCode::LimitExceeded, "LIMIT_EXCEEDED"),
+        (ErrorCode::NotFound, "NOT_FOUND"),
+        (ErrorCode::ParseError, "PARSE_ERROR"),
+        (ErrorCode::RateLimited, "RATE_LIMITED"),
+        (ErrorCode::Unauthorized, "UNAUTHORIZED"),
+        (ErrorCode::Unavailable, "UNAVAILABLE"),
+        (ErrorCode::UnknownVariable, "UNKNOWN_VARIABLE"),
+        (ErrorCode::UnsupportedModifier, "UNSUPPORTED_MODIFIER"),
+    ];
+    for &(code, name) in codes.iter() {
+        assert_eq!(code.as_str(), name);
+    }
+}
+
+#[test]
+fn it_maps_roll_errors_to_codes() {
+    assert_eq!(ApiError::from(RollError::InvalidNotation).code, ErrorCode::ParseError);
+    assert_eq!(ApiError::from(RollError::TooManyDice(200, 100)).code, ErrorCode::LimitExceeded);
+    assert_eq!(ApiError::from(RollError::TooManySides(2000, 1000)).code, ErrorCode::LimitExceeded);
+    assert_eq!(ApiError::from(RollError::ConflictingFlags("immutable_dice", "rr")).code, ErrorCode::ConflictingFlags);
+    assert_eq!(ApiError::from(RollError::NoSides).code, ErrorCode::InvalidRoll);
+    assert_eq!(ApiError::from(ParseError::UnsupportedModifier("kh")).code, ErrorCode::UnsupportedModifier);
+    assert_eq!(ApiError::from(ParseError::TooLong(2000, 1000)).code, ErrorCode::LimitExceeded);
+    assert_eq!(ApiError::from(ParseError::TooManyExpressions(100)).code, ErrorCode::LimitExceeded);
+    assert_eq!(ApiError::from(RollError::TooManyTerms(2)).code, ErrorCode::InvalidRoll);
+    assert_eq!(ApiError::from(ParseError::UnsupportedInDialect("x", Dialect::Roll20)).code, ErrorCode::UnsupportedModifier);
+    assert_eq!(ApiError::from(HistoryError::UnknownRoll).code, ErrorCode::NotFound);
+    assert_eq!(ApiError::from(WebhookError::InvalidUrl).code, ErrorCode::InvalidWebhook);
+
+    let error = ApiError::from(RollError::TooManyDice(200, 100));
+    assert_eq!(error.message, "Can't roll 200 dice, the most is 100");
+
+    let error = ApiError::from(ParseError::LookalikeCharacter('×', '*'));
+    assert_eq!(error.code, ErrorCode::ParseError);
+    assert_eq!(error.message, "The roll has a non-ASCII lookalike character ×, write it as *.");
+
+    let error = ApiError::from(ParseError::AtLine(3, 9, Box::new(ParseError::TooLong(2000, 1000))));
+    assert_eq!(error.code, ErrorCode::LimitExceeded);
+    assert_eq!(error.message, "Line 3, column 9: The roll is 2000 characters long, the most is 1000.");
+    assert_eq!(error.details["line"], json!(3));
+    assert_eq!(error.details["max"], json!(1000));
+}
+
+#[test]
+fn it_serializes_the_error_envelope() {
+    let error = ApiError::from(StatsError::OverLimits("200d6".to_string(), RollError::TooManyDice(200, 100)));
+    assert_eq!(::serde_json::to_string(&error).unwrap(),
+               r#"{"code":"LIMIT_EXCEEDED","details":{"expression":"200d6","limit":"max_dice","max":100,"value":200},"message":"Can't roll 200 dice, the most is 100"}"#);
+}
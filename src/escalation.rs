@@ -0,0 +1,213 @@
+use limits::Limits;
+use parser::parse;
+use roll::{Roll, RollError, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use serde_json;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// Most copies of `adds` an escalation adds, unless it's given a cap of its own
+pub const DEFAULT_CAP: u16 = 10;
+
+/// Extra effect rolled for how far a check beat its DC, e.g. +1d6 damage for every 5 points over
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Escalation {
+    /// Points over the DC each copy of `adds` takes, none are added when it's 0
+    pub per_margin: u16,
+
+    /// Notation of the roll added for each step of the margin, e.g. `1d6`
+    pub adds: String,
+
+    /// Most copies added however big the margin, `DEFAULT_CAP` unless set, or none to be held back
+    /// only by the limits
+    #[serde(default = "default_cap")]
+    pub cap: Option<u16>,
+}
+
+/// A check, the effect it decides and the extra rolls its margin added, made together
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChainedRoll {
+    /// A roll of `adds` for each step of the margin, in the order they were rolled
+    pub bonuses: Vec<Roll>,
+
+    pub check: Roll,
+    pub dc: i32,
+    pub effect: Roll,
+
+    /// If the cap held back copies the margin earned
+    pub is_capped: bool,
+
+    /// How far the check beat the DC, negative when it failed
+    pub margin: i32,
+
+    /// The effect and every bonus added up
+    pub value: i32,
+}
+
+impl Escalation {
+    pub fn new(per_margin: u16, adds: &str) -> Escalation {
+        Escalation {
+            per_margin,
+            adds: adds.to_string(),
+            cap: default_cap(),
+        }
+    }
+
+    /// Add at most this many copies, e.g. 5 for a spell that tops out at +5d6
+    pub fn with_cap(mut self, cap: u16) -> Escalation {
+        self.cap = Some(cap);
+        self
+    }
+
+    /// Copies of `adds` a margin earns, and if the cap held any back
+    ///
+    /// Only whole steps over the DC count, so meeting it exactly or missing it adds nothing.
+    pub fn copies(&self, margin: i32) -> (u16, bool) {
+        if margin <= 0 || self.per_margin == 0 {
+            return (0, false);
+        }
+        let earned = (margin / self.per_margin as i32).min(u16::max_value() as i32) as u16;
+        match self.cap {
+            Some(cap) if earned > cap => (cap, true),
+            _ => (earned, false),
+        }
+    }
+
+    /// Roll the check against the DC, then the effect, then a copy of `adds` for each step of the
+    /// margin
+    ///
+    /// The effect is rolled whether or not the check succeeds, for effects that still do something
+    /// on a miss, e.g. half damage. Each bonus's dice come after the effect's in the chain, so a
+    /// bonus's terms are numbered from 1, see `Roll::set_term_index`.
+    ///
+    /// Every roll is checked against the limits, and the dice of the check, the effect and every
+    /// copy together count towards `max_dice`, so a margin earning too many copies fails with
+    /// `TooManyDice` before the effect is rolled.
+    pub fn roll(&self, check: RollFlags, dc: i32, effect: RollFlags, limits: &Limits, roller: &mut Roller)
+                -> Result<ChainedRoll, RollError> {
+        let adds = parse(&self.adds).and_then(|mut rolls| rolls.pop()).ok_or(RollError::InvalidNotation)?;
+        adds.validate()?;
+        for flags in &[&check, &effect, &adds] {
+            limits.check(flags)?;
+        }
+
+        let check = Roll::with_roller(check, roller);
+        let margin = check.value - dc;
+        let (copies, is_capped) = self.copies(margin);
+        let dice = check.dice.len() as i64 + effect.n.max(0) as i64 + copies as i64 * adds.n.max(0) as i64;
+        if dice > limits.max_dice as i64 {
+            return Err(RollError::TooManyDice(dice.min(i16::max_value() as i64) as i16, limits.max_dice));
+        }
+
+        let effect = Roll::with_roller(effect, roller);
+        let mut bonuses = Vec::with_capacity(copies as usize);
+        for copy in 0..copies {
+            let mut bonus = Roll::with_roller(adds.clone(), roller);
+            bonus.set_term_index(copy + 1);
+            bonuses.push(bonus);
+        }
+        Ok(ChainedRoll {
+            value: effect.value + bonuses.iter().map(|bonus| bonus.value).sum::<i32>(),
+            bonuses,
+            check,
+            dc,
+            effect,
+            is_capped,
+            margin,
+        })
+    }
+}
+
+fn default_cap() -> Option<u16> {
+    Some(DEFAULT_CAP)
+}
+
+#[test]
+fn it_adds_a_copy_for_each_step_of_the_margin() {
+    let escalation = Escalation::new(5, "1d6");
+    let (check, effect) = (parse("1d20+5").unwrap().remove(0), parse("2d6").unwrap().remove(0));
+
+    // 16 + 5 beats DC 10 by 11, two whole steps of 5
+    let mut roller = ScriptedRoller::new(vec![16, 3, 4, 5, 2]);
+    let chained = escalation.roll(check, 10, effect, &Limits::default(), &mut roller).unwrap();
+    assert_eq!((chained.check.value, chained.margin), (21, 11));
+    assert_eq!(chained.bonuses.len(), 2);
+    assert_eq!(chained.bonuses.iter().map(|bonus| bonus.value).collect::<Vec<i32>>(), vec![5, 2]);
+    assert_eq!((chained.effect.value, chained.value), (7, 14));
+    assert_eq!(chained.bonuses[1].dice[0].term_index, 2);
+    assert!(!chained.is_capped);
+}
+
+#[test]
+fn it_caps_a_huge_margin() {
+    let escalation = Escalation::new(5, "1d6").with_cap(3);
+    assert_eq!(escalation.copies(40), (3, true));
+    assert_eq!(escalation.copies(15), (3, false));
+
+    let (check, effect) = (parse("1d20+30").unwrap().remove(0), parse("1d6").unwrap().remove(0));
+    let mut roller = ScriptedRoller::new(vec![20, 1, 6, 6, 6]);
+    let chained = escalation.roll(check, 10, effect, &Limits::default(), &mut roller).unwrap();
+    assert_eq!(chained.margin, 40);
+    assert_eq!(chained.bonuses.len(), 3);
+    assert!(chained.is_capped);
+    assert_eq!(chained.value, 19);
+}
+
+#[test]
+fn it_caps_copies_unless_told_otherwise() {
+    assert_eq!(Escalation::new(1, "1d6").copies(1000), (DEFAULT_CAP, true));
+
+    let mut uncapped = Escalation::new(1, "1d6");
+    uncapped.cap = None;
+    assert_eq!(uncapped.copies(1000), (1000, false));
+}
+
+#[test]
+fn it_counts_every_copy_towards_the_dice_limit() {
+    // 10 copies of 10d6 with the check and the effect make 102 dice, past the 100 a roll can have
+    let escalation = Escalation::new(1, "10d6");
+    let (check, effect) = (parse("1d20+30").unwrap().remove(0), parse("1d6").unwrap().remove(0));
+
+    // The scripted roller only has the check's die, so rolling the effect or a copy would panic
+    let result = escalation.roll(check, 10, effect, &Limits::default(), &mut ScriptedRoller::new(vec![20]));
+    assert_eq!(result.err(), Some(RollError::TooManyDice(102, 100)));
+}
+
+#[test]
+fn it_fails_before_rolling_copies_it_cant_roll() {
+    let (check, effect) = (parse("1d20").unwrap().remove(0), parse("1d6").unwrap().remove(0));
+    let result = Escalation::new(5, "1dQ").roll(check, 10, effect, &Limits::default(), &mut ScriptedRoller::new(vec![]));
+    assert_eq!(result.err(), Some(RollError::InvalidNotation));
+
+    let (check, effect) = (parse("1d20").unwrap().remove(0), parse("1d6").unwrap().remove(0));
+    let result = Escalation::new(5, "1d5000").roll(check, 10, effect, &Limits::default(), &mut ScriptedRoller::new(vec![]));
+    assert_eq!(result.err(), Some(RollError::TooManySides(5000, 1000)));
+}
+
+#[test]
+fn it_adds_nothing_without_a_whole_step() {
+    let escalation = Escalation::new(5, "1d6");
+
+    // The scripted roller runs out after the effect, so a bonus roll would panic
+    let (check, effect) = (parse("1d20").unwrap().remove(0), parse("2d6").unwrap().remove(0));
+    let failed = escalation.roll(check, 15, effect, &Limits::default(), &mut ScriptedRoller::new(vec![5, 3, 4])).unwrap();
+    assert_eq!(failed.margin, -10);
+    assert!(failed.bonuses.is_empty());
+    assert_eq!(failed.value, 7);
+
+    let (check, effect) = (parse("1d20").unwrap().remove(0), parse("2d6").unwrap().remove(0));
+    let met = escalation.roll(check, 15, effect, &Limits::default(), &mut ScriptedRoller::new(vec![15, 3, 4])).unwrap();
+    assert_eq!((met.margin, met.bonuses.len()), (0, 0));
+    assert_eq!(escalation.copies(4), (0, false));
+    assert_eq!(Escalation::new(0, "1d6").copies(20), (0, false));
+}
+
+#[test]
+fn it_reads_an_escalation_from_json() {
+    let escalation: Escalation = serde_json::from_str(r#"{"per_margin": 5, "adds": "1d6"}"#).unwrap();
+    assert_eq!((escalation.per_margin, escalation.adds.as_str(), escalation.cap), (5, "1d6", Some(DEFAULT_CAP)));
+
+    let uncapped: Escalation = serde_json::from_str(r#"{"per_margin": 5, "adds": "1d6", "cap": null}"#).unwrap();
+    assert_eq!(uncapped.cap, None);
+}
@@ -0,0 +1,379 @@
+use alloc::vec::Vec;
+use core::fmt;
+use rand_core::RngCore;
+
+/// Most dice a single pool can roll, so a typo can't run a small device out of memory
+pub const MAX_POOL_SIZE: u16 = 1000;
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum DieType {
+    D4,
+    D6,
+    D8,
+    D10,
+    D12,
+    D20,
+    D100,
+    Fate,
+    Other,
+}
+
+impl DieType {
+    /// How the die is written in notation, e.g. `d20` or `dF`
+    pub fn notation(&self) -> &'static str {
+        match self {
+            &DieType::D4 => "d4",
+            &DieType::D6 => "d6",
+            &DieType::D8 => "d8",
+            &DieType::D10 => "d10",
+            &DieType::D12 => "d12",
+            &DieType::D20 => "d20",
+            &DieType::D100 => "d100",
+            &DieType::Fate => "dF",
+            &DieType::Other => "d?",
+        }
+    }
+
+    /// The next smaller standard die, if there is one
+    pub fn smaller(&self) -> Option<DieType> {
+        match self {
+            &DieType::D4 => None,
+            &DieType::D6 => Some(DieType::D4),
+            &DieType::D8 => Some(DieType::D6),
+            &DieType::D10 => Some(DieType::D8),
+            &DieType::D12 => Some(DieType::D10),
+            &DieType::D20 => Some(DieType::D12),
+            &DieType::D100 => Some(DieType::D20),
+            &DieType::Fate => None,
+            &DieType::Other => None,
+        }
+    }
+
+    /// The standard die with this many sides, e.g. `D20` for 20
+    pub fn with_sides(sides: u16) -> Option<DieType> {
+        match sides {
+            4 => Some(DieType::D4),
+            6 => Some(DieType::D6),
+            8 => Some(DieType::D8),
+            10 => Some(DieType::D10),
+            12 => Some(DieType::D12),
+            20 => Some(DieType::D20),
+            100 => Some(DieType::D100),
+            _ => None,
+        }
+    }
+
+    /// The lowest face, -1 for Fate dice and 0 for dice with custom sides
+    pub fn min_face(&self) -> i16 {
+        match self {
+            &DieType::Fate => -1,
+            &DieType::Other => 0,
+            _ => 1,
+        }
+    }
+
+    /// The highest face, 0 for dice with custom sides
+    pub fn max_face(&self) -> i16 {
+        match self {
+            &DieType::D4 => 4,
+            &DieType::D6 => 6,
+            &DieType::D8 => 8,
+            &DieType::D10 => 10,
+            &DieType::D12 => 12,
+            &DieType::D20 => 20,
+            &DieType::D100 => 100,
+            &DieType::Fate => 1,
+            &DieType::Other => 0,
+        }
+    }
+
+    /// Land the die on one of its faces, every face as likely as the others
+    ///
+    /// Draws that would favour the lowest faces are thrown away and drawn again, rather than
+    /// wrapped around with a remainder.
+    pub fn roll<R: RngCore + ?Sized>(&self, rng: &mut R) -> i16 {
+        let faces = (self.max_face() - self.min_face() + 1) as u64;
+        let span = 1u64 << 32;
+        let limit = span - span % faces;
+        loop {
+            let draw = rng.next_u32() as u64;
+            if draw < limit {
+                return self.min_face() + (draw % faces) as i16;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    /// The notation isn't dice, numbers, `+` and `-`, e.g. `4d6kh3+2`
+    InvalidNotation,
+
+    /// Dice with sides other than those of a standard die, like `d7`
+    UnsupportedDie(u16),
+
+    /// Keeping or dropping more dice than the pool has
+    KeepsTooMany(u16, u16),
+
+    /// A pool with more dice than `MAX_POOL_SIZE`
+    TooManyDice(u16),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::InvalidNotation => write!(f, "invalid notation"),
+            EvalError::UnsupportedDie(sides) => write!(f, "d{} isn't a standard die", sides),
+            EvalError::KeepsTooMany(keep, n) => write!(f, "can't keep or drop {} of {} dice", keep, n),
+            EvalError::TooManyDice(n) => write!(f, "{} dice is more than {} in a pool", n, MAX_POOL_SIZE),
+        }
+    }
+}
+
+/// Which of a pool's dice count toward its total
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Keep {
+    All,
+    Highest(u16),
+    Lowest(u16),
+}
+
+/// Dice of one type rolled together, e.g. `4d6kh3`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pool {
+    pub die: DieType,
+    pub keep: Keep,
+    pub n: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Term {
+    Constant(i32),
+    Pool(Pool),
+}
+
+/// A die as it landed, and if it counted
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RolledDie {
+    pub die: DieType,
+    pub is_dropped: bool,
+    pub value: i16,
+}
+
+/// The dice an expression rolled, in the order they were rolled, and its total
+#[derive(Clone, Debug, PartialEq)]
+pub struct Evaluated {
+    pub dice: Vec<RolledDie>,
+    pub total: i32,
+}
+
+impl Pool {
+    /// Roll every die, then drop those the pool doesn't keep
+    ///
+    /// Ties are broken by dropping the dice rolled first, so the same draws always drop the same
+    /// dice.
+    pub fn roll<R: RngCore + ?Sized>(&self, rng: &mut R) -> Vec<RolledDie> {
+        let mut dice: Vec<RolledDie> = (0..self.n)
+            .map(|_| RolledDie {
+                die: self.die,
+                is_dropped: false,
+                value: self.die.roll(rng),
+            })
+            .collect();
+
+        let mut order: Vec<usize> = (0..dice.len()).collect();
+        let dropped = match self.keep {
+            Keep::All => 0,
+            Keep::Highest(keep) => {
+                order.sort_by_key(|&i| dice[i].value);
+                self.n - keep
+            },
+            Keep::Lowest(keep) => {
+                order.sort_by_key(|&i| -dice[i].value);
+                self.n - keep
+            },
+        };
+        for &i in order.iter().take(dropped as usize) {
+            dice[i].is_dropped = true;
+        }
+        dice
+    }
+}
+
+/// Dice and numbers added together, parsed from notation like `4d6kh3+2` or `2d20kl1-1`
+///
+/// Only what's needed to roll on devices without an operating system is understood: standard
+/// dice and Fate dice, keeping or dropping the highest or lowest, and adding or subtracting terms.
+/// Parse notation with everything else with `parser::parse`, which needs the standard library.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    /// Each term and if it's subtracted, in the order they're written
+    pub terms: Vec<(bool, Term)>,
+}
+
+impl Expression {
+    pub fn parse(notation: &str) -> Result<Expression, EvalError> {
+        let mut terms = Vec::new();
+        let mut is_subtracted = false;
+        let mut chars = notation.chars().filter(|c| !c.is_whitespace()).peekable();
+        loop {
+            let count = number(&mut chars);
+            let term = match chars.peek() {
+                Some(&'d') | Some(&'D') => {
+                    chars.next();
+                    Term::Pool(pool(count.unwrap_or(1), &mut chars)?)
+                },
+                _ => Term::Constant(count.ok_or(EvalError::InvalidNotation)? as i32),
+            };
+            terms.push((is_subtracted, term));
+
+            is_subtracted = match chars.next() {
+                Some('+') => false,
+                Some('-') => true,
+                Some(_) => return Err(EvalError::InvalidNotation),
+                None => break,
+            };
+        }
+        Ok(Expression {
+            terms,
+        })
+    }
+
+    /// Roll every pool, then add up the kept dice and the numbers
+    pub fn roll<R: RngCore + ?Sized>(&self, rng: &mut R) -> Evaluated {
+        let mut dice = Vec::new();
+        let mut total = 0;
+        for &(is_subtracted, ref term) in &self.terms {
+            let value = match *term {
+                Term::Constant(value) => value,
+                Term::Pool(ref pool) => {
+                    let rolled = pool.roll(rng);
+                    let value = rolled.iter().filter(|die| !die.is_dropped).map(|die| die.value as i32).sum();
+                    dice.extend(rolled);
+                    value
+                },
+            };
+            total += if is_subtracted { -value } else { value };
+        }
+        Evaluated {
+            dice,
+            total,
+        }
+    }
+}
+
+/// Parse and roll notation with the caller's random number generator, e.g. a seeded one
+pub fn roll<R: RngCore + ?Sized>(notation: &str, rng: &mut R) -> Result<Evaluated, EvalError> {
+    Ok(Expression::parse(notation)?.roll(rng))
+}
+
+fn number<I: Iterator<Item = char>>(chars: &mut ::core::iter::Peekable<I>) -> Option<u16> {
+    let mut value: Option<u16> = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        value = Some(value.unwrap_or(0).saturating_mul(10).saturating_add(digit as u16));
+    }
+    value
+}
+
+/// The rest of a pool after its `d`, e.g. `6kh3` of `4d6kh3`
+fn pool<I: Iterator<Item = char>>(n: u16, chars: &mut ::core::iter::Peekable<I>) -> Result<Pool, EvalError> {
+    if n > MAX_POOL_SIZE {
+        return Err(EvalError::TooManyDice(n));
+    }
+    let die = match chars.peek() {
+        Some(&'F') | Some(&'f') => {
+            chars.next();
+            DieType::Fate
+        },
+        _ => {
+            let sides = number(chars).ok_or(EvalError::InvalidNotation)?;
+            DieType::with_sides(sides).ok_or(EvalError::UnsupportedDie(sides))?
+        },
+    };
+
+    let keep = match chars.peek().cloned() {
+        Some('k') | Some('d') => {
+            let is_keep = chars.next() == Some('k');
+            let is_highest = match chars.next() {
+                Some('h') => true,
+                Some('l') => false,
+                _ => return Err(EvalError::InvalidNotation),
+            };
+            let count = number(chars).unwrap_or(1);
+            if count > n {
+                return Err(EvalError::KeepsTooMany(count, n));
+            }
+            match (is_keep, is_highest) {
+                (true, true) => Keep::Highest(count),
+                (true, false) => Keep::Lowest(count),
+                (false, true) => Keep::Lowest(n - count),
+                (false, false) => Keep::Highest(n - count),
+            }
+        },
+        _ => Keep::All,
+    };
+    Ok(Pool {
+        die,
+        keep,
+        n,
+    })
+}
+
+/// Hands out the draws it's given, over and over
+#[cfg(test)]
+struct Draws(Vec<u32>, usize);
+
+#[cfg(test)]
+impl RngCore for Draws {
+    fn next_u32(&mut self) -> u32 {
+        let draw = self.0[self.1 % self.0.len()];
+        self.1 += 1;
+        draw
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        ::rand_core::impls::next_u64_via_u32(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        ::rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), ::rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[test]
+fn it_rolls_four_d6_keeping_the_highest_three() {
+    let mut rng = Draws(vec![0, 5, 2, 3], 0);
+    let rolled = roll("4d6kh3", &mut rng).unwrap();
+    let values: Vec<i16> = rolled.dice.iter().map(|die| die.value).collect();
+    assert_eq!(values, vec![1, 6, 3, 4]);
+    assert!(rolled.dice[0].is_dropped);
+    assert_eq!(rolled.total, 13);
+
+    // Dropping the lowest is keeping the rest
+    assert_eq!(Expression::parse("4d6dl1"), Expression::parse("4d6kh3"));
+    let rolled = roll("2d20kl1 - 1 + dF", &mut Draws(vec![9, 14, 2], 0)).unwrap();
+    assert_eq!(rolled.total, 10 - 1 + 1);
+}
+
+#[test]
+fn it_throws_away_draws_that_would_favour_low_faces() {
+    // 2^32 isn't a multiple of 6, so the top 4 draws are thrown away
+    let mut rng = Draws(vec![u32::max_value(), u32::max_value() - 3, 7], 0);
+    assert_eq!(DieType::D6.roll(&mut rng), 2);
+    assert_eq!(rng.1, 3);
+}
+
+#[test]
+fn it_turns_away_what_it_cant_roll() {
+    assert_eq!(Expression::parse("4d7"), Err(EvalError::UnsupportedDie(7)));
+    assert_eq!(Expression::parse("4d6kh5"), Err(EvalError::KeepsTooMany(5, 4)));
+    assert_eq!(Expression::parse("1001d6"), Err(EvalError::TooManyDice(1001)));
+    assert_eq!(Expression::parse("4d6+"), Err(EvalError::InvalidNotation));
+    assert_eq!(Expression::parse("4d6!"), Err(EvalError::InvalidNotation));
+}
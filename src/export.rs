@@ -0,0 +1,408 @@
+use history::{HistoryError, HistoryQuery, LoggedRoll, RollStore, MAX_PAGE_SIZE};
+use std::io::{self, Write};
+#[cfg(feature = "export-parquet")]
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+#[cfg(feature = "export-parquet")]
+use parquet::errors::ParquetError;
+#[cfg(feature = "export-parquet")]
+use parquet::file::properties::WriterProperties;
+#[cfg(feature = "export-parquet")]
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+#[cfg(feature = "export-parquet")]
+use parquet::schema::parser::parse_message_type;
+#[cfg(feature = "export-parquet")]
+use std::sync::Arc;
+#[cfg(test)]
+use die::DieType;
+#[cfg(test)]
+use history::RollLog;
+#[cfg(test)]
+use roll::{ExplodeSuccessPolicy, Roll, RollFlags};
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// The type of a column's values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Bool,
+    Int,
+
+    /// Text that can be missing, an empty field in CSV and a null in Parquet
+    OptionalText,
+    Text,
+}
+
+/// Columns of the dice table, one row per die, in the order they're written
+///
+/// Names and order only change with a major version, so spreadsheets and scripts can rely on them.
+pub const DICE_COLUMNS: &[(&str, Kind)] = &[
+    ("roll_id", Kind::Text),
+    // Number of the roll in its room
+    ("sequence", Kind::Int),
+    // The room the roll was made in
+    ("session", Kind::Text),
+    ("participant", Kind::Text),
+    // The roll's equation
+    ("notation", Kind::Text),
+    // Where the die comes in the roll's dice in notation order, counting from 0
+    ("die_index", Kind::Int),
+    // e.g. `d20`, `dF` or `d?` for custom dice
+    ("die_type", Kind::Text),
+    // The face the die landed on
+    ("raw_value", Kind::Int),
+    // What the die counts for, the face or the halved face of a half die
+    ("final_value", Kind::Int),
+    ("is_dropped", Kind::Bool),
+    ("is_exploded", Kind::Bool),
+    ("is_rerolled", Kind::Bool),
+    ("is_successful", Kind::Bool),
+    ("is_manual", Kind::Bool),
+    // When the roll was made, RFC 3339
+    ("rolled_at", Kind::Text),
+    // When the die was last rolled, RFC 3339
+    ("die_rolled_at", Kind::Text),
+];
+
+/// Columns of the rolls table, one row per roll, in the order they're written
+///
+/// Names and order only change with a major version, the same as the dice table's.
+pub const ROLL_COLUMNS: &[(&str, Kind)] = &[
+    ("roll_id", Kind::Text),
+    ("sequence", Kind::Int),
+    ("session", Kind::Text),
+    ("participant", Kind::Text),
+    ("notation", Kind::Text),
+    // The roll's final value
+    ("total", Kind::Int),
+    // Number of kept dice that were successful
+    ("successes", Kind::Int),
+    // Number of dice rolled, rerolls and explosions included
+    ("dice", Kind::Int),
+    // The comment written with the notation, if any
+    ("comment", Kind::OptionalText),
+    ("rolled_at", Kind::Text),
+];
+
+/// Which table to export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Table {
+    Dice,
+    Rolls,
+}
+
+/// One value of a row
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Bool(bool),
+    Int(i64),
+    Null,
+    Text(String),
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    /// The room's history couldn't be read
+    History(HistoryError),
+
+    /// The output couldn't be written
+    Io(io::Error),
+
+    #[cfg(feature = "export-parquet")]
+    Parquet(ParquetError),
+}
+
+impl From<HistoryError> for ExportError {
+    fn from(error: HistoryError) -> ExportError {
+        ExportError::History(error)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(error: io::Error) -> ExportError {
+        ExportError::Io(error)
+    }
+}
+
+#[cfg(feature = "export-parquet")]
+impl From<ParquetError> for ExportError {
+    fn from(error: ParquetError) -> ExportError {
+        ExportError::Parquet(error)
+    }
+}
+
+impl Table {
+    pub fn columns(&self) -> &'static [(&'static str, Kind)] {
+        match *self {
+            Table::Dice => DICE_COLUMNS,
+            Table::Rolls => ROLL_COLUMNS,
+        }
+    }
+
+    /// The table's rows for a roll made in a room, in the order of its columns
+    pub fn rows(&self, room: &str, logged: &LoggedRoll) -> Vec<Vec<Cell>> {
+        let roll = &logged.roll;
+        let mut common = vec![
+            Cell::Text(roll.id.clone()),
+            Cell::Int(logged.sequence() as i64),
+            Cell::Text(room.to_string()),
+            Cell::Text(logged.participant.clone()),
+            Cell::Text(roll.equation.clone()),
+        ];
+        match *self {
+            Table::Dice => roll.dice_in_notation_order().into_iter().enumerate().map(|(index, die)| {
+                let mut row = common.clone();
+                row.extend(vec![
+                    Cell::Int(index as i64),
+                    Cell::Text(die.die.notation().to_string()),
+                    Cell::Int(die.value as i64),
+                    Cell::Int(die.counted() as i64),
+                    Cell::Bool(die.is_dropped),
+                    Cell::Bool(die.is_exploded),
+                    Cell::Bool(die.is_rerolled),
+                    Cell::Bool(die.is_successful),
+                    Cell::Bool(die.is_manual),
+                    Cell::Text(roll.timestamp.to_rfc3339()),
+                    Cell::Text(die.timestamp.to_rfc3339()),
+                ]);
+                row
+            }).collect(),
+            Table::Rolls => {
+                common.extend(vec![
+                    Cell::Int(roll.value as i64),
+                    Cell::Int(roll.successes() as i64),
+                    Cell::Int(roll.dice.len() as i64),
+                    comment(logged).map_or(Cell::Null, Cell::Text),
+                    Cell::Text(roll.timestamp.to_rfc3339()),
+                ]);
+                vec![common]
+            },
+        }
+    }
+}
+
+/// The comment written with a roll's notation, which its equation keeps in brackets
+///
+/// Custom sides follow a `d`, and tags are on the dice too, so the first other brackets are the
+/// comment.
+fn comment(logged: &LoggedRoll) -> Option<String> {
+    let roll = &logged.roll;
+    let tags = roll.dice.first().map(|die| die.tags.join(","));
+    let mut rest = roll.equation.as_str();
+    while let Some(open) = rest.find('[') {
+        let close = open + rest[open..].find(']')?;
+        let inside = &rest[open + 1..close];
+        let is_sides = rest[..open].ends_with('d');
+        if !is_sides && Some(inside) != tags.as_ref().map(|tags| tags.as_str()) {
+            return Some(inside.to_string());
+        }
+        rest = &rest[close + 1..];
+    }
+    None
+}
+
+/// Call back with each page of a room's history in turn, so only a page is held at a time
+fn each_page<F>(store: &dyn RollStore, room: &str, mut f: F) -> Result<(), ExportError>
+    where F: FnMut(&[LoggedRoll]) -> Result<(), ExportError> {
+    let mut query = HistoryQuery {
+        limit: Some(MAX_PAGE_SIZE),
+        ..HistoryQuery::default()
+    };
+    loop {
+        let page = store.page(room, &query)?;
+        f(&page.rolls)?;
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Write a field, quoting it when it has a comma, quote or line break in it
+fn csv_field(cell: &Cell) -> String {
+    let text = match *cell {
+        Cell::Bool(value) => value.to_string(),
+        Cell::Int(value) => value.to_string(),
+        Cell::Null => String::new(),
+        Cell::Text(ref text) => text.clone(),
+    };
+    if text.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// Write a table of a room's history as CSV with a header row, returning the number of rows after
+/// the header
+///
+/// Rolls are written a page at a time as the history hands them out, newest first, so the whole
+/// history is never held in memory. Hidden rolls are left out until they're revealed.
+pub fn export_csv<W: Write>(store: &dyn RollStore, room: &str, table: Table, mut writer: W) -> Result<usize, ExportError> {
+    let header: Vec<String> = table.columns().iter().map(|&(name, _)| name.to_string()).collect();
+    writeln!(writer, "{}", header.join(","))?;
+
+    let mut rows = 0;
+    each_page(store, room, |rolls| {
+        for logged in rolls {
+            for row in table.rows(room, logged) {
+                let fields: Vec<String> = row.iter().map(csv_field).collect();
+                writeln!(writer, "{}", fields.join(","))?;
+                rows += 1;
+            }
+        }
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(rows)
+}
+
+/// The Parquet schema of a table, written out from its columns
+#[cfg(feature = "export-parquet")]
+fn parquet_schema(table: Table) -> String {
+    let fields: Vec<String> = table.columns().iter().map(|&(name, kind)| match kind {
+        Kind::Bool => format!("REQUIRED BOOLEAN {};", name),
+        Kind::Int => format!("REQUIRED INT64 {};", name),
+        Kind::OptionalText => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", name),
+        Kind::Text => format!("REQUIRED BYTE_ARRAY {} (UTF8);", name),
+    }).collect();
+    let name = match table {
+        Table::Dice => "die",
+        Table::Rolls => "roll",
+    };
+    format!("message {} {{ {} }}", name, fields.join(" "))
+}
+
+#[cfg(feature = "export-parquet")]
+fn write_parquet_column(column: &mut SerializedColumnWriter, kind: Kind, cells: &[&Cell]) -> Result<(), ParquetError> {
+    match kind {
+        Kind::Bool => {
+            let values: Vec<bool> = cells.iter().map(|cell| **cell == Cell::Bool(true)).collect();
+            column.typed::<BoolType>().write_batch(&values, None, None)?;
+        },
+        Kind::Int => {
+            let values: Vec<i64> = cells.iter().map(|cell| match **cell {
+                Cell::Int(value) => value,
+                _ => 0,
+            }).collect();
+            column.typed::<Int64Type>().write_batch(&values, None, None)?;
+        },
+        Kind::OptionalText | Kind::Text => {
+            let values: Vec<ByteArray> = cells.iter().filter_map(|cell| match **cell {
+                Cell::Text(ref text) => Some(ByteArray::from(text.as_str())),
+                _ => None,
+            }).collect();
+            if kind == Kind::OptionalText {
+                let levels: Vec<i16> = cells.iter().map(|cell| if **cell == Cell::Null { 0 } else { 1 }).collect();
+                column.typed::<ByteArrayType>().write_batch(&values, Some(&levels), None)?;
+            } else {
+                column.typed::<ByteArrayType>().write_batch(&values, None, None)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Write a table of a room's history as Parquet, returning the number of rows
+///
+/// Each page of history is written as a row group as it's handed out, the same as `export_csv`,
+/// so the whole history is never held in memory.
+#[cfg(feature = "export-parquet")]
+pub fn export_parquet<W: Write + Send>(store: &dyn RollStore, room: &str, table: Table, writer: W) -> Result<usize, ExportError> {
+    let schema = Arc::new(parse_message_type(&parquet_schema(table))?);
+    let mut file = SerializedFileWriter::new(writer, schema, Arc::new(WriterProperties::builder().build()))?;
+
+    let mut rows = 0;
+    each_page(store, room, |rolls| {
+        let page: Vec<Vec<Cell>> = rolls.iter().flat_map(|logged| table.rows(room, logged)).collect();
+        if page.is_empty() {
+            return Ok(());
+        }
+
+        let mut group = file.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = group.next_column()? {
+            let cells: Vec<&Cell> = page.iter().map(|row| &row[index]).collect();
+            write_parquet_column(&mut column, table.columns()[index].1, &cells)?;
+            column.close()?;
+            index += 1;
+        }
+        group.close()?;
+        rows += page.len();
+        Ok(())
+    })?;
+    file.close()?;
+    Ok(rows)
+}
+
+/// A room with a plain d20 and then a 2d6 whose 6 exploded
+#[cfg(test)]
+fn log_with_explosion() -> RollLog {
+    let log = RollLog::new();
+    let d20 = RollFlags::builder().n(1).die(DieType::D20).equation("1d20").build().unwrap();
+    log.record("table", "alice", Roll::with_roller(d20, &mut ScriptedRoller::new(vec![12])));
+    let exploding = RollFlags::builder().n(2).die(DieType::D6).explode(ExplodeSuccessPolicy::CompareEachDie)
+        .equation("2d6!").build().unwrap();
+    log.record("table", "bob", Roll::with_roller(exploding, &mut ScriptedRoller::new(vec![6, 3, 4])));
+    log
+}
+
+#[test]
+fn it_exports_a_row_per_die_and_per_roll() {
+    let log = log_with_explosion();
+    let mut dice: Vec<u8> = vec![];
+    assert_eq!(export_csv(&log, "table", Table::Dice, &mut dice).unwrap(), 4);
+    let dice = String::from_utf8(dice).unwrap();
+    let lines: Vec<&str> = dice.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "roll_id,sequence,session,participant,notation,die_index,die_type,raw_value,final_value,\
+                          is_dropped,is_exploded,is_rerolled,is_successful,is_manual,rolled_at,die_rolled_at");
+
+    // Newest first, the exploded 6 and then the die it rolled
+    let exploded: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!(&exploded[1..10], &["2", "table", "bob", "2d6!", "0", "d6", "6", "6", "false"]);
+    assert_eq!(exploded[10], "true");
+    assert!(lines[3].contains(",2d6!,2,d6,4,4,"));
+
+    let mut rolls: Vec<u8> = vec![];
+    assert_eq!(export_csv(&log, "table", Table::Rolls, &mut rolls).unwrap(), 2);
+    let rolls = String::from_utf8(rolls).unwrap();
+    assert_eq!(rolls.lines().count(), 3);
+    assert!(rolls.lines().nth(1).unwrap().contains(",2,table,bob,2d6!,13,3,3,,"));
+
+    assert!(export_csv(&log, "attic", Table::Rolls, &mut Vec::new()).is_err());
+}
+
+#[test]
+fn it_escapes_commas_and_quotes_in_comments() {
+    let log = RollLog::new();
+    let flags = RollFlags::builder().n(1).die(DieType::D20).equation("1d20[sneak, \"attack\"]").build().unwrap();
+    log.record("table", "alice", Roll::with_roller(flags, &mut ScriptedRoller::new(vec![17])));
+
+    let mut rolls: Vec<u8> = vec![];
+    export_csv(&log, "table", Table::Rolls, &mut rolls).unwrap();
+    let rolls = String::from_utf8(rolls).unwrap();
+    let row = rolls.lines().nth(1).unwrap();
+    assert!(row.contains(",\"1d20[sneak, \"\"attack\"\"]\",17,1,1,\"sneak, \"\"attack\"\"\","));
+}
+
+#[cfg(feature = "export-parquet")]
+#[test]
+fn it_reads_back_the_parquet_export() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+    use std::fs::{self, File};
+
+    let log = log_with_explosion();
+    let path = ::std::env::temp_dir().join(format!("roll-api-export-{}.parquet", ::std::process::id()));
+    assert_eq!(export_parquet(&log, "table", Table::Dice, File::create(&path).unwrap()).unwrap(), 4);
+
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(|row| row.unwrap()).collect();
+    fs::remove_file(&path).ok();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0].get_long(1).unwrap(), 2);
+    assert_eq!(rows[0].get_string(2).unwrap(), "table");
+    assert_eq!(rows[0].get_long(7).unwrap(), 6);
+    assert!(rows[0].get_bool(10).unwrap());
+    assert_eq!(rows[3].get_string(4).unwrap(), "1d20");
+}
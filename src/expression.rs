@@ -0,0 +1,300 @@
+// Expressions are evaluated from notation users send, so nothing in them may panic. Tests can
+// unwrap.
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+
+use limits::{CostEstimate, Limits};
+use roll::{Roll, RollFlags, TagSubtotals};
+use roller::Roller;
+use std::collections::HashMap;
+#[cfg(test)]
+use roll::MAX_EXPLOSIONS;
+
+/// A roll that only applies when a named flag is set, e.g. extra damage on a critical hit
+pub struct ConditionalTerm {
+    /// Name of the flag guarding the roll
+    pub flag: String,
+
+    /// The roll to make when the flag is set
+    pub roll: RollFlags,
+}
+
+pub enum Term {
+    /// Dice that are always rolled
+    Roll(RollFlags),
+
+    /// Dice that are rolled only when their flag is set
+    Conditional(ConditionalTerm),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExpressionError {
+    /// A conditional term references a flag that was not supplied
+    UnknownFlag(String),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TermResult {
+    /// The flag guarding the term, if any
+    pub flag: Option<String>,
+
+    /// If the term was rolled and counted towards the total
+    pub is_active: bool,
+
+    /// The roll made for the term, inactive terms are never rolled
+    pub roll: Option<Roll>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExpressionResult {
+    /// Breakdown of every term in the expression
+    pub terms: Vec<TermResult>,
+
+    /// The combined value of every active term
+    pub value: i32,
+}
+
+impl ExpressionResult {
+    /// Sum of the kept dice with each tag across every active term, e.g. `fire: 7, cold: 3`
+    pub fn tag_subtotals(&self) -> TagSubtotals {
+        TagSubtotals::of(self.terms.iter().filter_map(|t| t.roll.as_ref()).flat_map(|r| r.dice.iter()))
+    }
+}
+
+/// A sum of rolls, some of which may be guarded by flags supplied at evaluation time
+pub struct Expression {
+    pub terms: Vec<Term>,
+}
+
+impl Expression {
+    pub fn new() -> Expression {
+        Expression {
+            terms: vec![],
+        }
+    }
+
+    /// Add a roll that is always made
+    pub fn push(&mut self, roll: RollFlags) {
+        self.terms.push(Term::Roll(roll));
+    }
+
+    /// Add a roll that is only made when the flag is set
+    pub fn push_conditional(&mut self, flag: &str, roll: RollFlags) {
+        self.terms.push(Term::Conditional(ConditionalTerm {
+            flag: flag.to_string(),
+            roll,
+        }));
+    }
+
+    /// Check that every flag referenced by the expression has been supplied
+    pub fn validate(&self, flags: &HashMap<String, bool>) -> Result<(), ExpressionError> {
+        for term in &self.terms {
+            if let &Term::Conditional(ref conditional) = term {
+                if !flags.contains_key(&conditional.flag) {
+                    return Err(ExpressionError::UnknownFlag(conditional.flag.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimate the cost of evaluating the expression without rolling anything
+    ///
+    /// Conditional terms may not be rolled, so they only count towards the upper bound.
+    pub fn cost_estimate(&self, limits: &Limits) -> CostEstimate {
+        let mut dice_lower = 0;
+        let mut dice_upper_bound = 0;
+        let mut nodes = 0;
+        for term in &self.terms {
+            let (estimate, is_conditional) = match term {
+                &Term::Roll(ref roll) => (limits.estimate(roll), false),
+                &Term::Conditional(ref conditional) => (limits.estimate(&conditional.roll), true),
+            };
+            if !is_conditional {
+                dice_lower += estimate.dice_lower;
+            }
+            dice_upper_bound += estimate.dice_upper_bound;
+
+            // The guard of a conditional term is a part of its own
+            nodes += estimate.nodes + if is_conditional { 1 } else { 0 };
+        }
+
+        CostEstimate {
+            class: limits.cost_class(dice_upper_bound),
+            dice_lower,
+            dice_upper_bound,
+            nodes,
+        }
+    }
+
+    /// Roll every active term, skipping conditional terms whose flag is not set
+    pub fn evaluate(self, flags: &HashMap<String, bool>, roller: &mut Roller) -> Result<ExpressionResult, ExpressionError> {
+        self.validate(flags)?;
+
+        let mut terms = vec![];
+        let mut value = 0;
+        for (term_index, term) in self.terms.into_iter().enumerate() {
+            let mut result = match term {
+                Term::Roll(roll) => TermResult {
+                    flag: None,
+                    is_active: true,
+                    roll: Some(Roll::with_roller(roll, roller)),
+                },
+                Term::Conditional(conditional) => {
+                    if flags[&conditional.flag] {
+                        TermResult {
+                            flag: Some(conditional.flag),
+                            is_active: true,
+                            roll: Some(Roll::with_roller(conditional.roll, roller)),
+                        }
+                    } else {
+                        TermResult {
+                            flag: Some(conditional.flag),
+                            is_active: false,
+                            roll: None,
+                        }
+                    }
+                },
+            };
+
+            if let Some(ref mut roll) = result.roll {
+                roll.set_term_index(term_index as u16);
+                value += roll.value;
+            }
+            terms.push(result);
+        }
+
+        Ok(ExpressionResult {
+            terms,
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+fn d6(n: i16) -> RollFlags {
+    use die::DieType;
+
+    let mut flags = RollFlags::new();
+    flags.die = DieType::D6;
+    flags.n = n;
+    flags.max = 6;
+    flags
+}
+
+#[cfg(test)]
+fn attack() -> Expression {
+    let mut expression = Expression::new();
+    expression.push(d6(2));
+    expression.push_conditional("crit", d6(1));
+    expression.push_conditional("sneak", d6(3));
+    expression
+}
+
+#[cfg(test)]
+fn values(result: &ExpressionResult) -> Vec<i16> {
+    result.terms.iter()
+        .filter_map(|t| t.roll.as_ref())
+        .flat_map(|r| r.dice.iter().map(|d| d.value))
+        .collect()
+}
+
+#[test]
+fn it_can_evaluate_conditional_terms() {
+    let mut flags = HashMap::new();
+    for &(crit, sneak) in &[(false, false), (true, false), (false, true), (true, true)] {
+        flags.insert("crit".to_string(), crit);
+        flags.insert("sneak".to_string(), sneak);
+
+        let result = attack().evaluate(&flags, &mut Roller::new()).unwrap();
+        assert_eq!(result.terms[1].is_active, crit);
+        assert_eq!(result.terms[1].roll.is_some(), crit);
+        assert_eq!(result.terms[2].is_active, sneak);
+        assert_eq!(result.terms[2].roll.is_some(), sneak);
+
+        let dice = 2 + if crit { 1 } else { 0 } + if sneak { 3 } else { 0 };
+        assert_eq!(values(&result).len(), dice);
+        assert_eq!(result.value, values(&result).iter().fold(0, |sum, v| sum + *v as i32));
+    }
+}
+
+#[test]
+fn it_consumes_no_randomness_for_inactive_terms() {
+    let mut flags = HashMap::new();
+    flags.insert("crit".to_string(), false);
+    flags.insert("sneak".to_string(), true);
+    let conditional = attack().evaluate(&flags, &mut Roller::seeded(7)).unwrap();
+
+    let mut plain = Expression::new();
+    plain.push(d6(2));
+    plain.push(d6(3));
+    let plain = plain.evaluate(&HashMap::new(), &mut Roller::seeded(7)).unwrap();
+
+    assert_eq!(values(&conditional), values(&plain));
+    assert_eq!(conditional.value, plain.value);
+}
+
+#[test]
+fn it_numbers_dice_by_term() {
+    let mut flags = HashMap::new();
+    flags.insert("crit".to_string(), false);
+    flags.insert("sneak".to_string(), true);
+    let result = attack().evaluate(&flags, &mut Roller::seeded(7)).unwrap();
+
+    // Inactive terms keep their place, so the sneak attack dice are still term 2
+    let indices: Vec<(u16, u16)> = result.terms.iter()
+        .filter_map(|t| t.roll.as_ref())
+        .flat_map(|r| r.dice.iter().map(|d| (d.term_index, d.position_in_term)))
+        .collect();
+    assert_eq!(indices, vec![(0, 0), (0, 1), (2, 0), (2, 1), (2, 2)]);
+}
+
+#[test]
+fn it_rejects_unknown_flags() {
+    let mut flags = HashMap::new();
+    flags.insert("crit".to_string(), true);
+    let result = attack().evaluate(&flags, &mut Roller::new());
+    assert_eq!(result.err(), Some(ExpressionError::UnknownFlag("sneak".to_string())));
+}
+
+#[test]
+fn it_can_estimate_a_grouped_roll() {
+    use limits::CostClass;
+
+    let limits = Limits::default();
+    let mut expression = attack();
+    let mut exploding = d6(1);
+    exploding.explode = true;
+    expression.push(exploding);
+
+    let estimate = expression.cost_estimate(&limits);
+    assert_eq!(estimate, CostEstimate {
+        class: CostClass::Moderate,
+        dice_lower: 3,
+        dice_upper_bound: 7 + limits.max_explosions,
+        nodes: 7,
+    });
+}
+
+#[test]
+fn it_bounds_the_dice_rolled_by_the_estimate() {
+    let exploding = || {
+        let mut expression = attack();
+        let mut exploding = d6(2);
+        exploding.explode = true;
+        expression.push(exploding);
+        expression
+    };
+    let estimate = exploding().cost_estimate(&Limits::default());
+    assert_eq!((estimate.dice_lower, estimate.dice_upper_bound), (4, 8 + 2 * MAX_EXPLOSIONS));
+
+    // The 6s explode, so more dice are rolled than the fewest, but never more than the most
+    let mut flags = HashMap::new();
+    for &(crit, sneak) in &[(false, false), (true, true)] {
+        flags.insert("crit".to_string(), crit);
+        flags.insert("sneak".to_string(), sneak);
+        let values = vec![6, 6, 6, 6, 6, 6, 6, 6, 6, 1, 6, 2, 3];
+        let rolled = exploding().evaluate(&flags, &mut Roller::scripted(values)).unwrap();
+        let dice: usize = rolled.terms.iter().filter_map(|t| t.roll.as_ref()).map(|r| r.dice.len()).sum();
+        assert!(dice > estimate.dice_lower && dice <= estimate.dice_upper_bound, "{} dice rolled", dice);
+    }
+}
@@ -0,0 +1,261 @@
+use deck::Card;
+use hmac::{Hmac, Mac};
+use roll::{Roll, RollFlags};
+use roller::Roller;
+use sha2::{Digest, Sha256};
+#[cfg(test)]
+use die::DieType;
+#[cfg(test)]
+use roller::REPLAY_EPOCH;
+
+/// Ingredients for a roll seed that neither the server nor the client controls alone
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeedRecipe {
+    /// Secret chosen by the server, revealed after rolling
+    pub server: [u8; 32],
+
+    /// Seed chosen by the client
+    pub client: String,
+
+    /// Index of the roll made under this server and client pair
+    pub nonce: u64,
+}
+
+impl SeedRecipe {
+    /// Derive the seed for the current nonce
+    ///
+    /// The seed is HMAC-SHA256 keyed with the server secret over the client seed, a colon, and the
+    /// nonce written in decimal, e.g. `my-client-seed:7`. The roller reads the 32 bytes as eight
+    /// little-endian words to key a ChaCha generator. Changing any of this breaks verification of
+    /// every roll made before the change.
+    pub fn derive(&self) -> [u8; 32] {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.server).expect("HMAC can take a key of any size");
+        mac.update(self.client.as_bytes());
+        mac.update(b":");
+        mac.update(self.nonce.to_string().as_bytes());
+
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&mac.finalize().into_bytes());
+        seed
+    }
+}
+
+/// Recompute a roll from the revealed server secret, checking every die came out the same
+///
+/// The roll is replayed at its `replay_epoch`, with rolls saved without one replayed as epoch 0,
+/// see `Roller::at_replay_epoch`. Rolls from an epoch newer than this library's `REPLAY_EPOCH`
+/// can't be replayed, so they never verify.
+pub fn verify_roll(server: [u8; 32], client: &str, flags: RollFlags, roll: &Roll) -> bool {
+    let nonce = match roll.nonce {
+        Some(nonce) => nonce,
+        None => return false,
+    };
+
+    let roller = Roller::derived(SeedRecipe {
+        server,
+        client: client.to_string(),
+        nonce,
+    });
+    let mut roller = match roller.at_replay_epoch(roll.replay_epoch.unwrap_or(0)) {
+        Some(roller) => roller,
+        None => return false,
+    };
+    let replayed = Roll::with_roller(flags, &mut roller);
+
+    replayed.dice.len() == roll.dice.len() &&
+        replayed.dice.iter().zip(roll.dice.iter()).all(|(a, b)| a.value == b.value)
+}
+
+/// Commit to a finished roll without giving away its dice, written as lowercase hex
+///
+/// The commitment is HMAC-SHA256 keyed with the roll ID over the roll's replay epoch, then the
+/// equation followed by every die as its ID, an equals sign, its value and a semicolon, e.g.
+/// `epoch=1;` then `1d20` then `<die id>=17;`. Rolls without an epoch leave it out. Die IDs are
+/// random and only handed out with the roll, so the dice can't be guessed from the commitment,
+/// and the epoch can't be changed to have the roll replayed another way.
+pub fn commit_roll(roll: &Roll) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(roll.id.as_bytes()).expect("HMAC can take a key of any size");
+    if let Some(epoch) = roll.replay_epoch {
+        mac.update(format!("epoch={};", epoch).as_bytes());
+    }
+    mac.update(roll.equation.as_bytes());
+    for die in &roll.dice {
+        mac.update(format!("{}={};", die.id, die.value).as_bytes());
+    }
+
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Check a revealed roll is the one that was committed to
+pub fn verify_commitment(commitment: &str, roll: &Roll) -> bool {
+    commit_roll(roll) == commitment
+}
+
+/// Commit to a server secret before anything is rolled or drawn with it, written as lowercase hex
+///
+/// The commitment is the SHA-256 of the secret's 32 bytes. Hand it out before the client picks
+/// their seed, so the server can't pick a secret that suits the client's seed.
+pub fn commit_seed(server: &[u8; 32]) -> String {
+    Sha256::digest(server).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Check a revealed server secret is the one that was committed to
+pub fn verify_seed(commitment: &str, server: &[u8; 32]) -> bool {
+    commit_seed(server) == commitment
+}
+
+/// Shuffle a full deck with the seed of the recipe's nonce
+///
+/// The shuffle is Fisher–Yates over `Card::canonical_deck`, from the last card down: for each
+/// position `i` from 53 to 1, the card there is swapped with the card at `j`, where `j` is drawn
+/// from 0 to `i` inclusive by `Roller::between` on a generator keyed the same way as derived rolls,
+/// see `SeedRecipe::derive`. Changing any of this breaks verification of every deck dealt before.
+pub fn shuffled_deck(recipe: &SeedRecipe) -> Vec<Card> {
+    let mut roller = Roller::derived(recipe.clone());
+    roller.begin_roll();
+
+    let mut cards = Card::canonical_deck();
+    for i in (1..cards.len()).rev() {
+        let j = roller.between(0, i as i16) as usize;
+        cards.swap(i, j);
+    }
+    cards
+}
+
+/// Replay a shuffle from its revealed recipe, checking the cards drawn are the top of the deck in
+/// the order they were drawn
+///
+/// Each reshuffle has its own nonce, so check the draws of each shuffle with the recipe at its
+/// nonce.
+pub fn verify_deck(recipe: &SeedRecipe, draws: &[Card]) -> bool {
+    shuffled_deck(recipe).starts_with(draws)
+}
+
+#[cfg(test)]
+fn server_secret() -> [u8; 32] {
+    let mut server = [0u8; 32];
+    for (i, byte) in server.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    server
+}
+
+#[cfg(test)]
+fn d20(n: i16) -> RollFlags {
+    let mut flags = RollFlags::new();
+    flags.die = DieType::D20;
+    flags.n = n;
+    flags.max = 20;
+    flags
+}
+
+#[test]
+fn it_derives_known_seeds() {
+    let mut recipe = SeedRecipe {
+        server: server_secret(),
+        client: "client-seed".to_string(),
+        nonce: 0,
+    };
+    assert_eq!(recipe.derive(), [
+        0xa2, 0xb4, 0xca, 0x1f, 0x22, 0x61, 0x2d, 0x03, 0x8d, 0x9f, 0x37, 0x59, 0x52, 0xdd, 0xce, 0xd1,
+        0x3d, 0x8c, 0xfe, 0x0f, 0x5a, 0x1e, 0x75, 0x4a, 0x3d, 0x7f, 0x48, 0xc3, 0x3c, 0xa0, 0x3a, 0x98,
+    ]);
+
+    recipe.nonce = 1;
+    assert_eq!(recipe.derive(), [
+        0xef, 0xac, 0xc0, 0x60, 0x2b, 0xcd, 0xa0, 0x0e, 0x13, 0xaa, 0x7a, 0x15, 0xc2, 0x21, 0xcc, 0x69,
+        0xec, 0x6c, 0xf6, 0x85, 0xa5, 0xb0, 0xf9, 0x8f, 0xc9, 0x39, 0x7e, 0xbf, 0x59, 0xbc, 0x18, 0xb1,
+    ]);
+}
+
+#[test]
+fn it_increments_the_nonce_per_roll() {
+    let mut roller = Roller::derived(SeedRecipe {
+        server: server_secret(),
+        client: "client-seed".to_string(),
+        nonce: 5,
+    });
+    let first = Roll::with_roller(d20(4), &mut roller);
+    let second = Roll::with_roller(d20(4), &mut roller);
+    assert_eq!(first.nonce, Some(5));
+    assert_eq!(second.nonce, Some(6));
+
+    // Rolling the same nonce again gives the same dice
+    let mut roller = Roller::derived(SeedRecipe {
+        server: server_secret(),
+        client: "client-seed".to_string(),
+        nonce: 6,
+    });
+    let replayed = Roll::with_roller(d20(4), &mut roller);
+    let values: Vec<i16> = second.dice.iter().map(|d| d.value).collect();
+    let replayed_values: Vec<i16> = replayed.dice.iter().map(|d| d.value).collect();
+    assert_eq!(values, replayed_values);
+
+    // Plain rolls don't carry a nonce
+    assert_eq!(Roll::new(d20(1)).nonce, None);
+}
+
+#[test]
+fn it_can_verify_a_roll() {
+    let mut roller = Roller::derived(SeedRecipe {
+        server: server_secret(),
+        client: "client-seed".to_string(),
+        nonce: 0,
+    });
+    Roll::with_roller(d20(8), &mut roller);
+    let roll = Roll::with_roller(d20(8), &mut roller);
+
+    assert!(verify_roll(server_secret(), "client-seed", d20(8), &roll));
+    assert!(!verify_roll([7; 32], "client-seed", d20(8), &roll));
+    assert!(!verify_roll(server_secret(), "other-seed", d20(8), &roll));
+}
+
+#[test]
+fn it_can_commit_to_a_roll() {
+    let mut roll = Roll::with_roller(d20(3), &mut Roller::seeded(3));
+    let commitment = commit_roll(&roll);
+    assert_eq!(commitment.len(), 64);
+    assert!(verify_commitment(&commitment, &roll));
+
+    roll.dice[1].value = if roll.dice[1].value == 20 { 1 } else { 20 };
+    assert!(!verify_commitment(&commitment, &roll));
+
+    // The epoch is committed to along with the dice
+    let mut roll = Roll::with_roller(d20(3), &mut Roller::seeded(3));
+    let commitment = commit_roll(&roll);
+    roll.replay_epoch = Some(0);
+    assert!(!verify_commitment(&commitment, &roll));
+}
+
+#[test]
+fn it_replays_a_roll_at_its_epoch() {
+    let recipe = SeedRecipe {
+        server: server_secret(),
+        client: "client-seed".to_string(),
+        nonce: 0,
+    };
+    let mut fate = RollFlags::new();
+    fate.die = DieType::Fate;
+    fate.n = 8;
+    fate.sides = Some(vec![-1, 0, 1]);
+
+    let roll = Roll::with_roller(fate.clone(), &mut Roller::derived(recipe.clone()));
+    assert_eq!(roll.replay_epoch, Some(REPLAY_EPOCH));
+    assert!(verify_roll(server_secret(), "client-seed", fate.clone(), &roll));
+
+    // Rolls saved before the epoch was kept were sampled the old way, and replay that way
+    let mut old = Roll::with_roller(fate.clone(), &mut Roller::derived(recipe.clone()).at_replay_epoch(0).unwrap());
+    old.replay_epoch = None;
+    assert!(verify_roll(server_secret(), "client-seed", fate.clone(), &old));
+    let mut mislabeled = roll.clone();
+    mislabeled.replay_epoch = None;
+    assert_ne!(old.dice.iter().map(|die| die.value).collect::<Vec<_>>(),
+               roll.dice.iter().map(|die| die.value).collect::<Vec<_>>());
+    assert!(!verify_roll(server_secret(), "client-seed", fate.clone(), &mislabeled));
+
+    // An epoch from a newer version of the library can't be replayed
+    let mut newer = roll.clone();
+    newer.replay_epoch = Some(REPLAY_EPOCH + 1);
+    assert!(Roller::derived(recipe).at_replay_epoch(REPLAY_EPOCH + 1).is_none());
+    assert!(!verify_roll(server_secret(), "client-seed", fate, &newer));
+}
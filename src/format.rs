@@ -0,0 +1,359 @@
+use die::{Die, DieType};
+use locale::{Clause, Locale, Message};
+use luck::{LuckReport, MIN_SAMPLE_SIZE};
+use roll::{ExplodeSuccessPolicy, Roll};
+use roller::RollBlock;
+use std::cmp::Ordering;
+use std::fmt::{self, Write};
+use std::mem;
+#[cfg(test)]
+use locale::English;
+#[cfg(test)]
+use luck::{DieLuck, ParticipantLuck};
+#[cfg(test)]
+use die::TakeKind;
+#[cfg(test)]
+use parser::{parse, parse_many};
+#[cfg(test)]
+use roll::RollFlags;
+#[cfg(test)]
+use roller::Roller;
+#[cfg(test)]
+use stats::{Distribution, RollProbability};
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// Writes a roll out as text for people
+pub trait RollFormatter {
+    /// Write the roll to `out`, e.g. a buffer reused for every roll
+    fn format_into(&self, roll: &Roll, out: &mut dyn Write) -> fmt::Result;
+
+    /// The roll written to a new string
+    fn format(&self, roll: &Roll) -> String {
+        let mut text = String::new();
+        self.format_into(roll, &mut text).expect("writing to a string never fails");
+        text
+    }
+
+    /// Write each roll of a block on a line of its own, after its label if it has one
+    fn format_block_into(&self, block: &RollBlock, out: &mut dyn Write) -> fmt::Result {
+        for (position, rolled) in block.rolls.iter().enumerate() {
+            if position > 0 {
+                out.write_char('\n')?;
+            }
+            if let Some(ref label) = rolled.label {
+                write!(out, "{}: ", label)?;
+            }
+            self.format_into(&rolled.roll, out)?;
+        }
+        Ok(())
+    }
+
+    /// The block written to a new string
+    fn format_block(&self, block: &RollBlock) -> String {
+        let mut text = String::new();
+        self.format_block_into(block, &mut text).expect("writing to a string never fails");
+        text
+    }
+}
+
+/// Writes a luck report out as text for people, e.g. to post to chat after a session
+pub trait LuckFormatter {
+    fn format_luck(&self, report: &LuckReport) -> String;
+}
+
+/// The equation, the dice with dropped ones struck through, and the total, e.g. `3d6: [4, ~~2~~, 6] = 10`
+///
+/// A taken die says so, e.g. `1d20+5: [took 10] = 15`, and a roll annotated with how likely it
+/// was says that after the total, e.g. `1d20: [20] = 20 (top 5% of possible outcomes)`.
+pub struct SummaryFormatter;
+
+impl RollFormatter for SummaryFormatter {
+    fn format_into(&self, roll: &Roll, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(&roll.equation)?;
+        out.write_str(": [")?;
+
+        // Dice are usually rolled in notation order already, so they're only sorted when they aren't
+        if roll.dice.windows(2).all(|pair| pair[0].cmp_in_notation(&pair[1]) != Ordering::Greater) {
+            write_summary_dice(roll.dice.iter(), out)?;
+        } else {
+            write_summary_dice(roll.dice_in_notation_order().into_iter(), out)?;
+        }
+        write!(out, "] = {}", roll.value)?;
+        if let Some(probability) = roll.probability {
+            write!(out, " ({})", probability)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_summary_dice<'a, I: Iterator<Item = &'a Die>>(dice: I, out: &mut dyn Write) -> fmt::Result {
+    for (position, die) in dice.enumerate() {
+        if position > 0 {
+            out.write_str(", ")?;
+        }
+        if die.taken.is_some() {
+            write!(out, "took {}", die.value)?;
+        } else if die.is_dropped {
+            write!(out, "~~{}~~", die.value)?;
+        } else {
+            write!(out, "{}", die.value)?;
+        }
+    }
+    Ok(())
+}
+
+/// One line per participant, luckiest first, e.g. `1. ana: z +2.31 over 42 dice, unlikely to be chance`
+impl LuckFormatter for SummaryFormatter {
+    fn format_luck(&self, report: &LuckReport) -> String {
+        let lines: Vec<String> = report.participants.iter().map(|luck| {
+            let caveat = if luck.sample_size < MIN_SAMPLE_SIZE {
+                "too few to tell"
+            } else if luck.is_significant {
+                "unlikely to be chance"
+            } else {
+                "could be chance"
+            };
+            format!("{}. {}: z {:+.2} over {} dice, {}", luck.rank, luck.participant, luck.z_score, luck.sample_size, caveat)
+        }).collect();
+        lines.join("\n")
+    }
+}
+
+/// Full sentences that read well aloud, e.g. to screen reader users, written by a locale
+///
+/// Die types are spelled out and what happened to each die is said after its value. Crits on kept
+/// d20s are stated, along with success or failure when there's a difficulty, and how likely the
+/// total was when the roll was annotated with it.
+pub struct VerboseFormatter<L: Locale> {
+    difficulty: Option<i32>,
+    locale: L,
+}
+
+impl<L: Locale> VerboseFormatter<L> {
+    pub fn new(locale: L) -> VerboseFormatter<L> {
+        VerboseFormatter {
+            difficulty: None,
+            locale,
+        }
+    }
+
+    /// Also say if the total met the difficulty
+    pub fn against(mut self, difficulty: i32) -> VerboseFormatter<L> {
+        self.difficulty = Some(difficulty);
+        self
+    }
+}
+
+impl<L: Locale> VerboseFormatter<L> {
+    /// Write a sentence, after a space unless it's the first
+    fn say(&self, message: &Message, is_first: &mut bool, out: &mut dyn Write) -> fmt::Result {
+        if !mem::replace(is_first, false) {
+            out.write_str(" ")?;
+        }
+        self.locale.write_text(message, out)
+    }
+}
+
+impl<L: Locale> RollFormatter for VerboseFormatter<L> {
+    fn format_into(&self, roll: &Roll, out: &mut dyn Write) -> fmt::Result {
+        let is_first = &mut true;
+        match roll.dice.first() {
+            Some(taken) if taken.taken.is_some() => self.say(&Message::Took { value: taken.value }, is_first, out)?,
+            first => {
+                let dice = roll.dice_in_notation_order().into_iter().map(|die| self.locale.text(&Message::Die {
+                    clauses: clauses(die),
+                    value: die.value,
+                })).collect();
+                let (die, sides) = first.map_or((DieType::Other, 0), |die| (die.die, die.max));
+                self.say(&Message::Rolled {
+                    count: rolled_count(roll),
+                    dice,
+                    die,
+                    sides,
+                }, is_first, out)?;
+            },
+        }
+
+        let modifier = roll.modifiers.iter().fold(0, |sum, modifier| sum + *modifier as i32);
+        if modifier != 0 {
+            self.say(&Message::Modifier(modifier), is_first, out)?;
+        }
+        self.say(&Message::Total {
+            is_after_dropping: roll.dice.iter().any(|die| die.is_dropped && !die.is_rerolled),
+            value: roll.value,
+        }, is_first, out)?;
+
+        // A taken 20 wasn't rolled, so it isn't a crit
+        let mut kept_d20s = roll.dice.iter()
+            .filter(|die| die.die == DieType::D20 && !die.is_dropped && die.taken.is_none());
+        if kept_d20s.clone().any(|die| die.value == 20) {
+            self.say(&Message::CriticalHit, is_first, out)?;
+        } else if kept_d20s.any(|die| die.value == 1) {
+            self.say(&Message::CriticalMiss, is_first, out)?;
+        }
+
+        if let Some(difficulty) = self.difficulty {
+            self.say(&Message::Against {
+                difficulty,
+                is_success: roll.value >= difficulty,
+            }, is_first, out)?;
+        }
+        if let Some(probability) = roll.probability {
+            self.say(&Message::Probability(probability), is_first, out)?;
+        }
+        Ok(())
+    }
+}
+
+/// A sentence for each participant, luckiest first, saying how much to make of their luck
+impl<L: Locale> LuckFormatter for VerboseFormatter<L> {
+    fn format_luck(&self, report: &LuckReport) -> String {
+        let sentences: Vec<String> = report.participants.iter().map(|luck| self.locale.text(&Message::Luck {
+            is_large_enough: luck.sample_size >= MIN_SAMPLE_SIZE,
+            is_significant: luck.is_significant,
+            participant: luck.participant.clone(),
+            rank: luck.rank,
+            sample_size: luck.sample_size,
+            z_score: luck.z_score,
+        })).collect();
+        sentences.join(" ")
+    }
+}
+
+/// What happened to the die, a rerolled die is always dropped so that goes unsaid
+fn clauses(die: &Die) -> Vec<Clause> {
+    let mut clauses = vec![];
+    if die.is_exploded {
+        clauses.push(Clause::Exploded);
+    }
+    if die.is_rerolled {
+        clauses.push(Clause::Rerolled);
+    } else if die.is_dropped {
+        clauses.push(Clause::Dropped);
+    }
+    clauses
+}
+
+/// Number of dice the roll started with, leaving out those added by rerolls and explosions
+fn rolled_count(roll: &Roll) -> usize {
+    let rerolls = roll.dice.iter().filter(|die| die.child.is_some()).count();
+    let explosions = match roll.explode_policy {
+        ExplodeSuccessPolicy::CompareEachDie => roll.dice.iter().filter(|die| die.is_exploded).count(),
+        ExplodeSuccessPolicy::CompoundThenCompare => 0,
+    };
+    roll.dice.len().saturating_sub(rerolls + explosions)
+}
+
+#[cfg(test)]
+fn d20(modifier: i16, value: i16) -> Roll {
+    let flags = RollFlags::builder().n(1).die(DieType::D20).modifier(modifier).build().unwrap();
+    Roll::with_roller(flags, &mut ScriptedRoller::new(vec![value]))
+}
+
+#[test]
+fn it_says_which_dice_were_dropped() {
+    let flags = RollFlags::builder().n(3).die(DieType::D6).kh(2).equation("3d6kh2").build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![4, 2, 6]));
+    assert_eq!(VerboseFormatter::new(English).against(9).format(&roll),
+               "Rolled three six-sided dice: 4, 2 which was dropped, and 6. Total after dropping: 10. \
+                This was a success against difficulty 9.");
+    assert_eq!(SummaryFormatter.format(&roll), "3d6kh2: [4, ~~2~~, 6] = 10");
+}
+
+#[test]
+fn it_says_which_dice_exploded() {
+    let flags = RollFlags::builder().n(1).die(DieType::D6).explode(ExplodeSuccessPolicy::CompareEachDie)
+        .modifier(1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 6, 2]));
+    assert_eq!(VerboseFormatter::new(English).format(&roll),
+               "Rolled a six-sided die: 6 which exploded, 6 which exploded, and 2. Adding 1. Total: 15.");
+}
+
+#[test]
+fn it_states_crits() {
+    assert_eq!(VerboseFormatter::new(English).format(&d20(5, 20)),
+               "Rolled a twenty-sided die: 20. Adding 5. Total: 25. This was a critical hit.");
+    assert_eq!(VerboseFormatter::new(English).against(10).format(&d20(3, 1)),
+               "Rolled a twenty-sided die: 1. Adding 3. Total: 4. This was a critical miss. \
+                This was a failure against difficulty 10.");
+}
+
+#[test]
+fn it_says_how_likely_the_total_was() {
+    let flags = parse("1d20+5").unwrap().pop().unwrap();
+    let mut roll = Roll::with_roller(flags.clone(), &mut ScriptedRoller::new(vec![20]));
+    roll.probability = Some(RollProbability::of(&Distribution::exact(&flags).unwrap(), roll.value));
+    assert_eq!(SummaryFormatter.format(&roll), "1d20+5: [20] = 25 (top 5% of possible outcomes)");
+    assert_eq!(VerboseFormatter::new(English).format(&roll),
+               "Rolled a twenty-sided die: 20. Adding 5. Total: 25. This was a critical hit. \
+                This was in the top 5% of possible outcomes.");
+}
+
+#[test]
+fn it_compares_against_a_difficulty() {
+    assert_eq!(VerboseFormatter::new(English).against(12).format(&d20(-1, 9)),
+               "Rolled a twenty-sided die: 9. Subtracting 1. Total: 8. This was a failure against difficulty 12.");
+    assert_eq!(VerboseFormatter::new(English).against(8).format(&d20(-1, 9)),
+               "Rolled a twenty-sided die: 9. Subtracting 1. Total: 8. This was a success against difficulty 8.");
+}
+
+#[test]
+fn it_says_when_a_check_was_taken() {
+    let taken = Roller::take(TakeKind::Twenty, 5, None);
+    assert_eq!(SummaryFormatter.format(&taken), "1d20+5: [took 20] = 25");
+    assert_eq!(VerboseFormatter::new(English).against(30).format(&taken),
+               "Took 20 instead of rolling. Adding 5. Total: 25. This was a failure against difficulty 30.");
+}
+
+#[test]
+fn it_writes_a_block_a_roll_to_a_line() {
+    let expressions = parse_many("Attack: 1d20+7\nDamage: 2d6+4").unwrap();
+    let block = Roller::scripted(vec![15, 3, 5]).roll_many_labeled(&expressions).unwrap();
+    assert_eq!(SummaryFormatter.format_block(&block), "Attack: 1d20+7: [15] = 22\nDamage: 2d6+4: [3, 5] = 12");
+
+    let expressions = parse_many("Attack: 1d20+7; 2d6+4").unwrap();
+    let block = Roller::scripted(vec![15, 3, 5]).roll_many_labeled(&expressions).unwrap();
+    assert_eq!(VerboseFormatter::new(English).format_block(&block),
+               "Attack: Rolled a twenty-sided die: 15. Adding 7. Total: 22.\n\
+                Rolled two six-sided dice: 3 and 5. Adding 4. Total: 12.");
+}
+
+#[cfg(test)]
+fn luck(participant: &str, rank: usize, sample_size: u64, z_score: f64, is_significant: bool) -> ParticipantLuck {
+    ParticipantLuck {
+        dice: vec![DieLuck {
+            die: DieType::D20,
+            expected_mean: 10.5,
+            expected_nat_max_rate: 0.05,
+            expected_nat_min_rate: 0.05,
+            is_large_enough: sample_size >= MIN_SAMPLE_SIZE,
+            is_significant,
+            nat_max_rate: 0.05,
+            nat_min_rate: 0.05,
+            observed_mean: 10.5,
+            sample_size,
+            z_score,
+        }],
+        is_significant,
+        participant: participant.to_string(),
+        rank,
+        sample_size,
+        z_score,
+    }
+}
+
+#[test]
+fn it_writes_out_who_was_luckiest() {
+    let report = LuckReport {
+        participants: vec![luck("ana", 1, 42, 2.314, true), luck("bo", 2, 35, -0.5, false), luck("cy", 3, 2, -2.4, false)],
+    };
+    assert_eq!(SummaryFormatter.format_luck(&report),
+               "1. ana: z +2.31 over 42 dice, unlikely to be chance\n\
+                2. bo: z -0.50 over 35 dice, could be chance\n\
+                3. cy: z -2.40 over 2 dice, too few to tell");
+    assert_eq!(VerboseFormatter::new(English).format_luck(&LuckReport {
+        participants: vec![luck("ana", 1, 42, 2.314, true), luck("cy", 2, 2, -2.4, false)],
+    }), "ana ranked first, rolling 2.3 standard errors above fair dice over 42 dice. That's unlikely to be chance. \
+         cy ranked second, rolling 2.4 standard errors below fair dice over 2 dice. That's too few dice to tell.");
+}
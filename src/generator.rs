@@ -0,0 +1,478 @@
+use limits::Limits;
+use parser::parse;
+use roll::{Roll, RollError};
+use roller::Roller;
+use serde_json;
+use table::{TableError, TableResult, Tables};
+#[cfg(test)]
+use table::{Table, TableEntry};
+
+/// One option of a weighted pick, chosen `weight` times in a hundred
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WeightedStep {
+    pub step: Step,
+    pub weight: u8,
+}
+
+/// A part of a generator
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Step {
+    /// Roll dice, multiplying the total, e.g. gems worth `2d6` times 10 gp
+    Dice {
+        label: String,
+        multiplier: i32,
+        notation: String,
+    },
+
+    /// Another generator, run where the step is
+    Generator(Generator),
+
+    /// The same thing every time, e.g. "A bag of holding"
+    Literal(String),
+
+    /// Roll for a count, then run the steps that many times, e.g. `1d4` items. A count written as
+    /// a number isn't rolled. Each run counts as a die against the limits.
+    Repeat {
+        count: String,
+        steps: Vec<Step>,
+    },
+
+    /// Roll once on the table with the ID
+    Table(String),
+
+    /// Roll a d100 to pick one of the steps by weight, the weights adding up to 100
+    Weighted(Vec<WeightedStep>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GeneratorError {
+    /// The definition isn't JSON, or isn't shaped like a generator
+    Json(String),
+
+    /// A step (first, e.g. `steps[1].Repeat.steps[0]`) can't be run, and why (second)
+    InvalidStep(String, String),
+
+    /// Rolling a step (first) failed
+    Roll(String, RollError),
+
+    /// Rolling on a table for a step (first) failed
+    Table(String, TableError),
+
+    /// More dice (first) were rolled over the whole generation than a roll can start with (second)
+    TooManyDice(usize, i16),
+}
+
+/// What a step turned up
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Generated {
+    Dice {
+        label: String,
+        roll: Roll,
+
+        /// The roll's value times the step's multiplier
+        value: i64,
+    },
+    Generator(GeneratedResult),
+    Literal(String),
+    Repeat {
+        /// The roll for the count, none when it was written as a number
+        count_roll: Option<Roll>,
+
+        /// What the steps turned up each time they were run
+        runs: Vec<Vec<Generated>>,
+    },
+    Table(TableResult),
+    Weighted {
+        /// Position of the option picked
+        option: usize,
+
+        /// The d100 that picked it
+        pick: i16,
+        result: Box<Generated>,
+    },
+}
+
+/// Everything a generator turned up, and every die and table roll used to get there
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GeneratedResult {
+    /// Dice rolled by this generator and those inside it, counting one die per table roll,
+    /// weighted pick and run of a repeat
+    pub dice: usize,
+
+    pub name: String,
+
+    /// What each step turned up, in order
+    pub results: Vec<Generated>,
+}
+
+/// A treasure generator or the like, made of dice, tables and weighted picks, e.g. "roll 1d4
+/// items; each item: 60% on table A, 30% on table B, 10% gems worth 2d6 × 10 gp"
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Generator {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl Generator {
+    /// Read a generator from JSON, checking every step against the tables it can roll on
+    pub fn from_json(json: &str, tables: &Tables) -> Result<Generator, GeneratorError> {
+        let generator: Generator = serde_json::from_str(json).map_err(|error| GeneratorError::Json(error.to_string()))?;
+        generator.validate(tables)?;
+        Ok(generator)
+    }
+
+    /// Check every step can be run, saying where the first that can't is
+    pub fn validate(&self, tables: &Tables) -> Result<(), GeneratorError> {
+        validate_steps(&self.steps, "steps", tables)
+    }
+
+    /// Run every step, failing once the dice of the whole generation go past `limits.max_dice`
+    pub fn generate(&self, tables: &Tables, limits: &Limits, roller: &mut Roller) -> Result<GeneratedResult, GeneratorError> {
+        self.validate(tables)?;
+        let mut generation = Generation {
+            dice: 0,
+            limits,
+            tables,
+        };
+        generation.run(self, roller)
+    }
+}
+
+fn validate_steps(steps: &[Step], path: &str, tables: &Tables) -> Result<(), GeneratorError> {
+    for (i, step) in steps.iter().enumerate() {
+        validate_step(step, &format!("{}[{}]", path, i), tables)?;
+    }
+    Ok(())
+}
+
+fn validate_step(step: &Step, path: &str, tables: &Tables) -> Result<(), GeneratorError> {
+    let invalid = |reason: String| Err(GeneratorError::InvalidStep(path.to_string(), reason));
+    match step {
+        &Step::Dice { ref notation, .. } => {
+            if parse(notation).map_or(true, |rolls| rolls.is_empty()) {
+                return invalid(format!("{} isn't dice notation", notation));
+            }
+        },
+        &Step::Generator(ref generator) => validate_steps(&generator.steps, &format!("{}.Generator.steps", path), tables)?,
+        &Step::Literal(_) => {},
+        &Step::Repeat { ref count, ref steps } => {
+            if count.parse::<u16>().is_err() && parse(count).map_or(true, |rolls| rolls.is_empty()) {
+                return invalid(format!("{} isn't a number or dice notation", count));
+            }
+            validate_steps(steps, &format!("{}.Repeat.steps", path), tables)?;
+        },
+        &Step::Table(ref id) => {
+            if tables.get(id).is_none() {
+                return invalid(format!("there's no table {}", id));
+            }
+        },
+        &Step::Weighted(ref options) => {
+            let total: u32 = options.iter().map(|option| option.weight as u32).sum();
+            if total != 100 {
+                return invalid(format!("weights add up to {}, not 100", total));
+            }
+            for (i, option) in options.iter().enumerate() {
+                validate_step(&option.step, &format!("{}.Weighted[{}].step", path, i), tables)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+struct Generation<'a> {
+    dice: usize,
+    limits: &'a Limits,
+    tables: &'a Tables,
+}
+
+impl<'a> Generation<'a> {
+    fn run(&mut self, generator: &Generator, roller: &mut Roller) -> Result<GeneratedResult, GeneratorError> {
+        let dice = self.dice;
+        let mut results = vec![];
+        for (i, step) in generator.steps.iter().enumerate() {
+            results.push(self.step(step, &format!("steps[{}]", i), roller)?);
+        }
+        Ok(GeneratedResult {
+            dice: self.dice - dice,
+            name: generator.name.clone(),
+            results,
+        })
+    }
+
+    fn step(&mut self, step: &Step, path: &str, roller: &mut Roller) -> Result<Generated, GeneratorError> {
+        Ok(match step {
+            &Step::Dice { ref label, multiplier, ref notation } => {
+                let roll = self.roll(notation, path, roller)?;
+                Generated::Dice {
+                    label: label.clone(),
+                    value: roll.value as i64 * multiplier as i64,
+                    roll,
+                }
+            },
+            &Step::Generator(ref generator) => Generated::Generator(self.run(generator, roller)?),
+            &Step::Literal(ref text) => Generated::Literal(text.clone()),
+            &Step::Repeat { ref count, ref steps } => {
+                let (times, count_roll) = match count.parse::<u16>() {
+                    Ok(times) => (times as i32, None),
+                    Err(_) => {
+                        let roll = self.roll(count, path, roller)?;
+                        (roll.value, Some(roll))
+                    },
+                };
+                // Runs count as dice, so repeats inside repeats can't multiply past the limits
+                let times = times.max(0) as usize;
+                self.add_dice(times)?;
+                let mut runs = vec![];
+                for _ in 0..times {
+                    let mut run = vec![];
+                    for (i, step) in steps.iter().enumerate() {
+                        run.push(self.step(step, &format!("{}.Repeat.steps[{}]", path, i), roller)?);
+                    }
+                    runs.push(run);
+                }
+                Generated::Repeat {
+                    count_roll,
+                    runs,
+                }
+            },
+            &Step::Table(ref id) => {
+                let result = self.tables.roll(id, roller).map_err(|error| GeneratorError::Table(path.to_string(), error))?;
+                self.add_dice(result.len())?;
+                Generated::Table(result)
+            },
+            &Step::Weighted(ref options) => {
+                self.add_dice(1)?;
+                let pick = roller.between(1, 100);
+                let mut reached = 0;
+                let option = options.iter().position(|option| {
+                    reached += option.weight as i16;
+                    pick <= reached
+                }).expect("weights are checked to add up to 100");
+                let result = self.step(&options[option].step, &format!("{}.Weighted[{}].step", path, option), roller)?;
+                Generated::Weighted {
+                    option,
+                    pick,
+                    result: Box::new(result),
+                }
+            },
+        })
+    }
+
+    /// Roll notation checked against the limits, counting its dice towards the whole generation
+    fn roll(&mut self, notation: &str, path: &str, roller: &mut Roller) -> Result<Roll, GeneratorError> {
+        let error = |error: RollError| GeneratorError::Roll(path.to_string(), error);
+        let flags = parse(notation).and_then(|mut rolls| rolls.pop()).ok_or(error(RollError::InvalidNotation))?;
+        flags.validate().and_then(|_| self.limits.check(&flags)).map_err(error)?;
+        let n = flags.n.max(0) as usize;
+        self.add_dice(n)?;
+
+        // Rerolls and explosions add to the dice after rolling
+        let roll = Roll::with_roller(flags, roller);
+        self.add_dice(roll.dice.len().saturating_sub(n))?;
+        Ok(roll)
+    }
+
+    fn add_dice(&mut self, dice: usize) -> Result<(), GeneratorError> {
+        self.dice += dice;
+        if self.dice > self.limits.max_dice as usize {
+            return Err(GeneratorError::TooManyDice(self.dice, self.limits.max_dice));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn it_picks_a_step_by_weight() {
+    let mut tables = Tables::new();
+    tables.insert(Table::new("art", 6, vec![
+        TableEntry::new(1, 3, "Silver ewer"),
+        TableEntry::new(4, 6, "Gold idol"),
+    ]).unwrap());
+    let generator = Generator {
+        name: "item".to_string(),
+        steps: vec![Step::Weighted(vec![
+            WeightedStep { step: Step::Table("art".to_string()), weight: 60 },
+            WeightedStep { step: Step::Literal("Nothing".to_string()), weight: 40 },
+        ])],
+    };
+
+    // A 45 picks the art table, which turns up its 5, and a 75 picks nothing
+    let result = generator.generate(&tables, &Limits::default(), &mut Roller::scripted(vec![45, 5])).unwrap();
+    assert_eq!(result.dice, 2);
+    match result.results[0] {
+        Generated::Weighted { option: 0, pick: 45, ref result } => match **result {
+            Generated::Table(ref art) => assert_eq!(art.entry.text, "Gold idol"),
+            _ => panic!("the art option should roll on its table"),
+        },
+        _ => panic!("a 45 should pick the first option"),
+    }
+    let result = generator.generate(&tables, &Limits::default(), &mut Roller::scripted(vec![75])).unwrap();
+    match result.results[0] {
+        Generated::Weighted { option: 1, pick: 75, ref result } => match **result {
+            Generated::Literal(ref text) => assert_eq!(text, "Nothing"),
+            _ => panic!("the second option should be the literal"),
+        },
+        _ => panic!("a 75 should pick the second option"),
+    }
+}
+
+#[test]
+fn it_repeats_steps_a_rolled_number_of_times() {
+    let generator = Generator {
+        name: "gems".to_string(),
+        steps: vec![Step::Repeat {
+            count: "1d4".to_string(),
+            steps: vec![Step::Dice {
+                label: "gp".to_string(),
+                multiplier: 10,
+                notation: "1d6".to_string(),
+            }],
+        }],
+    };
+    let result = generator.generate(&Tables::new(), &Limits::default(), &mut Roller::scripted(vec![3, 2, 4, 6])).unwrap();
+
+    // The count's die, a die for each of the 3 runs and the d6 of each
+    assert_eq!(result.dice, 7);
+    match result.results[0] {
+        Generated::Repeat { ref count_roll, ref runs } => {
+            assert_eq!(count_roll.as_ref().map(|roll| roll.value), Some(3));
+            let values: Vec<i64> = runs.iter().map(|run| match run[0] {
+                Generated::Dice { value, .. } => value,
+                _ => panic!("each run should roll the gems"),
+            }).collect();
+            assert_eq!(values, vec![20, 40, 60]);
+        },
+        _ => panic!("the step should repeat"),
+    }
+}
+
+#[test]
+fn it_runs_generators_inside_generators() {
+    let coins = Generator {
+        name: "coins".to_string(),
+        steps: vec![Step::Dice {
+            label: "gp".to_string(),
+            multiplier: 100,
+            notation: "3d6".to_string(),
+        }],
+    };
+    let hoard = Generator {
+        name: "hoard".to_string(),
+        steps: vec![Step::Literal("A bag of holding".to_string()), Step::Generator(coins)],
+    };
+    let result = hoard.generate(&Tables::new(), &Limits::default(), &mut Roller::scripted(vec![1, 2, 3])).unwrap();
+    assert_eq!(result.dice, 3);
+    match result.results[1] {
+        Generated::Generator(ref coins) => {
+            assert_eq!((coins.name.as_str(), coins.dice), ("coins", 3));
+            match coins.results[0] {
+                Generated::Dice { ref label, value, .. } => assert_eq!((label.as_str(), value), ("gp", 600)),
+                _ => panic!("the coins should be dice"),
+            }
+        },
+        _ => panic!("the second step should be the coins"),
+    }
+}
+
+#[test]
+fn it_generates_the_same_from_a_seed() {
+    let generator = Generator {
+        name: "gems".to_string(),
+        steps: vec![Step::Repeat {
+            count: "1d4".to_string(),
+            steps: vec![Step::Dice {
+                label: "gp".to_string(),
+                multiplier: 10,
+                notation: "2d6".to_string(),
+            }],
+        }],
+    };
+    let seeded = generator.generate(&Tables::new(), &Limits::default(), &mut Roller::seeded(42)).unwrap();
+    let again = generator.generate(&Tables::new(), &Limits::default(), &mut Roller::seeded(42)).unwrap();
+    assert_eq!(serde_json::to_value(&seeded).unwrap(), serde_json::to_value(&again).unwrap());
+
+    // Results keep their shape as JSON
+    let json = serde_json::to_value(&seeded).unwrap();
+    let restored: GeneratedResult = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&restored).unwrap(), json);
+}
+
+#[test]
+fn it_reads_definitions_from_json() {
+    let mut tables = Tables::new();
+    tables.insert(Table::new("art", 6, vec![TableEntry::new(1, 6, "Silver ewer")]).unwrap());
+    let json = r#"{"name": "hoard", "steps": [{"Repeat": {"count": "1d4", "steps": [{"Weighted": [
+        {"weight": 60, "step": {"Table": "art"}},
+        {"weight": 40, "step": {"Literal": "Nothing"}}
+    ]}]}}]}"#;
+    let generator = Generator::from_json(json, &tables).unwrap();
+    assert_eq!(generator.name, "hoard");
+    let written = serde_json::to_string(&generator).unwrap();
+    assert_eq!(Generator::from_json(&written, &tables), Ok(generator));
+
+    let short = r#"{"name": "hoard", "steps": [{"Repeat": {"count": "1d4", "steps": [{"Weighted": [
+        {"weight": 60, "step": {"Table": "art"}},
+        {"weight": 30, "step": {"Literal": "Nothing"}}
+    ]}]}}]}"#;
+    assert_eq!(Generator::from_json(short, &tables),
+               Err(GeneratorError::InvalidStep("steps[0].Repeat.steps[0]".to_string(),
+                                               "weights add up to 90, not 100".to_string())));
+
+    let missing = r#"{"name": "hoard", "steps": [{"Literal": "Coins"}, {"Table": "weapons"}]}"#;
+    assert_eq!(Generator::from_json(missing, &tables),
+               Err(GeneratorError::InvalidStep("steps[1]".to_string(), "there's no table weapons".to_string())));
+
+    match Generator::from_json(r#"{"name": "hoard", "steps": [{"Dice": 3}]}"#, &tables) {
+        Err(GeneratorError::Json(_)) => {},
+        other => panic!("expected a JSON error, got {:?}", other),
+    }
+}
+
+#[test]
+fn it_limits_dice_over_the_whole_generation() {
+    let generator = Generator {
+        name: "horde".to_string(),
+        steps: vec![Step::Repeat {
+            count: "10".to_string(),
+            steps: vec![Step::Dice {
+                label: "hp".to_string(),
+                multiplier: 1,
+                notation: "4d6".to_string(),
+            }],
+        }],
+    };
+    let limits = Limits {
+        max_dice: 30,
+        ..Limits::default()
+    };
+
+    // Every 4d6 is within the limits, but with the 10 runs the sixth takes the generation past them
+    let result = generator.generate(&Tables::new(), &limits, &mut Roller::seeded(1));
+    assert_eq!(result.err(), Some(GeneratorError::TooManyDice(34, 30)));
+    assert!(generator.generate(&Tables::new(), &Limits::default(), &mut Roller::seeded(1)).is_ok());
+}
+
+#[test]
+fn it_limits_repeats_over_the_whole_generation() {
+    let repeat = |count: &str, step: Step| Step::Repeat {
+        count: count.to_string(),
+        steps: vec![step],
+    };
+    let literal = Step::Literal("A copper piece".to_string());
+
+    // Runs of a literal roll nothing, but still count towards the limits
+    let many = Generator {
+        name: "pile".to_string(),
+        steps: vec![repeat("65535", literal.clone())],
+    };
+    assert_eq!(many.generate(&Tables::new(), &Limits::default(), &mut Roller::new()).err(),
+               Some(GeneratorError::TooManyDice(65535, 100)));
+
+    // Repeats inside repeats are counted together, 50 outer runs and then 50 for each
+    let nested = Generator {
+        name: "piles".to_string(),
+        steps: vec![repeat("50", repeat("50", literal))],
+    };
+    assert_eq!(nested.generate(&Tables::new(), &Limits::default(), &mut Roller::new()).err(),
+               Some(GeneratorError::TooManyDice(150, 100)));
+}
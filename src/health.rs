@@ -0,0 +1,230 @@
+#[cfg(feature = "server")]
+use config::Config;
+use history::RollStore;
+#[cfg(feature = "server")]
+use rocket::State;
+#[cfg(feature = "server")]
+use rocket::http::Status;
+#[cfg(feature = "server")]
+use rocket::response::status::Custom;
+#[cfg(feature = "server")]
+use rocket_contrib::Json;
+#[cfg(feature = "server")]
+use roller::Roller;
+#[cfg(all(test, feature = "server"))]
+use history::{HiddenRollReceipt, HistoryError, HistoryPage, HistoryQuery};
+#[cfg(all(test, feature = "server"))]
+use rocket::http::ContentType;
+#[cfg(all(test, feature = "server"))]
+use rocket::local::Client;
+#[cfg(all(test, feature = "server"))]
+use roll::Roll;
+
+/// The roller hands out values in range for every type of die
+pub const RNG: &str = "rng";
+
+/// Timestamps don't go backwards between rolls
+pub const CLOCK: &str = "clock";
+
+/// Room history can be reached
+pub const STORAGE: &str = "storage";
+
+/// The server's limits on rolls were loaded from its config
+pub const LIMITS: &str = "limits";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum HealthStatus {
+    #[serde(rename = "ok")]
+    Ok,
+
+    /// Working badly or not at all, see the component's detail
+    #[serde(rename = "degraded")]
+    Degraded,
+}
+
+/// How one part of the roller or server is doing
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ComponentHealth {
+    /// What was checked, e.g. `rng` or `storage`
+    pub component: String,
+
+    /// What went wrong, none when the component is ok
+    pub detail: Option<String>,
+
+    pub status: HealthStatus,
+}
+
+/// Every component checked, degraded overall when any one of them is
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub components: Vec<ComponentHealth>,
+    pub status: HealthStatus,
+}
+
+impl ComponentHealth {
+    pub fn ok(component: &str) -> ComponentHealth {
+        ComponentHealth {
+            component: component.to_string(),
+            detail: None,
+            status: HealthStatus::Ok,
+        }
+    }
+
+    pub fn degraded(component: &str, detail: &str) -> ComponentHealth {
+        ComponentHealth {
+            component: component.to_string(),
+            detail: Some(detail.to_string()),
+            status: HealthStatus::Degraded,
+        }
+    }
+}
+
+impl HealthReport {
+    pub fn new(components: Vec<ComponentHealth>) -> HealthReport {
+        let status = if components.iter().all(|component| component.status == HealthStatus::Ok) {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Degraded
+        };
+        HealthReport {
+            components,
+            status,
+        }
+    }
+
+    /// Add the result of another check
+    pub fn with(self, component: ComponentHealth) -> HealthReport {
+        let mut components = self.components;
+        components.push(component);
+        HealthReport::new(components)
+    }
+
+    /// The check of a component, if it was checked
+    pub fn get(&self, component: &str) -> Option<&ComponentHealth> {
+        self.components.iter().find(|health| health.component == component)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == HealthStatus::Ok
+    }
+}
+
+/// Check room history can be reached
+pub fn check_storage(store: &dyn RollStore) -> ComponentHealth {
+    match store.ping() {
+        Ok(()) => ComponentHealth::ok(STORAGE),
+        Err(error) => ComponentHealth::degraded(STORAGE, &format!("{:?}", error)),
+    }
+}
+
+/// Liveness, answering as long as the server is up without checking anything else
+#[cfg(feature = "server")]
+#[get("/healthz")]
+pub fn healthz() -> Json<HealthReport> {
+    Json(HealthReport::new(vec![]))
+}
+
+/// Readiness, running the roller's self-test and checking storage and limits
+///
+/// Answers 503 Service Unavailable with the report when any component is degraded.
+#[cfg(feature = "server")]
+#[get("/readyz")]
+pub fn readyz(config: Option<State<Config>>, log: Option<State<Box<dyn RollStore>>>) -> Result<Json<HealthReport>, Custom<Json<HealthReport>>> {
+    let storage = match log {
+        Some(log) => check_storage(&**log),
+        None => ComponentHealth::degraded(STORAGE, "no store was set up"),
+    };
+    let limits = match config {
+        Some(ref config) if config.max_dice_rolled > 0 => ComponentHealth::ok(LIMITS),
+        Some(_) => ComponentHealth::degraded(LIMITS, "max_dice_rolled is 0, every roll would be refused"),
+        None => ComponentHealth::degraded(LIMITS, "the config wasn't loaded"),
+    };
+
+    let report = Roller::new().self_test().with(storage).with(limits);
+    if report.is_ok() {
+        Ok(Json(report))
+    } else {
+        Err(Custom(Status::ServiceUnavailable, Json(report)))
+    }
+}
+
+/// A store that's always down
+#[cfg(all(test, feature = "server"))]
+struct DownStore;
+
+#[cfg(all(test, feature = "server"))]
+impl RollStore for DownStore {
+    fn record(&self, _room: &str, _participant: &str, _roll: Roll) -> Result<u64, HistoryError> {
+        Err(HistoryError::Unavailable)
+    }
+
+    fn record_hidden(&self, _room: &str, _participant: &str, _roll: Roll) -> Result<HiddenRollReceipt, HistoryError> {
+        Err(HistoryError::Unavailable)
+    }
+
+    fn reveal(&self, _room: &str, _id: &str) -> Result<Roll, HistoryError> {
+        Err(HistoryError::Unavailable)
+    }
+
+    fn page(&self, _room: &str, _query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+        Err(HistoryError::Unavailable)
+    }
+
+    fn by_sequence(&self, _room: &str, _sequence: u64) -> Result<Roll, HistoryError> {
+        Err(HistoryError::Unavailable)
+    }
+
+    fn ping(&self) -> Result<(), HistoryError> {
+        Err(HistoryError::Unavailable)
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+fn report(client: &Client, path: &str) -> (Status, HealthReport) {
+    let mut response = client.get(path).header(ContentType::JSON).dispatch();
+    let report = ::serde_json::from_str(&response.body_string().unwrap()).unwrap();
+    (response.status(), report)
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn it_is_ready_with_the_default_server() {
+    let client = Client::new(::rocket()).expect("the API should launch");
+    let (status, ready) = report(&client, "/readyz");
+    assert_eq!(status, Status::Ok);
+    assert!(ready.is_ok());
+    let components: Vec<&str> = ready.components.iter().map(|health| health.component.as_str()).collect();
+    assert_eq!(components, vec![RNG, CLOCK, STORAGE, LIMITS]);
+
+    assert_eq!(report(&client, "/healthz"), (Status::Ok, HealthReport::new(vec![])));
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn it_reports_a_failing_store_as_degraded() {
+    let config = Config {
+        access_control_allow_origin: "http://localhost:3000".to_string(),
+        max_dice_rolled: 10000,
+        max_preset_slots: 10,
+        roll_deadline: None,
+        slow_roll_warning: None,
+        strict_notation: false,
+    };
+    let rocket = ::rocket::ignite()
+        .mount("/", routes![healthz, readyz])
+        .manage(config)
+        .manage(Box::new(DownStore) as Box<dyn RollStore>);
+    let client = Client::new(rocket).expect("the API should launch");
+
+    let (status, ready) = report(&client, "/readyz");
+    assert_eq!(status, Status::ServiceUnavailable);
+    assert_eq!(ready.status, HealthStatus::Degraded);
+    assert_eq!(ready.get(STORAGE), Some(&ComponentHealth::degraded(STORAGE, "Unavailable")));
+    assert_eq!(ready.get(RNG).map(|health| health.status), Some(HealthStatus::Ok));
+    assert_eq!(ready.get(LIMITS).map(|health| health.status), Some(HealthStatus::Ok));
+
+    // Staying alive doesn't depend on the store
+    let (status, alive) = report(&client, "/healthz");
+    assert_eq!(status, Status::Ok);
+    assert!(alive.is_ok());
+}
@@ -0,0 +1,127 @@
+use die::{Die, DieType};
+use roll::{Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// How one die of a Hero System damage roll counted
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HeroDie {
+    /// BODY the die does
+    pub body: u8,
+
+    /// The face of the half die halved and rounded up, none for whole dice
+    pub halved: Option<i16>,
+
+    /// STUN the die does, its face or the half die's halved face
+    pub stun: i16,
+
+    /// The face the die landed on
+    pub value: i16,
+}
+
+/// A Hero System normal damage roll, e.g. 3½d6, counting STUN and BODY
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HeroDamageResult {
+    /// BODY done in all
+    pub body: i32,
+
+    /// Every die, the whole dice first and then any half die
+    pub dice: Vec<HeroDie>,
+
+    /// The half die, none when the dice were a whole number
+    pub half: Option<Roll>,
+
+    /// STUN done in all, the sum of the dice
+    pub stun: i32,
+
+    /// The whole dice
+    pub whole: Roll,
+}
+
+/// BODY a die does: none on a 1, two on a 6 and one otherwise, or for a half die none on 1 to 3
+/// and one on 4 to 6
+pub fn body(die: &Die) -> u8 {
+    match (die.halved, die.value) {
+        (Some(_), value) if value >= 4 => 1,
+        (Some(_), _) => 0,
+        (None, value) if value <= 1 => 0,
+        (None, value) if value >= 6 => 2,
+        (None, _) => 1,
+    }
+}
+
+/// Roll Hero System damage of a number of d6s, e.g. `3.5` for 3½d6
+pub fn hero_damage(dice: f32) -> HeroDamageResult {
+    hero_damage_with(dice, &mut Roller::new())
+}
+
+/// Roll Hero System damage using the given source of randomness
+///
+/// Any fraction of a die from a half up is a half die, rolled last. Less than a half is left out,
+/// as are negative numbers of dice.
+pub fn hero_damage_with(dice: f32, roller: &mut Roller) -> HeroDamageResult {
+    let dice = if dice.is_finite() { dice.max(0.0).min(i16::max_value() as f32) } else { 0.0 };
+    let n = dice.trunc() as i16;
+
+    let mut flags = RollFlags::builder().n(n).die(DieType::D6).build()
+        .expect("whole d6s are always a valid roll");
+    flags.equation = flags.canonical();
+    let whole = Roll::with_roller(flags, roller);
+
+    let half = if dice.fract() >= 0.5 {
+        let mut flags = RollFlags::builder().n(1).die(DieType::D6).halved().build()
+            .expect("a half d6 is always a valid roll");
+        flags.equation = flags.canonical();
+        Some(Roll::with_roller(flags, roller))
+    } else {
+        None
+    };
+
+    let dice: Vec<HeroDie> = whole.dice.iter().chain(half.iter().flat_map(|half| half.dice.iter()))
+        .map(|die| HeroDie {
+            body: body(die),
+            halved: die.halved,
+            stun: die.counted(),
+            value: die.value,
+        })
+        .collect();
+
+    HeroDamageResult {
+        body: dice.iter().map(|die| die.body as i32).sum(),
+        stun: dice.iter().map(|die| die.stun as i32).sum(),
+        dice,
+        half,
+        whole,
+    }
+}
+
+#[test]
+fn it_counts_body_on_every_face() {
+    let result = hero_damage_with(6.0, &mut ScriptedRoller::new(vec![1, 2, 3, 4, 5, 6]));
+    let body: Vec<u8> = result.dice.iter().map(|die| die.body).collect();
+    assert_eq!(body, vec![0, 1, 1, 1, 1, 2]);
+    assert_eq!((result.stun, result.body), (21, 6));
+    assert!(result.half.is_none());
+}
+
+#[test]
+fn it_rounds_the_half_die_up() {
+    let halves: Vec<(i16, u8)> = (1..7)
+        .map(|face| hero_damage_with(0.5, &mut ScriptedRoller::new(vec![face])).dice[0].clone())
+        .map(|die| (die.stun, die.body))
+        .collect();
+    assert_eq!(halves, vec![(1, 0), (1, 0), (2, 0), (2, 1), (3, 1), (3, 1)]);
+
+    let result = hero_damage_with(3.5, &mut ScriptedRoller::new(vec![6, 1, 4, 5]));
+    assert_eq!(result.dice.len(), 4);
+    assert_eq!(result.whole.value, 11);
+    assert_eq!((result.stun, result.body), (14, 4));
+
+    // The half die keeps the face it landed on as well as what it counts for
+    let json = ::serde_json::to_value(&result).unwrap();
+    assert_eq!(json["dice"][3]["value"], 5);
+    assert_eq!(json["dice"][3]["halved"], 3);
+    assert_eq!(json["half"]["dice"][0]["value"], 5);
+    assert_eq!(json["half"]["dice"][0]["halved"], 3);
+}
@@ -0,0 +1,681 @@
+use chrono::DateTime;
+use chrono::prelude::Utc;
+use fairness::commit_roll;
+use roll::Roll;
+use roller::CancellationToken;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Rolls returned when a page doesn't ask for a size
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Most rolls returned in a single page
+pub const MAX_PAGE_SIZE: usize = 200;
+
+/// A roll made in a room, in the order the log received it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LoggedRoll {
+    /// Who made the roll
+    pub participant: String,
+
+    pub roll: Roll,
+
+    #[serde(skip)]
+    is_hidden: bool,
+
+    #[serde(skip)]
+    sequence: u64,
+}
+
+/// What's handed out for a hidden roll until it's revealed
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HiddenRollReceipt {
+    /// Check the revealed roll against this with `fairness::verify_commitment`
+    pub commitment: String,
+
+    /// ID of the roll to reveal
+    pub id: String,
+}
+
+/// Filters and position of a page of roll history
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "server", derive(FromForm))]
+pub struct HistoryQuery {
+    /// Only include rolls made before this roll ID or RFC 3339 timestamp
+    pub before: Option<String>,
+
+    /// Continue from the `next_cursor` of a previous page
+    pub cursor: Option<String>,
+
+    /// Number of rolls to return
+    pub limit: Option<usize>,
+
+    /// Only include rolls whose equation contains this text
+    pub notation_contains: Option<String>,
+
+    /// Only include rolls made by this participant
+    pub participant: Option<String>,
+}
+
+/// Rolls from newest to oldest
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HistoryPage {
+    /// Pass back as the cursor to get the next page, none when there are no older rolls
+    pub next_cursor: Option<String>,
+
+    pub rolls: Vec<LoggedRoll>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HistoryError {
+    /// The before filter is neither a roll in the room nor a timestamp
+    InvalidBefore,
+
+    /// The cursor wasn't handed out by the log
+    InvalidCursor,
+
+    /// The room has no rolls
+    UnknownRoom,
+
+    /// The roll isn't in the room
+    UnknownRoll,
+
+    /// The participant hasn't rolled in the room
+    UnknownParticipant,
+
+    /// The store holding the history can't be reached
+    Unavailable,
+}
+
+impl LoggedRoll {
+    pub fn new(participant: &str, roll: Roll, is_hidden: bool, sequence: u64) -> LoggedRoll {
+        LoggedRoll {
+            participant: participant.to_string(),
+            roll,
+            is_hidden,
+            sequence,
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.is_hidden
+    }
+
+    /// Number of the roll in its room's history, in the order the store received it
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+/// Somewhere to keep each room's rolls, e.g. in memory or shared between servers
+pub trait RollStore: Send + Sync {
+    /// Add a finished roll to a room's history, giving it the room's next sequence number
+    fn record(&self, room: &str, participant: &str, roll: Roll) -> Result<u64, HistoryError>;
+
+    /// Keep a finished roll out of the room's history until it's revealed
+    fn record_hidden(&self, room: &str, participant: &str, roll: Roll) -> Result<HiddenRollReceipt, HistoryError>;
+
+    /// Show a hidden roll in the room's history, see `RollLog::reveal`
+    fn reveal(&self, room: &str, id: &str) -> Result<Roll, HistoryError>;
+
+    /// Get a page of a room's history, newest first, see `RollLog::page`
+    fn page(&self, room: &str, query: &HistoryQuery) -> Result<HistoryPage, HistoryError>;
+
+    /// Get a roll by its number in a room's history, see `RollLog::by_sequence`
+    fn by_sequence(&self, room: &str, sequence: u64) -> Result<Roll, HistoryError>;
+
+    /// Check the store can be reached, e.g. before a server says it's ready for rolls
+    fn ping(&self) -> Result<(), HistoryError> {
+        Ok(())
+    }
+}
+
+impl<'s> dyn RollStore + 's {
+    /// Every roll in a room matching the query's filters, newest first, read a page at a time
+    ///
+    /// Only the page being handed out is held, and the next page isn't read until the last roll
+    /// of this one has been taken. The query's cursor says where to start and its limit caps the
+    /// rolls handed out, none for every roll. A failure is handed out and ends the stream.
+    pub fn stream(&self, room: &str, query: &HistoryQuery) -> RollStream {
+        RollStream {
+            buffer: VecDeque::new(),
+            cancellation: None,
+            is_done: false,
+            query: HistoryQuery {
+                limit: Some(MAX_PAGE_SIZE),
+                ..query.clone()
+            },
+            remaining: query.limit,
+            room: room.to_string(),
+            store: self,
+        }
+    }
+}
+
+/// Rolls of a room read from a store as they're asked for, see `RollStore::stream`
+pub struct RollStream<'a> {
+    buffer: VecDeque<Roll>,
+    cancellation: Option<CancellationToken>,
+    is_done: bool,
+    query: HistoryQuery,
+    remaining: Option<usize>,
+    room: String,
+    store: &'a dyn RollStore,
+}
+
+impl<'a> RollStream<'a> {
+    /// Stop reading once the token is cancelled, e.g. when the client the rolls are for has left
+    pub fn cancel_with(mut self, token: CancellationToken) -> RollStream<'a> {
+        self.cancellation = Some(token);
+        self
+    }
+}
+
+impl<'a> Iterator for RollStream<'a> {
+    type Item = Result<Roll, HistoryError>;
+
+    fn next(&mut self) -> Option<Result<Roll, HistoryError>> {
+        loop {
+            if self.remaining == Some(0) || self.cancellation.as_ref().map_or(false, |token| token.is_cancelled()) {
+                return None;
+            }
+            if let Some(roll) = self.buffer.pop_front() {
+                self.remaining = self.remaining.map(|remaining| remaining - 1);
+                return Some(Ok(roll));
+            }
+            if self.is_done {
+                return None;
+            }
+
+            match self.store.page(&self.room, &self.query) {
+                Ok(page) => {
+                    self.buffer.extend(page.rolls.into_iter().map(|logged| logged.roll));
+                    match page.next_cursor {
+                        Some(cursor) => self.query.cursor = Some(cursor),
+                        None => self.is_done = true,
+                    }
+                },
+                Err(error) => {
+                    self.is_done = true;
+                    return Some(Err(error));
+                },
+            }
+        }
+    }
+}
+
+/// Every roll made in each room, kept in memory
+///
+/// Each room numbers its rolls from 1 as they're recorded, hidden ones included.
+pub struct RollLog {
+    rooms: Mutex<HashMap<String, Vec<LoggedRoll>>>,
+}
+
+impl RollLog {
+    pub fn new() -> RollLog {
+        RollLog {
+            rooms: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add a finished roll to a room's history, returning its number in the room
+    pub fn record(&self, room: &str, participant: &str, roll: Roll) -> u64 {
+        self.push(room, participant, roll, false)
+    }
+
+    /// Keep a finished roll out of the room's history until it's revealed
+    pub fn record_hidden(&self, room: &str, participant: &str, roll: Roll) -> HiddenRollReceipt {
+        let receipt = HiddenRollReceipt {
+            commitment: commit_roll(&roll),
+            id: roll.id.clone(),
+        };
+        self.push(room, participant, roll, true);
+        receipt
+    }
+
+    /// Show a hidden roll in the room's history, where it keeps the place it was recorded at
+    ///
+    /// Revealing a roll again, or one that was never hidden, just returns it.
+    pub fn reveal(&self, room: &str, id: &str) -> Result<Roll, HistoryError> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let rolls = match rooms.get_mut(room) {
+            Some(rolls) => rolls,
+            None => return Err(HistoryError::UnknownRoom),
+        };
+        match rolls.iter_mut().find(|logged| logged.roll.id == id) {
+            Some(logged) => {
+                logged.is_hidden = false;
+                Ok(logged.roll.clone())
+            },
+            None => Err(HistoryError::UnknownRoll),
+        }
+    }
+
+    /// Get a roll by its number in the room's history, e.g. 47 for "roll #47"
+    ///
+    /// Hidden rolls can't be looked up until they're revealed.
+    pub fn by_sequence(&self, room: &str, sequence: u64) -> Result<Roll, HistoryError> {
+        let rooms = self.rooms.lock().unwrap();
+        match rooms.get(room) {
+            Some(rolls) => find_sequence(rolls, sequence),
+            None => Err(HistoryError::UnknownRoom),
+        }
+    }
+
+    fn push(&self, room: &str, participant: &str, mut roll: Roll, is_hidden: bool) -> u64 {
+        // The room is locked from taking its number until the roll is in, so no two rolls share one
+        let mut rooms = self.rooms.lock().unwrap();
+        let rolls = rooms.entry(room.to_string()).or_insert_with(Vec::new);
+        let sequence = rolls.last().map_or(1, |logged| logged.sequence + 1);
+        roll.sequence = sequence;
+        rolls.push(LoggedRoll::new(participant, roll, is_hidden, sequence));
+        sequence
+    }
+
+    /// Get a page of a room's history, newest first
+    ///
+    /// Cursors point at the last roll of a page rather than an offset, so rolls recorded between
+    /// requests never shift the pages that follow.
+    pub fn page(&self, room: &str, query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+        let rooms = self.rooms.lock().unwrap();
+        match rooms.get(room) {
+            Some(rolls) => page_rolls(rolls, query),
+            None => Err(HistoryError::UnknownRoom),
+        }
+    }
+}
+
+impl RollStore for RollLog {
+    fn record(&self, room: &str, participant: &str, roll: Roll) -> Result<u64, HistoryError> {
+        Ok(RollLog::record(self, room, participant, roll))
+    }
+
+    fn record_hidden(&self, room: &str, participant: &str, roll: Roll) -> Result<HiddenRollReceipt, HistoryError> {
+        Ok(RollLog::record_hidden(self, room, participant, roll))
+    }
+
+    fn reveal(&self, room: &str, id: &str) -> Result<Roll, HistoryError> {
+        RollLog::reveal(self, room, id)
+    }
+
+    fn page(&self, room: &str, query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+        RollLog::page(self, room, query)
+    }
+
+    fn by_sequence(&self, room: &str, sequence: u64) -> Result<Roll, HistoryError> {
+        RollLog::by_sequence(self, room, sequence)
+    }
+
+    /// The log can't be used once a thread panicked holding it
+    fn ping(&self) -> Result<(), HistoryError> {
+        self.rooms.lock().map(|_| ()).map_err(|_| HistoryError::Unavailable)
+    }
+}
+
+/// Find a roll in a room's rolls by its number, the same for every store
+pub fn find_sequence(rolls: &[LoggedRoll], sequence: u64) -> Result<Roll, HistoryError> {
+    match rolls.iter().find(|logged| logged.sequence == sequence && !logged.is_hidden) {
+        Some(logged) => Ok(logged.roll.clone()),
+        None => Err(HistoryError::UnknownRoll),
+    }
+}
+
+/// Get a page of a room's rolls, in any order
+///
+/// Stores that don't keep rolls in memory load a room's rolls and page them with this, so every
+/// store filters, orders and hands out cursors the same way. Rolls are paged newest first by
+/// `Roll::cmp_by_sequence`, so rolls made in the same instant always come in the same order.
+pub fn page_rolls(rolls: &[LoggedRoll], query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+    if let Some(ref participant) = query.participant {
+        if !rolls.iter().any(|logged| !logged.is_hidden && &logged.participant == participant) {
+            return Err(HistoryError::UnknownParticipant);
+        }
+    }
+
+    let mut below = match query.cursor {
+        Some(ref cursor) => Some(decode_cursor(cursor)?),
+        None => None,
+    };
+    let mut before_time = None;
+    if let Some(ref before) = query.before {
+        match rolls.iter().find(|logged| !logged.is_hidden && &logged.roll.id == before) {
+            Some(logged) => {
+                below = Some(below.map_or(logged.sequence, |b| b.min(logged.sequence)));
+            },
+            None => match DateTime::parse_from_rfc3339(before) {
+                Ok(time) => before_time = Some(time.with_timezone(&Utc)),
+                Err(_) => return Err(HistoryError::InvalidBefore),
+            },
+        }
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1).min(MAX_PAGE_SIZE);
+    let mut ordered: Vec<&LoggedRoll> = rolls.iter().collect();
+    ordered.sort_by(|a, b| a.sequence.cmp(&b.sequence).then_with(|| a.roll.cmp_by_sequence(&b.roll)));
+
+    // The cursor is found by halving the history, rather than walking every roll after it
+    let end = match below {
+        Some(below) => match ordered.binary_search_by(|logged| logged.sequence.cmp(&below)) {
+            Ok(index) | Err(index) => index,
+        },
+        None => ordered.len(),
+    };
+    let mut page: Vec<LoggedRoll> = ordered[..end].iter().rev()
+        .filter(|logged| !logged.is_hidden)
+        .filter(|logged| before_time.map_or(true, |time| logged.roll.timestamp < time))
+        .filter(|logged| query.participant.as_ref().map_or(true, |p| &logged.participant == p))
+        .filter(|logged| query.notation_contains.as_ref().map_or(true, |text| logged.roll.equation.contains(text.as_str())))
+        .take(limit + 1)
+        .map(|logged| (*logged).clone())
+        .collect();
+
+    // One more than the limit was taken to see if there's another page
+    let next_cursor = if page.len() > limit {
+        page.truncate(limit);
+        page.last().map(|logged| encode_cursor(logged.sequence))
+    } else {
+        None
+    };
+
+    Ok(HistoryPage {
+        next_cursor,
+        rolls: page,
+    })
+}
+
+fn encode_cursor(sequence: u64) -> String {
+    format!("r{:x}", sequence)
+}
+
+fn decode_cursor(cursor: &str) -> Result<u64, HistoryError> {
+    if !cursor.starts_with('r') {
+        return Err(HistoryError::InvalidCursor);
+    }
+    u64::from_str_radix(&cursor[1..], 16).map_err(|_| HistoryError::InvalidCursor)
+}
+
+#[cfg(test)]
+fn log_with_rolls(n: usize) -> RollLog {
+    use roll::RollFlags;
+
+    let log = RollLog::new();
+    for i in 0..n {
+        let mut flags = RollFlags::new();
+        flags.n = 1;
+        flags.max = if i % 3 == 0 { 20 } else { 6 };
+        flags.equation = format!("1d{}", flags.max);
+        let participant = if i % 2 == 0 { "alice" } else { "bob" };
+        log.record("table", participant, Roll::new(flags));
+    }
+    log
+}
+
+#[cfg(test)]
+fn ids(page: &HistoryPage) -> Vec<String> {
+    page.rolls.iter().map(|logged| logged.roll.id.clone()).collect()
+}
+
+#[test]
+fn it_can_page_through_history() {
+    let log = log_with_rolls(150);
+    let mut seen = vec![];
+    let mut query = HistoryQuery {
+        limit: Some(50),
+        ..HistoryQuery::default()
+    };
+    loop {
+        let page = log.page("table", &query).unwrap();
+        seen.extend(ids(&page));
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+
+    // Every roll exactly once, newest first
+    let all = log.page("table", &HistoryQuery { limit: Some(150), ..HistoryQuery::default() }).unwrap();
+    assert_eq!(seen.len(), 150);
+    assert_eq!(seen, ids(&all));
+}
+
+#[test]
+fn it_keeps_pages_stable_while_rolls_arrive() {
+    let log = log_with_rolls(150);
+    let query = HistoryQuery {
+        limit: Some(50),
+        ..HistoryQuery::default()
+    };
+    let first = log.page("table", &query).unwrap();
+
+    // New rolls land at the front and don't shift the next page
+    for _ in 0..10 {
+        log.record("table", "alice", Roll::new(::roll::RollFlags::new()));
+    }
+    let second = log.page("table", &HistoryQuery {
+        cursor: first.next_cursor.clone(),
+        limit: Some(50),
+        ..HistoryQuery::default()
+    }).unwrap();
+    let expected = log.page("table", &HistoryQuery { limit: Some(160), ..HistoryQuery::default() }).unwrap();
+    assert_eq!(ids(&first), ids(&expected)[10..60].to_vec());
+    assert_eq!(ids(&second), ids(&expected)[60..110].to_vec());
+}
+
+#[test]
+fn it_can_filter_history() {
+    let log = log_with_rolls(150);
+    let by = |query: HistoryQuery| log.page("table", &HistoryQuery { limit: Some(200), ..query }).unwrap();
+
+    let alice = by(HistoryQuery { participant: Some("alice".to_string()), ..HistoryQuery::default() });
+    assert_eq!(alice.rolls.len(), 75);
+    assert!(alice.rolls.iter().all(|logged| logged.participant == "alice"));
+
+    let d20 = by(HistoryQuery { notation_contains: Some("d20".to_string()), ..HistoryQuery::default() });
+    assert_eq!(d20.rolls.len(), 50);
+
+    // Rolls 0, 6, 12, ... were made by alice with a d20
+    let both = by(HistoryQuery {
+        notation_contains: Some("d20".to_string()),
+        participant: Some("alice".to_string()),
+        ..HistoryQuery::default()
+    });
+    assert_eq!(both.rolls.len(), 25);
+
+    let all = by(HistoryQuery::default());
+    let before = by(HistoryQuery { before: Some(all.rolls[100].roll.id.clone()), ..HistoryQuery::default() });
+    assert_eq!(ids(&before), ids(&all)[101..].to_vec());
+
+    let time = all.rolls[100].roll.timestamp;
+    let earlier = by(HistoryQuery { before: Some(time.to_rfc3339()), ..HistoryQuery::default() });
+    assert!(earlier.rolls.iter().all(|logged| logged.roll.timestamp < time));
+}
+
+#[test]
+fn it_streams_history_a_page_at_a_time() {
+    let log = log_with_rolls(450);
+    let store: &dyn RollStore = &log;
+    let rolls: Vec<Roll> = store.stream("table", &HistoryQuery::default()).map(Result::unwrap).collect();
+    let sequences: Vec<u64> = rolls.iter().map(|roll| roll.sequence).collect();
+    assert_eq!(sequences, (1..451).rev().collect::<Vec<u64>>());
+
+    // The same filters as a page, with the limit capping the whole stream
+    let query = HistoryQuery {
+        limit: Some(100),
+        participant: Some("bob".to_string()),
+        notation_contains: Some("d20".to_string()),
+        ..HistoryQuery::default()
+    };
+    let rolls: Vec<Roll> = store.stream("table", &query).map(Result::unwrap).collect();
+    assert_eq!(rolls.len(), 75);
+    assert!(rolls.iter().all(|roll| roll.equation == "1d20" && roll.sequence % 2 == 0));
+    let query = HistoryQuery {
+        limit: Some(10),
+        ..HistoryQuery::default()
+    };
+    assert_eq!(store.stream("table", &query).count(), 10);
+
+    // Nothing more is read once the stream is cancelled
+    let token = CancellationToken::new();
+    let mut stream = store.stream("table", &HistoryQuery::default()).cancel_with(token.clone());
+    assert!(stream.next().is_some());
+    token.cancel();
+    assert!(stream.next().is_none());
+
+    let mut unknown = store.stream("cellar", &HistoryQuery::default());
+    assert_eq!(unknown.next().map(|roll| roll.err()), Some(Some(HistoryError::UnknownRoom)));
+    assert!(unknown.next().is_none());
+}
+
+#[test]
+fn it_rejects_bad_history_queries() {
+    let log = log_with_rolls(5);
+    let query = |query: HistoryQuery| log.page("table", &query).err();
+
+    assert_eq!(query(HistoryQuery { cursor: Some("nope".to_string()), ..HistoryQuery::default() }),
+               Some(HistoryError::InvalidCursor));
+    assert_eq!(query(HistoryQuery { before: Some("yesterday".to_string()), ..HistoryQuery::default() }),
+               Some(HistoryError::InvalidBefore));
+    assert_eq!(query(HistoryQuery { participant: Some("carol".to_string()), ..HistoryQuery::default() }),
+               Some(HistoryError::UnknownParticipant));
+    assert_eq!(log.page("attic", &HistoryQuery::default()).err(), Some(HistoryError::UnknownRoom));
+}
+
+#[test]
+fn it_keeps_hidden_rolls_out_of_history_until_revealed() {
+    use fairness::verify_commitment;
+
+    let log = log_with_rolls(3);
+    let mut flags = ::roll::RollFlags::new();
+    flags.n = 1;
+    flags.max = 20;
+    flags.equation = "1d20".to_string();
+    let hidden = Roll::new(flags);
+    let receipt = log.record_hidden("table", "gm", hidden.clone());
+    assert_eq!(receipt.id, hidden.id);
+
+    let page = log.page("table", &HistoryQuery::default()).unwrap();
+    assert_eq!(page.rolls.len(), 3);
+    assert!(!ids(&page).contains(&receipt.id));
+    assert_eq!(log.page("table", &HistoryQuery { participant: Some("gm".to_string()), ..HistoryQuery::default() }).err(),
+               Some(HistoryError::UnknownParticipant));
+
+    let revealed = log.reveal("table", &receipt.id).unwrap();
+    assert!(verify_commitment(&receipt.commitment, &revealed));
+    let page = log.page("table", &HistoryQuery::default()).unwrap();
+    assert_eq!(ids(&page)[0], receipt.id);
+
+    // Revealing again changes nothing
+    assert_eq!(log.reveal("table", &receipt.id).unwrap().id, revealed.id);
+    assert_eq!(log.page("table", &HistoryQuery::default()).unwrap().rolls.len(), 4);
+
+    assert_eq!(log.reveal("table", "nope").err(), Some(HistoryError::UnknownRoll));
+    assert_eq!(log.reveal("attic", &receipt.id).err(), Some(HistoryError::UnknownRoom));
+}
+
+#[test]
+fn it_numbers_rolls_by_room_without_repeating() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let log = Arc::new(RollLog::new());
+    let threads: Vec<_> = (0..8).map(|_| {
+        let log = log.clone();
+        thread::spawn(move || {
+            (0..50).map(|_| log.record("table", "alice", Roll::new(::roll::RollFlags::new()))).collect::<Vec<u64>>()
+        })
+    }).collect();
+    let mut sequences: Vec<u64> = threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect();
+    sequences.sort();
+    assert_eq!(sequences, (1..401).collect::<Vec<u64>>());
+
+    // Each room counts on its own, and the roll carries its number
+    assert_eq!(log.record("attic", "bob", Roll::new(::roll::RollFlags::new())), 1);
+    assert_eq!(log.by_sequence("table", 47).unwrap().sequence, 47);
+    assert_eq!(log.by_sequence("table", 401).err(), Some(HistoryError::UnknownRoll));
+    assert_eq!(log.by_sequence("cellar", 1).err(), Some(HistoryError::UnknownRoom));
+
+    // Hidden rolls take a number, but can't be looked up by it until they're revealed
+    let receipt = log.record_hidden("attic", "gm", Roll::new(::roll::RollFlags::new()));
+    assert_eq!(log.by_sequence("attic", 2).err(), Some(HistoryError::UnknownRoll));
+    log.reveal("attic", &receipt.id).unwrap();
+    assert_eq!(log.by_sequence("attic", 2).unwrap().id, receipt.id);
+}
+
+#[test]
+fn it_pages_rapid_rolls_in_the_order_they_were_made() {
+    use roll::RollFlags;
+
+    let log = RollLog::new();
+    let mut made = vec![];
+    for _ in 0..1000 {
+        let mut flags = RollFlags::new();
+        flags.n = 1;
+        flags.max = 6;
+        let roll = Roll::new(flags);
+        made.push(roll.id.clone());
+        log.record("table", "alice", roll);
+    }
+
+    let mut paged = vec![];
+    let mut query = HistoryQuery {
+        limit: Some(37),
+        ..HistoryQuery::default()
+    };
+    loop {
+        let page = log.page("table", &query).unwrap();
+        paged.extend(ids(&page));
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    paged.reverse();
+    assert_eq!(paged, made);
+}
+
+#[test]
+fn it_pages_rolls_handed_over_out_of_order() {
+    use roll::RollFlags;
+
+    // Stores that load rolls from elsewhere can hand them over in any order
+    let mut rolls: Vec<LoggedRoll> = (1..11).map(|sequence| {
+        let mut roll = Roll::new(RollFlags::new());
+        roll.sequence = sequence;
+        LoggedRoll::new("alice", roll, sequence == 4, sequence)
+    }).collect();
+    rolls.swap(2, 8);
+    rolls.reverse();
+
+    let query = HistoryQuery {
+        cursor: Some(encode_cursor(6)),
+        ..HistoryQuery::default()
+    };
+    let page = page_rolls(&rolls, &query).unwrap();
+    let sequences: Vec<u64> = page.rolls.iter().map(|logged| logged.sequence).collect();
+    assert_eq!(sequences, vec![5, 3, 2, 1]);
+
+    // A cursor between rolls, e.g. one a store has since lost, pages from below it
+    let query = HistoryQuery {
+        cursor: Some(encode_cursor(40)),
+        ..HistoryQuery::default()
+    };
+    assert_eq!(page_rolls(&rolls, &query).unwrap().rolls.len(), 9);
+}
+
+#[test]
+fn it_keeps_taken_checks_in_history() {
+    use die::TakeKind;
+    use roller::Roller;
+    use serde_json;
+
+    let log = RollLog::new();
+    log.record("table", "alice", Roller::take(TakeKind::Twenty, 3, Some(20)));
+    let page = log.page("table", &HistoryQuery::default()).unwrap();
+    let logged = &page.rolls[0].roll;
+    assert_eq!((logged.value, logged.rng_draws), (23, 0));
+    assert_eq!(logged.dice[0].taken, Some(TakeKind::Twenty));
+
+    // Taking survives being sent to a client
+    let sent: LoggedRoll = serde_json::from_str(&serde_json::to_string(&page.rolls[0]).unwrap()).unwrap();
+    assert_eq!(sent.roll.dice[0].taken, Some(TakeKind::Twenty));
+}
@@ -0,0 +1,357 @@
+use die::DieType;
+use roll::{ExplodeSuccessPolicy, RollFlags};
+use roller::RollOptions;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+use ttml::arg::ComparisonArg;
+#[cfg(test)]
+use parser::parse;
+#[cfg(test)]
+use roll::{Roll, RollError};
+#[cfg(test)]
+use roller::Roller;
+#[cfg(test)]
+use serde_json;
+#[cfg(test)]
+use testing::{assert_roll_total, ScriptedRoller};
+
+/// A house rule that changed a roll, kept on the roll so players can see why it came out as it did
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum HouseRule {
+    /// d20s crit on this face or higher
+    CritRange(i16),
+
+    /// The dice explode on their highest face because their term has this tag
+    ExplodeTag(String),
+
+    /// The total was raised to at least this, half the most the kept dice could roll
+    FloorAtHalf(i16),
+
+    /// Ones were rerolled once because their term has this tag
+    RerollOnesTag(String),
+
+    /// Dice of this number or more are counted as successes
+    SuccessThreshold(i16),
+}
+
+impl HouseRule {
+    /// Change the flags the way the rule changed them when it was applied, e.g. to roll again
+    /// under the same rules
+    ///
+    /// `CritRange` leaves the flags as they are, as crits are read from the roll's rules.
+    pub fn apply_to(&self, flags: &mut RollFlags) {
+        match *self {
+            HouseRule::CritRange(_) => {},
+            HouseRule::ExplodeTag(_) => {
+                flags.explode = true;
+                flags.explode_op = None;
+                flags.explode_policy = ExplodeSuccessPolicy::CompareEachDie;
+            },
+            HouseRule::FloorAtHalf(half) => flags.floor = Some(half),
+            HouseRule::RerollOnesTag(_) => {
+                flags.ro = 1;
+                flags.ro_op = Some(ComparisonArg::EqualTo);
+            },
+            HouseRule::SuccessThreshold(threshold) => flags.gte = threshold,
+        }
+    }
+}
+
+/// The rules a table plays by, applied under every roll made in its room
+///
+/// Rules only fill in what a request leaves out. Notation that explodes, rerolls, keeps, counts or
+/// floors dice itself is rolled as written for that part, and options passed with a request win
+/// over the rules' options.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct HouseRules {
+    /// Lowest face a d20 crits on, e.g. 19 for 19–20
+    #[serde(default)]
+    pub crit_range: Option<i16>,
+
+    /// How long a roll can take in milliseconds, when the request doesn't say
+    #[serde(default)]
+    pub deadline_ms: Option<u64>,
+
+    /// Dice whose term has one of these tags explode on their highest face, e.g. `damage`
+    #[serde(default)]
+    pub explode_tags: Vec<String>,
+
+    /// Raise totals to at least half the most the kept dice could roll
+    #[serde(default)]
+    pub floor_at_half: bool,
+
+    /// Dice whose term has one of these tags reroll ones once, e.g. `hit`
+    #[serde(default)]
+    pub reroll_ones_tags: Vec<String>,
+
+    /// Count dice of a number or more as successes, keyed by the die, e.g. `{"d10": 7}`
+    #[serde(default)]
+    pub success_thresholds: BTreeMap<String, i16>,
+}
+
+impl HouseRules {
+    pub fn new() -> HouseRules {
+        HouseRules::default()
+    }
+
+    /// Fill in what the flags leave out from the rules, returning each rule that changed them
+    ///
+    /// A rule that would leave the flags invalid is skipped.
+    pub fn apply(&self, flags: &mut RollFlags) -> Vec<HouseRule> {
+        let mut applied = vec![];
+
+        if !flags.explode && flags.die != DieType::Fate {
+            if let Some(tag) = first_tag(&self.explode_tags, flags) {
+                try_rule(flags, &mut applied, HouseRule::ExplodeTag(tag));
+            }
+        }
+
+        if flags.rr_op.is_none() && flags.ro_op.is_none() {
+            if let Some(tag) = first_tag(&self.reroll_ones_tags, flags) {
+                try_rule(flags, &mut applied, HouseRule::RerollOnesTag(tag));
+            }
+        }
+
+        let is_counting = flags.gt != 0 || flags.gte != 0 || flags.lt != 0 || flags.lte != 0;
+        let is_keeping = flags.kh != 0 || flags.kl != 0 || flags.dh != 0 || flags.dl != 0;
+        if !is_counting && !is_keeping {
+            if let Some(&threshold) = self.success_thresholds.get(flags.die.notation()) {
+                try_rule(flags, &mut applied, HouseRule::SuccessThreshold(threshold));
+            }
+        }
+
+        if self.floor_at_half && flags.floor.is_none() && !is_counting {
+            let kept = if flags.kh != 0 {
+                flags.kh
+            } else if flags.kl != 0 {
+                flags.kl
+            } else {
+                flags.n - flags.dh - flags.dl
+            };
+            let half = (kept.max(0) as i32 * flags.max as i32 / 2) as i16;
+            if half > 0 {
+                try_rule(flags, &mut applied, HouseRule::FloorAtHalf(half));
+            }
+        }
+
+        if let Some(low) = self.crit_range {
+            if flags.die == DieType::D20 && low < 20 {
+                applied.push(HouseRule::CritRange(low));
+            }
+        }
+        applied
+    }
+
+    /// The options of a request, with the rules' deadline when the request doesn't have one
+    pub fn roll_options(&self, request: RollOptions) -> RollOptions {
+        let defaults = match self.deadline_ms {
+            Some(milliseconds) => RollOptions::new().deadline(Duration::from_millis(milliseconds)),
+            None => RollOptions::new(),
+        };
+        request.or(&defaults)
+    }
+}
+
+/// The first of the tags the flags' term has
+fn first_tag(tags: &[String], flags: &RollFlags) -> Option<String> {
+    tags.iter().find(|tag| flags.tags.contains(tag)).cloned()
+}
+
+/// Change the flags for a rule, keeping the change only when the flags are still valid
+fn try_rule(flags: &mut RollFlags, applied: &mut Vec<HouseRule>, rule: HouseRule) {
+    let mut changed = flags.clone();
+    rule.apply_to(&mut changed);
+    if changed.validate().is_ok() {
+        *flags = changed;
+        applied.push(rule);
+    }
+}
+
+/// The room's rules were set by another API token, which owns the room
+#[derive(Debug, PartialEq)]
+pub struct NotOwner;
+
+/// The house rules of each room, kept in memory
+///
+/// The first API token to set a room's rules owns the room, and only it can change them after.
+#[derive(Default)]
+pub struct RoomRules {
+    /// The hash of the token that owns each room
+    owners: Mutex<HashMap<String, String>>,
+
+    rooms: Mutex<HashMap<String, HouseRules>>,
+}
+
+impl RoomRules {
+    pub fn new() -> RoomRules {
+        RoomRules::default()
+    }
+
+    /// Play by the rules in the room from now on, in place of any it had
+    pub fn set(&self, room: &str, rules: HouseRules) {
+        self.rooms.lock().unwrap().insert(room.to_string(), rules);
+    }
+
+    /// Set the room's rules for the owner of the room, by the hash of their API token
+    ///
+    /// A room no one owns yet is owned by the token from now on.
+    pub fn set_as_owner(&self, room: &str, token_hash: &str, rules: HouseRules) -> Result<(), NotOwner> {
+        let mut owners = self.owners.lock().unwrap();
+        let owner = owners.entry(room.to_string()).or_insert_with(|| token_hash.to_string());
+        if owner != token_hash {
+            return Err(NotOwner);
+        }
+        self.set(room, rules);
+        Ok(())
+    }
+
+    /// The rules of the room, the defaults when none were set
+    pub fn get(&self, room: &str) -> HouseRules {
+        self.rooms.lock().unwrap().get(room).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+fn table_rules() -> HouseRules {
+    HouseRules {
+        crit_range: Some(19),
+        explode_tags: vec!["damage".to_string()],
+        floor_at_half: true,
+        reroll_ones_tags: vec!["hit".to_string()],
+        ..HouseRules::default()
+    }
+}
+
+#[cfg(test)]
+fn with_rules(notation: &str, rules: &HouseRules) -> (RollFlags, Vec<HouseRule>) {
+    let mut flags = parse(notation).unwrap().pop().unwrap();
+    let applied = rules.apply(&mut flags);
+    (flags, applied)
+}
+
+#[test]
+fn it_fills_in_what_the_request_leaves_out() {
+    let (flags, applied) = with_rules("2d6[damage]", &table_rules());
+    assert!(flags.explode && flags.explode_op.is_none());
+    assert_eq!(applied, vec![HouseRule::ExplodeTag("damage".to_string()), HouseRule::FloorAtHalf(6)]);
+
+    // The 1 is rerolled into a 19, which crits under the house crit range
+    let (flags, applied) = with_rules("1d20+5[hit]", &table_rules());
+    assert_eq!(applied, vec![HouseRule::RerollOnesTag("hit".to_string()), HouseRule::FloorAtHalf(10),
+                             HouseRule::CritRange(19)]);
+    let mut roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1, 19]));
+    roll.house_rules = applied;
+    assert_roll_total(&roll, 24);
+    let kept = roll.dice.iter().find(|die| !die.is_dropped).unwrap();
+    assert!(!kept.is_critical() && roll.is_critical(kept));
+
+    let (flags, _) = with_rules("2d6", &table_rules());
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1, 2]));
+    assert!(roll.is_floored);
+    assert_roll_total(&roll, 6);
+}
+
+#[test]
+fn it_lets_the_request_win() {
+    let mut rules = table_rules();
+    rules.success_thresholds.insert("d10".to_string(), 7);
+
+    let (flags, applied) = with_rules("2d6!>5[damage]", &rules);
+    assert_eq!((flags.explode_op.is_some(), flags.explode_on), (true, 5));
+    assert!(!applied.contains(&HouseRule::ExplodeTag("damage".to_string())));
+
+    let (flags, applied) = with_rules("1d20rr<3[hit]", &rules);
+    assert!(flags.ro_op.is_none());
+    assert_eq!(flags.rr, 3);
+    assert!(!applied.contains(&HouseRule::RerollOnesTag("hit".to_string())));
+
+    let (flags, applied) = with_rules("5d10kh2", &rules);
+    assert_eq!(flags.gte, 0);
+    assert_eq!(applied, vec![HouseRule::FloorAtHalf(10)]);
+    let (flags, applied) = with_rules("5d10gte9", &rules);
+    assert_eq!(flags.gte, 9);
+    assert!(applied.is_empty());
+
+    let mut flags = RollFlags::builder().n(2).die(DieType::D6).floor(3).build().unwrap();
+    assert!(rules.apply(&mut flags).is_empty());
+    assert_eq!(flags.floor, Some(3));
+
+    // A request's deadline wins over a room's
+    rules.deadline_ms = Some(60 * 60 * 1000);
+    let options = rules.roll_options(RollOptions::new().deadline(Duration::from_millis(10)));
+    let mut roller = Roller::seeded(42).with_options(&options);
+    let result = ::stats::Distribution::try_simulated(flags, usize::max_value(), &mut roller);
+    assert_eq!(result.err(), Some(RollError::DeadlineExceeded));
+}
+
+#[test]
+fn it_rolls_the_same_request_differently_under_two_profiles() {
+    let mut counted = HouseRules::new();
+    counted.success_thresholds.insert("d10".to_string(), 7);
+    let plain = HouseRules::new();
+
+    let (flags, applied) = with_rules("10d10", &counted);
+    let counted_roll = Roll::with_roller(flags, &mut Roller::seeded(42));
+    let (flags, _) = with_rules("10d10", &plain);
+    let plain_roll = Roll::with_roller(flags, &mut Roller::seeded(42));
+
+    // The same dice, only one room counts the high ones as successes
+    let values = |roll: &Roll| roll.dice.iter().map(|die| die.value).collect::<Vec<i16>>();
+    let rolled = values(&plain_roll);
+    assert_eq!(values(&counted_roll), rolled);
+    assert_eq!(applied, vec![HouseRule::SuccessThreshold(7)]);
+
+    let high: Vec<i16> = rolled.iter().cloned().filter(|value| *value >= 7).collect();
+    assert_eq!(counted_roll.successes(), high.len());
+    assert_eq!(counted_roll.value, high.iter().map(|value| *value as i32).sum::<i32>());
+    assert_eq!(plain_roll.value, rolled.iter().map(|value| *value as i32).sum::<i32>());
+    assert_ne!(counted_roll.value, plain_roll.value);
+}
+
+#[test]
+fn it_amends_a_roll_under_the_rules_it_was_made_under() {
+    let rules = HouseRules {
+        explode_tags: vec!["damage".to_string()],
+        ..HouseRules::default()
+    };
+    let (flags, applied) = with_rules("1d6[damage]", &rules);
+    let mut roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3]));
+    roll.house_rules = applied;
+
+    // The 6 rolled in the die's place explodes, the same as it would have the first time
+    let amended = roll.amend_reroll(&roll.dice[0].id, &mut ScriptedRoller::new(vec![6, 2])).unwrap();
+    assert_roll_total(&amended, 8);
+    assert_eq!(amended.house_rules, vec![HouseRule::ExplodeTag("damage".to_string())]);
+}
+
+#[test]
+fn it_keeps_rules_per_room() {
+    let rooms = RoomRules::new();
+    rooms.set("tavern", table_rules());
+    assert_eq!(rooms.get("tavern"), table_rules());
+    assert_eq!(rooms.get("dungeon"), HouseRules::default());
+}
+
+#[test]
+fn it_only_lets_the_owner_change_a_rooms_rules() {
+    let rooms = RoomRules::new();
+    assert_eq!(rooms.set_as_owner("tavern", "gm", table_rules()), Ok(()));
+    assert_eq!(rooms.set_as_owner("tavern", "player", HouseRules::default()), Err(NotOwner));
+    assert_eq!(rooms.get("tavern"), table_rules());
+
+    assert_eq!(rooms.set_as_owner("tavern", "gm", HouseRules::default()), Ok(()));
+    assert_eq!(rooms.get("tavern"), HouseRules::default());
+    assert_eq!(rooms.set_as_owner("dungeon", "player", table_rules()), Ok(()));
+}
+
+#[test]
+fn it_round_trips_a_profile() {
+    let mut rules = table_rules();
+    rules.deadline_ms = Some(250);
+    rules.success_thresholds.insert("d10".to_string(), 7);
+
+    let json = serde_json::to_string(&rules).unwrap();
+    assert_eq!(serde_json::from_str::<HouseRules>(&json).unwrap(), rules);
+    assert_eq!(serde_json::from_str::<HouseRules>("{}").unwrap(), HouseRules::default());
+}
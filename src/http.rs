@@ -0,0 +1,412 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::str;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+#[cfg(feature = "tls")]
+use rustls::pki_types::ServerName;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+#[cfg(test)]
+use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+#[cfg(test)]
+use std::thread;
+
+/// Most bytes of a response that are read, past which it's given up on
+pub const MAX_RESPONSE_BYTES: u64 = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    Http,
+
+    /// TLS checked against the Mozilla roots, only with the `tls` feature
+    Https,
+}
+
+/// An `http://` or `https://` URL split into the parts a request needs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    pub scheme: Scheme,
+    pub host: String,
+    pub port: u16,
+
+    /// Path of the URL, `/` when it has none
+    pub path: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HttpError {
+    /// The URL couldn't be understood
+    InvalidUrl,
+
+    /// The response couldn't be read as HTTP, with why
+    InvalidResponse(String),
+
+    /// The request couldn't be sent or the response couldn't be read
+    Io(String),
+
+    /// The host only resolves to loopback, private or link-local addresses, which weren't allowed
+    PrivateHost(String),
+
+    /// The URL uses a scheme other than http, or https without the `tls` feature
+    UnsupportedScheme,
+}
+
+impl From<io::Error> for HttpError {
+    fn from(error: io::Error) -> HttpError {
+        HttpError::Io(error.to_string())
+    }
+}
+
+/// Where a request may connect to, and how long it waits on each step
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpOptions {
+    /// Only connect to public addresses, never loopback, private or link-local ones
+    pub public_only: bool,
+
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+}
+
+impl Default for HttpOptions {
+    fn default() -> HttpOptions {
+        HttpOptions {
+            public_only: false,
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A response as it came back, with its body unchunked
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// The first header with the name, whatever its case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|&&(ref n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, ref value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+
+    /// The body as UTF-8, failing rather than guessing when it isn't
+    pub fn text(&self) -> Result<&str, HttpError> {
+        str::from_utf8(&self.body)
+            .map_err(|_| HttpError::InvalidResponse(format!("the {} response's body isn't UTF-8", self.status)))
+    }
+}
+
+impl Url {
+    /// Split an `http://` or `https://host[:port][/path]` URL
+    pub fn parse(url: &str) -> Result<Url, HttpError> {
+        let (scheme, rest) = if url.starts_with("http://") {
+            (Scheme::Http, &url["http://".len()..])
+        } else if url.starts_with("https://") {
+            (Scheme::Https, &url["https://".len()..])
+        } else {
+            return match url.find("://") {
+                Some(_) => Err(HttpError::UnsupportedScheme),
+                None => Err(HttpError::InvalidUrl),
+            };
+        };
+        if scheme == Scheme::Https && !cfg!(feature = "tls") {
+            return Err(HttpError::UnsupportedScheme);
+        }
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let default_port = if scheme == Scheme::Https { 443 } else { 80 };
+        let (host, port) = match authority.rfind(':') {
+            // A bracketed IPv6 address has colons of its own
+            Some(index) if !authority.ends_with(']') => match authority[index + 1..].parse::<u16>() {
+                Ok(port) => (&authority[..index], port),
+                Err(_) => return Err(HttpError::InvalidUrl),
+            },
+            _ => (authority, default_port),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() || host.contains('@') {
+            return Err(HttpError::InvalidUrl);
+        }
+        Ok(Url {
+            scheme,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// If the host is known to be loopback, private or link-local without looking it up, e.g.
+    /// `localhost` or `10.0.0.1`
+    pub fn is_private_host(&self) -> bool {
+        let host = self.host.to_lowercase();
+        match host.parse::<IpAddr>() {
+            Ok(ip) => !is_public(&ip),
+            Err(_) => host == "localhost" || host.ends_with(".localhost") || host.ends_with(".local") ||
+                host.ends_with(".internal"),
+        }
+    }
+}
+
+/// If an address can be reached from the internet, rather than being loopback, private,
+/// link-local, shared, unspecified, broadcast or multicast
+pub fn is_public(ip: &IpAddr) -> bool {
+    match *ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            let is_shared = octets[0] == 100 && octets[1] & 0xc0 == 64;
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() ||
+                ip.is_broadcast() || ip.is_multicast() || ip.is_documentation() || is_shared || octets[0] == 0)
+        },
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(&IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            let is_unique_local = first & 0xfe00 == 0xfc00;
+            let is_link_local = first & 0xffc0 == 0xfe80;
+            !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || is_unique_local || is_link_local)
+        },
+    }
+}
+
+/// Make a request and read the whole response, with `Connection: close`
+///
+/// The target is the path and query to ask for, and the `Host`, `Content-Length` and `Connection`
+/// headers are added. Each address the host resolves to is tried in turn, skipping those that
+/// aren't public when the options say so, so a name can't be pointed at an internal address after
+/// it was checked.
+pub fn request(url: &Url, method: &str, target: &str, headers: &[(&str, &str)], body: &[u8], options: &HttpOptions)
+               -> Result<Response, HttpError> {
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, target, host_header(url));
+    for &(name, value) in headers {
+        head = head + name + ": " + value + "\r\n";
+    }
+    head = head + &format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+
+    let tcp = connect(url, options)?;
+    tcp.set_read_timeout(Some(options.read_timeout))?;
+    tcp.set_write_timeout(Some(options.write_timeout))?;
+    let mut stream = wrap(url, tcp)?;
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut response = vec![];
+    match stream.take(MAX_RESPONSE_BYTES + 1).read_to_end(&mut response) {
+        Ok(_) => {},
+        // Servers often close TLS without saying so, which is the end of the response all the same
+        Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => {},
+        Err(error) => return Err(error.into()),
+    }
+    if response.len() as u64 > MAX_RESPONSE_BYTES {
+        return Err(HttpError::InvalidResponse(format!("the response is longer than {} bytes", MAX_RESPONSE_BYTES)));
+    }
+    parse_response(&response)
+}
+
+/// Read a whole response, as sent with `Connection: close`
+pub fn parse_response(response: &[u8]) -> Result<Response, HttpError> {
+    let end = match response.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(end) => end,
+        None => return Err(HttpError::InvalidResponse("the response has no end to its headers".to_string())),
+    };
+    let head = str::from_utf8(&response[..end])
+        .map_err(|_| HttpError::InvalidResponse("the response's headers aren't UTF-8".to_string()))?;
+    let body = &response[end + 4..];
+    let mut lines = head.split("\r\n");
+
+    // The status line looks like `HTTP/1.1 404 Not Found`
+    let status = lines.next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| HttpError::InvalidResponse("the response has no status".to_string()))?;
+    let headers: Vec<(String, String)> = lines.filter_map(|line| {
+        line.find(':').map(|colon| (line[..colon].trim().to_string(), line[colon + 1..].trim().to_string()))
+    }).collect();
+
+    let mut response = Response {
+        status,
+        headers,
+        body: vec![],
+    };
+    let is_chunked = response.header("Transfer-Encoding")
+        .map_or(false, |encoding| encoding.to_lowercase().contains("chunked"));
+    response.body = if is_chunked {
+        unchunk(body)?
+    } else {
+        match response.header("Content-Length").map(|length| length.parse::<usize>()) {
+            Some(Ok(length)) if length <= body.len() => body[..length].to_vec(),
+            Some(Ok(_)) => return Err(HttpError::InvalidResponse("the response ended before its body".to_string())),
+            Some(Err(_)) => return Err(HttpError::InvalidResponse("the response's length isn't a number".to_string())),
+            None => body.to_vec(),
+        }
+    };
+    Ok(response)
+}
+
+/// Join the chunks of a body sent with `Transfer-Encoding: chunked`, dropping any trailers
+fn unchunk(mut body: &[u8]) -> Result<Vec<u8>, HttpError> {
+    let invalid = |why: &str| HttpError::InvalidResponse(format!("the response's chunks {}", why));
+    let mut joined = vec![];
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n").ok_or_else(|| invalid("end early"))?;
+        let line = str::from_utf8(&body[..line_end]).map_err(|_| invalid("have a size that isn't text"))?;
+
+        // Sizes are hex, and can have extensions after a `;`
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid("have a size that isn't hex"))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(joined);
+        }
+        if body.len() < size + 2 || &body[size..size + 2] != b"\r\n" {
+            return Err(invalid("are shorter than their sizes"));
+        }
+        joined.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+fn host_header(url: &Url) -> String {
+    let host = if url.host.contains(':') { format!("[{}]", url.host) } else { url.host.clone() };
+    let default_port = if url.scheme == Scheme::Https { 443 } else { 80 };
+    if url.port == default_port { host } else { format!("{}:{}", host, url.port) }
+}
+
+/// Connect to the first address of the host that answers, within the connect timeout
+fn connect(url: &Url, options: &HttpOptions) -> Result<TcpStream, HttpError> {
+    if options.public_only && url.is_private_host() {
+        return Err(HttpError::PrivateHost(url.host.clone()));
+    }
+    let addresses: Vec<SocketAddr> = (url.host.as_str(), url.port).to_socket_addrs()?
+        .filter(|address| !options.public_only || is_public(&address.ip()))
+        .collect();
+    if addresses.is_empty() {
+        return Err(HttpError::PrivateHost(url.host.clone()));
+    }
+
+    let mut last = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, options.connect_timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last = Some(error),
+        }
+    }
+    Err(last.map_or_else(|| HttpError::Io("no address could be reached".to_string()), HttpError::from))
+}
+
+/// Anything a request can be written to and its response read from
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+#[cfg(feature = "tls")]
+fn wrap(url: &Url, tcp: TcpStream) -> Result<Box<dyn Stream>, HttpError> {
+    if url.scheme == Scheme::Http {
+        return Ok(Box::new(tcp));
+    }
+
+    // Built once, as loading the roots isn't free
+    lazy_static! {
+        static ref CONFIG: Arc<ClientConfig> = {
+            let mut roots = RootCertStore::empty();
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+        };
+    }
+    let name = ServerName::try_from(url.host.clone()).map_err(|_| HttpError::InvalidUrl)?;
+    let connection = ClientConnection::new(CONFIG.clone(), name).map_err(|error| HttpError::Io(error.to_string()))?;
+    Ok(Box::new(StreamOwned::new(connection, tcp)))
+}
+
+#[cfg(not(feature = "tls"))]
+fn wrap(url: &Url, tcp: TcpStream) -> Result<Box<dyn Stream>, HttpError> {
+    match url.scheme {
+        Scheme::Http => Ok(Box::new(tcp)),
+        Scheme::Https => Err(HttpError::UnsupportedScheme),
+    }
+}
+
+#[test]
+fn it_parses_urls() {
+    assert_eq!(Url::parse("http://relay.example:8080/chain/").unwrap(), Url {
+        scheme: Scheme::Http,
+        host: "relay.example".to_string(),
+        port: 8080,
+        path: "/chain/".to_string(),
+    });
+    let url = Url::parse("http://[::1]").unwrap();
+    assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("::1", 80, "/"));
+    assert_eq!(host_header(&url), "[::1]");
+    assert_eq!(Url::parse("ftp://files.example").err(), Some(HttpError::UnsupportedScheme));
+    assert_eq!(Url::parse("files.example").err(), Some(HttpError::InvalidUrl));
+    assert_eq!(Url::parse("http://files.example:port/").err(), Some(HttpError::InvalidUrl));
+    assert_eq!(Url::parse("http://user@10.0.0.1/").err(), Some(HttpError::InvalidUrl));
+    if cfg!(feature = "tls") {
+        assert_eq!(Url::parse("https://relay.example").unwrap().port, 443);
+    }
+}
+
+#[test]
+fn it_knows_which_hosts_are_private() {
+    let private = |url: &str| Url::parse(url).unwrap().is_private_host();
+    assert!(private("http://localhost:8000"));
+    assert!(private("http://127.0.0.1"));
+    assert!(private("http://10.1.2.3"));
+    assert!(private("http://169.254.169.254/latest/meta-data"));
+    assert!(private("http://[fd00::1]"));
+    assert!(private("http://[::ffff:192.168.0.1]"));
+    assert!(private("http://metadata.google.internal"));
+    assert!(!private("http://93.184.216.34"));
+    assert!(!private("http://logs.example.com"));
+
+    assert!(is_public(&IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0, 0, 0, 0, 0, 1))));
+    assert!(!is_public(&IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))));
+    assert!(!is_public(&IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))));
+}
+
+#[test]
+fn it_reads_chunked_and_binary_responses() {
+    let response = parse_response(b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n4\r\n{\"a\"\r\n3;x=1\r\n:1}\r\n0\r\n\r\n").unwrap();
+    assert_eq!(response.text(), Ok("{\"a\":1}"));
+
+    let response = parse_response(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 2\r\n\r\n\xff\xfeextra").unwrap();
+    assert_eq!((response.status, response.body.len()), (502, 2));
+    assert!(response.text().is_err());
+
+    assert!(parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n10\r\nshort\r\n").is_err());
+    assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort").is_err());
+    assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+}
+
+#[test]
+fn it_refuses_private_hosts_when_asked_to() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).unwrap();
+        stream.write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n").unwrap();
+    });
+
+    let public_only = HttpOptions {
+        public_only: true,
+        ..HttpOptions::default()
+    };
+    assert_eq!(request(&url, "GET", "/", &[], b"", &public_only).err(), Some(HttpError::PrivateHost("127.0.0.1".to_string())));
+    assert_eq!(request(&url, "GET", "/", &[], b"", &HttpOptions::default()).unwrap().status, 204);
+}
@@ -0,0 +1,275 @@
+use chrono::Duration;
+use chrono::prelude::Utc;
+use die::Die;
+use roll::{ExplodeSuccessPolicy, Roll};
+use std::collections::{HashMap, HashSet};
+#[cfg(test)]
+use chrono::TimeZone;
+#[cfg(test)]
+use parser::parse;
+#[cfg(test)]
+use serde_json;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// How far past the server's clock a timestamp can be before it's taken as made up, in seconds
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Something about a roll that couldn't have happened by rolling it
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// More than one die of the roll has the ID
+    DuplicateId(String),
+
+    /// The die (first) was rerolled into a die (second) the roll doesn't have
+    MissingChild(String, String),
+
+    /// The die with the ID exploded, but no die was rolled for the explosion
+    ExplodedWithoutChild(String),
+
+    /// The die (first) landed on a value (second) that isn't one of its faces
+    ValueOutOfRange(String, i16),
+
+    /// The roll, or the die with the ID, has a timestamp too far in the future
+    FutureTimestamp(Option<String>),
+
+    /// A total of the roll (first) isn't what its dice, modifiers and divisors come to (second)
+    WrongTotal(&'static str, i64),
+
+    /// The roll's total is divided by zero
+    DividedByZero,
+}
+
+/// Why a roll sent in couldn't be used
+#[derive(Debug, PartialEq)]
+pub enum InvalidRoll {
+    /// The JSON couldn't be read as a roll
+    Malformed(String),
+
+    /// The roll was read, but breaks each of these
+    Violations(Vec<Violation>),
+}
+
+/// Every way the roll breaks what rolling it would guarantee, none for a roll that could have been rolled
+///
+/// Rolls made here always pass. Rolls handed back by clients, e.g. to be amended, can say anything,
+/// so check them before trusting them. A die that compounded its explosions is checked by the face
+/// it first landed on, as its value adds up every explosion. The totals are worked out again from
+/// the kept dice, the modifiers and the divisors. A floor or ceiling isn't kept with the roll, so
+/// a floored total only has to be above the dice and a ceilinged one below them.
+pub fn check(roll: &Roll) -> Vec<Violation> {
+    let latest = Utc::now() + Duration::seconds(MAX_CLOCK_SKEW_SECS);
+    let mut violations = vec![];
+    if roll.timestamp > latest {
+        violations.push(Violation::FutureTimestamp(None));
+    }
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for die in &roll.dice {
+        *counts.entry(die.id.as_str()).or_insert(0) += 1;
+    }
+    let mut reported = HashSet::new();
+
+    for die in &roll.dice {
+        if counts[die.id.as_str()] > 1 && reported.insert(die.id.as_str()) {
+            violations.push(Violation::DuplicateId(die.id.clone()));
+        }
+
+        let face = landed_face(die, roll.explode_policy);
+        if !die.has_face(face) {
+            violations.push(Violation::ValueOutOfRange(die.id.clone(), face));
+        }
+
+        if let Some(ref child) = die.child {
+            if !counts.contains_key(child.as_str()) {
+                violations.push(Violation::MissingChild(die.id.clone(), child.clone()));
+            }
+        }
+
+        // Each explosion rolls another die of the term after it, unless they compound, so there
+        // are at least as many dice after an exploded die as exploded dice from it on
+        if die.is_exploded && roll.explode_policy == ExplodeSuccessPolicy::CompareEachDie {
+            let later = |other: &&Die| other.term_index == die.term_index && other.position_in_term > die.position_in_term;
+            let rolled = roll.dice.iter().filter(later).count();
+            let needed = roll.dice.iter().filter(later).filter(|other| other.is_exploded).count() + 1;
+            if rolled < needed {
+                violations.push(Violation::ExplodedWithoutChild(die.id.clone()));
+            }
+        }
+
+        if die.timestamp > latest {
+            violations.push(Violation::FutureTimestamp(Some(die.id.clone())));
+        }
+    }
+    check_totals(roll, &mut violations);
+    violations
+}
+
+/// Add up the kept dice, then the modifiers and divisors, the same as rolling does
+fn check_totals(roll: &Roll, violations: &mut Vec<Violation>) {
+    let natural: i64 = roll.dice.iter().filter(|die| !die.is_dropped).map(|die| die.counted() as i64).sum();
+    if roll.natural_value as i64 != natural {
+        violations.push(Violation::WrongTotal("natural_value", natural));
+    }
+
+    let raw = roll.raw_value as i64;
+    let is_clamped = (roll.is_floored && raw > natural) || (roll.is_ceilinged && raw < natural);
+    if !is_clamped && raw != natural {
+        violations.push(Violation::WrongTotal("raw_value", natural));
+    }
+
+    let mut value = roll.modifiers.iter().fold(raw, |value, modifier| value + *modifier as i64);
+    for &divisor in &roll.divisors {
+        if divisor == 0 {
+            violations.push(Violation::DividedByZero);
+            return;
+        }
+        let clamped = value.max(i32::min_value() as i64).min(i32::max_value() as i64) as i32;
+        value = roll.rounding.divide(clamped, divisor as i32) as i64;
+    }
+    if roll.value as i64 != value {
+        violations.push(Violation::WrongTotal("value", value));
+    }
+}
+
+/// The face the die landed on before a blessing, curse or compounded explosion changed its value
+fn landed_face(die: &Die, policy: ExplodeSuccessPolicy) -> i16 {
+    if die.is_exploded && policy == ExplodeSuccessPolicy::CompoundThenCompare && die.natural.is_none() {
+        return die.roll_history.first().map_or(die.max, |record| record.value);
+    }
+    die.landed_on()
+}
+
+#[cfg(test)]
+fn rolled(notation: &str, values: Vec<i16>) -> Roll {
+    Roll::with_roller(parse(notation).unwrap().pop().unwrap(), &mut ScriptedRoller::new(values))
+}
+
+#[cfg(test)]
+fn round_trip(roll: &Roll) -> Result<Roll, InvalidRoll> {
+    Roll::from_json_validated(&serde_json::to_string(roll).unwrap())
+}
+
+#[test]
+fn it_accepts_rolls_made_here() {
+    assert!(round_trip(&rolled("4d6kh3", vec![3, 1, 6, 5])).is_ok());
+    assert!(round_trip(&rolled("2d6rr1!", vec![1, 6, 4, 2])).is_ok());
+    assert!(round_trip(&rolled("1d6!!", vec![6, 6, 2])).is_ok());
+    assert!(round_trip(&Roll::from_values("3dF", &[-1, 0, 1]).unwrap()).is_ok());
+}
+
+#[test]
+fn it_reports_values_that_arent_faces() {
+    let mut roll = rolled("2d6", vec![3, 4]);
+    roll.dice[1].value = 9;
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![
+        Violation::ValueOutOfRange(roll.dice[1].id.clone(), 9),
+    ])));
+}
+
+#[test]
+fn it_reports_explosions_without_a_die() {
+    let mut roll = rolled("2d6!", vec![3, 6, 2]);
+    roll.dice.pop();
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![
+        Violation::ExplodedWithoutChild(roll.dice[1].id.clone()),
+    ])));
+}
+
+#[test]
+fn it_reports_rerolls_into_missing_dice() {
+    let mut roll = rolled("1d6rr1", vec![1, 5]);
+    roll.dice.pop();
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![
+        Violation::MissingChild(roll.dice[0].id.clone(), roll.dice[0].child.clone().unwrap()),
+    ])));
+}
+
+#[test]
+fn it_reports_timestamps_in_the_future() {
+    let mut roll = rolled("2d6", vec![3, 4]);
+    roll.timestamp = Utc.ymd(3000, 1, 1).and_hms(0, 0, 0);
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![Violation::FutureTimestamp(None)])));
+
+    let mut roll = rolled("2d6", vec![3, 4]);
+    roll.dice[0].timestamp = Utc::now() + Duration::seconds(MAX_CLOCK_SKEW_SECS * 2);
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![
+        Violation::FutureTimestamp(Some(roll.dice[0].id.clone())),
+    ])));
+}
+
+#[test]
+fn it_reports_totals_that_arent_what_the_dice_come_to() {
+    let mut roll = rolled("4d6kh3+2", vec![3, 1, 6, 5]);
+    roll.value = 30;
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![Violation::WrongTotal("value", 16)])));
+
+    // Changing a kept die leaves the totals it adds up to behind
+    let mut roll = rolled("4d6kh3+2", vec![3, 1, 6, 5]);
+    roll.dice[2].value = 4;
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![
+        Violation::WrongTotal("natural_value", 12),
+        Violation::WrongTotal("raw_value", 12),
+    ])));
+}
+
+#[test]
+fn it_works_out_divided_and_floored_totals() {
+    assert!(round_trip(&rolled("3d6+1div2ceil", vec![3, 4, 6])).is_ok());
+    assert!(round_trip(&rolled("2d6floor8", vec![1, 2])).is_ok());
+
+    let mut roll = rolled("3d6div2", vec![3, 4, 6]);
+    roll.value = 7;
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![Violation::WrongTotal("value", 6)])));
+
+    // A floor only ever raises the total
+    let mut roll = rolled("2d6floor8", vec![1, 2]);
+    roll.raw_value = 2;
+    roll.value = 2;
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![Violation::WrongTotal("raw_value", 3)])));
+
+    let mut roll = rolled("3d6div2", vec![3, 4, 6]);
+    roll.divisors = vec![0];
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![Violation::DividedByZero])));
+}
+
+#[test]
+fn it_reports_duplicate_ids_once() {
+    let mut roll = rolled("3d6", vec![3, 4, 5]);
+    let id = roll.dice[0].id.clone();
+    roll.dice[1].id = id.clone();
+    roll.dice[2].id = id.clone();
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![Violation::DuplicateId(id)])));
+}
+
+#[test]
+fn it_reports_every_violation_together() {
+    let mut roll = rolled("2d6rr1!", vec![1, 6, 4, 3]);
+    roll.timestamp = Utc.ymd(3000, 1, 1).and_hms(0, 0, 0);
+    let rerolled = roll.dice[0].id.clone();
+    let child = roll.dice[0].child.clone().unwrap();
+    roll.dice.retain(|die| die.id != child);
+    roll.dice.pop();
+    let exploded = roll.dice[1].id.clone();
+    roll.dice[1].value = 12;
+    roll.dice.push(roll.dice[0].clone());
+
+    assert_eq!(round_trip(&roll).err(), Some(InvalidRoll::Violations(vec![
+        Violation::FutureTimestamp(None),
+        Violation::DuplicateId(rerolled.clone()),
+        Violation::MissingChild(rerolled.clone(), child.clone()),
+        Violation::ValueOutOfRange(exploded.clone(), 12),
+        Violation::ExplodedWithoutChild(exploded),
+        Violation::MissingChild(rerolled, child),
+        Violation::WrongTotal("natural_value", 12),
+        Violation::WrongTotal("raw_value", 12),
+    ])));
+}
+
+#[test]
+fn it_reports_json_that_isnt_a_roll() {
+    match Roll::from_json_validated("{\"dice\": 3}") {
+        Err(InvalidRoll::Malformed(_)) => {},
+        other => panic!("expected malformed JSON, got {:?}", other.map(|roll| roll.id)),
+    }
+}
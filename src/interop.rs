@@ -0,0 +1,322 @@
+use dialect::Dialect;
+use die::{Die, DieType, RollRecord};
+use lint::LintWarning;
+use parser::{parse_notation_with, ParseOptions};
+use roll::{Roll, RollFlags};
+use roller::Roller;
+use serde_json::{self, Value};
+use std::convert::TryFrom;
+
+/// Foundry's die modifiers that notation can say too, by the letters they start with
+const MAPPED_MODIFIERS: &[&str] = &["k", "kh", "kl", "d", "dh", "dl", "x", "r", "rr", "cs"];
+
+/// Foundry terms that count towards its total, but have nothing of ours to become
+const UNMAPPED_TERMS: &[&str] = &["Coin", "FunctionTerm", "MathTerm", "ParentheticalTerm", "PoolTerm", "StringTerm"];
+
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    /// The export isn't JSON, with why
+    InvalidJson(String),
+
+    /// A field every export has is missing or isn't what it should be, e.g. `terms`
+    MissingField(&'static str),
+
+    /// The roll was exported before it was rolled, so it has no results
+    NotEvaluated,
+
+    /// A number too big for what it becomes, with the field it was in, e.g. `faces` past 32767
+    OutOfRange(&'static str),
+
+    /// A term of a type Foundry doesn't come with, e.g. one a module adds
+    UnknownTerm(String),
+}
+
+impl From<serde_json::Error> for ImportError {
+    fn from(error: serde_json::Error) -> ImportError {
+        ImportError::InvalidJson(error.to_string())
+    }
+}
+
+/// Read a roll exported from Foundry VTT, e.g. from a campaign's chat log
+///
+/// Each result of a die term becomes a die, with discarded and rerolled results dropped and
+/// exploded results marked as exploded. The formula is kept as the equation, and read as notation
+/// for `normalized`. Foundry's total is kept as the roll's value whatever the dice add up to, so
+/// terms with nothing to become, like coins, pools or multiplying, are left out with a warning on
+/// the roll rather than changing the total. Terms of types Foundry doesn't come with are refused,
+/// as are numbers too big for what they become rather than being cut down to fit.
+pub fn from_foundry(json: &str) -> Result<Roll, ImportError> {
+    let export: Value = serde_json::from_str(json)?;
+    if export["evaluated"] == Value::Bool(false) {
+        return Err(ImportError::NotEvaluated);
+    }
+    let formula = export["formula"].as_str().ok_or(ImportError::MissingField("formula"))?;
+    let terms = export["terms"].as_array().ok_or(ImportError::MissingField("terms"))?;
+    let total = export["total"].as_f64().ok_or(ImportError::MissingField("total"))?;
+
+    // An empty roll gives the import an ID and timestamp of its own, without rolling anything
+    let mut roll = Roll::with_roller(RollFlags::new(), &mut Roller::scripted(vec![]));
+    roll.equation = formula.to_string();
+    let mut warnings = vec![];
+    let mut operator = "+";
+    let mut term_index = 0;
+    for (index, term) in terms.iter().enumerate() {
+        let class = term["class"].as_str().ok_or(ImportError::MissingField("class"))?;
+        match class {
+            "Die" | "FateDie" => {
+                if operator != "+" {
+                    warnings.push(warning("foundry-unmapped-operator", format!("Dice after {} are added", operator), index));
+                }
+                for modifier in term["modifiers"].as_array().into_iter().flat_map(|modifiers| modifiers.iter()) {
+                    let modifier = modifier.as_str().unwrap_or("");
+                    let name: String = modifier.chars().take_while(|c| c.is_alphabetic()).collect();
+                    if !MAPPED_MODIFIERS.contains(&name.as_str()) {
+                        let message = format!("The {} modifier has no notation, the dice are kept as Foundry rolled them", modifier);
+                        warnings.push(warning("foundry-unmapped-modifier", message, index));
+                    }
+                }
+                roll.dice.extend(dice(term, class == "FateDie", term_index, &roll)?);
+                term_index += 1;
+            },
+            "OperatorTerm" => {
+                operator = match term["operator"].as_str() {
+                    Some("+") => "+",
+                    Some("-") => "-",
+                    Some("*") => "*",
+                    Some("/") => "/",
+                    _ => return Err(ImportError::MissingField("operator")),
+                };
+                if operator == "*" || operator == "/" {
+                    warnings.push(warning("foundry-unmapped-operator", format!("{} isn't kept, the total is", operator), index));
+                }
+            },
+            "NumericTerm" => {
+                let number = term["number"].as_f64().ok_or(ImportError::MissingField("number"))?;
+                match operator {
+                    "+" => roll.modifiers.push(whole(number, "number")?),
+                    "-" => roll.modifiers.push(whole(-number, "number")?),
+                    _ => {},
+                }
+                if number.fract() != 0.0 {
+                    warnings.push(warning("foundry-rounded-number", format!("{} is rounded to a whole number", number), index));
+                }
+            },
+            class if UNMAPPED_TERMS.contains(&class) => {
+                let message = format!("The {} term has nothing to become, the total is kept as Foundry worked it out", class);
+                warnings.push(warning("foundry-unmapped-term", message, index));
+            },
+            class => return Err(ImportError::UnknownTerm(class.to_string())),
+        }
+    }
+
+    let options = ParseOptions::new().dialect(Dialect::Foundry);
+    match parse_notation_with(&with_keep_counts(formula), &options) {
+        Ok(rolls) => {
+            let written: Vec<String> = rolls.iter().map(|flags| flags.canonical()).collect();
+            roll.normalized = Some(written.join(" + "));
+        },
+        Err(_) => warnings.push(warning("foundry-unread-formula", "The formula isn't notation, so it's kept as it was written".to_string(), 0)),
+    }
+
+    if total.fract() != 0.0 {
+        warnings.push(warning("foundry-rounded-total", format!("The total of {} is rounded to a whole number", total), 0));
+    }
+    roll.natural_value = roll.dice.iter().filter(|die| !die.is_dropped).map(|die| die.value as i32).sum();
+    roll.raw_value = roll.natural_value;
+    roll.value = whole(total, "total")?;
+    roll.warnings = warnings;
+    Ok(roll)
+}
+
+/// A die for each of a term's results
+fn dice(term: &Value, is_fate: bool, term_index: u16, roll: &Roll) -> Result<Vec<Die>, ImportError> {
+    let faces = term["faces"].as_i64().ok_or(ImportError::MissingField("faces"))?;
+    let (die_type, min, max) = if is_fate {
+        (DieType::Fate, -1, 1)
+    } else {
+        let sides = i16::try_from(faces).map_err(|_| ImportError::OutOfRange("faces"))?;
+        (DieType::with_sides(sides as u16).unwrap_or(DieType::Other), 1, sides)
+    };
+    let tags: Vec<String> = term["options"]["flavor"].as_str().into_iter().map(|flavor| flavor.to_string()).collect();
+    let results = term["results"].as_array().ok_or(ImportError::MissingField("results"))?;
+
+    let mut dice = vec![];
+    for (position, result) in results.iter().enumerate() {
+        let value = result["result"].as_i64().ok_or(ImportError::MissingField("result"))?;
+        let value = i16::try_from(value).map_err(|_| ImportError::OutOfRange("result"))?;
+        let is_set = |flag: &str| result[flag] == Value::Bool(true);
+
+        let mut die = Die::with_range(die_type, min, max);
+        die.value = value;
+        die.roll_history.push(RollRecord {
+            timestamp: roll.timestamp,
+            value,
+        });
+        die.is_rerolled = is_set("rerolled");
+        die.is_dropped = die.is_rerolled || is_set("discarded") || result["active"] == Value::Bool(false);
+        die.is_exploded = is_set("exploded");
+        die.is_successful = match result["success"] {
+            Value::Bool(success) => success,
+            _ => !die.is_dropped,
+        };
+        die.position_in_term = u16::try_from(position).map_err(|_| ImportError::OutOfRange("results"))?;
+        die.tags = tags.clone();
+        die.term_index = term_index;
+        dice.push(die);
+    }
+    Ok(dice)
+}
+
+/// A number rounded to the nearest whole one, if it fits in what it becomes
+fn whole<T: TryFrom<i64>>(number: f64, field: &'static str) -> Result<T, ImportError> {
+    let rounded = number.round();
+    if !(rounded >= i64::min_value() as f64 && rounded < i64::max_value() as f64) {
+        return Err(ImportError::OutOfRange(field));
+    }
+    T::try_from(rounded as i64).map_err(|_| ImportError::OutOfRange(field))
+}
+
+fn warning(rule: &str, message: String, term: usize) -> LintWarning {
+    LintWarning {
+        rule: rule.to_string(),
+        message,
+        suggestion: None,
+        term,
+    }
+}
+
+/// The formula with Foundry's bare `kh` and `kl` given the count of 1 they stand for, leaving
+/// flavor text alone
+fn with_keep_counts(formula: &str) -> String {
+    let mut filled = String::with_capacity(formula.len() + 2);
+    let mut in_text = false;
+    let mut chars = formula.chars().peekable();
+    while let Some(c) = chars.next() {
+        filled.push(c);
+        match c {
+            '[' => in_text = true,
+            ']' => in_text = false,
+            '"' => in_text = !in_text,
+            'k' if !in_text => {
+                if let Some(&next) = chars.peek().filter(|&&next| next == 'h' || next == 'l') {
+                    filled.push(next);
+                    chars.next();
+                }
+                if !chars.peek().map_or(false, |next| next.is_digit(10)) {
+                    filled.push('1');
+                }
+            },
+            _ => {},
+        }
+    }
+    filled
+}
+
+#[test]
+fn it_imports_a_simple_attack() {
+    let roll = from_foundry(include_str!("../tests/fixtures/foundry/attack.json")).unwrap();
+    assert_eq!(roll.equation, "1d20[Attack] + 5");
+    assert_eq!((roll.natural_value, roll.value), (14, 19));
+    assert_eq!(roll.modifiers, vec![5]);
+    assert_eq!(roll.dice.len(), 1);
+    assert_eq!((roll.dice[0].die, roll.dice[0].value, roll.dice[0].max), (DieType::D20, 14, 20));
+    assert_eq!(roll.dice[0].tags, vec!["Attack"]);
+    assert!(roll.warnings.is_empty());
+    assert!(roll.normalized.is_some());
+}
+
+#[test]
+fn it_imports_exploded_dice() {
+    let roll = from_foundry(include_str!("../tests/fixtures/foundry/exploding.json")).unwrap();
+    assert_eq!((roll.natural_value, roll.value), (22, 22));
+    let exploded: Vec<i16> = roll.dice.iter().filter(|die| die.is_exploded).map(|die| die.value).collect();
+    assert_eq!(exploded, vec![6, 6]);
+    assert_eq!(roll.dice.len(), 6);
+    assert!(roll.dice.iter().all(|die| !die.is_dropped));
+    assert_eq!(roll.normalized, Some("4d6!".to_string()));
+}
+
+#[test]
+fn it_imports_discarded_dice_as_dropped() {
+    let roll = from_foundry(include_str!("../tests/fixtures/foundry/keep-highest.json")).unwrap();
+    assert_eq!((roll.natural_value, roll.value), (16, 20));
+    let dropped: Vec<i16> = roll.dice.iter().filter(|die| die.is_dropped).map(|die| die.value).collect();
+    assert_eq!(dropped, vec![7]);
+    assert_eq!(roll.normalized, Some("2d20kh1+4".to_string()));
+}
+
+#[test]
+fn it_counts_bare_keeps_as_one() {
+    assert_eq!(with_keep_counts("2d20kh + 1d20k[kh] \"keep\""), "2d20kh1 + 1d20k1[kh] \"keep\"");
+}
+
+#[test]
+fn it_keeps_its_own_serialization_of_an_import() {
+    let export = include_str!("../tests/fixtures/foundry/keep-highest.json");
+    let roll = from_foundry(export).unwrap();
+    let json = serde_json::to_string(&roll).unwrap();
+    let again: Roll = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&again).unwrap(), json);
+}
+
+#[test]
+fn it_imports_the_same_content_again() {
+    // Importing again makes a roll with its own ID but the same content
+    let export = include_str!("../tests/fixtures/foundry/exploding.json");
+    let (first, second) = (from_foundry(export).unwrap(), from_foundry(export).unwrap());
+    assert!(first.id != second.id);
+    assert_eq!(first.content_hash(), second.content_hash());
+}
+
+#[test]
+fn it_warns_about_what_it_cant_map() {
+    let export = r#"{"formula": "(1d6 + 1d4) * 2", "total": 14, "evaluated": true, "terms": [
+        {"class": "ParentheticalTerm", "term": "1d6 + 1d4", "options": {}, "evaluated": true},
+        {"class": "OperatorTerm", "operator": "*", "options": {}, "evaluated": true},
+        {"class": "NumericTerm", "number": 2, "options": {}, "evaluated": true}
+    ]}"#;
+    let roll = from_foundry(export).unwrap();
+    assert_eq!(roll.value, 14);
+    assert!(roll.modifiers.is_empty());
+    let rules: Vec<&str> = roll.warnings.iter().map(|warning| warning.rule.as_str()).collect();
+    assert_eq!(&rules[..2], &["foundry-unmapped-term", "foundry-unmapped-operator"]);
+}
+
+#[test]
+fn it_refuses_terms_foundry_doesnt_come_with() {
+    let export = r#"{"formula": "1d6", "total": 3, "terms": [{"class": "WildMagicTerm"}]}"#;
+    assert_eq!(from_foundry(export).err(), Some(ImportError::UnknownTerm("WildMagicTerm".to_string())));
+}
+
+#[test]
+fn it_refuses_rolls_that_werent_rolled() {
+    assert_eq!(from_foundry(r#"{"formula": "1d6", "evaluated": false}"#).err(), Some(ImportError::NotEvaluated));
+    assert!(match from_foundry("not json") {
+        Err(ImportError::InvalidJson(_)) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn it_refuses_numbers_too_big_to_keep() {
+    let modifier = r#"{"formula": "40000", "total": 40000, "terms": [{"class": "NumericTerm", "number": 40000}]}"#;
+    assert_eq!(from_foundry(modifier).err(), Some(ImportError::OutOfRange("number")));
+
+    let faces = r#"{"formula": "1d70000", "total": 3, "terms": [{"class": "Die", "faces": 70000, "results": []}]}"#;
+    assert_eq!(from_foundry(faces).err(), Some(ImportError::OutOfRange("faces")));
+
+    let result = r#"{"formula": "1d6", "total": 3, "terms": [{"class": "Die", "faces": 6, "results": [{"result": 65539}]}]}"#;
+    assert_eq!(from_foundry(result).err(), Some(ImportError::OutOfRange("result")));
+
+    let total = r#"{"formula": "1d6", "total": 1e12, "terms": []}"#;
+    assert_eq!(from_foundry(total).err(), Some(ImportError::OutOfRange("total")));
+}
+
+#[test]
+fn it_rounds_fractional_numbers_with_a_warning() {
+    let export = r#"{"formula": "1.6", "total": 1.6, "terms": [{"class": "NumericTerm", "number": 1.6}]}"#;
+    let roll = from_foundry(export).unwrap();
+    assert_eq!((roll.modifiers.clone(), roll.value), (vec![2], 2));
+    let rules: Vec<&str> = roll.warnings.iter().map(|warning| warning.rule.as_str()).collect();
+    assert!(rules.contains(&"foundry-rounded-number"));
+}
@@ -0,0 +1,187 @@
+use parser::MODIFIERS;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(test)]
+use parser::{parse_notation, parse_notation_with, ParseOptions};
+#[cfg(test)]
+use std::sync::Arc;
+
+/// How dialects spell modifiers standard notation spells otherwise, see `dialect::Dialect`
+const DIALECT_TOKENS: [&str; 5] = ["k", "r", "x", "xo", "cs"];
+
+/// Tokens of `MODIFIERS` that write the dice themselves rather than modify them
+const DICE_TOKENS: [&str; 4] = ["d", "dF", "hd", "[]"];
+
+#[derive(Debug, PartialEq)]
+pub enum KeywordError {
+    /// The alias is already notation, standard or a dialect's, so it can't be read as another modifier
+    Conflict(String),
+
+    /// The alias has something other than letters in it
+    InvalidAlias(String),
+
+    /// The pack couldn't be read as JSON, with why
+    Json(String),
+
+    /// The alias (first) is for something (second) that isn't a modifier of standard notation
+    UnknownModifier(String, String),
+}
+
+impl fmt::Display for KeywordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &KeywordError::Conflict(ref alias) => write!(f, "The keyword {} is already notation", alias),
+            &KeywordError::InvalidAlias(ref alias) => write!(f, "The keyword {} can only have letters", alias),
+            &KeywordError::Json(ref reason) => write!(f, "The keyword pack couldn't be read: {}", reason),
+            &KeywordError::UnknownModifier(ref alias, ref modifier) => {
+                write!(f, "The keyword {} is for {}, which isn't a modifier", alias, modifier)
+            },
+        }
+    }
+}
+
+/// Modifiers written in another language, e.g. French players' `4d6gm3` for `4d6kh3`
+///
+/// Each alias is a word of letters read as one of the modifiers of standard notation, see
+/// `parser::MODIFIERS`, wherever a modifier can go. Aliases are read before dialects, and only
+/// change how notation is written: parsed rolls, their equations and `to_notation` are standard
+/// notation, so rolls saved with a pack read the same without it. Aliases match whatever their
+/// case, and can't be notation already, which is checked when the pack is loaded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct KeywordPack {
+    /// Each alias, lowercased, and the modifier it's read as
+    aliases: BTreeMap<String, String>,
+
+    /// The language the pack is for, e.g. `fr`
+    pub language: String,
+}
+
+impl KeywordPack {
+    /// A pack of aliases and the modifiers they're read as, e.g. `("gm", "kh")`
+    pub fn new(language: &str, aliases: &[(&str, &str)]) -> Result<KeywordPack, KeywordError> {
+        let mut pack = KeywordPack {
+            aliases: BTreeMap::new(),
+            language: language.to_string(),
+        };
+        for &(alias, modifier) in aliases {
+            pack.aliases.insert(alias.to_lowercase(), modifier.to_string());
+        }
+        pack.check()?;
+        Ok(pack)
+    }
+
+    /// Read a pack from JSON, e.g. `{"language": "fr", "aliases": {"gm": "kh", "explosif": "!"}}`
+    pub fn from_json(json: &str) -> Result<KeywordPack, KeywordError> {
+        let pack: KeywordPack = serde_json::from_str(json).map_err(|error| KeywordError::Json(error.to_string()))?;
+        KeywordPack::new(&pack.language, &pack.aliases.iter().map(|(alias, modifier)| (alias.as_str(), modifier.as_str()))
+            .collect::<Vec<(&str, &str)>>())
+    }
+
+    /// Each alias, lowercased, and the modifier it's read as
+    pub fn aliases(&self) -> &BTreeMap<String, String> {
+        &self.aliases
+    }
+
+    /// Check every alias is letters, isn't notation already and is for a modifier, as `new` does
+    ///
+    /// Packs read from JSON with serde rather than `from_json` haven't been checked, e.g. one
+    /// saved in a `Session`.
+    pub fn check(&self) -> Result<(), KeywordError> {
+        for (alias, modifier) in &self.aliases {
+            if alias.is_empty() || !alias.chars().all(char::is_alphabetic) {
+                return Err(KeywordError::InvalidAlias(alias.clone()));
+            }
+            let is_notation = MODIFIERS.iter().map(|spec| spec.token).chain(DIALECT_TOKENS.iter().cloned())
+                .any(|token| token.to_lowercase() == *alias);
+            if is_notation {
+                return Err(KeywordError::Conflict(alias.clone()));
+            }
+            let is_modifier = MODIFIERS.iter().any(|spec| spec.token == modifier && !DICE_TOKENS.contains(&spec.token));
+            if !is_modifier {
+                return Err(KeywordError::UnknownModifier(alias.clone(), modifier.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the aliases in notation as the modifiers they're read as, leaving comments, sides and
+    /// tags as they were
+    pub fn expand(&self, command: &str) -> String {
+        let chars: Vec<char> = command.chars().collect();
+        let mut expanded = String::with_capacity(command.len());
+        let mut in_comment = false;
+        let mut depth = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '"' => in_comment = !in_comment,
+                '[' if !in_comment => depth += 1,
+                ']' if !in_comment && depth > 0 => depth -= 1,
+                _ => {},
+            }
+
+            // Modifiers follow the sides of the dice, or the number or brackets of another modifier
+            let follows_dice = !in_comment && depth == 0 && i > 0 && (chars[i - 1].is_digit(10) || chars[i - 1] == ']');
+            let len = if follows_dice { chars[i..].iter().take_while(|c| c.is_alphabetic()).count() } else { 0 };
+            let word: String = chars[i..i + len].iter().collect();
+            match self.aliases.get(&word.to_lowercase()) {
+                Some(modifier) if len > 0 => {
+                    expanded += modifier;
+                    i += len;
+                },
+                _ => {
+                    expanded.push(c);
+                    i += 1;
+                },
+            }
+        }
+        expanded
+    }
+}
+
+#[cfg(test)]
+fn french() -> KeywordPack {
+    KeywordPack::from_json(r#"{"language": "fr", "aliases": {"gm": "kh", "gp": "kl", "explosif": "!", "relance": "rr"}}"#).unwrap()
+}
+
+#[test]
+fn it_reads_aliases_as_the_standard_modifiers() {
+    let options = ParseOptions::new().keywords(Arc::new(french()));
+    let aliased = [("4d6gm3", "4d6kh3"), ("2d20GP1+5", "2d20kl1+5"), ("3d6explosif", "3d6!"),
+                   ("2d6relance1", "2d6rr1"), ("4d6[feu]gm3 \"gm\"", "4d6[feu]kh3 \"gm\"")];
+    for &(french, english) in aliased.iter() {
+        let parsed = parse_notation_with(french, &options).unwrap();
+        let expected = parse_notation(english).unwrap();
+        assert_eq!(parsed[0].canonical(), expected[0].canonical(), "{}", french);
+        assert_eq!(parsed[0].equation, expected[0].equation, "{}", french);
+    }
+
+    // Comments are left as they were written
+    assert_eq!(french().expand("4d6gm3 \"gm\""), "4d6kh3 \"gm\"");
+}
+
+#[test]
+fn it_rejects_packs_that_clash_with_notation() {
+    assert_eq!(KeywordPack::new("fr", &[("gm", "kh"), ("KH", "kl")]).err(), Some(KeywordError::Conflict("kh".to_string())));
+    assert_eq!(KeywordPack::new("de", &[("x", "!")]).err(), Some(KeywordError::Conflict("x".to_string())));
+    assert_eq!(KeywordPack::new("fr", &[("plafond", "ceiling"), ("min", "max")]).err(),
+               Some(KeywordError::Conflict("min".to_string())));
+    assert_eq!(KeywordPack::new("fr", &[("gm3", "kh")]).err(), Some(KeywordError::InvalidAlias("gm3".to_string())));
+    assert_eq!(KeywordPack::new("fr", &[("dé", "d")]).err(),
+               Some(KeywordError::UnknownModifier("dé".to_string(), "d".to_string())));
+    assert_eq!(KeywordPack::from_json(r#"{"language": "fr", "aliases": {"dl": "dh"}}"#).err(),
+               Some(KeywordError::Conflict("dl".to_string())));
+    assert_eq!(KeywordError::Conflict("dl".to_string()).to_string(), "The keyword dl is already notation");
+}
+
+#[test]
+fn it_writes_aliased_rolls_back_in_standard_notation() {
+    let options = ParseOptions::new().keywords(Arc::new(french()));
+    let parsed = parse_notation_with("4d6gm3+2", &options).unwrap().pop().unwrap();
+    let notation = parsed.to_notation().unwrap();
+    assert_eq!(notation, "4d6kh3+2");
+    assert_eq!(parsed.normalized, Some("4d6kh3+2".to_string()));
+    assert_eq!(parse_notation(&notation).unwrap()[0].canonical(), parsed.canonical());
+}
@@ -0,0 +1,167 @@
+/// A single rung of a success ladder
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Band {
+    /// The lowest value that reaches this band
+    pub threshold: i32,
+
+    /// Name of the band (e.g. "partial", "critical")
+    pub label: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LadderError {
+    /// A ladder needs at least one band
+    Empty,
+
+    /// The threshold at this index is not greater than the one before it
+    NotIncreasing(usize),
+}
+
+/// The band a roll landed in
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Outcome {
+    /// Name of the band
+    pub label: String,
+
+    /// Position of the band on the ladder, starting from the bottom rung
+    pub band_index: usize,
+
+    /// The value that was graded, either the roll total or the total minus the DC
+    pub margin: i32,
+}
+
+/// An ordered list of named bands used to grade a roll
+///
+/// Each threshold is the lowest value that reaches its band. Anything below the first threshold
+/// still lands on the bottom rung, so every value maps to exactly one band.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SuccessLadder {
+    bands: Vec<Band>,
+}
+
+impl SuccessLadder {
+    pub fn new(bands: Vec<(i32, &str)>) -> Result<SuccessLadder, LadderError> {
+        if bands.is_empty() {
+            return Err(LadderError::Empty);
+        }
+
+        for (idx, pair) in bands.windows(2).enumerate() {
+            if pair[1].0 <= pair[0].0 {
+                return Err(LadderError::NotIncreasing(idx + 1));
+            }
+        }
+
+        Ok(SuccessLadder {
+            bands: bands.into_iter().map(|(threshold, label)| Band {
+                threshold,
+                label: label.to_string(),
+            }).collect(),
+        })
+    }
+
+    /// The Fate ladder, from Terrible (-2) up to Legendary (+8)
+    pub fn fate() -> SuccessLadder {
+        SuccessLadder::new(vec![
+            (-2, "Terrible"),
+            (-1, "Poor"),
+            (0, "Mediocre"),
+            (1, "Average"),
+            (2, "Fair"),
+            (3, "Good"),
+            (4, "Great"),
+            (5, "Superb"),
+            (6, "Fantastic"),
+            (7, "Epic"),
+            (8, "Legendary"),
+        ]).expect("the Fate ladder is always increasing")
+    }
+
+    /// Failure below the DC and success at it or over, for grading a check's margin
+    pub fn pass_fail() -> SuccessLadder {
+        SuccessLadder::new(vec![(-1, "failure"), (0, "success")]).expect("the pass or fail ladder is always increasing")
+    }
+
+    /// The bands of the ladder, from the bottom rung up
+    pub fn bands(&self) -> &Vec<Band> {
+        &self.bands
+    }
+
+    /// Determine which band a value lands in
+    pub fn outcome(&self, margin: i32) -> Outcome {
+        let band_index = self.bands.iter().rposition(|band| margin >= band.threshold).unwrap_or(0);
+        Outcome {
+            label: self.bands[band_index].label.clone(),
+            band_index,
+            margin,
+        }
+    }
+}
+
+#[test]
+fn it_rejects_invalid_ladders() {
+    assert_eq!(SuccessLadder::new(vec![]), Err(LadderError::Empty));
+    assert_eq!(
+        SuccessLadder::new(vec![(0, "failure"), (5, "partial"), (5, "full")]),
+        Err(LadderError::NotIncreasing(2))
+    );
+    assert_eq!(
+        SuccessLadder::new(vec![(10, "full"), (0, "failure")]),
+        Err(LadderError::NotIncreasing(1))
+    );
+}
+
+#[test]
+fn it_can_grade_boundaries() {
+    let ladder = SuccessLadder::new(vec![
+        (-5, "fumble"),
+        (0, "failure"),
+        (4, "partial"),
+        (8, "full"),
+        (12, "critical"),
+    ]).unwrap();
+
+    assert_eq!(ladder.outcome(-20).label, "fumble");
+    assert_eq!(ladder.outcome(-5).label, "fumble");
+    assert_eq!(ladder.outcome(-1).label, "fumble");
+    assert_eq!(ladder.outcome(0).label, "failure");
+    assert_eq!(ladder.outcome(3).label, "failure");
+    assert_eq!(ladder.outcome(4).label, "partial");
+    assert_eq!(ladder.outcome(8).label, "full");
+    assert_eq!(ladder.outcome(12).label, "critical");
+    assert_eq!(ladder.outcome(12).band_index, 4);
+    assert_eq!(ladder.outcome(40).band_index, 4);
+    assert_eq!(ladder.outcome(40).margin, 40);
+}
+
+#[test]
+fn it_can_grade_a_single_band() {
+    let ladder = SuccessLadder::new(vec![(10, "success")]).unwrap();
+    assert_eq!(ladder.outcome(10).band_index, 0);
+    assert_eq!(ladder.outcome(-3).band_index, 0);
+    assert_eq!(ladder.outcome(99).label, "success");
+}
+
+#[test]
+fn it_can_grade_on_the_fate_ladder() {
+    let ladder = SuccessLadder::fate();
+    assert_eq!(ladder.outcome(-4).label, "Terrible");
+    assert_eq!(ladder.outcome(0).label, "Mediocre");
+    assert_eq!(ladder.outcome(3).label, "Good");
+    assert_eq!(ladder.outcome(9).label, "Legendary");
+}
+
+#[test]
+fn it_can_grade_a_roll_against_a_dc() {
+    use roll::{Roll, RollFlags};
+
+    let ladder = SuccessLadder::new(vec![(0, "failure"), (1, "success"), (5, "critical")]).unwrap();
+
+    let mut flags = RollFlags::new();
+    flags.modifiers = vec![15];
+    let mut roll = Roll::new(flags);
+
+    assert_eq!(roll.grade(&ladder, Some(10)).label, "critical");
+    assert_eq!(roll.grade(&ladder, Some(15)).label, "failure");
+    assert_eq!(roll.grade(&ladder, None).margin, 15);
+    assert_eq!(roll.outcome.as_ref().unwrap().band_index, 2);
+}
@@ -0,0 +1,261 @@
+use die::DieType;
+use roll::{ExplodeSuccessPolicy, Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use serde_json;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// Where a layer of options came from, from the lowest precedence to the highest
+///
+/// A request wins over the room's house rules, the same as `HouseRules::apply`, and a GM's one-off
+/// override wins over both.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Provenance {
+    HouseRule,
+    Request,
+    Override,
+}
+
+/// How a d20 was rolled once every source of advantage and disadvantage was counted
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum AdvantageOutcome {
+    Advantage,
+    Disadvantage,
+
+    /// Neither, or both, which cancel out however many sources each has
+    Straight,
+}
+
+/// Options for a roll from one source, merged with the other layers by `ResolvedOptions::merge`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OptionLayer {
+    pub provenance: Provenance,
+
+    /// Sources of advantage in the layer, e.g. 2 for being hidden and helped
+    #[serde(default)]
+    pub advantage: u8,
+
+    /// Sources of disadvantage in the layer
+    #[serde(default)]
+    pub disadvantage: u8,
+
+    /// Explode the dice on their highest face, or stop them exploding, none to leave it to the
+    /// other layers
+    #[serde(default)]
+    pub explode: Option<bool>,
+}
+
+/// A layer that set a boolean option, and what it set it to
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LayerChoice {
+    pub provenance: Provenance,
+    pub value: bool,
+}
+
+/// The options every layer came to together, kept on the roll so players can see where they came
+/// from
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ResolvedOptions {
+    pub advantage: AdvantageOutcome,
+
+    /// The layer of each source of advantage, in the order the layers were given
+    pub advantage_sources: Vec<Provenance>,
+
+    /// The layer of each source of disadvantage, in the order the layers were given
+    pub disadvantage_sources: Vec<Provenance>,
+
+    /// The layer whose choice to explode was used, none when no layer made one
+    pub explode: Option<LayerChoice>,
+
+    /// If the options were rolled with, false when they didn't fit the flags, e.g. advantage on 3d6
+    pub is_applied: bool,
+}
+
+impl OptionLayer {
+    pub fn new(provenance: Provenance) -> OptionLayer {
+        OptionLayer {
+            provenance,
+            advantage: 0,
+            disadvantage: 0,
+            explode: None,
+        }
+    }
+
+    /// Add a source of advantage
+    pub fn advantage(mut self) -> OptionLayer {
+        self.advantage = self.advantage.saturating_add(1);
+        self
+    }
+
+    /// Add a source of disadvantage
+    pub fn disadvantage(mut self) -> OptionLayer {
+        self.disadvantage = self.disadvantage.saturating_add(1);
+        self
+    }
+
+    pub fn explode(mut self, explode: bool) -> OptionLayer {
+        self.explode = Some(explode);
+        self
+    }
+}
+
+impl ResolvedOptions {
+    /// Merge the layers, in any order
+    ///
+    /// Sources of advantage and disadvantage are counted from every layer, and any advantage with
+    /// any disadvantage is a straight roll, as in 5e. An override only cancels advantage by adding
+    /// disadvantage, the same as any other layer. Other options are taken from the layer with the
+    /// highest precedence that sets them, the last such layer given when two have the same.
+    pub fn merge(layers: &[OptionLayer]) -> ResolvedOptions {
+        let sources = |count: fn(&OptionLayer) -> u8| -> Vec<Provenance> {
+            layers.iter()
+                .flat_map(|layer| (0..count(layer)).map(move |_| layer.provenance))
+                .collect()
+        };
+        let advantage_sources = sources(|layer| layer.advantage);
+        let disadvantage_sources = sources(|layer| layer.disadvantage);
+        let advantage = match (advantage_sources.is_empty(), disadvantage_sources.is_empty()) {
+            (false, true) => AdvantageOutcome::Advantage,
+            (true, false) => AdvantageOutcome::Disadvantage,
+            _ => AdvantageOutcome::Straight,
+        };
+
+        let explode = layers.iter()
+            .filter_map(|layer| layer.explode.map(|value| LayerChoice {
+                provenance: layer.provenance,
+                value,
+            }))
+            .fold(None, |chosen: Option<LayerChoice>, choice| match chosen {
+                Some(ref chosen) if chosen.provenance > choice.provenance => Some(chosen.clone()),
+                _ => Some(choice),
+            });
+
+        ResolvedOptions {
+            advantage,
+            advantage_sources,
+            disadvantage_sources,
+            explode,
+            is_applied: false,
+        }
+    }
+
+    /// Change the flags to roll with the options, noting if they were changed
+    ///
+    /// Advantage rolls a single d20 twice keeping the highest, and disadvantage the lowest. The
+    /// options are left out when the flags would end up invalid or when advantage is asked of
+    /// anything other than a single d20.
+    pub fn apply(&mut self, flags: &mut RollFlags) {
+        let mut changed = flags.clone();
+        let is_single_d20 = flags.n == 1 && flags.die == DieType::D20 && flags.kh == 0 && flags.kl == 0;
+        match self.advantage {
+            AdvantageOutcome::Straight => {},
+            _ if !is_single_d20 => return,
+            AdvantageOutcome::Advantage => {
+                changed.n = 2;
+                changed.kh = 1;
+            },
+            AdvantageOutcome::Disadvantage => {
+                changed.n = 2;
+                changed.kl = 1;
+            },
+        }
+
+        match self.explode {
+            Some(LayerChoice { value: true, .. }) if !changed.explode => {
+                changed.explode = true;
+                changed.explode_op = None;
+                changed.explode_policy = ExplodeSuccessPolicy::CompareEachDie;
+            },
+            Some(LayerChoice { value: false, .. }) => changed.explode = false,
+            _ => {},
+        }
+
+        if changed.validate().is_ok() {
+            *flags = changed;
+            self.is_applied = true;
+        }
+    }
+}
+
+/// Merge the layers, roll the flags with them and keep how they were merged on the roll
+pub fn roll_with_layers(mut flags: RollFlags, layers: &[OptionLayer], roller: &mut Roller) -> Roll {
+    let mut options = ResolvedOptions::merge(layers);
+    options.apply(&mut flags);
+    let mut roll = Roll::with_roller(flags, roller);
+    roll.options = Some(options);
+    roll
+}
+
+#[cfg(test)]
+fn d20() -> RollFlags {
+    RollFlags::builder().n(1).die(DieType::D20).build().unwrap()
+}
+
+#[test]
+fn it_cancels_advantage_with_any_disadvantage() {
+    let request = OptionLayer::new(Provenance::Request).advantage().advantage();
+    let rules = OptionLayer::new(Provenance::HouseRule).disadvantage();
+    let options = ResolvedOptions::merge(&[request.clone(), rules]);
+    assert_eq!(options.advantage, AdvantageOutcome::Straight);
+    assert_eq!(options.advantage_sources, vec![Provenance::Request, Provenance::Request]);
+    assert_eq!(options.disadvantage_sources, vec![Provenance::HouseRule]);
+
+    let roll = roll_with_layers(d20(), &[request.clone(), OptionLayer::new(Provenance::HouseRule).disadvantage()],
+                                &mut ScriptedRoller::new(vec![12]));
+    assert_eq!((roll.dice.len(), roll.value), (1, 12));
+
+    let roll = roll_with_layers(d20(), &[request], &mut ScriptedRoller::new(vec![4, 15]));
+    assert_eq!((roll.dice.len(), roll.value), (2, 15));
+    let roll = roll_with_layers(d20(), &[OptionLayer::new(Provenance::Override).disadvantage()],
+                                &mut ScriptedRoller::new(vec![4, 15]));
+    assert_eq!(roll.value, 4);
+
+    // Advantage only fits a single d20
+    let mut options = ResolvedOptions::merge(&[OptionLayer::new(Provenance::Request).advantage()]);
+    let mut flags = RollFlags::builder().n(3).die(DieType::D6).build().unwrap();
+    options.apply(&mut flags);
+    assert!(!options.is_applied);
+    assert_eq!(flags.n, 3);
+}
+
+#[test]
+fn it_lets_an_override_beat_the_request() {
+    let layers = vec![
+        OptionLayer::new(Provenance::Override).explode(false),
+        OptionLayer::new(Provenance::Request).explode(true),
+        OptionLayer::new(Provenance::HouseRule).explode(true),
+    ];
+    let options = ResolvedOptions::merge(&layers);
+    assert_eq!(options.explode, Some(LayerChoice {
+        provenance: Provenance::Override,
+        value: false,
+    }));
+
+    // Without the override the request wins over the house rule
+    let options = ResolvedOptions::merge(&[OptionLayer::new(Provenance::Request).explode(false),
+                                           OptionLayer::new(Provenance::HouseRule).explode(true)]);
+    assert_eq!(options.explode.map(|choice| (choice.provenance, choice.value)), Some((Provenance::Request, false)));
+
+    let mut flags = RollFlags::builder().n(2).die(DieType::D6).build().unwrap();
+    flags.explode = true;
+    let roll = roll_with_layers(flags, &layers, &mut ScriptedRoller::new(vec![6, 3]));
+    assert_eq!((roll.dice.len(), roll.value), (2, 9));
+}
+
+#[test]
+fn it_records_the_layers_on_the_roll() {
+    let layers = vec![
+        OptionLayer::new(Provenance::HouseRule).advantage(),
+        OptionLayer::new(Provenance::Override).explode(true),
+    ];
+    let roll = roll_with_layers(d20(), &layers, &mut ScriptedRoller::new(vec![9, 17]));
+    let json = serde_json::to_value(&roll).unwrap();
+    assert_eq!(json["options"]["advantage"], "Advantage");
+    assert_eq!(json["options"]["advantage_sources"], json!(["HouseRule"]));
+    assert_eq!(json["options"]["explode"], json!({"provenance": "Override", "value": true}));
+    assert_eq!(json["options"]["is_applied"], true);
+
+    let read: Roll = serde_json::from_value(json).unwrap();
+    assert_eq!(read.options, roll.options);
+}
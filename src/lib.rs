@@ -0,0 +1,319 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "server", feature(plugin))]
+#![cfg_attr(feature = "server", plugin(rocket_codegen))]
+
+//! Roll RPG dice, as a library or as the HTTP API behind Power VTT
+//!
+//! Without the default `std` feature only `eval` is built, for rolling on devices with no
+//! operating system, see `eval::Expression`. The HTTP API is the default `server` feature, so
+//! `--no-default-features --features std` builds the library without Rocket, e.g. for WASM.
+
+#[macro_use] extern crate serde_derive;
+#[cfg(feature = "std")]
+#[macro_use] extern crate serde_json;
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate chrono;
+#[cfg(feature = "beacon")]
+extern crate drand_verify;
+#[cfg(feature = "receipts")]
+extern crate ed25519_dalek;
+#[cfg(feature = "std")]
+extern crate hmac;
+#[cfg(feature = "std")]
+#[macro_use] extern crate lazy_static;
+#[cfg(feature = "export-parquet")]
+extern crate parquet;
+#[cfg(feature = "std")]
+extern crate rand;
+extern crate rand_core;
+#[cfg(feature = "storage-redis")]
+extern crate redis;
+#[cfg(feature = "server")]
+extern crate rocket;
+#[cfg(feature = "server")]
+extern crate rocket_contrib;
+#[cfg(feature = "tls")]
+extern crate rustls;
+extern crate serde;
+#[cfg(feature = "std")]
+extern crate sha2;
+#[cfg(feature = "async")]
+extern crate tokio;
+#[cfg(feature = "tracing")]
+#[macro_use] extern crate tracing;
+#[cfg(all(test, feature = "tracing"))]
+extern crate tracing_subscriber;
+#[cfg(feature = "std")]
+extern crate ttml;
+#[cfg(feature = "std")]
+extern crate uuid;
+#[cfg(feature = "tls")]
+extern crate webpki_roots;
+
+#[cfg(feature = "std")]
+pub mod die;
+/// Rolls made on blocking threads for async servers, with limits on how many run at once
+#[cfg(feature = "async")]
+pub mod async_roller;
+/// Dice seeded from a public randomness beacon, so not even the server's operator can bias them
+#[cfg(feature = "beacon")]
+pub mod beacon;
+#[cfg(feature = "std")]
+pub mod blades;
+#[cfg(feature = "std")]
+pub mod burning_wheel;
+#[cfg(feature = "server")]
+pub mod capabilities;
+/// Typed requests to the HTTP API, waiting out its rate limit
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod cors;
+#[cfg(feature = "std")]
+pub mod count;
+#[cfg(feature = "std")]
+pub mod damage;
+#[cfg(feature = "std")]
+pub mod deck;
+#[cfg(feature = "std")]
+pub mod dialect;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod escalation;
+/// Dice rolled with any random number generator, with no standard library
+#[cfg(feature = "core-nostd")]
+pub mod eval;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod expression;
+#[cfg(feature = "std")]
+pub mod fairness;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod generator;
+#[cfg(feature = "std")]
+pub mod health;
+#[cfg(feature = "std")]
+pub mod hero;
+#[cfg(feature = "std")]
+pub mod history;
+#[cfg(feature = "std")]
+pub mod http;
+#[cfg(feature = "std")]
+pub mod house_rules;
+#[cfg(feature = "std")]
+pub mod integrity;
+#[cfg(feature = "std")]
+pub mod interop;
+#[cfg(feature = "std")]
+pub mod keywords;
+#[cfg(feature = "std")]
+pub mod ladder;
+#[cfg(feature = "std")]
+pub mod layers;
+#[cfg(feature = "std")]
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod locale;
+#[cfg(feature = "std")]
+pub mod luck;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod ndjson;
+#[cfg(feature = "std")]
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod partition;
+#[cfg(feature = "std")]
+pub mod pbta;
+#[cfg(feature = "std")]
+pub mod percentile;
+#[cfg(feature = "std")]
+pub mod pf2;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod presets;
+#[cfg(feature = "std")]
+pub mod rate_limit;
+/// Signed exports of rolls that can't be edited after they're handed out
+#[cfg(feature = "receipts")]
+pub mod receipt;
+/// Room history and rate limits shared between servers through Redis
+#[cfg(feature = "storage-redis")]
+pub mod redis_store;
+#[cfg(feature = "std")]
+pub mod request;
+#[cfg(feature = "std")]
+pub mod roll;
+#[cfg(feature = "std")]
+pub mod roller;
+#[cfg(feature = "std")]
+pub mod rounding;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod sotdl;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod suggest;
+#[cfg(feature = "std")]
+pub mod table;
+#[cfg(feature = "std")]
+pub mod text_table;
+/// Helpers for deterministic tests of code that rolls dice
+#[cfg(all(feature = "std", any(test, feature = "test-util")))]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod timing;
+#[cfg(feature = "std")]
+pub mod tower;
+#[cfg(feature = "std")]
+pub mod trigger;
+#[cfg(feature = "server")]
+pub mod v1;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "std")]
+pub mod webhook;
+
+/// The types needed for most rolls
+#[cfg(feature = "std")]
+pub mod prelude {
+    pub use die::{Die, DieType, FateFaces};
+    pub use limits::Limits;
+    pub use parser::parse;
+    pub use roll::{ExplodeSuccessPolicy, Roll, RollError, RollFlags};
+    pub use roller::{CancellationToken, RollOptions, Roller};
+    pub use rounding::Rounding;
+    pub use {roll, roll_seeded};
+}
+
+#[cfg(feature = "std")]
+use limits::Limits;
+#[cfg(feature = "std")]
+use roll::{Roll, RollError, RollFlags};
+#[cfg(feature = "std")]
+use roller::{DefaultRoller, Roller};
+#[cfg(feature = "std")]
+use std::sync::{Arc, RwLock};
+
+// Made the first time it's needed, with lazy_static as the nightly Rocket builds on has no OnceLock
+#[cfg(feature = "std")]
+lazy_static! {
+    static ref DEFAULT_ROLLER: RwLock<Arc<DefaultRoller>> = RwLock::new(Arc::new(DefaultRoller::new()));
+}
+
+/// Roll dice from TTML notation, the quickest way to get a roll
+///
+/// The roll is checked against the default limits, so notation can come straight from users. It's
+/// rolled with `default_roller()`, which can be swapped for a seeded one.
+///
+/// ```
+/// let roll = roll_api::roll("3d6+2").unwrap();
+/// assert!(roll.value >= 5 && roll.value <= 20);
+///
+/// assert!(roll_api::roll("1000d6").is_err());
+/// ```
+#[cfg(feature = "std")]
+pub fn roll(notation: &str) -> Result<Roll, RollError> {
+    default_roller().roll(notation)
+}
+
+/// Roll dice from TTML notation with a seed, the same seed always rolls the same dice
+///
+/// ```
+/// let first = roll_api::roll_seeded("4d6kh3", 42).unwrap();
+/// let again = roll_api::roll_seeded("4d6kh3", 42).unwrap();
+/// assert_eq!(first.value, again.value);
+/// ```
+#[cfg(feature = "std")]
+pub fn roll_seeded(notation: &str, seed: u64) -> Result<Roll, RollError> {
+    Ok(Roll::with_roller(checked_flags(notation, &Limits::default())?, &mut Roller::seeded(seed)))
+}
+
+/// The roller `roll` rolls with, made the first time it's needed
+///
+/// Replacing the default doesn't change the roller handed back, so rolls already going with it
+/// finish the way they started.
+#[cfg(feature = "std")]
+pub fn default_roller() -> Arc<DefaultRoller> {
+    DEFAULT_ROLLER.read().unwrap().clone()
+}
+
+/// Roll with this from now on, e.g. a seeded roller for a whole test binary
+///
+/// ```
+/// use roll_api::roller::DefaultRoller;
+///
+/// roll_api::set_default_roller(DefaultRoller::new().seeded(42));
+/// let first = roll_api::roll("4d6kh3").unwrap();
+/// roll_api::set_default_roller(DefaultRoller::new().seeded(42));
+/// let again = roll_api::roll("4d6kh3").unwrap();
+/// assert_eq!(first.value, again.value);
+/// ```
+#[cfg(feature = "std")]
+pub fn set_default_roller(roller: DefaultRoller) {
+    *DEFAULT_ROLLER.write().unwrap() = Arc::new(roller);
+}
+
+/// Go back to rolling with the thread-local generator under the default limits
+#[cfg(feature = "std")]
+pub fn reset_default_roller() {
+    set_default_roller(DefaultRoller::new());
+}
+
+#[cfg(feature = "std")]
+fn checked_flags(notation: &str, limits: &Limits) -> Result<RollFlags, RollError> {
+    let flags = match parser::parse(notation).and_then(|mut rolls| rolls.pop()) {
+        Some(flags) => flags,
+        None => return Err(RollError::InvalidNotation),
+    };
+    flags.validate()?;
+    limits.check(&flags)?;
+    Ok(flags)
+}
+
+/// Build the HTTP API
+#[cfg(feature = "server")]
+pub fn rocket() -> rocket::Rocket {
+    rocket_with(Box::new(roller::ThreadRoller))
+}
+
+/// Build the HTTP API around a roll engine, e.g. a mock in tests
+#[cfg(feature = "server")]
+pub fn rocket_with(roller: Box<dyn roller::DiceRoller>) -> rocket::Rocket {
+    with_metrics(rocket::ignite())
+        .mount("/v1", routes![v1::roll, v1::room_roll, v1::room_roll_hidden, v1::set_room_rules, v1::room_rules, v1::room_reveal, v1::room_roll_by_sequence, v1::room_history, v1::room_history_latest, v1::room_export, v1::room_export_all, v1::stats, v1::combined_stats, v1::validate, v1::register_webhook, v1::webhook_status])
+        .mount("/", routes![health::healthz, health::readyz, capabilities::get_capabilities])
+        .catch(errors![v1::not_found, v1::too_many_requests])
+        .manage(roller)
+        .manage(house_rules::RoomRules::new())
+        .manage(webhook::Webhooks::new(webhook::RetryPolicy::default()))
+        .attach(config::ConfigMiddleware)
+        .attach(cors::CORS)
+}
+
+/// Serve `GET /metrics` and count every roll in it
+#[cfg(feature = "metrics")]
+fn with_metrics(rocket: rocket::Rocket) -> rocket::Rocket {
+    rocket.mount("/", routes![metrics::metrics]).manage(metrics::Metrics::new())
+}
+
+#[cfg(all(feature = "server", not(feature = "metrics")))]
+fn with_metrics(rocket: rocket::Rocket) -> rocket::Rocket {
+    rocket
+}
@@ -0,0 +1,276 @@
+use roll::{ExplodeSuccessPolicy, MAX_EXPLOSIONS, MAX_REROLLS, RollError, RollFlags};
+use ttml::arg::ComparisonArg;
+
+/// Bounds on how big a roll can be, to keep rolls from user input cheap
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Limits {
+    /// Most dice a single roll can start with
+    pub max_dice: i16,
+
+    /// Most dice a single die can add by exploding, rolling always stops here
+    pub max_explosions: usize,
+
+    /// Most rounds of rerolling forever, rolling always stops here
+    pub max_rerolls: usize,
+
+    /// Most steps working out how likely a roll's total was can take, see `stats::exact_steps`
+    ///
+    /// Rolls past it are left without a probability rather than holding up the roll.
+    #[serde(default = "default_max_probability_steps")]
+    pub max_probability_steps: u64,
+
+    /// Most faces a single die can have
+    pub max_sides: i32,
+}
+
+fn default_max_probability_steps() -> u64 {
+    1_000_000
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_dice: 100,
+            max_explosions: MAX_EXPLOSIONS,
+            max_probability_steps: default_max_probability_steps(),
+            max_rerolls: MAX_REROLLS,
+            max_sides: 1000,
+        }
+    }
+}
+
+/// How expensive a roll is to make, compared to the most dice it can start with
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+pub enum CostClass {
+    /// No more dice than a roll can start with
+    Cheap,
+
+    /// Up to ten times as many dice as a roll can start with
+    Moderate,
+
+    Expensive,
+}
+
+/// What a roll could cost, worked out without rolling anything
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CostEstimate {
+    pub class: CostClass,
+
+    /// Fewest dice the roll will roll
+    pub dice_lower: usize,
+
+    /// Most dice the roll can roll, when every reroll and explosion runs to its cap
+    pub dice_upper_bound: usize,
+
+    /// Number of parts in the roll: the dice, each modifier and divisor, and each flag applied
+    pub nodes: usize,
+}
+
+impl Limits {
+    /// Check the flags stay within the limits and can finish rolling
+    pub fn check(&self, flags: &RollFlags) -> Result<(), RollError> {
+        if flags.n > self.max_dice {
+            return Err(RollError::TooManyDice(flags.n, self.max_dice));
+        }
+
+        let sides = match flags.sides {
+            Some(ref sides) => sides.len() as i32,
+            None => flags.max as i32 - flags.min as i32 + 1,
+        };
+        if sides > self.max_sides {
+            return Err(RollError::TooManySides(sides, self.max_sides));
+        }
+        // Dice with nothing to land on can't be rolled, e.g. a lowest side above the highest
+        if flags.n > 0 && sides <= 0 {
+            return Err(match flags.sides {
+                Some(_) => RollError::NoSides,
+                None => RollError::MinAboveMax(flags.min, flags.max),
+            });
+        }
+
+        // Rerolling or exploding forever when every face matches would never stop
+        let faces = match flags.sides {
+            Some(ref sides) => sides.clone(),
+            None => (flags.min as i32..flags.max as i32 + 1).map(|face| face as i16).collect(),
+        };
+        let always = |op: &ComparisonArg, target: i16| {
+            flags.n > 0 && !faces.is_empty() && faces.iter().all(|face| matches(op, *face, target))
+        };
+        if let Some(ref op) = flags.rr_op {
+            if always(op, flags.rr) {
+                return Err(RollError::EndlessReroll);
+            }
+        }
+        if flags.explode {
+            let explodes = match flags.explode_op {
+                Some(ref op) => always(op, flags.explode_on),
+                None => faces.len() == 1,
+            };
+            if explodes {
+                return Err(RollError::EndlessExplosion);
+            }
+
+            // A compounded die adds up every explosion in its own value
+            if flags.explode_policy == ExplodeSuccessPolicy::CompoundThenCompare {
+                let highest = faces.iter().map(|face| (*face as i64).abs()).max().unwrap_or(0);
+                let most = highest.saturating_mul(self.max_explosions as i64 + 1);
+                if flags.n > 0 && most > i16::max_value() as i64 {
+                    return Err(RollError::CompoundOverflow(most));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimate how many dice the flags will roll
+    pub fn estimate(&self, flags: &RollFlags) -> CostEstimate {
+        let n = flags.n.max(0) as usize;
+        let mut dice_upper_bound = n;
+        if flags.ro_op.is_some() {
+            dice_upper_bound += n;
+        }
+        if flags.rr_op.is_some() {
+            dice_upper_bound += n * self.max_rerolls;
+        }
+        if flags.explode {
+            dice_upper_bound += n * self.max_explosions;
+        }
+
+        let applied = [
+            flags.ceiling.is_some(),
+            flags.dh != 0,
+            flags.dl != 0,
+            flags.explode,
+            flags.floor.is_some(),
+            flags.gt != 0,
+            flags.gte != 0,
+            flags.kh != 0,
+            flags.kl != 0,
+            flags.lt != 0,
+            flags.lte != 0,
+            flags.ro_op.is_some(),
+            flags.rr_op.is_some(),
+        ];
+        let nodes = 1 + flags.modifiers.len() + flags.divisors.len() + applied.iter().filter(|&&set| set).count();
+
+        CostEstimate {
+            class: self.cost_class(dice_upper_bound),
+            dice_lower: n,
+            dice_upper_bound,
+            nodes,
+        }
+    }
+
+    /// Class of a roll that can roll up to this many dice
+    pub fn cost_class(&self, dice_upper_bound: usize) -> CostClass {
+        let max_dice = self.max_dice.max(0) as usize;
+        if dice_upper_bound <= max_dice {
+            CostClass::Cheap
+        } else if dice_upper_bound <= max_dice * 10 {
+            CostClass::Moderate
+        } else {
+            CostClass::Expensive
+        }
+    }
+}
+
+/// If a value passes a comparison against a threshold
+pub fn matches(op: &ComparisonArg, value: i16, threshold: i16) -> bool {
+    match op {
+        &ComparisonArg::GreaterThan => value > threshold,
+        &ComparisonArg::GreaterThanOrEqual => value >= threshold,
+        &ComparisonArg::LessThan => value < threshold,
+        &ComparisonArg::LessThanOrEqual => value <= threshold,
+        &ComparisonArg::EqualTo => value == threshold,
+    }
+}
+
+#[cfg(test)]
+fn d6(n: i16) -> RollFlags {
+    let mut flags = RollFlags::new();
+    flags.n = n;
+    flags.max = 6;
+    flags
+}
+
+#[test]
+fn it_allows_rolls_within_limits() {
+    let limits = Limits::default();
+    assert_eq!(limits.check(&d6(100)), Ok(()));
+
+    let mut flags = d6(4);
+    flags.rr_op = Some(ComparisonArg::LessThanOrEqual);
+    flags.rr = 5;
+    assert_eq!(limits.check(&flags), Ok(()));
+}
+
+#[test]
+fn it_rejects_rolls_past_limits() {
+    let limits = Limits::default();
+    assert_eq!(limits.check(&d6(101)), Err(RollError::TooManyDice(101, 100)));
+
+    let mut flags = d6(1);
+    flags.max = 5000;
+    assert_eq!(limits.check(&flags), Err(RollError::TooManySides(5000, 1000)));
+
+    let mut flags = d6(4);
+    flags.rr_op = Some(ComparisonArg::LessThanOrEqual);
+    flags.rr = 6;
+    assert_eq!(limits.check(&flags), Err(RollError::EndlessReroll));
+}
+
+#[test]
+fn it_rejects_dice_without_sides_to_land_on() {
+    let limits = Limits::default();
+    let mut flags = d6(1);
+    flags.min = 30;
+    assert_eq!(limits.check(&flags), Err(RollError::MinAboveMax(30, 6)));
+    flags.min = 7;
+    assert_eq!(limits.check(&flags), Err(RollError::MinAboveMax(7, 6)));
+
+    let mut flags = d6(1);
+    flags.sides = Some(vec![]);
+    assert_eq!(limits.check(&flags), Err(RollError::NoSides));
+
+    // Rolling no dice rolls no sides
+    flags.n = 0;
+    assert_eq!(limits.check(&flags), Ok(()));
+}
+
+#[test]
+fn it_can_estimate_a_plain_pool() {
+    let estimate = Limits::default().estimate(&d6(4));
+    assert_eq!(estimate, CostEstimate {
+        class: CostClass::Cheap,
+        dice_lower: 4,
+        dice_upper_bound: 4,
+        nodes: 1,
+    });
+}
+
+#[test]
+fn it_can_estimate_an_exploding_pool() {
+    let limits = Limits {
+        max_explosions: 10,
+        ..Limits::default()
+    };
+    let mut flags = d6(4);
+    flags.explode = true;
+    flags.modifiers = vec![2];
+    let estimate = limits.estimate(&flags);
+    assert_eq!(estimate.dice_lower, 4);
+    assert_eq!(estimate.dice_upper_bound, 44);
+    assert_eq!(estimate.nodes, 3);
+    assert_eq!(estimate.class, CostClass::Cheap);
+
+    // Rerolling forever adds a round of rerolls for every die
+    flags.rr_op = Some(ComparisonArg::EqualTo);
+    flags.rr = 1;
+    let estimate = limits.estimate(&flags);
+    assert_eq!(estimate.dice_upper_bound, 4 + 40 + 4 * MAX_REROLLS);
+    assert_eq!(estimate.class, CostClass::Moderate);
+
+    let mut flags = d6(100);
+    flags.explode = true;
+    assert_eq!(limits.estimate(&flags).class, CostClass::Expensive);
+}
@@ -0,0 +1,317 @@
+use roll::RollFlags;
+use std::collections::BTreeSet;
+#[cfg(test)]
+use parser::parse_notation;
+
+/// Something in notation that rolls, but almost certainly isn't what was meant
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LintWarning {
+    /// ID of the rule that found it, e.g. `keep-single-die`, which suppresses it
+    pub rule: String,
+
+    pub message: String,
+
+    /// The notation rewritten the way it was probably meant, when that's clear
+    pub suggestion: Option<String>,
+
+    /// Index of the roll it was found in, counting from 0. From `validate_notation` it's the index
+    /// of the term between `+` and `-` signs, counting terms that weren't linted.
+    pub term: usize,
+}
+
+/// A roll and if it's taken away from the rest rather than added, e.g. the `1d4` of `1d20 - 1d4`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignedRoll {
+    pub flags: RollFlags,
+    pub is_subtracted: bool,
+}
+
+impl SignedRoll {
+    pub fn added(flags: RollFlags) -> SignedRoll {
+        SignedRoll {
+            flags,
+            is_subtracted: false,
+        }
+    }
+}
+
+/// Index of each roll a rule found something in, with the rolls rewritten when it can say how
+type Found = Vec<(usize, Option<Vec<SignedRoll>>)>;
+
+/// A check for notation that's probably wrong
+pub struct LintRule {
+    /// Short name of the rule, given to `LintOptions::suppress` to turn it off
+    pub id: &'static str,
+
+    pub message: &'static str,
+
+    check: fn(&[SignedRoll]) -> Found,
+}
+
+/// Every rule notation is linted with
+pub const RULES: &[LintRule] = &[
+    LintRule {
+        id: "keep-single-die",
+        message: "Keeping the highest or lowest of a single die keeps it whatever it rolls",
+        check: keep_single_die,
+    },
+    LintRule {
+        id: "keep-more-than-rolled",
+        message: "Keeping more dice than are rolled keeps every die",
+        check: keep_more_than_rolled,
+    },
+    LintRule {
+        id: "added-d20s",
+        message: "Two d20s added together aren't advantage, which keeps the highest of them",
+        check: added_d20s,
+    },
+    LintRule {
+        id: "unreachable-threshold",
+        message: "No die can land on the success threshold, so every die fails",
+        check: unreachable_threshold,
+    },
+    LintRule {
+        id: "explode-single-face",
+        message: "A die with one face explodes every time it's rolled",
+        check: explode_single_face,
+    },
+];
+
+/// Which lint rules to skip
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct LintOptions {
+    /// IDs of the rules that are turned off
+    pub suppressed: BTreeSet<String>,
+}
+
+impl LintOptions {
+    pub fn new() -> LintOptions {
+        LintOptions::default()
+    }
+
+    /// Turn off the rule with the ID, e.g. `added-d20s` for a game that adds two d20s on purpose
+    pub fn suppress(mut self, rule: &str) -> LintOptions {
+        self.suppressed.insert(rule.to_string());
+        self
+    }
+}
+
+/// Check the rolls of parsed notation against every rule
+pub fn lint(rolls: &[RollFlags]) -> Vec<LintWarning> {
+    lint_with(rolls, &LintOptions::new())
+}
+
+/// Check the rolls of parsed notation against every rule that isn't suppressed, every roll added
+pub fn lint_with(rolls: &[RollFlags], options: &LintOptions) -> Vec<LintWarning> {
+    let added: Vec<SignedRoll> = rolls.iter().cloned().map(SignedRoll::added).collect();
+    lint_signed_with(&added, options)
+}
+
+/// Check rolls that are added or subtracted against every rule that isn't suppressed
+///
+/// Warnings are in the order of the rules, and of the rolls for each rule. Suggestions are
+/// written in canonical form, each roll joined with ` + ` or ` - `.
+pub fn lint_signed_with(rolls: &[SignedRoll], options: &LintOptions) -> Vec<LintWarning> {
+    let mut warnings = vec![];
+    for rule in RULES.iter().filter(|rule| !options.suppressed.contains(rule.id)) {
+        for (term, rewritten) in (rule.check)(rolls) {
+            warnings.push(LintWarning {
+                rule: rule.id.to_string(),
+                message: rule.message.to_string(),
+                suggestion: rewritten.map(|rolls| write(&rolls)),
+                term,
+            });
+        }
+    }
+    warnings
+}
+
+/// The rolls as notation, each with its sign
+fn write(rolls: &[SignedRoll]) -> String {
+    let mut written = String::new();
+    for (i, roll) in rolls.iter().enumerate() {
+        written += match (i, roll.is_subtracted) {
+            (0, false) => "",
+            (0, true) => "-",
+            (_, false) => " + ",
+            (_, true) => " - ",
+        };
+        written += &roll.flags.canonical();
+    }
+    written
+}
+
+/// The rolls with one of them changed
+fn rewrite<F: Fn(&mut RollFlags)>(rolls: &[SignedRoll], index: usize, change: F) -> Vec<SignedRoll> {
+    let mut rewritten = rolls.to_vec();
+    change(&mut rewritten[index].flags);
+    rewritten
+}
+
+/// The lowest and highest faces the roll's dice can land on
+fn face_range(flags: &RollFlags) -> (i16, i16) {
+    match flags.sides {
+        Some(ref sides) if !sides.is_empty() => (*sides.iter().min().unwrap_or(&0), *sides.iter().max().unwrap_or(&0)),
+        _ => (flags.min, flags.max),
+    }
+}
+
+fn keep_single_die(rolls: &[SignedRoll]) -> Found {
+    rolls.iter().map(|roll| &roll.flags).enumerate()
+        .filter(|&(_, flags)| flags.n == 1 && (flags.kh > 0 || flags.kl > 0))
+        .map(|(i, _)| (i, Some(rewrite(rolls, i, |flags| {
+            flags.kh = 0;
+            flags.kl = 0;
+        }))))
+        .collect()
+}
+
+fn keep_more_than_rolled(rolls: &[SignedRoll]) -> Found {
+    rolls.iter().map(|roll| &roll.flags).enumerate()
+        .filter(|&(_, flags)| flags.n > 1 && (flags.kh > flags.n || flags.kl > flags.n))
+        .map(|(i, _)| (i, Some(rewrite(rolls, i, |flags| {
+            flags.kh = 0;
+            flags.kl = 0;
+        }))))
+        .collect()
+}
+
+/// A single d20 with nothing but a number added to it
+fn is_plain_d20(flags: &RollFlags) -> bool {
+    let counts = [flags.kh, flags.kl, flags.gt, flags.gte, flags.lt, flags.lte, flags.dh, flags.dl];
+    flags.n == 1 && flags.sides.is_none() && (flags.min, flags.max) == (1, 20) && counts.iter().all(|&count| count == 0)
+        && !flags.explode && !flags.is_halved && flags.rr_op.is_none() && flags.ro_op.is_none()
+        && flags.divisors.is_empty()
+}
+
+/// Two d20s added together, while a d20 taken away from another is a contest, not advantage
+fn added_d20s(rolls: &[SignedRoll]) -> Found {
+    let plain: Vec<usize> = (0..rolls.len()).filter(|&i| is_plain_d20(&rolls[i].flags)).collect();
+    if plain.iter().any(|&i| rolls[i].is_subtracted) {
+        return vec![];
+    }
+    if plain.len() != 2 {
+        return vec![];
+    }
+
+    let (first, second) = (plain[0], plain[1]);
+    let mut rewritten = rewrite(rolls, first, |flags| {
+        flags.n = 2;
+        flags.kh = 1;
+    });
+    let modifiers = rewritten[second].flags.modifiers.clone();
+    rewritten[first].flags.modifiers.extend(modifiers);
+    rewritten.remove(second);
+    vec![(second, Some(rewritten))]
+}
+
+fn unreachable_threshold(rolls: &[SignedRoll]) -> Found {
+    let mut found = vec![];
+    for (i, flags) in rolls.iter().map(|roll| &roll.flags).enumerate() {
+        let (min, max) = face_range(flags);
+        let (above, below) = (flags.gt >= max || flags.gte > max, flags.lt <= min || flags.lte < min);
+        let rewritten = if (flags.gt != 0 || flags.gte != 0) && above {
+            // A threshold of 0 isn't a threshold at all, so there's nothing to suggest
+            Some(rewrite(rolls, i, |flags| {
+                flags.gt = 0;
+                flags.gte = max;
+            })).filter(|_| max != 0)
+        } else if (flags.lt != 0 || flags.lte != 0) && below {
+            Some(rewrite(rolls, i, |flags| {
+                flags.lt = 0;
+                flags.lte = min;
+            })).filter(|_| min != 0)
+        } else {
+            continue;
+        };
+        found.push((i, rewritten));
+    }
+    found
+}
+
+fn explode_single_face(rolls: &[SignedRoll]) -> Found {
+    rolls.iter().map(|roll| &roll.flags).enumerate()
+        .filter(|&(_, flags)| flags.explode && face_range(flags).0 == face_range(flags).1)
+        .map(|(i, _)| (i, Some(rewrite(rolls, i, |flags| {
+            flags.explode = false;
+            flags.explode_op = None;
+        }))))
+        .collect()
+}
+
+/// The rules that fired on the notation, and their suggestions
+#[cfg(test)]
+fn check(notation: &str) -> Vec<(String, Option<String>)> {
+    lint(&parse_notation(notation).unwrap()).into_iter().map(|warning| (warning.rule, warning.suggestion)).collect()
+}
+
+#[test]
+fn it_warns_about_keeping_the_only_die() {
+    assert_eq!(check("1d20kh1+3"), vec![("keep-single-die".to_string(), Some("1d20+3".to_string()))]);
+    assert_eq!(check("2d20kh3"), vec![("keep-more-than-rolled".to_string(), Some("2d20".to_string()))]);
+    assert_eq!(check("2d20kl1"), vec![]);
+}
+
+#[test]
+fn it_suggests_advantage_for_added_d20s() {
+    let warnings = lint(&parse_notation("1d20+2 + 1d20").unwrap());
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].rule, "added-d20s");
+    assert_eq!(warnings[0].term, 1);
+    assert_eq!(warnings[0].suggestion, Some("2d20kh1+2".to_string()));
+
+    // Adding a d20 to other dice is fine, and so are three d20s
+    assert_eq!(check("1d20 + 1d6"), vec![]);
+    assert_eq!(check("1d20 + 1d20 + 1d20"), vec![]);
+}
+
+#[test]
+fn it_warns_about_thresholds_no_die_reaches() {
+    assert_eq!(check("1d6gte9"), vec![("unreachable-threshold".to_string(), Some("1d6gte6".to_string()))]);
+    assert_eq!(check("8d6gt6"), vec![("unreachable-threshold".to_string(), Some("8d6gte6".to_string()))]);
+    assert_eq!(check("8d6gte6"), vec![]);
+}
+
+#[test]
+fn it_keeps_subtracted_rolls_subtracted() {
+    let roll = |notation: &str, is_subtracted: bool| SignedRoll {
+        flags: parse_notation(notation).unwrap().pop().unwrap(),
+        is_subtracted,
+    };
+    let contest = [roll("1d20", false), roll("1d20", true)];
+    assert_eq!(lint_signed_with(&contest, &LintOptions::new()), vec![]);
+
+    let warnings = lint_signed_with(&[roll("1d20", false), roll("1d4kh1", true)], &LintOptions::new());
+    assert_eq!(warnings.len(), 1);
+    assert_eq!((warnings[0].term, warnings[0].suggestion.clone()), (1, Some("1d20 - 1d4".to_string())));
+}
+
+#[test]
+fn it_warns_about_exploding_a_single_face() {
+    assert_eq!(check("3d1!"), vec![("explode-single-face".to_string(), Some("3d1".to_string()))]);
+    assert_eq!(check("3d6!"), vec![]);
+}
+
+#[test]
+fn it_suggests_notation_that_parses() {
+    for notation in ["1d20kh1", "2d20kh3", "1d20 + 1d20", "1d6gte9", "8d10lt1", "3d1!", "3d[2,2]!"].iter() {
+        let warnings = lint(&parse_notation(notation).unwrap());
+        assert!(!warnings.is_empty(), "{} wasn't linted", notation);
+        for suggestion in warnings.iter().filter_map(|warning| warning.suggestion.as_ref()) {
+            let rolls = parse_notation(suggestion).unwrap();
+            assert_eq!(lint(&rolls), vec![], "{} from {} is linted", suggestion, notation);
+        }
+    }
+}
+
+#[test]
+fn it_leaves_clean_notation_and_suppressed_rules_alone() {
+    for notation in ["1d20+5", "4d6kh3", "2d20kl1", "8d6gte5", "3d10!!>=9+2", "4dF+2", "2d6[fire] + 1d6[cold]"].iter() {
+        assert_eq!(check(notation), vec![], "{} was linted", notation);
+    }
+
+    let rolls = parse_notation("1d20kh1 + 1d20").unwrap();
+    assert_eq!(lint(&rolls).len(), 1);
+    let options = LintOptions::new().suppress("keep-single-die");
+    assert_eq!(lint_with(&rolls, &options), vec![]);
+}
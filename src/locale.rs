@@ -0,0 +1,267 @@
+use die::DieType;
+use stats::RollProbability;
+use std::fmt::{self, Write};
+
+/// Something that happened to a die, said after its value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clause {
+    Dropped,
+    Exploded,
+    Rerolled,
+}
+
+/// A piece of text about a roll, written out by a locale
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// A die's value and what happened to it, e.g. `2 which was dropped`
+    Die {
+        clauses: Vec<Clause>,
+        value: i16,
+    },
+
+    /// The dice that were rolled and each of them already written, e.g. `Rolled two six-sided dice: 4 and 6.`
+    Rolled {
+        count: usize,
+        dice: Vec<String>,
+        die: DieType,
+        sides: i16,
+    },
+
+    /// A d20 set to a face instead of rolled, e.g. `Took 10 instead of rolling.`
+    Took {
+        value: i16,
+    },
+
+    /// The modifiers added to the dice, e.g. `Adding 2.`
+    Modifier(i32),
+
+    /// The final total, and if any dice were dropped to reach it
+    Total {
+        is_after_dropping: bool,
+        value: i32,
+    },
+
+    /// A kept d20 landed on 20
+    CriticalHit,
+
+    /// A kept d20 landed on 1
+    CriticalMiss,
+
+    /// How the total compared to a difficulty
+    Against {
+        difficulty: i32,
+        is_success: bool,
+    },
+
+    /// How likely the total was, e.g. `This was in the top 5% of possible outcomes.`
+    Probability(RollProbability),
+
+    /// Where a participant ranked for luck over a session, and how much to make of it
+    Luck {
+        is_large_enough: bool,
+        is_significant: bool,
+        participant: String,
+        rank: usize,
+        sample_size: u64,
+        z_score: f64,
+    },
+}
+
+/// Writes messages about rolls in a language, so text for people can be translated
+pub trait Locale {
+    fn text(&self, message: &Message) -> String;
+
+    /// Write the text straight to `out`, e.g. a formatter's buffer
+    ///
+    /// By default the text is built and then copied, so locales that can write it bit by bit
+    /// should, to save formatting every roll from allocating.
+    fn write_text(&self, message: &Message, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(&self.text(message))
+    }
+}
+
+/// English, with counts and sides spelled out and lists joined with a serial comma
+pub struct English;
+
+/// A count or number of sides, spelled out up to twenty
+struct Number(usize);
+
+impl Number {
+    fn word(&self) -> Option<&'static str> {
+        const WORDS: [&str; 21] = ["zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+                                   "ten", "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen",
+                                   "seventeen", "eighteen", "nineteen", "twenty"];
+        if self.0 < WORDS.len() {
+            Some(WORDS[self.0])
+        } else if self.0 == 100 {
+            Some("hundred")
+        } else {
+            None
+        }
+    }
+
+    /// If it's said starting with a vowel, so it takes "an", e.g. eight or 80
+    fn sounds_like_a_vowel(&self) -> bool {
+        match self.word() {
+            Some(word) => word.starts_with(|c: char| "aeiou".contains(c)),
+            None => {
+                let mut leading = self.0;
+                while leading >= 10 {
+                    leading /= 10;
+                }
+                leading == 8
+            },
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.word() {
+            Some(word) => f.write_str(word),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl English {
+    fn write_list(items: &[String], out: &mut dyn Write) -> fmt::Result {
+        for (position, item) in items.iter().enumerate() {
+            if position > 0 {
+                out.write_str(match (items.len(), position + 1 == items.len()) {
+                    (2, _) => " and ",
+                    (_, true) => ", and ",
+                    _ => ", ",
+                })?;
+            }
+            out.write_str(item)?;
+        }
+        Ok(())
+    }
+
+    fn ordinal(n: usize) -> String {
+        const WORDS: [&str; 11] = ["zeroth", "first", "second", "third", "fourth", "fifth", "sixth", "seventh",
+                                   "eighth", "ninth", "tenth"];
+        if n < WORDS.len() {
+            return WORDS[n].to_string();
+        }
+        let suffix = match (n % 10, n % 100) {
+            (_, 11) | (_, 12) | (_, 13) => "th",
+            (1, _) => "st",
+            (2, _) => "nd",
+            (3, _) => "rd",
+            _ => "th",
+        };
+        format!("{}{}", n, suffix)
+    }
+
+    fn write_dice(count: usize, die: DieType, sides: i16, out: &mut dyn Write) -> fmt::Result {
+        let sides = Number(sides.max(0) as usize);
+        if count == 1 {
+            let is_an = match die {
+                DieType::Fate | DieType::Other => false,
+                _ => sides.sounds_like_a_vowel(),
+            };
+            out.write_str(if is_an { "an " } else { "a " })?;
+        } else {
+            write!(out, "{} ", Number(count))?;
+        }
+
+        let noun = if count == 1 { "die" } else { "dice" };
+        match die {
+            DieType::Fate => write!(out, "Fate {}", noun),
+            DieType::Other => write!(out, "{} with custom sides", noun),
+            _ => write!(out, "{}-sided {}", sides, noun),
+        }
+    }
+}
+
+impl Locale for English {
+    fn text(&self, message: &Message) -> String {
+        let mut text = String::new();
+        self.write_text(message, &mut text).expect("writing to a string never fails");
+        text
+    }
+
+    fn write_text(&self, message: &Message, out: &mut dyn Write) -> fmt::Result {
+        match message {
+            &Message::Die { ref clauses, value } => {
+                write!(out, "{}", value)?;
+                for (position, clause) in clauses.iter().enumerate() {
+                    out.write_str(if position == 0 { " which " } else { " and " })?;
+                    out.write_str(match clause {
+                        &Clause::Dropped => "was dropped",
+                        &Clause::Exploded => "exploded",
+                        &Clause::Rerolled => "was rerolled",
+                    })?;
+                }
+                Ok(())
+            },
+            &Message::Rolled { count: 0, .. } => out.write_str("Rolled no dice."),
+            &Message::Rolled { count, ref dice, die, sides } => {
+                out.write_str("Rolled ")?;
+                English::write_dice(count, die, sides, out)?;
+                out.write_str(": ")?;
+                English::write_list(dice, out)?;
+                out.write_str(".")
+            },
+            &Message::Took { value } => write!(out, "Took {} instead of rolling.", value),
+            &Message::Modifier(modifier) => {
+                if modifier < 0 {
+                    write!(out, "Subtracting {}.", -modifier)
+                } else {
+                    write!(out, "Adding {}.", modifier)
+                }
+            },
+            &Message::Total { is_after_dropping, value } => {
+                if is_after_dropping {
+                    write!(out, "Total after dropping: {}.", value)
+                } else {
+                    write!(out, "Total: {}.", value)
+                }
+            },
+            &Message::CriticalHit => out.write_str("This was a critical hit."),
+            &Message::CriticalMiss => out.write_str("This was a critical miss."),
+            &Message::Against { difficulty, is_success } => {
+                let outcome = if is_success { "success" } else { "failure" };
+                write!(out, "This was a {} against difficulty {}.", outcome, difficulty)
+            },
+            &Message::Probability(probability) => write!(out, "This was in the {}.", probability),
+            &Message::Luck { is_large_enough, is_significant, ref participant, rank, sample_size, z_score } => {
+                let direction = if z_score < 0.0 { "below" } else { "above" };
+                let caveat = if !is_large_enough {
+                    "That's too few dice to tell."
+                } else if is_significant {
+                    "That's unlikely to be chance."
+                } else {
+                    "That could be chance."
+                };
+                write!(out, "{} ranked {}, rolling {:.1} standard errors {} fair dice over {} {}. {}", participant,
+                       English::ordinal(rank), z_score.abs(), direction, sample_size,
+                       if sample_size == 1 { "die" } else { "dice" }, caveat)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+fn written<F: Fn(&mut dyn Write) -> fmt::Result>(write: F) -> String {
+    let mut text = String::new();
+    write(&mut text).unwrap();
+    text
+}
+
+#[test]
+fn it_spells_out_dice_in_english() {
+    assert_eq!(written(|out| English::write_dice(3, DieType::D6, 6, out)), "three six-sided dice");
+    assert_eq!(written(|out| English::write_dice(1, DieType::D8, 8, out)), "an eight-sided die");
+    assert_eq!(written(|out| English::write_dice(1, DieType::D100, 100, out)), "a hundred-sided die");
+    assert_eq!(written(|out| English::write_dice(2, DieType::Other, 37, out)), "two dice with custom sides");
+    assert_eq!(written(|out| English::write_dice(4, DieType::Fate, 1, out)), "four Fate dice");
+    assert_eq!(written(|out| English::write_dice(30, DieType::D20, 20, out)), "30 twenty-sided dice");
+    assert_eq!(written(|out| English::write_dice(1, DieType::Other, 80, out)), "a die with custom sides");
+    assert_eq!(written(|out| English::write_dice(1, DieType::D100, 80, out)), "an 80-sided die");
+    assert_eq!(written(|out| English::write_list(&["4".to_string(), "6".to_string()], out)), "4 and 6");
+    assert_eq!(written(|out| English::write_list(&["1".to_string(), "4".to_string(), "6".to_string()], out)), "1, 4, and 6");
+    assert_eq!((English::ordinal(2), English::ordinal(12), English::ordinal(22)), ("second".to_string(), "12th".to_string(), "22nd".to_string()));
+}
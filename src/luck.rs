@@ -0,0 +1,213 @@
+use die::DieType;
+use stats::{DieStats, SessionStats};
+use std::cmp::Ordering;
+#[cfg(test)]
+use parser::parse;
+#[cfg(test)]
+use roll::Roll;
+#[cfg(test)]
+use roller::Roller;
+
+/// Fewest dice a z-score is trusted for, below it the normal approximation it relies on is rough
+pub const MIN_SAMPLE_SIZE: u64 = 30;
+
+/// How far from 0 a z-score has to be to be significant at 95%, either way
+pub const Z_95: f64 = 1.96;
+
+/// How one type of die landed for a participant, against how it lands on average
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DieLuck {
+    pub die: DieType,
+
+    /// Average value of a fair die, e.g. 10.5 for a d20 and 0 for a Fate die
+    pub expected_mean: f64,
+
+    /// How often a fair die lands on its highest face, and on its lowest
+    pub expected_nat_max_rate: f64,
+    pub expected_nat_min_rate: f64,
+
+    /// If there were at least `MIN_SAMPLE_SIZE` dice, without which the z-score means little
+    pub is_large_enough: bool,
+
+    /// If the sample was large enough and the z-score at least `Z_95` from 0
+    pub is_significant: bool,
+
+    pub nat_max_rate: f64,
+    pub nat_min_rate: f64,
+    pub observed_mean: f64,
+
+    /// Number of dice counted
+    pub sample_size: u64,
+
+    /// Standard errors the observed mean is above the expected mean, below it when negative
+    pub z_score: f64,
+}
+
+/// How lucky a participant was over every type of die they rolled
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ParticipantLuck {
+    /// Each type of die, in the order they were first rolled
+    pub dice: Vec<DieLuck>,
+
+    /// If there were at least `MIN_SAMPLE_SIZE` dice and the z-score is at least `Z_95` from 0
+    pub is_significant: bool,
+
+    pub participant: String,
+
+    /// Where the participant ranks, 1 for the luckiest
+    pub rank: usize,
+
+    /// Number of dice counted, of every type
+    pub sample_size: u64,
+
+    /// The z-scores of each type of die combined, each type counting the same
+    pub z_score: f64,
+}
+
+/// Who rolled better than the dice should, luckiest first
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LuckReport {
+    pub participants: Vec<ParticipantLuck>,
+}
+
+impl LuckReport {
+    pub fn luckiest(&self) -> Option<&ParticipantLuck> {
+        self.participants.first()
+    }
+
+    pub fn unluckiest(&self) -> Option<&ParticipantLuck> {
+        self.participants.last()
+    }
+}
+
+/// The mean, variance and number of faces of a fair die of the type, none for custom sides
+fn expectation(die: DieType) -> Option<(f64, f64, f64)> {
+    let (min, max) = (die.min_face(), die.max_face());
+    if max <= min {
+        return None;
+    }
+    let faces = (max - min + 1) as f64;
+    Some(((min + max) as f64 / 2.0, (faces * faces - 1.0) / 12.0, faces))
+}
+
+fn die_luck(stats: &DieStats) -> Option<DieLuck> {
+    let (mean, variance, faces) = expectation(stats.die)?;
+    if stats.count == 0 {
+        return None;
+    }
+    let n = stats.count as f64;
+    let observed_mean = stats.sum as f64 / n;
+    let z_score = (observed_mean - mean) / (variance / n).sqrt();
+    let is_large_enough = stats.count >= MIN_SAMPLE_SIZE;
+    Some(DieLuck {
+        die: stats.die,
+        expected_mean: mean,
+        expected_nat_max_rate: 1.0 / faces,
+        expected_nat_min_rate: 1.0 / faces,
+        is_large_enough,
+        is_significant: is_large_enough && z_score.abs() >= Z_95,
+        nat_max_rate: stats.nat_max as f64 / n,
+        nat_min_rate: stats.nat_min as f64 / n,
+        observed_mean,
+        sample_size: stats.count,
+        z_score,
+    })
+}
+
+/// Rank every participant by how far above what the dice should have rolled they rolled
+///
+/// Each type of die is compared with a fair one: its observed mean against the expected mean as a
+/// z-score, and how often it landed on its highest and lowest faces. A participant's z-score
+/// combines those of each type of die they rolled. Dice with custom sides have nothing to compare
+/// with and are left out, as are participants who only rolled those. Dice are counted as the
+/// stats counted them, so leave dropped dice out of the stats to leave them out here.
+pub fn compare_participants(stats: &SessionStats) -> LuckReport {
+    let mut participants = vec![];
+    for participant in stats.participants() {
+        let report = match stats.report_for(&participant) {
+            Some(report) => report,
+            None => continue,
+        };
+        let dice: Vec<DieLuck> = report.dice.iter().filter_map(die_luck).collect();
+        if dice.is_empty() {
+            continue;
+        }
+
+        // Stouffer's method, which keeps the combined z-score standard normal
+        let z_score = dice.iter().map(|die| die.z_score).sum::<f64>() / (dice.len() as f64).sqrt();
+        let sample_size = dice.iter().map(|die| die.sample_size).sum();
+        participants.push(ParticipantLuck {
+            dice,
+            is_significant: sample_size >= MIN_SAMPLE_SIZE && z_score.abs() >= Z_95,
+            participant,
+            rank: 0,
+            sample_size,
+            z_score,
+        });
+    }
+
+    participants.sort_by(|a, b| b.z_score.partial_cmp(&a.z_score).unwrap_or(Ordering::Equal)
+        .then_with(|| a.participant.cmp(&b.participant)));
+    for (index, participant) in participants.iter_mut().enumerate() {
+        participant.rank = index + 1;
+    }
+    LuckReport {
+        participants,
+    }
+}
+
+#[cfg(test)]
+fn record(stats: &mut SessionStats, participant: &str, notation: &str, values: Vec<i16>) {
+    let flags = parse(notation).unwrap().pop().unwrap();
+    stats.record_for(participant, &Roll::with_roller(flags, &mut Roller::scripted(values)));
+}
+
+#[test]
+fn it_compares_d20s_with_a_fair_die() {
+    let mut stats = SessionStats::new(false);
+    record(&mut stats, "ana", "4d20", vec![20, 20, 1, 15]);
+    let report = compare_participants(&stats);
+
+    let d20 = &report.participants[0].dice[0];
+    assert_eq!((d20.die, d20.sample_size), (DieType::D20, 4));
+    assert!((d20.expected_mean - 10.5).abs() < 1e-9);
+    assert!((d20.observed_mean - 14.0).abs() < 1e-9);
+    // A d20's variance is (20² - 1) / 12, so the standard error of 4 dice is √(33.25 / 4)
+    assert!((d20.z_score - 3.5 / (33.25f64 / 4.0).sqrt()).abs() < 1e-9);
+    assert!((d20.nat_max_rate - 0.5).abs() < 1e-9 && (d20.nat_min_rate - 0.25).abs() < 1e-9);
+    assert!((d20.expected_nat_max_rate - 0.05).abs() < 1e-9);
+}
+
+#[test]
+fn it_compares_fate_dice_with_a_fair_die() {
+    let mut stats = SessionStats::new(false);
+    record(&mut stats, "bo", "4dF", vec![1, 1, 1, 0]);
+    let fate = &compare_participants(&stats).participants[0].dice[0];
+    assert_eq!(fate.die, DieType::Fate);
+    assert!(fate.expected_mean.abs() < 1e-9);
+    assert!((fate.expected_nat_min_rate - 1.0 / 3.0).abs() < 1e-9);
+    // Three equally likely faces of -1, 0 and 1 have a variance of 2/3
+    assert!((fate.z_score - 0.75 / (2.0f64 / 3.0 / 4.0).sqrt()).abs() < 1e-9);
+}
+
+#[test]
+fn it_flags_a_tiny_sample_as_not_significant() {
+    let mut stats = SessionStats::new(false);
+    record(&mut stats, "cy", "2d20", vec![20, 20]);
+    record(&mut stats, "di", "1d20", vec![10]);
+    let mut values = vec![1; 20];
+    values.extend(vec![2; 20]);
+    record(&mut stats, "ed", "40d20", values);
+    let report = compare_participants(&stats);
+
+    // Two natural 20s are over 2 standard errors above a fair d20, but too few to go by
+    let cy = &report.participants[0];
+    assert_eq!((cy.participant.as_str(), cy.rank), ("cy", 1));
+    assert!(cy.z_score > Z_95);
+    assert!(!cy.dice[0].is_large_enough && !cy.is_significant);
+
+    let ed = report.unluckiest().unwrap();
+    assert_eq!((ed.participant.as_str(), ed.rank, ed.sample_size), ("ed", 3, 40));
+    assert!(ed.is_significant && ed.dice[0].is_significant);
+    assert_eq!(report.participants[1].participant, "di");
+}
@@ -0,0 +1,186 @@
+use error::ErrorCode;
+#[cfg(feature = "server")]
+use rocket::State;
+use roll::Roll;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Counter of rolls asked for, labelled with `outcome`: `ok`, or the error code in lower case,
+/// e.g. `parse_error` or `limit_exceeded`
+pub const ROLLS_TOTAL: &str = "roll_api_rolls_total";
+
+/// Histogram of the seconds taken to parse and roll a request, failed or not
+pub const ROLL_DURATION_SECONDS: &str = "roll_api_roll_duration_seconds";
+
+/// Histogram of the dice in each roll made, counting rerolls and explosions
+pub const DICE_PER_ROLL: &str = "roll_api_dice_per_roll";
+
+/// Counter of values drawn from rollers, see `Roll::rng_draws`
+pub const RNG_DRAWS_TOTAL: &str = "roll_api_rng_draws_total";
+
+/// Gauge of rooms that have had a roll made in them since the server started
+pub const ACTIVE_ROOMS: &str = "roll_api_active_rooms";
+
+/// Upper bounds of the duration buckets, in seconds
+const DURATION_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+/// Upper bounds of the dice buckets
+const DICE_BUCKETS: [f64; 8] = [1.0, 2.0, 5.0, 10.0, 20.0, 100.0, 1000.0, 10000.0];
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn write(&self, name: &str, help: &str, out: &mut String) {
+        writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name).unwrap();
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count).unwrap();
+        writeln!(out, "{}_sum {}\n{}_count {}", name, self.sum, name, self.count).unwrap();
+    }
+}
+
+struct Registry {
+    dice: Histogram,
+    durations: Histogram,
+    outcomes: BTreeMap<String, u64>,
+    rng_draws: u64,
+    rooms: HashSet<String>,
+}
+
+/// Counts of what the server has rolled, written out for Prometheus at `GET /metrics`
+///
+/// Managed by the server when it's built with the `metrics` feature.
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            registry: Mutex::new(Registry {
+                dice: Histogram::new(&DICE_BUCKETS),
+                durations: Histogram::new(&DURATION_BUCKETS),
+                outcomes: BTreeMap::new(),
+                rng_draws: 0,
+                rooms: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Count a request to roll, with the roll when it was made or why it wasn't
+    pub fn record(&self, result: Result<&Roll, ErrorCode>, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        let outcome = match result {
+            Ok(roll) => {
+                registry.dice.observe(roll.dice.len() as f64);
+                registry.rng_draws += roll.rng_draws as u64;
+                "ok".to_string()
+            },
+            Err(code) => code.as_str().to_lowercase(),
+        };
+        *registry.outcomes.entry(outcome).or_insert(0) += 1;
+        registry.durations.observe(elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9);
+    }
+
+    /// Count a room as active
+    pub fn record_room(&self, room: &str) {
+        let mut registry = self.registry.lock().unwrap();
+        if !registry.rooms.contains(room) {
+            registry.rooms.insert(room.to_string());
+        }
+    }
+
+    /// Every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+        writeln!(out, "# HELP {} Rolls asked for, by outcome\n# TYPE {} counter", ROLLS_TOTAL, ROLLS_TOTAL).unwrap();
+        for (outcome, count) in &registry.outcomes {
+            writeln!(out, "{}{{outcome=\"{}\"}} {}", ROLLS_TOTAL, outcome, count).unwrap();
+        }
+        registry.durations.write(ROLL_DURATION_SECONDS, "Seconds taken to parse and roll a request", &mut out);
+        registry.dice.write(DICE_PER_ROLL, "Dice in each roll made", &mut out);
+        writeln!(out, "# HELP {} Values drawn from rollers\n# TYPE {} counter\n{} {}", RNG_DRAWS_TOTAL, RNG_DRAWS_TOTAL,
+                 RNG_DRAWS_TOTAL, registry.rng_draws).unwrap();
+        writeln!(out, "# HELP {} Rooms rolled in since the server started\n# TYPE {} gauge\n{} {}", ACTIVE_ROOMS,
+                 ACTIVE_ROOMS, ACTIVE_ROOMS, registry.rooms.len()).unwrap();
+        out
+    }
+}
+
+#[cfg(feature = "server")]
+#[get("/metrics")]
+pub fn metrics(metrics: State<Metrics>) -> String {
+    metrics.render()
+}
+
+#[test]
+fn it_writes_every_metric() {
+    let metrics = Metrics::new();
+    let roll = Roll::from_values("2d6!", &[6, 3, 2]).unwrap();
+    metrics.record(Ok(&roll), Duration::from_millis(2));
+    metrics.record(Err(ErrorCode::ParseError), Duration::from_millis(20));
+    metrics.record_room("tavern");
+    metrics.record_room("tavern");
+
+    let text = metrics.render();
+    assert!(text.contains("roll_api_rolls_total{outcome=\"ok\"} 1\n"));
+    assert!(text.contains("roll_api_rolls_total{outcome=\"parse_error\"} 1\n"));
+    assert!(text.contains("roll_api_roll_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+    assert!(text.contains("roll_api_roll_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+    assert!(text.contains("roll_api_dice_per_roll_bucket{le=\"2\"} 0\n"));
+    assert!(text.contains("roll_api_dice_per_roll_bucket{le=\"5\"} 1\n"));
+    assert!(text.contains("roll_api_dice_per_roll_sum 3\n"));
+    assert!(text.contains("roll_api_active_rooms 1\n"));
+}
+
+#[cfg(all(test, feature = "metrics"))]
+#[test]
+fn it_serves_metrics_after_rolling() {
+    use rocket::http::{ContentType, Status};
+    use rocket::local::Client;
+
+    let client = Client::new(::rocket()).expect("the API should launch");
+    client.get("/v1/1d20").header(ContentType::JSON).dispatch();
+    client.get("/v1/rooms/tavern/alice/2d6").header(ContentType::JSON).dispatch();
+    client.get("/v1/4dQ").header(ContentType::JSON).dispatch();
+    client.get("/v1/100d6rr1").header(ContentType::JSON).dispatch();
+
+    let mut response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let text = response.body_string().unwrap();
+    assert!(text.contains("roll_api_rolls_total{outcome=\"ok\"} 2\n"));
+    assert!(text.contains("roll_api_rolls_total{outcome=\"parse_error\"} 1\n"));
+    assert!(text.contains("roll_api_rolls_total{outcome=\"limit_exceeded\"} 1\n"));
+    assert!(text.contains("roll_api_roll_duration_seconds_count 4\n"));
+    assert!(text.contains("roll_api_dice_per_roll_count 2\n"));
+    assert!(text.contains("roll_api_rng_draws_total 3\n"));
+    assert!(text.contains("roll_api_active_rooms 1\n"));
+}
@@ -0,0 +1,169 @@
+use chrono::DateTime;
+use chrono::prelude::Utc;
+use history::RollStream;
+use roll::Roll;
+use serde_json;
+use std::io::{self, Read};
+#[cfg(test)]
+use history::{HistoryError, HistoryPage, HistoryQuery, HiddenRollReceipt, RollLog, RollStore};
+#[cfg(test)]
+use std::io::{BufRead, BufReader};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A roll cut down to what's needed to show it, for exports where size matters more than detail
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CompactRoll {
+    /// The kept dice as they landed, in the order they were rolled
+    pub dice: Vec<i16>,
+
+    pub equation: String,
+    pub id: String,
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub value: i32,
+}
+
+impl<'a> From<&'a Roll> for CompactRoll {
+    fn from(roll: &Roll) -> CompactRoll {
+        CompactRoll {
+            dice: roll.dice.iter().filter(|die| !die.is_dropped).map(|die| die.value).collect(),
+            equation: roll.equation.clone(),
+            id: roll.id.clone(),
+            sequence: roll.sequence,
+            timestamp: roll.timestamp,
+            value: roll.value,
+        }
+    }
+}
+
+/// Rolls written as newline-delimited JSON, one roll a line, as the reader is read
+///
+/// A roll is only taken from the stream once the lines before it have been read, so a slow
+/// reader slows reading the store and a reader that's dropped stops it. A failure to read the store
+/// is returned as an I/O error, after the lines before it.
+pub struct NdjsonReader<'a> {
+    compact: bool,
+    line: Vec<u8>,
+    position: usize,
+    rolls: RollStream<'a>,
+}
+
+impl<'a> NdjsonReader<'a> {
+    /// Write each roll in full, or as a `CompactRoll` when compact
+    pub fn new(rolls: RollStream<'a>, compact: bool) -> NdjsonReader<'a> {
+        NdjsonReader {
+            compact,
+            line: vec![],
+            position: 0,
+            rolls,
+        }
+    }
+
+    fn next_line(&mut self) -> io::Result<bool> {
+        let roll = match self.rolls.next() {
+            Some(Ok(roll)) => roll,
+            Some(Err(error)) => return Err(io::Error::new(io::ErrorKind::Other, format!("{:?}", error))),
+            None => return Ok(false),
+        };
+
+        self.line.clear();
+        self.position = 0;
+        let written = if self.compact {
+            serde_json::to_writer(&mut self.line, &CompactRoll::from(&roll))
+        } else {
+            serde_json::to_writer(&mut self.line, &roll)
+        };
+        written.map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        self.line.push(b'\n');
+        Ok(true)
+    }
+}
+
+impl<'a> Read for NdjsonReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.line.len() && !self.next_line()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.line.len() - self.position);
+        buf[..n].copy_from_slice(&self.line[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// A store that counts the pages read from it
+#[cfg(test)]
+struct CountingStore {
+    log: RollLog,
+    pages: AtomicUsize,
+}
+
+#[cfg(test)]
+impl RollStore for CountingStore {
+    fn record(&self, room: &str, participant: &str, roll: Roll) -> Result<u64, HistoryError> {
+        RollStore::record(&self.log, room, participant, roll)
+    }
+
+    fn record_hidden(&self, room: &str, participant: &str, roll: Roll) -> Result<HiddenRollReceipt, HistoryError> {
+        RollStore::record_hidden(&self.log, room, participant, roll)
+    }
+
+    fn reveal(&self, room: &str, id: &str) -> Result<Roll, HistoryError> {
+        self.log.reveal(room, id)
+    }
+
+    fn page(&self, room: &str, query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+        self.pages.fetch_add(1, Ordering::SeqCst);
+        self.log.page(room, query)
+    }
+
+    fn by_sequence(&self, room: &str, sequence: u64) -> Result<Roll, HistoryError> {
+        self.log.by_sequence(room, sequence)
+    }
+}
+
+#[test]
+fn it_writes_a_roll_a_line() {
+    let log = RollLog::new();
+    for value in 1..1001 {
+        log.record("tavern", "alice", Roll::from_values("2d20kh1", &[value % 20 + 1, 1]).unwrap());
+    }
+    let store: &dyn RollStore = &log;
+
+    let lines: Vec<String> = BufReader::new(NdjsonReader::new(store.stream("tavern", &HistoryQuery::default()), false))
+        .lines()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(lines.len(), 1000);
+    let newest: Roll = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!((newest.sequence, newest.dice.len()), (1000, 2));
+
+    let mut compact = String::new();
+    NdjsonReader::new(store.stream("tavern", &HistoryQuery::default()), true).read_to_string(&mut compact).unwrap();
+    let oldest: CompactRoll = serde_json::from_str(compact.lines().last().unwrap()).unwrap();
+    assert_eq!((oldest.sequence, oldest.dice, oldest.value), (1, vec![2], 2));
+    assert!(compact.len() < lines.iter().map(|line| line.len()).sum::<usize>() / 4);
+}
+
+#[test]
+fn it_stops_reading_the_store_when_dropped() {
+    let store = CountingStore {
+        log: RollLog::new(),
+        pages: AtomicUsize::new(0),
+    };
+    for _ in 0..1000 {
+        store.log.record("tavern", "alice", Roll::from_values("1d20", &[10]).unwrap());
+    }
+    let dyn_store: &dyn RollStore = &store;
+
+    let mut reader = BufReader::new(NdjsonReader::new(dyn_store.stream("tavern", &HistoryQuery::default()), false));
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    drop(reader);
+    assert_eq!(store.pages.load(Ordering::SeqCst), 1);
+
+    // A failure ends the lines with an error
+    let mut reader = NdjsonReader::new(dyn_store.stream("cellar", &HistoryQuery::default()), false);
+    assert!(reader.read_to_string(&mut String::new()).is_err());
+}
@@ -0,0 +1,1231 @@
+// Notation comes straight from users, so nothing in parsing may panic on it. Tests can unwrap.
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+
+use dialect::{translate, Dialect};
+use die::DieType;
+use keywords::KeywordPack;
+use roll::{comparison_str, copy_comparison, ExplodeSuccessPolicy, RollFlags};
+use rounding::Rounding;
+use std::sync::Arc;
+use ttml::arg::{Arg, ArgValue, ComparisonArg, RollArg};
+use ttml::parser::parse_step_p;
+#[cfg(test)]
+use limits::Limits;
+#[cfg(test)]
+use rand::{Rng, SeedableRng, XorShiftRng};
+#[cfg(test)]
+use request::RollRequest;
+#[cfg(test)]
+use roll::{group_notation, Roll, RollFlagsBuilder};
+#[cfg(test)]
+use roller::Roller;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// Sides Fate dice written as `dF` are parsed as
+const FATE_SIDES: &str = "[-1,0,1]";
+
+/// Most characters notation can have, past which it isn't parsed at all
+pub const MAX_NOTATION_LENGTH: usize = 1000;
+
+/// Most expressions a block of notation can have, see `parse_many`
+pub const MAX_EXPRESSIONS: usize = 100;
+
+/// A modifier notation can be written with, see `MODIFIERS`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModifierSpec {
+    /// How the modifier is written in standard notation, without its number
+    pub token: &'static str,
+
+    pub description: &'static str,
+
+    /// Standard notation using it
+    pub example: &'static str,
+}
+
+/// Every modifier parsing understands, in standard notation, for listing what can be written
+///
+/// Tokens are how they show up in a roll's equation once parsed, so adding a modifier to the
+/// parser without adding it here fails `capabilities`' tests.
+pub const MODIFIERS: &[ModifierSpec] = &[
+    ModifierSpec { token: "d", description: "Roll dice, e.g. three six-sided dice", example: "3d6" },
+    ModifierSpec { token: "dF", description: "Roll Fate dice, four when no number is given", example: "4dF" },
+    ModifierSpec { token: "hd", description: "Roll a half die, counting half its face rounded up", example: "1hd6" },
+    ModifierSpec { token: "[]", description: "Tag the dice, or give them custom sides after the d", example: "2d6[fire]" },
+    ModifierSpec { token: "kh", description: "Keep the highest dice", example: "4d6kh3" },
+    ModifierSpec { token: "kl", description: "Keep the lowest dice", example: "2d20kl1" },
+    ModifierSpec { token: "dh", description: "Drop the highest dice", example: "5d20dh1" },
+    ModifierSpec { token: "dl", description: "Drop the lowest dice", example: "4d6dl1" },
+    ModifierSpec { token: "gt", description: "Count dice over the number as successes", example: "8d6gt4" },
+    ModifierSpec { token: "gte", description: "Count dice landing on the number or over as successes", example: "8d6gte5" },
+    ModifierSpec { token: "lt", description: "Count dice under the number as successes", example: "8d10lt3" },
+    ModifierSpec { token: "lte", description: "Count dice landing on the number or under as successes", example: "8d10lte2" },
+    ModifierSpec { token: "rr", description: "Reroll dice matching the comparison until they don't", example: "2d6rr1" },
+    ModifierSpec { token: "ro", description: "Reroll dice matching the comparison once", example: "2d20ro1" },
+    ModifierSpec { token: "min", description: "Lowest face of the dice", example: "1d20min10" },
+    ModifierSpec { token: "max", description: "Highest face of the dice", example: "1d20max15" },
+    ModifierSpec { token: "floor", description: "Lowest the roll's total can be", example: "1d20floor5" },
+    ModifierSpec { token: "ceiling", description: "Highest the roll's total can be", example: "1d20ceiling15" },
+    ModifierSpec { token: "!", description: "Explode dice on their highest face, or on the comparison", example: "1d10!>8" },
+    ModifierSpec { token: "!!", description: "Compound exploding dice into one", example: "3d10!!>=9" },
+    ModifierSpec { token: "div", description: "Divide the total, rounding down", example: "8d6div2" },
+    ModifierSpec { token: "ceil", description: "Round a divided total up", example: "8d6div2ceil" },
+    ModifierSpec { token: "halfup", description: "Round a divided total to the nearest, halves up", example: "8d6div2halfup" },
+    ModifierSpec { token: "trunc", description: "Round a divided total towards zero", example: "1d6-3div2trunc" },
+    ModifierSpec { token: "nr", description: "Land every die on a different face", example: "6d49nr" },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// The command isn't valid TTML
+    InvalidNotation,
+
+    /// Strict parsing found a character that looks like an ASCII one, along with the ASCII one
+    LookalikeCharacter(char, char),
+
+    /// The notation has more characters (first) than can be parsed (second)
+    TooLong(usize, usize),
+
+    /// The block has more expressions than the most it can have, which is given
+    TooManyExpressions(usize),
+
+    /// The modifier is written another dialect's way, or means something else in this one
+    UnsupportedInDialect(&'static str, Dialect),
+
+    /// The flag can't be used on Fate dice
+    UnsupportedModifier(&'static str),
+
+    /// An expression of a block, starting on a line (first) at a column (second), couldn't be parsed
+    AtLine(usize, usize, Box<ParseError>),
+}
+
+/// One expression of a block of notation, with its label and where it was written
+#[derive(Clone)]
+pub struct LabeledExpression {
+    /// Character of the line the notation starts at, counting from 1
+    pub column: usize,
+
+    /// Written before a colon ahead of the notation, e.g. `Attack` for `Attack: 1d20+7`
+    pub label: Option<String>,
+
+    /// Line of the block the expression is on, counting from 1
+    pub line: usize,
+
+    /// The notation as it was written, without its label
+    pub notation: String,
+
+    pub rolls: Vec<RollFlags>,
+}
+
+/// How notation is read before it's parsed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParseOptions {
+    /// Whose notation to read, standard notation by default
+    pub dialect: Dialect,
+
+    /// Reject lookalike characters instead of reading them as their ASCII equivalents
+    pub is_strict: bool,
+
+    /// Modifiers written in another language, read before the dialect
+    pub keywords: Option<Arc<KeywordPack>>,
+}
+
+impl ParseOptions {
+    pub fn new() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    /// Read notation written for a virtual tabletop, e.g. Roll20's `4d6k3`
+    pub fn dialect(mut self, dialect: Dialect) -> ParseOptions {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Reject lookalike characters, e.g. full-width digits
+    pub fn strict(mut self) -> ParseOptions {
+        self.is_strict = true;
+        self
+    }
+
+    /// Read the pack's aliases as the modifiers they're for, e.g. French players' `4d6gm3`
+    pub fn keywords(mut self, pack: Arc<KeywordPack>) -> ParseOptions {
+        self.keywords = Some(pack);
+        self
+    }
+}
+
+/// Parse a roll command as if it was passed through TTML, returning the flags of every roll in it
+pub fn parse(command: &str) -> Option<Vec<RollFlags>> {
+    parse_notation(command).ok()
+}
+
+/// Parse a roll command, saying why it couldn't be parsed
+///
+/// Fate dice can be written `4dF` or `4df`, with a bare `dF` rolling the usual four dice. The
+/// highest and lowest dice can be dropped together with `dh` and `dl`, e.g. `5d20dh1dl1`. Dice
+/// explode with `!`, or compound with `!!`, on their highest face or on a comparison like `!>8`.
+/// Terms can be tagged, e.g. `2d6[fire] + 1d6[cold]` or `1d8[fire,magic]`, and every die they
+/// roll carries their tags.
+///
+/// Lookalike characters pasted from chat apps are read as ASCII, e.g. `３ｄ６＋２` as `3d6+2`, `×`
+/// as `*` and `−` or `–` as `-`. Comments are kept as they were written, though smart quotes
+/// around them are read as `"`.
+pub fn parse_notation(command: &str) -> Result<Vec<RollFlags>, ParseError> {
+    parse_notation_with(command, &ParseOptions::new())
+}
+
+/// Parse a roll command with options, e.g. to reject lookalike characters
+///
+/// When any lookalikes were read as ASCII, or a dialect's modifiers were written the standard
+/// way, each roll's flags keep the command as it was parsed.
+///
+/// Notation longer than `MAX_NOTATION_LENGTH` characters isn't parsed, which keeps the time spent on
+/// any one command bounded. Numbers too big for a roll are read as the biggest that fits.
+pub fn parse_notation_with(command: &str, options: &ParseOptions) -> Result<Vec<RollFlags>, ParseError> {
+    let mut normalized = normalize_notation(command, options)?;
+    if let Some(ref pack) = options.keywords {
+        normalized = pack.expand(&normalized);
+    }
+    let normalized = translate(&normalized, options.dialect)?;
+    let was_normalized = normalized != command;
+
+    // TTML has no Fate dice, so they're rolled as custom sides and marked as Fate afterwards
+    let (command, has_fate) = expand_fate(&expand_half_dice(&normalized));
+
+    // TTML has no drop or explode modifiers or tags either, so they're taken out and set on their
+    // roll afterwards
+    let (command, modifiers) = strip_modifiers(&command);
+    let mut rolls = parse_ttml(&command).ok_or(ParseError::InvalidNotation)?;
+    for stripped in modifiers {
+        let last = rolls.len().saturating_sub(1);
+        let flags = &mut rolls[stripped.roll.min(last)];
+        match stripped.modifier {
+            Modifier::Ceiling(n) => {
+                flags.ceiling = Some(n);
+                flags.equation = flags.equation.clone() + "ceiling" + &n.to_string();
+            },
+            Modifier::Divide(n, rounding) => {
+                flags.divisors.push(n);
+                flags.equation = flags.equation.clone() + "div" + &n.to_string();
+                if let Some(rounding) = rounding {
+                    flags.rounding = rounding;
+                    flags.equation += rounding.name();
+                }
+            },
+            Modifier::DropHigh(n) => {
+                flags.dh = flags.dh.saturating_add(n);
+                flags.equation = flags.equation.clone() + "dh" + &n.to_string();
+            },
+            Modifier::DropLow(n) => {
+                flags.dl = flags.dl.saturating_add(n);
+                flags.equation = flags.equation.clone() + "dl" + &n.to_string();
+            },
+            Modifier::Explode(policy, trigger) => {
+                flags.explode = true;
+                flags.explode_policy = policy;
+                flags.equation = flags.equation.clone() + "!";
+                if policy == ExplodeSuccessPolicy::CompoundThenCompare {
+                    flags.equation = flags.equation.clone() + "!";
+                }
+                if let Some((op, target)) = trigger {
+                    flags.equation = flags.equation.clone() + comparison_str(&op) + &target.to_string();
+                    flags.explode_op = Some(op);
+                    flags.explode_on = target;
+                }
+            },
+            Modifier::Floor(n) => {
+                flags.floor = Some(n);
+                flags.equation = flags.equation.clone() + "floor" + &n.to_string();
+            },
+            Modifier::Halve => {
+                flags.is_halved = true;
+                flags.equation = flags.equation.replacen("d", "hd", 1);
+            },
+            Modifier::NoRepeats => {
+                flags.without_replacement = true;
+                flags.equation += "nr";
+            },
+            Modifier::Tags(tags) => {
+                flags.equation = flags.equation.clone() + "[" + &tags.join(",") + "]";
+                for tag in tags {
+                    if !flags.tags.contains(&tag) {
+                        flags.tags.push(tag);
+                    }
+                }
+            },
+        }
+    }
+
+    if has_fate {
+        for flags in rolls.iter_mut().filter(|flags| flags.equation.contains(FATE_SIDES)) {
+            let unsupported = [("kh", flags.kh != 0), ("kl", flags.kl != 0), ("gt", flags.gt != 0),
+                               ("gte", flags.gte != 0), ("lt", flags.lt != 0), ("lte", flags.lte != 0),
+                               ("dh", flags.dh != 0), ("dl", flags.dl != 0), ("explode", flags.explode)];
+            if let Some(&(name, _)) = unsupported.iter().find(|&&(_, is_set)| is_set) {
+                return Err(ParseError::UnsupportedModifier(name));
+            }
+            flags.die = DieType::Fate;
+            flags.equation = flags.equation.replace(FATE_SIDES, "dF");
+        }
+    }
+
+    if was_normalized {
+        for flags in rolls.iter_mut() {
+            flags.normalized = Some(normalized.clone());
+        }
+    }
+    Ok(rolls)
+}
+
+/// Parse a block of notation, e.g. a macro pasted from a virtual tabletop, into its expressions in order
+///
+/// Expressions are split by lines, and by semicolons outside of brackets and quotes, and each can
+/// start with a label, e.g. `Attack: 1d20+7; Damage: 2d6+4`. Blank lines and lines starting with
+/// `#` or `//` are skipped. An expression that doesn't parse fails the block with
+/// `ParseError::AtLine`, saying where the expression starts, and a block with more than
+/// `MAX_EXPRESSIONS` fails with `ParseError::TooManyExpressions` before the rest are parsed.
+pub fn parse_many(input: &str) -> Result<Vec<LabeledExpression>, ParseError> {
+    let mut expressions = vec![];
+    for (index, line) in input.lines().enumerate() {
+        let text = line.trim_start();
+        if text.starts_with('#') || text.starts_with("//") {
+            continue;
+        }
+
+        for (start, expression) in split_expressions(line) {
+            if expression.trim().is_empty() {
+                continue;
+            }
+            if expressions.len() == MAX_EXPRESSIONS {
+                return Err(ParseError::TooManyExpressions(MAX_EXPRESSIONS));
+            }
+            let (label, offset) = split_label(expression);
+            let notation = expression[offset..].trim();
+            let indent = expression[offset..].len() - expression[offset..].trim_start().len();
+            let column = line[..start + offset + indent].chars().count() + 1;
+            let rolls = parse_notation(notation).map_err(|error| ParseError::AtLine(index + 1, column, Box::new(error)))?;
+            expressions.push(LabeledExpression {
+                column,
+                label,
+                line: index + 1,
+                notation: notation.to_string(),
+                rolls,
+            });
+        }
+    }
+    Ok(expressions)
+}
+
+/// Each expression of a line and the byte it starts at, split by semicolons outside of brackets and comments
+fn split_expressions(line: &str) -> Vec<(usize, &str)> {
+    let mut expressions = vec![];
+    let mut depth = 0usize;
+    let mut comment_quote = None;
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        // Comments are closed the way `normalize` reads them
+        match comment_quote {
+            Some(opening) => {
+                if c == '"' || (opening != '"' && is_quote(c)) {
+                    comment_quote = None;
+                }
+                continue;
+            },
+            None if is_quote(c) => {
+                comment_quote = Some(c);
+                continue;
+            },
+            None => {},
+        }
+
+        match ascii_for(c).unwrap_or(c) {
+            '[' | '(' | '{' => depth += 1,
+            ']' | ')' | '}' => depth = depth.saturating_sub(1),
+            ';' if depth == 0 => {
+                expressions.push((start, &line[start..i]));
+                start = i + c.len_utf8();
+            },
+            _ => {},
+        }
+    }
+    expressions.push((start, &line[start..]));
+    expressions
+}
+
+/// The label of an expression, and the byte its notation starts at
+///
+/// Only a colon ahead of any bracket or comment ends a label, so one in a comment doesn't.
+fn split_label(expression: &str) -> (Option<String>, usize) {
+    let end = expression.find(|c: char| is_quote(c) || "[({".contains(ascii_for(c).unwrap_or(c)))
+        .unwrap_or(expression.len());
+    match expression[..end].find(':') {
+        Some(colon) if !expression[..colon].trim().is_empty() => {
+            (Some(expression[..colon].trim().to_string()), colon + 1)
+        },
+        _ => (None, 0),
+    }
+}
+
+/// If the character opens or closes a comment, a plain quote or one that looks like it
+fn is_quote(c: char) -> bool {
+    c == '"' || ascii_for(c) == Some('"')
+}
+
+/// The ASCII character a lookalike is read as, e.g. `3` for `３` or `*` for `×`
+fn ascii_for(c: char) -> Option<char> {
+    // Full-width forms are laid out in the same order as ASCII
+    let code = c as u32;
+    if code >= 0xff01 && code <= 0xff5e {
+        return ::std::char::from_u32(code - 0xfee0);
+    }
+
+    match c {
+        '\u{3000}' => Some(' '),
+        '×' | '✕' | '∗' => Some('*'),
+        '−' | '‐' | '‑' | '–' | '—' => Some('-'),
+        '“' | '”' | '„' => Some('"'),
+        _ => None,
+    }
+}
+
+/// Read lookalike characters as ASCII the way parsing does, e.g. to show what was understood
+pub fn normalize_notation(command: &str, options: &ParseOptions) -> Result<String, ParseError> {
+    let length = command.chars().count();
+    if length > MAX_NOTATION_LENGTH {
+        return Err(ParseError::TooLong(length, MAX_NOTATION_LENGTH));
+    }
+    normalize(command, options.is_strict)
+}
+
+/// Read lookalike characters as ASCII, leaving what's inside comments as it was written
+fn normalize(command: &str, is_strict: bool) -> Result<String, ParseError> {
+    let mut normalized = String::with_capacity(command.len());
+    let mut comment_quote = None;
+    for c in command.chars() {
+        let ascii = ascii_for(c);
+        let is_quote = c == '"' || ascii == Some('"');
+        match comment_quote {
+            // A comment opened with a plain quote can have smart quotes inside it
+            Some(opening) => {
+                if c != '"' && (opening == '"' || !is_quote) {
+                    normalized.push(c);
+                    continue;
+                }
+                comment_quote = None;
+            },
+            None if is_quote => comment_quote = Some(c),
+            None => {},
+        }
+
+        match ascii {
+            Some(ascii) if is_strict => return Err(ParseError::LookalikeCharacter(c, ascii)),
+            Some(ascii) => normalized.push(ascii),
+            None => normalized.push(c),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Write Fate dice as custom sides TTML understands, e.g. `dF+1` becomes `4d[-1,0,1]+1`
+fn expand_fate(command: &str) -> (String, bool) {
+    let chars: Vec<char> = command.chars().collect();
+    let mut expanded = String::new();
+    let mut has_fate = false;
+    let mut in_comment = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' {
+            in_comment = !in_comment;
+        }
+
+        let is_fate = !in_comment && c == 'd' &&
+            chars.get(i + 1).map_or(false, |next| *next == 'F' || *next == 'f');
+        if is_fate {
+            if i == 0 || !chars[i - 1].is_digit(10) {
+                expanded.push('4');
+            }
+            expanded = expanded + "d" + FATE_SIDES;
+            has_fate = true;
+            i += 2;
+        } else {
+            expanded.push(c);
+            i += 1;
+        }
+    }
+    (expanded, has_fate)
+}
+
+/// Write Hero System half dice as a term of their own, e.g. `3.5d6` becomes `3d6 + 1hd6`
+fn expand_half_dice(command: &str) -> String {
+    let chars: Vec<char> = command.chars().collect();
+    let mut expanded = String::new();
+    let mut in_comment = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' {
+            in_comment = !in_comment;
+        }
+
+        let is_half = !in_comment && c == '.' && chars.get(i + 1) == Some(&'5') && chars.get(i + 2) == Some(&'d');
+        if !is_half {
+            expanded.push(c);
+            i += 1;
+            continue;
+        }
+
+        let sides: String = chars[i + 3..].iter().take_while(|next| next.is_digit(10)).collect();
+        let whole_digits = chars[..i].iter().rev().take_while(|prev| prev.is_digit(10)).count();
+        let whole: String = chars[i - whole_digits..i].iter().collect();
+        if whole.chars().all(|digit| digit == '0') {
+            // Half a die on its own, e.g. `0.5d6` or `.5d6`
+            let kept = expanded.chars().count() - whole_digits;
+            expanded = expanded.chars().take(kept).collect();
+            expanded = expanded + "1hd" + &sides;
+        } else {
+            expanded = expanded + "d" + &sides + " + 1hd" + &sides;
+        }
+        i += 3 + sides.len();
+    }
+    expanded
+}
+
+/// A modifier TTML doesn't have, taken out of a command
+enum Modifier {
+    Ceiling(i16),
+
+    /// Divide the total, with how to round it when that's written after the divisor
+    Divide(i16, Option<Rounding>),
+    DropHigh(i16),
+    DropLow(i16),
+    Explode(ExplodeSuccessPolicy, Option<(ComparisonArg, i16)>),
+    Floor(i16),
+
+    /// The roll's dice are half dice, e.g. `1hd6`
+    Halve,
+
+    /// The roll's dice land on different faces, e.g. `6d49nr`
+    NoRepeats,
+    Tags(Vec<String>),
+}
+
+struct Stripped {
+    modifier: Modifier,
+
+    /// Index of the roll the modifier follows
+    roll: usize,
+}
+
+/// Take the modifiers TTML doesn't have out of a command, e.g. `5d20dh1dl1` becomes `5d20`
+///
+/// The command is read once, counting the rolls as it goes: TTML starts a new roll after an
+/// operator written on its own, e.g. the `+` of `2d6 + 1d6`, while `1d20+2` is a single roll.
+fn strip_modifiers(command: &str) -> (String, Vec<Stripped>) {
+    let chars: Vec<char> = command.chars().collect();
+    let mut stripped = String::new();
+    let mut modifiers = vec![];
+    let mut in_comment = false;
+    let mut roll = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '"' {
+            in_comment = !in_comment;
+        }
+
+        let is_spaced = |at: usize| chars.get(at).map_or(false, |next| next.is_whitespace());
+        if !in_comment && "+-*/".contains(c) && i > 0 && is_spaced(i - 1) && is_spaced(i + 1) {
+            roll += 1;
+        }
+
+        // The `h` of a half die comes before its dice, so the dice are kept and the roll they make
+        // is halved, e.g. `1hd6` becomes `1d6`
+        let is_half = !in_comment && c == 'h' && i > 0 && chars[i - 1].is_digit(10) && chars.get(i + 1) == Some(&'d');
+        if is_half {
+            let sides = chars[i + 2..].iter().take_while(|next| next.is_digit(10)).count();
+            stripped.extend(&chars[i + 1..i + 2 + sides]);
+            modifiers.push(Stripped { modifier: Modifier::Halve, roll });
+            i += 2 + sides;
+            continue;
+        }
+
+        let modifier = if in_comment {
+            None
+        } else if c == 'd' {
+            strip_drop(&chars[i..]).or_else(|| strip_divide(&chars[i..]))
+        } else if c == 'n' && chars.get(i + 1) == Some(&'r') && !chars.get(i + 2).map_or(false, |next| next.is_alphabetic()) {
+            Some((Modifier::NoRepeats, 2))
+        } else if c == '!' {
+            Some(strip_explode(&chars[i..]))
+        } else if c == 'f' || c == 'c' {
+            strip_bound(&chars[i..])
+        } else if c == '[' && (i == 0 || chars[i - 1] != 'd') {
+            strip_tags(&chars[i..])
+        } else {
+            None
+        };
+        match modifier {
+            Some((modifier, len)) => {
+                modifiers.push(Stripped { modifier, roll });
+                i += len;
+            },
+            None => {
+                stripped.push(c);
+                i += 1;
+            },
+        }
+    }
+    (stripped, modifiers)
+}
+
+/// Read a drop modifier like `dh1` from the start of the characters, along with its length
+fn strip_drop(chars: &[char]) -> Option<(Modifier, usize)> {
+    let kind = chars.get(1).cloned();
+    let digits = chars.iter().skip(2).take_while(|next| next.is_digit(10)).count();
+    if (kind != Some('h') && kind != Some('l')) || digits == 0 {
+        return None;
+    }
+
+    let n: String = chars[2..2 + digits].iter().collect();
+    let n = n.parse().unwrap_or(i16::max_value());
+    let modifier = if kind == Some('h') { Modifier::DropHigh(n) } else { Modifier::DropLow(n) };
+    Some((modifier, 2 + digits))
+}
+
+/// Read a divisor like `div2` or `div2ceil` from the start of the characters, along with its
+/// length
+///
+/// A rounding other than the default of rounding down is written straight after the divisor, and
+/// `floor` and `ceiling` bounds that follow are left for `strip_bound`.
+fn strip_divide(chars: &[char]) -> Option<(Modifier, usize)> {
+    if chars.get(1) != Some(&'i') || chars.get(2) != Some(&'v') {
+        return None;
+    }
+    let number: String = chars[3..].iter().enumerate()
+        .take_while(|&(j, next)| next.is_digit(10) || (j == 0 && *next == '-'))
+        .map(|(_, next)| *next)
+        .collect();
+    let n = saturate(number.parse::<i64>().ok()?);
+    let len = 3 + number.len();
+
+    let rest: String = chars[len..].iter().take(7).collect();
+    let roundings = [("ceiling", None), ("ceil", Some(Rounding::Ceil)), ("halfup", Some(Rounding::HalfUp)),
+                     ("trunc", Some(Rounding::Truncate))];
+    match roundings.iter().find(|&&(name, _)| rest.starts_with(name)) {
+        Some(&(name, Some(rounding))) => Some((Modifier::Divide(n, Some(rounding)), len + name.len())),
+        _ => Some((Modifier::Divide(n, None), len)),
+    }
+}
+
+/// Read tags like `[fire]` or `[fire,magic]` from the start of the characters, along with their
+/// length
+///
+/// Custom sides follow a `d` and are only numbers, so brackets anywhere else with a letter in them
+/// are tags.
+fn strip_tags(chars: &[char]) -> Option<(Modifier, usize)> {
+    let close = chars.iter().position(|c| *c == ']')?;
+    let inside: String = chars[1..close].iter().collect();
+    if !inside.chars().any(|c| c.is_alphabetic()) {
+        return None;
+    }
+
+    let tags: Vec<String> = inside.split(',').map(|tag| tag.trim().to_string()).collect();
+    let is_valid = |tag: &String| !tag.is_empty() && tag.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+    if !tags.iter().all(is_valid) {
+        return None;
+    }
+    Some((Modifier::Tags(tags), close + 1))
+}
+
+/// Read an explosion like `!`, `!!` or `!>=9` from the start of the characters, along with its
+/// length
+///
+/// `!=10` and `!==10` both explode on a 10.
+fn strip_explode(chars: &[char]) -> (Modifier, usize) {
+    let mut len = 1;
+    let mut policy = ExplodeSuccessPolicy::CompareEachDie;
+    if chars.get(1) == Some(&'!') {
+        policy = ExplodeSuccessPolicy::CompoundThenCompare;
+        len += 1;
+    }
+
+    let rest: String = chars[len..].iter().collect();
+    let ops = [(">=", ComparisonArg::GreaterThanOrEqual), ("<=", ComparisonArg::LessThanOrEqual),
+               ("==", ComparisonArg::EqualTo), (">", ComparisonArg::GreaterThan),
+               ("<", ComparisonArg::LessThan), ("=", ComparisonArg::EqualTo)];
+    for &(ref text, ref op) in ops.iter() {
+        if !rest.starts_with(text) {
+            continue;
+        }
+
+        let number: String = rest[text.len()..].chars().enumerate()
+            .take_while(|&(j, next)| next.is_digit(10) || (j == 0 && next == '-'))
+            .map(|(_, next)| next)
+            .collect();
+        if let Ok(target) = number.parse::<i16>() {
+            let trigger = (copy_comparison(op), target);
+            return (Modifier::Explode(policy, Some(trigger)), len + text.len() + number.len());
+        }
+        break;
+    }
+    (Modifier::Explode(policy, None), len)
+}
+
+/// Read a bound on the total like `floor5` or `ceiling-2` from the start of the characters, along
+/// with its length
+fn strip_bound(chars: &[char]) -> Option<(Modifier, usize)> {
+    let rest: String = chars.iter().take(8).collect();
+    let name = ["floor", "ceiling"].iter().find(|name| rest.starts_with(*name))?;
+    let number: String = chars[name.len()..].iter().enumerate()
+        .take_while(|&(j, next)| next.is_digit(10) || (j == 0 && *next == '-'))
+        .map(|(_, next)| *next)
+        .collect();
+    let n = saturate(number.parse::<i64>().ok()?);
+    let modifier = if *name == "floor" { Modifier::Floor(n) } else { Modifier::Ceiling(n) };
+    Some((modifier, name.len() + number.len()))
+}
+
+/// A number TTML read as the closest one a roll can hold, e.g. `70000d6` as `32767d6`
+fn saturate<N: Into<i64>>(n: N) -> i16 {
+    let n = n.into();
+    n.max(i16::min_value() as i64).min(i16::max_value() as i64) as i16
+}
+
+fn parse_ttml(command: &str) -> Option<Vec<RollFlags>> {
+    let input = "!roll ".to_string() + command;
+    let step_result = {
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!("parse", command = %command).entered();
+        parse_step_p(input.as_bytes())
+    };
+    let step = match step_result {
+        Ok((_, step)) => step,
+        Err(_) => return None,
+    };
+
+    // Build a list of rolls
+    let mut rolls: Vec<RollFlags> = vec![];
+
+    // Build a list of flags
+    let mut flags = RollFlags::new();
+
+    // Loop through each step, push the dice when necessary
+    for arg in &step.args {
+        if let &Arg::Roll(RollArg::N(ArgValue::Number(n))) = arg {
+            flags.n = saturate(n);
+            flags.equation = flags.equation + &n.to_string();
+        } else if let &Arg::Roll(RollArg::D(ArgValue::Number(d))) = arg {
+            flags.max = saturate(d);
+            flags.die = match d {
+                100   => DieType::D100,
+                20    => DieType::D20,
+                12    => DieType::D12,
+                10    => DieType::D10,
+                8     => DieType::D8,
+                6     => DieType::D6,
+                4     => DieType::D4,
+                _     => DieType::Other,
+            };
+            flags.equation = flags.equation + &"d" + &d.to_string();
+        } else if let &Arg::Roll(RollArg::H(ArgValue::Number(h))) = arg {
+            flags.kh = saturate(h);
+            flags.equation = flags.equation + &"kh" + &h.to_string();
+        } else if let &Arg::Roll(RollArg::L(ArgValue::Number(l))) = arg {
+            flags.kl = saturate(l);
+            flags.equation = flags.equation + &"kl" + &l.to_string();
+        } else if let &Arg::Roll(RollArg::GT(ArgValue::Number(gt))) = arg {
+            flags.gt = saturate(gt);
+            flags.equation = flags.equation + &"gt" + &gt.to_string();
+        } else if let &Arg::Roll(RollArg::GTE(ArgValue::Number(gte))) = arg {
+            flags.gte = saturate(gte);
+            flags.equation = flags.equation + &"gte" + &gte.to_string();
+        } else if let &Arg::Roll(RollArg::LT(ArgValue::Number(lt))) = arg {
+            flags.lt = saturate(lt);
+            flags.equation = flags.equation + &"lt" + &lt.to_string();
+        } else if let &Arg::Roll(RollArg::LTE(ArgValue::Number(lte))) = arg {
+            flags.lte = saturate(lte);
+            flags.equation = flags.equation + &"lte" + &lte.to_string();
+        } else if let &Arg::Roll(RollArg::RR(ref comparitive)) = arg {
+            flags.rr = match &comparitive.value {
+                &ArgValue::Number(n) => saturate(n),
+                _ => 0
+            };
+            match comparitive.op {
+                ComparisonArg::GreaterThan => {
+                    flags.rr_op = Some(ComparisonArg::GreaterThan);
+                    flags.equation = flags.equation + &"rr>" + &flags.rr.to_string();
+                },
+                ComparisonArg::GreaterThanOrEqual => {
+                    flags.rr_op = Some(ComparisonArg::GreaterThanOrEqual);
+                    flags.equation = flags.equation + &"rr>=" + &flags.rr.to_string();
+                },
+                ComparisonArg::LessThan => {
+                    flags.rr_op = Some(ComparisonArg::LessThan);
+                    flags.equation = flags.equation + &"rr<" + &flags.rr.to_string();
+                },
+                ComparisonArg::LessThanOrEqual => {
+                    flags.rr_op = Some(ComparisonArg::LessThanOrEqual);
+                    flags.equation = flags.equation + &"rr<=" + &flags.rr.to_string();
+                },
+                ComparisonArg::EqualTo => {
+                    flags.rr_op = Some(ComparisonArg::EqualTo);
+                    flags.equation = flags.equation + &"rr==" + &flags.rr.to_string();
+                },
+            };
+        } else if let &Arg::Roll(RollArg::RO(ref comparitive)) = arg {
+            flags.ro = match &comparitive.value {
+                &ArgValue::Number(n) => saturate(n),
+                _ => 0
+            };
+            match comparitive.op {
+                ComparisonArg::GreaterThan => {
+                    flags.ro_op = Some(ComparisonArg::GreaterThan);
+                    flags.equation = flags.equation + &"ro>" + &flags.ro.to_string();
+                },
+                ComparisonArg::GreaterThanOrEqual => {
+                    flags.ro_op = Some(ComparisonArg::GreaterThanOrEqual);
+                    flags.equation = flags.equation + &"ro>=" + &flags.ro.to_string();
+                },
+                ComparisonArg::LessThan => {
+                    flags.ro_op = Some(ComparisonArg::LessThan);
+                    flags.equation = flags.equation + &"ro<" + &flags.ro.to_string();
+                },
+                ComparisonArg::LessThanOrEqual => {
+                    flags.ro_op = Some(ComparisonArg::LessThanOrEqual);
+                    flags.equation = flags.equation + &"ro<=" + &flags.ro.to_string();
+                },
+                ComparisonArg::EqualTo => {
+                    flags.ro_op = Some(ComparisonArg::EqualTo);
+                    flags.equation = flags.equation + &"ro==" + &flags.ro.to_string();
+                },
+            };
+        } else if let &Arg::Roll(RollArg::ModifierPos(ArgValue::Number(mp))) = arg {
+            flags.modifiers.push(saturate(mp));
+            flags.equation = flags.equation + &"+" + &mp.to_string();
+        } else if let &Arg::Roll(RollArg::ModifierNeg(ArgValue::Number(mn))) = arg {
+            flags.modifiers.push(0i16.saturating_sub(saturate(mn)));
+            flags.equation = flags.equation + &"-" + &mn.to_string();
+        } else if let &Arg::Roll(RollArg::Max(ArgValue::Number(max))) = arg {
+            flags.max = saturate(max);
+            flags.equation = flags.equation + &"max" + &max.to_string();
+        } else if let &Arg::Roll(RollArg::Min(ArgValue::Number(min))) = arg {
+            flags.min = saturate(min);
+            flags.equation = flags.equation + &"min" + &min.to_string();
+        } else if let &Arg::Roll(RollArg::Sides(ref r_sides)) = arg {
+            let sides: Vec<i16> = r_sides.into_iter().map(|side| match side {
+                &ArgValue::Number(n) => saturate(n),
+                _ => 0 as i16
+            }).collect();
+            flags.sides = Some(sides.clone());
+            flags.min = *sides.iter().min().unwrap_or(&0);
+            flags.max = *sides.iter().max().unwrap_or(&0);
+            let side_strs: Vec<String> = sides.into_iter().map(|side| (side.to_string())).collect();
+            flags.equation = flags.equation + &"[" + &side_strs.join(",") + &"]";
+        } else if let &Arg::Roll(RollArg::Comment(ArgValue::Text(ref comment))) = arg {
+            flags.comment = Some(comment.clone());
+            flags.equation = flags.equation + &"[" + comment + &"]";
+        } else if let &Arg::Roll(RollArg::Primitive(_)) = arg {
+            // Finish this roll before starting the next one
+            rolls.push(flags);
+
+            // Reset the flags
+            flags = RollFlags::new();
+        }
+    }
+
+    // The last roll isn't followed by a primitive
+    rolls.push(flags);
+    Some(rolls)
+}
+
+#[test]
+fn it_can_parse_fate_dice() {
+    let rolls = parse_notation("4dF+2").unwrap();
+    assert_eq!(rolls.len(), 1);
+    assert_eq!(rolls[0].die, DieType::Fate);
+    assert_eq!(rolls[0].n, 4);
+    assert_eq!(rolls[0].modifiers, vec![2]);
+    assert_eq!(rolls[0].equation, "4dF+2");
+
+    // A bare dF rolls four dice
+    let rolls = parse_notation("df").unwrap();
+    assert_eq!(rolls[0].n, 4);
+    assert_eq!(rolls[0].sides, Some(vec![-1, 0, 1]));
+}
+
+#[test]
+fn it_rejects_keeping_fate_dice() {
+    assert_eq!(parse_notation("4dFkh2").err(), Some(ParseError::UnsupportedModifier("kh")));
+    assert_eq!(parse_notation("4dFgte1").err(), Some(ParseError::UnsupportedModifier("gte")));
+    assert_eq!(parse_notation("4dQ").err(), Some(ParseError::InvalidNotation));
+}
+
+#[test]
+fn it_can_parse_half_dice() {
+    let rolls = parse_notation("3.5d6").unwrap();
+    assert_eq!(rolls.len(), 2);
+    assert_eq!((rolls[0].n, rolls[0].is_halved), (3, false));
+    assert_eq!((rolls[1].n, rolls[1].is_halved), (1, true));
+    assert_eq!(rolls[1].equation, "1hd6");
+    assert_eq!(rolls[1].canonical(), "1hd6");
+
+    let spelled: Vec<String> = parse_notation("3d6 + 1hd6").unwrap().iter().map(|flags| flags.canonical()).collect();
+    assert_eq!(spelled, vec!["3d6", "1hd6"]);
+    assert_eq!(parse_notation(".5d6").unwrap()[0].canonical(), "1hd6");
+    assert_eq!(parse_notation("0.5d6+2").unwrap()[0].canonical(), "1hd6+2");
+
+    // The half die keeps the face it landed on and counts for half of it
+    let half = Roll::with_roller(rolls[1].clone(), &mut ScriptedRoller::new(vec![5]));
+    assert_eq!((half.dice[0].value, half.dice[0].halved, half.value), (5, Some(3), 3));
+}
+
+#[test]
+fn it_can_parse_drop_modifiers() {
+    let rolls = parse_notation("5d20dh1dl1").unwrap();
+    assert_eq!(rolls.len(), 1);
+    assert_eq!((rolls[0].n, rolls[0].max), (5, 20));
+    assert_eq!((rolls[0].dh, rolls[0].dl), (1, 1));
+    assert_eq!(rolls[0].equation, "5d20dh1dl1");
+    assert_eq!(rolls[0].canonical(), "5d20dh1dl1");
+
+    // Drops written more than once add up
+    let rolls = parse_notation("6d6dl1dl1").unwrap();
+    assert_eq!((rolls[0].dh, rolls[0].dl), (0, 2));
+
+    assert_eq!(parse_notation("4dFdh1").err(), Some(ParseError::UnsupportedModifier("dh")));
+}
+
+#[test]
+fn it_can_parse_explosions() {
+    let rolls = parse_notation("1d10!>8").unwrap();
+    assert!(rolls[0].explode);
+    assert_eq!(rolls[0].explode_op.as_ref().map(comparison_str), Some(">"));
+    assert_eq!(rolls[0].explode_on, 8);
+    assert_eq!(rolls[0].canonical(), "1d10!>8");
+
+    let rolls = parse_notation("3d10!!>=9+2").unwrap();
+    assert_eq!(rolls[0].explode_policy, ExplodeSuccessPolicy::CompoundThenCompare);
+    assert_eq!(rolls[0].explode_op.as_ref().map(comparison_str), Some(">="));
+    assert_eq!(rolls[0].modifiers, vec![2]);
+
+    let rolls = parse_notation("1d10!=10").unwrap();
+    assert_eq!((rolls[0].explode_op.as_ref().map(comparison_str), rolls[0].explode_on), (Some("=="), 10));
+
+    // A plain explosion has no trigger, and explodes on the highest face
+    let rolls = parse_notation("2d6!").unwrap();
+    assert!(rolls[0].explode && rolls[0].explode_op.is_none());
+
+    // Comments can still shout
+    let rolls = parse_notation("1d20 \"Rolling for gold!\"").unwrap();
+    assert!(!rolls[0].explode);
+}
+
+#[test]
+fn it_can_parse_tags() {
+    let rolls = parse_notation("2d6[fire] + 1d6[cold]").unwrap();
+    assert_eq!(rolls.len(), 2);
+    assert_eq!(rolls[0].tags, vec!["fire"]);
+    assert_eq!(rolls[0].equation, "2d6[fire]");
+    assert_eq!(rolls[1].tags, vec!["cold"]);
+    assert_eq!((rolls[1].n, rolls[1].max), (1, 6));
+
+    // Tags can be listed or written one after another, and don't change the canonical form
+    let rolls = parse_notation("1d8[fire, magic]!+2").unwrap();
+    assert_eq!(rolls[0].tags, vec!["fire", "magic"]);
+    assert!(rolls[0].explode);
+    assert_eq!(rolls[0].modifiers, vec![2]);
+    assert_eq!(rolls[0].canonical(), "1d8!+2");
+    let rolls = parse_notation("1d8[fire][magic][fire]").unwrap();
+    assert_eq!(rolls[0].tags, vec!["fire", "magic"]);
+
+    // Custom sides and Fate dice aren't tags
+    let rolls = parse_notation("2d[1,3,5][odd]").unwrap();
+    assert_eq!(rolls[0].sides, Some(vec![1, 3, 5]));
+    assert_eq!(rolls[0].tags, vec!["odd"]);
+    let rolls = parse_notation("4dF[luck]").unwrap();
+    assert_eq!(rolls[0].die, DieType::Fate);
+    assert_eq!(rolls[0].tags, vec!["luck"]);
+}
+
+#[test]
+fn it_strips_modifiers_onto_the_roll_they_follow() {
+    let rolls = parse_notation("2d6!+1 + 4d6dl1 + 1hd6 + 1d8[fire]").unwrap();
+    assert_eq!(rolls.len(), 4);
+    assert!(rolls[0].explode && rolls[0].dl == 0);
+    assert_eq!((rolls[0].modifiers.clone(), rolls[1].dl), (vec![1], 1));
+    assert!(!rolls[1].explode && rolls[2].is_halved);
+    assert!(rolls[2].tags.is_empty());
+    assert_eq!(rolls[3].tags, vec!["fire"]);
+}
+
+#[test]
+fn it_reads_full_width_notation_as_ascii() {
+    let rolls = parse_notation("３ｄ６＋２").unwrap();
+    assert_eq!((rolls[0].n, rolls[0].max, rolls[0].modifiers.clone()), (3, 6, vec![2]));
+    assert_eq!(rolls[0].equation, "3d6+2");
+    assert_eq!(rolls[0].normalized, Some("3d6+2".to_string()));
+
+    let rolls = parse_notation("１ｄ２０−１").unwrap();
+    assert_eq!(rolls[0].modifiers, vec![-1]);
+
+    // Plain notation has nothing to record
+    assert_eq!(parse_notation("3d6+2").unwrap()[0].normalized, None);
+}
+
+#[test]
+fn it_rejects_lookalikes_when_strict() {
+    let strict = ParseOptions::new().strict();
+    assert_eq!(parse_notation_with("３d6", &strict).err(), Some(ParseError::LookalikeCharacter('３', '3')));
+    assert_eq!(parse_notation_with("1d20–1", &strict).err(), Some(ParseError::LookalikeCharacter('–', '-')));
+    assert!(parse_notation_with("1d20-1 \"ＧＯ！\"", &strict).is_ok());
+}
+
+#[test]
+fn it_keeps_comments_as_they_were_written() {
+    let rolls = parse_notation("１ｄ２０ \"ゴールド！×２\"").unwrap();
+    assert_eq!(rolls[0].max, 20);
+    assert!(rolls[0].equation.contains("ゴールド！×２"));
+    assert_eq!(rolls[0].normalized, Some("1d20 \"ゴールド！×２\"".to_string()));
+
+    // Smart quotes around a comment are read as plain ones, and plain ones can hold smart ones
+    let rolls = parse_notation("1d20 “Ｒｏｌｌ！”").unwrap();
+    assert!(rolls[0].equation.contains("Ｒｏｌｌ！"));
+    assert_eq!(rolls[0].normalized, Some("1d20 \"Ｒｏｌｌ！\"".to_string()));
+    let rolls = parse_notation("1d20 \"“ゴールド”\"").unwrap();
+    assert!(rolls[0].equation.contains("“ゴールド”"));
+    assert_eq!(rolls[0].normalized, None);
+}
+
+#[test]
+fn it_reads_numbers_too_big_for_a_roll_as_the_biggest_that_fits() {
+    assert_eq!(parse_notation("1d6dh32767dh32767").unwrap()[0].dh, i16::max_value());
+    assert_eq!(parse_notation("1d6-32768").unwrap()[0].modifiers, vec![-32767]);
+    assert_eq!(parse_notation("70000d6").unwrap()[0].n, i16::max_value());
+}
+
+#[test]
+fn it_refuses_notation_that_is_too_long() {
+    let long = "1d6".to_string() + &"+1".repeat(MAX_NOTATION_LENGTH);
+    assert_eq!(parse_notation(&long).err(), Some(ParseError::TooLong(long.len(), MAX_NOTATION_LENGTH)));
+    let normalized = normalize_notation(&long, &ParseOptions::new());
+    assert_eq!(normalized.err(), Some(ParseError::TooLong(long.len(), MAX_NOTATION_LENGTH)));
+}
+
+/// Every input fuzzing has crashed on, kept in `tests/fuzz_regressions`, parses and rolls without
+/// panicking
+#[test]
+fn it_survives_inputs_that_crashed_it_before() {
+    let dir = format!("{}/tests/fuzz_regressions", env!("CARGO_MANIFEST_DIR"));
+    let limits = Limits::default();
+    for entry in ::std::fs::read_dir(dir).unwrap() {
+        let bytes = ::std::fs::read(entry.unwrap().path()).unwrap();
+        let input = String::from_utf8_lossy(&bytes);
+        let _ = normalize_notation(&input, &ParseOptions::new().strict());
+        let _ = parse_notation_with(&input, &ParseOptions::new().strict());
+        let _ = RollRequest::new(input.as_ref()).roll(&limits, &mut Roller::manual(vec![]));
+        let _ = RollRequest::new(input.as_ref()).roll(&limits, &mut Roller::seeded(0));
+    }
+}
+
+#[test]
+fn it_can_parse_floors_and_ceilings() {
+    let rolls = parse_notation("1d20floor5ceiling15+2").unwrap();
+    assert_eq!((rolls[0].floor, rolls[0].ceiling, rolls[0].modifiers.clone()), (Some(5), Some(15), vec![2]));
+    assert_eq!(rolls[0].equation, "1d20+2floor5ceiling15");
+    assert_eq!(parse_notation("2d6floor-1").unwrap()[0].floor, Some(-1));
+
+    // Only the whole word is a bound
+    assert_eq!(parse_notation("1d20floor").err(), Some(ParseError::InvalidNotation));
+    assert_eq!(parse_notation("1d20 \"ceiling5\"").unwrap()[0].ceiling, None);
+}
+
+/// The fields notation can set, written out so two rolls can be compared
+#[cfg(test)]
+fn structure(flags: &RollFlags) -> String {
+    let op = |op: &Option<ComparisonArg>| op.as_ref().map(comparison_str);
+    format!("{:?}", (
+        (flags.n, flags.die, flags.min, flags.max, &flags.sides, flags.is_halved),
+        (flags.kh, flags.kl, flags.gt, flags.gte, flags.lt, flags.lte, flags.dh, flags.dl),
+        (flags.explode, flags.explode_policy, op(&flags.explode_op), flags.explode_on),
+        (op(&flags.rr_op), flags.rr, op(&flags.ro_op), flags.ro),
+        (flags.floor, flags.ceiling, &flags.modifiers, &flags.tags, &flags.comment),
+        (&flags.divisors, if flags.divisors.is_empty() { None } else { Some(flags.rounding) }, flags.without_replacement),
+    ))
+}
+
+#[test]
+fn it_parses_built_rolls_back_from_their_notation() {
+    let dice = [DieType::D4, DieType::D6, DieType::D8, DieType::D10, DieType::D12, DieType::D20, DieType::D100];
+    let comparisons = || vec![ComparisonArg::EqualTo, ComparisonArg::GreaterThan, ComparisonArg::GreaterThanOrEqual,
+                              ComparisonArg::LessThan, ComparisonArg::LessThanOrEqual];
+    let mut rng = XorShiftRng::from_seed([0x5d1e2f30, 0x41526374, 0x8596a7b8, 0xc9dae0f1]);
+    for _ in 0..2000 {
+        let mut builder = RollFlags::builder().n(rng.gen_range(1, 10));
+        let kind = rng.gen_range(0, 10);
+        let is_fate = kind == 0;
+        if is_fate {
+            builder = builder.die(DieType::Fate);
+        } else if kind == 1 {
+            let sides: Vec<i16> = (0..rng.gen_range(1, 6)).map(|_| rng.gen_range(1, 13)).collect();
+            builder = builder.sides(sides);
+        } else {
+            let die = dice[rng.gen_range(0, dice.len())];
+            builder = builder.die(die);
+            match rng.gen_range(0, 4) {
+                0 => builder = builder.max(die.max_face() - 1).min(2),
+                1 => builder = builder.halved(),
+                _ => {},
+            }
+        }
+
+        if !is_fate {
+            let counts: [fn(RollFlagsBuilder, i16) -> RollFlagsBuilder; 8] = [
+                RollFlagsBuilder::kh, RollFlagsBuilder::kl, RollFlagsBuilder::gt, RollFlagsBuilder::gte,
+                RollFlagsBuilder::lt, RollFlagsBuilder::lte, RollFlagsBuilder::dh, RollFlagsBuilder::dl,
+            ];
+            if let Some(count) = counts.get(rng.gen_range(0, counts.len() * 2)) {
+                builder = count(builder, rng.gen_range(1, 4));
+            }
+            match rng.gen_range(0, 5) {
+                0 => builder = builder.explode(ExplodeSuccessPolicy::CompareEachDie),
+                1 => builder = builder.explode(ExplodeSuccessPolicy::CompoundThenCompare),
+                2 => {
+                    let op = comparisons().remove(rng.gen_range(0, 5));
+                    builder = builder.explode(ExplodeSuccessPolicy::CompareEachDie).explode_on(op, rng.gen_range(2, 7));
+                },
+                _ => {},
+            }
+            if rng.gen() {
+                builder = builder.rr(comparisons().remove(rng.gen_range(0, 5)), rng.gen_range(1, 4));
+            }
+            if rng.gen() {
+                builder = builder.ro(comparisons().remove(rng.gen_range(0, 5)), rng.gen_range(1, 4));
+            }
+        }
+
+        let floor = rng.gen_range(-3, 6);
+        match rng.gen_range(0, 4) {
+            0 => builder = builder.floor(floor),
+            1 => builder = builder.ceiling(floor + rng.gen_range(0, 20)),
+            2 => builder = builder.floor(floor).ceiling(floor + rng.gen_range(0, 20)),
+            _ => {},
+        }
+        for _ in 0..rng.gen_range(0, 4) {
+            let modifier = rng.gen_range(1, 10);
+            builder = builder.modifier(if rng.gen() { modifier } else { -modifier });
+        }
+        for _ in 0..rng.gen_range(0, 3) {
+            let divisor = rng.gen_range(1, 5);
+            builder = builder.divide_by(if rng.gen() { divisor } else { -divisor });
+        }
+        let roundings = [Rounding::Floor, Rounding::Ceil, Rounding::HalfUp, Rounding::Truncate];
+        builder = builder.rounding(roundings[rng.gen_range(0, roundings.len())]);
+        for tag in ["fire", "cold", "magic"].iter() {
+            if rng.gen() {
+                builder = builder.tag(tag);
+            }
+        }
+        if rng.gen() {
+            builder = builder.comment("Rolling to hit!");
+        }
+
+        let built = builder.build().unwrap();
+        let notation = builder.to_notation().unwrap();
+        let parsed = parse_notation(&notation).unwrap();
+        assert_eq!(parsed.len(), 1, "{}", notation);
+        assert_eq!(structure(&parsed[0]), structure(&built), "{}", notation);
+    }
+}
+
+#[test]
+fn it_parses_divisors_back_from_their_notation() {
+    let halved = RollFlags::builder().n(8).die(DieType::D6).modifier(1).divide_by(2).rounding(Rounding::Ceil).build().unwrap();
+    assert_eq!(halved.to_notation(), Some("8d6+1div2ceil".to_string()));
+    let parsed = parse_notation("8d6+1div2ceil").unwrap();
+    assert_eq!((parsed[0].divisors.clone(), parsed[0].rounding, parsed[0].modifiers.clone()), (vec![2], Rounding::Ceil, vec![1]));
+
+    // Rounding down is left unwritten, and a bound after a divisor is still a bound
+    let parsed = parse_notation("1d20div2floor3").unwrap();
+    assert_eq!((parsed[0].divisors.clone(), parsed[0].rounding, parsed[0].floor), (vec![2], Rounding::Floor, Some(3)));
+    let parsed = parse_notation("1d20div2ceiling8").unwrap();
+    assert_eq!((parsed[0].rounding, parsed[0].ceiling), (Rounding::Floor, Some(8)));
+    assert_eq!(parse_notation("1d20div").err(), Some(ParseError::InvalidNotation));
+}
+
+#[test]
+fn it_parses_draws_without_replacement_back_from_their_notation() {
+    let lottery = RollFlags::builder().n(6).sides(vec![10, 20, 30, 40, 50, 60]).without_replacement().build().unwrap();
+    assert_eq!(lottery.to_notation(), Some("6d[10,20,30,40,50,60]nr".to_string()));
+    let parsed = parse_notation(&lottery.to_notation().unwrap()).unwrap();
+    assert_eq!(structure(&parsed[0]), structure(&lottery));
+
+    let drawn = Roll::with_roller(parse_notation("6d6nr").unwrap().remove(0), &mut Roller::seeded(7));
+    let mut faces: Vec<i16> = drawn.dice.iter().map(|die| die.value).collect();
+    faces.sort();
+    assert_eq!(faces, vec![1, 2, 3, 4, 5, 6]);
+    assert!(!parse_notation("2d6[inr]").unwrap()[0].without_replacement);
+}
+
+#[test]
+fn it_writes_rolls_made_together_as_one_command() {
+    let fire = RollFlags::builder().n(2).die(DieType::D6).tag("fire").build().unwrap();
+    let cold = RollFlags::builder().n(1).die(DieType::D6).tag("cold").comment("frost brand").build().unwrap();
+    let notation = group_notation(&[fire.clone(), cold.clone()]).unwrap();
+    assert_eq!(notation, "2d6[fire] + 1d6[cold] \"frost brand\"");
+    let parsed: Vec<String> = parse_notation(&notation).unwrap().iter().map(structure).collect();
+    assert_eq!(parsed, vec![structure(&fire), structure(&cold)]);
+
+    // A comment anywhere but the end would swallow the rolls after it
+    assert_eq!(group_notation(&[cold, fire]), None);
+    assert_eq!(group_notation(&[]), None);
+}
+
+#[test]
+fn it_parses_a_block_of_labeled_notation() {
+    let block = "# Longsword\nAttack: 1d20+7\n\n  // on a hit\nDamage: 2d6+4";
+    let expressions = parse_many(block).unwrap();
+    let found: Vec<(Option<String>, usize, usize, String)> = expressions.iter()
+        .map(|expression| (expression.label.clone(), expression.line, expression.column, expression.notation.clone()))
+        .collect();
+    assert_eq!(found, vec![
+        (Some("Attack".to_string()), 2, 9, "1d20+7".to_string()),
+        (Some("Damage".to_string()), 5, 9, "2d6+4".to_string()),
+    ]);
+    assert_eq!(expressions[1].rolls[0].n, 2);
+
+    let expressions = parse_many("Attack: 1d20+7; 2d6+4;").unwrap();
+    assert_eq!(expressions.len(), 2);
+    assert_eq!((expressions[1].label.clone(), expressions[1].column), (None, 17));
+}
+
+#[test]
+fn it_caps_the_expressions_of_a_block() {
+    let block = vec!["1d6"; MAX_EXPRESSIONS].join("; ");
+    assert_eq!(parse_many(&block).map(|expressions| expressions.len()), Ok(MAX_EXPRESSIONS));
+
+    // Blank expressions and comments don't count
+    let block = format!("# {} rolls\n{};;\n", MAX_EXPRESSIONS, block);
+    assert_eq!(parse_many(&block).map(|expressions| expressions.len()), Ok(MAX_EXPRESSIONS));
+    assert_eq!(parse_many(&(block + "1d6")).err(), Some(ParseError::TooManyExpressions(MAX_EXPRESSIONS)));
+}
+
+#[test]
+fn it_doesnt_split_on_semicolons_in_brackets_or_quotes() {
+    assert_eq!(split_expressions("1d20 [a; b]; 1d6"), vec![(0, "1d20 [a; b]"), (12, " 1d6")]);
+    assert_eq!(split_expressions("1d20 “a; b”; 1d6").len(), 2);
+
+    let expressions = parse_many("Attack: 1d20+7 \"sneak; flanking: yes\"; Damage: 1d6").unwrap();
+    assert_eq!(expressions.len(), 2);
+    assert_eq!(expressions[0].notation, "1d20+7 \"sneak; flanking: yes\"");
+    assert_eq!(expressions[0].rolls[0].comment, Some("sneak; flanking: yes".to_string()));
+}
+
+#[test]
+fn it_says_which_line_and_column_failed_to_parse() {
+    let block = "Attack: 1d20+7\nDamage: 2d6+4\nFire:  1dQ";
+    assert_eq!(parse_many(block).err(), Some(ParseError::AtLine(3, 8, Box::new(ParseError::InvalidNotation))));
+
+    let block = "1d20\n\n1d6; ２d6 ; 1dQ";
+    assert_eq!(parse_many(block).err(), Some(ParseError::AtLine(3, 12, Box::new(ParseError::InvalidNotation))));
+}
@@ -0,0 +1,163 @@
+use roll::{Roll, RollError};
+use std::collections::HashSet;
+#[cfg(test)]
+use die::DieType;
+#[cfg(test)]
+use roll::RollFlags;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// Name of the partition holding the dice nobody assigned
+pub const REMAINDER: &str = "remainder";
+
+/// Dice of a roll set aside for one purpose, e.g. the two dice put towards an attack
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Partition {
+    /// IDs of the roll's dice in the partition, in the order they were assigned
+    pub dice: Vec<String>,
+
+    pub name: String,
+
+    /// Number of the partition's dice that were successful
+    pub successes: usize,
+
+    /// Sum of the partition's dice
+    pub total: i32,
+}
+
+/// A finished roll with its kept dice shared out between named partitions, see `Roll::partition`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PartitionedRoll {
+    /// Partitions in the order they were assigned
+    pub partitions: Vec<Partition>,
+
+    /// Kept dice nobody assigned, along with whatever the roll's modifiers, floor, ceiling and
+    /// divisors added to its total
+    pub remainder: Partition,
+
+    /// ID of the roll the dice are from
+    pub roll_id: String,
+}
+
+impl PartitionedRoll {
+    /// Share out a roll's kept dice, failing if a die isn't in the roll, was dropped or is assigned
+    /// twice
+    pub fn of(roll: &Roll, assignments: &[(String, Vec<String>)]) -> Result<PartitionedRoll, RollError> {
+        let mut assigned = HashSet::new();
+        let mut partitions = vec![];
+        for &(ref name, ref ids) in assignments {
+            for id in ids {
+                match roll.dice.iter().find(|die| &die.id == id) {
+                    None => return Err(RollError::UnknownDie(id.clone())),
+                    Some(die) if die.is_dropped => return Err(RollError::DroppedDie(id.clone())),
+                    Some(_) => {},
+                }
+                if !assigned.insert(id.as_str()) {
+                    return Err(RollError::AssignedTwice(id.clone()));
+                }
+            }
+            partitions.push(PartitionedRoll::partition(roll, name, ids.clone()));
+        }
+
+        let unassigned = roll.dice.iter()
+            .filter(|die| !die.is_dropped && !assigned.contains(die.id.as_str()))
+            .map(|die| die.id.clone())
+            .collect();
+        let mut remainder = PartitionedRoll::partition(roll, REMAINDER, unassigned);
+        remainder.total += roll.value - roll.natural_value;
+
+        Ok(PartitionedRoll {
+            partitions,
+            remainder,
+            roll_id: roll.id.clone(),
+        })
+    }
+
+    fn partition(roll: &Roll, name: &str, ids: Vec<String>) -> Partition {
+        let dice: Vec<_> = ids.iter().filter_map(|id| roll.dice.iter().find(|die| &die.id == id)).collect();
+        Partition {
+            successes: dice.iter().filter(|die| die.is_successful).count(),
+            total: dice.iter().map(|die| die.counted() as i32).sum(),
+            dice: ids,
+            name: name.to_string(),
+        }
+    }
+
+    /// The partition with the name, the remainder included
+    pub fn get(&self, name: &str) -> Option<&Partition> {
+        self.partitions.iter().chain(Some(&self.remainder)).find(|partition| partition.name == name)
+    }
+}
+
+#[test]
+fn it_refuses_to_assign_a_die_twice() {
+    let flags = RollFlags::builder().n(4).die(DieType::D6).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 2, 5, 3]));
+    let assignments = vec![
+        ("attack".to_string(), vec![roll.dice[0].id.clone(), roll.dice[2].id.clone()]),
+        ("defend".to_string(), vec![roll.dice[2].id.clone()]),
+    ];
+    assert_eq!(roll.partition(&assignments), Err(RollError::AssignedTwice(roll.dice[2].id.clone())));
+}
+
+#[test]
+fn it_refuses_dice_that_are_not_in_the_roll() {
+    let flags = RollFlags::builder().n(2).die(DieType::D6).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 2]));
+    let unknown = vec![("attack".to_string(), vec!["nope".to_string()])];
+    assert_eq!(roll.partition(&unknown), Err(RollError::UnknownDie("nope".to_string())));
+}
+
+#[test]
+fn it_refuses_dropped_dice() {
+    // Dice that missed the target were dropped, so they can't be assigned
+    let flags = RollFlags::builder().n(2).die(DieType::D6).gte(5).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 2]));
+    let missed = vec![("attack".to_string(), vec![roll.dice[1].id.clone()])];
+    assert_eq!(roll.partition(&missed), Err(RollError::DroppedDie(roll.dice[1].id.clone())));
+}
+
+#[test]
+fn it_counts_successes_in_each_partition() {
+    let flags = RollFlags::builder().n(5).die(DieType::D6).gte(5).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 2, 5, 5, 1]));
+    let assignments = vec![
+        ("attack".to_string(), vec![roll.dice[0].id.clone(), roll.dice[2].id.clone()]),
+        ("defend".to_string(), vec![roll.dice[3].id.clone()]),
+    ];
+
+    let partitioned = roll.partition(&assignments).unwrap();
+    assert_eq!(partitioned.get("attack").map(|partition| partition.successes), Some(2));
+    assert_eq!(partitioned.get("defend").map(|partition| partition.successes), Some(1));
+    assert_eq!(partitioned.remainder.successes, 0);
+    assert!(partitioned.remainder.dice.is_empty());
+}
+
+#[test]
+fn it_adds_up_to_the_roll() {
+    let flags = RollFlags::builder().n(5).die(DieType::D8).dl(1).modifier(3).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![7, 1, 4, 8, 2]));
+    let assignments = vec![
+        ("attack".to_string(), vec![roll.dice[3].id.clone(), roll.dice[0].id.clone()]),
+        ("defend".to_string(), vec![roll.dice[2].id.clone()]),
+    ];
+
+    let partitioned = roll.partition(&assignments).unwrap();
+    assert_eq!(partitioned.get("attack").map(|partition| partition.total), Some(15));
+    assert_eq!(partitioned.remainder.dice, vec![roll.dice[4].id.clone()]);
+    assert_eq!(partitioned.remainder.total, 5);
+    let total: i32 = partitioned.partitions.iter().map(|partition| partition.total).sum();
+    assert_eq!(total + partitioned.remainder.total, roll.value);
+}
+
+#[test]
+fn it_points_back_at_the_roll_and_its_dice() {
+    let flags = RollFlags::builder().n(2).die(DieType::D8).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![7, 1]));
+    let assignments = vec![("attack".to_string(), vec![roll.dice[1].id.clone()])];
+
+    let json = ::serde_json::to_value(&roll.partition(&assignments).unwrap()).unwrap();
+    assert_eq!(json["roll_id"], roll.id.as_str());
+    assert_eq!(json["partitions"][0]["dice"][0], roll.dice[1].id.as_str());
+    assert_eq!(json["remainder"]["name"], REMAINDER);
+}
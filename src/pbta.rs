@@ -0,0 +1,185 @@
+use die::DieType;
+use ladder::{LadderError, SuccessLadder};
+use roll::{Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::{assert_die_flags, assert_roll_total, FlagSet, ScriptedRoller};
+
+/// How a Powered by the Apocalypse move went, from worst to best
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+pub enum PbtaOutcome {
+    Miss,
+    WeakHit,
+    StrongHit,
+
+    /// Only reached when the bands have an advanced threshold
+    Advanced,
+}
+
+/// Rolling extra dice for a move and keeping two of them
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum PbtaAdvantage {
+    /// Roll 2d6
+    Normal,
+
+    /// Roll 3d6 and keep the highest two
+    Advantage,
+
+    /// Roll 3d6 and keep the lowest two
+    Disadvantage,
+}
+
+/// The lowest total reaching each outcome, anything below the weak hit is a miss
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PbtaBands {
+    pub weak_hit: i32,
+    pub strong_hit: i32,
+
+    /// Lowest total of an advanced move, none when the move has no advanced outcome
+    pub advanced: Option<i32>,
+}
+
+impl Default for PbtaBands {
+    fn default() -> PbtaBands {
+        PbtaBands {
+            weak_hit: 7,
+            strong_hit: 10,
+            advanced: None,
+        }
+    }
+}
+
+impl PbtaBands {
+    /// The usual bands with an advanced outcome on 12 or more
+    pub fn with_advanced() -> PbtaBands {
+        PbtaBands {
+            advanced: Some(12),
+            ..PbtaBands::default()
+        }
+    }
+
+    /// The bands as a success ladder, failing if the thresholds don't go up
+    pub fn ladder(&self) -> Result<SuccessLadder, LadderError> {
+        // Totals below the weak hit land on the bottom rung, so the miss threshold is never reached
+        let mut bands = vec![(self.weak_hit - 1, "miss"), (self.weak_hit, "weak hit"), (self.strong_hit, "strong hit")];
+        if let Some(advanced) = self.advanced {
+            bands.push((advanced, "advanced"));
+        }
+        SuccessLadder::new(bands)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PbtaResult {
+    pub outcome: PbtaOutcome,
+
+    /// The dice rolled, graded on the bands
+    pub roll: Roll,
+
+    /// The kept dice plus the stat
+    pub total: i32,
+}
+
+/// Make a Powered by the Apocalypse move of 2d6 plus the stat, with the usual bands
+pub fn pbta_move(stat: i16, advantage: PbtaAdvantage) -> PbtaResult {
+    pbta_move_with(stat, advantage, &PbtaBands::default(), &mut Roller::new())
+        .expect("the usual bands always go up")
+}
+
+/// Make a move on the given bands, using the given source of randomness
+pub fn pbta_move_with(stat: i16, advantage: PbtaAdvantage, bands: &PbtaBands, roller: &mut Roller) -> Result<PbtaResult, LadderError> {
+    let ladder = bands.ladder()?;
+
+    let builder = RollFlags::builder().die(DieType::D6).modifier(stat);
+    let builder = match advantage {
+        PbtaAdvantage::Normal => builder.n(2),
+        PbtaAdvantage::Advantage => builder.n(3).kh(2),
+        PbtaAdvantage::Disadvantage => builder.n(3).kl(2),
+    };
+    let mut flags = builder.build().expect("a move is always a valid roll");
+    flags.equation = flags.canonical();
+
+    let mut roll = Roll::with_roller(flags, roller);
+    let outcome = match roll.grade(&ladder, None).band_index {
+        0 => PbtaOutcome::Miss,
+        1 => PbtaOutcome::WeakHit,
+        2 => PbtaOutcome::StrongHit,
+        _ => PbtaOutcome::Advanced,
+    };
+
+    Ok(PbtaResult {
+        outcome,
+        total: roll.value,
+        roll,
+    })
+}
+
+#[cfg(test)]
+fn move_with(values: Vec<i16>, stat: i16, advantage: PbtaAdvantage, bands: &PbtaBands) -> PbtaResult {
+    pbta_move_with(stat, advantage, bands, &mut ScriptedRoller::new(values)).unwrap()
+}
+
+#[test]
+fn it_grades_moves_at_the_boundaries() {
+    let bands = PbtaBands::default();
+    let outcome = |values: Vec<i16>, stat: i16| move_with(values, stat, PbtaAdvantage::Normal, &bands).outcome;
+
+    assert_eq!(outcome(vec![1, 1], -1), PbtaOutcome::Miss);
+    assert_eq!(outcome(vec![3, 3], 0), PbtaOutcome::Miss);
+    assert_eq!(outcome(vec![3, 3], 1), PbtaOutcome::WeakHit);
+    assert_eq!(outcome(vec![4, 5], 0), PbtaOutcome::WeakHit);
+    assert_eq!(outcome(vec![4, 5], 1), PbtaOutcome::StrongHit);
+
+    // Without an advanced threshold, 12 and up is still a strong hit
+    assert_eq!(outcome(vec![6, 5], 0), PbtaOutcome::StrongHit);
+    assert_eq!(outcome(vec![6, 6], 3), PbtaOutcome::StrongHit);
+}
+
+#[test]
+fn it_grades_advanced_moves() {
+    let bands = PbtaBands::with_advanced();
+    let result = move_with(vec![6, 5], 0, PbtaAdvantage::Normal, &bands);
+    assert_eq!(result.outcome, PbtaOutcome::StrongHit);
+    assert_eq!(result.total, 11);
+
+    let result = move_with(vec![6, 5], 1, PbtaAdvantage::Normal, &bands);
+    assert_eq!(result.outcome, PbtaOutcome::Advanced);
+    assert_eq!(result.roll.outcome.as_ref().map(|outcome| outcome.label.as_str()), Some("advanced"));
+}
+
+#[test]
+fn it_can_shift_the_bands() {
+    let bands = PbtaBands {
+        weak_hit: 8,
+        strong_hit: 11,
+        advanced: None,
+    };
+    assert_eq!(move_with(vec![3, 4], 0, PbtaAdvantage::Normal, &bands).outcome, PbtaOutcome::Miss);
+    assert_eq!(move_with(vec![4, 6], 0, PbtaAdvantage::Normal, &bands).outcome, PbtaOutcome::WeakHit);
+
+    let backwards = PbtaBands {
+        weak_hit: 10,
+        strong_hit: 7,
+        advanced: None,
+    };
+    assert_eq!(pbta_move_with(0, PbtaAdvantage::Normal, &backwards, &mut Roller::new()).err(),
+               Some(LadderError::NotIncreasing(2)));
+}
+
+#[test]
+fn it_keeps_two_dice_with_advantage() {
+    let result = move_with(vec![2, 6, 4], 1, PbtaAdvantage::Advantage, &PbtaBands::default());
+    assert_roll_total(&result.roll, 11);
+    assert_eq!(result.outcome, PbtaOutcome::StrongHit);
+    assert_die_flags(&result.roll.dice[0], FlagSet {
+        is_dropped: true,
+        is_successful: true,
+        ..FlagSet::default()
+    });
+    assert!(result.roll.dice[1..].iter().all(|d| !d.is_dropped));
+
+    let result = move_with(vec![2, 6, 4], 1, PbtaAdvantage::Disadvantage, &PbtaBands::default());
+    assert_roll_total(&result.roll, 7);
+    assert_eq!(result.outcome, PbtaOutcome::WeakHit);
+    assert!(result.roll.dice[1].is_dropped);
+}
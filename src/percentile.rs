@@ -0,0 +1,239 @@
+use die::DieType;
+use roll::{Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// Which game's rules degrees are counted by, since they count them differently
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum PercentileSystem {
+    /// Dark Heresy 2nd edition: a success is one degree, plus one for each tens digit the roll is
+    /// under the target's, and a failure the same the other way. 01 always succeeds and 100 always
+    /// fails.
+    DarkHeresy2,
+
+    /// Warhammer Fantasy Roleplay 4th edition: success levels are the target's tens digit less the
+    /// roll's, so a bare success or failure is 0. 01 to 05 always succeed and 96 to 100 always
+    /// fail, with their success levels kept at 0 or better and 0 or worse.
+    Wfrp4e,
+}
+
+/// What matching digits mean, e.g. 33 or 100 read as 00
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Double {
+    /// Doubles on a success
+    Critical,
+
+    /// Doubles on a failure
+    Fumble,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PercentileOptions {
+    /// Roll a tens d10 and a units d10 instead of a d100, reading a 10 on either as 0 and 00 as 100
+    pub linked_dice: bool,
+
+    pub system: PercentileSystem,
+}
+
+impl Default for PercentileOptions {
+    fn default() -> PercentileOptions {
+        PercentileOptions {
+            linked_dice: false,
+            system: PercentileSystem::DarkHeresy2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PercentileResult {
+    /// Degrees of success above 0 and of failure below it, or WFRP success levels
+    pub degrees: i16,
+
+    /// If the tens and units digits matched, and what that meant
+    pub double: Option<Double>,
+
+    pub is_success: bool,
+
+    /// The percentile rolled, from 1 to 100
+    pub natural: u8,
+
+    /// The d100, or the tens and units d10s in that order with linked dice
+    pub roll: Roll,
+
+    pub system: PercentileSystem,
+    pub target: u8,
+
+    /// Tens digit of the percentile, 0 for 100 as it's read as 00
+    pub tens: u8,
+
+    /// Units digit of the percentile
+    pub units: u8,
+}
+
+/// Roll a percentile under a target, e.g. a Dark Heresy test against a characteristic of 45
+pub fn roll_under_percentile(target: u8, options: &PercentileOptions) -> PercentileResult {
+    roll_under_percentile_with(target, options, &mut Roller::new())
+}
+
+/// Roll a percentile under a target using the given source of randomness
+///
+/// Rolling the target or under succeeds. Degrees compare the tens digits of the target and roll,
+/// with 100 counted as a tens digit of 10.
+pub fn roll_under_percentile_with(target: u8, options: &PercentileOptions, roller: &mut Roller) -> PercentileResult {
+    let builder = if options.linked_dice {
+        RollFlags::builder().n(2).die(DieType::D10)
+    } else {
+        RollFlags::builder().n(1).die(DieType::D100)
+    };
+    let mut flags = builder.build().expect("a percentile is always a valid roll");
+    flags.equation = flags.canonical();
+    let roll = Roll::with_roller(flags, roller);
+
+    let (tens, units) = if options.linked_dice {
+        ((roll.dice[0].value % 10) as u8, (roll.dice[1].value % 10) as u8)
+    } else {
+        let value = roll.dice[0].value % 100;
+        ((value / 10) as u8, (value % 10) as u8)
+    };
+    let natural = match tens * 10 + units {
+        0 => 100,
+        natural => natural,
+    };
+
+    let is_success = match options.system {
+        PercentileSystem::DarkHeresy2 => natural == 1 || (natural < 100 && natural <= target),
+        PercentileSystem::Wfrp4e => natural <= 5 || (natural < 96 && natural <= target),
+    };
+    let difference = (target.min(100) / 10) as i16 - (natural / 10) as i16;
+    let degrees = match (options.system, is_success) {
+        (PercentileSystem::DarkHeresy2, true) => (difference + 1).max(1),
+        (PercentileSystem::DarkHeresy2, false) => (difference - 1).min(-1),
+        (PercentileSystem::Wfrp4e, true) => difference.max(0),
+        (PercentileSystem::Wfrp4e, false) => difference.min(0),
+    };
+    let double = match (tens == units, is_success) {
+        (false, _) => None,
+        (true, true) => Some(Double::Critical),
+        (true, false) => Some(Double::Fumble),
+    };
+
+    PercentileResult {
+        degrees,
+        double,
+        is_success,
+        natural,
+        roll,
+        system: options.system,
+        target,
+        tens,
+        units,
+    }
+}
+
+#[test]
+fn it_succeeds_on_the_target() {
+    let dark_heresy = PercentileOptions::default();
+    let result = roll_under_percentile_with(45, &dark_heresy, &mut ScriptedRoller::new(vec![45]));
+    assert!(result.is_success);
+    assert_eq!((result.degrees, result.double), (1, None));
+    let result = roll_under_percentile_with(45, &dark_heresy, &mut ScriptedRoller::new(vec![46]));
+    assert!(!result.is_success);
+    assert_eq!(result.degrees, -1);
+}
+
+#[test]
+fn it_counts_no_degrees_for_a_bare_wfrp_success() {
+    let wfrp = PercentileOptions {
+        system: PercentileSystem::Wfrp4e,
+        ..PercentileOptions::default()
+    };
+    let result = roll_under_percentile_with(45, &wfrp, &mut ScriptedRoller::new(vec![45]));
+    assert!(result.is_success);
+    assert_eq!(result.degrees, 0);
+}
+
+#[test]
+fn it_counts_every_tens_digit_between_the_roll_and_the_target() {
+    let dark_heresy = PercentileOptions::default();
+    let wfrp = PercentileOptions {
+        system: PercentileSystem::Wfrp4e,
+        ..PercentileOptions::default()
+    };
+    assert_eq!(roll_under_percentile_with(65, &dark_heresy, &mut ScriptedRoller::new(vec![12])).degrees, 6);
+    assert_eq!(roll_under_percentile_with(65, &wfrp, &mut ScriptedRoller::new(vec![12])).degrees, 5);
+    assert_eq!(roll_under_percentile_with(35, &wfrp, &mut ScriptedRoller::new(vec![82])).degrees, -5);
+}
+
+#[test]
+fn it_reads_doubles_as_crits_and_fumbles() {
+    let wfrp = PercentileOptions {
+        system: PercentileSystem::Wfrp4e,
+        ..PercentileOptions::default()
+    };
+    let result = roll_under_percentile_with(50, &wfrp, &mut ScriptedRoller::new(vec![33]));
+    assert!(result.is_success);
+    assert_eq!((result.tens, result.units), (3, 3));
+    assert_eq!((result.degrees, result.double), (2, Some(Double::Critical)));
+
+    let result = roll_under_percentile_with(30, &wfrp, &mut ScriptedRoller::new(vec![44]));
+    assert!(!result.is_success);
+    assert_eq!((result.degrees, result.double), (-1, Some(Double::Fumble)));
+    assert_eq!(roll_under_percentile_with(50, &wfrp, &mut ScriptedRoller::new(vec![34])).double, None);
+}
+
+#[test]
+fn it_counts_a_dark_heresy_fumble_as_a_failure_by_degrees() {
+    let dark_heresy = PercentileOptions::default();
+    let result = roll_under_percentile_with(30, &dark_heresy, &mut ScriptedRoller::new(vec![44]));
+    assert_eq!((result.degrees, result.double), (-2, Some(Double::Fumble)));
+}
+
+#[test]
+fn it_always_fails_on_100() {
+    for &system in [PercentileSystem::DarkHeresy2, PercentileSystem::Wfrp4e].iter() {
+        let options = PercentileOptions {
+            system,
+            ..PercentileOptions::default()
+        };
+        let result = roll_under_percentile_with(100, &options, &mut ScriptedRoller::new(vec![100]));
+        assert!(!result.is_success);
+        assert_eq!(result.natural, 100);
+        assert_eq!(result.double, Some(Double::Fumble));
+        let degrees = if system == PercentileSystem::Wfrp4e { 0 } else { -1 };
+        assert_eq!(result.degrees, degrees);
+    }
+}
+
+#[test]
+fn it_fails_wfrp_from_96_and_succeeds_up_to_5() {
+    let wfrp = PercentileOptions {
+        system: PercentileSystem::Wfrp4e,
+        ..PercentileOptions::default()
+    };
+    assert!(!roll_under_percentile_with(99, &wfrp, &mut ScriptedRoller::new(vec![96])).is_success);
+    let result = roll_under_percentile_with(3, &wfrp, &mut ScriptedRoller::new(vec![4]));
+    assert!(result.is_success);
+    assert_eq!(result.degrees, 0);
+
+    // Dark Heresy goes by the target, though a 1 always succeeds
+    let dark_heresy = PercentileOptions::default();
+    assert!(roll_under_percentile_with(99, &dark_heresy, &mut ScriptedRoller::new(vec![96])).is_success);
+    assert!(roll_under_percentile_with(0, &dark_heresy, &mut ScriptedRoller::new(vec![1])).is_success);
+}
+
+#[test]
+fn it_rolls_linked_tens_and_units() {
+    let linked = PercentileOptions {
+        linked_dice: true,
+        ..PercentileOptions::default()
+    };
+    let result = roll_under_percentile_with(20, &linked, &mut ScriptedRoller::new(vec![10, 7]));
+    assert_eq!((result.tens, result.units, result.natural), (0, 7, 7));
+    assert_eq!(result.roll.dice.len(), 2);
+    assert_eq!(result.degrees, 3);
+
+    let result = roll_under_percentile_with(99, &linked, &mut ScriptedRoller::new(vec![10, 10]));
+    assert_eq!(result.natural, 100);
+    assert!(!result.is_success);
+}
@@ -0,0 +1,176 @@
+use die::{Die, DieType};
+use roll::{Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::{assert_roll_total, ScriptedRoller};
+
+/// How well a Pathfinder 2e check went, from worst to best
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
+pub enum Degree {
+    CriticalFailure,
+    Failure,
+    Success,
+    CriticalSuccess,
+}
+
+impl Degree {
+    /// Grade a total against a DC, meeting it is a success and beating it by 10 is a critical
+    pub fn of(total: i32, dc: i32) -> Degree {
+        if total >= dc + 10 {
+            Degree::CriticalSuccess
+        } else if total >= dc {
+            Degree::Success
+        } else if total <= dc - 10 {
+            Degree::CriticalFailure
+        } else {
+            Degree::Failure
+        }
+    }
+
+    /// One degree better, a critical success stays one
+    pub fn step_up(self) -> Degree {
+        match self {
+            Degree::CriticalFailure => Degree::Failure,
+            Degree::Failure => Degree::Success,
+            Degree::Success | Degree::CriticalSuccess => Degree::CriticalSuccess,
+        }
+    }
+
+    /// One degree worse, a critical failure stays one
+    pub fn step_down(self) -> Degree {
+        match self {
+            Degree::CriticalFailure | Degree::Failure => Degree::CriticalFailure,
+            Degree::Success => Degree::Failure,
+            Degree::CriticalSuccess => Degree::Success,
+        }
+    }
+}
+
+/// Rolling twice for a check and keeping one of the dice
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum FortuneEffect {
+    /// Roll once
+    Normal,
+
+    /// Roll twice and keep the higher die
+    Fortune,
+
+    /// Roll twice and keep the lower die
+    Misfortune,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Pf2CheckResult {
+    /// The degree after a natural 20 or 1 stepped it
+    pub degree: Degree,
+
+    /// The d20 that counted
+    pub die: Die,
+
+    /// The value the kept d20 landed on
+    pub natural: i16,
+
+    /// The roll behind the check, with both dice under fortune or misfortune
+    pub roll: Roll,
+
+    /// The natural roll plus the modifier
+    pub total: i32,
+
+    /// The degree from comparing the total to the DC alone
+    pub unadjusted_degree: Degree,
+}
+
+/// Make a Pathfinder 2e check of a d20 plus the modifier against a DC
+pub fn pf2_check(modifier: i16, dc: i16) -> Pf2CheckResult {
+    pf2_check_with(modifier, dc, FortuneEffect::Normal, &mut Roller::new())
+}
+
+/// Make a Pathfinder 2e check using the given source of randomness
+pub fn pf2_check_with(modifier: i16, dc: i16, fortune: FortuneEffect, roller: &mut Roller) -> Pf2CheckResult {
+    let builder = RollFlags::builder().die(DieType::D20).modifier(modifier);
+    let builder = match fortune {
+        FortuneEffect::Normal => builder.n(1),
+        FortuneEffect::Fortune => builder.n(2).kh(1),
+        FortuneEffect::Misfortune => builder.n(2).kl(1),
+    };
+    let mut flags = builder.build().expect("a d20 check is always a valid roll");
+    flags.equation = flags.canonical();
+    let roll = Roll::with_roller(flags, roller);
+
+    let die = roll.dice.iter().find(|d| !d.is_dropped).expect("a check always keeps a die").clone();
+    let unadjusted_degree = Degree::of(roll.value, dc as i32);
+    let degree = if die.is_critical() {
+        unadjusted_degree.step_up()
+    } else if die.is_fumble() {
+        unadjusted_degree.step_down()
+    } else {
+        unadjusted_degree
+    };
+
+    Pf2CheckResult {
+        degree,
+        natural: die.value,
+        die,
+        total: roll.value,
+        roll,
+        unadjusted_degree,
+    }
+}
+
+#[cfg(test)]
+fn check(values: Vec<i16>, modifier: i16, dc: i16, fortune: FortuneEffect) -> Pf2CheckResult {
+    pf2_check_with(modifier, dc, fortune, &mut ScriptedRoller::new(values))
+}
+
+#[test]
+fn it_grades_checks_at_the_boundaries() {
+    // Exactly 10 over the DC is a critical success, one short is a plain success
+    assert_eq!(check(vec![15], 5, 10, FortuneEffect::Normal).degree, Degree::CriticalSuccess);
+    assert_eq!(check(vec![14], 5, 10, FortuneEffect::Normal).degree, Degree::Success);
+
+    // Meeting the DC succeeds
+    assert_eq!(check(vec![5], 5, 10, FortuneEffect::Normal).degree, Degree::Success);
+    assert_eq!(check(vec![4], 5, 10, FortuneEffect::Normal).degree, Degree::Failure);
+
+    // Exactly 10 under the DC is a critical failure
+    assert_eq!(check(vec![5], 5, 20, FortuneEffect::Normal).degree, Degree::CriticalFailure);
+    assert_eq!(check(vec![6], 5, 20, FortuneEffect::Normal).degree, Degree::Failure);
+}
+
+#[test]
+fn it_steps_up_on_a_natural_20() {
+    let result = check(vec![20], 0, 25, FortuneEffect::Normal);
+    assert_eq!(result.unadjusted_degree, Degree::Failure);
+    assert_eq!(result.degree, Degree::Success);
+    assert_eq!(result.natural, 20);
+    assert_eq!(result.total, 20);
+
+    // A critical success can't get any better
+    assert_eq!(check(vec![20], 10, 10, FortuneEffect::Normal).degree, Degree::CriticalSuccess);
+}
+
+#[test]
+fn it_steps_down_on_a_natural_1() {
+    let result = check(vec![1], 20, 15, FortuneEffect::Normal);
+    assert_eq!(result.unadjusted_degree, Degree::Success);
+    assert_eq!(result.degree, Degree::Failure);
+    assert_eq!(result.total, 21);
+
+    assert_eq!(check(vec![1], 0, 15, FortuneEffect::Normal).degree, Degree::CriticalFailure);
+}
+
+#[test]
+fn it_keeps_a_die_for_fortune_and_misfortune() {
+    let fortune = check(vec![3, 18], 2, 15, FortuneEffect::Fortune);
+    assert_eq!(fortune.natural, 18);
+    assert_eq!(fortune.degree, Degree::Success);
+    assert_eq!(fortune.roll.dice.len(), 2);
+    assert_roll_total(&fortune.roll, 20);
+
+    // Misfortune keeps the natural 1, stepping the failure down
+    let misfortune = check(vec![1, 18], 2, 10, FortuneEffect::Misfortune);
+    assert_eq!(misfortune.natural, 1);
+    assert_eq!(misfortune.unadjusted_degree, Degree::Failure);
+    assert_eq!(misfortune.degree, Degree::CriticalFailure);
+    assert_eq!(misfortune.die.id, misfortune.roll.dice[0].id);
+}
@@ -0,0 +1,299 @@
+use die::DieType;
+use roll::{ExplodeSuccessPolicy, Roll, RollError, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// Something done to a pool's dice after they're rolled
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Stage {
+    /// Mark the kept dice landing on the number or higher as successes, without dropping the rest
+    CountAtLeast(i16),
+    DropHighest(i16),
+    DropLowest(i16),
+    Explode(ExplodeSuccessPolicy),
+    KeepHighest(i16),
+    KeepLowest(i16),
+    Modifier(i16),
+}
+
+/// What a pool's roll has to reach for a branch to be rolled
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Condition {
+    SuccessesAtLeast(usize),
+    TotalAtLeast(i32),
+}
+
+impl Condition {
+    pub fn holds(&self, result: &PipelineResult) -> bool {
+        match *self {
+            Condition::SuccessesAtLeast(successes) => result.successes >= successes,
+            Condition::TotalAtLeast(total) => result.roll.value >= total,
+        }
+    }
+}
+
+/// A pipeline rolled after another when its condition holds
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Branch {
+    pub condition: Condition,
+    pub pipeline: Pipeline,
+}
+
+/// A pool of dice, what's done to them once rolled, and the pools rolled next depending on how it
+/// went, e.g. `8d6`, exploding, dropping the lowest two and counting 5s and up, with a `1d8` bonus
+/// on three successes
+///
+/// Built up a stage at a time and serialized as its plan, so it can be shown before it's rolled.
+/// Each pool is rolled as a single roll, so its stages happen in the order they always do: dice
+/// explode, then are kept or dropped, then counted, then modified.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Pipeline {
+    pub branches: Vec<Branch>,
+    pub die: DieType,
+    pub n: i16,
+    pub stages: Vec<Stage>,
+}
+
+/// A pool's roll, and those of the branches that were rolled after it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PipelineResult {
+    /// Branches whose condition held, in the order they were added
+    pub branches: Vec<PipelineResult>,
+
+    pub roll: Roll,
+
+    /// Number of kept dice counted as successes
+    pub successes: usize,
+}
+
+impl PipelineResult {
+    /// Every roll made, this pool's first and then each branch's in turn
+    pub fn rolls(&self) -> Vec<&Roll> {
+        let mut rolls = vec![&self.roll];
+        for branch in &self.branches {
+            rolls.extend(branch.rolls());
+        }
+        rolls
+    }
+}
+
+impl Pipeline {
+    /// Start with a pool of dice, e.g. `8d6`
+    pub fn pool(n: i16, die: DieType) -> Pipeline {
+        Pipeline {
+            branches: vec![],
+            die,
+            n,
+            stages: vec![],
+        }
+    }
+
+    pub fn explode(self, policy: ExplodeSuccessPolicy) -> Pipeline {
+        self.stage(Stage::Explode(policy))
+    }
+
+    pub fn drop_lowest(self, n: i16) -> Pipeline {
+        self.stage(Stage::DropLowest(n))
+    }
+
+    pub fn drop_highest(self, n: i16) -> Pipeline {
+        self.stage(Stage::DropHighest(n))
+    }
+
+    pub fn keep_highest(self, n: i16) -> Pipeline {
+        self.stage(Stage::KeepHighest(n))
+    }
+
+    pub fn keep_lowest(self, n: i16) -> Pipeline {
+        self.stage(Stage::KeepLowest(n))
+    }
+
+    pub fn count_at_least(self, target: i16) -> Pipeline {
+        self.stage(Stage::CountAtLeast(target))
+    }
+
+    pub fn modifier(self, modifier: i16) -> Pipeline {
+        self.stage(Stage::Modifier(modifier))
+    }
+
+    /// Roll another pipeline after this one when the condition holds
+    pub fn then_if(mut self, condition: Condition, pipeline: Pipeline) -> Pipeline {
+        self.branches.push(Branch {
+            condition,
+            pipeline,
+        });
+        self
+    }
+
+    fn stage(mut self, stage: Stage) -> Pipeline {
+        self.stages.push(stage);
+        self
+    }
+
+    /// The flags the pool is rolled with, everything but counting
+    pub fn flags(&self) -> Result<RollFlags, RollError> {
+        let mut builder = RollFlags::builder().n(self.n).die(self.die);
+        for stage in &self.stages {
+            builder = match *stage {
+                Stage::CountAtLeast(_) => builder,
+                Stage::DropHighest(n) => builder.dh(n),
+                Stage::DropLowest(n) => builder.dl(n),
+                Stage::Explode(policy) => builder.explode(policy),
+                Stage::KeepHighest(n) => builder.kh(n),
+                Stage::KeepLowest(n) => builder.kl(n),
+                Stage::Modifier(modifier) => builder.modifier(modifier),
+            };
+        }
+        builder.build()
+    }
+
+    /// The pool written as notation, with counting as `gte`, e.g. `8d6!gte5`
+    ///
+    /// Branches can't be written in notation, and `gte` counts dice before they're kept rather than
+    /// after, so pipelines with branches or with counting after keeping or dropping have none.
+    pub fn to_notation(&self) -> Option<String> {
+        if !self.branches.is_empty() {
+            return None;
+        }
+
+        let mut flags = self.flags().ok()?;
+        for stage in &self.stages {
+            if let Stage::CountAtLeast(target) = *stage {
+                if flags.kh != 0 || flags.kl != 0 || flags.dh != 0 || flags.dl != 0 {
+                    return None;
+                }
+                flags.gte = target;
+            }
+        }
+        flags.to_notation()
+    }
+
+    /// Check the pool and every branch can be rolled, without rolling anything
+    pub fn validate(&self) -> Result<(), RollError> {
+        self.flags()?;
+        for branch in &self.branches {
+            branch.pipeline.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Roll the pool, then each branch whose condition holds
+    ///
+    /// The whole pipeline is checked first, so a branch that can't be rolled fails before any dice
+    /// are.
+    pub fn run(&self, roller: &mut Roller) -> Result<PipelineResult, RollError> {
+        self.validate()?;
+        Ok(self.run_validated(roller))
+    }
+
+    fn run_validated(&self, roller: &mut Roller) -> PipelineResult {
+        let flags = self.flags().expect("pipelines are validated before they're run");
+        let mut roll = Roll::with_roller(flags, roller);
+
+        // Counting marks dice rather than keeping them, so it goes after whatever was dropped
+        for stage in &self.stages {
+            if let Stage::CountAtLeast(target) = *stage {
+                for die in roll.dice.iter_mut().filter(|die| !die.is_dropped && die.value >= target) {
+                    die.success();
+                }
+            }
+        }
+
+        let mut result = PipelineResult {
+            branches: vec![],
+            successes: roll.successes(),
+            roll,
+        };
+        for branch in &self.branches {
+            if branch.condition.holds(&result) {
+                let rolled = branch.pipeline.run_validated(roller);
+                result.branches.push(rolled);
+            }
+        }
+        result
+    }
+}
+
+#[test]
+fn it_rolls_the_branch_when_its_condition_holds() {
+    let pipeline = Pipeline::pool(8, DieType::D6)
+        .explode(ExplodeSuccessPolicy::CompareEachDie)
+        .drop_lowest(2)
+        .count_at_least(5)
+        .then_if(Condition::SuccessesAtLeast(3), Pipeline::pool(1, DieType::D8));
+
+    // Both 6s explode, into a 3 and a 2, then the 1 and the first 2 are dropped
+    let mut roller = ScriptedRoller::new(vec![6, 2, 5, 1, 3, 5, 4, 6, 3, 2, 7]);
+    let result = pipeline.run(&mut roller).unwrap();
+    assert_eq!(result.roll.dice.len(), 10);
+    assert_eq!(result.roll.dice.iter().filter(|die| die.is_dropped).count(), 2);
+    assert_eq!(result.successes, 4);
+    assert_eq!(result.roll.value, 34);
+
+    assert_eq!(result.branches.len(), 1);
+    assert_eq!(result.branches[0].roll.value, 7);
+    let values: Vec<i32> = result.rolls().iter().map(|roll| roll.value).collect();
+    assert_eq!(values, vec![34, 7]);
+}
+
+#[test]
+fn it_skips_the_branch_when_its_condition_fails() {
+    let pipeline = Pipeline::pool(4, DieType::D6)
+        .count_at_least(5)
+        .then_if(Condition::SuccessesAtLeast(2), Pipeline::pool(1, DieType::D8));
+    let result = pipeline.run(&mut ScriptedRoller::new(vec![1, 5, 3, 4])).unwrap();
+    assert_eq!(result.successes, 1);
+    assert_eq!(result.roll.value, 13);
+    assert!(result.branches.is_empty());
+}
+
+#[test]
+fn it_rolls_the_same_dice_as_its_flags() {
+    let pipeline = Pipeline::pool(8, DieType::D6).explode(ExplodeSuccessPolicy::CompareEachDie).drop_lowest(2);
+    let seeded = pipeline.run(&mut Roller::seeded(42)).unwrap();
+    let alone = Roll::with_roller(pipeline.flags().unwrap(), &mut Roller::seeded(42));
+    let values = |roll: &Roll| roll.dice.iter().map(|die| die.value).collect::<Vec<i16>>();
+    assert_eq!(values(&seeded.roll), values(&alone));
+}
+
+#[test]
+fn it_fails_before_rolling_when_a_branch_cannot_be_rolled() {
+    let conflicting = Pipeline::pool(1, DieType::D6).keep_highest(1).keep_lowest(1);
+    let pipeline = Pipeline::pool(2, DieType::D6).then_if(Condition::TotalAtLeast(1), conflicting);
+    assert_eq!(pipeline.run(&mut ScriptedRoller::new(vec![])).err(), Some(RollError::ConflictingFlags("kh", "kl")));
+}
+
+#[test]
+fn it_serializes_its_plan() {
+    let pipeline = Pipeline::pool(8, DieType::D6)
+        .drop_lowest(2)
+        .count_at_least(5)
+        .then_if(Condition::SuccessesAtLeast(3), Pipeline::pool(1, DieType::D8));
+    assert_eq!(::serde_json::to_value(&pipeline).unwrap(), json!({
+        "branches": [{
+            "condition": { "SuccessesAtLeast": 3 },
+            "pipeline": { "branches": [], "die": "D8", "n": 1, "stages": [] }
+        }],
+        "die": "D6",
+        "n": 8,
+        "stages": [
+            { "DropLowest": 2 },
+            { "CountAtLeast": 5 }
+        ]
+    }));
+}
+
+#[test]
+fn it_writes_a_pool_as_notation() {
+    let pool = Pipeline::pool(8, DieType::D6).explode(ExplodeSuccessPolicy::CompareEachDie).count_at_least(5).modifier(1);
+    assert_eq!(pool.to_notation(), Some("8d6gte5!+1".to_string()));
+    let parsed = ::parser::parse(&pool.to_notation().unwrap()).unwrap();
+    assert_eq!((parsed[0].n, parsed[0].gte, parsed[0].explode), (8, 5, true));
+
+    // Neither branches nor counting the kept dice have notation
+    let branching = Pipeline::pool(1, DieType::D20).then_if(Condition::TotalAtLeast(15), Pipeline::pool(2, DieType::D6));
+    assert_eq!(branching.to_notation(), None);
+    assert_eq!(Pipeline::pool(4, DieType::D6).keep_highest(3).to_notation(), Some("4d6kh3".to_string()));
+    assert_eq!(Pipeline::pool(4, DieType::D6).keep_highest(3).count_at_least(5).to_notation(), None);
+}
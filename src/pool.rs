@@ -0,0 +1,262 @@
+use die::{Die, DieType};
+use roll::RollError;
+use roller::Roller;
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+/// How a persistent pool loses dice after each roll
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum DepletionRule {
+    /// Remove dice that show this face
+    RemoveOn(i16),
+
+    /// Replace dice showing their maximum face with the next smaller die, removing a d4
+    DowngradeOnMax,
+}
+
+/// A die that was removed or swapped for a smaller one
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PoolChange {
+    /// Position of the die in the pool before the roll
+    pub index: usize,
+
+    /// The type of die before the change
+    pub from: DieType,
+
+    /// The type of die after the change, none if the die was removed
+    pub to: Option<DieType>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum PoolRoll {
+    /// The pool had no dice left to roll
+    Empty,
+
+    /// The dice that were rolled and what happened to the pool because of them
+    Rolled {
+        dice: Vec<Die>,
+        changes: Vec<PoolChange>,
+    },
+}
+
+/// Dice of several types rolled together, e.g. 2d6, 1d8 and 3d4 of mixed damage
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CupResult {
+    /// Every die in the cup, in the order they were asked for
+    pub dice: Vec<Die>,
+
+    /// Sum of the dice of each type
+    pub subtotals: BTreeMap<DieType, i32>,
+
+    /// Sum of every die
+    pub total: i32,
+}
+
+/// Written as each type of die and its values, then the total, e.g. `2d6 [3, 4] + 1d8 [5] = 12`
+impl fmt::Display for CupResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut types: Vec<DieType> = vec![];
+        for die in &self.dice {
+            if !types.contains(&die.die) {
+                types.push(die.die);
+            }
+        }
+
+        let groups: Vec<String> = types.iter().map(|die_type| {
+            let values: Vec<String> = self.dice.iter()
+                .filter(|die| die.die == *die_type)
+                .map(|die| die.value.to_string())
+                .collect();
+            format!("{}{} [{}]", values.len(), die_type.notation(), values.join(", "))
+        }).collect();
+        write!(f, "{} = {}", groups.join(" + "), self.total)
+    }
+}
+
+/// A pool of dice that shrinks as it is rolled, kept between rolls and sessions
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PersistentPool {
+    /// The dice currently in the pool
+    pub dice: Vec<DieType>,
+
+    /// Rules applied to each die after rolling, the first matching rule wins
+    pub rules: Vec<DepletionRule>,
+}
+
+impl PersistentPool {
+    pub fn new(n: usize, die: DieType, rules: Vec<DepletionRule>) -> PersistentPool {
+        PersistentPool {
+            dice: vec![die; n],
+            rules,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dice.is_empty()
+    }
+
+    /// Roll the current pool, then remove or downgrade dice for the next roll
+    ///
+    /// The pool is left as it was when the roller stops part way through.
+    pub fn roll(&mut self, roller: &mut Roller) -> PoolRoll {
+        if self.is_empty() {
+            return PoolRoll::Empty;
+        }
+
+        let mut dice = vec![];
+        let mut changes = vec![];
+        let mut remaining = vec![];
+        for (index, die_type) in self.dice.iter().enumerate() {
+            if roller.should_stop() {
+                break;
+            }
+
+            let mut die = Die::new(*die_type);
+            let value = die.roll_with(roller);
+
+            let change = self.rules.iter().filter_map(|rule| match rule {
+                &DepletionRule::RemoveOn(face) if value == face => Some(None),
+                &DepletionRule::DowngradeOnMax if value == die.max => Some(die_type.smaller()),
+                _ => None,
+            }).next();
+
+            match change {
+                Some(to) => {
+                    changes.push(PoolChange {
+                        index,
+                        from: *die_type,
+                        to,
+                    });
+                    if let Some(smaller) = to {
+                        remaining.push(smaller);
+                    }
+                },
+                None => remaining.push(*die_type),
+            }
+            dice.push(die);
+        }
+
+        if roller.stopped().is_ok() {
+            self.dice = remaining;
+        }
+        PoolRoll::Rolled {
+            dice,
+            changes,
+        }
+    }
+
+    /// Roll the pool the same as `roll`, failing instead if the roller stops before every die is rolled
+    pub fn try_roll(&mut self, roller: &mut Roller) -> Result<PoolRoll, RollError> {
+        roller.stopped()?;
+        let roll = self.roll(roller);
+        roller.stopped()?;
+        Ok(roll)
+    }
+}
+
+#[test]
+fn it_can_remove_dice_from_a_pool() {
+    let mut pool = PersistentPool::new(4, DieType::D6, vec![DepletionRule::RemoveOn(1)]);
+    let mut roller = ScriptedRoller::new(vec![1, 4, 1, 6]);
+    match pool.roll(&mut roller) {
+        PoolRoll::Rolled { dice, changes } => {
+            assert_eq!(dice.len(), 4);
+            assert_eq!(changes, vec![
+                PoolChange { index: 0, from: DieType::D6, to: None },
+                PoolChange { index: 2, from: DieType::D6, to: None },
+            ]);
+        },
+        PoolRoll::Empty => panic!("pool should not be empty"),
+    }
+    assert_eq!(pool.dice, vec![DieType::D6, DieType::D6]);
+}
+
+#[test]
+fn it_can_downgrade_dice_in_a_pool() {
+    let mut pool = PersistentPool {
+        dice: vec![DieType::D8, DieType::D6, DieType::D4],
+        rules: vec![DepletionRule::RemoveOn(1), DepletionRule::DowngradeOnMax],
+    };
+    let mut roller = ScriptedRoller::new(vec![8, 6, 4]);
+    pool.roll(&mut roller);
+    assert_eq!(pool.dice, vec![DieType::D6, DieType::D4]);
+
+    // Downgrading past a d4 removes the die
+    let mut roller = ScriptedRoller::new(vec![2, 4]);
+    match pool.roll(&mut roller) {
+        PoolRoll::Rolled { changes, .. } => {
+            assert_eq!(changes, vec![PoolChange { index: 1, from: DieType::D4, to: None }]);
+        },
+        PoolRoll::Empty => panic!("pool should not be empty"),
+    }
+    assert_eq!(pool.dice, vec![DieType::D6]);
+}
+
+#[test]
+fn it_can_roll_an_empty_pool() {
+    let mut pool = PersistentPool::new(1, DieType::D4, vec![DepletionRule::RemoveOn(1)]);
+    let mut roller = ScriptedRoller::new(vec![1]);
+    pool.roll(&mut roller);
+    assert!(pool.is_empty());
+
+    match pool.roll(&mut roller) {
+        PoolRoll::Empty => {},
+        PoolRoll::Rolled { .. } => panic!("pool should be empty"),
+    }
+}
+
+#[test]
+fn it_can_serialize_a_pool() {
+    use serde_json;
+
+    let pool = PersistentPool {
+        dice: vec![DieType::D8, DieType::D4],
+        rules: vec![DepletionRule::DowngradeOnMax],
+    };
+    let json = serde_json::to_string(&pool).unwrap();
+    let restored: PersistentPool = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored, pool);
+}
+
+#[test]
+fn it_can_roll_a_cup() {
+    let mut roller = ScriptedRoller::new(vec![3, 4, 5, 1, 2, 4]);
+    let cup = roller.roll_cup(&[(DieType::D6, 2), (DieType::D8, 1), (DieType::D4, 3)]);
+    assert_eq!(cup.dice.len(), 6);
+    assert_eq!(cup.subtotals.get(&DieType::D6), Some(&7));
+    assert_eq!(cup.subtotals.get(&DieType::D8), Some(&5));
+    assert_eq!(cup.subtotals.get(&DieType::D4), Some(&7));
+    assert_eq!(cup.total, 19);
+    assert_eq!(cup.to_string(), "2d6 [3, 4] + 1d8 [5] + 3d4 [1, 2, 4] = 19");
+
+    // The same type asked for twice shares a subtotal
+    let cup = ScriptedRoller::new(vec![6, 2, 1]).roll_cup(&[(DieType::D6, 1), (DieType::D4, 1), (DieType::D6, 1)]);
+    assert_eq!(cup.subtotals.get(&DieType::D6), Some(&7));
+    assert_eq!(cup.to_string(), "2d6 [6, 1] + 1d4 [2] = 9");
+}
+
+#[test]
+fn it_can_roll_an_empty_cup() {
+    let cup = ScriptedRoller::new(vec![]).roll_cup(&[]);
+    assert!(cup.dice.is_empty());
+    assert!(cup.subtotals.is_empty());
+    assert_eq!(cup.total, 0);
+
+    // Types asked for with no dice don't show up
+    let cup = ScriptedRoller::new(vec![]).roll_cup(&[(DieType::D20, 0)]);
+    assert!(cup.subtotals.is_empty());
+}
+
+#[test]
+fn it_can_roll_fate_and_custom_dice_in_a_cup() {
+    let cup = ScriptedRoller::new(vec![1, -1, 1, 12]).roll_cup(&[(DieType::Fate, 3), (DieType::Other, 1), (DieType::D12, 1)]);
+    assert_eq!(cup.subtotals.get(&DieType::Fate), Some(&1));
+
+    // Other dice have no faces of their own, so they land on 0 without using a value
+    assert_eq!(cup.subtotals.get(&DieType::Other), Some(&0));
+    assert_eq!(cup.subtotals.get(&DieType::D12), Some(&12));
+    assert_eq!(cup.total, 13);
+    assert_eq!(cup.to_string(), "3dF [1, -1, 1] + 1d? [0] + 1d12 [12] = 13");
+}
@@ -0,0 +1,348 @@
+use layers::{roll_with_layers, OptionLayer, Provenance};
+use limits::Limits;
+use request::{OwnedRollRequest, RepeatedRoll, RollRequest};
+use roll::{Roll, RollError};
+use roller::Roller;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+#[cfg(test)]
+use layers::AdvantageOutcome;
+#[cfg(test)]
+use serde_json;
+#[cfg(test)]
+use testing::ScriptedRoller;
+
+#[derive(Debug, PartialEq)]
+pub enum PresetError {
+    /// Nothing has been saved to the slot
+    MissingSlot(u16),
+
+    /// This many slots are already filled, the most the store can have
+    TooManySlots(usize),
+
+    /// A word of the overrides that isn't an option or a modifier, e.g. `adv` misspelled
+    UnknownOverride(String),
+
+    /// The preset couldn't be rolled, e.g. its notation is past the limits
+    Roll(RollError),
+}
+
+impl From<RollError> for PresetError {
+    fn from(err: RollError) -> PresetError {
+        PresetError::Roll(err)
+    }
+}
+
+/// A roll saved to a slot with a name to show for it, e.g. `longsword attack` for `1d20+7`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Preset {
+    pub name: String,
+
+    #[serde(deserialize_with = "owned_request")]
+    pub request: OwnedRollRequest,
+}
+
+/// What to change about a preset for one roll of it, e.g. `adv +2` for advantage and 2 more
+///
+/// The options are a layer of the request, merged by `ResolvedOptions::merge`, so advantage and
+/// disadvantage together are a straight roll.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct PartialOptions {
+    /// Sources of advantage
+    #[serde(default)]
+    pub advantage: u8,
+
+    /// Sources of disadvantage
+    #[serde(default)]
+    pub disadvantage: u8,
+
+    /// Explode the dice on their highest face, or stop them exploding, none to leave them be
+    #[serde(default)]
+    pub explode: Option<bool>,
+
+    /// Added to the preset's modifiers, in order
+    #[serde(default)]
+    pub modifiers: Vec<i16>,
+}
+
+impl PartialOptions {
+    pub fn new() -> PartialOptions {
+        PartialOptions::default()
+    }
+
+    /// Read overrides written after a slot, e.g. `adv +2`
+    ///
+    /// Words are `adv` or `advantage`, `dis` or `disadvantage`, `explode` or `noexplode`, and
+    /// modifiers with their sign, matched whatever their case.
+    pub fn parse(words: &str) -> Result<PartialOptions, PresetError> {
+        let mut options = PartialOptions::new();
+        for word in words.split_whitespace() {
+            options = match word.to_lowercase().as_str() {
+                "adv" | "advantage" => options.advantage(),
+                "dis" | "disadvantage" => options.disadvantage(),
+                "explode" => options.explode(true),
+                "noexplode" => options.explode(false),
+                signed if signed.starts_with(|c: char| c == '+' || c == '-') => match signed.parse() {
+                    Ok(modifier) => options.modifier(modifier),
+                    Err(_) => return Err(PresetError::UnknownOverride(word.to_string())),
+                },
+                _ => return Err(PresetError::UnknownOverride(word.to_string())),
+            };
+        }
+        Ok(options)
+    }
+
+    /// Add a source of advantage
+    pub fn advantage(mut self) -> PartialOptions {
+        self.advantage = self.advantage.saturating_add(1);
+        self
+    }
+
+    /// Add a source of disadvantage
+    pub fn disadvantage(mut self) -> PartialOptions {
+        self.disadvantage = self.disadvantage.saturating_add(1);
+        self
+    }
+
+    pub fn explode(mut self, explode: bool) -> PartialOptions {
+        self.explode = Some(explode);
+        self
+    }
+
+    /// Add a modifier to the total, can be called more than once
+    pub fn modifier(mut self, modifier: i16) -> PartialOptions {
+        self.modifiers.push(modifier);
+        self
+    }
+
+    /// The options as a layer of the request
+    pub fn layer(&self) -> OptionLayer {
+        OptionLayer {
+            provenance: Provenance::Request,
+            advantage: self.advantage,
+            disadvantage: self.disadvantage,
+            explode: self.explode,
+        }
+    }
+}
+
+/// A participant's presets in numbered slots, e.g. slot 1 for their usual attack
+///
+/// Slots are kept in order of their number, up to `max_slots` of them. The store is kept in the
+/// participant's `Session` and saved with it, while the cap is set by whoever runs the presets,
+/// e.g. from `max_preset_slots` in the config, never read with them.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PresetStore {
+    /// Most slots that can be filled
+    #[serde(skip, default = "default_max_slots")]
+    max_slots: usize,
+
+    /// Presets by slot
+    #[serde(default)]
+    slots: BTreeMap<u16, Preset>,
+}
+
+impl Default for PresetStore {
+    fn default() -> PresetStore {
+        PresetStore {
+            max_slots: default_max_slots(),
+            slots: BTreeMap::new(),
+        }
+    }
+}
+
+impl PresetStore {
+    pub fn new() -> PresetStore {
+        PresetStore::default()
+    }
+
+    pub fn with_max_slots(mut self, max_slots: usize) -> PresetStore {
+        self.max_slots = max_slots;
+        self
+    }
+
+    pub fn max_slots(&self) -> usize {
+        self.max_slots
+    }
+
+    /// Save a roll to one of the slots, returning what was in it before
+    ///
+    /// Replacing a filled slot always fits, while filling another fails once `max_slots` are
+    /// filled. Nothing is checked about the notation until it's rolled.
+    pub fn set_slot(&mut self, slot: u16, name: &str, request: RollRequest) -> Result<Option<Preset>, PresetError> {
+        if !self.slots.contains_key(&slot) && self.slots.len() >= self.max_slots {
+            return Err(PresetError::TooManySlots(self.max_slots));
+        }
+        Ok(self.slots.insert(slot, Preset {
+            name: name.to_string(),
+            request: request.into_owned(),
+        }))
+    }
+
+    pub fn get(&self, slot: u16) -> Option<&Preset> {
+        self.slots.get(&slot)
+    }
+
+    /// The filled slots, lowest first
+    pub fn list(&self) -> Vec<(u16, &Preset)> {
+        self.slots.iter().map(|(slot, preset)| (*slot, preset)).collect()
+    }
+
+    /// Empty one of the slots, returning what was in it
+    pub fn remove(&mut self, slot: u16) -> Option<Preset> {
+        self.slots.remove(&slot)
+    }
+
+    /// Roll the preset in one of the slots with the overrides, as many times as its `repeat`
+    ///
+    /// The overrides' modifiers are added to the preset's, and the roll is checked against the
+    /// limits with them. Their options are merged as a layer of the request with
+    /// `roll_with_layers`, so each roll keeps how they were applied as its `options`. The dice of
+    /// every repeat together have to stay within the limits, the same as
+    /// `RollRequest::roll_repeated`, and presets with a seed roll with it.
+    pub fn roll_slot(&self, slot: u16, overrides: &PartialOptions, limits: &Limits, roller: &mut Roller)
+                     -> Result<RepeatedRoll, PresetError> {
+        let preset = self.get(slot).ok_or(PresetError::MissingSlot(slot))?;
+        let repeat = preset.request.repeat.unwrap_or(1);
+        if repeat == 0 {
+            return Err(PresetError::Roll(RollError::NoRepeats));
+        }
+        let mut flags = preset.request.checked_flags(limits)?;
+        for modifier in &overrides.modifiers {
+            flags.modifiers.push(*modifier);
+            flags.equation += &format!("{:+}", modifier);
+        }
+        limits.check(&flags)?;
+        let dice = flags.n.max(0) as i32 * repeat as i32;
+        if dice > limits.max_dice as i32 {
+            let dice = dice.min(i16::max_value() as i32) as i16;
+            return Err(PresetError::Roll(RollError::TooManyDice(dice, limits.max_dice)));
+        }
+
+        let mut seeded = preset.request.seed.map(Roller::seeded);
+        let roller = seeded.as_mut().unwrap_or(roller);
+        let layers = [overrides.layer()];
+        let rolls: Vec<Roll> = (0..repeat).map(|_| roll_with_layers(flags.clone(), &layers, roller)).collect();
+        let totals: Vec<i32> = rolls.iter().map(|roll| roll.value).collect();
+        Ok(RepeatedRoll {
+            max: *totals.iter().max().unwrap(),
+            min: *totals.iter().min().unwrap(),
+            rolls,
+            sum: totals.iter().map(|total| *total as i64).sum(),
+            totals,
+        })
+    }
+}
+
+fn default_max_slots() -> usize {
+    10
+}
+
+/// A request read from JSON with its strings copied, as a preset outlives what it was read from
+fn owned_request<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OwnedRollRequest, D::Error> {
+    RollRequest::deserialize(deserializer).map(RollRequest::into_owned)
+}
+
+#[test]
+fn it_rolls_a_preset_with_advantage_on_top() {
+    let mut store = PresetStore::new();
+    store.set_slot(1, "longsword attack", RollRequest::new("1d20+7")).unwrap();
+    let overrides = PartialOptions::parse("adv +2").unwrap();
+    assert_eq!(overrides, PartialOptions::new().advantage().modifier(2));
+
+    let rolled = store.roll_slot(1, &overrides, &Limits::default(), &mut ScriptedRoller::new(vec![4, 15])).unwrap();
+    let roll = &rolled.rolls[0];
+    assert_eq!((roll.dice.len(), roll.value), (2, 24));
+    assert_eq!(roll.equation, "1d20+7+2");
+    let options = roll.options.clone().unwrap();
+    assert_eq!((options.advantage, options.is_applied), (AdvantageOutcome::Advantage, true));
+
+    // Advantage and disadvantage cancel out, the same as any other layers
+    let straight = PartialOptions::parse("ADV dis").unwrap();
+    let rolled = store.roll_slot(1, &straight, &Limits::default(), &mut ScriptedRoller::new(vec![4])).unwrap();
+    assert_eq!((rolled.rolls[0].dice.len(), rolled.totals), (1, vec![11]));
+    assert_eq!(PartialOptions::parse("adv +two").err(), Some(PresetError::UnknownOverride("+two".to_string())));
+}
+
+#[test]
+fn it_rolls_a_preset_as_many_times_as_it_repeats() {
+    let mut store = PresetStore::new();
+    store.set_slot(1, "magic missile", RollRequest {
+        repeat: Some(3),
+        ..RollRequest::new("1d4+1")
+    }).unwrap();
+    let overrides = PartialOptions::new().modifier(1);
+    let rolled = store.roll_slot(1, &overrides, &Limits::default(), &mut ScriptedRoller::new(vec![1, 4, 2])).unwrap();
+    assert_eq!(rolled.totals, vec![3, 6, 4]);
+    assert_eq!((rolled.min, rolled.max, rolled.sum), (3, 6, 13));
+    assert!(rolled.rolls.iter().all(|roll| roll.equation == "1d4+1+1"));
+
+    // Every repeat's dice count against the limits together
+    store.set_slot(2, "swarm", RollRequest {
+        repeat: Some(30),
+        ..RollRequest::new("4d6")
+    }).unwrap();
+    assert_eq!(store.roll_slot(2, &overrides, &Limits::default(), &mut Roller::new()).err(),
+               Some(PresetError::Roll(RollError::TooManyDice(120, 100))));
+    store.set_slot(3, "nothing", RollRequest {
+        repeat: Some(0),
+        ..RollRequest::new("1d6")
+    }).unwrap();
+    assert_eq!(store.roll_slot(3, &overrides, &Limits::default(), &mut Roller::new()).err(),
+               Some(PresetError::Roll(RollError::NoRepeats)));
+}
+
+#[test]
+fn it_caps_the_slots_filled() {
+    let mut store = PresetStore::new().with_max_slots(3);
+    store.set_slot(1, "longsword attack", RollRequest::new("1d20+7")).unwrap();
+    store.set_slot(3, "damage", RollRequest::new("1d8+4")).unwrap();
+    store.set_slot(2, "initiative", RollRequest::new("1d20+2")).unwrap();
+    assert_eq!(store.set_slot(4, "stealth", RollRequest::new("1d20+5")).err(), Some(PresetError::TooManySlots(3)));
+
+    // Replacing a slot still fits
+    let replaced = store.set_slot(1, "greatsword attack", RollRequest::new("1d20+6")).unwrap();
+    assert_eq!(replaced.map(|preset| preset.name), Some("longsword attack".to_string()));
+    assert_eq!(store.list().iter().map(|&(slot, preset)| (slot, preset.name.as_str())).collect::<Vec<_>>(),
+               vec![(1, "greatsword attack"), (2, "initiative"), (3, "damage")]);
+
+    assert_eq!(store.remove(2).map(|preset| preset.name), Some("initiative".to_string()));
+    assert!(store.set_slot(4, "stealth", RollRequest::new("1d20+5")).is_ok());
+}
+
+#[test]
+fn it_keeps_presets_but_not_the_cap_when_saved() {
+    let mut store = PresetStore::new().with_max_slots(3);
+    store.set_slot(2, "fireball", RollRequest {
+        comment: Some("dex save".into()),
+        seed: Some(42),
+        ..RollRequest::new("8d6[fire]")
+    }).unwrap();
+    let json = serde_json::to_value(&store).unwrap();
+    assert!(json.get("max_slots").is_none());
+    let read: PresetStore = serde_json::from_value(json).unwrap();
+    assert_eq!(read.list(), store.list());
+    assert_eq!(read.max_slots(), 10);
+
+    // The cap can't be raised by editing the saved store either
+    let edited: PresetStore = serde_json::from_str(r#"{"max_slots": 500, "slots": {}}"#).unwrap();
+    assert_eq!(edited.max_slots(), 10);
+
+    // A seeded preset rolls the same dice after it's read back
+    let rolled = |store: &PresetStore| store.roll_slot(2, &PartialOptions::new(), &Limits::default(),
+                                                       &mut Roller::new()).unwrap().totals;
+    assert_eq!(rolled(&read), rolled(&store));
+}
+
+#[test]
+fn it_fails_to_roll_an_empty_slot() {
+    let mut store = PresetStore::new();
+    store.set_slot(1, "longsword attack", RollRequest::new("1d20+7")).unwrap();
+    assert_eq!(store.roll_slot(2, &PartialOptions::new(), &Limits::default(), &mut Roller::new()).err(),
+               Some(PresetError::MissingSlot(2)));
+
+    // A filled slot fails with why its roll couldn't be made
+    store.set_slot(2, "too many", RollRequest::new("1000d6")).unwrap();
+    assert_eq!(store.roll_slot(2, &PartialOptions::new(), &Limits::default(), &mut Roller::new()).err(),
+               Some(PresetError::Roll(RollError::TooManyDice(1000, 100))));
+}
@@ -0,0 +1,237 @@
+#[cfg(feature = "server")]
+use rocket::Outcome;
+#[cfg(feature = "server")]
+use rocket::State;
+#[cfg(feature = "server")]
+use rocket::http::Status;
+#[cfg(feature = "server")]
+use rocket::request::{self, FromRequest, Request};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+#[cfg(test)]
+use std::time::Duration;
+
+/// Buckets kept by the in-memory limiter before full ones are forgotten
+const MAX_BUCKETS: usize = 10000;
+
+/// How many requests a client can make, as a bucket of tokens that refills over time
+///
+/// Every request takes a token, so a client can make `capacity` requests at once and then
+/// `refill_per_second` requests a second after that.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenBucket {
+    pub capacity: u32,
+
+    pub refill_per_second: u32,
+}
+
+/// A request that has to wait before it can be made
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denied {
+    /// Seconds until the client's bucket has a token again
+    pub retry_after: u64,
+}
+
+/// Counts requests by client, e.g. in memory or shared between servers
+pub trait RateLimiter: Send + Sync {
+    /// Take a token from the key's bucket
+    fn acquire(&self, key: &str) -> Result<(), Denied>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets kept in memory, so each server counts requests on its own
+pub struct MemoryLimiter {
+    bucket: TokenBucket,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryLimiter {
+    pub fn new(bucket: TokenBucket) -> MemoryLimiter {
+        MemoryLimiter {
+            bucket,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn acquire_at(&self, key: &str, now: Instant) -> Result<(), Denied> {
+        let capacity = self.bucket.capacity as f64;
+        let rate = self.bucket.refill_per_second.max(1) as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Full buckets are the same as no bucket, so forget them when there are too many
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| bucket.tokens + elapsed(bucket.updated, now) * rate < capacity);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + elapsed(bucket.updated, now) * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Denied {
+                retry_after: ((1.0 - bucket.tokens) / rate).ceil().max(1.0) as u64,
+            })
+        }
+    }
+}
+
+impl RateLimiter for MemoryLimiter {
+    fn acquire(&self, key: &str) -> Result<(), Denied> {
+        self.acquire_at(key, Instant::now())
+    }
+}
+
+fn elapsed(since: Instant, now: Instant) -> f64 {
+    let elapsed = now.duration_since(since);
+    elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9
+}
+
+/// The limiter the HTTP API checks requests against, managed when rate limiting is configured
+#[cfg(feature = "server")]
+pub struct RateLimits {
+    /// Requests turned away by key, so the 429 catcher can say when to retry
+    denials: Mutex<HashMap<String, u64>>,
+
+    limiter: Box<dyn RateLimiter>,
+}
+
+#[cfg(feature = "server")]
+impl RateLimits {
+    pub fn new(limiter: Box<dyn RateLimiter>) -> RateLimits {
+        RateLimits {
+            denials: Mutex::new(HashMap::new()),
+            limiter,
+        }
+    }
+
+    /// Seconds the client that was just turned away has to wait, forgetting the denial
+    pub fn take_retry_after(&self, request: &Request) -> Option<u64> {
+        self.denials.lock().unwrap().remove(&client_key(request))
+    }
+}
+
+/// Say who a request is from: its address, and its API token when it has one
+///
+/// Anyone can send a token, so one stolen or made up only ever shares a bucket with requests from
+/// the same address. Tokens are hashed so they're never stored by the limiter.
+#[cfg(feature = "server")]
+pub fn client_key(request: &Request) -> String {
+    let address = match request.remote() {
+        Some(address) => format!("ip:{}", address.ip()),
+        None => "ip:unknown".to_string(),
+    };
+    match bearer_token(request) {
+        Some(token) => format!("token:{}:{}", hash_token(token), address),
+        None => address,
+    }
+}
+
+/// The API token in the request's `Authorization: Bearer` header
+#[cfg(feature = "server")]
+pub fn bearer_token<'r>(request: &'r Request) -> Option<&'r str> {
+    request.headers().get_one("Authorization")
+        .and_then(|authorization| if authorization.starts_with("Bearer ") { Some(&authorization[7..]) } else { None })
+}
+
+/// Hash an API token, so it can be told apart from others without being kept
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Guard for routes that count against the client's rate limit, failing with a 429 once it's used up
+#[cfg(feature = "server")]
+pub struct Throttle;
+
+#[cfg(feature = "server")]
+impl<'a, 'r> FromRequest<'a, 'r> for Throttle {
+    type Error = Denied;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Throttle, Denied> {
+        match throttle(request, client_key(request)) {
+            Ok(()) => Outcome::Success(Throttle),
+            Err(denied) => Outcome::Failure((Status::TooManyRequests, denied)),
+        }
+    }
+}
+
+/// Guard for requests that are cheap to answer, e.g. checking notation as it's typed
+///
+/// They're counted in a bucket of their own, so they never use up the client's rolls.
+#[cfg(feature = "server")]
+pub struct LightThrottle;
+
+#[cfg(feature = "server")]
+impl<'a, 'r> FromRequest<'a, 'r> for LightThrottle {
+    type Error = Denied;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<LightThrottle, Denied> {
+        match throttle(request, format!("light:{}", client_key(request))) {
+            Ok(()) => Outcome::Success(LightThrottle),
+            Err(denied) => Outcome::Failure((Status::TooManyRequests, denied)),
+        }
+    }
+}
+
+/// Take a token from the key's bucket, when requests are rate limited
+#[cfg(feature = "server")]
+fn throttle(request: &Request, key: String) -> Result<(), Denied> {
+    let limits = match request.guard::<State<RateLimits>>() {
+        Outcome::Success(limits) => limits,
+        _ => return Ok(()),
+    };
+    limits.limiter.acquire(&key).map_err(|denied| {
+        // The catcher looks denials up by the client, whichever bucket turned them away
+        limits.denials.lock().unwrap().insert(client_key(request), denied.retry_after);
+        denied
+    })
+}
+
+#[cfg(test)]
+fn limiter() -> MemoryLimiter {
+    MemoryLimiter::new(TokenBucket {
+        capacity: 3,
+        refill_per_second: 1,
+    })
+}
+
+#[test]
+fn it_allows_a_burst_up_to_the_capacity() {
+    let limiter = limiter();
+    let now = Instant::now();
+    for _ in 0..3 {
+        assert_eq!(limiter.acquire_at("ip:127.0.0.1", now), Ok(()));
+    }
+    assert_eq!(limiter.acquire_at("ip:127.0.0.1", now), Err(Denied { retry_after: 1 }));
+
+    // Other clients have their own bucket
+    assert_eq!(limiter.acquire_at("ip:127.0.0.2", now), Ok(()));
+}
+
+#[test]
+fn it_refills_buckets_over_time() {
+    let limiter = limiter();
+    let now = Instant::now();
+    for _ in 0..3 {
+        limiter.acquire_at("ip:127.0.0.1", now).unwrap();
+    }
+    assert!(limiter.acquire_at("ip:127.0.0.1", now + Duration::from_millis(500)).is_err());
+    assert_eq!(limiter.acquire_at("ip:127.0.0.1", now + Duration::from_millis(1500)), Ok(()));
+
+    // A bucket never holds more than its capacity, however long it's left
+    let later = now + Duration::from_secs(60);
+    for _ in 0..3 {
+        limiter.acquire_at("ip:127.0.0.1", later).unwrap();
+    }
+    assert!(limiter.acquire_at("ip:127.0.0.1", later).is_err());
+}
@@ -0,0 +1,195 @@
+use chrono::DateTime;
+use chrono::prelude::Utc;
+use ed25519_dalek::{Signature, Signer};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+use fairness::{commit_roll, verify_commitment};
+use integrity::{self, Violation};
+use roll::Roll;
+use serde_json;
+#[cfg(test)]
+use testing::{assert_roll_total, ScriptedRoller};
+
+/// Version of the payload written by `Roll::export_receipt`
+pub const RECEIPT_VERSION: u32 = 1;
+
+/// A roll exported as a single signed file, so it can't be edited after it's handed out
+///
+/// The payload is the canonical serialization of a `VerifiedRoll`: compact JSON with no
+/// whitespace, each object's fields in the order they're declared, and only strings, integers,
+/// booleans and nulls, as rolls have no floats. Timestamps are RFC 3339 strings. The signature is
+/// Ed25519 over the payload's UTF-8 bytes exactly as stored, so a receipt keeps verifying after
+/// later versions change how rolls are serialized.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Receipt {
+    /// The signed JSON
+    pub payload: String,
+
+    /// Public key of the signer, as lowercase hex
+    pub public_key: String,
+
+    /// Signature of the payload, as lowercase hex
+    pub signature: String,
+}
+
+/// What a receipt says, once its signature has been checked
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VerifiedRoll {
+    /// Commitment to the roll's dice, see `fairness::commit_roll`
+    pub commitment: String,
+
+    /// When the receipt was exported
+    pub exported_at: DateTime<Utc>,
+
+    /// Index of the roll when its seed was derived from a server and client seed, for replaying it
+    pub nonce: Option<u64>,
+
+    /// The notation that was rolled
+    pub request: String,
+
+    /// The roll, along with the options it was rolled with
+    pub roll: Roll,
+
+    pub version: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReceiptError {
+    /// The roll doesn't match the commitment in the receipt
+    CommitmentMismatch,
+
+    /// The signature doesn't match the payload and key, e.g. the payload was edited
+    InvalidSignature,
+
+    /// The roll couldn't have been rolled, e.g. it was edited before it was signed
+    InvalidRoll(Vec<Violation>),
+
+    /// The signature isn't hex or the payload isn't a receipt
+    Malformed,
+
+    /// The receipt was written by a newer version
+    UnsupportedVersion(u32),
+}
+
+impl Roll {
+    /// Export the roll as a receipt signed with the key
+    pub fn export_receipt(&self, key: &SigningKey) -> Receipt {
+        let verified = VerifiedRoll {
+            commitment: commit_roll(self),
+            exported_at: Utc::now(),
+            nonce: self.nonce,
+            request: self.equation.clone(),
+            roll: self.clone(),
+            version: RECEIPT_VERSION,
+        };
+        let payload = serde_json::to_string(&verified).expect("rolls always serialize");
+        let signature: Signature = key.sign(payload.as_bytes());
+
+        Receipt {
+            public_key: to_hex(&key.verifying_key().to_bytes()),
+            signature: to_hex(&signature.to_bytes()),
+            payload,
+        }
+    }
+}
+
+impl Receipt {
+    /// Check the receipt was signed with the key, hasn't been edited since, and holds a roll that could
+    /// have been rolled
+    pub fn verify(&self, public_key: &VerifyingKey) -> Result<VerifiedRoll, ReceiptError> {
+        let bytes = from_hex(&self.signature).ok_or(ReceiptError::Malformed)?;
+        if bytes.len() != 64 {
+            return Err(ReceiptError::Malformed);
+        }
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&bytes);
+        public_key.verify_strict(self.payload.as_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| ReceiptError::InvalidSignature)?;
+
+        let verified: VerifiedRoll = serde_json::from_str(&self.payload).map_err(|_| ReceiptError::Malformed)?;
+        if verified.version > RECEIPT_VERSION {
+            return Err(ReceiptError::UnsupportedVersion(verified.version));
+        }
+        if !verify_commitment(&verified.commitment, &verified.roll) {
+            return Err(ReceiptError::CommitmentMismatch);
+        }
+        let violations = integrity::check(&verified.roll);
+        if !violations.is_empty() {
+            return Err(ReceiptError::InvalidRoll(violations));
+        }
+        Ok(verified)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+fn signing_key() -> SigningKey {
+    SigningKey::from_bytes(&[7u8; 32])
+}
+
+#[cfg(test)]
+fn d20_roll(value: i16) -> Roll {
+    use parser::parse;
+
+    let flags = parse("1d20+3").unwrap().pop().unwrap();
+    Roll::with_roller(flags, &mut ScriptedRoller::new(vec![value]))
+}
+
+#[test]
+fn it_verifies_an_exported_receipt() {
+    let roll = d20_roll(17);
+    let receipt = roll.export_receipt(&signing_key());
+    assert_eq!(receipt.public_key, to_hex(&signing_key().verifying_key().to_bytes()));
+
+    let verified = receipt.verify(&signing_key().verifying_key()).unwrap();
+    assert_roll_total(&verified.roll, 20);
+    assert_eq!(verified.request, "1d20+3");
+    assert_eq!(verified.roll.id, roll.id);
+    assert_eq!(verified.version, RECEIPT_VERSION);
+}
+
+#[test]
+fn it_rejects_a_tampered_receipt() {
+    let receipt = d20_roll(17).export_receipt(&signing_key());
+
+    let mut tampered = receipt.clone();
+    tampered.payload = tampered.payload.replace("\"value\":20", "\"value\":23");
+    assert!(tampered.payload != receipt.payload);
+    assert_eq!(tampered.verify(&signing_key().verifying_key()).err(), Some(ReceiptError::InvalidSignature));
+
+    let other = SigningKey::from_bytes(&[8u8; 32]);
+    assert_eq!(receipt.verify(&other.verifying_key()).err(), Some(ReceiptError::InvalidSignature));
+
+    let mut truncated = receipt.clone();
+    truncated.signature.pop();
+    assert_eq!(truncated.verify(&signing_key().verifying_key()).err(), Some(ReceiptError::Malformed));
+}
+
+#[test]
+fn it_rejects_a_receipt_for_a_roll_that_couldnt_be_rolled() {
+    let mut roll = d20_roll(17);
+    roll.value = 23;
+    let receipt = roll.export_receipt(&signing_key());
+    assert_eq!(receipt.verify(&signing_key().verifying_key()).err(),
+               Some(ReceiptError::InvalidRoll(vec![Violation::WrongTotal("value", 20)])));
+}
+
+#[test]
+fn it_keeps_verifying_a_golden_receipt() {
+    // Exported by the first version of receipts, this has to verify for as long as they exist
+    let receipt: Receipt = serde_json::from_str(include_str!("../fixtures/receipt-v1.json")).unwrap();
+    let verified = receipt.verify(&signing_key().verifying_key()).unwrap();
+    assert_eq!(verified.version, 1);
+    assert_eq!(verified.request, "1d20+3");
+    assert_eq!(verified.roll.id, "roll-0");
+    assert_roll_total(&verified.roll, 20);
+}
@@ -0,0 +1,406 @@
+use fairness::commit_roll;
+use history::{find_sequence, page_rolls, HiddenRollReceipt, HistoryError, HistoryPage, HistoryQuery, LoggedRoll, RollStore};
+use rate_limit::{Denied, RateLimiter, TokenBucket};
+use redis::{self, Client, Connection, RedisError, RedisResult, Script};
+use roll::Roll;
+use serde_json;
+use std::time::Duration;
+#[cfg(test)]
+use std::env;
+#[cfg(test)]
+use uuid::Uuid;
+
+/// How long to wait for Redis before treating it as unreachable
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Take a token from a bucket kept as a hash of its tokens and when they were counted
+///
+/// Time comes from Redis rather than the caller, so servers with drifting clocks share buckets
+/// fairly. Returns 0 when a token was taken, otherwise the seconds until there's one to take.
+const TOKEN_BUCKET: &str = "
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) / 1000 * rate)
+local retry_after = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    retry_after = math.ceil((1 - tokens) / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tokens, 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate * 1000))
+return retry_after
+";
+
+/// Take a room's next sequence number, starting after its newest roll when the room has no counter
+/// yet, e.g. for rooms kept before rooms were numbered
+const NEXT_SEQUENCE: &str = "
+local sequence = redis.call('INCR', KEYS[1])
+if sequence == 1 then
+    local newest = redis.call('ZRANGE', KEYS[2], -1, -1, 'WITHSCORES')
+    if newest[2] then
+        sequence = tonumber(newest[2]) + 1
+        redis.call('SET', KEYS[1], sequence)
+    end
+end
+return sequence
+";
+
+/// What to do with a request when Redis can't be reached
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailurePolicy {
+    /// Carry on without Redis: rolls are returned without being kept, and requests aren't counted
+    Open,
+
+    /// Turn the request away: with a 503 when it needs history, and a 429 when it's rate limited
+    Closed,
+}
+
+/// A roll as it's kept in Redis
+#[derive(Deserialize, Serialize)]
+struct StoredRoll {
+    is_hidden: bool,
+    participant: String,
+    roll: Roll,
+    sequence: u64,
+}
+
+impl StoredRoll {
+    fn into_logged(self) -> LoggedRoll {
+        LoggedRoll::new(&self.participant, self.roll, self.is_hidden, self.sequence)
+    }
+}
+
+/// Rolls kept in Redis, so every server behind a load balancer shares each room's history
+///
+/// Each room is a sorted set of rolls as JSON, scored by a sequence every server takes from the
+/// room's counter in Redis, so rolls keep the order Redis received them in whichever server made
+/// them. Counters live alongside the rolls, so numbers carry on where they were after a restart.
+pub struct RedisStore {
+    client: Client,
+    failure: FailurePolicy,
+    next_sequence: Script,
+    prefix: String,
+    ttl: Option<usize>,
+}
+
+impl RedisStore {
+    /// Keep rolls under keys starting with the prefix, at a `redis://` URL
+    ///
+    /// Nothing is sent until the first roll, so this only fails when the URL isn't valid.
+    pub fn open(url: &str, prefix: &str, failure: FailurePolicy) -> Result<RedisStore, RedisError> {
+        Ok(RedisStore {
+            client: Client::open(url)?,
+            failure,
+            next_sequence: Script::new(NEXT_SEQUENCE),
+            prefix: prefix.to_string(),
+            ttl: None,
+        })
+    }
+
+    /// Forget a room's history once it's had no rolls for a number of seconds
+    pub fn with_ttl(mut self, seconds: usize) -> RedisStore {
+        self.ttl = Some(seconds);
+        self
+    }
+
+    fn room_key(&self, room: &str) -> String {
+        format!("{}:rooms:{}", self.prefix, room)
+    }
+
+    fn sequence_key(&self, room: &str) -> String {
+        format!("{}:rooms:{}:sequence", self.prefix, room)
+    }
+
+    fn push(&self, room: &str, participant: &str, mut roll: Roll, is_hidden: bool) -> RedisResult<u64> {
+        let mut connection = connect(&self.client)?;
+        let sequence: u64 = self.next_sequence.key(self.sequence_key(room))
+            .key(self.room_key(room))
+            .invoke(&mut connection)?;
+        roll.sequence = sequence;
+        let stored = StoredRoll {
+            is_hidden,
+            participant: participant.to_string(),
+            roll,
+            sequence,
+        };
+        let key = self.room_key(room);
+        let mut pipe = redis::pipe();
+        pipe.atomic().cmd("ZADD").arg(&key).arg(sequence).arg(to_json(&stored)).ignore();
+        if let Some(ttl) = self.ttl {
+            pipe.cmd("EXPIRE").arg(&key).arg(ttl).ignore();
+            pipe.cmd("EXPIRE").arg(self.sequence_key(room)).arg(ttl).ignore();
+        }
+        pipe.query::<()>(&mut connection)?;
+        Ok(sequence)
+    }
+
+    /// Every roll in the room, oldest first
+    fn load(&self, connection: &mut Connection, room: &str) -> RedisResult<Vec<(String, StoredRoll)>> {
+        let members: Vec<String> = redis::cmd("ZRANGE").arg(self.room_key(room)).arg(0).arg(-1).query(connection)?;
+        Ok(members.into_iter()
+            .filter_map(|member| {
+                let stored = serde_json::from_str(&member).ok();
+                stored.map(|stored| (member, stored))
+            })
+            .collect())
+    }
+
+    /// Go on without the write when failing open, with the fallback in place of what it returned,
+    /// otherwise give up on the request
+    fn written<T>(&self, result: RedisResult<T>, fallback: T) -> Result<T, HistoryError> {
+        match result {
+            Ok(value) => Ok(value),
+            Err(_error) => {
+                #[cfg(feature = "tracing")]
+                warn!(error = %_error, "roll not kept, redis is unavailable");
+                if self.failure == FailurePolicy::Open {
+                    Ok(fallback)
+                } else {
+                    Err(HistoryError::Unavailable)
+                }
+            },
+        }
+    }
+}
+
+impl RollStore for RedisStore {
+    /// Rolls that aren't kept because Redis is down and failing open get no number, so 0
+    fn record(&self, room: &str, participant: &str, roll: Roll) -> Result<u64, HistoryError> {
+        let result = self.push(room, participant, roll, false);
+        self.written(result, 0)
+    }
+
+    fn record_hidden(&self, room: &str, participant: &str, roll: Roll) -> Result<HiddenRollReceipt, HistoryError> {
+        let receipt = HiddenRollReceipt {
+            commitment: commit_roll(&roll),
+            id: roll.id.clone(),
+        };
+        let result = self.push(room, participant, roll, true).map(|_| receipt.clone());
+        self.written(result, receipt)
+    }
+
+    fn reveal(&self, room: &str, id: &str) -> Result<Roll, HistoryError> {
+        let mut connection = connect(&self.client).map_err(|_| HistoryError::Unavailable)?;
+        let rolls = self.load(&mut connection, room).map_err(|_| HistoryError::Unavailable)?;
+        if rolls.is_empty() {
+            return Err(HistoryError::UnknownRoom);
+        }
+        let (member, mut stored) = match rolls.into_iter().find(|&(_, ref stored)| stored.roll.id == id) {
+            Some(found) => found,
+            None => return Err(HistoryError::UnknownRoll),
+        };
+
+        if stored.is_hidden {
+            stored.is_hidden = false;
+            let key = self.room_key(room);
+            redis::pipe().atomic()
+                .cmd("ZREM").arg(&key).arg(member).ignore()
+                .cmd("ZADD").arg(&key).arg(stored.sequence).arg(to_json(&stored)).ignore()
+                .query::<()>(&mut connection)
+                .map_err(|_| HistoryError::Unavailable)?;
+        }
+        Ok(stored.roll)
+    }
+
+    fn page(&self, room: &str, query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+        let mut connection = connect(&self.client).map_err(|_| HistoryError::Unavailable)?;
+        let rolls: Vec<LoggedRoll> = self.load(&mut connection, room)
+            .map_err(|_| HistoryError::Unavailable)?
+            .into_iter()
+            .map(|(_, stored)| stored.into_logged())
+            .collect();
+        if rolls.is_empty() {
+            return Err(HistoryError::UnknownRoom);
+        }
+        page_rolls(&rolls, query)
+    }
+
+    fn by_sequence(&self, room: &str, sequence: u64) -> Result<Roll, HistoryError> {
+        let mut connection = connect(&self.client).map_err(|_| HistoryError::Unavailable)?;
+        let members: Vec<String> = redis::cmd("ZRANGEBYSCORE").arg(self.room_key(room)).arg(sequence).arg(sequence)
+            .query(&mut connection)
+            .map_err(|_| HistoryError::Unavailable)?;
+        let rolls: Vec<LoggedRoll> = members.iter()
+            .filter_map(|member| serde_json::from_str::<StoredRoll>(member).ok())
+            .map(StoredRoll::into_logged)
+            .collect();
+        if rolls.is_empty() {
+            let exists: bool = redis::cmd("EXISTS").arg(self.room_key(room)).query(&mut connection)
+                .map_err(|_| HistoryError::Unavailable)?;
+            return Err(if exists { HistoryError::UnknownRoll } else { HistoryError::UnknownRoom });
+        }
+        find_sequence(&rolls, sequence)
+    }
+
+    /// Unavailable whenever Redis doesn't answer, even when failing open
+    fn ping(&self) -> Result<(), HistoryError> {
+        let mut connection = connect(&self.client).map_err(|_| HistoryError::Unavailable)?;
+        redis::cmd("PING").query::<String>(&mut connection).map(|_| ()).map_err(|_| HistoryError::Unavailable)
+    }
+}
+
+/// Token buckets kept in Redis, so a client's requests count against one limit on every server
+pub struct RedisLimiter {
+    bucket: TokenBucket,
+    client: Client,
+    failure: FailurePolicy,
+    prefix: String,
+    script: Script,
+}
+
+impl RedisLimiter {
+    pub fn open(url: &str, prefix: &str, bucket: TokenBucket, failure: FailurePolicy) -> Result<RedisLimiter, RedisError> {
+        Ok(RedisLimiter {
+            bucket,
+            client: Client::open(url)?,
+            failure,
+            prefix: prefix.to_string(),
+            script: Script::new(TOKEN_BUCKET),
+        })
+    }
+
+    fn take(&self, key: &str) -> RedisResult<u64> {
+        let mut connection = connect(&self.client)?;
+        self.script.key(format!("{}:rate:{}", self.prefix, key))
+            .arg(self.bucket.capacity)
+            .arg(self.bucket.refill_per_second.max(1))
+            .invoke(&mut connection)
+    }
+}
+
+impl RateLimiter for RedisLimiter {
+    fn acquire(&self, key: &str) -> Result<(), Denied> {
+        match self.take(key) {
+            Ok(0) => Ok(()),
+            Ok(retry_after) => Err(Denied { retry_after }),
+            Err(_error) => {
+                #[cfg(feature = "tracing")]
+                warn!(error = %_error, "request not counted, redis is unavailable");
+                if self.failure == FailurePolicy::Open {
+                    Ok(())
+                } else {
+                    Err(Denied { retry_after: 1 })
+                }
+            },
+        }
+    }
+}
+
+fn connect(client: &Client) -> RedisResult<Connection> {
+    let connection = client.get_connection_with_timeout(TIMEOUT)?;
+    connection.set_read_timeout(Some(TIMEOUT))?;
+    connection.set_write_timeout(Some(TIMEOUT))?;
+    Ok(connection)
+}
+
+fn to_json(stored: &StoredRoll) -> String {
+    serde_json::to_string(stored).expect("rolls always serialize")
+}
+
+/// Redis to run the tests against, set with `REDIS_URL`
+///
+/// Tests that need Redis are ignored unless asked for, with `cargo test --features storage-redis --
+/// --ignored`.
+#[cfg(test)]
+fn redis_url() -> String {
+    env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string())
+}
+
+/// A prefix no other test run uses, so runs against the same Redis don't see each other's rolls
+#[cfg(test)]
+fn test_prefix() -> String {
+    format!("roll-api-test:{}", Uuid::new_v4())
+}
+
+#[test]
+#[ignore]
+fn it_shares_history_between_servers() {
+    let prefix = test_prefix();
+    let first = RedisStore::open(&redis_url(), &prefix, FailurePolicy::Closed).unwrap().with_ttl(60);
+    let second = RedisStore::open(&redis_url(), &prefix, FailurePolicy::Closed).unwrap();
+
+    let roll = Roll::from_values("1d20", &[17]).unwrap();
+    first.record("tavern", "alice", roll.clone()).unwrap();
+    let hidden = Roll::from_values("1d20", &[3]).unwrap();
+    let receipt = second.record_hidden("tavern", "gm", hidden.clone()).unwrap();
+
+    // Either server sees the roll the other kept, and nothing hidden
+    let page = second.page("tavern", &HistoryQuery::default()).unwrap();
+    assert_eq!(page.rolls.len(), 1);
+    assert_eq!(page.rolls[0].roll.id, roll.id);
+    assert_eq!(page.rolls[0].participant, "alice");
+
+    // A roll hidden on one server can be revealed on the other, in the place it was kept
+    assert_eq!(first.reveal("tavern", &receipt.id).unwrap().id, hidden.id);
+    let page = second.page("tavern", &HistoryQuery::default()).unwrap();
+    let ids: Vec<String> = page.rolls.iter().map(|logged| logged.roll.id.clone()).collect();
+    assert_eq!(ids, vec![hidden.id.clone(), roll.id.clone()]);
+
+    assert_eq!(first.page("attic", &HistoryQuery::default()).err(), Some(HistoryError::UnknownRoom));
+    assert_eq!(first.reveal("tavern", "nope").err(), Some(HistoryError::UnknownRoll));
+}
+
+#[test]
+#[ignore]
+fn it_carries_on_numbering_rolls_after_a_restart() {
+    let prefix = test_prefix();
+    let store = RedisStore::open(&redis_url(), &prefix, FailurePolicy::Closed).unwrap().with_ttl(60);
+    let first = Roll::from_values("1d20", &[17]).unwrap();
+    assert_eq!(store.record("tavern", "alice", first.clone()), Ok(1));
+    assert_eq!(store.record("tavern", "bob", Roll::from_values("1d20", &[4]).unwrap()), Ok(2));
+    drop(store);
+
+    // A new server on the same Redis picks up where the last left off, with rooms numbered apart
+    let restarted = RedisStore::open(&redis_url(), &prefix, FailurePolicy::Closed).unwrap().with_ttl(60);
+    assert_eq!(restarted.record("tavern", "alice", Roll::from_values("1d20", &[9]).unwrap()), Ok(3));
+    assert_eq!(restarted.record("attic", "alice", Roll::from_values("1d20", &[9]).unwrap()), Ok(1));
+    assert_eq!(restarted.by_sequence("tavern", 1).unwrap().id, first.id);
+    assert_eq!(restarted.by_sequence("tavern", 1).unwrap().sequence, 1);
+    assert_eq!(restarted.by_sequence("tavern", 4).err(), Some(HistoryError::UnknownRoll));
+    assert_eq!(restarted.by_sequence("cellar", 1).err(), Some(HistoryError::UnknownRoom));
+}
+
+#[test]
+#[ignore]
+fn it_shares_rate_limits_between_servers() {
+    let prefix = test_prefix();
+    let bucket = TokenBucket {
+        capacity: 2,
+        refill_per_second: 1,
+    };
+    let first = RedisLimiter::open(&redis_url(), &prefix, bucket, FailurePolicy::Closed).unwrap();
+    let second = RedisLimiter::open(&redis_url(), &prefix, bucket, FailurePolicy::Closed).unwrap();
+
+    assert_eq!(first.acquire("ip:127.0.0.1"), Ok(()));
+    assert_eq!(second.acquire("ip:127.0.0.1"), Ok(()));
+    assert_eq!(first.acquire("ip:127.0.0.1"), Err(Denied { retry_after: 1 }));
+    assert_eq!(second.acquire("ip:127.0.0.2"), Ok(()));
+}
+
+#[test]
+fn it_fails_open_or_closed_without_redis() {
+    // Nothing listens on port 1
+    let url = "redis://127.0.0.1:1/";
+    let roll = Roll::from_values("1d20", &[17]).unwrap();
+
+    let open = RedisStore::open(url, "roll-api", FailurePolicy::Open).unwrap();
+    assert_eq!(open.record("tavern", "alice", roll.clone()), Ok(0));
+    assert_eq!(open.record_hidden("tavern", "alice", roll.clone()).unwrap().id, roll.id);
+    assert_eq!(open.page("tavern", &HistoryQuery::default()).err(), Some(HistoryError::Unavailable));
+
+    let closed = RedisStore::open(url, "roll-api", FailurePolicy::Closed).unwrap();
+    assert_eq!(closed.record("tavern", "alice", roll.clone()), Err(HistoryError::Unavailable));
+
+    let bucket = TokenBucket {
+        capacity: 2,
+        refill_per_second: 1,
+    };
+    assert_eq!(RedisLimiter::open(url, "roll-api", bucket, FailurePolicy::Open).unwrap().acquire("ip:127.0.0.1"), Ok(()));
+    assert!(RedisLimiter::open(url, "roll-api", bucket, FailurePolicy::Closed).unwrap().acquire("ip:127.0.0.1").is_err());
+}
@@ -0,0 +1,238 @@
+use limits::Limits;
+use parser::parse;
+use roll::{Roll, RollError, RollFlags};
+use roller::Roller;
+use serde::{Deserialize, Deserializer};
+use std::borrow::Cow;
+#[cfg(test)]
+use serde_json;
+
+/// A roll asked for by a client, e.g. one of a batch read from JSON
+///
+/// Strings are borrowed from the JSON they're read from when they can be, so reading a big batch
+/// doesn't copy every string. Strings with escapes in them are copied. Use `into_owned` to keep a
+/// request once the JSON is gone.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RollRequest<'a> {
+    /// A note from the client to go with the roll, e.g. `sneak attack`
+    #[serde(borrow, default, deserialize_with = "borrow_optional")]
+    pub comment: Option<Cow<'a, str>>,
+
+    /// The client's own ID for the request, to match rolls up with what asked for them
+    #[serde(borrow, default, deserialize_with = "borrow_optional")]
+    pub id: Option<Cow<'a, str>>,
+
+    /// The dice to roll in TTML notation
+    #[serde(borrow)]
+    pub notation: Cow<'a, str>,
+
+    /// Who the roll is for
+    #[serde(borrow, default, deserialize_with = "borrow_optional")]
+    pub participant: Option<Cow<'a, str>>,
+
+    /// Number of times to roll the notation with `roll_repeated`, each roll kept apart
+    #[serde(default)]
+    pub repeat: Option<u16>,
+
+    /// Roll with this seed rather than at random
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// The same notation rolled several times, each roll kept apart, e.g. six `4d6kh3` for stats
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RepeatedRoll {
+    /// The highest total
+    pub max: i32,
+
+    /// The lowest total
+    pub min: i32,
+
+    /// Every roll, in the order they were made
+    pub rolls: Vec<Roll>,
+
+    /// Sum of every total
+    pub sum: i64,
+
+    /// The total of each roll, in the order they were made
+    pub totals: Vec<i32>,
+}
+
+/// A request that owns its strings, e.g. to keep it around or send it to another thread
+pub type OwnedRollRequest = RollRequest<'static>;
+
+impl<'a> RollRequest<'a> {
+    pub fn new<N: Into<Cow<'a, str>>>(notation: N) -> RollRequest<'a> {
+        RollRequest {
+            comment: None,
+            id: None,
+            notation: notation.into(),
+            participant: None,
+            repeat: None,
+            seed: None,
+        }
+    }
+
+    /// Copy every borrowed string, so the request can outlive what it was read from
+    pub fn into_owned(self) -> OwnedRollRequest {
+        RollRequest {
+            comment: self.comment.map(|comment| Cow::Owned(comment.into_owned())),
+            id: self.id.map(|id| Cow::Owned(id.into_owned())),
+            notation: Cow::Owned(self.notation.into_owned()),
+            participant: self.participant.map(|participant| Cow::Owned(participant.into_owned())),
+            repeat: self.repeat,
+            seed: self.seed,
+        }
+    }
+
+    /// Roll the last roll in the notation, checked against the limits
+    ///
+    /// Requests with a seed roll with it, the rest with the roller.
+    pub fn roll(&self, limits: &Limits, roller: &mut Roller) -> Result<Roll, RollError> {
+        let flags = self.checked_flags(limits)?;
+        let mut seeded = self.seed.map(Roller::seeded);
+        Ok(Roll::with_roller(flags, seeded.as_mut().unwrap_or(roller)))
+    }
+
+    /// Roll the notation `repeat` times, once when it isn't set
+    ///
+    /// Every roll draws its own dice from the same roller, so a seed makes all of them again. The
+    /// dice of every roll together have to stay within the limits, and repeating zero times fails
+    /// with `RollError::NoRepeats`.
+    pub fn roll_repeated(&self, limits: &Limits, roller: &mut Roller) -> Result<RepeatedRoll, RollError> {
+        let repeat = self.repeat.unwrap_or(1);
+        if repeat == 0 {
+            return Err(RollError::NoRepeats);
+        }
+        let flags = self.checked_flags(limits)?;
+        let dice = flags.n.max(0) as i32 * repeat as i32;
+        if dice > limits.max_dice as i32 {
+            return Err(RollError::TooManyDice(dice.min(i16::max_value() as i32) as i16, limits.max_dice));
+        }
+
+        let mut seeded = self.seed.map(Roller::seeded);
+        let roller = seeded.as_mut().unwrap_or(roller);
+        let rolls: Vec<Roll> = (0..repeat).map(|_| Roll::with_roller(flags.clone(), roller)).collect();
+        let totals: Vec<i32> = rolls.iter().map(|roll| roll.value).collect();
+        Ok(RepeatedRoll {
+            max: *totals.iter().max().unwrap(),
+            min: *totals.iter().min().unwrap(),
+            rolls,
+            sum: totals.iter().map(|total| *total as i64).sum(),
+            totals,
+        })
+    }
+
+    /// The last roll in the notation, checked against the limits, e.g. to change before rolling it
+    pub fn checked_flags(&self, limits: &Limits) -> Result<RollFlags, RollError> {
+        let flags = parse(&self.notation).and_then(|mut rolls| rolls.pop()).ok_or(RollError::InvalidNotation)?;
+        flags.validate()?;
+        limits.check(&flags)?;
+        Ok(flags)
+    }
+}
+
+/// An optional string borrowed from the input when it has no escapes
+///
+/// `#[serde(borrow)]` alone always copies strings inside an `Option`.
+fn borrow_optional<'de: 'a, 'a, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error> {
+    #[derive(Deserialize)]
+    struct Borrowed<'a>(#[serde(borrow)] Cow<'a, str>);
+
+    Ok(Option::<Borrowed>::deserialize(deserializer)?.map(|borrowed| borrowed.0))
+}
+
+#[cfg(test)]
+fn is_borrowed(value: &Option<Cow<str>>) -> bool {
+    match value {
+        &Some(Cow::Borrowed(_)) => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn it_borrows_strings_from_the_json() {
+    let json = r#"{"id": "r-1", "notation": "1d20+5", "participant": "alice", "comment": "sneak \"attack\""}"#;
+    let request: RollRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(request.notation, "1d20+5");
+    match request.notation {
+        Cow::Borrowed(_) => {},
+        Cow::Owned(_) => panic!("the notation should be borrowed"),
+    }
+    assert!(is_borrowed(&request.id) && is_borrowed(&request.participant));
+
+    // Escapes have to be copied out
+    assert_eq!(request.comment.as_ref().map(|comment| comment.as_ref()), Some("sneak \"attack\""));
+    assert!(!is_borrowed(&request.comment));
+}
+
+#[test]
+fn it_round_trips_borrowed_and_owned_requests() {
+    let json = r#"[{"notation": "4d6kh3", "seed": 42}, {"id": "r-2", "notation": "2d6[fire]"}]"#;
+    let batch: Vec<RollRequest> = serde_json::from_str(json).unwrap();
+    assert_eq!(batch[0].seed, Some(42));
+    assert_eq!(batch[1].comment, None);
+
+    let written = serde_json::to_string(&batch).unwrap();
+    assert_eq!(serde_json::from_str::<Vec<RollRequest>>(&written).unwrap(), batch);
+
+    // Owned requests outlive the JSON they were read from
+    let owned: Vec<OwnedRollRequest> = {
+        let json = written.clone();
+        let batch: Vec<RollRequest> = serde_json::from_str(&json).unwrap();
+        batch.into_iter().map(RollRequest::into_owned).collect()
+    };
+    assert_eq!(owned, batch);
+    assert_eq!(serde_json::to_string(&owned).unwrap(), written);
+}
+
+#[test]
+fn it_rolls_a_request() {
+    let request = RollRequest {
+        seed: Some(42),
+        ..RollRequest::new("4d6kh3")
+    };
+    let first = request.roll(&Limits::default(), &mut Roller::new()).unwrap();
+    let again = request.roll(&Limits::default(), &mut Roller::new()).unwrap();
+    assert_eq!(first.value, again.value);
+
+    assert_eq!(RollRequest::new("4dQ").roll(&Limits::default(), &mut Roller::new()).err(),
+               Some(RollError::InvalidNotation));
+    assert_eq!(RollRequest::new("1000d6").roll(&Limits::default(), &mut Roller::new()).err(),
+               Some(RollError::TooManyDice(1000, 100)));
+}
+
+#[test]
+fn it_repeats_a_roll() {
+    let request = RollRequest {
+        repeat: Some(6),
+        seed: Some(42),
+        ..RollRequest::new("4d6kh3")
+    };
+    let stats = request.roll_repeated(&Limits::default(), &mut Roller::new()).unwrap();
+    assert_eq!(stats.rolls.len(), 6);
+    assert!(stats.rolls.iter().all(|roll| roll.dice.len() == 4 && roll.dice.iter().filter(|die| die.is_dropped).count() == 1));
+    assert_eq!(stats.totals, stats.rolls.iter().map(|roll| roll.value).collect::<Vec<i32>>());
+    assert_eq!(stats.sum, stats.totals.iter().map(|total| *total as i64).sum::<i64>());
+    assert_eq!(stats.min, *stats.totals.iter().min().unwrap());
+    assert_eq!(stats.max, *stats.totals.iter().max().unwrap());
+
+    // Each roll draws its own dice, and the seed makes them all again
+    let mut roller = Roller::seeded(42);
+    let one_by_one: Vec<i32> = (0..6).map(|_| Roll::with_roller(parse("4d6kh3").unwrap().pop().unwrap(), &mut roller).value).collect();
+    assert_eq!(stats.totals, one_by_one);
+    assert_eq!(request.roll_repeated(&Limits::default(), &mut Roller::new()).unwrap().totals, stats.totals);
+}
+
+#[test]
+fn it_checks_repeats_against_the_limits() {
+    let repeated = |notation: &str, repeat: u16| RollRequest {
+        repeat: Some(repeat),
+        ..RollRequest::new(notation)
+    }.roll_repeated(&Limits::default(), &mut Roller::new()).err();
+
+    assert_eq!(repeated("4d6kh3", 0), Some(RollError::NoRepeats));
+    assert_eq!(repeated("20d6", 5), None);
+    assert_eq!(repeated("20d6", 6), Some(RollError::TooManyDice(120, 100)));
+    assert_eq!(repeated("100d6", 1000), Some(RollError::TooManyDice(i16::max_value(), 100)));
+}
@@ -1,38 +1,113 @@
+// Rolls are evaluated from notation users send, so nothing in them may panic. Tests can unwrap.
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+
 use chrono::DateTime;
 use chrono::prelude::Utc;
-use die::Die;
-use die::DieType;
-use uuid::Uuid;
+use damage::{DamageProfile, DamageReport};
+use die::{self, Die, DieType, FateFaces};
+use house_rules::HouseRule;
+use integrity::{self, InvalidRoll};
+use layers::ResolvedOptions;
+use ladder::{Outcome, SuccessLadder};
+use limits::{matches, Limits};
+use lint::LintWarning;
+use parser::parse;
+use partition::PartitionedRoll;
+use roller::Roller;
+use session::PointSpend;
+#[cfg(test)]
+use testing::{assert_roll_total, ScriptedRoller};
+use rounding::Rounding;
+use sha2::{Digest, Sha256};
+use stats::{exact_steps, DistributionCache, RollProbability};
+use timing::{RollTiming, Stopwatch};
+use std::cmp::Ordering;
+use std::fmt;
 use ttml::arg::ComparisonArg;
 
+/// Most dice a single die can add by exploding, unless the roller's limits say otherwise, see
+/// `Limits::max_explosions`
+pub const MAX_EXPLOSIONS: usize = 100;
+
+/// Most rounds of rerolling a roll goes through when rerolling forever, unless the roller's limits
+/// say otherwise, see `Limits::max_rerolls`
+pub const MAX_REROLLS: usize = 100;
+
+/// FNV-1a's 64 bit offset basis and prime, written out so fingerprints never depend on std's hasher
+const FINGERPRINT_OFFSET: u64 = 0xcbf29ce484222325;
+const FINGERPRINT_PRIME: u64 = 0x100000001b3;
+
 // Rolls all the arguments into a single struct
 pub struct RollFlags {
+    pub ceiling: Option<i16>,
+
+    /// Text written with the notation in quotes, e.g. `to hit` in `1d20+5 "to hit"`
+    pub comment: Option<String>,
+    pub dh: i16,
     pub die: DieType,
+    pub divisors: Vec<i16>,
+    pub dl: i16,
     pub equation: String,
-    pub gt: u16,
-    pub gte: u16,
+    pub explode: bool,
+
+    /// Value compared against the trigger, when there is one
+    pub explode_on: i16,
+
+    /// Comparison a die explodes on, none explodes on the highest face
+    pub explode_op: Option<ComparisonArg>,
+    pub explode_policy: ExplodeSuccessPolicy,
+    pub floor: Option<i16>,
+    pub gt: i16,
+    pub gte: i16,
+    pub immutable_dice: bool,
+
+    /// Each die counts for half its face, rounded up, e.g. the Hero System's half die `1hd6`
+    pub is_halved: bool,
     pub kh: i16,
     pub kl: i16,
-    pub lt: u16,
-    pub lte: u16,
+    pub lt: i16,
+    pub lte: i16,
     pub max: i16,
     pub min: i16,
     pub modifiers: Vec<i16>,
     pub n: i16,
+
+    /// The command as it was parsed, when lookalike characters were read as ASCII, e.g. `3d6` for `３ｄ６`,
+    /// or a dialect's modifiers were written the standard way
+    pub normalized: Option<String>,
     pub ro: i16,
     pub rr: i16,
     pub rr_op: Option<ComparisonArg>,
     pub ro_op: Option<ComparisonArg>,
+    pub rounding: Rounding,
     pub sides: Option<Vec<i16>>,
+
+    /// Tags given to every die the roll makes, e.g. `2d6[fire]`
+    pub tags: Vec<String>,
+
+    /// Every die lands on a different face, e.g. to draw lottery numbers
+    pub without_replacement: bool,
 }
 
 impl RollFlags {
     pub fn new() -> RollFlags {
         RollFlags {
+            ceiling: None,
+            comment: None,
+            dh: 0,
             die: DieType::Other,
+            divisors: vec![],
+            dl: 0,
             equation: "".to_string(),
+            explode: false,
+            explode_on: 0,
+            explode_op: None,
+            explode_policy: ExplodeSuccessPolicy::CompareEachDie,
+            floor: None,
             gt: 0,
             gte: 0,
+            immutable_dice: false,
+            is_halved: false,
             kh: 0,
             kl: 0,
             lt: 0,
@@ -41,236 +116,2534 @@ impl RollFlags {
             min: 1,
             modifiers: vec![],
             n: 0,
+            normalized: None,
             ro: 0,
             rr: 0,
             rr_op: None,
             ro_op: None,
+            rounding: Rounding::Floor,
             sides: None,
+            tags: vec![],
+            without_replacement: false,
         }
     }
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Roll {
-    /// Unique identifier for the roll
-    pub id: String,
 
-    /// The dice that compose this roll
-    pub dice: Vec<Die>,
-
-    /// Calculated equation of the roll
-    pub equation: String,
-
-    /// Modifiers to apply to the combined value
-    pub modifiers: Vec<i16>,
+    /// Roll a number of Fate dice shaped like the template, e.g. 4dF
+    pub fn fate(n: i16, template: &Die) -> RollFlags {
+        let mut flags = RollFlags::new();
+        flags.die = DieType::Fate;
+        flags.equation = n.to_string() + "dF";
+        flags.max = template.max;
+        flags.min = template.min;
+        flags.n = n;
+        flags.sides = template.sides.clone();
+        flags
+    }
 
-    /// The combined value of the die before modifiers
-    pub raw_value: i32,
+    /// Start building flags that are checked for conflicts as they're built
+    pub fn builder() -> RollFlagsBuilder {
+        RollFlagsBuilder {
+            flags: RollFlags::new(),
+        }
+    }
 
-    /// Timestamp
-    pub timestamp: DateTime<Utc>,
+    /// Check the flags describe a roll that can be made
+    pub fn validate(&self) -> Result<(), RollError> {
+        if self.n > 0 {
+            let no_sides = match self.sides {
+                Some(ref sides) => sides.is_empty(),
+                None => self.max == 0,
+            };
+            if no_sides {
+                return Err(RollError::NoSides);
+            }
 
-    /// The final combined value of the die after modifiers
-    pub value: i32,
-}
+            // A range of faces with its lowest above its highest has nothing to roll, e.g. 1d20min30
+            if self.sides.is_none() && self.min > self.max {
+                return Err(RollError::MinAboveMax(self.min, self.max));
+            }
+        }
 
-impl Roll {
-    pub fn new(flags: RollFlags) -> Roll {
-        let mut dice = vec![];
-        for _ in 0..flags.n {
-            let mut die = Die::new(flags.die);
-            die.set_min(flags.min);
-            die.set_max(flags.max);
+        if let (Some(floor), Some(ceiling)) = (self.floor, self.ceiling) {
+            if floor > ceiling {
+                return Err(RollError::FloorAboveCeiling(floor, ceiling));
+            }
+        }
 
-            match flags.sides {
-                Some(ref sides) => { die.sides = Some(sides.clone()); }
-                None => {}
-            };
+        if self.divisors.contains(&0) {
+            return Err(RollError::DivideByZero);
+        }
 
-            dice.push(die);
+        // Rerolling rolls a die in place of another, which immutable dice rule out
+        if self.immutable_dice {
+            if self.rr_op.is_some() {
+                return Err(RollError::ConflictingFlags("immutable_dice", "rr"));
+            }
+            if self.ro_op.is_some() {
+                return Err(RollError::ConflictingFlags("immutable_dice", "ro"));
+            }
         }
 
+        // Rerolls and explosions roll more dice than there are faces set aside for
+        if self.without_replacement {
+            let extra = [("rr", self.rr_op.is_some()), ("ro", self.ro_op.is_some()), ("explode", self.explode)];
+            if let Some(&(name, _)) = extra.iter().find(|&&(_, is_set)| is_set) {
+                return Err(RollError::ConflictingFlags("without_replacement", name));
+            }
+            let faces = self.distinct_faces().len();
+            if self.n as i32 > faces as i32 {
+                return Err(RollError::NotEnoughFaces(self.n, faces));
+            }
+        }
+        Ok(())
+    }
 
-        // Roll each dice
-        for die in &mut dice {
-            die.roll();
+    /// Every face the roll's dice can land on once each, in the order of their sides
+    fn distinct_faces(&self) -> Vec<i16> {
+        let mut faces = vec![];
+        for face in Roll::build_die(self).faces() {
+            if !faces.contains(&face) {
+                faces.push(face);
+            }
         }
+        faces
+    }
 
-        let mut roll = Roll {
-            dice,
-            equation: flags.equation,
-            timestamp: Utc::now(),
-            id: Uuid::new_v4().to_string(),
-            modifiers: Vec::new(),
-            raw_value: 0,
-            value: 0,
-        };
+    /// A number for the roll that's the same for every spelling of it, e.g. to cache or count rolls
+    ///
+    /// The fingerprint is the 64 bit FNV-1a hash of `canonical`, so `1d20 + 2 + 3` and `1d20+5` share
+    /// one. It's the same on every run and platform, and only changes with a major version.
+    pub fn fingerprint(&self) -> u64 {
+        self.canonical().bytes().fold(FINGERPRINT_OFFSET, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(FINGERPRINT_PRIME)
+        })
+    }
 
-        // If we have reroll flags, execute it
-        match flags.rr_op {
-            Some(op) => {
-                roll.reroll_dice_forever(&op, flags.rr);
+    /// Describe the roll in a fixed form, so rolls that behave the same are written the same
+    ///
+    /// Comments, tags and the die type are left out, the sides are written as the range or list they
+    /// roll, and modifiers are summed, e.g. `1d20 + 2 + 3 [attack]` becomes `1d20+5`.
+    pub fn canonical(&self) -> String {
+        let mut canonical = self.n.to_string();
+        if self.is_halved {
+            canonical = canonical + "h";
+        }
+        match self.sides {
+            Some(ref sides) => {
+                let side_strs: Vec<String> = sides.iter().map(|side| side.to_string()).collect();
+                canonical = canonical + "d[" + &side_strs.join(",") + "]";
             },
-            None => {} // do nothing
-        };
-
-        match flags.ro_op {
-            Some(op) => {
-                roll.reroll_dice_once(&op, flags.ro);
+            None => {
+                canonical = canonical + "d" + &self.max.to_string();
+                if self.min != 1 {
+                    canonical = canonical + "min" + &self.min.to_string();
+                }
             },
-            None => {} // do nothing
-        };
-
-        // Keep or drop dice that fit certain criteria
-        if flags.gt != 0 {
-            roll.keep_greater_than(flags.gt);
-        } else if flags.gte != 0 {
-            roll.keep_greater_than_or_equal_to(flags.gte);
-        } else if flags.lt != 0 {
-            roll.keep_less_than(flags.lt);
-        } else if flags.lte != 0 {
-            roll.keep_less_than_or_equal_to(flags.lte);
-        } else if flags.kh != 0 {
-            roll.keep_high(flags.kh as u16);
-        } else if flags.kl != 0 {
-            roll.keep_low(flags.kl as u16);
         }
 
-        // Once everything has been rerolled, dropped, etc, count the total
-        let raw_value = roll.dice.iter().filter(|d| !d.is_dropped).fold(0, |sum, d| sum + d.value as i32);
-        roll.raw_value = raw_value;
-        roll.value = raw_value;
+        let counts = [("kh", self.kh as i32), ("kl", self.kl as i32), ("gt", self.gt as i32),
+                      ("gte", self.gte as i32), ("lt", self.lt as i32), ("lte", self.lte as i32),
+                      ("dh", self.dh as i32), ("dl", self.dl as i32)];
+        for &(name, count) in counts.iter() {
+            if count != 0 {
+                canonical = canonical + name + &count.to_string();
+            }
+        }
 
-        // Apply and add our modifiers
-        if flags.modifiers.len() > 0 {
-            for modifier in flags.modifiers.into_iter() {
-                roll.modifiers.push(modifier);
-                roll.value += modifier as i32;
+        if self.explode {
+            canonical = canonical + "!";
+            if self.explode_policy == ExplodeSuccessPolicy::CompoundThenCompare {
+                canonical = canonical + "!";
             }
+            if let Some(ref op) = self.explode_op {
+                canonical = canonical + comparison_str(op) + &self.explode_on.to_string();
+            }
+        }
+        if let Some(ref op) = self.rr_op {
+            canonical = canonical + "rr" + comparison_str(op) + &self.rr.to_string();
+        }
+        if let Some(ref op) = self.ro_op {
+            canonical = canonical + "ro" + comparison_str(op) + &self.ro.to_string();
+        }
+        if let Some(floor) = self.floor {
+            canonical = canonical + "floor" + &floor.to_string();
+        }
+        if let Some(ceiling) = self.ceiling {
+            canonical = canonical + "ceiling" + &ceiling.to_string();
         }
 
-        roll
-    }
+        let modifier: i32 = self.modifiers.iter().map(|m| *m as i32).sum();
+        if modifier > 0 {
+            canonical = canonical + "+" + &modifier.to_string();
+        } else if modifier < 0 {
+            canonical = canonical + &modifier.to_string();
+        }
 
-    /// Keep the dice greater than a number
-    pub fn keep_greater_than(&mut self, keep: u16) {
-        for die in &mut self.dice {
-            if (die.value as u16) <= keep {
-                die.drop();
-            } else {
-                die.success();
+        if !self.divisors.is_empty() {
+            for divisor in &self.divisors {
+                canonical = "(".to_string() + &canonical + ")/" + &divisor.to_string();
             }
+            canonical = canonical + "round" + self.rounding.name();
         }
+        canonical
     }
 
-    /// Keep the dice greater than or equal to a number
-    pub fn keep_greater_than_or_equal_to(&mut self, keep: u16) {
-        for die in &mut self.dice {
-            if (die.value as u16) < keep {
-                die.drop();
-            } else {
-                die.success();
+    /// Write the roll as notation that parses back to the same flags, e.g. to show or store a roll
+    /// that was built rather than parsed
+    ///
+    /// Unlike `canonical`, tags, comments, the die type and each modifier are kept, and divisors are
+    /// written as `div2` with their rounding after them, e.g. `8d6div2ceil`. A comment with a `"`
+    /// in it can't be written in notation, so flags with one have none.
+    pub fn to_notation(&self) -> Option<String> {
+        if self.comment.as_ref().map_or(false, |comment| comment.contains('"')) {
+            return None;
+        }
+
+        let mut notation = self.n.to_string();
+        if self.is_halved {
+            notation += "h";
+        }
+        let standard_max = self.die.max_face();
+        match self.sides {
+            Some(ref sides) if self.die == DieType::Fate && sides[..] == [-1, 0, 1] => notation += "dF",
+            None if self.die == DieType::Fate => notation += "dF",
+            Some(ref sides) => {
+                let sides: Vec<String> = sides.iter().map(|side| side.to_string()).collect();
+                notation = notation + "d[" + &sides.join(",") + "]";
+            },
+            // Notation reads the die type from its sides, so other faces of a known die are written
+            // as changing its highest face, e.g. `1d20max15`
+            None if self.die != DieType::Other && standard_max > 0 => {
+                notation = notation + "d" + &standard_max.to_string();
+                if self.max != standard_max {
+                    notation = notation + "max" + &self.max.to_string();
+                }
+            },
+            None => notation = notation + "d" + &self.max.to_string(),
+        }
+        if self.sides.is_none() && self.die != DieType::Fate && self.min != 1 {
+            notation = notation + "min" + &self.min.to_string();
+        }
+        if !self.tags.is_empty() {
+            notation = notation + "[" + &self.tags.join(",") + "]";
+        }
+        if self.without_replacement {
+            notation += "nr";
+        }
+
+        let counts = [("kh", self.kh), ("kl", self.kl), ("gt", self.gt), ("gte", self.gte), ("lt", self.lt),
+                      ("lte", self.lte), ("dh", self.dh), ("dl", self.dl)];
+        for &(name, count) in counts.iter().filter(|&&(_, count)| count != 0) {
+            notation = notation + name + &count.to_string();
+        }
+        if self.explode {
+            notation += if self.explode_policy == ExplodeSuccessPolicy::CompoundThenCompare { "!!" } else { "!" };
+            if let Some(ref op) = self.explode_op {
+                notation = notation + comparison_str(op) + &self.explode_on.to_string();
+            }
+        }
+        // A reroll on one face is written without its comparison, the way TTML reads it
+        let reroll = |op: &ComparisonArg| match op {
+            &ComparisonArg::EqualTo => "",
+            op => comparison_str(op),
+        };
+        if let Some(ref op) = self.rr_op {
+            notation = notation + "rr" + reroll(op) + &self.rr.to_string();
+        }
+        if let Some(ref op) = self.ro_op {
+            notation = notation + "ro" + reroll(op) + &self.ro.to_string();
+        }
+        if let Some(floor) = self.floor {
+            notation = notation + "floor" + &floor.to_string();
+        }
+        if let Some(ceiling) = self.ceiling {
+            notation = notation + "ceiling" + &ceiling.to_string();
+        }
+        for modifier in &self.modifiers {
+            if *modifier >= 0 {
+                notation += "+";
             }
+            notation += &modifier.to_string();
         }
+        for divisor in &self.divisors {
+            notation = notation + "div" + &divisor.to_string();
+        }
+        // Rounding only changes a divided total, and rounding down is what notation does unless told
+        if !self.divisors.is_empty() && self.rounding != Rounding::Floor {
+            notation += self.rounding.name();
+        }
+        if let Some(ref comment) = self.comment {
+            notation = notation + " \"" + comment + "\"";
+        }
+        Some(notation)
     }
+}
 
-    /// Keep the dice less than a number
-    pub fn keep_less_than(&mut self, keep: u16) {
-        for die in &mut self.dice {
-            if (die.value as u16) >= keep {
-                die.drop();
-            } else {
-                die.success();
-            }
+impl Clone for RollFlags {
+    fn clone(&self) -> RollFlags {
+        RollFlags {
+            ceiling: self.ceiling,
+            comment: self.comment.clone(),
+            dh: self.dh,
+            die: self.die,
+            divisors: self.divisors.clone(),
+            dl: self.dl,
+            equation: self.equation.clone(),
+            explode: self.explode,
+            explode_on: self.explode_on,
+            explode_op: self.explode_op.as_ref().map(copy_comparison),
+            explode_policy: self.explode_policy,
+            floor: self.floor,
+            gt: self.gt,
+            gte: self.gte,
+            immutable_dice: self.immutable_dice,
+            is_halved: self.is_halved,
+            kh: self.kh,
+            kl: self.kl,
+            lt: self.lt,
+            lte: self.lte,
+            max: self.max,
+            min: self.min,
+            modifiers: self.modifiers.clone(),
+            n: self.n,
+            normalized: self.normalized.clone(),
+            ro: self.ro,
+            rr: self.rr,
+            rr_op: self.rr_op.as_ref().map(copy_comparison),
+            ro_op: self.ro_op.as_ref().map(copy_comparison),
+            rounding: self.rounding,
+            sides: self.sides.clone(),
+            tags: self.tags.clone(),
+            without_replacement: self.without_replacement,
         }
     }
+}
+
+/// Fluent builder for roll flags, clone it to override a set of defaults per roll
+#[derive(Clone)]
+pub struct RollFlagsBuilder {
+    flags: RollFlags,
+}
+
+impl RollFlagsBuilder {
+    /// Number of dice to roll
+    pub fn n(mut self, n: i16) -> RollFlagsBuilder {
+        self.flags.n = n;
+        self
+    }
 
-    /// Keep the dice less than or equal to a number
-    pub fn keep_less_than_or_equal_to(&mut self, keep: u16) {
-        for die in &mut self.dice {
-            if (die.value as u16) > keep {
-                die.drop();
-            } else {
-                die.success();
-            }
+    /// Type of die to roll, along with its usual minimum and maximum
+    ///
+    /// Fate dice get the sides `4dF` rolls, one each of -1, 0 and 1.
+    pub fn die(mut self, die: DieType) -> RollFlagsBuilder {
+        let template = Die::new(die);
+        self.flags.die = die;
+        self.flags.min = template.min;
+        self.flags.max = template.max;
+        if die == DieType::Fate {
+            self.flags.sides = Some(vec![-1, 0, 1]);
         }
+        self
     }
 
-    /// Keep the highest rolled dice
-    pub fn keep_high(&mut self, keep: u16) {
-        // Sort the dice by value, drop everything below the keep value
-        let mut count = 0;
-        self.dice.sort_by(|a, b| b.value.cmp(&a.value));
-        for die in &mut self.dice {
-            if count >= keep {
-                die.drop();
-            }
-            count += 1;
+    pub fn min(mut self, min: i16) -> RollFlagsBuilder {
+        self.flags.min = min;
+        self
+    }
+
+    pub fn max(mut self, max: i16) -> RollFlagsBuilder {
+        self.flags.max = max;
+        self
+    }
+
+    /// Roll custom sides, with the lowest and highest as the minimum and maximum the same as parsing
+    pub fn sides(mut self, sides: Vec<i16>) -> RollFlagsBuilder {
+        if let (Some(&min), Some(&max)) = (sides.iter().min(), sides.iter().max()) {
+            self.flags.min = min;
+            self.flags.max = max;
         }
-        // sort by timestamp again before finishing the method
-        self.dice.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        self.flags.sides = Some(sides);
+        self
     }
 
-    /// Keep the lowest rolled dice
-    pub fn keep_low(&mut self, keep: u16) {
-        // Sort the dice by value, drop everything below the keep value
-        let mut count = 0;
-        self.dice.sort_by(|a, b| a.value.cmp(&b.value));
-        for die in &mut self.dice {
-            if count >= keep {
-                die.drop();
-            }
-            count += 1;
+    /// Land every die on a different face, as if drawing the faces from a bag
+    pub fn without_replacement(mut self) -> RollFlagsBuilder {
+        self.flags.without_replacement = true;
+        self
+    }
+
+    /// Tag every die the roll makes, can be used more than once
+    pub fn tag(mut self, tag: &str) -> RollFlagsBuilder {
+        if !self.flags.tags.iter().any(|t| t == tag) {
+            self.flags.tags.push(tag.to_string());
         }
-        // sort by timestamp again before finishing the method
-        self.dice.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        self
     }
 
-    /// Reroll dice one time that are above or below a certain threshold
-    pub fn reroll_dice_once(&mut self, op: &ComparisonArg, threshold: i16) {
-        let mut new_dice = Vec::new();
-        for die in &mut self.dice {
-            let comparison = match op {
-                &ComparisonArg::GreaterThan => !die.is_rerolled && die.value > threshold,
-                &ComparisonArg::GreaterThanOrEqual => !die.is_rerolled && die.value >= threshold,
-                &ComparisonArg::LessThan => !die.is_rerolled && die.value < threshold,
-                &ComparisonArg::LessThanOrEqual => !die.is_rerolled && die.value <= threshold,
-                &ComparisonArg::EqualTo => !die.is_rerolled && die.value == threshold,
-            };
+    pub fn equation(mut self, equation: &str) -> RollFlagsBuilder {
+        self.flags.equation = equation.to_string();
+        self
+    }
 
-            if comparison {
-                let mut d = Die::new(die.die);
-                d.roll();
-                &die.rerolled(&d);
-                &die.drop();
-                new_dice.push(d);
-            }
+    /// Write text with the roll, e.g. `to hit`
+    pub fn comment(mut self, comment: &str) -> RollFlagsBuilder {
+        self.flags.comment = Some(comment.to_string());
+        self
+    }
+
+    /// Count each die for half its face, rounded up
+    pub fn halved(mut self) -> RollFlagsBuilder {
+        self.flags.is_halved = true;
+        self
+    }
+
+    pub fn kh(mut self, kh: i16) -> RollFlagsBuilder {
+        self.flags.kh = kh;
+        self
+    }
+
+    pub fn kl(mut self, kl: i16) -> RollFlagsBuilder {
+        self.flags.kl = kl;
+        self
+    }
+
+    /// Drop the highest dice, can be used along with `dl`
+    pub fn dh(mut self, dh: i16) -> RollFlagsBuilder {
+        self.flags.dh = dh;
+        self
+    }
+
+    /// Drop the lowest dice, can be used along with `dh`
+    pub fn dl(mut self, dl: i16) -> RollFlagsBuilder {
+        self.flags.dl = dl;
+        self
+    }
+
+    /// Drop the highest and lowest dice, see `Roll::drop_extremes`
+    pub fn drop_extremes(self, high: i16, low: i16) -> RollFlagsBuilder {
+        self.dh(high).dl(low)
+    }
+
+    /// Keep the middle dice of the number set so far, so set `n` first
+    ///
+    /// When the dice can't be trimmed evenly, the extra die is dropped from the high end.
+    pub fn keep_middle(self, keep: i16) -> RollFlagsBuilder {
+        let (high, low) = middle_trim(self.flags.n.max(0) as usize, keep.max(0) as usize);
+        self.drop_extremes(high as i16, low as i16)
+    }
+
+    pub fn gt(mut self, gt: i16) -> RollFlagsBuilder {
+        self.flags.gt = gt;
+        self
+    }
+
+    pub fn gte(mut self, gte: i16) -> RollFlagsBuilder {
+        self.flags.gte = gte;
+        self
+    }
+
+    pub fn lt(mut self, lt: i16) -> RollFlagsBuilder {
+        self.flags.lt = lt;
+        self
+    }
+
+    pub fn lte(mut self, lte: i16) -> RollFlagsBuilder {
+        self.flags.lte = lte;
+        self
+    }
+
+    /// Refuse to roll any of the dice again once they've been rolled
+    pub fn immutable_dice(mut self, immutable_dice: bool) -> RollFlagsBuilder {
+        self.flags.immutable_dice = immutable_dice;
+        self
+    }
+
+    /// Reroll dice matching the comparison until they don't
+    pub fn rr(mut self, op: ComparisonArg, rr: i16) -> RollFlagsBuilder {
+        self.flags.rr_op = Some(op);
+        self.flags.rr = rr;
+        self
+    }
+
+    /// Reroll dice matching the comparison once
+    pub fn ro(mut self, op: ComparisonArg, ro: i16) -> RollFlagsBuilder {
+        self.flags.ro_op = Some(op);
+        self.flags.ro = ro;
+        self
+    }
+
+    /// Roll another die whenever one lands on its highest face
+    pub fn explode(mut self, policy: ExplodeSuccessPolicy) -> RollFlagsBuilder {
+        self.flags.explode = true;
+        self.flags.explode_policy = policy;
+        self
+    }
+
+    /// Explode dice matching the comparison instead of those landing on their highest face
+    pub fn explode_on(mut self, op: ComparisonArg, target: i16) -> RollFlagsBuilder {
+        self.flags.explode = true;
+        self.flags.explode_op = Some(op);
+        self.flags.explode_on = target;
+        self
+    }
+
+    /// Add a modifier to the total, can be called more than once
+    pub fn modifier(mut self, modifier: i16) -> RollFlagsBuilder {
+        self.flags.modifiers.push(modifier);
+        self
+    }
+
+    pub fn floor(mut self, floor: i16) -> RollFlagsBuilder {
+        self.flags.floor = Some(floor);
+        self
+    }
+
+    pub fn ceiling(mut self, ceiling: i16) -> RollFlagsBuilder {
+        self.flags.ceiling = Some(ceiling);
+        self
+    }
+
+    /// Divide the total after modifiers, can be called more than once
+    pub fn divide_by(mut self, divisor: i16) -> RollFlagsBuilder {
+        self.flags.divisors.push(divisor);
+        self
+    }
+
+    pub fn rounding(mut self, rounding: Rounding) -> RollFlagsBuilder {
+        self.flags.rounding = rounding;
+        self
+    }
+
+    /// Check the flags, leaving the builder untouched so it can be built again
+    pub fn build(&self) -> Result<RollFlags, RollError> {
+        // Only one way of keeping dice is used when rolling, so more than one is a mistake
+        let flags = &self.flags;
+        let keeps = [("gt", flags.gt != 0), ("gte", flags.gte != 0), ("lt", flags.lt != 0),
+                     ("lte", flags.lte != 0), ("kh", flags.kh != 0), ("kl", flags.kl != 0)];
+        let mut set = keeps.iter().filter(|&&(_, is_set)| is_set).map(|&(name, _)| name);
+        let first = set.next();
+        if let (Some(first), Some(second)) = (first, set.next()) {
+            return Err(RollError::ConflictingFlags(first, second));
         }
 
-        self.dice.append(&mut new_dice);
+        // Dropping the highest and lowest dice go together, but not with another way of keeping
+        let drop = if flags.dh != 0 { Some("dh") } else if flags.dl != 0 { Some("dl") } else { None };
+        if let (Some(first), Some(drop)) = (first, drop) {
+            return Err(RollError::ConflictingFlags(first, drop));
+        }
+
+        flags.validate()?;
+        Ok(flags.clone())
     }
 
-    /// Reroll dice forever that are above or below a certain threshold
-    pub fn reroll_dice_forever(&mut self, op: &ComparisonArg, threshold: i16) {
-        // Reroll any dice that need to be rerolled
-        self.reroll_dice_once(&op, threshold);
+    /// The flags built so far written as notation, none when they don't build or can't be written,
+    /// see `RollFlags::to_notation`
+    pub fn to_notation(&self) -> Option<String> {
+        self.build().ok()?.to_notation()
+    }
+}
 
-        let mut has_more = false;
-        for die in self.dice.iter() {
-            let comparison = match op {
-                &ComparisonArg::GreaterThan => !die.is_rerolled && die.value > threshold,
-                &ComparisonArg::GreaterThanOrEqual => !die.is_rerolled && die.value >= threshold,
-                &ComparisonArg::LessThan => !die.is_rerolled && die.value < threshold,
-                &ComparisonArg::LessThanOrEqual => !die.is_rerolled && die.value <= threshold,
-                &ComparisonArg::EqualTo => !die.is_rerolled && die.value == threshold,
-            };
+/// Sums of kept dice grouped by tag, a die with several tags counting towards each
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct TagSubtotals(pub Vec<(String, i32)>);
 
-            if comparison {
-                has_more = true
+impl TagSubtotals {
+    /// Add up the kept dice by tag, in the order the tags first appear
+    pub fn of<'a, I: Iterator<Item = &'a Die>>(dice: I) -> TagSubtotals {
+        let mut subtotals: Vec<(String, i32)> = vec![];
+        for die in dice.filter(|d| !d.is_dropped) {
+            for tag in &die.tags {
+                match subtotals.iter().position(|&(ref t, _)| t == tag) {
+                    Some(i) => subtotals[i].1 += die.counted() as i32,
+                    None => subtotals.push((tag.clone(), die.counted() as i32)),
+                }
             }
         }
-        if has_more {
-            self.reroll_dice_forever(op, threshold);
-        }
+        TagSubtotals(subtotals)
+    }
+
+    /// The subtotal of a tag, zero when no kept die has it
+    pub fn get(&self, tag: &str) -> i32 {
+        self.0.iter().find(|&&(ref t, _)| t == tag).map_or(0, |&(_, total)| total)
+    }
+}
+
+/// Written as each tag and its subtotal, e.g. `fire: 7, cold: 3`
+impl fmt::Display for TagSubtotals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let subtotals: Vec<String> = self.0.iter().map(|&(ref tag, total)| format!("{}: {}", tag, total)).collect();
+        write!(f, "{}", subtotals.join(", "))
+    }
+}
+
+/// How dice added by explosions are compared when counting successes, each on its own by default
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ExplodeSuccessPolicy {
+    /// Every die is compared on its own, including the dice added by explosions
+    CompareEachDie,
+
+    /// Explosions add to the die that exploded, which is compared once with its total
+    CompoundThenCompare,
+}
+
+/// How many of a number of dice to drop from the high and low ends to keep the middle ones
+fn middle_trim(n: usize, keep: usize) -> (usize, usize) {
+    let trim = n.saturating_sub(keep);
+    let low = trim / 2;
+    (trim - low, low)
+}
+
+/// Copy a comparison, which TTML doesn't make cloneable
+pub fn copy_comparison(op: &ComparisonArg) -> ComparisonArg {
+    match op {
+        &ComparisonArg::GreaterThan => ComparisonArg::GreaterThan,
+        &ComparisonArg::GreaterThanOrEqual => ComparisonArg::GreaterThanOrEqual,
+        &ComparisonArg::LessThan => ComparisonArg::LessThan,
+        &ComparisonArg::LessThanOrEqual => ComparisonArg::LessThanOrEqual,
+        &ComparisonArg::EqualTo => ComparisonArg::EqualTo,
+    }
+}
+
+/// Write rolls made together as one command, e.g. `2d6[fire] + 1d6[cold]`
+///
+/// A comment ends the command, so only the last roll can have one. None when there are no rolls or
+/// any can't be written, see `RollFlags::to_notation`.
+pub fn group_notation(rolls: &[RollFlags]) -> Option<String> {
+    let (_, rest) = rolls.split_last()?;
+    if rest.iter().any(|flags| flags.comment.is_some()) {
+        return None;
+    }
+    let written: Option<Vec<String>> = rolls.iter().map(|flags| flags.to_notation()).collect();
+    written.map(|written| written.join(" + "))
+}
+
+/// Write a comparison the way canonical notation does, e.g. `>=`
+pub fn comparison_str(op: &ComparisonArg) -> &'static str {
+    match op {
+        &ComparisonArg::GreaterThan => ">",
+        &ComparisonArg::GreaterThanOrEqual => ">=",
+        &ComparisonArg::LessThan => "<",
+        &ComparisonArg::LessThanOrEqual => "<=",
+        &ComparisonArg::EqualTo => "==",
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RollError {
+    /// The die is immutable and has already been rolled
+    AlreadyRolled,
+
+    /// The die with the ID was assigned to more than one partition
+    AssignedTwice(String),
+
+    /// Rolling was given up on because its cancellation token was cancelled
+    Cancelled,
+
+    /// A compounded die could total this much, more than a die's value can hold
+    CompoundOverflow(i64),
+
+    /// Two flags were set that can't be used together
+    ConflictingFlags(&'static str, &'static str),
+
+    /// Rolling was given up on because it went past its deadline
+    DeadlineExceeded,
+
+    /// The total was divided by zero
+    DivideByZero,
+
+    /// Every face of the die would explode, so rolling would never finish
+    EndlessExplosion,
+
+    /// Every face of the die would be rerolled, so rolling would never finish
+    EndlessReroll,
+
+    /// The die with the ID was dropped, so it can't be amended or partitioned
+    DroppedDie(String),
+
+    /// The floor (first) is higher than the ceiling (second)
+    FloorAboveCeiling(i16, i16),
+
+    /// The notation couldn't be parsed
+    InvalidNotation,
+
+    /// The lowest face (first) is higher than the highest (second), e.g. 1d20min30
+    MinAboveMax(i16, i16),
+
+    /// A roll was asked to be repeated zero times
+    NoRepeats,
+
+    /// Dice were requested without any sides, e.g. 3d0
+    NoSides,
+
+    /// More dice (first) were asked to land on different faces than the die has (second)
+    NotEnoughFaces(i16, usize),
+
+    /// Too many rolls were being made or waiting to be, so the roll wasn't started
+    Saturated,
+
+    /// More dice (first) were requested than allowed (second)
+    TooManyDice(i16, i16),
+
+    /// A die has more sides (first) than allowed (second)
+    TooManySides(i32, i32),
+
+    /// Notation with this many rolls was given where only one can be rolled, e.g. `2d6 + 1d8`
+    TooManyTerms(usize),
+
+    /// No die in the roll has the ID
+    UnknownDie(String),
+
+    /// The entered value at an index (first) isn't a face of the die (second is the value)
+    ValueOutOfRange(usize, i16),
+
+    /// The roll needed a number of values (first) other than the number entered (second)
+    WrongValueCount(usize, usize),
+}
+
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RollError::AlreadyRolled => write!(f, "The die has already been rolled"),
+            &RollError::AssignedTwice(ref id) => write!(f, "The die {} was assigned more than once", id),
+            &RollError::Cancelled => write!(f, "The roll was cancelled"),
+            &RollError::CompoundOverflow(most) => {
+                write!(f, "A compounded die could total {}, the most is {}", most, i16::max_value())
+            },
+            &RollError::ConflictingFlags(first, second) => {
+                write!(f, "The {} and {} flags can't be used together", first, second)
+            },
+            &RollError::DeadlineExceeded => write!(f, "The roll took too long"),
+            &RollError::DivideByZero => write!(f, "Rolls can't be divided by zero"),
+            &RollError::EndlessExplosion => write!(f, "Every side of the die would explode"),
+            &RollError::DroppedDie(ref id) => write!(f, "The die {} was dropped and can't be used", id),
+            &RollError::EndlessReroll => write!(f, "Every side of the die would be rerolled"),
+            &RollError::FloorAboveCeiling(floor, ceiling) => {
+                write!(f, "The floor ({}) can't be higher than the ceiling ({})", floor, ceiling)
+            },
+            &RollError::InvalidNotation => write!(f, "The roll couldn't be understood"),
+            &RollError::MinAboveMax(min, max) => {
+                write!(f, "The lowest side ({}) can't be higher than the highest ({})", min, max)
+            },
+            &RollError::NoRepeats => write!(f, "A roll has to be repeated at least once"),
+            &RollError::NoSides => write!(f, "Dice need at least one side to be rolled"),
+            &RollError::NotEnoughFaces(n, faces) => {
+                write!(f, "Can't roll {} dice on different faces of a die with {}", n, faces)
+            },
+            &RollError::Saturated => write!(f, "Too many rolls are being made, try again shortly"),
+            &RollError::TooManyDice(n, max) => write!(f, "Can't roll {} dice, the most is {}", n, max),
+            &RollError::TooManySides(sides, max) => {
+                write!(f, "Dice can't have {} sides, the most is {}", sides, max)
+            },
+            &RollError::TooManyTerms(terms) => write!(f, "Only one roll can be made here, not {}", terms),
+            &RollError::UnknownDie(ref id) => write!(f, "The roll has no die {}", id),
+            &RollError::ValueOutOfRange(index, value) => {
+                write!(f, "Value {} ({}) can't be rolled on the die", index + 1, value)
+            },
+            &RollError::WrongValueCount(expected, found) => {
+                write!(f, "The roll needs {} values but {} were entered", expected, found)
+            },
+        }
+    }
+}
+
+/// The public randomness beacon round a roll's seed was derived from, see `beacon::roll_with_beacon`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BeaconDraw {
+    /// Who the roll was announced for, hashed into the request along with the flags and round
+    #[serde(default)]
+    pub participant: String,
+
+    /// The round's randomness as lowercase hex, as the beacon published it
+    pub randomness: String,
+
+    /// Hash of the request the roll was made for, mixed into the seed so each request has its own dice
+    pub request_hash: String,
+
+    pub round: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Roll {
+    /// Unique identifier for the roll
+    pub id: String,
+
+    /// The roll this one was amended from, see `amend_reroll`
+    #[serde(default)]
+    pub amended_from: Option<String>,
+
+    /// The beacon round the roll's seed was derived from, so anyone can roll it again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beacon: Option<BeaconDraw>,
+
+    /// The content hash of the finished roll as lowercase hex, see `stamp_hashes`
+    #[serde(default)]
+    pub content_hash: Option<String>,
+
+    /// The dice that compose this roll, in the order they were rolled
+    ///
+    /// Dice added by rerolls and explosions come after those the roll started with, but the order
+    /// isn't kept by anything that sorts or groups the dice. Each die's `term_index` and
+    /// `position_in_term` say where it comes in the notation whatever the order, see
+    /// `dice_in_notation_order`.
+    pub dice: Vec<Die>,
+
+    /// Divisors applied to the total after modifiers, in order
+    pub divisors: Vec<i16>,
+
+    /// Calculated equation of the roll
+    pub equation: String,
+
+    /// How exploded dice were compared when counting successes
+    pub explode_policy: ExplodeSuccessPolicy,
+
+    /// The kept Fate dice as `+`, `0` and `-` in the order they were rolled, none for other dice
+    pub fate_glyphs: Option<String>,
+
+    /// The fingerprint of the roll's flags as 16 hex digits, since JSON numbers can't hold every one
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+
+    /// The room's house rules that changed the roll, see `HouseRules::apply`
+    #[serde(default)]
+    pub house_rules: Vec<HouseRule>,
+
+    /// If the kept dice were lowered to the ceiling
+    pub is_ceilinged: bool,
+
+    /// If the kept dice were raised to the floor
+    pub is_floored: bool,
+
+    /// Modifiers to apply to the combined value
+    pub modifiers: Vec<i16>,
+
+    /// The combined value of the kept dice as rolled, before any floor or ceiling
+    pub natural_value: i32,
+
+    /// Index of the roll when its seed was derived from a server and client seed
+    pub nonce: Option<u64>,
+
+    /// The notation as it was parsed, when lookalike characters in it were read as ASCII or it was
+    /// written in another dialect
+    #[serde(default)]
+    pub normalized: Option<String>,
+
+    /// How the layers of options the roll was made with were merged, see `layers::roll_with_layers`
+    #[serde(default)]
+    pub options: Option<ResolvedOptions>,
+
+    /// Graded outcome, if the roll was checked against a success ladder
+    pub outcome: Option<Outcome>,
+
+    /// The points spent to make the roll by rerolling a die of another, see `Session::spend_for_reroll`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_spent: Option<PointSpend>,
+
+    /// How likely the total was, only when the roller was asked, see `RollOptions::annotate_probability`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probability: Option<RollProbability>,
+
+    /// The combined value of the die before modifiers, after any floor or ceiling
+    pub raw_value: i32,
+
+    /// How the seed was turned into dice, only when the roll was seeded, see `roller::REPLAY_EPOCH`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_epoch: Option<u32>,
+
+    /// Number of values drawn from the roller to make the roll, see `Roller::total_draws`
+    #[serde(default)]
+    pub rng_draws: u32,
+
+    /// How the total was rounded after each division
+    pub rounding: Rounding,
+
+    /// Number of the roll in its room's history, counting from 1, or 0 when it wasn't kept in one
+    #[serde(default)]
+    pub sequence: u64,
+
+    /// Timestamp
+    pub timestamp: DateTime<Utc>,
+
+    /// Where the time went making the roll, only when the roller was asked to time it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<RollTiming>,
+
+    /// The final combined value of the die after modifiers
+    pub value: i32,
+
+    /// What in the notation is probably not what was meant, see `lint`, or what an imported roll
+    /// left out, see `interop`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<LintWarning>,
+
+    /// If every die landed on a different face
+    #[serde(default)]
+    pub without_replacement: bool,
+}
+
+impl Roll {
+    pub fn new(flags: RollFlags) -> Roll {
+        Roll::with_roller(flags, &mut Roller::new())
+    }
+
+    /// Roll the same as `with_roller`, failing instead if the roller stops before the roll is done
+    ///
+    /// Nothing rolled before stopping is returned, see `Roller::with_options`.
+    pub fn try_with_roller(flags: RollFlags, roller: &mut Roller) -> Result<Roll, RollError> {
+        roller.stopped()?;
+        let roll = Roll::with_roller(flags, roller);
+        roller.stopped()?;
+        Ok(roll)
+    }
+
+    /// Build and roll the dice using the given source of randomness
+    pub fn with_roller(flags: RollFlags, roller: &mut Roller) -> Roll {
+        #[cfg(feature = "tracing")]
+        let _span = debug_span!("term", equation = %flags.equation).entered();
+
+        let nonce = roller.begin_roll();
+        let draws = roller.total_draws();
+        let mut stopwatch = Stopwatch::start(roller.captures_timing());
+
+        let mut dice = Roll::build_dice(&flags);
+
+        // Roll each dice, drawing faces from those left when they can't repeat
+        if flags.without_replacement {
+            Roll::draw_distinct_faces(&flags, &mut dice, roller);
+        } else {
+            for die in &mut dice {
+                die.roll_with(roller);
+            }
+        }
+
+        let mut roll = Roll {
+            amended_from: None,
+            beacon: None,
+            content_hash: None,
+            dice,
+            divisors: vec![],
+            equation: flags.equation.clone(),
+            explode_policy: flags.explode_policy,
+            fate_glyphs: None,
+            fingerprint: None,
+            house_rules: vec![],
+            timestamp: Utc::now(),
+            timing: None,
+            id: die::new_id(),
+            is_ceilinged: false,
+            is_floored: false,
+            modifiers: Vec::new(),
+            natural_value: 0,
+            nonce,
+            normalized: flags.normalized.clone(),
+            options: None,
+            outcome: None,
+            points_spent: None,
+            probability: None,
+            raw_value: 0,
+            replay_epoch: roller.replay_epoch(),
+            rng_draws: 0,
+            rounding: flags.rounding,
+            sequence: 0,
+            value: 0,
+            warnings: vec![],
+            without_replacement: flags.without_replacement,
+        };
+        let rolling_us = stopwatch.lap();
+
+        // If we have reroll flags, execute it
+        match flags.rr_op {
+            Some(ref op) => {
+                roll.reroll_dice_forever(op, flags.rr, roller);
+            },
+            None => {} // do nothing
+        };
+
+        match flags.ro_op {
+            Some(ref op) => {
+                roll.reroll_dice_once(op, flags.ro, roller);
+            },
+            None => {} // do nothing
+        };
+        let rerolls_us = stopwatch.lap();
+
+        if flags.explode {
+            let target = flags.explode_on;
+            let trigger = flags.explode_op.as_ref().map(|op| (op, target));
+            roll.explode_dice_when(trigger, flags.explode_policy, roller);
+        }
+        let explosions_us = stopwatch.lap();
+
+        for (position, die) in roll.dice.iter_mut().enumerate() {
+            die.position_in_term = position as u16;
+        }
+        roll.select_and_total(&flags, roller);
+        roll.rng_draws = (roller.total_draws() - draws) as u32;
+        if stopwatch.is_running() {
+            roll.timing = Some(RollTiming {
+                explosions_us,
+                parse_us: None,
+                rerolls_us,
+                rolling_us,
+                selection_us: stopwatch.lap(),
+                total_us: stopwatch.total(),
+            });
+        }
+        if let Some(cache) = roller.probability_cache() {
+            roll.annotate_probability(&flags, cache, roller.limits());
+        }
+        roll
+    }
+
+    /// Note how likely the roll's total was, or warn that it can't be worked out exactly
+    ///
+    /// Distributions that would take more than the limits' `max_probability_steps` to work out
+    /// are skipped with a `probability-too-costly` warning, unless they're already cached. The
+    /// warnings are for the roll's term, see `set_term_index`.
+    pub fn annotate_probability(&mut self, flags: &RollFlags, cache: &DistributionCache, limits: &Limits) {
+        let steps = exact_steps(flags);
+        if steps > limits.max_probability_steps && !cache.contains(flags) {
+            let message = format!("Working out how likely the total was would take {} steps, more than the {} \
+                                   allowed", steps, limits.max_probability_steps);
+            self.warn_about_probability("probability-too-costly", message);
+            return;
+        }
+        match cache.distribution(flags) {
+            Some(cached) => self.probability = Some(RollProbability::of(&cached.distribution, self.value)),
+            None => self.warn_about_probability("probability-unsupported", "How likely the total was can't be \
+                worked out exactly for rolls that count, reroll or explode dice, or keep dice from big pools".to_string()),
+        }
+    }
+
+    fn warn_about_probability(&mut self, rule: &str, message: String) {
+        let term = self.dice.first().map_or(0, |die| die.term_index as usize);
+        self.warnings.push(LintWarning {
+            rule: rule.to_string(),
+            message,
+            suggestion: None,
+            term,
+        });
+    }
+
+    /// Land each die on a face no die before it landed on, shuffling the faces part way
+    ///
+    /// Each die picks one of the faces not yet used, which are swapped to the front as they're
+    /// picked, the same as a Fisher-Yates shuffle stopped after as many faces as there are dice.
+    fn draw_distinct_faces(flags: &RollFlags, dice: &mut Vec<Die>, roller: &mut Roller) {
+        let mut faces = flags.distinct_faces();
+        for (i, die) in dice.iter_mut().enumerate().take(faces.len()) {
+            let face = die.roll_among(&faces[i..], roller);
+            if let Some(j) = faces[i..].iter().position(|f| *f == face) {
+                faces.swap(i, i + j);
+            }
+        }
+    }
+
+    /// Keep or drop the dice the flags pick, then count the total
+    fn select_and_total(&mut self, flags: &RollFlags, roller: &mut Roller) {
+        // Half dice keep the face they landed on, so it's only halved for counting
+        if flags.is_halved {
+            for die in &mut self.dice {
+                die.halved = Some(Rounding::Ceil.divide(die.value as i32, 2) as i16);
+            }
+        }
+
+        // Keep or drop dice that fit certain criteria
+        if flags.gt != 0 {
+            self.keep_greater_than(flags.gt);
+        } else if flags.gte != 0 {
+            self.keep_greater_than_or_equal_to(flags.gte);
+        } else if flags.lt != 0 {
+            self.keep_less_than(flags.lt);
+        } else if flags.lte != 0 {
+            self.keep_less_than_or_equal_to(flags.lte);
+        } else if flags.kh != 0 {
+            self.keep_high(flags.kh as u16);
+        } else if flags.kl != 0 {
+            self.keep_low(flags.kl as u16);
+        } else if flags.dh != 0 || flags.dl != 0 {
+            self.drop_extremes_ranked(flags.dh.max(0) as usize, flags.dl.max(0) as usize, roller.scratch());
+        }
+
+        // Once everything has been rerolled, dropped, etc, count the total
+        let natural_value = self.dice.iter().filter(|d| !d.is_dropped).fold(0, |sum, d| sum + d.counted() as i32);
+        self.natural_value = natural_value;
+
+        // Raise or lower the kept dice to the floor and ceiling before modifiers
+        let mut raw_value = natural_value;
+        if let Some(floor) = flags.floor {
+            if raw_value < floor as i32 {
+                raw_value = floor as i32;
+                self.is_floored = true;
+            }
+        }
+        if let Some(ceiling) = flags.ceiling {
+            if raw_value > ceiling as i32 {
+                raw_value = ceiling as i32;
+                self.is_ceilinged = true;
+            }
+        }
+        self.raw_value = raw_value;
+        self.value = raw_value;
+
+        // Apply and add our modifiers
+        for modifier in &flags.modifiers {
+            self.modifiers.push(*modifier);
+            self.value += *modifier as i32;
+        }
+
+        // Divide the total, rounding after each division
+        for &divisor in &flags.divisors {
+            self.divisors.push(divisor);
+            self.value = self.rounding.divide(self.value, divisor as i32);
+        }
+
+        // Fate rolls are read off the Fate ladder
+        if flags.die == DieType::Fate {
+            self.fate_glyphs = Some(self.dice.iter().filter(|d| !d.is_dropped).map(|d| match d.value {
+                v if v > 0 => '+',
+                0 => '0',
+                _ => '-',
+            }).collect());
+            self.outcome = Some(SuccessLadder::fate().outcome(self.value));
+        }
+
+    }
+
+    /// Fill in `fingerprint` and `content_hash` from the flags and the roll as it is now
+    ///
+    /// Neither is filled in when rolling, so simulations don't pay for them. Fill them in once a
+    /// roll is finished, e.g. before storing it or sending it to a client.
+    pub fn stamp_hashes(&mut self, flags: &RollFlags) {
+        self.content_hash = Some(to_hex(&self.content_hash()));
+        self.fingerprint = Some(format!("{:016x}", flags.fingerprint()));
+    }
+
+    /// Hash what was rolled and how it added up, e.g. to spot the same roll being submitted twice
+    ///
+    /// The hash is SHA-256 over the equation, then each die's type, range, sides, tags, flags and
+    /// every value it landed on, then the modifiers, divisors, rounding and totals. IDs, timestamps
+    /// and any grading are left out, so changing a value changes the hash but rolling it again or
+    /// freezing it doesn't.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut content = format!("equation={}\n", self.equation);
+        for die in &self.dice {
+            let sides: Vec<String> = die.sides.iter().flat_map(|sides| sides.iter()).map(|side| side.to_string()).collect();
+            let history: Vec<String> = die.roll_history.iter().map(|record| record.value.to_string()).collect();
+            let flags = [die.is_dropped, die.is_exploded, die.is_immutable, die.is_manual, die.is_rerolled, die.is_successful];
+            let flags: String = flags.iter().map(|flag| if *flag { '1' } else { '0' }).collect();
+            content += &format!("die={}:{}:{}:[{}]:[{}]:{}:[{}]:{}\n", die.die.notation(), die.min, die.max,
+                                sides.join(","), die.tags.join(","), flags, history.join(","), die.value);
+
+            // Only taken dice say so, so the hashes of every other roll stay the same
+            if let Some(taken) = die.taken {
+                content += &format!("taken={}\n", taken.face());
+            }
+        }
+
+        let modifiers: Vec<String> = self.modifiers.iter().map(|modifier| modifier.to_string()).collect();
+        let divisors: Vec<String> = self.divisors.iter().map(|divisor| divisor.to_string()).collect();
+        content += &format!("modifiers=[{}]\ndivisors=[{}]\nrounding={:?}\nexplode_policy={:?}\n", modifiers.join(","),
+                            divisors.join(","), self.rounding, self.explode_policy);
+        content += &format!("totals={}:{}:{}:{}:{}\n", self.natural_value, self.raw_value, self.value, self.is_floored,
+                            self.is_ceilinged);
+
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(content.as_bytes()));
+        hash
+    }
+
+    /// Build a roll from values entered by hand, e.g. dice rolled at the table
+    ///
+    /// Values are used in the order the dice would be rolled: one for each die, then one for each
+    /// reroll and then each explosion, so `2d6rr1` entered as `[1, 4, 3]` rerolls the 1 into a 3.
+    /// Every die is marked as manual.
+    pub fn from_values(notation: &str, values: &[i16]) -> Result<Roll, RollError> {
+        let flags = match parse(notation).and_then(|mut rolls| rolls.pop()) {
+            Some(flags) => flags,
+            None => return Err(RollError::InvalidNotation),
+        };
+        flags.validate()?;
+
+        let template = Roll::build_die(&flags);
+        if let Some(index) = values.iter().position(|value| !template.has_face(*value)) {
+            return Err(RollError::ValueOutOfRange(index, values[index]));
+        }
+
+        let mut roller = Roller::manual(values.to_vec());
+        let mut roll = Roll::with_roller(flags, &mut roller);
+        let needed = values.len() + roller.missing() - roller.unused();
+        if needed != values.len() {
+            return Err(RollError::WrongValueCount(needed, values.len()));
+        }
+
+        for die in &mut roll.dice {
+            die.is_manual = true;
+        }
+        Ok(roll)
+    }
+
+    /// Read a roll sent back by a client, refusing one that couldn't have been rolled
+    ///
+    /// Deserializing a roll with serde trusts whatever it says, which is fine for storage the
+    /// server wrote itself. This also checks the roll with `integrity::check` and hands back every
+    /// violation found, e.g. a die on a value it doesn't have, two dice with the same ID or a total
+    /// that isn't what the dice come to.
+    pub fn from_json_validated(json: &str) -> Result<Roll, InvalidRoll> {
+        let roll: Roll = ::serde_json::from_str(json).map_err(|err| InvalidRoll::Malformed(err.to_string()))?;
+        let violations = integrity::check(&roll);
+        if !violations.is_empty() {
+            return Err(InvalidRoll::Violations(violations));
+        }
+        Ok(roll)
+    }
+
+    /// Reroll one kept die of a finished roll, e.g. when a player spends a resource to reroll it
+    ///
+    /// The roll is left as it was and a new roll is handed back, with its own ID and the ID of
+    /// this one as `amended_from`. In the new roll the die is rerolled, with the die rolled in its
+    /// place following it the same as any other reroll, and it explodes if the roll's dice do.
+    /// Which dice are kept, the successes and the totals are then worked out again, the same as if
+    /// the dice had been rolled that way. The flags are read from the equation, so the roll has to
+    /// have been made from notation. Outcomes graded with `grade` have to be graded again, and the
+    /// content hash stamped again. The new roll's `rng_draws` counts the original's draws too.
+    /// The house rules the roll was made under are applied to the flags again, so the die explodes
+    /// or rerolls the same as the rest did. Rolls made without replacement can't be amended, as
+    /// the die could land on another's face.
+    pub fn amend_reroll(&self, die_id: &str, roller: &mut Roller) -> Result<Roll, RollError> {
+        // The die could land on a face another die already has
+        if self.without_replacement {
+            return Err(RollError::ConflictingFlags("without_replacement", "rr"));
+        }
+
+        let mut flags = match parse(&self.equation).and_then(|mut rolls| rolls.pop()) {
+            Some(flags) => flags,
+            None => return Err(RollError::InvalidNotation),
+        };
+        for rule in &self.house_rules {
+            rule.apply_to(&mut flags);
+        }
+        let index = self.dice.iter().position(|die| die.id == die_id)
+            .ok_or_else(|| RollError::UnknownDie(die_id.to_string()))?;
+        if self.dice[index].is_dropped {
+            return Err(RollError::DroppedDie(die_id.to_string()));
+        }
+        if self.dice[index].is_immutable {
+            return Err(RollError::AlreadyRolled);
+        }
+
+        let draws = roller.total_draws();
+        let mut amended = self.clone();
+        amended.id = die::new_id();
+        amended.amended_from = Some(self.id.clone());
+        amended.beacon = None;
+        amended.content_hash = None;
+        amended.nonce = None;
+        amended.replay_epoch = None;
+        amended.timestamp = Utc::now();
+        amended.timing = None;
+
+        let mut replacement = Roll {
+            dice: vec![amended.dice[index].rerolled_copy()],
+            ..amended.clone()
+        };
+        replacement.dice[0].roll_with(roller);
+        amended.dice[index].rerolled(&replacement.dice[0]);
+        if flags.explode {
+            let trigger = flags.explode_op.as_ref().map(|op| (op, flags.explode_on));
+            replacement.explode_dice_when(trigger, flags.explode_policy, roller);
+        }
+        let next = amended.dice.iter().map(|die| die.position_in_term + 1).max().unwrap_or(0);
+        for (offset, die) in replacement.dice.iter_mut().enumerate() {
+            die.position_in_term = next + offset as u16;
+        }
+        amended.dice.append(&mut replacement.dice);
+
+        amended.dice[index].drop();
+        amended.reselect(&flags, roller);
+        amended.rng_draws = self.rng_draws + (roller.total_draws() - draws) as u32;
+        Ok(amended)
+    }
+
+    /// Move every die of the type by the delta, kept within its faces, then pick and total the
+    /// dice again, returning how many dice were moved
+    ///
+    /// Each moved die keeps the face it landed on as `natural`, and crits and fumbles are still
+    /// read from that face. The rest of the roll goes by the moved values: dice are kept, dropped
+    /// and counted as successes from them, and they add up to the total. Rerolled dice are left as
+    /// they were, as the dice rolled in their place count instead. A compounded die's value is
+    /// already past its highest face, so it's only kept from going under its lowest.
+    pub fn adjust(&mut self, die: DieType, delta: i16, flags: &RollFlags, roller: &mut Roller) -> usize {
+        let is_compounding = flags.explode_policy == ExplodeSuccessPolicy::CompoundThenCompare;
+        let mut moved = 0;
+        for adjusted in self.dice.iter_mut().filter(|d| d.die == die && !d.is_rerolled) {
+            let natural = adjusted.natural.unwrap_or(adjusted.value);
+            let highest = if is_compounding && adjusted.is_exploded { i16::max_value() } else { adjusted.max };
+            adjusted.natural = Some(natural);
+            adjusted.value = natural.saturating_add(delta).max(adjusted.min).min(highest);
+            moved += 1;
+        }
+        if moved > 0 {
+            self.reselect(flags, roller);
+        }
+        moved
+    }
+
+    /// Forget which dice were kept and what they came to, then pick and total them again
+    ///
+    /// Only rerolled dice stay dropped, everything else is picked again.
+    fn reselect(&mut self, flags: &RollFlags, roller: &mut Roller) {
+        for die in self.dice.iter_mut().filter(|die| !die.is_rerolled) {
+            die.is_dropped = false;
+            die.is_successful = false;
+        }
+        self.divisors.clear();
+        self.fate_glyphs = None;
+        self.is_ceilinged = false;
+        self.is_floored = false;
+        self.modifiers.clear();
+        self.outcome = None;
+        self.select_and_total(flags, roller);
+    }
+
+    /// Build every die of the term up front, into a list sized for them
+    fn build_dice(flags: &RollFlags) -> Vec<Die> {
+        let mut dice = Vec::with_capacity(flags.n.max(0) as usize);
+        for _ in 0..flags.n {
+            dice.push(Roll::build_die(flags));
+        }
+        dice
+    }
+
+    fn build_die(flags: &RollFlags) -> Die {
+        let mut die = Die::with_range(flags.die, flags.min, flags.max);
+        die.is_immutable = flags.immutable_dice;
+        die.tags = flags.tags.clone();
+
+        if let Some(ref sides) = flags.sides {
+            // Rolling dice without any sides is already rejected by validation
+            die.set_sides(sides.clone()).ok();
+            if flags.die == DieType::Fate {
+                die.fate_faces = FateFaces::from_sides(sides);
+            }
+        }
+        die
+    }
+
+    /// Grade the final value against a success ladder, optionally as the margin over a DC
+    // The outcome is unwrapped just after it's set, so it's always there
+    #[allow(clippy::unwrap_used)]
+    pub fn grade(&mut self, ladder: &SuccessLadder, dc: Option<i32>) -> &Outcome {
+        let margin = match dc {
+            Some(dc) => self.value - dc,
+            None => self.value,
+        };
+        self.outcome = Some(ladder.outcome(margin));
+        self.outcome.as_ref().unwrap()
+    }
+
+    /// If the die landed on a face that crits, its highest or any in a house crit range
+    pub fn is_critical(&self, die: &Die) -> bool {
+        die.is_critical() || self.house_rules.iter().any(|rule| match rule {
+            &HouseRule::CritRange(low) => die.die == DieType::D20 && die.landed_on() >= low,
+            _ => false,
+        })
+    }
+
+    /// Number of kept dice that were successful
+    pub fn successes(&self) -> usize {
+        self.dice.iter().filter(|d| d.is_successful && !d.is_dropped).count()
+    }
+
+    /// Give every die the index of the roll's term in its notation, e.g. 1 for the `1d6` of
+    /// `2d6 + 1d6`
+    pub fn set_term_index(&mut self, term_index: u16) {
+        for die in &mut self.dice {
+            die.term_index = term_index;
+        }
+        for warning in self.warnings.iter_mut().filter(|warning| warning.rule.starts_with("probability-")) {
+            warning.term = term_index as usize;
+        }
+    }
+
+    /// The dice in the order of the notation, by term and then by position in the term, however
+    /// they've been sorted since
+    pub fn dice_in_notation_order(&self) -> Vec<&Die> {
+        let mut dice: Vec<&Die> = self.dice.iter().collect();
+        dice.sort_by(|a, b| a.cmp_in_notation(b));
+        dice
+    }
+
+    /// Compare rolls by their number in their room's history, then by when they were made, then
+    /// by ID, so rolls made in the same instant sort the same way every time
+    ///
+    /// Use it with `sort_by`, e.g. to page a history. Rolls the same by this order can still differ
+    /// in their dice or totals, so it isn't what `==` means for a roll.
+    pub fn cmp_by_sequence(&self, other: &Roll) -> Ordering {
+        self.sequence.cmp(&other.sequence)
+            .then_with(|| self.timestamp.cmp(&other.timestamp))
+            .then_with(|| self.id.cmp(&other.id))
+    }
+
+    /// Sum of the kept dice with the tag
+    pub fn total_for_tag(&self, tag: &str) -> i32 {
+        self.dice.iter().filter(|d| !d.is_dropped && d.has_tag(tag)).fold(0, |sum, d| sum + d.counted() as i32)
+    }
+
+    /// Number of kept dice with the tag that were successful
+    pub fn successes_for_tag(&self, tag: &str) -> usize {
+        self.dice.iter().filter(|d| d.is_successful && !d.is_dropped && d.has_tag(tag)).count()
+    }
+
+    /// Sum of the kept dice with each tag, in the order the tags first appear
+    pub fn tag_subtotals(&self) -> TagSubtotals {
+        TagSubtotals::of(self.dice.iter())
+    }
+
+    /// The damage a target takes from the roll, by type, leaving the roll as it was
+    ///
+    /// The roll's value is damage of the first tag on its dice, or of the profile's default tag
+    /// when they have none, and dice of other tags do damage of their own, see
+    /// `DamageProfile::apply`. Use that for every term of a damage roll together.
+    pub fn apply_damage_profile(&self, profile: &DamageProfile) -> DamageReport {
+        profile.apply(::std::iter::once(self))
+    }
+
+    /// Share out the kept dice between named partitions by ID, e.g. two to attack and one to defend
+    ///
+    /// Each partition counts its own total and successes the same as the roll does. Kept dice that
+    /// weren't assigned go in the remainder, which also takes the modifiers, so the totals add up to
+    /// the roll's value. The roll is left as it was.
+    pub fn partition(&self, assignments: &[(String, Vec<String>)]) -> Result<PartitionedRoll, RollError> {
+        PartitionedRoll::of(self, assignments)
+    }
+
+    /// Roll another die for every kept die that lands on its highest face, chaining explosions
+    pub fn explode_dice(&mut self, policy: ExplodeSuccessPolicy, roller: &mut Roller) {
+        self.explode_dice_when(None, policy, roller);
+    }
+
+    /// Roll another die for every kept die matching the trigger, chaining explosions
+    ///
+    /// Without a trigger, dice explode on their highest face. The trigger is always checked against
+    /// the face a die landed on, so a compounded die keeps exploding on each new face rather than
+    /// on its growing total. Dice that would explode on every face don't explode at all, instead of
+    /// rolling up to the most explosions allowed, the roller's `Limits::max_explosions`.
+    pub fn explode_dice_when(&mut self, trigger: Option<(&ComparisonArg, i16)>, policy: ExplodeSuccessPolicy,
+                             roller: &mut Roller) {
+        let fires = |die: &Die, value: i16| match trigger {
+            Some((op, target)) => matches(op, value, target),
+            None => value == die.max,
+        };
+
+        let max_explosions = roller.limits().max_explosions;
+        let mut new_dice = Vec::new();
+        for die in &mut self.dice {
+            if die.is_dropped || die.faces().iter().all(|face| fires(die, *face)) {
+                continue;
+            }
+
+            let mut exploding = fires(die, die.value);
+            let mut explosions = 0;
+            while exploding && explosions < max_explosions && !roller.should_stop() {
+                let mut extra = die.unrolled_copy();
+                let value = extra.roll_with(roller);
+                explosions += 1;
+
+                if explosions == 1 {
+                    die.is_exploded = true;
+                }
+                exploding = fires(&extra, value);
+                match policy {
+                    ExplodeSuccessPolicy::CompareEachDie => {
+                        extra.is_exploded = exploding;
+                        new_dice.push(extra);
+                    },
+                    ExplodeSuccessPolicy::CompoundThenCompare => match die.value.checked_add(value) {
+                        Some(total) => die.value = total,
+                        // Compounding stops where the die's value can't hold any more, the same
+                        // as at the most explosions allowed. `Limits::check` turns such rolls away.
+                        None => break,
+                    },
+                }
+            }
+        }
+
+        self.dice.append(&mut new_dice);
+    }
+
+    /// Keep the dice greater than a number
+    pub fn keep_greater_than(&mut self, keep: i16) {
+        for die in &mut self.dice {
+            if die.value <= keep {
+                die.drop();
+            } else {
+                die.success();
+            }
+        }
+    }
+
+    /// Keep the dice greater than or equal to a number
+    pub fn keep_greater_than_or_equal_to(&mut self, keep: i16) {
+        for die in &mut self.dice {
+            if die.value < keep {
+                die.drop();
+            } else {
+                die.success();
+            }
+        }
+    }
+
+    /// Keep the dice matching a comparison, e.g. only the 6s, dropping the rest
+    ///
+    /// Dice already dropped stay dropped, and every die may be dropped. Like the other ways of
+    /// keeping dice, only the dice are marked and the totals aren't worked out again.
+    pub fn keep_where(&mut self, op: &ComparisonArg, target: i16) {
+        for die in self.dice.iter_mut().filter(|die| !die.is_dropped) {
+            if matches(op, die.value, target) {
+                die.success();
+            } else {
+                die.drop();
+            }
+        }
+    }
+
+    /// Keep the dice less than a number
+    pub fn keep_less_than(&mut self, keep: i16) {
+        for die in &mut self.dice {
+            if die.value >= keep {
+                die.drop();
+            } else {
+                die.success();
+            }
+        }
+    }
+
+    /// Keep the dice less than or equal to a number
+    pub fn keep_less_than_or_equal_to(&mut self, keep: i16) {
+        for die in &mut self.dice {
+            if die.value > keep {
+                die.drop();
+            } else {
+                die.success();
+            }
+        }
+    }
+
+    /// Keep the highest rolled dice, leaving out dice that were already dropped, e.g. by a reroll
+    ///
+    /// The dice stay in the order they were rolled, and of tied dice the first rolled are kept.
+    pub fn keep_high(&mut self, keep: u16) {
+        let mut ranked = self.kept_indices();
+        // The sort is stable, which keeps tied dice in the order they were rolled
+        ranked.sort_by(|&a, &b| self.dice[b].value.cmp(&self.dice[a].value));
+        for &i in ranked.iter().skip(keep as usize) {
+            self.dice[i].drop();
+        }
+    }
+
+    /// Keep the lowest rolled dice, leaving out dice that were already dropped, e.g. by a reroll
+    ///
+    /// The dice stay in the order they were rolled, and of tied dice the first rolled are kept.
+    pub fn keep_low(&mut self, keep: u16) {
+        let mut ranked = self.kept_indices();
+        ranked.sort_by_key(|&i| self.dice[i].value);
+        for &i in ranked.iter().skip(keep as usize) {
+            self.dice[i].drop();
+        }
+    }
+
+    /// Where each die that hasn't been dropped is in the roll
+    fn kept_indices(&self) -> Vec<usize> {
+        (0..self.dice.len()).filter(|&i| !self.dice[i].is_dropped).collect()
+    }
+
+    /// Drop the highest and lowest kept dice, e.g. 5d20 dropping one of each to sum the middle three
+    ///
+    /// Both ends are picked from a single ranking of the values as rolled, rather than ranking the
+    /// dice again after dropping the highest. Tied dice rank in the order they were rolled, so of
+    /// tied dice the first is dropped as lowest and the last as highest. When there are more drops
+    /// than dice, the lowest are dropped first and every die is dropped.
+    pub fn drop_extremes(&mut self, high: usize, low: usize) {
+        self.drop_extremes_ranked(high, low, &mut vec![]);
+    }
+
+    /// Drop the highest and lowest kept dice, ranking them in the given buffer so it can be reused
+    fn drop_extremes_ranked(&mut self, high: usize, low: usize, ranked: &mut Vec<usize>) {
+        ranked.clear();
+        ranked.extend((0..self.dice.len()).filter(|&i| !self.dice[i].is_dropped));
+        // The sort is stable, which keeps tied dice in the order they were rolled
+        ranked.sort_by_key(|&i| self.dice[i].value);
+
+        let low = low.min(ranked.len());
+        let high = high.min(ranked.len() - low);
+        let kept = ranked.len() - high;
+        for &i in ranked[..low].iter().chain(ranked[kept..].iter()) {
+            self.dice[i].drop();
+        }
+    }
+
+    /// Keep the middle kept dice, dropping the extra die from the high end when they can't be
+    /// trimmed evenly
+    pub fn keep_middle(&mut self, keep: usize) {
+        let (high, low) = middle_trim(self.dice.iter().filter(|d| !d.is_dropped).count(), keep);
+        self.drop_extremes(high, low);
+    }
+
+    /// Reroll dice one time that are above or below a certain threshold
+    pub fn reroll_dice_once(&mut self, op: &ComparisonArg, threshold: i16, roller: &mut Roller) {
+        let mut new_dice = Vec::new();
+        for die in &mut self.dice {
+            let comparison = match op {
+                &ComparisonArg::GreaterThan => !die.is_rerolled && die.value > threshold,
+                &ComparisonArg::GreaterThanOrEqual => !die.is_rerolled && die.value >= threshold,
+                &ComparisonArg::LessThan => !die.is_rerolled && die.value < threshold,
+                &ComparisonArg::LessThanOrEqual => !die.is_rerolled && die.value <= threshold,
+                &ComparisonArg::EqualTo => !die.is_rerolled && die.value == threshold,
+            };
+
+            if comparison {
+                let mut d = die.rerolled_copy();
+                d.roll_with(roller);
+                &die.rerolled(&d);
+                &die.drop();
+                new_dice.push(d);
+            }
+        }
+
+        self.dice.append(&mut new_dice);
+    }
+
+    /// Reroll dice forever that are above or below a certain threshold
+    ///
+    /// Rerolling stops after the roller's `Limits::max_rerolls` rounds, see `RollOptions::limits`.
+    pub fn reroll_dice_forever(&mut self, op: &ComparisonArg, threshold: i16, roller: &mut Roller) {
+        let max_rerolls = roller.limits().max_rerolls;
+        let mut rounds = 0;
+        loop {
+            // Reroll any dice that need to be rerolled
+            self.reroll_dice_once(&op, threshold, roller);
+            rounds += 1;
+
+            let mut has_more = false;
+            for die in self.dice.iter() {
+                let comparison = match op {
+                    &ComparisonArg::GreaterThan => !die.is_rerolled && die.value > threshold,
+                    &ComparisonArg::GreaterThanOrEqual => !die.is_rerolled && die.value >= threshold,
+                    &ComparisonArg::LessThan => !die.is_rerolled && die.value < threshold,
+                    &ComparisonArg::LessThanOrEqual => !die.is_rerolled && die.value <= threshold,
+                    &ComparisonArg::EqualTo => !die.is_rerolled && die.value == threshold,
+                };
+
+                if comparison {
+                    has_more = true
+                }
+            }
+
+            // A manual roller that ran out would hand back the same value forever
+            if !has_more || roller.is_exhausted() || rounds >= max_rerolls || roller.should_stop() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn d20(n: i16) -> RollFlags {
+    let mut flags = RollFlags::new();
+    flags.die = DieType::D20;
+    flags.n = n;
+    flags.max = 20;
+    flags
+}
+
+#[test]
+fn it_can_floor_the_kept_die() {
+    let mut flags = d20(1);
+    flags.floor = Some(10);
+    flags.modifiers = vec![5];
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3]));
+    assert_eq!(roll.natural_value, 3);
+    assert_eq!(roll.raw_value, 10);
+    assert_roll_total(&roll, 15);
+    assert!(roll.is_floored);
+
+    let mut flags = d20(1);
+    flags.floor = Some(10);
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![10]));
+    assert_eq!(roll.raw_value, 10);
+    assert!(!roll.is_floored);
+}
+
+#[test]
+fn it_can_floor_the_die_kept_with_advantage() {
+    // The floor only applies to the kept die, so a low dropped die doesn't matter
+    let mut flags = d20(2);
+    flags.kh = 1;
+    flags.floor = Some(10);
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![2, 14]));
+    assert_eq!(roll.natural_value, 14);
+    assert_eq!(roll.raw_value, 14);
+    assert!(!roll.is_floored);
+
+    let mut flags = d20(2);
+    flags.kh = 1;
+    flags.floor = Some(10);
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![2, 7]));
+    assert_eq!(roll.natural_value, 7);
+    assert_eq!(roll.raw_value, 10);
+    assert!(roll.is_floored);
+}
+
+#[test]
+fn it_can_ceiling_the_kept_die() {
+    let mut flags = d20(2);
+    flags.kl = 1;
+    flags.ceiling = Some(15);
+    flags.modifiers = vec![-1];
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![19, 20]));
+    assert_eq!(roll.natural_value, 19);
+    assert_eq!(roll.raw_value, 15);
+    assert_roll_total(&roll, 14);
+    assert!(roll.is_ceilinged);
+    assert!(!roll.is_floored);
+}
+
+#[test]
+fn it_rejects_a_floor_above_the_ceiling() {
+    let mut flags = d20(1);
+    flags.floor = Some(12);
+    flags.ceiling = Some(8);
+    assert_eq!(flags.validate(), Err(RollError::FloorAboveCeiling(12, 8)));
+
+    flags.ceiling = Some(12);
+    assert_eq!(flags.validate(), Ok(()));
+}
+
+#[test]
+fn it_rejects_a_lowest_side_above_the_highest() {
+    let mut flags = d20(1);
+    flags.min = 30;
+    assert_eq!(flags.validate(), Err(RollError::MinAboveMax(30, 20)));
+
+    flags.min = 20;
+    assert_eq!(flags.validate(), Ok(()));
+}
+
+#[test]
+fn it_can_roll_zero_dice() {
+    let mut none = d20(0);
+    none.gt = 10;
+    assert_eq!(none.validate(), Ok(()));
+    let roll = Roll::with_roller(none, &mut ScriptedRoller::new(vec![]));
+    assert!(roll.dice.is_empty());
+    assert_roll_total(&roll, 0);
+    assert_eq!(roll.dice.iter().filter(|d| d.is_successful).count(), 0);
+
+    // An empty term doesn't use up any randomness
+    let mut roller = Roller::seeded(3);
+    Roll::with_roller(d20(0), &mut roller);
+    let after_empty = Roll::with_roller(d20(1), &mut roller);
+    let plain = Roll::with_roller(d20(1), &mut Roller::seeded(3));
+    assert_eq!(after_empty.dice[0].value, plain.dice[0].value);
+}
+
+#[test]
+fn it_can_roll_a_d1() {
+    let mut d1 = RollFlags::new();
+    d1.n = 3;
+    d1.max = 1;
+
+    let mut roller = Roller::seeded(3);
+    let roll = Roll::with_roller(d1, &mut roller);
+    assert_roll_total(&roll, 3);
+
+    let after_d1 = Roll::with_roller(d20(1), &mut roller);
+    let plain = Roll::with_roller(d20(1), &mut Roller::seeded(3));
+    assert_eq!(after_d1.dice[0].value, plain.dice[0].value);
+}
+
+#[test]
+fn it_rejects_dice_without_sides() {
+    let mut d0 = RollFlags::new();
+    d0.n = 3;
+    assert_eq!(d0.validate(), Err(RollError::NoSides));
+
+    d0.sides = Some(vec![]);
+    assert_eq!(d0.validate(), Err(RollError::NoSides));
+
+    d0.n = 0;
+    assert_eq!(d0.validate(), Ok(()));
+}
+
+#[test]
+fn it_draws_every_die_on_a_different_face() {
+    let flags = RollFlags::builder().n(4).sides(vec![10, 20, 30, 40, 50, 60]).without_replacement().build().unwrap();
+    for seed in 0..200 {
+        let roll = Roll::with_roller(flags.clone(), &mut Roller::seeded(seed));
+        let mut values: Vec<i16> = roll.dice.iter().map(|die| die.value).collect();
+        values.sort();
+        values.dedup();
+        assert_eq!(values.len(), 4);
+        assert!(roll.without_replacement);
+    }
+
+    // As many dice as faces draws every face
+    let roll = Roll::with_roller(RollFlags::builder().n(6).die(DieType::D6).without_replacement().build().unwrap(),
+                                 &mut Roller::seeded(9));
+    let mut values: Vec<i16> = roll.dice.iter().map(|die| die.value).collect();
+    values.sort();
+    assert_eq!(values, vec![1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn it_draws_parsed_dice_without_replacement() {
+    let flags = parse("4d6nr").unwrap().pop().unwrap();
+    for seed in 0..50 {
+        let roll = Roll::with_roller(flags.clone(), &mut Roller::seeded(seed));
+        let mut values: Vec<i16> = roll.dice.iter().map(|die| die.value).collect();
+        values.sort();
+        values.dedup();
+        assert_eq!(values.len(), 4);
+        assert!(values.iter().all(|value| *value >= 1 && *value <= 6));
+    }
+}
+
+#[test]
+fn it_checks_there_are_faces_to_draw() {
+    let lottery = RollFlags::builder().sides(vec![10, 20, 30, 40, 50, 60]).without_replacement();
+    assert_eq!(lottery.clone().n(7).build().err(), Some(RollError::NotEnoughFaces(7, 6)));
+    assert_eq!(lottery.clone().n(2).explode(ExplodeSuccessPolicy::CompareEachDie).build().err(),
+               Some(RollError::ConflictingFlags("without_replacement", "explode")));
+    assert_eq!(lottery.n(2).rr(ComparisonArg::LessThan, 20).build().err(),
+               Some(RollError::ConflictingFlags("without_replacement", "rr")));
+
+    // Repeated faces only count once
+    let repeated = RollFlags::builder().n(3).sides(vec![1, 1, 2]).without_replacement().build();
+    assert_eq!(repeated.err(), Some(RollError::NotEnoughFaces(3, 2)));
+}
+
+#[test]
+fn it_draws_the_same_faces_from_the_same_seed() {
+    let flags = RollFlags::builder().n(6).sides(vec![10, 20, 30, 40, 50, 60]).without_replacement().build().unwrap();
+    let values = |roll: Roll| roll.dice.iter().map(|die| die.value).collect::<Vec<i16>>();
+    let first = values(Roll::with_roller(flags.clone(), &mut Roller::seeded(42)));
+    assert_eq!(values(Roll::with_roller(flags.clone(), &mut Roller::seeded(42))), first);
+
+    // Each face drawn is set aside, and the last die has only one face left so draws nothing
+    let mut roller = ScriptedRoller::new(vec![30, 10, 60, 20, 50]);
+    let roll = Roll::with_roller(flags, &mut roller);
+    assert_eq!(values(roll.clone()), vec![30, 10, 60, 20, 50, 40]);
+    assert_eq!(roll.rng_draws, 5);
+    assert_eq!(roll.amend_reroll(&roll.dice[0].id.clone(), &mut Roller::new()).err(),
+               Some(RollError::ConflictingFlags("without_replacement", "rr")));
+}
+
+#[cfg(all(test, feature = "tracing"))]
+fn capture_events<F: FnOnce()>(f: F) -> Vec<(String, i64)> {
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    struct Visitor(String, i64);
+
+    impl Visit for Visitor {
+        fn record_i64(&mut self, field: &Field, value: i64) {
+            if field.name() == "value" {
+                self.1 = value;
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    struct Capture(Arc<Mutex<Vec<(String, i64)>>>);
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event, _ctx: Context<S>) {
+            let mut visitor = Visitor(String::new(), 0);
+            event.record(&mut visitor);
+            self.0.lock().unwrap().push((visitor.0, visitor.1));
+        }
+    }
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let subscriber = tracing_subscriber::registry().with(Capture(events.clone()));
+    tracing::subscriber::with_default(subscriber, f);
+
+    let events = events.lock().unwrap();
+    events.clone()
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn it_traces_rerolls_and_drops() {
+    let mut flags = RollFlags::new();
+    flags.die = DieType::D6;
+    flags.n = 3;
+    flags.max = 6;
+    flags.ro = 2;
+    flags.ro_op = Some(ComparisonArg::LessThan);
+    flags.kh = 2;
+
+    let events = capture_events(|| {
+        Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1, 4, 6, 5]));
+    });
+    let decisions: Vec<(String, i64)> = events.into_iter().filter(|e| e.0 != "roll").collect();
+
+    assert_eq!(decisions, vec![
+        ("reroll".to_string(), 1),
+        ("drop".to_string(), 1),
+        ("drop".to_string(), 4),
+        ("drop".to_string(), 1),
+    ]);
+}
+
+#[test]
+fn it_can_write_a_canonical_roll() {
+    let mut flags = d20(1);
+    flags.equation = "1d20+2+3[attack]".to_string();
+    flags.modifiers = vec![2, 3];
+    assert_eq!(flags.canonical(), "1d20+5");
+
+    let mut flags = d20(2);
+    flags.kh = 1;
+    flags.rr = 1;
+    flags.rr_op = Some(ComparisonArg::EqualTo);
+    flags.modifiers = vec![-4, 1];
+    assert_eq!(flags.canonical(), "2d20kh1rr==1-3");
+
+    let mut flags = RollFlags::new();
+    flags.n = 4;
+    flags.sides = Some(vec![-1, 0, 1]);
+    assert_eq!(flags.canonical(), "4d[-1,0,1]");
+}
+
+#[test]
+fn it_can_build_a_roll_from_values() {
+    let roll = Roll::from_values("4d6kh3", &[3, 6, 2, 5]).unwrap();
+    assert_roll_total(&roll, 14);
+    assert!(roll.dice.iter().all(|d| d.is_manual));
+    let dropped: Vec<i16> = roll.dice.iter().filter(|d| d.is_dropped).map(|d| d.value).collect();
+    assert_eq!(dropped, vec![2]);
+
+    // Rerolls take the next value
+    let roll = Roll::from_values("2d6rr1", &[1, 4, 3]).unwrap();
+    assert_roll_total(&roll, 7);
+    assert_eq!(roll.dice.len(), 3);
+}
+
+#[test]
+fn it_rejects_values_a_die_cannot_show() {
+    assert_eq!(Roll::from_values("4d6", &[3, 7, 2, 5]).err(), Some(RollError::ValueOutOfRange(1, 7)));
+    assert_eq!(Roll::from_values("2d6", &[0, 1]).err(), Some(RollError::ValueOutOfRange(0, 0)));
+}
+
+#[test]
+fn it_rejects_the_wrong_number_of_values() {
+    assert_eq!(Roll::from_values("4d6kh3", &[3, 6, 2]).err(), Some(RollError::WrongValueCount(4, 3)));
+    assert_eq!(Roll::from_values("4d6kh3", &[3, 6, 2, 5, 1]).err(), Some(RollError::WrongValueCount(4, 5)));
+    assert_eq!(Roll::from_values("2d6rr1", &[1, 4]).err(), Some(RollError::WrongValueCount(3, 2)));
+}
+
+#[test]
+fn it_can_chain_divisions() {
+    let rounded = |rounding: Rounding| {
+        let mut flags = d20(1);
+        flags.modifiers = vec![-10];
+        flags.divisors = vec![2, 2];
+        flags.rounding = rounding;
+        Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3])).value
+    };
+
+    // -7 / 2 / 2, rounding after each division
+    assert_eq!(rounded(Rounding::Floor), -2);
+    assert_eq!(rounded(Rounding::Ceil), -1);
+    assert_eq!(rounded(Rounding::HalfUp), -1);
+    assert_eq!(rounded(Rounding::Truncate), -1);
+}
+
+#[test]
+fn it_records_the_rounding() {
+    let mut flags = d20(1);
+    flags.divisors = vec![2];
+    flags.rounding = Rounding::Ceil;
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![15]));
+    assert_roll_total(&roll, 8);
+    assert_eq!(roll.divisors, vec![2]);
+    assert_eq!(roll.rounding, Rounding::Ceil);
+
+    let mut flags = d20(1);
+    flags.divisors = vec![0];
+    assert_eq!(flags.validate(), Err(RollError::DivideByZero));
+}
+
+#[test]
+fn it_builds_the_same_defaults_as_new() {
+    let built = RollFlags::builder().build().unwrap();
+    let new = RollFlags::new();
+    assert!(built.ceiling.is_none() && built.floor.is_none());
+    assert_eq!(built.die, new.die);
+    assert_eq!(built.divisors, new.divisors);
+    assert_eq!(built.equation, new.equation);
+    assert_eq!((built.gt, built.gte, built.lt, built.lte), (new.gt, new.gte, new.lt, new.lte));
+    assert_eq!((built.kh, built.kl), (new.kh, new.kl));
+    assert_eq!((built.min, built.max, built.n), (new.min, new.max, new.n));
+    assert_eq!(built.modifiers, new.modifiers);
+    assert_eq!((built.ro, built.rr), (new.ro, new.rr));
+    assert!(built.ro_op.is_none() && built.rr_op.is_none());
+    assert_eq!(built.rounding, new.rounding);
+    assert_eq!(built.sides, new.sides);
+}
+
+#[test]
+fn it_rejects_conflicting_flags() {
+    let base = RollFlags::builder().n(2).die(DieType::D20);
+    let set = |builder: RollFlagsBuilder, name: &str| match name {
+        "gt" => builder.gt(10),
+        "gte" => builder.gte(10),
+        "lt" => builder.lt(10),
+        "lte" => builder.lte(10),
+        "kh" => builder.kh(1),
+        _ => builder.kl(1),
+    };
+
+    // Every pair of ways to keep dice conflicts, named in the same order
+    let keeps = ["gt", "gte", "lt", "lte", "kh", "kl"];
+    for (i, &first) in keeps.iter().enumerate() {
+        assert!(set(base.clone(), first).build().is_ok());
+        for &second in keeps[i + 1..].iter() {
+            let both = set(set(base.clone(), first), second);
+            assert_eq!(both.build().err(), Some(RollError::ConflictingFlags(first, second)));
+        }
+    }
+
+    let capped = base.clone().floor(15).ceiling(10);
+    assert_eq!(capped.build().err(), Some(RollError::FloorAboveCeiling(15, 10)));
+    assert_eq!(base.clone().divide_by(0).build().err(), Some(RollError::DivideByZero));
+    assert_eq!(RollFlags::builder().n(1).build().err(), Some(RollError::NoSides));
+}
+
+#[test]
+fn it_can_reuse_a_builder() {
+    let defaults = RollFlags::builder().n(1).die(DieType::D20).modifier(5);
+    let attack = defaults.clone().kh(1).n(2).build().unwrap();
+    let check = defaults.build().unwrap();
+    let again = defaults.build().unwrap();
+
+    assert_eq!(attack.canonical(), "2d20kh1+5");
+    assert_eq!(check.canonical(), "1d20+5");
+    assert_eq!(again.canonical(), check.canonical());
+    assert_eq!(Roll::with_roller(check, &mut ScriptedRoller::new(vec![12])).value, 17);
+}
+
+#[test]
+fn it_counts_successes_on_each_exploded_die() {
+    let flags = RollFlags::builder().n(1).die(DieType::D6).gte(5)
+        .explode(ExplodeSuccessPolicy::CompareEachDie).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 6, 2]));
+    let values: Vec<i16> = roll.dice.iter().map(|d| d.value).collect();
+    assert_eq!(values, vec![6, 6, 2]);
+    assert_eq!(roll.successes(), 2);
+    assert_eq!(roll.explode_policy, ExplodeSuccessPolicy::CompareEachDie);
+
+    let exploded: Vec<bool> = roll.dice.iter().map(|d| d.is_exploded).collect();
+    assert_eq!(exploded, vec![true, true, false]);
+    let successful: Vec<bool> = roll.dice.iter().map(|d| !d.is_dropped).collect();
+    assert_eq!(successful, vec![true, true, false]);
+}
+
+#[test]
+fn it_counts_successes_on_compounded_dice() {
+    let flags = RollFlags::builder().n(1).die(DieType::D6).gte(5)
+        .explode(ExplodeSuccessPolicy::CompoundThenCompare).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 6, 2]));
+    assert_eq!(roll.dice.len(), 1);
+    assert_eq!(roll.dice[0].value, 14);
+    assert!(roll.dice[0].is_exploded);
+    assert!(roll.dice[0].is_successful);
+    assert_eq!(roll.successes(), 1);
+    assert_eq!(roll.explode_policy, ExplodeSuccessPolicy::CompoundThenCompare);
+}
+
+#[test]
+fn it_can_roll_custom_fate_dice() {
+    let template = Die::fate_with_faces(3, 2, 1).unwrap();
+    let roll = Roll::with_roller(RollFlags::fate(4, &template), &mut Roller::seeded(3));
+    assert_eq!(roll.equation, "4dF");
+    assert_eq!(roll.dice.len(), 4);
+    for die in roll.dice.iter() {
+        assert_eq!(die.fate_faces, template.fate_faces);
+        assert!(die.value >= -1 && die.value <= 1);
+    }
+
+    // Rerolled dice keep the custom faces
+    let mut flags = RollFlags::fate(2, &template);
+    flags.ro_op = Some(ComparisonArg::EqualTo);
+    flags.ro = -1;
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![-1, 1, 0]));
+    assert_eq!(roll.dice[2].fate_faces, template.fate_faces);
+    assert_roll_total(&roll, 1);
+
+    // A plain template rolls like an ordinary Fate die
+    let roll = Roll::with_roller(RollFlags::fate(4, &Die::new(DieType::Fate)), &mut Roller::seeded(3));
+    assert!(roll.value >= -4 && roll.value <= 4);
+    assert!(roll.dice.iter().all(|d| d.fate_faces.is_none() && d.min == -1 && d.max == 1));
+}
+
+#[test]
+fn it_carries_the_history_over_to_rerolled_dice() {
+    let flags = RollFlags::builder().n(1).die(DieType::D6).rr(ComparisonArg::LessThan, 3).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1, 2, 5]));
+    let kept: Vec<&Die> = roll.dice.iter().filter(|d| !d.is_dropped).collect();
+    assert_eq!(kept.len(), 1);
+    let values: Vec<i16> = kept[0].roll_history.iter().map(|record| record.value).collect();
+    assert_eq!(values, vec![1, 2, 5]);
+    assert_roll_total(&roll, 5);
+}
+
+#[test]
+fn it_can_make_dice_immutable() {
+    let flags = RollFlags::builder().n(2).die(DieType::D6).immutable_dice(true).build().unwrap();
+    let mut roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3, 4]));
+    assert_eq!(roll.dice[0].try_roll_with(&mut ScriptedRoller::new(vec![6])), Err(RollError::AlreadyRolled));
+
+    let rerolling = RollFlags::builder().n(2).die(DieType::D6).immutable_dice(true).ro(ComparisonArg::EqualTo, 1).build();
+    assert_eq!(rerolling.err(), Some(RollError::ConflictingFlags("immutable_dice", "ro")));
+}
+
+#[cfg(test)]
+fn shift_die(n: i16) -> RollFlagsBuilder {
+    RollFlags::builder().n(n).sides(vec![-2, -1, 0, 1, 2]).min(-2).max(2)
+}
+
+#[test]
+fn it_rolls_shift_dice() {
+    let roll = Roll::with_roller(shift_die(3).build().unwrap(), &mut ScriptedRoller::new(vec![-2, 0, 2]));
+    assert_roll_total(&roll, 0);
+
+    // The largest face is a crit and the smallest a fumble, whatever their sign
+    assert!(roll.dice[0].is_fumble());
+    assert!(!roll.dice[1].is_critical() && !roll.dice[1].is_fumble());
+    assert!(roll.dice[2].is_critical());
+
+    let roll = Roll::with_roller(shift_die(2).kh(1).build().unwrap(), &mut ScriptedRoller::new(vec![-2, -1]));
+    assert_roll_total(&roll, -1);
+}
+
+#[test]
+fn it_counts_successes_on_shift_dice() {
+    let flags = shift_die(5).lte(-1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![-2, -1, 0, 1, 2]));
+    assert_eq!(roll.successes(), 2);
+    assert_roll_total(&roll, -3);
+
+    let flags = shift_die(5).gt(-1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![-2, -1, 0, 1, 2]));
+    assert_eq!(roll.successes(), 3);
+
+    // Rerolling against a negative threshold
+    let flags = shift_die(1).rr(ComparisonArg::LessThanOrEqual, -1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![-2, -1, 1]));
+    assert_roll_total(&roll, 1);
+}
+
+#[test]
+fn it_formats_shift_dice() {
+    let flags = shift_die(4).lte(-1).modifier(-2).build().unwrap();
+    assert_eq!(flags.canonical(), "4d[-2,-1,0,1,2]lte-1-2");
+    assert!(!flags.canonical().contains("--") && !flags.canonical().contains("+-"));
+
+    let flags = shift_die(4).modifier(3).modifier(-5).build().unwrap();
+    assert_eq!(flags.canonical(), "4d[-2,-1,0,1,2]-2");
+}
+
+#[test]
+fn it_rolls_fate_notation() {
+    let flags = parse("4dF+2").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1, 0, -1, 1]));
+    assert_roll_total(&roll, 3);
+    assert_eq!(roll.fate_glyphs, Some("+0-+".to_string()));
+    assert_eq!(roll.outcome.as_ref().map(|outcome| outcome.label.as_str()), Some("Good"));
+    assert!(roll.dice.iter().all(|d| d.die == DieType::Fate));
+
+    // Other dice have no glyphs or ladder
+    let roll = Roll::with_roller(d20(1), &mut ScriptedRoller::new(vec![12]));
+    assert_eq!(roll.fate_glyphs, None);
+    assert!(roll.outcome.is_none());
+}
+
+#[cfg(test)]
+fn dropped(roll: &Roll) -> Vec<bool> {
+    roll.dice.iter().map(|d| d.is_dropped).collect()
+}
+
+#[test]
+fn it_drops_the_highest_and_lowest_dice() {
+    let flags = parse("5d20dh1dl1").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![7, 20, 1, 12, 9]));
+    assert_roll_total(&roll, 28);
+    assert_eq!(dropped(&roll), vec![false, true, true, false, false]);
+
+    let flags = RollFlags::builder().n(5).die(DieType::D20).drop_extremes(2, 1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![7, 20, 1, 12, 9]));
+    assert_roll_total(&roll, 16);
+}
+
+#[test]
+fn it_drops_tied_dice_in_the_order_they_were_rolled() {
+    // One ranking picks both ends, so the middle of three tied dice is the one kept
+    let flags = RollFlags::builder().n(3).die(DieType::D6).drop_extremes(1, 1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![5, 5, 5]));
+    assert_eq!(dropped(&roll), vec![true, false, true]);
+
+    let flags = RollFlags::builder().n(4).die(DieType::D6).drop_extremes(1, 1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3, 5, 3, 5]));
+    assert_roll_total(&roll, 8);
+    assert_eq!(dropped(&roll), vec![true, false, false, true]);
+
+    // More drops than dice drops everything, lowest first
+    let flags = RollFlags::builder().n(2).die(DieType::D6).drop_extremes(2, 1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![2, 4]));
+    assert_roll_total(&roll, 0);
+}
+
+#[test]
+fn it_keeps_dice_in_the_order_they_were_rolled() {
+    let values = |roll: &Roll| roll.dice.iter().map(|d| d.value).collect::<Vec<i16>>();
+    let mut roll = Roll::with_roller(d20(4), &mut ScriptedRoller::new(vec![3, 17, 9, 17]));
+    let ids: Vec<String> = roll.dice.iter().map(|d| d.id.clone()).collect();
+    roll.keep_high(2);
+    assert_eq!(values(&roll), vec![3, 17, 9, 17]);
+    assert_eq!(dropped(&roll), vec![true, false, true, false]);
+    assert_eq!(roll.dice.iter().map(|d| d.id.clone()).collect::<Vec<String>>(), ids);
+
+    // Of tied dice the first rolled are kept, and dice already dropped aren't kept again
+    let mut roll = Roll::with_roller(d20(4), &mut ScriptedRoller::new(vec![9, 2, 9, 2]));
+    roll.keep_high(1);
+    assert_eq!(dropped(&roll), vec![false, true, true, true]);
+    let mut roll = Roll::with_roller(d20(4), &mut ScriptedRoller::new(vec![9, 2, 9, 2]));
+    roll.dice[1].drop();
+    roll.keep_low(2);
+    assert_eq!(values(&roll), vec![9, 2, 9, 2]);
+    assert_eq!(dropped(&roll), vec![false, true, true, false]);
+}
+
+#[test]
+fn it_keeps_the_middle_dice() {
+    let flags = RollFlags::builder().n(5).die(DieType::D20).keep_middle(3).build().unwrap();
+    assert_eq!((flags.dh, flags.dl), (1, 1));
+
+    // Four dice can't be trimmed evenly to one, so the extra die comes off the high end
+    let flags = RollFlags::builder().n(4).die(DieType::D6).keep_middle(1).build().unwrap();
+    assert_eq!((flags.dh, flags.dl), (2, 1));
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![2, 6, 4, 3]));
+    assert_roll_total(&roll, 3);
+
+    let mut roll = Roll::with_roller(d20(4), &mut ScriptedRoller::new(vec![2, 6, 4, 3]));
+    roll.keep_middle(2);
+    assert_eq!(dropped(&roll), vec![true, true, false, false]);
+}
+
+#[test]
+fn it_rejects_dropping_with_another_keep() {
+    let flags = RollFlags::builder().n(4).die(DieType::D6).kh(3).dl(1).build();
+    assert_eq!(flags.err(), Some(RollError::ConflictingFlags("kh", "dl")));
+}
+
+#[test]
+fn it_explodes_on_a_comparison() {
+    let flags = parse("1d10!>8").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![9, 10, 3]));
+    let values: Vec<i16> = roll.dice.iter().map(|d| d.value).collect();
+    assert_eq!(values, vec![9, 10, 3]);
+    let exploded: Vec<bool> = roll.dice.iter().map(|d| d.is_exploded).collect();
+    assert_eq!(exploded, vec![true, true, false]);
+    assert_roll_total(&roll, 22);
+
+    // Compounding compares each new face, not the growing total
+    let flags = RollFlags::builder().n(1).die(DieType::D10)
+        .explode(ExplodeSuccessPolicy::CompoundThenCompare)
+        .explode_on(ComparisonArg::GreaterThanOrEqual, 9).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![9, 10, 3]));
+    assert_eq!(roll.dice.len(), 1);
+    assert_roll_total(&roll, 22);
+}
+
+#[test]
+fn it_stops_at_the_rollers_limits() {
+    use roller::RollOptions;
+
+    let limits = Limits {
+        max_explosions: 2,
+        max_rerolls: 3,
+        ..Limits::default()
+    };
+    let options = RollOptions::new().limits(limits);
+    let flags = parse("1d2!").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut Roller::scripted(vec![2, 2, 2, 2, 2]).with_options(&options));
+    assert_eq!(roll.dice.iter().map(|d| d.value).collect::<Vec<i16>>(), vec![2, 2, 2]);
+    assert_roll_total(&roll, 6);
+
+    // Rerolling forever stops after the limit's rounds too
+    let flags = parse("1d6rr1").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut Roller::scripted(vec![1, 1, 1, 1, 1, 1]).with_options(&options));
+    assert_eq!(roll.dice.len(), 4);
+    assert_eq!(roll.dice.iter().filter(|d| !d.is_dropped).count(), 1);
+}
+
+#[test]
+fn it_catches_explosions_that_always_fire() {
+    let flags = RollFlags::builder().n(2).die(DieType::D6).explode_on(ComparisonArg::GreaterThanOrEqual, 1).build().unwrap();
+    assert_eq!(Limits::default().check(&flags), Err(RollError::EndlessExplosion));
+
+    // Rolled anyway, the dice don't explode rather than exploding up to the cap
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3, 5]));
+    assert_eq!(roll.dice.len(), 2);
+    assert!(roll.dice.iter().all(|d| !d.is_exploded));
+
+    let flags = RollFlags::builder().n(2).die(DieType::D6).explode_on(ComparisonArg::GreaterThan, 4).build().unwrap();
+    assert_eq!(Limits::default().check(&flags), Ok(()));
+    let one_face = RollFlags::builder().n(1).max(1).explode(ExplodeSuccessPolicy::CompareEachDie).build().unwrap();
+    assert_eq!(Limits::default().check(&one_face), Err(RollError::EndlessExplosion));
+}
+
+#[test]
+fn it_stops_compounding_before_a_die_overflows() {
+    let flags = RollFlags::builder().n(1).max(1000).explode(ExplodeSuccessPolicy::CompoundThenCompare)
+        .explode_on(ComparisonArg::GreaterThan, 1).build().unwrap();
+    assert_eq!(Limits::default().check(&flags), Err(RollError::CompoundOverflow(101000)));
+
+    // Rolled anyway, the die keeps the last total it could hold
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1000; 40]));
+    assert_eq!(roll.dice.len(), 1);
+    assert_eq!(roll.dice[0].value, 32000);
+    assert_roll_total(&roll, 32000);
+
+    let flags = RollFlags::builder().n(1).die(DieType::D20).explode(ExplodeSuccessPolicy::CompoundThenCompare)
+        .build().unwrap();
+    assert_eq!(Limits::default().check(&flags), Ok(()));
+}
+
+#[test]
+fn it_carries_tags_to_every_die() {
+    // Both explosions of the first die carry its tag
+    let flags = RollFlags::builder().n(2).die(DieType::D6).tag("fire").explode(ExplodeSuccessPolicy::CompareEachDie)
+        .build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![6, 2, 6, 3]));
+    assert_eq!(roll.dice.len(), 4);
+    assert!(roll.dice.iter().all(|d| d.tags == vec!["fire"]));
+    assert_eq!(roll.total_for_tag("fire"), 17);
+    assert_eq!(roll.total_for_tag("cold"), 0);
+
+    // So does the die a reroll replaces the 1 with, and the dropped 1 no longer counts
+    let flags = RollFlags::builder().n(2).die(DieType::D6).tag("cold").rr(ComparisonArg::LessThan, 2).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1, 4, 5]));
+    assert_eq!(roll.dice.len(), 3);
+    assert!(roll.dice[2].has_tag("cold"));
+    assert_eq!(roll.total_for_tag("cold"), 9);
+    assert_eq!(roll.successes_for_tag("cold"), 2);
+}
+
+#[test]
+fn it_keeps_the_notation_order_of_sorted_dice() {
+    let flags = RollFlags::builder().n(4).die(DieType::D6).explode(ExplodeSuccessPolicy::CompareEachDie)
+        .equation("4d6!").build().unwrap();
+    let mut roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![2, 6, 4, 1, 3]));
+    roll.set_term_index(1);
+    let indices = |dice: Vec<&Die>| dice.iter().map(|d| (d.term_index, d.position_in_term, d.value)).collect::<Vec<_>>();
+    let rolled = vec![(1, 0, 2), (1, 1, 6), (1, 2, 4), (1, 3, 1), (1, 4, 3)];
+    assert_eq!(indices(roll.dice.iter().collect()), rolled);
+
+    // Sorting the dice, and writing them out and back, leaves where they come in the notation
+    roll.dice.sort_by(|a, b| b.value.cmp(&a.value));
+    let restored: Roll = ::serde_json::from_str(&::serde_json::to_string(&roll).unwrap()).unwrap();
+    assert_eq!(restored.dice[0].value, 6);
+    assert_eq!(indices(restored.dice_in_notation_order()), rolled);
+
+    // Dice rolled in place of others carry their term, and come after every die before them
+    let first = roll.dice.iter().find(|d| d.position_in_term == 0).unwrap().id.clone();
+    let amended = roll.amend_reroll(&first, &mut ScriptedRoller::new(vec![5])).unwrap();
+    let last = amended.dice.last().unwrap();
+    assert_eq!((last.term_index, last.position_in_term, last.value), (1, 5, 5));
+}
+
+#[test]
+fn it_can_subtotal_dice_by_tag() {
+    let flags = RollFlags::builder().n(3).die(DieType::D6).tag("fire").tag("magic").kh(2).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![3, 1, 4]));
+    let subtotals = roll.tag_subtotals();
+    assert_eq!(subtotals, TagSubtotals(vec![("fire".to_string(), 7), ("magic".to_string(), 7)]));
+    assert_eq!(subtotals.get("magic"), 7);
+    assert_eq!(subtotals.get("cold"), 0);
+    assert_eq!(subtotals.to_string(), "fire: 7, magic: 7");
+
+    // Untagged dice aren't in any subtotal
+    let roll = Roll::with_roller(d20(1), &mut ScriptedRoller::new(vec![12]));
+    assert_eq!(roll.tag_subtotals().to_string(), "");
+}
+
+#[test]
+fn it_fingerprints_every_spelling_of_a_roll_the_same() {
+    let mut spelled_out = d20(1);
+    spelled_out.equation = "1d20 + 2 + 3 [attack]".to_string();
+    spelled_out.modifiers = vec![2, 3];
+    spelled_out.tags = vec!["attack".to_string()];
+    let mut summed = d20(1);
+    summed.modifiers = vec![5];
+    assert_eq!(spelled_out.fingerprint(), summed.fingerprint());
+
+    // Fixed by FNV-1a over the canonical form, so this never changes between runs or versions
+    assert_eq!(RollFlags::new().fingerprint(), 0x4f145a181ddfcbf3);
+
+    summed.modifiers = vec![6];
+    assert!(spelled_out.fingerprint() != summed.fingerprint());
+    let mut roll = Roll::with_roller(summed.clone(), &mut ScriptedRoller::new(vec![4]));
+    assert!(roll.fingerprint.is_none());
+    roll.stamp_hashes(&summed);
+    assert_eq!(roll.fingerprint, Some(format!("{:016x}", summed.fingerprint())));
+}
+
+#[test]
+fn it_hashes_the_content_of_a_roll() {
+    use testing::freeze;
+
+    let flags = RollFlags::builder().n(3).die(DieType::D6).kh(2).build().unwrap();
+    let mut roll = Roll::with_roller(flags.clone(), &mut ScriptedRoller::new(vec![4, 2, 6]));
+    let again = Roll::with_roller(flags.clone(), &mut ScriptedRoller::new(vec![4, 2, 6]));
+    assert!(roll.id != again.id);
+    assert_eq!(roll.content_hash(), again.content_hash());
+    roll.stamp_hashes(&flags);
+    assert_eq!(roll.content_hash, Some(to_hex(&roll.content_hash())));
+
+    let hash = roll.content_hash();
+    freeze(&mut roll);
+    assert_eq!(roll.content_hash(), hash);
+
+    let mut changed = roll.clone();
+    changed.dice[0].value = 5;
+    assert!(changed.content_hash() != hash);
+    let mut changed = roll.clone();
+    changed.dice[1].is_dropped = false;
+    assert!(changed.content_hash() != hash);
+    let mut changed = roll.clone();
+    changed.value += 1;
+    assert!(changed.content_hash() != hash);
+    let mut changed = roll.clone();
+    changed.equation = "3d6kh2".to_string();
+    assert!(changed.content_hash() != hash);
+}
+
+#[test]
+fn it_amends_a_roll_by_rerolling_one_die() {
+    let flags = parse("2d20kh1+2").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![15, 8]));
+    assert_roll_total(&roll, 17);
+
+    // Rerolling the kept die low means the other die is kept instead
+    let kept = roll.dice[0].id.clone();
+    let amended = roll.amend_reroll(&kept, &mut ScriptedRoller::new(vec![3])).unwrap();
+    assert!(amended.id != roll.id);
+    assert_eq!(amended.amended_from, Some(roll.id.clone()));
+    assert_eq!(amended.dice.len(), 3);
+    assert!(amended.dice[0].is_rerolled && amended.dice[0].is_dropped);
+    assert_eq!(amended.dice[0].child, Some(amended.dice[2].id.clone()));
+    assert_eq!(amended.dice[2].value, 3);
+    assert!(amended.dice[2].is_dropped);
+    assert!(!amended.dice[1].is_dropped);
+    assert_roll_total(&amended, 10);
+
+    // The original roll is left as it was
+    assert_eq!(roll.dice.len(), 2);
+    assert!(!roll.dice[0].is_rerolled && !roll.dice[0].is_dropped);
+    assert_roll_total(&roll, 17);
+
+    // Amending again carries on the chain, and a natural 20 is a crit
+    let amended_again = amended.amend_reroll(&amended.dice[1].id, &mut ScriptedRoller::new(vec![20])).unwrap();
+    assert_eq!(amended_again.amended_from, Some(amended.id.clone()));
+    let kept: Vec<&Die> = amended_again.dice.iter().filter(|die| !die.is_dropped).collect();
+    assert_eq!(kept.len(), 1);
+    assert!(kept[0].is_critical());
+    assert_roll_total(&amended_again, 22);
+}
+
+#[test]
+fn it_counts_successes_again_when_amending() {
+    let flags = parse("5d10gte7").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![8, 3, 10, 7, 1]));
+    assert_eq!(roll.successes(), 3);
+
+    let amended = roll.amend_reroll(&roll.dice[0].id, &mut ScriptedRoller::new(vec![2])).unwrap();
+    assert_eq!(amended.successes(), 2);
+    assert_roll_total(&amended, 17);
+    assert_eq!(roll.successes(), 3);
+}
+
+#[test]
+fn it_only_amends_kept_dice() {
+    let flags = parse("2d20kh1").unwrap().pop().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![15, 8]));
+    let dropped = roll.dice[1].id.clone();
+    assert_eq!(roll.amend_reroll(&dropped, &mut ScriptedRoller::new(vec![3])).err(), Some(RollError::DroppedDie(dropped)));
+    assert_eq!(roll.amend_reroll("nope", &mut ScriptedRoller::new(vec![3])).err(),
+               Some(RollError::UnknownDie("nope".to_string())));
+}
+
+#[test]
+fn it_counts_every_value_drawn() {
+    let mut roller = Roller::seeded(7);
+    let pool = Roll::with_roller(parse("5d6").unwrap().pop().unwrap(), &mut roller);
+    assert_eq!(pool.rng_draws, 5);
+
+    // Each explosion in the chain draws another value
+    let chain = Roll::with_roller(parse("2d6!").unwrap().pop().unwrap(), &mut ScriptedRoller::new(vec![6, 3, 6, 2]));
+    assert_eq!(chain.rng_draws, 4);
+
+    let rerolled = Roll::with_roller(parse("2d6ro1").unwrap().pop().unwrap(), &mut ScriptedRoller::new(vec![1, 4, 5]));
+    assert_eq!(rerolled.rng_draws, 3);
+    let amended = rerolled.amend_reroll(&rerolled.dice[1].id, &mut ScriptedRoller::new(vec![2])).unwrap();
+    assert_eq!(amended.rng_draws, 4);
+
+    // Dice that can only land one way don't draw anything
+    let fixed = Roll::with_roller(parse("3d1").unwrap().pop().unwrap(), &mut roller);
+    assert_eq!(fixed.rng_draws, 0);
+    assert_eq!(roller.total_draws(), 5);
+
+    Roll::with_roller(parse("1d20").unwrap().pop().unwrap(), &mut roller);
+    assert_eq!(roller.total_draws(), 6);
+}
+
+#[test]
+fn it_orders_rolls_made_in_the_same_instant() {
+    let mut rolls: Vec<Roll> = (0..20).map(|_| Roll::with_roller(d20(1), &mut ScriptedRoller::new(vec![10]))).collect();
+    let timestamp = rolls[0].timestamp;
+    for roll in &mut rolls {
+        roll.timestamp = timestamp;
+    }
+
+    // Without a sequence, rolls made together fall back on their IDs
+    let mut sorted = rolls.clone();
+    sorted.sort_by(|a, b| a.cmp_by_sequence(b));
+    let ids: Vec<&str> = sorted.iter().map(|roll| roll.id.as_str()).collect();
+    let mut expected: Vec<&str> = rolls.iter().map(|roll| roll.id.as_str()).collect();
+    expected.sort();
+    assert_eq!(ids, expected);
+
+    // A sequence goes before anything else
+    for (i, roll) in rolls.iter_mut().rev().enumerate() {
+        roll.sequence = i as u64 + 1;
     }
+    let first = rolls.iter().min_by(|a, b| a.cmp_by_sequence(b)).unwrap();
+    assert_eq!((first.sequence, first.id.as_str()), (1, rolls[19].id.as_str()));
+    assert_eq!(rolls[3].cmp_by_sequence(&rolls[3].clone()), Ordering::Equal);
+    assert_eq!(rolls[3].cmp_by_sequence(&rolls[4]), Ordering::Greater);
 }
@@ -0,0 +1,930 @@
+use die::{Die, DieType, TakeKind};
+use fairness::SeedRecipe;
+use health::{self, ComponentHealth, HealthReport};
+use ladder::SuccessLadder;
+use limits::Limits;
+use pool::CupResult;
+use rand::distributions::{IndependentSample, Range};
+use rand::{self, ChaChaRng, Rng, SeedableRng, ThreadRng};
+use parser::{parse, LabeledExpression};
+use roll::{Roll, RollError, RollFlags};
+use stats::DistributionCache;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Checks between each look at the clock, reading it costs more than rolling a die
+const CLOCK_INTERVAL: u32 = 16;
+
+/// Dice rolled by `Roller::self_test`, each of them `SELF_TEST_ROLLS` times
+const SELF_TEST_DICE: [DieType; 8] = [DieType::D4, DieType::D6, DieType::D8, DieType::D10, DieType::D12, DieType::D20, DieType::D100, DieType::Fate];
+
+const SELF_TEST_ROLLS: usize = 8;
+
+/// Version of how a seed is turned into dice, kept on every seeded roll as its `replay_epoch`
+///
+/// Seeded and derived rollers key ChaCha20 with the seed's 32-bit words, low word first, and draw
+/// each face or side with rejection sampling over 32-bit values, so a seed rolls the same dice on
+/// 32-bit, 64-bit and WASM targets. `tests/golden/replay-vectors.json` pins the dice rolled for known
+/// seeds. Anything that changes them, e.g. another generator or another way of sampling, has to
+/// bump the epoch, so a replay can tell the roll was made another way.
+///
+/// - 0: rolls saved before the epoch was kept, which have none. Custom sides and Fate dice were
+///   picked with a `usize` draw, 64 bits on the 64-bit servers they were made on, and are replayed
+///   with a 64-bit draw on every target.
+/// - 1: custom sides and Fate dice are picked with a 32-bit draw, so they land the same on every
+///   target. The seed still keys ChaCha20 as its low 32-bit word then its high one, and dice with
+///   a range of faces are still drawn as before, so only rolls with custom sides or Fate dice
+///   differ from epoch 0.
+///
+/// `Roller::at_replay_epoch` turns away epochs past this one, as they were rolled some way this
+/// version doesn't know.
+pub const REPLAY_EPOCH: u32 = 1;
+
+enum Source {
+    Thread(ThreadRng),
+    Seeded(ChaChaRng),
+    Derived(SeedRecipe, ChaChaRng),
+    Scripted(VecDeque<i16>),
+    Manual(VecDeque<i16>, usize),
+}
+
+/// Tripped to stop rolls that are still going, e.g. when the client that asked for them has left
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Stop every roll made with the token, and every clone of it, at its next check
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Bounds on how long rolling can go on, and if it's timed, see `Roller::with_options`
+#[derive(Clone, Debug, Default)]
+pub struct RollOptions {
+    cancellation: Option<CancellationToken>,
+    capture_timing: bool,
+    deadline: Option<Duration>,
+    limits: Option<Limits>,
+    probability_cache: Option<Arc<DistributionCache>>,
+}
+
+impl RollOptions {
+    pub fn new() -> RollOptions {
+        RollOptions::default()
+    }
+
+    /// Give up on rolls still going after this long, counted from when the roller is made
+    pub fn deadline(mut self, deadline: Duration) -> RollOptions {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Give up on rolls once the token is cancelled
+    pub fn cancel_with(mut self, token: CancellationToken) -> RollOptions {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Time each part of every roll, kept on the roll as its `timing`
+    ///
+    /// Off by default, when the clock isn't read at all.
+    pub fn capture_timing(mut self) -> RollOptions {
+        self.capture_timing = true;
+        self
+    }
+
+    /// Work out how likely each roll's total was, kept on the roll as its `probability`
+    ///
+    /// Distributions are looked up through the cache, so an expression rolled again isn't worked
+    /// out again. Rolls without an exact distribution are warned about instead, see
+    /// `Roll::annotate_probability`.
+    pub fn annotate_probability(mut self, cache: Arc<DistributionCache>) -> RollOptions {
+        self.probability_cache = Some(cache);
+        self
+    }
+
+    /// Bound the work done on rolls besides rolling them by these limits instead of the defaults,
+    /// e.g. how many steps working out a roll's probability can take
+    pub fn limits(mut self, limits: Limits) -> RollOptions {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Fill in what these options leave out from the defaults, keeping what they set
+    pub fn or(self, defaults: &RollOptions) -> RollOptions {
+        RollOptions {
+            cancellation: self.cancellation.or_else(|| defaults.cancellation.clone()),
+            capture_timing: self.capture_timing || defaults.capture_timing,
+            deadline: self.deadline.or(defaults.deadline),
+            limits: self.limits.or_else(|| defaults.limits.clone()),
+            probability_cache: self.probability_cache.or_else(|| defaults.probability_cache.clone()),
+        }
+    }
+}
+
+/// Why a roller stopped rolling
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stop {
+    Cancelled,
+    Deadline,
+}
+
+struct Budget {
+    cancellation: Option<CancellationToken>,
+    checks: u32,
+    deadline: Option<Instant>,
+    stopped: Option<Stop>,
+}
+
+impl Budget {
+    fn check(&mut self) -> bool {
+        if self.stopped.is_some() {
+            return true;
+        }
+
+        if self.cancellation.as_ref().map_or(false, |token| token.is_cancelled()) {
+            self.stopped = Some(Stop::Cancelled);
+        } else if self.checks % CLOCK_INTERVAL == 0 && self.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            self.stopped = Some(Stop::Deadline);
+        }
+        self.checks = self.checks.wrapping_add(1);
+        self.stopped.is_some()
+    }
+}
+
+/// Source of randomness for rolling dice
+pub struct Roller {
+    budget: Option<Budget>,
+    captures_timing: bool,
+    draws: u64,
+    limits: Limits,
+    probability_cache: Option<Arc<DistributionCache>>,
+
+    /// How seeded and derived rollers turn their seeds into dice, see `at_replay_epoch`
+    replay_epoch: u32,
+
+    /// Reused by each roll for its temporaries, e.g. ranking dice to drop, so they don't allocate
+    scratch: Vec<usize>,
+
+    source: Source,
+}
+
+impl Roller {
+    /// Roll with the thread-local random number generator
+    pub fn new() -> Roller {
+        Roller {
+            budget: None,
+            captures_timing: false,
+            draws: 0,
+            limits: Limits::default(),
+            probability_cache: None,
+            replay_epoch: REPLAY_EPOCH,
+            scratch: vec![],
+            source: Source::Thread(rand::thread_rng()),
+        }
+    }
+
+    /// Roll with a deterministic generator, the same seed always produces the same dice
+    pub fn seeded(seed: u64) -> Roller {
+        let seed = [(seed & 0xffff_ffff) as u32, (seed >> 32) as u32];
+        Roller {
+            budget: None,
+            captures_timing: false,
+            draws: 0,
+            limits: Limits::default(),
+            probability_cache: None,
+            replay_epoch: REPLAY_EPOCH,
+            scratch: vec![],
+            source: Source::Seeded(ChaChaRng::from_seed(&seed[..])),
+        }
+    }
+
+    /// Roll the flags over and over with a seeded generator, handing out each total as it's rolled
+    ///
+    /// The stream never ends, so take as many totals as are needed. Rolls aren't kept once their
+    /// total has been handed out.
+    pub fn simulate_stream(flags: RollFlags, seed: u64) -> SimulationStream {
+        SimulationStream {
+            flags,
+            roller: Roller::seeded(seed),
+        }
+    }
+
+    /// Roll a number of dice of each type together, without going through notation
+    pub fn roll_cup(&mut self, spec: &[(DieType, u16)]) -> CupResult {
+        let mut dice = vec![];
+        let mut subtotals = BTreeMap::new();
+        let mut total = 0;
+        for &(die_type, n) in spec {
+            for _ in 0..n {
+                let mut die = Die::new(die_type);
+                let value = die.roll_with(self) as i32;
+                *subtotals.entry(die_type).or_insert(0) += value;
+                total += value;
+                dice.push(die);
+            }
+        }
+
+        CupResult {
+            dice,
+            subtotals,
+            total,
+        }
+    }
+
+    /// Settle a d20 check without rolling, e.g. taking 10, recording it the same as any other roll
+    ///
+    /// The roll has a single d20 set to the face taken and marked with `Die::taken`, so nothing is
+    /// drawn and its `rng_draws` is 0. Faces past those of a d20 are taken as the closest it has,
+    /// and recorded as that face. With a DC the total is graded as a success or failure, see
+    /// `SuccessLadder::pass_fail`.
+    pub fn take(kind: TakeKind, modifier: i16, dc: Option<i16>) -> Roll {
+        let mut builder = RollFlags::builder().n(1).die(DieType::D20);
+        if modifier != 0 {
+            builder = builder.modifier(modifier);
+        }
+        let mut flags = builder.build().expect("a d20 with a modifier can always be built");
+        flags.equation = flags.to_notation().unwrap_or_default();
+
+        // The face is set by a roller of its own, so no randomness is used
+        let face = kind.face().max(1).min(20);
+        let kind = match kind {
+            TakeKind::Value(_) => TakeKind::Value(face),
+            kind => kind,
+        };
+        let mut roll = Roll::with_roller(flags, &mut Roller::scripted(vec![face]));
+        roll.rng_draws = 0;
+        for die in &mut roll.dice {
+            die.taken = Some(kind);
+        }
+        if let Some(dc) = dc {
+            roll.grade(&SuccessLadder::pass_fail(), Some(dc as i32));
+        }
+        roll
+    }
+
+    /// Roll each expression of a block in order, e.g. one from `parser::parse_many`
+    ///
+    /// Each expression is rolled as one roll, so one with more than one roll in it, e.g. `2d6 +
+    /// 1d8`, fails with `RollError::TooManyTerms`. Every expression is checked before any is
+    /// rolled. A seeded roller rolls the same block the same way every time.
+    pub fn roll_many_labeled(&mut self, expressions: &[LabeledExpression]) -> Result<RollBlock, RollError> {
+        for expression in expressions {
+            match expression.rolls.len() {
+                0 => return Err(RollError::InvalidNotation),
+                1 => expression.rolls[0].validate()?,
+                terms => return Err(RollError::TooManyTerms(terms)),
+            }
+        }
+
+        let mut rolls = vec![];
+        for expression in expressions {
+            rolls.push(LabeledRoll {
+                label: expression.label.clone(),
+                line: expression.line,
+                roll: Roll::try_with_roller(expression.rolls[0].clone(), self)?,
+            });
+        }
+        Ok(RollBlock { rolls })
+    }
+
+    /// Roll with a generator reseeded from the recipe at the start of every roll
+    pub fn derived(recipe: SeedRecipe) -> Roller {
+        Roller {
+            budget: None,
+            captures_timing: false,
+            draws: 0,
+            limits: Limits::default(),
+            probability_cache: None,
+            replay_epoch: REPLAY_EPOCH,
+            scratch: vec![],
+            source: Source::Derived(recipe, ChaChaRng::new_unseeded()),
+        }
+    }
+
+    /// Roll with a generator keyed by the 32 bytes, read as eight little-endian words the same as a
+    /// derived seed
+    pub fn keyed(seed: [u8; 32]) -> Roller {
+        Roller {
+            budget: None,
+            captures_timing: false,
+            draws: 0,
+            limits: Limits::default(),
+            probability_cache: None,
+            replay_epoch: REPLAY_EPOCH,
+            scratch: vec![],
+            source: Source::Seeded(Roller::chacha(&seed)),
+        }
+    }
+
+    fn chacha(seed: &[u8; 32]) -> ChaChaRng {
+        let words: Vec<u32> = seed.chunks(4).map(|b| {
+            b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+        }).collect();
+        ChaChaRng::from_seed(&words[..])
+    }
+
+    /// The `REPLAY_EPOCH` of the rolls a seeded or derived roller makes, none for any other roller
+    pub fn replay_epoch(&self) -> Option<u32> {
+        match self.source {
+            Source::Seeded(_) | Source::Derived(..) => Some(self.replay_epoch),
+            _ => None,
+        }
+    }
+
+    /// Turn seeds into dice the way they were in an earlier epoch, to replay rolls made then
+    ///
+    /// Rolls saved without an epoch were made in epoch 0. Epochs newer than `REPLAY_EPOCH` are
+    /// from a version of the library that rolls some other way, so there's no roller for them.
+    pub fn at_replay_epoch(mut self, epoch: u32) -> Option<Roller> {
+        if epoch > REPLAY_EPOCH {
+            return None;
+        }
+        self.replay_epoch = epoch;
+        Some(self)
+    }
+
+    /// Make the next roll of a roller whose seeds are derived use the seed of the given nonce
+    ///
+    /// Other rollers carry on from where they were.
+    pub fn seek(&mut self, nonce: u64) {
+        if let Source::Derived(ref mut recipe, _) = self.source {
+            recipe.nonce = nonce;
+        }
+    }
+
+    /// Hand out the given values in order instead of rolling, useful for tests
+    pub fn scripted(values: Vec<i16>) -> Roller {
+        Roller {
+            budget: None,
+            captures_timing: false,
+            draws: 0,
+            limits: Limits::default(),
+            probability_cache: None,
+            replay_epoch: REPLAY_EPOCH,
+            scratch: vec![],
+            source: Source::Scripted(values.into_iter().collect()),
+        }
+    }
+
+    /// Use values entered by hand, e.g. from physical dice, counting any the roll needed past the end
+    pub fn manual(values: Vec<i16>) -> Roller {
+        Roller {
+            budget: None,
+            captures_timing: false,
+            draws: 0,
+            limits: Limits::default(),
+            probability_cache: None,
+            replay_epoch: REPLAY_EPOCH,
+            scratch: vec![],
+            source: Source::Manual(values.into_iter().collect(), 0),
+        }
+    }
+
+    /// Stop rolling once the options' deadline passes or their token is cancelled
+    ///
+    /// Rerolls, explosions and simulations check the roller as they go and stop early, so use the
+    /// `try_` versions of rolls to get an error instead of what was rolled before stopping.
+    pub fn with_options(mut self, options: &RollOptions) -> Roller {
+        self.budget = Some(Budget {
+            cancellation: options.cancellation.clone(),
+            checks: 0,
+            deadline: options.deadline.map(|deadline| Instant::now() + deadline),
+            stopped: None,
+        });
+        self.captures_timing = options.capture_timing;
+        self.limits = options.limits.clone().unwrap_or_default();
+        self.probability_cache = options.probability_cache.clone();
+        self
+    }
+
+    /// The roller's scratch buffer, emptied but keeping the room it's grown to
+    pub fn scratch(&mut self) -> &mut Vec<usize> {
+        self.scratch.clear();
+        &mut self.scratch
+    }
+
+    /// If rolls made with the roller time each of their parts, see `RollOptions::capture_timing`
+    pub fn captures_timing(&self) -> bool {
+        self.captures_timing
+    }
+
+    /// The limits on work done on rolls besides rolling them, see `RollOptions::limits`
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// The cache distributions are looked up through when rolls are annotated with their probability,
+    /// see `RollOptions::annotate_probability`
+    pub fn probability_cache(&self) -> Option<&DistributionCache> {
+        self.probability_cache.as_ref().map(|cache| &**cache)
+    }
+
+    /// If rolling should stop, checked by loops that could roll for a long time
+    pub fn should_stop(&mut self) -> bool {
+        match self.budget {
+            Some(ref mut budget) => budget.check(),
+            None => false,
+        }
+    }
+
+    /// Fail with the reason the roller stopped, if it has
+    pub fn stopped(&self) -> Result<(), RollError> {
+        match self.budget.as_ref().and_then(|budget| budget.stopped) {
+            Some(Stop::Cancelled) => Err(RollError::Cancelled),
+            Some(Stop::Deadline) => Err(RollError::DeadlineExceeded),
+            None => Ok(()),
+        }
+    }
+
+    /// If a manual roller has been asked for more values than it was given
+    pub fn is_exhausted(&self) -> bool {
+        self.missing() > 0
+    }
+
+    /// Number of values a manual roller was asked for past the end of its values
+    pub fn missing(&self) -> usize {
+        match self.source {
+            Source::Manual(_, missing) => missing,
+            _ => 0,
+        }
+    }
+
+    /// Number of values a manual or scripted roller hasn't handed out yet
+    pub fn unused(&self) -> usize {
+        match self.source {
+            Source::Scripted(ref values) => values.len(),
+            Source::Manual(ref values, _) => values.len(),
+            _ => 0,
+        }
+    }
+
+    /// Number of values the roller has drawn, from its generator or its script, since it was made
+    ///
+    /// Dice that can only land one way and values entered by hand don't draw anything.
+    pub fn total_draws(&self) -> u64 {
+        self.draws
+    }
+
+    /// Roll a handful of every type of die, checking the values are in range and the dice's
+    /// timestamps don't go backwards
+    ///
+    /// Uses up values like any other roll, so run it on a roller made for the purpose. The d100s
+    /// all landing on the same face is taken as the source being stuck.
+    pub fn self_test(&mut self) -> HealthReport {
+        let mut dice: Vec<Die> = vec![];
+        for die_type in SELF_TEST_DICE.iter() {
+            for _ in 0..SELF_TEST_ROLLS {
+                let mut die = Die::new(*die_type);
+                die.roll_with(self);
+                dice.push(die);
+            }
+        }
+
+        let out_of_range = dice.iter().find(|die| die.value < die.min || die.value > die.max);
+        let d100s: Vec<i16> = dice.iter().filter(|die| die.die == DieType::D100).map(|die| die.value).collect();
+        let rng = match out_of_range {
+            Some(die) => ComponentHealth::degraded(health::RNG, &format!("a {} landed on {}", die.die.notation(), die.value)),
+            None if d100s.iter().all(|value| *value == d100s[0]) => {
+                ComponentHealth::degraded(health::RNG, &format!("every d100 landed on {}", d100s[0]))
+            },
+            None => ComponentHealth::ok(health::RNG),
+        };
+
+        let clock = match dice.windows(2).find(|pair| pair[1].timestamp < pair[0].timestamp) {
+            Some(pair) => ComponentHealth::degraded(health::CLOCK, &format!("went back from {} to {}", pair[0].timestamp, pair[1].timestamp)),
+            None => ComponentHealth::ok(health::CLOCK),
+        };
+        HealthReport::new(vec![rng, clock])
+    }
+
+    /// Prepare to make a new roll, returning the nonce used when seeds are derived
+    pub fn begin_roll(&mut self) -> Option<u64> {
+        match self.source {
+            Source::Derived(ref mut recipe, ref mut rng) => {
+                *rng = Roller::chacha(&recipe.derive());
+
+                let nonce = recipe.nonce;
+                recipe.nonce += 1;
+                Some(nonce)
+            },
+            _ => None,
+        }
+    }
+
+    /// Generate a number between min and max, inclusive
+    pub fn between(&mut self, min: i16, max: i16) -> i16 {
+        // Values entered by hand line up with the dice, even dice that can only land one way
+        if let Source::Manual(ref mut values, ref mut missing) = self.source {
+            return Roller::next_manual(values, missing, min);
+        }
+
+        // There's only one possible value, don't use up any randomness on it
+        if min == max {
+            return min;
+        }
+
+        self.draws += 1;
+        let between = Range::new(min as i32, max as i32 + 1);
+        let roll = match self.source {
+            Source::Thread(ref mut rng) => between.ind_sample(rng),
+            Source::Seeded(ref mut rng) => between.ind_sample(rng),
+            Source::Derived(_, ref mut rng) => between.ind_sample(rng),
+            Source::Scripted(ref mut values) => return Roller::next_scripted(values),
+            Source::Manual(..) => unreachable!(),
+        };
+        roll as i16
+    }
+
+    /// Pick one of the given sides
+    pub fn side(&mut self, sides: &[i16]) -> i16 {
+        if let Source::Manual(ref mut values, ref mut missing) = self.source {
+            return Roller::next_manual(values, missing, sides[0]);
+        }
+
+        if sides.len() == 1 {
+            return sides[0];
+        }
+
+        self.draws += 1;
+        let epoch = self.replay_epoch;
+        let idx = match self.source {
+            Source::Thread(ref mut rng) => Roller::index(sides.len(), epoch, rng),
+            Source::Seeded(ref mut rng) => Roller::index(sides.len(), epoch, rng),
+            Source::Derived(_, ref mut rng) => Roller::index(sides.len(), epoch, rng),
+            Source::Scripted(ref mut values) => return Roller::next_scripted(values),
+            Source::Manual(..) => unreachable!(),
+        };
+        sides[idx]
+    }
+
+    /// Draw an index below `len` the way the epoch did
+    ///
+    /// Since epoch 1 it's drawn as a u32 rather than a usize, which is 32 bits on some targets and
+    /// 64 on others. Epoch 0 drew a usize on the 64-bit servers rolls were made on, so it's replayed
+    /// as a u64 whatever the target.
+    fn index<R: Rng>(len: usize, epoch: u32, rng: &mut R) -> usize {
+        if epoch == 0 {
+            Range::new(0u64, len as u64).ind_sample(rng) as usize
+        } else {
+            Range::new(0u32, len.min(u32::max_value() as usize) as u32).ind_sample(rng) as usize
+        }
+    }
+
+    fn next_scripted(values: &mut VecDeque<i16>) -> i16 {
+        values.pop_front().expect("scripted roller ran out of values")
+    }
+
+    fn next_manual(values: &mut VecDeque<i16>, missing: &mut usize, fallback: i16) -> i16 {
+        match values.pop_front() {
+            Some(value) => value,
+            None => {
+                *missing += 1;
+                fallback
+            },
+        }
+    }
+}
+
+/// The whole roll engine, so code that rolls dice can be handed a mock in its tests
+///
+/// `Roller` is tied to the thread it was made on, so the engine is shared between threads as a
+/// `DiceRoller` instead, e.g. as a `Box<dyn DiceRoller>` in the server's state.
+pub trait DiceRoller: Send + Sync {
+    /// Roll the flags once they've been validated
+    fn roll(&self, flags: &RollFlags) -> Result<Roll, RollError>;
+
+    /// Roll the flags, giving up past the options' deadline or once they're cancelled
+    ///
+    /// Engines that can't be stopped part way through roll the same as `roll`.
+    fn roll_with_options(&self, flags: &RollFlags, _options: &RollOptions) -> Result<Roll, RollError> {
+        self.roll(flags)
+    }
+
+    /// Parse and roll notation, rolling only the last roll in it
+    fn roll_notation(&self, notation: &str) -> Result<Roll, RollError> {
+        match parse(notation).and_then(|mut rolls| rolls.pop()) {
+            Some(flags) => self.roll(&flags),
+            None => Err(RollError::InvalidNotation),
+        }
+    }
+}
+
+/// Rolls with a new thread-local `Roller` each time, the way the API always has
+pub struct ThreadRoller;
+
+impl DiceRoller for ThreadRoller {
+    fn roll(&self, flags: &RollFlags) -> Result<Roll, RollError> {
+        flags.validate()?;
+        Ok(Roll::with_roller(flags.clone(), &mut Roller::new()))
+    }
+
+    fn roll_with_options(&self, flags: &RollFlags, options: &RollOptions) -> Result<Roll, RollError> {
+        flags.validate()?;
+        Roll::try_with_roller(flags.clone(), &mut Roller::new().with_options(options))
+    }
+}
+
+/// How the free `roll_api::roll` rolls, swapped for another with `roll_api::set_default_roller`
+///
+/// By default rolls use the thread-local generator and are checked against the default limits. A
+/// seeded default doesn't share one generator between threads: each roll is seeded from the seed
+/// and its index among the rolls made with the default, kept as the roll's `nonce`. Rolling `n`
+/// times from any number of threads rolls the same `n` rolls as rolling them one after another,
+/// only which thread gets which index changes.
+#[derive(Debug, Default)]
+pub struct DefaultRoller {
+    limits: Limits,
+    next_nonce: AtomicU64,
+    options: RollOptions,
+    seed: Option<u64>,
+}
+
+impl DefaultRoller {
+    pub fn new() -> DefaultRoller {
+        DefaultRoller::default()
+    }
+
+    /// Seed every roll, the same seed always rolls the same dice in the same order
+    pub fn seeded(mut self, seed: u64) -> DefaultRoller {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Check notation against these limits instead of the defaults
+    pub fn limits(mut self, limits: Limits) -> DefaultRoller {
+        self.limits = limits;
+        self
+    }
+
+    /// Bound every roll, e.g. to give up on rolls past a deadline
+    pub fn options(mut self, options: RollOptions) -> DefaultRoller {
+        self.options = options;
+        self
+    }
+
+    /// Parse and roll notation, rolling only the last roll in it
+    pub fn roll(&self, notation: &str) -> Result<Roll, RollError> {
+        let flags = ::checked_flags(notation, &self.limits)?;
+        let roller = match self.seed {
+            Some(seed) => {
+                let mut server = [0u8; 32];
+                for (i, byte) in server.iter_mut().take(8).enumerate() {
+                    *byte = (seed >> (8 * i)) as u8;
+                }
+                Roller::derived(SeedRecipe {
+                    client: String::new(),
+                    nonce: self.next_nonce.fetch_add(1, Ordering::SeqCst),
+                    server,
+                })
+            },
+            None => Roller::new(),
+        };
+        Roll::try_with_roller(flags, &mut roller.with_options(&self.options))
+    }
+}
+
+impl<T: DiceRoller + ?Sized> DiceRoller for Arc<T> {
+    fn roll(&self, flags: &RollFlags) -> Result<Roll, RollError> {
+        (**self).roll(flags)
+    }
+
+    fn roll_with_options(&self, flags: &RollFlags, options: &RollOptions) -> Result<Roll, RollError> {
+        (**self).roll_with_options(flags, options)
+    }
+}
+
+/// A roll of a block of notation, with the label it was written with
+#[derive(Clone, Debug, Serialize)]
+pub struct LabeledRoll {
+    pub label: Option<String>,
+
+    /// Line of the block the roll was written on, counting from 1
+    pub line: usize,
+
+    pub roll: Roll,
+}
+
+/// Every roll of a block of notation in the order they were written, see `Roller::roll_many_labeled`
+#[derive(Clone, Debug, Serialize)]
+pub struct RollBlock {
+    pub rolls: Vec<LabeledRoll>,
+}
+
+/// Totals of a roll made over and over, see `Roller::simulate_stream`
+pub struct SimulationStream {
+    flags: RollFlags,
+    roller: Roller,
+}
+
+impl Iterator for SimulationStream {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        Some(Roll::with_roller(self.flags.clone(), &mut self.roller).value as i64)
+    }
+}
+
+#[test]
+fn it_can_roll_between_inclusive_bounds() {
+    let mut roller = Roller::new();
+    let mut seen = vec![false; 4];
+    for _ in 0..1000 {
+        let roll = roller.between(1, 4);
+        assert!(roll >= 1 && roll <= 4);
+        seen[(roll - 1) as usize] = true;
+    }
+    assert!(seen.iter().all(|s| *s));
+}
+
+#[test]
+fn it_can_script_rolls() {
+    let mut roller = Roller::scripted(vec![3, 20, -1]);
+    assert_eq!(roller.between(1, 20), 3);
+    assert_eq!(roller.between(1, 20), 20);
+    assert_eq!(roller.side(&[-1, 0, 1]), -1);
+}
+
+#[test]
+fn it_counts_manual_values() {
+    let mut roller = Roller::manual(vec![4, 2]);
+    assert_eq!(roller.between(1, 6), 4);
+    assert_eq!(roller.unused(), 1);
+    assert!(!roller.is_exhausted());
+
+    assert_eq!(roller.between(1, 6), 2);
+    assert_eq!(roller.between(1, 6), 1);
+    assert_eq!(roller.side(&[3, 5]), 3);
+    assert_eq!(roller.between(1, 1), 1);
+    assert_eq!(roller.missing(), 3);
+    assert!(roller.is_exhausted());
+}
+
+#[test]
+fn it_consumes_no_randomness_for_a_single_value() {
+    let mut a = Roller::seeded(9);
+    let mut b = Roller::seeded(9);
+    assert_eq!(a.between(1, 1), 1);
+    assert_eq!(a.side(&[5]), 5);
+    assert_eq!(a.between(1, 20), b.between(1, 20));
+
+    let mut scripted = Roller::scripted(vec![17]);
+    assert_eq!(scripted.between(1, 1), 1);
+    assert_eq!(scripted.between(1, 20), 17);
+}
+
+#[test]
+fn it_can_repeat_seeded_rolls() {
+    let mut a = Roller::seeded(42);
+    let mut b = Roller::seeded(42);
+    let a_rolls: Vec<i16> = (0..20).map(|_| a.between(1, 20)).collect();
+    let b_rolls: Vec<i16> = (0..20).map(|_| b.between(1, 20)).collect();
+    assert_eq!(a_rolls, b_rolls);
+
+    let mut c = Roller::seeded(43);
+    let c_rolls: Vec<i16> = (0..20).map(|_| c.between(1, 20)).collect();
+    assert_ne!(a_rolls, c_rolls);
+}
+
+#[test]
+fn it_streams_the_same_totals_as_rolling() {
+    use die::DieType;
+
+    let flags = RollFlags::builder().n(3).die(DieType::D6).modifier(2).build().unwrap();
+    let streamed: Vec<i64> = Roller::simulate_stream(flags.clone(), 11).take(20).collect();
+
+    let mut roller = Roller::seeded(11);
+    let rolled: Vec<i64> = (0..20).map(|_| Roll::with_roller(flags.clone(), &mut roller).value as i64).collect();
+    assert_eq!(streamed, rolled);
+}
+
+#[test]
+fn it_rolls_through_the_engine_trait() {
+    let engine: Box<dyn DiceRoller> = Box::new(ThreadRoller);
+    let roll = engine.roll_notation("3d6+2").unwrap();
+    assert_eq!(roll.dice.len(), 3);
+    assert!(roll.value >= 5 && roll.value <= 20);
+
+    assert_eq!(engine.roll_notation("4dQ").err(), Some(RollError::InvalidNotation));
+    assert_eq!(engine.roll_notation("3d0").err(), Some(RollError::NoSides));
+}
+
+#[test]
+fn it_rolls_a_block_in_order_under_one_seed() {
+    use parser::parse_many;
+    let expressions = parse_many("Attack: 1d20+7\nDamage: 2d6+4\n3d6").unwrap();
+    let block = Roller::scripted(vec![15, 3, 5, 1, 2, 3]).roll_many_labeled(&expressions).unwrap();
+    let rolled: Vec<(Option<&str>, usize, i32)> = block.rolls.iter()
+        .map(|rolled| (rolled.label.as_ref().map(|label| label.as_str()), rolled.line, rolled.roll.value))
+        .collect();
+    assert_eq!(rolled, vec![(Some("Attack"), 1, 22), (Some("Damage"), 2, 12), (None, 3, 6)]);
+
+    let values = |block: RollBlock| block.rolls.iter().map(|rolled| rolled.roll.value).collect::<Vec<i32>>();
+    assert_eq!(values(Roller::seeded(9).roll_many_labeled(&expressions).unwrap()),
+               values(Roller::seeded(9).roll_many_labeled(&expressions).unwrap()));
+
+    let expressions = parse_many("1d20; 3d0").unwrap();
+    assert_eq!(Roller::new().roll_many_labeled(&expressions).err(), Some(RollError::NoSides));
+}
+
+#[test]
+fn it_refuses_to_roll_part_of_an_expression() {
+    use parser::parse_many;
+    let expressions = parse_many("Attack: 1d20+7\nDamage: 2d6[fire] + 1d6[cold]").unwrap();
+    let mut roller = Roller::scripted(vec![]);
+    assert_eq!(roller.roll_many_labeled(&expressions).err(), Some(RollError::TooManyTerms(2)));
+
+    // Nothing is rolled, not even the expressions before it
+    assert_eq!(roller.total_draws(), 0);
+}
+
+#[test]
+fn it_tests_itself() {
+    let report = Roller::seeded(5).self_test();
+    assert!(report.is_ok());
+    assert_eq!(report.get(health::RNG), Some(&ComponentHealth::ok(health::RNG)));
+
+    // A d4 landing on a 7 is out of range
+    let mut values = vec![7];
+    values.extend(vec![1; 47]);
+    values.extend((1..9).collect::<Vec<i16>>());
+    values.extend(vec![0; 8]);
+    let report = Roller::scripted(values).self_test();
+    assert!(!report.is_ok());
+    assert_eq!(report.get(health::RNG), Some(&ComponentHealth::degraded(health::RNG, "a d4 landed on 7")));
+    assert_eq!(report.get(health::CLOCK).map(|health| health.status), Some(health::HealthStatus::Ok));
+
+    // Values run out and every die lands on its lowest face
+    let report = Roller::manual(vec![]).self_test();
+    assert_eq!(report.get(health::RNG), Some(&ComponentHealth::degraded(health::RNG, "every d100 landed on 1")));
+}
+
+#[cfg(test)]
+fn endless_reroll() -> RollFlags {
+    use ttml::arg::ComparisonArg;
+
+    RollFlags::builder().n(10).die(DieType::D20).rr(ComparisonArg::EqualTo, 1).build().unwrap()
+}
+
+#[test]
+fn it_gives_up_on_rolls_past_their_deadline() {
+    // Every die lands on a 1 and is rerolled, enough values for the reroll to run all of its rounds
+    let options = RollOptions::new().deadline(Duration::from_millis(0));
+    let mut roller = Roller::scripted(vec![1; 1010]).with_options(&options);
+    assert_eq!(Roll::try_with_roller(endless_reroll(), &mut roller).err(), Some(RollError::DeadlineExceeded));
+
+    // Only the dice and the first round of rerolls were rolled before giving up
+    assert_eq!(roller.unused(), 990);
+    assert_eq!(Roll::try_with_roller(endless_reroll(), &mut roller).err(), Some(RollError::DeadlineExceeded));
+    assert_eq!(roller.unused(), 990);
+}
+
+#[test]
+fn it_gives_up_on_rolls_once_cancelled() {
+    let token = CancellationToken::new();
+    let mut roller = Roller::scripted(vec![1; 1010]).with_options(&RollOptions::new().cancel_with(token.clone()));
+    token.cancel();
+    assert_eq!(Roll::try_with_roller(endless_reroll(), &mut roller).err(), Some(RollError::Cancelled));
+    assert_eq!(roller.unused(), 990);
+
+    // Rollers without options never stop
+    let mut roller = Roller::scripted(vec![1; 1010]);
+    assert!(Roll::try_with_roller(endless_reroll(), &mut roller).is_ok());
+    assert_eq!(roller.unused(), 0);
+}
+
+#[test]
+fn it_takes_a_check_without_drawing() {
+    let taken = Roller::take(TakeKind::Ten, 5, Some(15));
+    assert_eq!(taken.rng_draws, 0);
+    assert_eq!((taken.equation.as_str(), taken.value), ("1d20+5", 15));
+    assert_eq!(taken.dice[0].taken, Some(TakeKind::Ten));
+    assert_eq!(taken.outcome.as_ref().map(|outcome| (outcome.label.as_str(), outcome.margin)), Some(("success", 0)));
+}
+
+#[test]
+fn it_grades_a_taken_check_the_same_as_a_rolled_one() {
+    let missed = Roller::take(TakeKind::Value(12), -1, Some(12));
+    assert_eq!(missed.value, 11);
+    assert_eq!(missed.outcome.map(|outcome| outcome.label), Some("failure".to_string()));
+    assert!(Roller::take(TakeKind::Twenty, 0, None).outcome.is_none());
+}
+
+#[test]
+fn it_records_the_face_a_d20_has_in_place_of_one_it_doesnt() {
+    let taken = Roller::take(TakeKind::Value(25), 0, None);
+    assert_eq!((taken.value, taken.dice[0].value), (20, 20));
+    assert_eq!(taken.dice[0].taken, Some(TakeKind::Value(20)));
+    assert_eq!(Roller::take(TakeKind::Value(-3), 0, None).dice[0].taken, Some(TakeKind::Value(1)));
+}
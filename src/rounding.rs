@@ -0,0 +1,98 @@
+/// How to round a value that falls between two whole numbers
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Rounding {
+    /// Round down, towards negative infinity
+    Floor,
+
+    /// Round up, towards positive infinity
+    Ceil,
+
+    /// Round to the nearest whole number, halves towards positive infinity
+    HalfUp,
+
+    /// Round towards zero
+    Truncate,
+}
+
+impl Rounding {
+    /// Divide two whole numbers, rounding the exact quotient
+    pub fn divide(&self, numerator: i32, denominator: i32) -> i32 {
+        let (n, d) = (numerator as i64, denominator as i64);
+        let quotient = match self {
+            &Rounding::Floor => floor_div(n, d),
+            &Rounding::Ceil => -floor_div(-n, d),
+            &Rounding::HalfUp => floor_div(2 * n + d, 2 * d),
+            &Rounding::Truncate => n / d,
+        };
+        quotient as i32
+    }
+
+    /// Round a fractional value, e.g. the average of a die
+    pub fn round(&self, value: f64) -> i32 {
+        let rounded = match self {
+            &Rounding::Floor => value.floor(),
+            &Rounding::Ceil => value.ceil(),
+            &Rounding::HalfUp => (value + 0.5).floor(),
+            &Rounding::Truncate => value.trunc(),
+        };
+        rounded as i32
+    }
+
+    /// Short name used when writing a roll out
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Rounding::Floor => "floor",
+            &Rounding::Ceil => "ceil",
+            &Rounding::HalfUp => "halfup",
+            &Rounding::Truncate => "trunc",
+        }
+    }
+}
+
+/// Integer division rounded towards negative infinity, whatever the signs
+fn floor_div(n: i64, d: i64) -> i64 {
+    let quotient = n / d;
+    if n % d != 0 && (n < 0) != (d < 0) {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+#[test]
+fn it_can_divide_negative_numerators() {
+    // -3.5
+    assert_eq!(Rounding::Floor.divide(-7, 2), -4);
+    assert_eq!(Rounding::Ceil.divide(-7, 2), -3);
+    assert_eq!(Rounding::HalfUp.divide(-7, 2), -3);
+    assert_eq!(Rounding::Truncate.divide(-7, 2), -3);
+
+    // -2.666...
+    assert_eq!(Rounding::Floor.divide(-8, 3), -3);
+    assert_eq!(Rounding::Ceil.divide(-8, 3), -2);
+    assert_eq!(Rounding::HalfUp.divide(-8, 3), -3);
+    assert_eq!(Rounding::Truncate.divide(-8, 3), -2);
+
+    // Negative denominators round the same way
+    assert_eq!(Rounding::Floor.divide(7, -2), -4);
+    assert_eq!(Rounding::HalfUp.divide(7, -2), -3);
+}
+
+#[test]
+fn it_can_divide_positive_numerators() {
+    assert_eq!(Rounding::Floor.divide(7, 2), 3);
+    assert_eq!(Rounding::Ceil.divide(7, 2), 4);
+    assert_eq!(Rounding::HalfUp.divide(7, 2), 4);
+    assert_eq!(Rounding::Truncate.divide(7, 2), 3);
+    assert_eq!(Rounding::Ceil.divide(6, 2), 3);
+}
+
+#[test]
+fn it_can_round_fractions() {
+    assert_eq!(Rounding::Floor.round(-3.5), -4);
+    assert_eq!(Rounding::Ceil.round(-3.5), -3);
+    assert_eq!(Rounding::HalfUp.round(-3.5), -3);
+    assert_eq!(Rounding::Truncate.round(-3.5), -3);
+    assert_eq!(Rounding::HalfUp.round(3.5), 4);
+    assert_eq!(Rounding::HalfUp.round(-3.6), -4);
+}
@@ -0,0 +1,464 @@
+use die::DieType;
+use history::LoggedRoll;
+use keywords::KeywordPack;
+use parser::ParseOptions;
+use presets::PresetStore;
+use roll::{Roll, RollError, RollFlags};
+use roller::Roller;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+#[cfg(test)]
+use keywords::KeywordError;
+#[cfg(test)]
+use limits::Limits;
+#[cfg(test)]
+use parser::{parse, parse_notation_with};
+#[cfg(test)]
+use presets::{PartialOptions, PresetError};
+#[cfg(test)]
+use request::RollRequest;
+#[cfg(test)]
+use serde_json;
+
+/// How long a blessing lasts
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum BlessingDuration {
+    /// For this many rolls with a die of its type, counting down with each
+    RollCount(u32),
+
+    /// Until it's cleared, e.g. at the end of a scene
+    UntilCleared,
+}
+
+/// A delta added to every die of a type a participant rolls, a curse when it's negative
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Blessing {
+    pub delta: i16,
+    pub die: DieType,
+    pub duration: BlessingDuration,
+}
+
+/// A point spent to reroll a die, e.g. a luck point
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PointSpend {
+    /// The roll made by the reroll
+    pub amended_id: String,
+
+    /// The die rerolled
+    pub die_id: String,
+
+    /// Kind of point spent, e.g. `luck` or `hero`
+    pub kind: String,
+
+    /// The roll the die was rerolled from
+    pub roll_id: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SpendError {
+    /// The participant has none of the kind of point left
+    NotEnoughPoints(String),
+
+    /// The roll was made by someone else, the participant who made it
+    NotTheirRoll(String),
+
+    /// The die couldn't be rerolled, e.g. it was dropped
+    Roll(RollError),
+}
+
+impl From<RollError> for SpendError {
+    fn from(err: RollError) -> SpendError {
+        SpendError::Roll(err)
+    }
+}
+
+/// What a participant has for a scene that carries over from one roll to the next, e.g. a blessed d20
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Session {
+    blessings: Vec<Blessing>,
+
+    /// Modifiers the participant writes in their language, see `parse_options`
+    #[serde(default, deserialize_with = "checked_keywords")]
+    keywords: Option<Arc<KeywordPack>>,
+
+    pub participant: String,
+
+    /// Points left of each kind, e.g. `luck`
+    #[serde(default)]
+    points: BTreeMap<String, u32>,
+
+    /// Rolls the participant has saved to slots, e.g. slot 1 for their usual attack
+    #[serde(default)]
+    presets: PresetStore,
+
+    /// Every point spent, oldest first
+    #[serde(default)]
+    spends: Vec<PointSpend>,
+}
+
+impl Session {
+    pub fn new(participant: &str) -> Session {
+        Session {
+            blessings: vec![],
+            keywords: None,
+            participant: participant.to_string(),
+            points: BTreeMap::new(),
+            presets: PresetStore::new(),
+            spends: vec![],
+        }
+    }
+
+    /// Let the participant fill this many preset slots, e.g. `max_preset_slots` from the config
+    ///
+    /// The cap isn't saved with the session, so set it again when a session is read back.
+    pub fn with_max_preset_slots(mut self, max_slots: usize) -> Session {
+        self.presets = self.presets.with_max_slots(max_slots);
+        self
+    }
+
+    /// The participant's presets, see `presets::PresetStore`
+    pub fn presets(&self) -> &PresetStore {
+        &self.presets
+    }
+
+    pub fn presets_mut(&mut self) -> &mut PresetStore {
+        &mut self.presets
+    }
+
+    /// Add the delta to every die of the type the participant rolls, e.g. `bless(DieType::D20, 1,
+    /// BlessingDuration::RollCount(3))` for a blessed d20 over the next three d20 rolls
+    ///
+    /// A negative delta curses the die. Blessings on the same type of die add up.
+    pub fn bless(&mut self, die: DieType, delta: i16, duration: BlessingDuration) {
+        self.blessings.push(Blessing {
+            delta,
+            die,
+            duration,
+        });
+    }
+
+    /// Blessings and curses still in effect, in the order they were given
+    pub fn blessings(&self) -> &Vec<Blessing> {
+        &self.blessings
+    }
+
+    /// Lift every blessing and curse on the type of die
+    pub fn clear(&mut self, die: DieType) {
+        self.blessings.retain(|blessing| blessing.die != die);
+    }
+
+    /// Read the participant's notation with the pack's aliases from now on, e.g. French players' `4d6gm3`
+    pub fn use_keywords(&mut self, pack: Arc<KeywordPack>) {
+        self.keywords = Some(pack);
+    }
+
+    /// How to read the participant's notation, with their keywords if they have any
+    pub fn parse_options(&self) -> ParseOptions {
+        match self.keywords {
+            Some(ref pack) => ParseOptions::new().keywords(pack.clone()),
+            None => ParseOptions::new(),
+        }
+    }
+
+    /// Give the participant points of a kind to spend, e.g. `grant_points("luck", 3)` at the start of
+    /// a session
+    pub fn grant_points(&mut self, kind: &str, amount: u32) {
+        let points = self.points.entry(kind.to_string()).or_insert(0);
+        *points = points.saturating_add(amount);
+    }
+
+    /// Points of the kind the participant has left
+    pub fn points(&self, kind: &str) -> u32 {
+        self.points.get(kind).cloned().unwrap_or(0)
+    }
+
+    /// Points left of every kind the participant has been given
+    pub fn balances(&self) -> &BTreeMap<String, u32> {
+        &self.points
+    }
+
+    /// Every point the participant spent, oldest first
+    pub fn spends(&self) -> &Vec<PointSpend> {
+        &self.spends
+    }
+
+    /// Spend a point of the kind to reroll a die of one of the participant's rolls
+    ///
+    /// The roll is taken as the log has it, so it can be checked it's the participant's own. The
+    /// die is rerolled with `Roll::amend_reroll`, and the point is only taken once it has been, so
+    /// a participant without a point left, someone else's roll or a die that can't be rerolled
+    /// costs nothing and rolls nothing. The spend is recorded on the amended roll as `points_spent`
+    /// and in `spends`.
+    pub fn spend_for_reroll(&mut self, kind: &str, logged: &LoggedRoll, die_id: &str, roller: &mut Roller)
+                            -> Result<Roll, SpendError> {
+        if logged.participant != self.participant {
+            return Err(SpendError::NotTheirRoll(logged.participant.clone()));
+        }
+        let roll = &logged.roll;
+        if self.points(kind) == 0 {
+            return Err(SpendError::NotEnoughPoints(kind.to_string()));
+        }
+        let mut amended = roll.amend_reroll(die_id, roller)?;
+
+        let spend = PointSpend {
+            amended_id: amended.id.clone(),
+            die_id: die_id.to_string(),
+            kind: kind.to_string(),
+            roll_id: roll.id.clone(),
+        };
+        if let Some(points) = self.points.get_mut(kind) {
+            *points -= 1;
+        }
+        amended.points_spent = Some(spend.clone());
+        self.spends.push(spend);
+        Ok(amended)
+    }
+
+    /// Roll the flags for the participant, moving the dice that are blessed or cursed
+    ///
+    /// The dice are moved before they're kept, dropped, counted as successes and totalled, but
+    /// crits and fumbles are still read from the face they landed on, see `Roll::adjust`. Each
+    /// blessing on a type of die the roll rolled counts down by one, and is lifted when it runs out.
+    pub fn roll(&mut self, flags: RollFlags, roller: &mut Roller) -> Roll {
+        let mut roll = Roll::with_roller(flags.clone(), roller);
+
+        let mut blessed: Vec<DieType> = vec![];
+        for blessing in &self.blessings {
+            if !blessed.contains(&blessing.die) {
+                blessed.push(blessing.die);
+            }
+        }
+        for die in blessed {
+            if !roll.dice.iter().any(|rolled| rolled.die == die) {
+                continue;
+            }
+            let delta = self.blessings.iter()
+                .filter(|blessing| blessing.die == die)
+                .fold(0i16, |sum, blessing| sum.saturating_add(blessing.delta));
+            if delta != 0 {
+                roll.adjust(die, delta, &flags, roller);
+            }
+            for blessing in self.blessings.iter_mut().filter(|blessing| blessing.die == die) {
+                if let BlessingDuration::RollCount(ref mut rolls) = blessing.duration {
+                    *rolls = rolls.saturating_sub(1);
+                }
+            }
+        }
+        self.blessings.retain(|blessing| blessing.duration != BlessingDuration::RollCount(0));
+        roll
+    }
+}
+
+/// A keyword pack read from JSON, checked the same as one loaded with `KeywordPack::from_json`
+fn checked_keywords<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Arc<KeywordPack>>, D::Error> {
+    match Option::<KeywordPack>::deserialize(deserializer)? {
+        Some(pack) => {
+            pack.check().map_err(D::Error::custom)?;
+            Ok(Some(Arc::new(pack)))
+        },
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+fn flags(notation: &str) -> RollFlags {
+    parse(notation).unwrap().pop().unwrap()
+}
+
+#[test]
+fn it_lifts_a_blessing_after_its_rolls() {
+    let mut session = Session::new("alice");
+    session.bless(DieType::D20, 1, BlessingDuration::RollCount(2));
+
+    let roll = session.roll(flags("1d20+2"), &mut Roller::scripted(vec![10]));
+    assert_eq!((roll.dice[0].natural, roll.dice[0].value, roll.value), (Some(10), 11, 13));
+
+    // Rolls without a d20 don't count down
+    let roll = session.roll(flags("2d6"), &mut Roller::scripted(vec![3, 4]));
+    assert_eq!((roll.dice[0].natural, roll.value), (None, 7));
+    assert_eq!(session.blessings()[0].duration, BlessingDuration::RollCount(1));
+
+    assert_eq!(session.roll(flags("1d20"), &mut Roller::scripted(vec![5])).value, 6);
+    assert!(session.blessings().is_empty());
+    let roll = session.roll(flags("1d20"), &mut Roller::scripted(vec![5]));
+    assert_eq!((roll.dice[0].natural, roll.value), (None, 5));
+
+    // Blessings until cleared stay until they are
+    session.bless(DieType::D6, -1, BlessingDuration::UntilCleared);
+    for _ in 0..3 {
+        assert_eq!(session.roll(flags("1d6"), &mut Roller::scripted(vec![4])).value, 3);
+    }
+    session.clear(DieType::D6);
+    assert_eq!(session.roll(flags("1d6"), &mut Roller::scripted(vec![4])).value, 4);
+}
+
+#[test]
+fn it_keeps_moved_dice_on_their_faces() {
+    let mut session = Session::new("alice");
+    session.bless(DieType::D20, 1, BlessingDuration::UntilCleared);
+    let roll = session.roll(flags("1d20"), &mut Roller::scripted(vec![20]));
+    assert_eq!((roll.dice[0].natural, roll.dice[0].value), (Some(20), 20));
+
+    let mut session = Session::new("bob");
+    session.bless(DieType::D6, -2, BlessingDuration::UntilCleared);
+    let roll = session.roll(flags("2d6"), &mut Roller::scripted(vec![2, 6]));
+    let values: Vec<i16> = roll.dice.iter().map(|die| die.value).collect();
+    assert_eq!(values, vec![1, 4]);
+    assert_eq!(roll.value, 5);
+
+    // Dice are kept by their moved values
+    let mut session = Session::new("carol");
+    session.bless(DieType::D6, 1, BlessingDuration::UntilCleared);
+    let roll = session.roll(flags("2d6kh1"), &mut Roller::scripted(vec![5, 3]));
+    assert_eq!(roll.value, 6);
+    let dropped: Vec<i16> = roll.dice.iter().filter(|die| die.is_dropped).map(|die| die.value).collect();
+    assert_eq!(dropped, vec![4]);
+}
+
+#[test]
+fn it_moves_compounded_dice_past_their_highest_face() {
+    let mut session = Session::new("alice");
+    session.bless(DieType::D6, 1, BlessingDuration::UntilCleared);
+    let roll = session.roll(flags("1d6!!"), &mut Roller::scripted(vec![6, 6, 2]));
+    assert_eq!((roll.dice[0].natural, roll.dice[0].value, roll.value), (Some(14), 15, 15));
+
+    // Only the lowest face still holds them
+    let mut session = Session::new("bob");
+    session.bless(DieType::D6, -20, BlessingDuration::UntilCleared);
+    let roll = session.roll(flags("1d6!!"), &mut Roller::scripted(vec![6, 3]));
+    assert_eq!((roll.dice[0].natural, roll.dice[0].value), (Some(9), 1));
+}
+
+#[test]
+fn it_reads_crits_from_the_natural_face() {
+    let mut session = Session::new("alice");
+    session.bless(DieType::D20, 1, BlessingDuration::UntilCleared);
+
+    // A blessed 19 counts as 20, but isn't a crit
+    let roll = session.roll(flags("1d20"), &mut Roller::scripted(vec![19]));
+    assert_eq!(roll.value, 20);
+    assert!(!roll.dice[0].is_critical());
+    assert!(!roll.is_critical(&roll.dice[0]));
+
+    let roll = session.roll(flags("1d20"), &mut Roller::scripted(vec![20]));
+    assert!(roll.dice[0].is_critical());
+
+    // A blessed 1 is still a fumble
+    let roll = session.roll(flags("1d20"), &mut Roller::scripted(vec![1]));
+    assert_eq!(roll.value, 2);
+    assert!(roll.dice[0].is_fumble());
+}
+
+#[test]
+fn it_spends_a_point_to_reroll_a_die() {
+    let mut session = Session::new("alice");
+    session.grant_points("luck", 2);
+    let roll = session.roll(flags("2d6"), &mut Roller::scripted(vec![1, 5]));
+    let logged = LoggedRoll::new("alice", roll.clone(), false, 1);
+
+    let amended = session.spend_for_reroll("luck", &logged, &roll.dice[0].id, &mut Roller::scripted(vec![6])).unwrap();
+    assert_eq!(amended.amended_from, Some(roll.id.clone()));
+    assert_eq!(amended.value, 11);
+    assert_eq!(session.points("luck"), 1);
+
+    let spend = PointSpend {
+        amended_id: amended.id.clone(),
+        die_id: roll.dice[0].id.clone(),
+        kind: "luck".to_string(),
+        roll_id: roll.id.clone(),
+    };
+    assert_eq!(amended.points_spent, Some(spend.clone()));
+    assert_eq!(session.spends(), &vec![spend]);
+}
+
+#[test]
+fn it_refuses_to_overspend() {
+    let mut session = Session::new("alice");
+    session.grant_points("hero", 1);
+    let roll = session.roll(flags("2d6kh1"), &mut Roller::scripted(vec![1, 5]));
+    let logged = LoggedRoll::new("alice", roll.clone(), false, 1);
+
+    // The roller has nothing to hand out, so a reroll would panic
+    assert_eq!(session.spend_for_reroll("luck", &logged, &roll.dice[1].id, &mut Roller::scripted(vec![])).err(),
+               Some(SpendError::NotEnoughPoints("luck".to_string())));
+
+    // A die that can't be rerolled doesn't cost a point
+    assert_eq!(session.spend_for_reroll("hero", &logged, &roll.dice[0].id, &mut Roller::scripted(vec![])).err(),
+               Some(SpendError::Roll(RollError::DroppedDie(roll.dice[0].id.clone()))));
+    assert_eq!(session.points("hero"), 1);
+    assert!(session.spends().is_empty());
+    assert_eq!(roll.value, 5);
+}
+
+#[test]
+fn it_refuses_to_reroll_someone_elses_roll() {
+    let mut session = Session::new("alice");
+    session.grant_points("luck", 1);
+    let roll = Session::new("bob").roll(flags("1d20"), &mut Roller::scripted(vec![2]));
+    let logged = LoggedRoll::new("bob", roll.clone(), false, 1);
+
+    assert_eq!(session.spend_for_reroll("luck", &logged, &roll.dice[0].id, &mut Roller::scripted(vec![])).err(),
+               Some(SpendError::NotTheirRoll("bob".to_string())));
+    assert_eq!(session.points("luck"), 1);
+    assert!(session.spends().is_empty());
+}
+
+#[test]
+fn it_keeps_balances_when_saved() {
+    let mut session = Session::new("alice");
+    session.grant_points("luck", 3);
+    session.grant_points("hero", 1);
+    let roll = session.roll(flags("1d20"), &mut Roller::scripted(vec![2]));
+    let logged = LoggedRoll::new("alice", roll.clone(), false, 1);
+    session.spend_for_reroll("luck", &logged, &roll.dice[0].id, &mut Roller::scripted(vec![17])).unwrap();
+
+    let restored: Session = serde_json::from_str(&serde_json::to_string(&session).unwrap()).unwrap();
+    assert_eq!(restored, session);
+    assert_eq!((restored.points("luck"), restored.points("hero"), restored.points("fate")), (2, 1, 0));
+    assert_eq!(restored.spends().len(), 1);
+
+    // Sessions saved before points were kept still restore, with none
+    let old: Session = serde_json::from_str(r#"{"blessings": [], "participant": "bob"}"#).unwrap();
+    assert!(old.balances().is_empty());
+}
+
+#[test]
+fn it_reads_notation_with_the_participants_keywords() {
+    let mut session = Session::new("amélie");
+    assert_eq!(session.parse_options(), ParseOptions::new());
+    session.use_keywords(Arc::new(KeywordPack::new("fr", &[("gm", "kh")]).unwrap()));
+
+    let restored: Session = serde_json::from_str(&serde_json::to_string(&session).unwrap()).unwrap();
+    let rolls = parse_notation_with("4d6gm3", &restored.parse_options()).unwrap();
+    assert_eq!(rolls[0].canonical(), flags("4d6kh3").canonical());
+
+    // A pack edited in the saved session is checked the same as one loaded on its own
+    let edited = r#"{"blessings": [], "participant": "amélie", "keywords": {"language": "fr", "aliases": {"d": "kh"}}}"#;
+    let error = serde_json::from_str::<Session>(edited).err().unwrap();
+    assert!(error.to_string().starts_with(&KeywordError::Conflict("d".to_string()).to_string()));
+    let edited = r#"{"blessings": [], "participant": "amélie", "keywords": {"language": "fr", "aliases": {"gm": "d"}}}"#;
+    assert!(serde_json::from_str::<Session>(edited).is_err());
+    let none: Session = serde_json::from_str(r#"{"blessings": [], "participant": "amélie", "keywords": null}"#).unwrap();
+    assert_eq!(none.parse_options(), ParseOptions::new());
+}
+
+#[test]
+fn it_keeps_the_participants_presets_with_the_session() {
+    let mut session = Session::new("ana").with_max_preset_slots(1);
+    session.presets_mut().set_slot(1, "longsword attack", RollRequest::new("1d20+7")).unwrap();
+    assert_eq!(session.presets_mut().set_slot(2, "damage", RollRequest::new("1d8+4")).err(),
+               Some(PresetError::TooManySlots(1)));
+
+    // The presets are saved with the session, but the cap is set again when it's read back
+    let restored: Session = serde_json::from_str(&serde_json::to_string(&session).unwrap()).unwrap();
+    let rolled = restored.presets().roll_slot(1, &PartialOptions::new(), &Limits::default(), &mut Roller::scripted(vec![11]));
+    assert_eq!(rolled.unwrap().totals, vec![18]);
+    assert_eq!(restored.presets().max_slots(), 10);
+    assert_eq!(restored.with_max_preset_slots(1).presets().max_slots(), 1);
+
+    // Sessions saved before presets were kept still restore, with none
+    let old: Session = serde_json::from_str(r#"{"blessings": [], "participant": "bob"}"#).unwrap();
+    assert!(old.presets().list().is_empty());
+}
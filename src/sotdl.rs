@@ -0,0 +1,107 @@
+use die::DieType;
+use roll::{Roll, RollFlags};
+use roller::Roller;
+#[cfg(test)]
+use testing::{assert_roll_total, ScriptedRoller};
+
+/// A Shadow of the Demon Lord roll of a d20 plus the highest of any boon or bane d6s
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SotdlResult {
+    /// Banes left after cancelling against boons, and so the number of bane d6s rolled
+    pub banes: u8,
+
+    /// Boons left after cancelling against banes, and so the number of boon d6s rolled
+    pub boons: u8,
+
+    /// Number of boons and banes that cancelled each other out, one for one
+    pub cancelled: u8,
+
+    /// The d20 plus the modifier
+    pub d20: Roll,
+
+    /// The boon or bane d6s, with all but the highest dropped, none when they all cancelled
+    pub d6s: Option<Roll>,
+
+    /// The d20 roll, plus the highest boon die or minus the highest bane die
+    pub total: i32,
+}
+
+/// Roll a d20 plus the modifier with boons and banes
+pub fn sotdl_roll(modifier: i16, boons: u8, banes: u8) -> SotdlResult {
+    sotdl_roll_with(modifier, boons, banes, &mut Roller::new())
+}
+
+/// Roll with boons and banes using the given source of randomness
+///
+/// Boons and banes cancel one for one before anything is rolled, so 3 boons and 2 banes roll a
+/// single boon die.
+pub fn sotdl_roll_with(modifier: i16, boons: u8, banes: u8, roller: &mut Roller) -> SotdlResult {
+    let cancelled = boons.min(banes);
+    let (boons, banes) = (boons - cancelled, banes - cancelled);
+
+    let mut flags = RollFlags::builder().n(1).die(DieType::D20).modifier(modifier).build()
+        .expect("a d20 is always a valid roll");
+    flags.equation = flags.canonical();
+    let d20 = Roll::with_roller(flags, roller);
+
+    let net = boons.max(banes);
+    let d6s = if net > 0 {
+        let mut flags = RollFlags::builder().n(net as i16).die(DieType::D6).kh(1).build()
+            .expect("boon and bane dice are always a valid roll");
+        flags.equation = flags.canonical();
+        Some(Roll::with_roller(flags, roller))
+    } else {
+        None
+    };
+
+    let highest = d6s.as_ref().map_or(0, |d6s| d6s.value);
+    let total = if banes > 0 {
+        d20.value - highest
+    } else {
+        d20.value + highest
+    };
+
+    SotdlResult {
+        banes,
+        boons,
+        cancelled,
+        d20,
+        d6s,
+        total,
+    }
+}
+
+#[test]
+fn it_adds_the_highest_boon() {
+    // Two of the boons cancel the banes, leaving a single boon die
+    let result = sotdl_roll_with(2, 3, 2, &mut ScriptedRoller::new(vec![11, 4]));
+    assert_eq!((result.boons, result.banes, result.cancelled), (1, 0, 2));
+    assert_roll_total(&result.d20, 13);
+    assert_eq!(result.d6s.as_ref().unwrap().dice.len(), 1);
+    assert_eq!(result.total, 17);
+
+    let result = sotdl_roll_with(0, 2, 0, &mut ScriptedRoller::new(vec![9, 2, 5]));
+    let d6s = result.d6s.unwrap();
+    assert!(d6s.dice[0].is_dropped);
+    assert!(!d6s.dice[1].is_dropped);
+    assert_eq!(result.total, 14);
+}
+
+#[test]
+fn it_subtracts_the_highest_bane() {
+    let result = sotdl_roll_with(1, 1, 3, &mut ScriptedRoller::new(vec![15, 6, 3]));
+    assert_eq!((result.boons, result.banes, result.cancelled), (0, 2, 1));
+    let d6s = result.d6s.unwrap();
+    assert!(!d6s.dice[0].is_dropped);
+    assert!(d6s.dice[1].is_dropped);
+    assert_eq!(result.total, 10);
+}
+
+#[test]
+fn it_rolls_no_d6s_when_boons_and_banes_cancel() {
+    // Only the d20 is scripted, so rolling a d6 would run out of values
+    let result = sotdl_roll_with(-1, 2, 2, &mut ScriptedRoller::new(vec![8]));
+    assert_eq!((result.boons, result.banes, result.cancelled), (0, 0, 2));
+    assert!(result.d6s.is_none());
+    assert_eq!(result.total, 7);
+}
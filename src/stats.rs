@@ -0,0 +1,1469 @@
+use die::DieType;
+use limits::Limits;
+use parser::parse;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use roll::{Roll, RollError, RollFlags};
+use roller::Roller;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+#[cfg(test)]
+use serde_json;
+
+/// Rolls made to estimate a distribution that can't be worked out exactly
+pub const SIMULATION_TRIALS: usize = 10000;
+
+/// Most dice a roll that keeps or drops dice can have for its distribution to be worked out exactly
+///
+/// Working out which dice are kept takes about faces × dice² × totals steps, so with the largest
+/// dice allowed that's around ten million. Bigger pools are simulated instead.
+pub const MAX_EXACT_KEPT_DICE: i16 = 10;
+
+/// Most faces the dice of a roll that keeps or drops dice can have for it to be worked out exactly
+pub const MAX_EXACT_KEPT_FACES: usize = 100;
+
+/// Most steps adding up a sum of dice can take for its distribution to be worked out exactly
+///
+/// Adding each die takes about faces × totals so far steps, so a sum takes about faces² × dice² / 2,
+/// which for 100 dice of 1000 faces is billions. Bigger sums are simulated instead.
+pub const MAX_EXACT_SUM_STEPS: u64 = 10_000_000;
+
+/// Most expressions `combined_distribution` adds together
+pub const MAX_COMBINED_EXPRESSIONS: usize = 20;
+
+/// Most outcomes `enumerate` goes through by default before giving up
+pub const MAX_ENUMERATED_OUTCOMES: u64 = 10_000_000;
+
+/// Most dice a pool can have for `PoolStats` to work it out exactly by default, bigger pools that
+/// only add their dice are approximated
+pub const MAX_EXACT_POOL_DICE: i16 = 50;
+
+/// Berry–Esseen constant for sums of identical dice, as tightened by Shevtsova (2011)
+const BERRY_ESSEEN: f64 = 0.4748;
+
+/// Chance of each possible total of a roll
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Distribution {
+    /// If the probabilities were estimated by rolling rather than worked out exactly
+    pub is_approximate: bool,
+
+    /// Probability of every total that can be rolled, keyed by the total
+    pub probabilities: BTreeMap<i32, f64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EnumerationError {
+    /// The roll can't be rolled
+    Roll(RollError),
+
+    /// The roll has more outcomes (first) than were allowed (second), counting at least those of
+    /// the dice it starts with
+    TooLarge(u64, u64),
+
+    /// Faces drawn without replacement depend on the faces drawn before them, which isn't supported
+    WithoutReplacement,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StatsError {
+    /// The expression isn't a valid roll
+    InvalidNotation(String),
+
+    /// The expression is past the limits that keep rolls cheap
+    OverLimits(String, RollError),
+
+    /// Adding the distributions together would take more steps (first) than allowed (second)
+    TooLarge(u64, u64),
+
+    /// More expressions (first) were given than can be added together (second)
+    TooManyExpressions(usize, usize),
+
+    /// Simulating one of the rolls was stopped, e.g. past its deadline
+    Stopped(RollError),
+}
+
+/// Work out the distribution of the sum of several independent rolls, e.g. a round of damage
+///
+/// Rolls without an exact distribution are simulated, which marks the result as approximate. The
+/// rolls are checked against the default limits, see `combined_distribution_with`.
+pub fn combined_distribution(expressions: &[&str]) -> Result<Distribution, StatsError> {
+    combined_distribution_with(expressions, &Limits::default(), &mut Roller::new())
+}
+
+/// Work out the distribution of the sum of several independent rolls within the limits
+///
+/// Up to `MAX_COMBINED_EXPRESSIONS` rolls can be added together, and the dice of every roll
+/// together count against `limits.max_dice`. Adding each distribution to the total so far takes
+/// a step for every pair of their totals, and adding them all up can take up to
+/// `MAX_EXACT_SUM_STEPS`. Rolls are simulated with the roller, so its deadline covers all of them.
+pub fn combined_distribution_with(expressions: &[&str], limits: &Limits, roller: &mut Roller)
+                                  -> Result<Distribution, StatsError> {
+    if expressions.len() > MAX_COMBINED_EXPRESSIONS {
+        return Err(StatsError::TooManyExpressions(expressions.len(), MAX_COMBINED_EXPRESSIONS));
+    }
+
+    // Everything is checked before anything is worked out
+    let mut rolls = Vec::with_capacity(expressions.len());
+    let mut dice: i32 = 0;
+    for expression in expressions {
+        let flags = match parse(expression).and_then(|mut rolls| rolls.pop()) {
+            Some(flags) => flags,
+            None => return Err(StatsError::InvalidNotation(expression.to_string())),
+        };
+        if flags.validate().is_err() {
+            return Err(StatsError::InvalidNotation(expression.to_string()));
+        }
+        if let Err(error) = limits.check(&flags) {
+            return Err(StatsError::OverLimits(expression.to_string(), error));
+        }
+        dice += flags.n.max(0) as i32;
+        if dice > limits.max_dice as i32 {
+            let error = RollError::TooManyDice(dice.min(i16::max_value() as i32) as i16, limits.max_dice);
+            return Err(StatsError::OverLimits(expression.to_string(), error));
+        }
+        rolls.push(flags);
+    }
+
+    let mut combined = Distribution::constant(0);
+    let mut steps: u64 = 0;
+    for flags in rolls {
+        let distribution = match Distribution::exact(&flags) {
+            Some(distribution) => distribution,
+            None => Distribution::try_simulated(flags, SIMULATION_TRIALS, roller).map_err(StatsError::Stopped)?,
+        };
+        steps += combined.probabilities.len() as u64 * distribution.probabilities.len() as u64;
+        if steps > MAX_EXACT_SUM_STEPS {
+            return Err(StatsError::TooLarge(steps, MAX_EXACT_SUM_STEPS));
+        }
+        combined = combined.convolve(&distribution);
+    }
+    Ok(combined)
+}
+
+/// Work out the distribution of the dice with a tag across several rolls, e.g. the fire damage
+/// of `2d6[fire] + 1d6[cold]`
+///
+/// Modifiers, floors, ceilings and divisors apply to a whole roll rather than its dice, so only the
+/// kept dice are counted, the same as `Roll::total_for_tag`. Rolls without an exact distribution
+/// are simulated.
+pub fn tagged_distribution(rolls: &[RollFlags], tag: &str) -> Distribution {
+    let mut combined = Distribution::constant(0);
+    for flags in rolls.iter().filter(|flags| flags.tags.iter().any(|t| t == tag)) {
+        let mut dice = flags.clone();
+        dice.ceiling = None;
+        dice.divisors.clear();
+        dice.floor = None;
+        dice.modifiers.clear();
+
+        let distribution = match Distribution::exact(&dice) {
+            Some(distribution) => distribution,
+            None => Distribution::simulated(dice, SIMULATION_TRIALS, &mut Roller::new()),
+        };
+        combined = combined.convolve(&distribution);
+    }
+    combined
+}
+
+impl Distribution {
+    /// Work out the exact distribution of a roll
+    ///
+    /// Sums of dice are supported, along with keeping or dropping the highest or lowest dice of
+    /// pools up to `MAX_EXACT_KEPT_DICE` dice of `MAX_EXACT_KEPT_FACES` faces. Rolls that count,
+    /// reroll or explode dice, keep dice from bigger pools, or add up more than
+    /// `MAX_EXACT_SUM_STEPS` allows, return none.
+    pub fn exact(flags: &RollFlags) -> Option<Distribution> {
+        if flags.gt != 0 || flags.gte != 0 || flags.lt != 0 || flags.lte != 0 || flags.rr_op.is_some() ||
+            flags.ro_op.is_some() || flags.explode {
+            return None;
+        }
+        if flags.validate().is_err() {
+            return None;
+        }
+
+        let faces: Vec<i32> = faces(flags).into_iter().map(|face| face as i32).collect();
+        if flags.n > 0 && faces.is_empty() {
+            return None;
+        }
+
+        let totals = match kept_ranks(flags) {
+            Some((low, high)) => {
+                if flags.n > MAX_EXACT_KEPT_DICE || faces.len() > MAX_EXACT_KEPT_FACES {
+                    return None;
+                }
+                kept_totals(&faces, flags.n.max(0) as usize, low, high)
+            },
+            None => {
+                let (n, count) = (flags.n.max(0) as u64, faces.len() as u64);
+                if count * count * n * n / 2 > MAX_EXACT_SUM_STEPS {
+                    return None;
+                }
+
+                // Add one die at a time to the totals rolled so far
+                let mut totals = BTreeMap::new();
+                totals.insert(0, 1.0);
+                for _ in 0..flags.n {
+                    let mut next = BTreeMap::new();
+                    for (total, p) in &totals {
+                        for face in &faces {
+                            *next.entry(total + face).or_insert(0.0) += p / faces.len() as f64;
+                        }
+                    }
+                    totals = next;
+                }
+                totals
+            },
+        };
+
+        let modifier: i32 = flags.modifiers.iter().map(|m| *m as i32).sum();
+        let mut probabilities = BTreeMap::new();
+        for (total, p) in totals {
+            let mut total = total;
+            if let Some(floor) = flags.floor {
+                total = total.max(floor as i32);
+            }
+            if let Some(ceiling) = flags.ceiling {
+                total = total.min(ceiling as i32);
+            }
+            let total = flags.divisors.iter().fold(total + modifier, |total, divisor| {
+                flags.rounding.divide(total, *divisor as i32)
+            });
+            *probabilities.entry(total).or_insert(0.0) += p;
+        }
+
+        Some(Distribution {
+            is_approximate: false,
+            probabilities,
+        })
+    }
+
+    /// Estimate the distribution of a roll by rolling it over and over
+    pub fn simulated(flags: RollFlags, trials: usize, roller: &mut Roller) -> Distribution {
+        let mut counts = BTreeMap::new();
+        let mut rolled = 0;
+        for _ in 0..trials {
+            if roller.should_stop() {
+                break;
+            }
+            let roll = Roll::with_roller(flags.clone(), roller);
+            *counts.entry(roll.value).or_insert(0) += 1;
+            rolled += 1;
+        }
+
+        Distribution {
+            is_approximate: true,
+            probabilities: counts.into_iter().map(|(total, count)| (total, count as f64 / rolled as f64)).collect(),
+        }
+    }
+
+    /// Simulate the same as `simulated`, failing instead if the roller stops before every trial is rolled
+    pub fn try_simulated(flags: RollFlags, trials: usize, roller: &mut Roller) -> Result<Distribution, RollError> {
+        let distribution = Distribution::simulated(flags, trials, roller);
+        roller.stopped()?;
+        Ok(distribution)
+    }
+
+    fn constant(total: i32) -> Distribution {
+        let mut probabilities = BTreeMap::new();
+        probabilities.insert(total, 1.0);
+        Distribution {
+            is_approximate: false,
+            probabilities,
+        }
+    }
+
+    /// The distribution of the sum of this roll and another, independent one
+    pub fn convolve(&self, other: &Distribution) -> Distribution {
+        let mut probabilities = BTreeMap::new();
+        for (a, p) in &self.probabilities {
+            for (b, q) in &other.probabilities {
+                *probabilities.entry(a + b).or_insert(0.0) += p * q;
+            }
+        }
+
+        Distribution {
+            is_approximate: self.is_approximate || other.is_approximate,
+            probabilities,
+        }
+    }
+
+    /// The average total
+    pub fn mean(&self) -> f64 {
+        self.probabilities.iter().map(|(total, p)| *total as f64 * p).sum()
+    }
+
+    /// Chance of rolling exactly the given total
+    pub fn probability(&self, total: i32) -> f64 {
+        *self.probabilities.get(&total).unwrap_or(&0.0)
+    }
+
+    /// Chance of rolling the given total or higher
+    pub fn probability_at_least(&self, total: i32) -> f64 {
+        self.probabilities.range(total..).map(|(_, p)| p).sum()
+    }
+
+    /// Chance of rolling the given total or lower
+    pub fn probability_at_most(&self, total: i32) -> f64 {
+        self.probabilities.range(..=total).map(|(_, p)| p).sum()
+    }
+
+    /// Chance of the combined total of several rolls reaching the target, the same as
+    /// `probability_at_least`
+    pub fn probability_total_at_least(&self, target: i32) -> f64 {
+        self.probability_at_least(target)
+    }
+}
+
+/// How likely a roll's total was, see `RollOptions::annotate_probability`
+///
+/// Written as how far out in the likelier tail the total was, e.g. `top 5% of possible outcomes`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RollProbability {
+    /// Chance of rolling the total or higher
+    pub exact_or_higher: f64,
+
+    /// Chance of rolling the total or lower
+    pub exact_or_lower: f64,
+
+    /// Percent of outcomes the total beat, counting half of those it tied, from 0 to 100
+    pub percentile: f64,
+}
+
+impl RollProbability {
+    pub fn of(distribution: &Distribution, total: i32) -> RollProbability {
+        let exact_or_lower = distribution.probability_at_most(total);
+        RollProbability {
+            exact_or_higher: distribution.probability_at_least(total),
+            exact_or_lower,
+            percentile: (exact_or_lower - distribution.probability(total) / 2.0) * 100.0,
+        }
+    }
+}
+
+impl fmt::Display for RollProbability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (tail, chance) = if self.exact_or_higher <= self.exact_or_lower {
+            ("top", self.exact_or_higher)
+        } else {
+            ("bottom", self.exact_or_lower)
+        };
+        let percent = chance * 100.0;
+        if percent >= 1.0 {
+            write!(f, "{} {:.0}% of possible outcomes", tail, percent)
+        } else if percent >= 0.1 {
+            write!(f, "{} {:.1}% of possible outcomes", tail, percent)
+        } else {
+            // Rounding down would say it couldn't happen, when it just did
+            write!(f, "{} 0.1% of possible outcomes", tail)
+        }
+    }
+}
+
+/// Work out the exact distribution of a roll's total by rolling every combination of faces it can
+/// land on
+///
+/// See `enumerate_by`.
+pub fn enumerate(flags: &RollFlags, max_outcomes: u64) -> Result<Distribution, EnumerationError> {
+    enumerate_by(flags, max_outcomes, |roll| roll.value)
+}
+
+/// Work out the exact distribution of something about a roll, e.g. its successes, by rolling every
+/// combination of faces it can land on
+///
+/// This is brute force meant as ground truth for testing, so every combination is rolled the
+/// usual way with the faces handed to the roller one at a time, the same as dice rolled by hand.
+/// Whatever a roll does to its dice is covered, rerolls and explosions included, each extra die
+/// branching into every face. It gives up once there are more than `max_outcomes` outcomes,
+/// checked before rolling anything against the combinations of the dice the roll starts with.
+pub fn enumerate_by<F: Fn(&Roll) -> i32>(flags: &RollFlags, max_outcomes: u64, measure: F)
+                                         -> Result<Distribution, EnumerationError> {
+    flags.validate().map_err(EnumerationError::Roll)?;
+    if flags.without_replacement {
+        return Err(EnumerationError::WithoutReplacement);
+    }
+    let faces = faces(flags);
+    if faces.is_empty() {
+        return Err(EnumerationError::Roll(RollError::NoSides));
+    }
+    let starting = (faces.len() as u64).checked_pow(flags.n.max(0) as u32).unwrap_or(u64::max_value());
+    if starting > max_outcomes {
+        return Err(EnumerationError::TooLarge(starting, max_outcomes));
+    }
+
+    // Roll each run of faces, and when the roll needs another die, every face it could land on
+    let mut probabilities = BTreeMap::new();
+    let mut outcomes = 0;
+    let mut runs: Vec<(Vec<i16>, f64)> = vec![(vec![], 1.0)];
+    while let Some((run, p)) = runs.pop() {
+        let mut roller = Roller::manual(run.clone());
+        let roll = Roll::with_roller(flags.clone(), &mut roller);
+        if roller.missing() == 0 {
+            outcomes += 1;
+            if outcomes > max_outcomes {
+                return Err(EnumerationError::TooLarge(outcomes, max_outcomes));
+            }
+            *probabilities.entry(measure(&roll)).or_insert(0.0) += p;
+            continue;
+        }
+
+        for face in &faces {
+            let mut longer = run.clone();
+            longer.push(*face);
+            runs.push((longer, p / faces.len() as f64));
+        }
+    }
+
+    Ok(Distribution {
+        is_approximate: false,
+        probabilities,
+    })
+}
+
+/// Every face a roll's dice can land on, repeated faces once for each time they appear
+/// About how many steps `Distribution::exact` takes to work out the roll's distribution
+///
+/// Sums add each face of each die to every total so far, and kept dice place each face among
+/// every total of every number of dice ranked so far.
+pub fn exact_steps(flags: &RollFlags) -> u64 {
+    let (n, count) = (flags.n.max(0) as u64, faces(flags).len() as u64);
+    let steps = count.saturating_mul(count).saturating_mul(n).saturating_mul(n);
+    match kept_ranks(flags) {
+        Some(_) => steps.saturating_mul(n),
+        None => steps / 2,
+    }
+}
+
+fn faces(flags: &RollFlags) -> Vec<i16> {
+    match flags.sides {
+        Some(ref sides) => sides.clone(),
+        None => (flags.min..flags.max.saturating_add(1)).collect(),
+    }
+}
+
+/// Ranks of the kept dice counting from the lowest, `low..high`, none when every die is kept
+///
+/// Keeping the highest or lowest goes before dropping, the same as rolling.
+fn kept_ranks(flags: &RollFlags) -> Option<(usize, usize)> {
+    let n = flags.n.max(0) as usize;
+    if flags.kh != 0 {
+        let keep = (flags.kh.max(0) as usize).min(n);
+        Some((n - keep, n))
+    } else if flags.kl != 0 {
+        Some((0, (flags.kl.max(0) as usize).min(n)))
+    } else if flags.dh != 0 || flags.dl != 0 {
+        let low = (flags.dl.max(0) as usize).min(n);
+        let high = (flags.dh.max(0) as usize).min(n - low);
+        Some((low, n - high))
+    } else {
+        None
+    }
+}
+
+/// Distribution of the sum of the dice ranked `low..high` from the lowest, out of `n` dice
+///
+/// Faces are gone through from lowest to highest, counting how many of the dice land on each. The
+/// dice on a face take the next ranks, so it's known how many of them are kept without sorting.
+fn kept_totals(faces: &[i32], n: usize, low: usize, high: usize) -> BTreeMap<i32, f64> {
+    let mut weights = BTreeMap::new();
+    for face in faces {
+        *weights.entry(*face).or_insert(0.0) += 1.0 / faces.len() as f64;
+    }
+
+    // Totals of the kept dice so far, by the number of dice ranked so far
+    let mut ranked = vec![BTreeMap::new(); n + 1];
+    ranked[0].insert(0, 1.0);
+    for (face, weight) in &weights {
+        let mut next = vec![BTreeMap::new(); n + 1];
+        for placed in 0..n + 1 {
+            for (total, p) in &ranked[placed] {
+                // Ways to pick which of the dice left land on this face
+                let mut ways = 1.0;
+                for count in 0..n - placed + 1 {
+                    let kept = (placed + count).min(high).saturating_sub(placed.max(low)) as i32;
+                    *next[placed + count].entry(total + kept * face).or_insert(0.0) +=
+                        p * ways * weight.powi(count as i32);
+                    ways = ways * (n - placed - count) as f64 / (count + 1) as f64;
+                }
+            }
+        }
+        ranked = next;
+    }
+    ranked.pop().unwrap_or_default()
+}
+
+/// How a pool's stats were worked out
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Method {
+    Exact,
+
+    /// From the normal distribution with the pool's mean and variance
+    Approximate,
+}
+
+/// When `PoolStats` works a pool out exactly
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    /// Exactly for pools of up to this many dice, approximately for bigger ones that can be
+    UpTo(i16),
+
+    /// Always exactly, however long it takes
+    Exact,
+
+    /// Always approximately, for pools that only add their dice
+    Approximate,
+}
+
+impl Default for Precision {
+    fn default() -> Precision {
+        Precision::UpTo(MAX_EXACT_POOL_DICE)
+    }
+}
+
+/// The mean and spread of a roll's total, and the chance of reaching a target, without
+/// necessarily working out every total, e.g. for 100d6
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PoolStats {
+    /// Most the chance of reaching any target can be off by, 0 when worked out exactly
+    pub error_bound: f64,
+    pub mean: f64,
+    pub method: Method,
+    pub variance: f64,
+
+    #[serde(skip)]
+    distribution: Option<Distribution>,
+}
+
+impl PoolStats {
+    /// Work out the stats of a roll as precisely as asked
+    ///
+    /// Pools that only add their dice and modifiers are approximated from their mean and variance,
+    /// which are worked out from the faces. Anything else, e.g. keeping the highest, is only
+    /// worked out exactly, so it returns none with `Precision::Approximate` or when
+    /// `Distribution::exact` can't work it out.
+    pub fn new(flags: &RollFlags, precision: Precision) -> Option<PoolStats> {
+        let is_approximate = match precision {
+            Precision::UpTo(max_dice) => flags.n > max_dice && is_plain_sum(flags),
+            Precision::Exact => false,
+            Precision::Approximate => true,
+        };
+        if is_approximate {
+            return PoolStats::approximate(flags);
+        }
+
+        let distribution = Distribution::exact(flags)?;
+        let mean = distribution.mean();
+        let variance = distribution.probabilities.iter().map(|(total, p)| (*total as f64 - mean).powi(2) * p).sum();
+        Some(PoolStats {
+            error_bound: 0.0,
+            mean,
+            method: Method::Exact,
+            variance,
+            distribution: Some(distribution),
+        })
+    }
+
+    /// The normal approximation of a pool that only adds its dice
+    ///
+    /// The error bound is the Berry–Esseen bound on how far the sum of the dice can be from the
+    /// normal distribution, which shrinks with the square root of the number of dice. It's loose,
+    /// e.g. 0.14 for 20d6 which is really off by about 0.0013 at most.
+    fn approximate(flags: &RollFlags) -> Option<PoolStats> {
+        if !is_plain_sum(flags) || flags.validate().is_err() {
+            return None;
+        }
+        let faces = faces(flags);
+        if faces.is_empty() {
+            return None;
+        }
+
+        // Moments of a single die
+        let count = faces.len() as f64;
+        let die_mean = faces.iter().map(|&face| face as f64).sum::<f64>() / count;
+        let die_variance = faces.iter().map(|&face| (face as f64 - die_mean).powi(2)).sum::<f64>() / count;
+        let third_moment = faces.iter().map(|&face| (face as f64 - die_mean).abs().powi(3)).sum::<f64>() / count;
+
+        let n = flags.n.max(0) as f64;
+        let modifier: i32 = flags.modifiers.iter().map(|m| *m as i32).sum();
+        let error_bound = if die_variance > 0.0 && n > 0.0 {
+            (BERRY_ESSEEN * third_moment / (die_variance.powf(1.5) * n.sqrt())).min(1.0)
+        } else {
+            0.0
+        };
+        Some(PoolStats {
+            error_bound,
+            mean: n * die_mean + modifier as f64,
+            method: Method::Approximate,
+            variance: n * die_variance,
+            distribution: None,
+        })
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance.sqrt()
+    }
+
+    /// Chance of rolling the target or higher
+    ///
+    /// Approximations take totals as whole numbers, so the target counts from half a point below it.
+    pub fn probability_at_least(&self, target: i32) -> f64 {
+        if let Some(ref distribution) = self.distribution {
+            return distribution.probability_at_least(target);
+        }
+        if self.variance == 0.0 {
+            return if target as f64 <= self.mean { 1.0 } else { 0.0 };
+        }
+        let z = (target as f64 - 0.5 - self.mean) / self.std_dev();
+        erfc(z / 2f64.sqrt()) / 2.0
+    }
+}
+
+/// If the roll only adds its dice and modifiers, so its total is close to normal for big pools
+fn is_plain_sum(flags: &RollFlags) -> bool {
+    let counts = [flags.kh, flags.kl, flags.dh, flags.dl, flags.gt, flags.gte, flags.lt, flags.lte];
+    counts.iter().all(|&count| count == 0) && !flags.explode && flags.rr_op.is_none() && flags.ro_op.is_none()
+        && flags.ceiling.is_none() && flags.floor.is_none() && flags.divisors.is_empty() && !flags.is_halved
+        && !flags.without_replacement
+}
+
+/// The complementary error function, to within 1.2e-7 (Numerical Recipes' `erfcc`)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let coefficients = [-1.26551223, 1.00002368, 0.37409196, 0.09678418, -0.18628806, 0.27886807, -1.13520398,
+                        1.48851587, -0.82215223, 0.17087277];
+    let series = coefficients.iter().rev().fold(0.0, |sum, c| c + t * sum);
+    let tail = t * (-z * z + series).exp();
+    if x >= 0.0 { tail } else { 2.0 - tail }
+}
+
+/// Samples kept by `OnlineStats::consume` for estimating percentiles
+pub const RESERVOIR_SIZE: usize = 1000;
+
+/// Mean, variance and range of a stream of totals, worked out without keeping every total
+///
+/// The mean and variance use Welford's method. A fixed-size reservoir of totals picked uniformly
+/// from the stream is kept for estimating percentiles.
+pub struct OnlineStats {
+    count: u64,
+    max: Option<i64>,
+    mean: f64,
+    min: Option<i64>,
+    reservoir: Vec<i64>,
+    reservoir_size: usize,
+    rng: XorShiftRng,
+
+    /// Sum of squared differences from the mean
+    squares: f64,
+}
+
+impl OnlineStats {
+    /// Start counting, keeping up to the given number of totals for percentiles
+    pub fn new(reservoir_size: usize) -> OnlineStats {
+        OnlineStats {
+            count: 0,
+            max: None,
+            mean: 0.0,
+            min: None,
+            reservoir: Vec::with_capacity(reservoir_size),
+            reservoir_size,
+            rng: XorShiftRng::from_seed([0x193a6754, 0xa8a7d469, 0x97830e05, 0x113ba7bb]),
+            squares: 0.0,
+        }
+    }
+
+    /// Count the first `n` totals of a stream, e.g. `Roller::simulate_stream`
+    pub fn consume<I: Iterator<Item = i64>>(iter: I, n: usize) -> OnlineStats {
+        let mut stats = OnlineStats::new(RESERVOIR_SIZE);
+        for total in iter.take(n) {
+            stats.push(total);
+        }
+        stats
+    }
+
+    /// Count a single total
+    pub fn push(&mut self, total: i64) {
+        self.count += 1;
+        let delta = total as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squares += delta * (total as f64 - self.mean);
+        self.min = Some(self.min.map_or(total, |min| min.min(total)));
+        self.max = Some(self.max.map_or(total, |max| max.max(total)));
+
+        // Every total seen so far has the same chance of being in the reservoir
+        if self.reservoir.len() < self.reservoir_size {
+            self.reservoir.push(total);
+        } else if self.reservoir_size > 0 {
+            let index = self.rng.gen_range(0, self.count) as usize;
+            if index < self.reservoir_size {
+                self.reservoir[index] = total;
+            }
+        }
+    }
+
+    /// Number of totals counted
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The average total, zero before anything is counted
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Population variance of the totals
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.squares / self.count as f64
+        }
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    pub fn max(&self) -> Option<i64> {
+        self.max
+    }
+
+    pub fn min(&self) -> Option<i64> {
+        self.min
+    }
+
+    /// Estimate the total at a percentile between 0 and 100 from the reservoir
+    pub fn percentile(&self, percentile: f64) -> Option<i64> {
+        if self.reservoir.is_empty() {
+            return None;
+        }
+        let mut sorted = self.reservoir.clone();
+        sorted.sort();
+        let rank = (percentile.max(0.0).min(100.0) / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+        Some(sorted[rank])
+    }
+}
+
+/// A distribution looked up through the cache
+#[derive(Clone, Debug)]
+pub struct CachedDistribution {
+    pub distribution: Arc<Distribution>,
+
+    /// If the distribution was already in the cache rather than computed for this lookup
+    pub was_cached: bool,
+}
+
+#[derive(Debug)]
+struct CacheEntries {
+    distributions: HashMap<String, Arc<Distribution>>,
+    hits: u64,
+    misses: u64,
+
+    /// Canonical expressions from least to most recently used
+    recency: VecDeque<String>,
+}
+
+/// Least recently used cache of distributions, keyed by the canonical form of the roll
+#[derive(Debug)]
+pub struct DistributionCache {
+    capacity: usize,
+    entries: Mutex<CacheEntries>,
+}
+
+impl DistributionCache {
+    pub fn new(capacity: usize) -> DistributionCache {
+        DistributionCache {
+            capacity,
+            entries: Mutex::new(CacheEntries {
+                distributions: HashMap::new(),
+                hits: 0,
+                misses: 0,
+                recency: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// If the distribution of the roll is cached, without counting as a lookup
+    pub fn contains(&self, flags: &RollFlags) -> bool {
+        self.entries.lock().unwrap().distributions.contains_key(&flags.canonical())
+    }
+
+    /// Look up the distribution of a roll, computing and storing it if it isn't cached
+    pub fn distribution(&self, flags: &RollFlags) -> Option<CachedDistribution> {
+        let key = flags.canonical();
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let cached = entries.distributions.get(&key).cloned();
+            if let Some(distribution) = cached {
+                entries.hits += 1;
+                entries.recency.retain(|k| k != &key);
+                entries.recency.push_back(key);
+                return Some(CachedDistribution {
+                    distribution,
+                    was_cached: true,
+                });
+            }
+        }
+
+        // Compute outside the lock so other lookups aren't held up
+        let distribution = match Distribution::exact(flags) {
+            Some(distribution) => Arc::new(distribution),
+            None => return None,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.misses += 1;
+        if self.capacity > 0 && !entries.distributions.contains_key(&key) {
+            if entries.distributions.len() >= self.capacity {
+                if let Some(oldest) = entries.recency.pop_front() {
+                    entries.distributions.remove(&oldest);
+                }
+            }
+            entries.distributions.insert(key.clone(), distribution.clone());
+            entries.recency.push_back(key);
+        }
+        Some(CachedDistribution {
+            distribution,
+            was_cached: false,
+        })
+    }
+
+    /// Number of lookups answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.entries.lock().unwrap().hits
+    }
+
+    /// Number of lookups that had to compute a distribution
+    pub fn misses(&self) -> u64 {
+        self.entries.lock().unwrap().misses
+    }
+
+    /// Number of distributions currently stored
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().distributions.len()
+    }
+
+    /// Remove every stored distribution, keeping the hit and miss counts
+    pub fn invalidate(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.distributions.clear();
+        entries.recency.clear();
+    }
+}
+
+/// Running totals for one type of die
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DieStats {
+    /// Average natural value of the dice
+    pub average: f64,
+
+    /// Number of dice counted
+    pub count: u64,
+
+    /// The type of die
+    pub die: DieType,
+
+    /// Number of times each face came up
+    pub faces: BTreeMap<i16, u64>,
+
+    /// Highest natural value rolled
+    pub max: i16,
+
+    /// Lowest natural value rolled
+    pub min: i16,
+
+    /// Number of dice that landed on their highest face
+    pub nat_max: u64,
+
+    /// Number of dice that landed on their lowest face
+    pub nat_min: u64,
+
+    /// Sum of the natural values
+    pub sum: i64,
+}
+
+/// Summary of every die in the rolls fed to the stats
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SessionReport {
+    /// Stats for each type of die, in the order they were first rolled
+    pub dice: Vec<DieStats>,
+
+    /// Number of rolls counted
+    pub rolls: u64,
+}
+
+impl SessionReport {
+    fn new() -> SessionReport {
+        SessionReport {
+            dice: vec![],
+            rolls: 0,
+        }
+    }
+
+    fn record(&mut self, roll: &Roll, exclude_dropped: bool) {
+        self.rolls += 1;
+        for die in roll.dice.iter().filter(|d| !(exclude_dropped && d.is_dropped)) {
+            let index = match self.dice.iter().position(|stats| stats.die == die.die) {
+                Some(index) => index,
+                None => {
+                    self.dice.push(DieStats {
+                        average: 0.0,
+                        count: 0,
+                        die: die.die,
+                        faces: BTreeMap::new(),
+                        max: die.value,
+                        min: die.value,
+                        nat_max: 0,
+                        nat_min: 0,
+                        sum: 0,
+                    });
+                    self.dice.len() - 1
+                },
+            };
+
+            let stats = &mut self.dice[index];
+            stats.count += 1;
+            stats.sum += die.value as i64;
+            stats.average = stats.sum as f64 / stats.count as f64;
+            stats.max = stats.max.max(die.value);
+            stats.min = stats.min.min(die.value);
+            *stats.faces.entry(die.value).or_insert(0) += 1;
+
+            if die.is_critical() {
+                stats.nat_max += 1;
+            }
+            if die.is_fumble() {
+                stats.nat_min += 1;
+            }
+        }
+    }
+}
+
+/// Counts how each type of die has been landing over a session, e.g. a night of play
+///
+/// Only the natural values of the dice are counted, before modifiers, floors or ceilings.
+pub struct SessionStats {
+    /// Leave dropped dice, including rerolled ones, out of the counts
+    pub exclude_dropped: bool,
+
+    overall: SessionReport,
+    participants: HashMap<String, SessionReport>,
+}
+
+impl SessionStats {
+    pub fn new(exclude_dropped: bool) -> SessionStats {
+        SessionStats {
+            exclude_dropped,
+            overall: SessionReport::new(),
+            participants: HashMap::new(),
+        }
+    }
+
+    /// Count the dice of a finished roll
+    pub fn record(&mut self, roll: &Roll) {
+        self.overall.record(roll, self.exclude_dropped);
+    }
+
+    /// Count the dice of a finished roll made by a participant, also counting it for the session
+    pub fn record_for(&mut self, participant: &str, roll: &Roll) {
+        self.record(roll);
+        self.participants.entry(participant.to_string())
+            .or_insert_with(SessionReport::new)
+            .record(roll, self.exclude_dropped);
+    }
+
+    /// Summary of every roll in the session
+    pub fn report(&self) -> SessionReport {
+        self.overall.clone()
+    }
+
+    /// Everyone who's rolled, in alphabetical order
+    pub fn participants(&self) -> Vec<String> {
+        let mut participants: Vec<String> = self.participants.keys().cloned().collect();
+        participants.sort();
+        participants
+    }
+
+    /// Summary of the rolls made by a participant, if they've rolled
+    pub fn report_for(&self, participant: &str) -> Option<SessionReport> {
+        self.participants.get(participant).cloned()
+    }
+}
+
+#[cfg(test)]
+fn dice(n: i16, max: i16, modifiers: Vec<i16>) -> RollFlags {
+    let mut flags = RollFlags::new();
+    flags.n = n;
+    flags.max = max;
+    flags.modifiers = modifiers;
+    flags
+}
+
+#[test]
+fn it_can_compute_an_exact_distribution() {
+    let distribution = Distribution::exact(&dice(2, 6, vec![])).unwrap();
+    assert_eq!(distribution.probabilities.len(), 11);
+    assert!((distribution.probability(7) - 6.0 / 36.0).abs() < 1e-9);
+    assert!((distribution.mean() - 7.0).abs() < 1e-9);
+    assert!((distribution.probability_at_least(11) - 3.0 / 36.0).abs() < 1e-9);
+
+    let mut flags = dice(1, 20, vec![5]);
+    flags.floor = Some(10);
+    let distribution = Distribution::exact(&flags).unwrap();
+    assert!((distribution.probability(15) - 10.0 / 20.0).abs() < 1e-9);
+    assert_eq!(distribution.probability(14), 0.0);
+
+    let mut flags = dice(2, 20, vec![]);
+    flags.explode = true;
+    assert_eq!(Distribution::exact(&flags), None);
+
+    // Sums within the limits can still be too much work to add up
+    assert!(Distribution::exact(&dice(100, 100, vec![])).is_some());
+    assert_eq!(Distribution::exact(&dice(100, 1000, vec![])), None);
+}
+
+#[test]
+fn it_weights_repeated_custom_sides() {
+    let mut flags = RollFlags::new();
+    flags.n = 1;
+    flags.sides = Some(vec![1, 1, 6]);
+    let distribution = Distribution::exact(&flags).unwrap();
+    assert!((distribution.probability(1) - 2.0 / 3.0).abs() < 1e-9);
+    assert!((distribution.probability(6) - 1.0 / 3.0).abs() < 1e-9);
+    assert_eq!(distribution.probabilities.len(), 2);
+}
+
+#[test]
+fn it_counts_cache_hits_and_misses() {
+    let cache = DistributionCache::new(4);
+    assert!(!cache.distribution(&dice(2, 6, vec![])).unwrap().was_cached);
+    assert!(cache.distribution(&dice(2, 6, vec![])).unwrap().was_cached);
+    assert!(cache.distribution(&dice(2, 6, vec![])).unwrap().was_cached);
+    assert_eq!(cache.hits(), 2);
+    assert_eq!(cache.misses(), 1);
+
+    cache.invalidate();
+    assert_eq!(cache.len(), 0);
+    assert!(!cache.distribution(&dice(2, 6, vec![])).unwrap().was_cached);
+    assert_eq!(cache.misses(), 2);
+}
+
+#[test]
+fn it_evicts_the_least_recently_used_distribution() {
+    let cache = DistributionCache::new(2);
+    cache.distribution(&dice(1, 20, vec![]));
+    cache.distribution(&dice(2, 6, vec![]));
+
+    // Using the d20 again makes the 2d6 the oldest
+    cache.distribution(&dice(1, 20, vec![]));
+    cache.distribution(&dice(8, 6, vec![]));
+    assert_eq!(cache.len(), 2);
+
+    assert!(cache.distribution(&dice(1, 20, vec![])).unwrap().was_cached);
+    assert!(!cache.distribution(&dice(2, 6, vec![])).unwrap().was_cached);
+}
+
+#[test]
+fn it_shares_entries_between_equivalent_rolls() {
+    let cache = DistributionCache::new(4);
+    let mut attack = dice(1, 20, vec![2, 3]);
+    attack.equation = "1d20+2+3[attack]".to_string();
+    let mut check = dice(1, 20, vec![5]);
+    check.equation = "1d20+5".to_string();
+
+    let first = cache.distribution(&attack).unwrap();
+    let second = cache.distribution(&check).unwrap();
+    assert!(second.was_cached);
+    assert!(Arc::ptr_eq(&first.distribution, &second.distribution));
+    assert_eq!(cache.len(), 1);
+}
+
+#[cfg(test)]
+fn session_rolls() -> Vec<Roll> {
+    use testing::ScriptedRoller;
+
+    let mut advantage = dice(2, 20, vec![5]);
+    advantage.die = DieType::D20;
+    advantage.kh = 1;
+    let mut check = dice(1, 20, vec![]);
+    check.die = DieType::D20;
+    let mut damage = dice(2, 6, vec![]);
+    damage.die = DieType::D6;
+
+    let mut roller = ScriptedRoller::new(vec![20, 3, 1, 6, 1]);
+    vec![
+        Roll::with_roller(advantage, &mut roller),
+        Roll::with_roller(check, &mut roller),
+        Roll::with_roller(damage, &mut roller),
+    ]
+}
+
+#[test]
+fn it_can_count_dice_over_a_session() {
+    let mut stats = SessionStats::new(false);
+    for roll in session_rolls().iter() {
+        stats.record(roll);
+    }
+
+    let report = stats.report();
+    assert_eq!(report.rolls, 3);
+    assert_eq!(report.dice.len(), 2);
+
+    let d20 = &report.dice[0];
+    assert_eq!(d20.die, DieType::D20);
+    assert_eq!(d20.count, 3);
+    assert_eq!(d20.sum, 24);
+    assert!((d20.average - 8.0).abs() < 1e-9);
+    assert_eq!(d20.min, 1);
+    assert_eq!(d20.max, 20);
+    assert_eq!(d20.nat_max, 1);
+    assert_eq!(d20.nat_min, 1);
+    assert_eq!(d20.faces.get(&3), Some(&1));
+    assert_eq!(d20.faces.len(), 3);
+
+    let d6 = &report.dice[1];
+    assert_eq!(d6.die, DieType::D6);
+    assert_eq!(d6.count, 2);
+    assert_eq!(d6.sum, 7);
+    assert_eq!(d6.nat_max, 1);
+    assert_eq!(d6.nat_min, 1);
+}
+
+#[test]
+fn it_can_leave_dropped_dice_out_of_a_session() {
+    let mut stats = SessionStats::new(true);
+    let rolls = session_rolls();
+    stats.record_for("ana", &rolls[0]);
+    stats.record_for("ana", &rolls[1]);
+    stats.record_for("bo", &rolls[2]);
+
+    // The 3 was dropped by keeping the highest
+    let d20 = &stats.report().dice[0];
+    assert_eq!(d20.count, 2);
+    assert_eq!(d20.sum, 21);
+    assert_eq!(d20.faces.get(&3), None);
+
+    let ana = stats.report_for("ana").unwrap();
+    assert_eq!(ana.rolls, 2);
+    assert_eq!(ana.dice.len(), 1);
+    assert_eq!(stats.report_for("bo").unwrap().dice[0].die, DieType::D6);
+    assert_eq!(stats.report_for("cy"), None);
+}
+
+#[test]
+fn it_can_combine_distributions() {
+    let combined = combined_distribution(&["1d4", "1d6"]).unwrap();
+    assert!(!combined.is_approximate);
+    assert_eq!(combined.probabilities.len(), 9);
+
+    // 24 equally likely pairs, with 4 of them adding up to each of 5, 6 and 7
+    for &(total, ways) in &[(2, 1), (3, 2), (4, 3), (5, 4), (6, 4), (7, 4), (8, 3), (9, 2), (10, 1)] {
+        assert!((combined.probability(total) - ways as f64 / 24.0).abs() < 1e-9);
+    }
+    assert!((combined.probability_total_at_least(8) - 6.0 / 24.0).abs() < 1e-9);
+    assert!((combined.mean() - 6.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_can_combine_exact_and_simulated_distributions() {
+    // Too many dice to keep exactly, so the pool is simulated
+    let combined = combined_distribution(&["2d6+4", "12d6kh3"]).unwrap();
+    assert!(combined.is_approximate);
+
+    let total: f64 = combined.probabilities.values().sum();
+    assert!((total - 1.0).abs() < 1e-9);
+    assert!(combined.probability_total_at_least(7) > 0.99);
+    assert_eq!(combined.probability_total_at_least(37), 0.0);
+
+    assert_eq!(combined_distribution(&["2d6", "nope"]).err(), Some(StatsError::InvalidNotation("nope".to_string())));
+}
+
+#[test]
+fn it_limits_the_whole_combined_request() {
+    let many = vec!["1d6"; MAX_COMBINED_EXPRESSIONS + 1];
+    assert_eq!(combined_distribution(&many).err(), Some(StatsError::TooManyExpressions(21, 20)));
+
+    // Each roll is within the limits, but not all of them together
+    let error = StatsError::OverLimits("60d6".to_string(), RollError::TooManyDice(120, 100));
+    assert_eq!(combined_distribution(&["60d6", "60d6"]).err(), Some(error));
+
+    // Nor is adding up the totals of wide rolls
+    assert_eq!(combined_distribution(&["50d1000", "50d1000"]).err().map(|error| match error {
+        StatsError::TooLarge(_, max) => max,
+        _ => 0,
+    }), Some(MAX_EXACT_SUM_STEPS));
+}
+
+#[test]
+fn it_gives_up_on_combined_simulations_past_their_deadline() {
+    use roller::RollOptions;
+    use std::time::Duration;
+
+    let mut roller = Roller::seeded(42).with_options(&RollOptions::new().deadline(Duration::from_millis(0)));
+    assert_eq!(combined_distribution_with(&["2d6", "12d6kh3"], &Limits::default(), &mut roller).err(),
+               Some(StatsError::Stopped(RollError::DeadlineExceeded)));
+}
+
+#[test]
+fn it_can_compute_the_distribution_of_kept_dice() {
+    let mut flags = dice(2, 20, vec![]);
+    flags.kh = 1;
+    let advantage = Distribution::exact(&flags).unwrap();
+    assert!(!advantage.is_approximate);
+    assert!((advantage.probability_at_least(11) - 0.75).abs() < 1e-9);
+    assert!((advantage.probability(20) - 39.0 / 400.0).abs() < 1e-9);
+
+    flags.kh = 0;
+    flags.kl = 1;
+    let disadvantage = Distribution::exact(&flags).unwrap();
+    assert!((disadvantage.probability_at_least(11) - 0.25).abs() < 1e-9);
+
+    let mut flags = dice(4, 6, vec![]);
+    flags.kh = 3;
+    let distribution = Distribution::exact(&flags).unwrap();
+    assert!((distribution.mean() - 12.2446).abs() < 1e-3);
+    assert!((distribution.probability(18) - 21.0 / 1296.0).abs() < 1e-9);
+    assert_eq!(distribution.probabilities.keys().cloned().collect::<Vec<i32>>(), (3..19).collect::<Vec<i32>>());
+
+    // Dropping both ends of 3d6 leaves the middle die, a 6 needs at least two of them
+    let mut flags = dice(3, 6, vec![]);
+    flags.dh = 1;
+    flags.dl = 1;
+    let distribution = Distribution::exact(&flags).unwrap();
+    assert!((distribution.probability(6) - 16.0 / 216.0).abs() < 1e-9);
+    assert!((distribution.mean() - 3.5).abs() < 1e-9);
+
+    let mut flags = dice(MAX_EXACT_KEPT_DICE + 1, 6, vec![]);
+    flags.kh = 3;
+    assert_eq!(Distribution::exact(&flags), None);
+}
+
+#[test]
+fn it_can_compute_the_distribution_of_shift_dice() {
+    let mut flags = dice(2, 2, vec![]);
+    flags.min = -2;
+    flags.sides = Some(vec![-2, -1, 0, 1, 2]);
+    let distribution = Distribution::exact(&flags).unwrap();
+
+    assert_eq!(distribution.probabilities.keys().cloned().collect::<Vec<i32>>(), (-4..5).collect::<Vec<i32>>());
+    assert!(distribution.mean().abs() < 1e-9);
+    assert!((distribution.probability(-4) - 1.0 / 25.0).abs() < 1e-9);
+    assert!((distribution.probability(0) - 5.0 / 25.0).abs() < 1e-9);
+    assert!((distribution.probability_at_least(-3) - 24.0 / 25.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_streams_the_same_stats_as_a_batch_simulation() {
+    let mut flags = dice(3, 6, vec![2]);
+    flags.kh = 2;
+
+    let streamed = OnlineStats::consume(Roller::simulate_stream(flags.clone(), 42), 100000);
+    let batch = Distribution::simulated(flags, 100000, &mut Roller::seeded(42));
+    let batch_variance: f64 = batch.probabilities.iter()
+        .map(|(total, p)| (*total as f64 - batch.mean()).powi(2) * p)
+        .sum();
+
+    assert_eq!(streamed.count(), 100000);
+    assert!((streamed.mean() - batch.mean()).abs() < 1e-9);
+    assert!((streamed.variance() - batch_variance).abs() < 1e-6);
+    assert_eq!(streamed.min(), batch.probabilities.keys().next().map(|min| *min as i64));
+    assert_eq!(streamed.max(), batch.probabilities.keys().last().map(|max| *max as i64));
+
+    // 4 to 14, with a median near the batch's
+    let median = streamed.percentile(50.0).unwrap();
+    assert!(batch.probability_at_least(median as i32) >= 0.4 && batch.probability_at_least(median as i32 + 1) <= 0.6);
+    assert!(streamed.percentile(0.0).unwrap() >= 4 && streamed.percentile(100.0).unwrap() <= 14);
+}
+
+#[test]
+fn it_can_compute_the_distribution_of_a_tag() {
+    let rolls = parse("2d6[fire] + 1d6[cold]+3").unwrap();
+    let fire = tagged_distribution(&rolls, "fire");
+    assert!(!fire.is_approximate);
+    assert!((fire.probability(7) - 6.0 / 36.0).abs() < 1e-9);
+    assert!((fire.mean() - 7.0).abs() < 1e-9);
+
+    // The modifier belongs to the cold roll, not its dice
+    let cold = tagged_distribution(&rolls, "cold");
+    assert_eq!(cold.probabilities.keys().cloned().collect::<Vec<i32>>(), vec![1, 2, 3, 4, 5, 6]);
+    assert_eq!(tagged_distribution(&rolls, "acid").probability(0), 1.0);
+}
+
+#[test]
+fn it_gives_up_on_simulations_past_their_deadline() {
+    use roller::RollOptions;
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let mut roller = Roller::seeded(42).with_options(&RollOptions::new().deadline(Duration::from_millis(10)));
+    let result = Distribution::try_simulated(dice(3, 6, vec![]), usize::max_value(), &mut roller);
+    assert_eq!(result.err(), Some(RollError::DeadlineExceeded));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[cfg(test)]
+fn assert_close(found: &Distribution, expected: &Distribution, tolerance: f64) {
+    let totals: Vec<&i32> = found.probabilities.keys().chain(expected.probabilities.keys()).collect();
+    for total in totals {
+        let (a, b) = (found.probability(*total), expected.probability(*total));
+        assert!((a - b).abs() < tolerance, "P({}) was {} rather than {}", total, a, b);
+    }
+}
+
+#[test]
+fn it_enumerates_the_same_distributions_as_it_works_out() {
+    for notation in &["2d6", "4d6kh3", "3d20dh1dl1+3"] {
+        let flags = parse(notation).unwrap().pop().unwrap();
+        let enumerated = enumerate(&flags, MAX_ENUMERATED_OUTCOMES).unwrap();
+        assert_close(&enumerated, &Distribution::exact(&flags).unwrap(), 1e-9);
+
+        let simulated = Distribution::simulated(flags, 100000, &mut Roller::seeded(11));
+        assert_close(&enumerated, &simulated, 0.01);
+    }
+
+    let two_d6 = enumerate(&parse("2d6").unwrap()[0], MAX_ENUMERATED_OUTCOMES).unwrap();
+    assert!((two_d6.probability(7) - 6.0 / 36.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_enumerates_rerolls_that_cant_be_worked_out() {
+    let flags = parse("3d6ro<2").unwrap().pop().unwrap();
+    assert_eq!(Distribution::exact(&flags), None);
+
+    // A 1 is rerolled once, so each die averages 5/6 × 4 + 1/6 × 3.5
+    let enumerated = enumerate(&flags, MAX_ENUMERATED_OUTCOMES).unwrap();
+    assert!((enumerated.mean() - 3.0 * (5.0 / 6.0 * 4.0 + 3.5 / 6.0)).abs() < 1e-9);
+    assert!((enumerated.probabilities.values().sum::<f64>() - 1.0).abs() < 1e-9);
+    assert!((enumerated.probability(3) - 1.0 / 6.0f64.powi(6)).abs() < 1e-12);
+
+    let simulated = Distribution::simulated(flags, 100000, &mut Roller::seeded(11));
+    assert_close(&enumerated, &simulated, 0.01);
+}
+
+#[test]
+fn it_enumerates_successes_and_refuses_big_rolls() {
+    // Each die lands on a 5 or 6 a third of the time
+    let flags = parse("3d6gte5").unwrap().pop().unwrap();
+    let successes = enumerate_by(&flags, MAX_ENUMERATED_OUTCOMES, |roll| roll.successes() as i32).unwrap();
+    assert!((successes.probability(3) - 1.0 / 27.0).abs() < 1e-9);
+    assert!((successes.probability(0) - 8.0 / 27.0).abs() < 1e-9);
+
+    let flags = parse("10d20").unwrap().pop().unwrap();
+    let too_large = EnumerationError::TooLarge(20u64.pow(10), MAX_ENUMERATED_OUTCOMES);
+    assert_eq!(enumerate(&flags, MAX_ENUMERATED_OUTCOMES), Err(too_large));
+
+    // Explosions can go past the limit even when the starting dice don't
+    let flags = parse("2d6!").unwrap().pop().unwrap();
+    assert_eq!(enumerate(&flags, 1000), Err(EnumerationError::TooLarge(1001, 1000)));
+}
+
+#[test]
+fn it_approximates_big_pools_within_the_bound() {
+    let flags = dice(20, 6, vec![]);
+    let exact = PoolStats::new(&flags, Precision::Exact).unwrap();
+    let approximate = PoolStats::new(&flags, Precision::Approximate).unwrap();
+    assert_eq!((exact.method, approximate.method), (Method::Exact, Method::Approximate));
+    assert!((approximate.mean - exact.mean).abs() < 1e-9);
+    assert!((approximate.variance - 20.0 * 35.0 / 12.0).abs() < 1e-9);
+    assert!((approximate.variance - exact.variance).abs() < 1e-6);
+
+    // 20d6 is never off by more than half a percent, well inside the bound
+    for target in 15..126 {
+        let error = (approximate.probability_at_least(target) - exact.probability_at_least(target)).abs();
+        assert!(error < 0.005, "off by {} at {}", error, target);
+        assert!(error <= approximate.error_bound);
+    }
+    assert_eq!(exact.error_bound, 0.0);
+    assert!(approximate.error_bound < 0.15);
+}
+
+#[test]
+fn it_switches_to_approximating_past_the_threshold() {
+    let big = dice(100, 6, vec![5]);
+    let stats = PoolStats::new(&big, Precision::default()).unwrap();
+    assert_eq!(stats.method, Method::Approximate);
+    assert!((stats.mean - 355.0).abs() < 1e-9);
+    assert!((stats.probability_at_least(355) - 0.5).abs() < 0.02);
+    let json = serde_json::to_string(&stats).unwrap();
+    assert!(json.contains("\"method\":\"Approximate\""), "{}", json);
+
+    assert_eq!(PoolStats::new(&big, Precision::UpTo(100)).unwrap().method, Method::Exact);
+    let json = serde_json::to_string(&PoolStats::new(&dice(2, 6, vec![]), Precision::default()).unwrap()).unwrap();
+    assert!(json.contains("\"method\":\"Exact\""), "{}", json);
+
+    // Keeping dice isn't close to normal, so it's only worked out exactly
+    let mut kept = dice(60, 6, vec![]);
+    kept.kh = 3;
+    assert_eq!(PoolStats::new(&kept, Precision::Approximate), None);
+    assert_eq!(PoolStats::new(&kept, Precision::default()), None);
+}
+
+#[test]
+fn it_annotates_rolls_with_how_likely_their_total_was() {
+    use roller::RollOptions;
+
+    let cache = Arc::new(DistributionCache::new(8));
+    let options = RollOptions::new().annotate_probability(cache.clone());
+    let mut roller = Roller::scripted(vec![20]).with_options(&options);
+    let roll = Roll::with_roller(parse("1d20+5").unwrap().pop().unwrap(), &mut roller);
+    let probability = roll.probability.unwrap();
+    assert!((probability.exact_or_higher - 0.05).abs() < 1e-9);
+    assert!((probability.exact_or_lower - 1.0).abs() < 1e-9);
+    assert!((probability.percentile - 97.5).abs() < 1e-9);
+    assert_eq!(probability.to_string(), "top 5% of possible outcomes");
+
+    // The same expression rolled again is looked up rather than worked out again
+    let mut roller = Roller::scripted(vec![6, 6, 6, 1, 6, 6, 6, 1]).with_options(&options);
+    let first = Roll::with_roller(parse("4d6kh3").unwrap().pop().unwrap(), &mut roller);
+    let again = Roll::with_roller(parse("4d6kh3").unwrap().pop().unwrap(), &mut roller);
+    assert!((first.probability.unwrap().exact_or_higher - 21.0 / 1296.0).abs() < 1e-9);
+    assert_eq!(first.probability, again.probability);
+    assert_eq!(first.probability.unwrap().to_string(), "top 2% of possible outcomes");
+    assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+    // Exploding dice have no exact distribution, so the roll says so instead
+    let mut roller = Roller::scripted(vec![2, 3, 4]).with_options(&options);
+    let exploding = Roll::with_roller(parse("3d6!").unwrap().pop().unwrap(), &mut roller);
+    assert_eq!(exploding.probability, None);
+    assert_eq!(exploding.warnings.iter().map(|warning| warning.rule.as_str()).collect::<Vec<&str>>(),
+               vec!["probability-unsupported"]);
+
+    // Rolls aren't annotated unless asked
+    assert_eq!(Roll::with_roller(parse("1d20").unwrap().pop().unwrap(), &mut Roller::scripted(vec![20])).probability, None);
+}
+
+#[test]
+fn it_skips_probabilities_too_costly_to_work_out() {
+    use roller::RollOptions;
+
+    let cache = Arc::new(DistributionCache::new(8));
+    let limits = Limits {
+        max_probability_steps: 1000,
+        ..Limits::default()
+    };
+    let options = RollOptions::new().annotate_probability(cache.clone()).limits(limits);
+    assert_eq!(exact_steps(&parse("10d20").unwrap().pop().unwrap()), 20000);
+    let mut roller = Roller::seeded(1).with_options(&options);
+    let big = Roll::with_roller(parse("10d20").unwrap().pop().unwrap(), &mut roller);
+    assert_eq!(big.probability, None);
+    assert_eq!(big.warnings.iter().map(|warning| warning.rule.as_str()).collect::<Vec<&str>>(),
+               vec!["probability-too-costly"]);
+    assert_eq!(big.warnings[0].message, "Working out how likely the total was would take 20000 steps, more than \
+                                         the 1000 allowed");
+    assert_eq!((cache.hits(), cache.misses()), (0, 0));
+    assert!(Roll::with_roller(parse("1d20").unwrap().pop().unwrap(), &mut roller).probability.is_some());
+
+    // The warning is for the roll's term once it's given one
+    let mut exploding = Roll::with_roller(parse("3d6!").unwrap().pop().unwrap(), &mut roller);
+    exploding.set_term_index(2);
+    assert_eq!(exploding.warnings.iter().map(|warning| (warning.rule.as_str(), warning.term)).collect::<Vec<_>>(),
+               vec![("probability-unsupported", 2)]);
+}
+
+#[test]
+fn it_writes_how_likely_a_total_was_from_the_likelier_tail() {
+    let distribution = Distribution::exact(&dice(1, 100, vec![])).unwrap();
+    assert_eq!(RollProbability::of(&distribution, 3).to_string(), "bottom 3% of possible outcomes");
+    assert_eq!(RollProbability::of(&distribution, 50).to_string(), "bottom 50% of possible outcomes");
+    let three_d20 = Distribution::exact(&dice(3, 20, vec![])).unwrap();
+    assert_eq!(RollProbability::of(&three_d20, 56).to_string(), "top 0.4% of possible outcomes");
+    assert_eq!(RollProbability::of(&three_d20, 60).to_string(), "top 0.1% of possible outcomes");
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+#[cfg(test)]
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+/// Most suggestions handed back at once
+pub const MAX_SUGGESTIONS: usize = 8;
+
+/// Die sizes, most often rolled first
+const DIE_SIZES: [(&str, &str); 8] = [
+    ("20", "Twenty-sided die"),
+    ("6", "Six-sided die"),
+    ("8", "Eight-sided die"),
+    ("4", "Four-sided die"),
+    ("10", "Ten-sided die"),
+    ("12", "Twelve-sided die"),
+    ("100", "Hundred-sided die"),
+    ("F", "Fate die"),
+];
+
+/// Modifiers that can follow a die, most often used first
+const MODIFIERS: [(&str, &str); 13] = [
+    ("kh", "Keep the highest dice"),
+    ("kl", "Keep the lowest dice"),
+    ("dh", "Drop the highest dice"),
+    ("dl", "Drop the lowest dice"),
+    ("!", "Explode dice on their highest face"),
+    ("rr", "Reroll matching dice until they don't match"),
+    ("ro", "Reroll matching dice once"),
+    ("gt", "Keep dice greater than a number"),
+    ("gte", "Keep dice of a number or more"),
+    ("lt", "Keep dice less than a number"),
+    ("lte", "Keep dice of a number or less"),
+    ("min", "Lowest a die can roll"),
+    ("max", "Highest a die can roll"),
+];
+
+/// A way to carry on writing notation, for a client to offer as the user types
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Suggestion {
+    /// What the suggestion does, short enough for a dropdown
+    pub description: String,
+
+    /// Text to insert at the cursor
+    pub insert: String,
+
+    /// The whole piece of notation the suggestion finishes, e.g. `kh` when `h` is inserted
+    pub label: String,
+}
+
+/// Suggest ways to carry on the notation before the cursor, e.g. `kh` and `kl` for `2d6k`
+///
+/// The cursor counts characters rather than bytes. Suggestions are ranked with the most used
+/// first, and anything can be passed in, the worst that happens is there are no suggestions.
+pub fn suggest(partial_input: &str, cursor: usize) -> Vec<Suggestion> {
+    suggest_with(partial_input, cursor, &HashMap::new())
+}
+
+/// Suggest the same as `suggest`, along with the variables that could follow an `@`
+///
+/// Variables are suggested in alphabetical order.
+pub fn suggest_with(partial_input: &str, cursor: usize, variables: &HashMap<String, i32>) -> Vec<Suggestion> {
+    let before: Vec<char> = partial_input.chars().take(cursor).collect();
+
+    // Nothing is suggested inside comments, tags or custom sides
+    let in_comment = before.iter().filter(|&&c| c == '"').count() % 2 == 1;
+    let in_brackets = before.iter().rposition(|&c| c == '[') > before.iter().rposition(|&c| c == ']');
+    let mut suggestions = if in_comment || in_brackets {
+        vec![]
+    } else if let Some(name) = variable_prefix(&before) {
+        variable_suggestions(&name, variables)
+    } else {
+        notation_suggestions(&before)
+    };
+    suggestions.truncate(MAX_SUGGESTIONS);
+    suggestions
+}
+
+/// The start of the variable name being written, when the cursor follows an `@`
+fn variable_prefix(before: &[char]) -> Option<String> {
+    let start = before.iter().rposition(|&c| !c.is_alphanumeric() && c != '_').map_or(0, |i| i + 1);
+    if start > 0 && before[start - 1] == '@' {
+        Some(before[start..].iter().collect())
+    } else {
+        None
+    }
+}
+
+fn variable_suggestions(prefix: &str, variables: &HashMap<String, i32>) -> Vec<Suggestion> {
+    let mut names: Vec<(&String, &i32)> = variables.iter()
+        .filter(|&(name, _)| name.starts_with(prefix) && name.len() > prefix.len())
+        .collect();
+    names.sort();
+    names.into_iter().map(|(name, value)| Suggestion {
+        description: format!("Variable, {} for now", value),
+        insert: name[prefix.len()..].to_string(),
+        label: format!("@{}", name),
+    }).collect()
+}
+
+fn notation_suggestions(before: &[char]) -> Vec<Suggestion> {
+    // The letters being written and the term they're part of, a term starts after an operator
+    let word_start = before.iter().rposition(|c| !c.is_ascii_alphabetic()).map_or(0, |i| i + 1);
+    let word: String = before[word_start..].iter().collect();
+    let head = &before[..word_start];
+    let term = &head[term_start(head)..];
+    let die = term.iter().position(|&c| c == 'd');
+
+    if !word.is_empty() {
+        return match die {
+            Some(_) => completions(&MODIFIERS, &word),
+            None if word == "d" => completions(&DIE_SIZES, ""),
+            None => vec![],
+        };
+    }
+
+    // Sides being written straight after the die's `d`, e.g. `2d1` could go on to `2d12`
+    let digits = term.iter().rev().take_while(|c| c.is_digit(10)).count();
+    if let Some(die) = die {
+        if digits > 0 && die == term.len() - digits - 1 {
+            let sides: String = term[term.len() - digits..].iter().collect();
+            let mut suggestions = completions(&DIE_SIZES, &sides);
+            suggestions.extend(completions(&MODIFIERS, ""));
+            return suggestions;
+        }
+    }
+
+    match term.last() {
+        Some(&'!') if term.len() < 2 || term[term.len() - 2] != '!' => vec![Suggestion {
+            description: "Compound exploded dice into one".to_string(),
+            insert: "!".to_string(),
+            label: "!!".to_string(),
+        }],
+        Some(&']') if die.is_some() => completions(&MODIFIERS, ""),
+        _ => vec![],
+    }
+}
+
+/// Where the last term starts, after an operator or group that isn't inside brackets
+fn term_start(head: &[char]) -> usize {
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, &c) in head.iter().enumerate() {
+        if c == '[' {
+            depth += 1;
+        } else if c == ']' {
+            depth = (depth - 1).max(0);
+        } else if depth == 0 && (c.is_whitespace() || "+-*/(),".contains(c)) {
+            start = i + 1;
+        }
+    }
+    start
+}
+
+/// Every choice that carries on from what's been written, leaving out what's already been written
+fn completions(choices: &[(&str, &str)], written: &str) -> Vec<Suggestion> {
+    choices.iter()
+        .filter(|&&(label, _)| label.starts_with(written) && label.len() > written.len())
+        .map(|&(label, description)| Suggestion {
+            description: description.to_string(),
+            insert: label[written.len()..].to_string(),
+            label: label.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn labels(suggestions: Vec<Suggestion>) -> Vec<String> {
+    suggestions.into_iter().map(|suggestion| suggestion.label).collect()
+}
+
+#[test]
+fn it_suggests_at_the_cursor() {
+    assert_eq!(labels(suggest("2d6k", 4)), vec!["kh", "kl"]);
+    assert_eq!(suggest("2d6k", 4)[0].insert, "h");
+    assert_eq!(labels(suggest("2d6kh3", 2)), vec!["20", "6", "8", "4", "10", "12", "100", "F"]);
+    assert_eq!(labels(suggest("2d6kh3", 6)), Vec::<String>::new());
+    assert_eq!(labels(suggest("1d1", 3))[..3].to_vec(), vec!["10", "12", "100"]);
+    assert_eq!(labels(suggest("1d20g", 5)), vec!["gt", "gte"]);
+    assert_eq!(labels(suggest("1d20gt", 6)), vec!["gte"]);
+    assert_eq!(labels(suggest("3d10!", 5)), vec!["!!"]);
+
+    // Every modifier can follow a whole die, only the most used are handed back
+    let after_die = suggest("1d20+4d6", 8);
+    assert_eq!(after_die.len(), MAX_SUGGESTIONS);
+    assert_eq!(labels(after_die)[..2].to_vec(), vec!["kh", "kl"]);
+    assert!(suggest("1d20 \"Rolling d", 15).is_empty());
+    assert!(suggest("2d6[fire, m", 11).is_empty());
+}
+
+#[test]
+fn it_suggests_inside_a_group() {
+    assert_eq!(labels(suggest("(1d)d6", 3))[..2].to_vec(), vec!["20", "6"]);
+    assert_eq!(labels(suggest("(1d4)d6", 4))[..2].to_vec(), vec!["kh", "kl"]);
+    assert!(suggest("(1d4)d6", 5).is_empty());
+    assert_eq!(labels(suggest("(1d4)d6", 6))[..2].to_vec(), vec!["20", "6"]);
+    assert_eq!(labels(suggest("(1d4)d6", 7))[..2].to_vec(), vec!["kh", "kl"]);
+    assert_eq!(labels(suggest("(1d4)d6[fire, magic]d", 21)), vec!["dh", "dl"]);
+}
+
+#[test]
+fn it_suggests_variables() {
+    let mut variables = HashMap::new();
+    variables.insert("level".to_string(), 5);
+    variables.insert("luck".to_string(), 2);
+    variables.insert("strength".to_string(), 3);
+
+    assert_eq!(labels(suggest_with("(1+@", 4, &variables)), vec!["@level", "@luck", "@strength"]);
+    let suggestions = suggest_with("(1+@le)d8", 6, &variables);
+    assert_eq!(labels(suggestions.clone()), vec!["@level"]);
+    assert_eq!(suggestions[0].insert, "vel");
+    assert_eq!(suggestions[0].description, "Variable, 5 for now");
+
+    // Without variables there's nothing to suggest
+    assert!(suggest("(1+@", 4).is_empty());
+}
+
+#[test]
+fn it_never_panics_on_garbage() {
+    let alphabet: Vec<char> = "0123456789dDFkhlgtemaxinor!<>=+-*/()[]\"@_, ３ｄ×é🎲".chars().collect();
+    let mut variables = HashMap::new();
+    variables.insert("level".to_string(), 1);
+    let mut rng = XorShiftRng::from_seed([0x2b1c3d4e, 0x5f607182, 0x93a4b5c6, 0xd7e8f901]);
+    for _ in 0..5000 {
+        let length = rng.gen_range(0, 16);
+        let input: String = (0..length).map(|_| alphabet[rng.gen_range(0, alphabet.len())]).collect();
+        let cursor = rng.gen_range(0, length + 4);
+        let suggestions = suggest_with(&input, cursor, &variables);
+        assert!(suggestions.len() <= MAX_SUGGESTIONS);
+        assert_eq!(suggestions, suggest_with(&input, cursor, &variables));
+    }
+}
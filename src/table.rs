@@ -0,0 +1,453 @@
+use roller::Roller;
+use std::collections::HashMap;
+#[cfg(test)]
+use serde_json;
+
+/// What happens when a table entry is rolled, on top of the entry itself
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Effect {
+    /// Add to every later roll on the same table, e.g. `+10` for each step deeper into a dungeon
+    Modifier(i16),
+
+    /// Roll on the same table this many more times, e.g. "98-00: roll twice more"
+    RollAgain(u8),
+
+    /// Roll once on the table with this ID
+    RollOn(String),
+}
+
+/// A range of faces on a table and what they turn up
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TableEntry {
+    /// What happens as well, if anything
+    pub effect: Option<Effect>,
+
+    /// The highest face that selects the entry
+    pub high: i16,
+
+    /// The lowest face that selects the entry
+    pub low: i16,
+
+    /// What the entry turns up, e.g. "A rusty longsword"
+    pub text: String,
+}
+
+impl TableEntry {
+    pub fn new(low: i16, high: i16, text: &str) -> TableEntry {
+        TableEntry {
+            effect: None,
+            high,
+            low,
+            text: text.to_string(),
+        }
+    }
+
+    pub fn with_effect(mut self, effect: Effect) -> TableEntry {
+        self.effect = Some(effect);
+        self
+    }
+
+    fn contains(&self, value: i16) -> bool {
+        self.low <= value && value <= self.high
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TableError {
+    /// Rolls again chained deeper than the limit
+    TooDeep(u8),
+
+    /// Rolling turned up more entries than the limit
+    TooManyResults(usize),
+
+    /// A face of the table is in more than one entry
+    Overlapping(i16),
+
+    /// A face of the table isn't in any entry
+    Uncovered(i16),
+
+    /// Every face of the table was excluded, so there was nothing left to roll
+    NothingLeft(String),
+
+    /// The table has no entries or its die no faces, so there's nothing to roll
+    Empty(String),
+
+    /// No table has the ID
+    UnknownTable(String),
+}
+
+/// A random table rolled with a single die, e.g. a d100 treasure table
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Table {
+    /// The entries, from the lowest face up
+    entries: Vec<TableEntry>,
+
+    /// If rolls again leave out the entry that asked for them, so "roll twice more" can't turn up
+    /// again and again
+    pub exclude_trigger: bool,
+
+    pub id: String,
+
+    /// Number of faces on the die the table is rolled with
+    pub sides: i16,
+}
+
+impl Table {
+    /// A table whose entries cover every face of its die exactly once
+    pub fn new(id: &str, sides: i16, mut entries: Vec<TableEntry>) -> Result<Table, TableError> {
+        if entries.is_empty() || sides < 1 {
+            return Err(TableError::Empty(id.to_string()));
+        }
+
+        // The lowest face no entry covers yet, none once they cover up to the highest face there is
+        entries.sort_by_key(|entry| entry.low);
+        let mut next = Some(1);
+        for entry in &entries {
+            let uncovered = match next {
+                Some(uncovered) if entry.low >= uncovered => uncovered,
+                _ => return Err(TableError::Overlapping(entry.low)),
+            };
+            if entry.low > uncovered || entry.high < entry.low {
+                return Err(TableError::Uncovered(uncovered));
+            }
+            next = entry.high.checked_add(1);
+        }
+        match next {
+            Some(uncovered) if uncovered <= sides => return Err(TableError::Uncovered(uncovered)),
+            Some(uncovered) if uncovered - 1 == sides => {},
+            _ => return Err(TableError::Overlapping(sides.saturating_add(1))),
+        }
+
+        Ok(Table {
+            entries,
+            exclude_trigger: false,
+            id: id.to_string(),
+            sides,
+        })
+    }
+
+    /// Leave out the entry that asked to roll again when rolling again
+    pub fn excluding_trigger(mut self) -> Table {
+        self.exclude_trigger = true;
+        self
+    }
+
+    /// The entries, from the lowest face up
+    pub fn entries(&self) -> &Vec<TableEntry> {
+        &self.entries
+    }
+
+    /// The entry a face selects, faces pushed past either end by modifiers select the entry at
+    /// that end
+    ///
+    /// Tables made with `new` have an entry for every face, while one read some other way might
+    /// not, which fails with the face left uncovered.
+    pub fn entry_for(&self, value: i16) -> Result<&TableEntry, TableError> {
+        if self.entries.is_empty() || self.sides < 1 {
+            return Err(TableError::Empty(self.id.clone()));
+        }
+        let value = value.max(1).min(self.sides);
+        self.entries.iter().find(|entry| entry.contains(value)).ok_or(TableError::Uncovered(value))
+    }
+}
+
+/// How far rolling on tables can go before it's stopped
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TableLimits {
+    /// Most rolls again or on other tables that can be chained from the first roll
+    pub max_depth: u8,
+
+    /// Most entries a roll can turn up, counting those that asked for more rolls
+    pub max_results: usize,
+}
+
+impl Default for TableLimits {
+    fn default() -> TableLimits {
+        TableLimits {
+            max_depth: 5,
+            max_results: 50,
+        }
+    }
+}
+
+/// An entry turned up by rolling on a table, with everything it asked to be rolled
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TableResult {
+    /// The entry rolls again or on other tables turned up, in the order they were rolled
+    pub children: Vec<TableResult>,
+
+    /// The entry that was selected
+    pub entry: TableEntry,
+
+    /// Modifier added to the face from earlier rolls on the table
+    pub modifier: i16,
+
+    /// The face the die landed on
+    pub natural: i16,
+
+    /// ID of the table rolled on
+    pub table: String,
+
+    /// The face with the modifier added, kept to the faces of the die
+    pub value: i16,
+}
+
+impl TableResult {
+    /// The entries that didn't ask for more rolls, in the order they were rolled
+    pub fn leaves(&self) -> Vec<&TableEntry> {
+        if self.children.is_empty() {
+            return vec![&self.entry];
+        }
+        self.children.iter().flat_map(|child| child.leaves()).collect()
+    }
+
+    /// Number of entries turned up, including this one
+    pub fn len(&self) -> usize {
+        1 + self.children.iter().map(|child| child.len()).sum::<usize>()
+    }
+}
+
+/// Tables that can be rolled on, and that can roll on each other by ID
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Tables {
+    tables: HashMap<String, Table>,
+}
+
+impl Tables {
+    pub fn new() -> Tables {
+        Tables::default()
+    }
+
+    /// Add a table, replacing any with the same ID
+    pub fn insert(&mut self, table: Table) {
+        self.tables.insert(table.id.clone(), table);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Table> {
+        self.tables.get(id)
+    }
+
+    /// Roll on a table, within the default limits
+    pub fn roll(&self, id: &str, roller: &mut Roller) -> Result<TableResult, TableError> {
+        self.roll_with_limits(id, &TableLimits::default(), roller)
+    }
+
+    /// Roll on a table, following every roll again and roll on another table it turns up
+    ///
+    /// Modifiers apply to every roll on their table after the entry with them, including those
+    /// that come back up the tree. Going past either limit fails the whole roll rather than
+    /// handing back part of it.
+    pub fn roll_with_limits(&self, id: &str, limits: &TableLimits, roller: &mut Roller)
+                            -> Result<TableResult, TableError> {
+        let mut resolution = Resolution {
+            limits,
+            modifiers: HashMap::new(),
+            results: 0,
+            tables: self,
+        };
+        resolution.roll(id, None, 0, roller)
+    }
+}
+
+struct Resolution<'a> {
+    limits: &'a TableLimits,
+    modifiers: HashMap<String, i16>,
+    results: usize,
+    tables: &'a Tables,
+}
+
+impl<'a> Resolution<'a> {
+    fn roll(&mut self, id: &str, excluded: Option<&TableEntry>, depth: u8, roller: &mut Roller)
+            -> Result<TableResult, TableError> {
+        if depth > self.limits.max_depth {
+            return Err(TableError::TooDeep(self.limits.max_depth));
+        }
+        self.results += 1;
+        if self.results > self.limits.max_results {
+            return Err(TableError::TooManyResults(self.limits.max_results));
+        }
+
+        let tables = self.tables;
+        let table = tables.get(id).ok_or_else(|| TableError::UnknownTable(id.to_string()))?;
+        let modifier = self.modifiers.get(id).cloned().unwrap_or(0);
+        if table.entries.is_empty() || table.sides < 1 {
+            return Err(TableError::Empty(id.to_string()));
+        }
+
+        // Only faces that don't land on the excluded entry are rolled, so leaving it out never
+        // needs another roll
+        let natural = match excluded {
+            Some(excluded) => {
+                let mut faces = vec![];
+                for face in 1..=table.sides {
+                    if table.entry_for(face.saturating_add(modifier))? != excluded {
+                        faces.push(face);
+                    }
+                }
+                if faces.is_empty() {
+                    return Err(TableError::NothingLeft(id.to_string()));
+                }
+                roller.side(&faces)
+            },
+            None => roller.between(1, table.sides),
+        };
+        let value = natural.saturating_add(modifier).max(1).min(table.sides);
+        let entry = table.entry_for(value)?.clone();
+
+        let mut children = vec![];
+        match entry.effect {
+            Some(Effect::Modifier(add)) => {
+                *self.modifiers.entry(id.to_string()).or_insert(0) += add;
+            },
+            Some(Effect::RollAgain(times)) => {
+                let excluded = if table.exclude_trigger {
+                    Some(&entry)
+                } else {
+                    None
+                };
+                for _ in 0..times {
+                    children.push(self.roll(id, excluded, depth + 1, roller)?);
+                }
+            },
+            Some(Effect::RollOn(ref other)) => {
+                children.push(self.roll(other, None, depth + 1, roller)?);
+            },
+            None => {},
+        }
+
+        Ok(TableResult {
+            children,
+            entry,
+            modifier,
+            natural,
+            table: id.to_string(),
+            value,
+        })
+    }
+}
+
+#[test]
+fn it_rolls_again_into_two_results() {
+    let mut tables = Tables::new();
+    tables.insert(Table::new("treasure", 100, vec![
+        TableEntry::new(1, 50, "Copper coins"),
+        TableEntry::new(51, 90, "A gem").with_effect(Effect::RollOn("gems".to_string())),
+        TableEntry::new(91, 100, "Roll twice more").with_effect(Effect::RollAgain(2)),
+    ]).unwrap());
+    tables.insert(Table::new("gems", 6, vec![TableEntry::new(1, 5, "Quartz"), TableEntry::new(6, 6, "Ruby")]).unwrap());
+
+    let result = tables.roll("treasure", &mut Roller::scripted(vec![99, 12, 60, 6])).unwrap();
+    assert_eq!(result.entry.text, "Roll twice more");
+    assert_eq!(result.natural, 99);
+    assert_eq!(result.children.len(), 2);
+    assert_eq!(result.children[0].natural, 12);
+    assert_eq!(result.children[1].children[0].table, "gems");
+    let leaves: Vec<&str> = result.leaves().iter().map(|entry| entry.text.as_str()).collect();
+    assert_eq!(leaves, vec!["Copper coins", "Ruby"]);
+    assert_eq!(result.len(), 4);
+}
+
+#[test]
+fn it_stops_chains_at_the_limits() {
+    let mut tables = Tables::new();
+    tables.insert(Table::new("treasure", 100, vec![
+        TableEntry::new(1, 97, "Copper coins"),
+        TableEntry::new(98, 100, "Roll twice more").with_effect(Effect::RollAgain(2)),
+    ]).unwrap());
+
+    let limits = TableLimits {
+        max_depth: 2,
+        max_results: 50,
+    };
+    let chained = tables.roll_with_limits("treasure", &limits, &mut Roller::scripted(vec![100, 98, 99]));
+    assert_eq!(chained, Err(TableError::TooDeep(2)));
+
+    let limits = TableLimits {
+        max_depth: 5,
+        max_results: 3,
+    };
+    let wide = tables.roll_with_limits("treasure", &limits, &mut Roller::scripted(vec![100, 1, 98]));
+    assert_eq!(wide, Err(TableError::TooManyResults(3)));
+}
+
+#[test]
+fn it_leaves_out_the_trigger_when_rolling_again() {
+    let table = Table::new("loop", 4, vec![
+        TableEntry::new(1, 3, "Roll twice more").with_effect(Effect::RollAgain(2)),
+        TableEntry::new(4, 4, "A map"),
+    ]).unwrap();
+    let mut tables = Tables::new();
+    tables.insert(table.clone());
+
+    // Three faces in four roll again, so without leaving them out the chain soon runs too deep
+    let looping = (0..20).filter(|seed| tables.roll("loop", &mut Roller::seeded(*seed)).is_err()).count();
+    assert!(looping > 0);
+
+    // Leaving them out, every roll again lands on the only other entry
+    tables.insert(table.excluding_trigger());
+    for seed in 0..20 {
+        let result = tables.roll("loop", &mut Roller::seeded(seed)).unwrap();
+        assert!(result.leaves().iter().all(|entry| entry.text == "A map"));
+        assert!(result.children.iter().all(|child| child.children.is_empty()));
+    }
+}
+
+#[test]
+fn it_adds_modifiers_to_later_rolls() {
+    let table = Table::new("depths", 20, vec![
+        TableEntry::new(1, 10, "Go deeper").with_effect(Effect::Modifier(10)),
+        TableEntry::new(11, 18, "Keep exploring").with_effect(Effect::RollAgain(2)),
+        TableEntry::new(19, 20, "A dragon"),
+    ]).unwrap();
+    let mut tables = Tables::new();
+    tables.insert(table);
+
+    // The second roll again is 5 + 10, and its own rolls again go past the last face
+    let result = tables.roll("depths", &mut Roller::scripted(vec![12, 5, 5, 15, 9])).unwrap();
+    assert_eq!(result.children[0].entry.text, "Go deeper");
+    let deeper = &result.children[1];
+    assert_eq!((deeper.natural, deeper.modifier, deeper.value), (5, 10, 15));
+    let values: Vec<(i16, i16)> = deeper.children.iter().map(|child| (child.natural, child.value)).collect();
+    assert_eq!(values, vec![(15, 20), (9, 19)]);
+    let leaves: Vec<&str> = result.leaves().iter().map(|entry| entry.text.as_str()).collect();
+    assert_eq!(leaves, vec!["Go deeper", "A dragon", "A dragon"]);
+
+    // Modifiers only last for the roll they're part of
+    let mut roller = Roller::scripted(vec![3, 19]);
+    tables.roll("depths", &mut roller).unwrap();
+    let again = tables.roll("depths", &mut roller).unwrap();
+    assert_eq!((again.modifier, again.value), (0, 19));
+}
+
+#[test]
+fn it_checks_entries_cover_the_die() {
+    assert_eq!(Table::new("gap", 6, vec![TableEntry::new(1, 2, "a"), TableEntry::new(4, 6, "b")]),
+               Err(TableError::Uncovered(3)));
+    assert_eq!(Table::new("overlap", 6, vec![TableEntry::new(1, 4, "a"), TableEntry::new(3, 6, "b")]),
+               Err(TableError::Overlapping(3)));
+    assert_eq!(Table::new("short", 6, vec![TableEntry::new(1, 5, "a")]).err(), Some(TableError::Uncovered(6)));
+    assert_eq!(Tables::new().roll("missing", &mut Roller::new()), Err(TableError::UnknownTable("missing".to_string())));
+}
+
+#[test]
+fn it_rejects_tables_with_nothing_to_roll() {
+    assert_eq!(Table::new("empty", 6, vec![]), Err(TableError::Empty("empty".to_string())));
+    assert_eq!(Table::new("faceless", 0, vec![TableEntry::new(1, 1, "a")]), Err(TableError::Empty("faceless".to_string())));
+
+    // A table read without `new` fails to roll rather than panicking
+    let table: Table = serde_json::from_str(r#"{"entries": [], "exclude_trigger": false, "id": "read", "sides": 6}"#).unwrap();
+    assert_eq!(table.entry_for(3), Err(TableError::Empty("read".to_string())));
+    let mut tables = Tables::new();
+    tables.insert(table);
+    assert_eq!(tables.roll("read", &mut Roller::new()), Err(TableError::Empty("read".to_string())));
+}
+
+#[test]
+fn it_checks_entries_up_to_the_highest_face() {
+    let top = i16::max_value();
+    let table = Table::new("wide", top, vec![TableEntry::new(1, top - 1, "a"), TableEntry::new(top, top, "b")]).unwrap();
+    assert_eq!(table.entry_for(top).map(|entry| entry.text.as_str()), Ok("b"));
+    assert_eq!(Table::new("past", 6, vec![TableEntry::new(1, top, "a")]), Err(TableError::Overlapping(7)));
+    assert_eq!(Table::new("twice", top, vec![TableEntry::new(1, top, "a"), TableEntry::new(top, top, "b")]),
+               Err(TableError::Overlapping(top)));
+}
@@ -0,0 +1,347 @@
+use chrono::DateTime;
+use chrono::prelude::{TimeZone, Utc};
+use die::Die;
+use roll::{Roll, RollError, RollFlags};
+use roller::{DiceRoller, Roller};
+use serde_json;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// A roller that hands out the given values in order instead of rolling
+///
+/// It derefs to the `Roller` it wraps, so `&mut ScriptedRoller` can be passed wherever a
+/// `&mut Roller` is taken.
+pub struct ScriptedRoller(Roller);
+
+impl ScriptedRoller {
+    pub fn new(values: Vec<i16>) -> ScriptedRoller {
+        ScriptedRoller(Roller::scripted(values))
+    }
+
+    pub fn into_inner(self) -> Roller {
+        self.0
+    }
+}
+
+impl Deref for ScriptedRoller {
+    type Target = Roller;
+
+    fn deref(&self) -> &Roller {
+        &self.0
+    }
+}
+
+impl DerefMut for ScriptedRoller {
+    fn deref_mut(&mut self) -> &mut Roller {
+        &mut self.0
+    }
+}
+
+/// A roll engine that hands out queued responses instead of rolling, recording what it was asked
+/// to roll
+///
+/// Share it with the code under test through an `Arc` to check the requests afterwards.
+#[derive(Default)]
+pub struct MockRoller {
+    requests: Mutex<Vec<RollFlags>>,
+    responses: Mutex<VecDeque<Result<Roll, RollError>>>,
+}
+
+impl MockRoller {
+    pub fn new() -> MockRoller {
+        MockRoller::default()
+    }
+
+    /// Queue a response for the next roll, responses are handed out in the order they're queued
+    pub fn respond_with(&self, response: Result<Roll, RollError>) -> &MockRoller {
+        self.responses.lock().unwrap().push_back(response);
+        self
+    }
+
+    /// The flags of every roll asked for so far, oldest first
+    pub fn requests(&self) -> Vec<RollFlags> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl DiceRoller for MockRoller {
+    fn roll(&self, flags: &RollFlags) -> Result<Roll, RollError> {
+        self.requests.lock().unwrap().push(flags.clone());
+        self.responses.lock().unwrap().pop_front()
+            .unwrap_or_else(|| panic!("no response queued for a mock roll of {}", flags.equation))
+    }
+}
+
+/// The flags of a die checked by `assert_die_flags`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlagSet {
+    pub is_dropped: bool,
+    pub is_exploded: bool,
+    pub is_manual: bool,
+    pub is_rerolled: bool,
+    pub is_successful: bool,
+}
+
+impl FlagSet {
+    /// The flags currently set on a die
+    pub fn of(die: &Die) -> FlagSet {
+        FlagSet {
+            is_dropped: die.is_dropped,
+            is_exploded: die.is_exploded,
+            is_manual: die.is_manual,
+            is_rerolled: die.is_rerolled,
+            is_successful: die.is_successful,
+        }
+    }
+}
+
+/// The moment every roll passed through `freeze` happened at
+pub fn fixed_time() -> DateTime<Utc> {
+    Utc.timestamp(1500000000, 0)
+}
+
+/// Check a roll's total, along with the kept dice adding up to its natural value
+pub fn assert_roll_total(roll: &Roll, expected: i32) {
+    let values: Vec<i16> = roll.dice.iter().map(|d| d.value).collect();
+    assert_eq!(roll.value, expected, "total of {} with dice {:?}", roll.equation, values);
+
+    let kept = roll.dice.iter().filter(|d| !d.is_dropped).fold(0, |sum, d| sum + d.value as i32);
+    assert_eq!(roll.natural_value, kept, "natural value of {} with dice {:?}", roll.equation, values);
+}
+
+/// Check every flag of a die
+pub fn assert_die_flags(die: &Die, expected: FlagSet) {
+    assert_eq!(FlagSet::of(die), expected, "flags of die {} showing {}", die.id, die.value);
+}
+
+/// Give a roll and its dice sequential ids and a fixed timestamp, so it can be compared to a golden
+/// file
+pub fn freeze(roll: &mut Roll) {
+    roll.id = "roll-0".to_string();
+    roll.timestamp = fixed_time();
+
+    let ids: Vec<(String, String)> = roll.dice.iter().enumerate()
+        .map(|(i, die)| (die.id.clone(), format!("die-{}", i)))
+        .collect();
+    for die in &mut roll.dice {
+        die.timestamp = fixed_time();
+        for record in &mut die.roll_history {
+            record.timestamp = fixed_time();
+        }
+        if let Some(&(_, ref id)) = ids.iter().find(|&&(ref old, _)| old == &die.id) {
+            die.id = id.clone();
+        }
+        if let Some(child) = die.child.take() {
+            die.child = ids.iter().find(|&&(ref old, _)| old == &child).map(|&(_, ref id)| id.clone()).or(Some(child));
+        }
+    }
+}
+
+/// Load a roll from a golden file's JSON
+pub fn roll_fixture(json: &str) -> Roll {
+    serde_json::from_str(json).expect("roll fixture should be a serialized roll")
+}
+
+#[cfg(test)]
+fn fate(n: i16) -> ::roll::RollFlags {
+    use die::DieType;
+    use roll::RollFlags;
+
+    RollFlags::fate(n, &Die::new(DieType::Fate))
+}
+
+#[test]
+fn it_can_assert_roll_totals_and_flags() {
+    use die::DieType;
+    use roll::RollFlags;
+
+    let flags = RollFlags::builder().n(2).die(DieType::D20).kh(1).build().unwrap();
+    let roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![4, 17]));
+    assert_roll_total(&roll, 17);
+    assert_die_flags(&roll.dice[0], FlagSet {
+        is_dropped: true,
+        is_successful: true,
+        ..FlagSet::default()
+    });
+    assert_die_flags(&roll.dice[1], FlagSet {
+        is_successful: true,
+        ..FlagSet::default()
+    });
+}
+
+#[test]
+#[should_panic(expected = "total of")]
+fn it_fails_on_the_wrong_total() {
+    let roll = Roll::with_roller(fate(1), &mut ScriptedRoller::new(vec![1]));
+    assert_roll_total(&roll, 0);
+}
+
+#[test]
+fn it_can_freeze_a_roll() {
+    use die::DieType;
+    use roll::RollFlags;
+    use ttml::arg::ComparisonArg;
+
+    let flags = RollFlags::builder().n(2).die(DieType::D6).ro(ComparisonArg::EqualTo, 1).build().unwrap();
+    let mut roll = Roll::with_roller(flags, &mut ScriptedRoller::new(vec![1, 5, 3]));
+    freeze(&mut roll);
+
+    assert_eq!(roll.id, "roll-0");
+    assert_eq!(roll.timestamp, fixed_time());
+    let ids: Vec<&str> = roll.dice.iter().map(|d| d.id.as_str()).collect();
+    assert_eq!(ids, vec!["die-0", "die-1", "die-2"]);
+    assert_eq!(roll.dice[0].child, Some("die-2".to_string()));
+    assert!(roll.dice.iter().all(|d| d.timestamp == fixed_time()));
+}
+
+#[test]
+fn it_can_load_a_roll_fixture() {
+    let mut roll = Roll::with_roller(fate(2), &mut ScriptedRoller::new(vec![1, 1]));
+    freeze(&mut roll);
+    let loaded = roll_fixture(&serde_json::to_string(&roll).unwrap());
+    assert_roll_total(&loaded, 2);
+    assert_eq!(loaded.id, "roll-0");
+}
+
+#[test]
+fn it_can_mock_the_roll_engine() {
+    use std::sync::Arc;
+
+    let canned = Roll::with_roller(fate(2), &mut ScriptedRoller::new(vec![1, 1]));
+    let mock = Arc::new(MockRoller::new());
+    mock.respond_with(Ok(canned)).respond_with(Err(RollError::NoSides));
+
+    // Code under test only sees the trait
+    let engine: Box<dyn DiceRoller> = Box::new(mock.clone());
+    assert_roll_total(&engine.roll_notation("1d20+5").unwrap(), 2);
+    assert_eq!(engine.roll(&fate(4)).err(), Some(RollError::NoSides));
+
+    let requests: Vec<String> = mock.requests().iter().map(|flags| flags.equation.clone()).collect();
+    assert_eq!(requests, vec!["1d20+5", "4dF"]);
+}
+
+#[test]
+#[should_panic(expected = "no response queued")]
+fn it_fails_when_the_mock_runs_out() {
+    MockRoller::new().roll_notation("1d20").ok();
+}
+
+/// Rolls kept as golden files in `tests/golden`, whose serialized shape must never drift
+#[cfg(test)]
+const GOLDEN_ROLLS: [&str; 5] = ["plain-die", "pool-with-drops", "explosion-chain", "success-count", "fate"];
+
+#[cfg(test)]
+fn golden_roll(name: &str) -> Roll {
+    use die::DieType;
+    use roll::ExplodeSuccessPolicy;
+
+    let (flags, values) = match name {
+        "plain-die" => (RollFlags::builder().n(1).die(DieType::D20).modifier(3).equation("1d20+3"), vec![17]),
+        "pool-with-drops" => (RollFlags::builder().n(4).die(DieType::D6).kh(3).equation("4d6kh3"), vec![3, 5, 1, 6]),
+        "explosion-chain" => {
+            let flags = RollFlags::builder().n(1).die(DieType::D6).explode(ExplodeSuccessPolicy::CompareEachDie);
+            (flags.equation("1d6!"), vec![6, 6, 2])
+        },
+        "success-count" => (RollFlags::builder().n(5).die(DieType::D10).gte(7).equation("5d10>=7"), vec![8, 3, 10, 7, 1]),
+        "fate" => {
+            let mut roll = Roll::with_roller(fate(4), &mut ScriptedRoller::new(vec![1, 0, -1, 1]));
+            roll.stamp_hashes(&fate(4));
+            freeze(&mut roll);
+            return roll;
+        },
+        _ => panic!("no golden roll named {}", name),
+    };
+    let flags = flags.build().unwrap();
+    let mut roll = Roll::with_roller(flags.clone(), &mut ScriptedRoller::new(values));
+    roll.stamp_hashes(&flags);
+    freeze(&mut roll);
+    roll
+}
+
+/// Terms rolled together, one of them conditional on a flag that isn't set
+#[cfg(test)]
+fn golden_expression() -> ::expression::ExpressionResult {
+    use die::DieType;
+    use expression::Expression;
+    use std::collections::HashMap;
+
+    let mut expression = Expression::new();
+    expression.push(RollFlags::builder().n(2).die(DieType::D6).tag("fire").equation("2d6[fire]").build().unwrap());
+    expression.push(RollFlags::builder().n(1).die(DieType::D6).tag("cold").equation("1d6[cold]").build().unwrap());
+    expression.push_conditional("crit", RollFlags::builder().n(2).die(DieType::D6).equation("2d6").build().unwrap());
+
+    let mut flags = HashMap::new();
+    flags.insert("crit".to_string(), false);
+    let mut result = expression.evaluate(&flags, &mut ScriptedRoller::new(vec![4, 3, 5])).unwrap();
+    for term in &mut result.terms {
+        if let Some(ref mut roll) = term.roll {
+            freeze(roll);
+        }
+    }
+    result
+}
+
+/// Read a golden file, or write it when `REGENERATE_GOLDEN` is set
+///
+/// Golden files are compared as JSON values rather than as text, so only the fields and their
+/// values matter, not their order or the whitespace between them.
+#[cfg(test)]
+fn golden_file(name: &str, current: &serde_json::Value) -> serde_json::Value {
+    use std::env;
+    use std::fs;
+
+    let path = format!("{}/tests/golden/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+    if env::var("REGENERATE_GOLDEN").is_ok() {
+        fs::write(&path, serde_json::to_string_pretty(current).unwrap() + "\n").unwrap();
+    }
+    let json = fs::read_to_string(&path).expect("golden file should exist, set REGENERATE_GOLDEN to write it");
+    serde_json::from_str(&json).expect("golden file should be JSON")
+}
+
+#[test]
+fn it_reads_and_writes_every_golden_roll() {
+    use expression::ExpressionResult;
+
+    for name in GOLDEN_ROLLS.iter() {
+        let golden = golden_file(name, &serde_json::to_value(&golden_roll(name)).unwrap());
+        let roll: Roll = serde_json::from_value(golden.clone()).expect("golden roll should still deserialize");
+        assert_eq!(serde_json::to_value(&roll).unwrap(), golden, "golden roll {} changed shape", name);
+    }
+
+    let golden = golden_file("grouped", &serde_json::to_value(&golden_expression()).unwrap());
+    let result: ExpressionResult = serde_json::from_value(golden.clone()).expect("golden expression should still deserialize");
+    assert_eq!(serde_json::to_value(&result).unwrap(), golden, "golden expression changed shape");
+}
+
+#[test]
+fn it_formats_every_golden_roll_the_same() {
+    use format::{RollFormatter, SummaryFormatter, VerboseFormatter};
+    use locale::English;
+
+    let mut formatted = serde_json::Map::new();
+    let mut buffer = String::new();
+    for name in GOLDEN_ROLLS.iter() {
+        let roll = golden_roll(name);
+        buffer.clear();
+        VerboseFormatter::new(English).format_into(&roll, &mut buffer).unwrap();
+        assert_eq!(VerboseFormatter::new(English).format(&roll), buffer);
+        formatted.insert(name.to_string(), json!({
+            "summary": SummaryFormatter.format(&roll),
+            "verbose": buffer,
+        }));
+    }
+    let current = serde_json::Value::Object(formatted);
+    assert_eq!(golden_file("formatted", &current), current, "golden rolls are formatted differently");
+}
+
+#[test]
+fn it_still_rolls_every_golden_roll() {
+    for name in GOLDEN_ROLLS.iter() {
+        let current = serde_json::to_value(&golden_roll(name)).unwrap();
+        assert_eq!(golden_file(name, &current), current, "golden roll {} rolls differently", name);
+    }
+
+    let current = serde_json::to_value(&golden_expression()).unwrap();
+    assert_eq!(golden_file("grouped", &current), current, "golden expression rolls differently");
+}
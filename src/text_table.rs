@@ -0,0 +1,330 @@
+use limits::Limits;
+use parser::parse;
+use roll::{Roll, RollError, RollFlags};
+use roller::Roller;
+use serde_json;
+use std::collections::HashSet;
+use table::{Table, TableEntry, TableError, TableLimits, TableResult, Tables};
+
+/// Why a placeholder couldn't be filled
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlaceholderError {
+    /// The placeholder's `{` is never closed
+    Unclosed,
+
+    /// Rolling the placeholder's dice failed
+    Roll(RollError),
+
+    /// Rolling on the placeholder's table failed, e.g. no table has its ID and it isn't dice
+    Table(TableError),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextTableError {
+    /// A table pack isn't JSON, or isn't shaped like a pack
+    Json(String),
+
+    /// More than one table of a pack has the ID
+    DuplicateTable(String),
+
+    /// The table with the ID has no entries, or more than a die can have faces
+    InvalidTable(String),
+
+    /// A placeholder in an entry of a table couldn't be filled, the innermost when they're nested
+    Placeholder {
+        placeholder: String,
+        reason: PlaceholderError,
+        table: String,
+    },
+
+    /// Rolling on the table being rendered failed, e.g. no table has its ID
+    Table(TableError),
+}
+
+impl From<TableError> for TextTableError {
+    fn from(err: TableError) -> TextTableError {
+        TextTableError::Table(err)
+    }
+}
+
+/// What a placeholder was filled with
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Filled {
+    /// The roll of the placeholder's dice, its total written in its place
+    Dice(Roll),
+
+    /// The placeholder's table, rendered in its place
+    Table(RenderedText),
+}
+
+/// A placeholder of a template and what it was filled with
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FilledPlaceholder {
+    pub filled: Filled,
+
+    /// What was between the braces, e.g. `noun` or `1d4+1`
+    pub placeholder: String,
+}
+
+/// Text rendered from a table of templates, with everything rolled to fill it in
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RenderedText {
+    /// Every placeholder of the entries rendered, in the order they were filled
+    pub placeholders: Vec<FilledPlaceholder>,
+
+    /// The roll on the table
+    pub result: TableResult,
+
+    /// The entries rendered with every placeholder filled, e.g. `Grim Crown of the 4 Hills`
+    pub text: String,
+}
+
+/// A table of a pack, each entry one face of the die it's rolled with
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PackTable {
+    /// Templates, written more than once to make them more likely
+    pub entries: Vec<String>,
+
+    pub id: String,
+}
+
+/// Tables of templates shared together, e.g. a community's fantasy names
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TablePack {
+    pub name: String,
+    pub tables: Vec<PackTable>,
+}
+
+impl TablePack {
+    /// Read a pack from JSON, e.g. `{"name": "Names", "tables": [{"id": "noun", "entries": ["Blade", "Crown"]}]}`
+    pub fn from_json(json: &str) -> Result<TablePack, TextTableError> {
+        let pack: TablePack = serde_json::from_str(json).map_err(|error| TextTableError::Json(error.to_string()))?;
+        let mut ids = HashSet::new();
+        for table in &pack.tables {
+            if !ids.insert(table.id.as_str()) {
+                return Err(TextTableError::DuplicateTable(table.id.clone()));
+            }
+            if table.entries.is_empty() || table.entries.len() > i16::max_value() as usize {
+                return Err(TextTableError::InvalidTable(table.id.clone()));
+            }
+        }
+        Ok(pack)
+    }
+
+    /// Add every table of the pack, replacing any with the same ID
+    pub fn add_to(&self, tables: &mut Tables) -> Result<(), TextTableError> {
+        for table in &self.tables {
+            let entries = table.entries.iter().enumerate().map(|(face, text)| {
+                TableEntry::new(face as i16 + 1, face as i16 + 1, text)
+            }).collect();
+            let sides = table.entries.len() as i16;
+            tables.insert(Table::new(&table.id, sides, entries).map_err(|_| TextTableError::InvalidTable(table.id.clone()))?);
+        }
+        Ok(())
+    }
+}
+
+/// Roll on a table of templates and fill in their placeholders, within the default limits
+pub fn render(tables: &Tables, id: &str, roller: &mut Roller) -> Result<RenderedText, TextTableError> {
+    render_with_limits(tables, id, &TableLimits::default(), roller)
+}
+
+/// Roll on a table of templates and fill in their placeholders, e.g. `{adjective} {noun} of the
+/// {place}` or `{1d4+1} goblins`
+///
+/// A placeholder is rendered from the table with its ID, or else rolled as dice and replaced with
+/// the total. Tables are rendered the same way however deep they're nested, each placeholder going
+/// a level deeper, so a table that fills itself in stops at `max_depth`, and every table roll
+/// counts towards `max_results`. The entries a roll turns up that don't ask for more rolls are
+/// rendered, joined with `, `.
+pub fn render_with_limits(tables: &Tables, id: &str, limits: &TableLimits, roller: &mut Roller)
+                          -> Result<RenderedText, TextTableError> {
+    let mut rendering = Rendering {
+        limits,
+        results: 0,
+        tables,
+    };
+    rendering.render(id, 0, roller)
+}
+
+struct Rendering<'a> {
+    limits: &'a TableLimits,
+    results: usize,
+    tables: &'a Tables,
+}
+
+impl<'a> Rendering<'a> {
+    fn render(&mut self, id: &str, depth: u8, roller: &mut Roller) -> Result<RenderedText, TextTableError> {
+        if depth > self.limits.max_depth {
+            return Err(TextTableError::Table(TableError::TooDeep(self.limits.max_depth)));
+        }
+        let result = self.tables.roll_with_limits(id, self.limits, roller)?;
+        self.results += result.len();
+        if self.results > self.limits.max_results {
+            return Err(TextTableError::Table(TableError::TooManyResults(self.limits.max_results)));
+        }
+
+        let mut placeholders = vec![];
+        let mut texts = vec![];
+        for entry in result.leaves() {
+            texts.push(self.fill(&entry.text, id, depth, &mut placeholders, roller)?);
+        }
+        Ok(RenderedText {
+            placeholders,
+            text: texts.join(", "),
+            result,
+        })
+    }
+
+    fn fill(&mut self, template: &str, id: &str, depth: u8, placeholders: &mut Vec<FilledPlaceholder>,
+            roller: &mut Roller) -> Result<String, TextTableError> {
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            text += &rest[..open];
+            let close = match rest[open..].find('}') {
+                Some(close) => open + close,
+                None => return Err(TextTableError::Placeholder {
+                    placeholder: rest[open + 1..].to_string(),
+                    reason: PlaceholderError::Unclosed,
+                    table: id.to_string(),
+                }),
+            };
+            let placeholder = rest[open + 1..close].trim();
+            let failed = |reason| TextTableError::Placeholder {
+                placeholder: placeholder.to_string(),
+                reason,
+                table: id.to_string(),
+            };
+
+            let filled = if self.tables.get(placeholder).is_some() {
+                match self.render(placeholder, depth + 1, roller) {
+                    Ok(rendered) => Filled::Table(rendered),
+                    Err(TextTableError::Table(err)) => return Err(failed(PlaceholderError::Table(err))),
+                    Err(err) => return Err(err),
+                }
+            } else {
+                Filled::Dice(roll_dice(placeholder).map_err(failed)
+                    .map(|flags| Roll::with_roller(flags, roller))?)
+            };
+            match filled {
+                Filled::Dice(ref roll) => text += &roll.value.to_string(),
+                Filled::Table(ref rendered) => text += &rendered.text,
+            }
+            placeholders.push(FilledPlaceholder {
+                filled,
+                placeholder: placeholder.to_string(),
+            });
+            rest = &rest[close + 1..];
+        }
+        text += rest;
+        Ok(text)
+    }
+}
+
+/// The flags of a placeholder's dice, checked against the default limits, or why it isn't dice
+fn roll_dice(placeholder: &str) -> Result<RollFlags, PlaceholderError> {
+    let flags = match parse(placeholder).and_then(|mut rolls| rolls.pop()) {
+        Some(flags) => flags,
+        None => return Err(PlaceholderError::Table(TableError::UnknownTable(placeholder.to_string()))),
+    };
+    flags.validate().map_err(PlaceholderError::Roll)?;
+    Limits::default().check(&flags).map_err(PlaceholderError::Roll)?;
+    Ok(flags)
+}
+
+#[cfg(test)]
+fn names() -> Tables {
+    let pack = TablePack::from_json(r#"{
+        "name": "Names",
+        "tables": [
+            {"id": "name", "entries": ["{adjective} {noun} of the {place}"]},
+            {"id": "adjective", "entries": ["Red", "Grim"]},
+            {"id": "noun", "entries": ["Blade", "Crown", "Hound"]},
+            {"id": "place", "entries": ["{1d4+1} Hills", "Sunken {noun}"]},
+            {"id": "band", "entries": ["{1d4+1} goblins"]}
+        ]
+    }"#).unwrap();
+    let mut tables = Tables::new();
+    pack.add_to(&mut tables).unwrap();
+    tables
+}
+
+#[test]
+fn it_renders_nested_templates() {
+    let tables = names();
+
+    // The d1 name table doesn't draw, so the values go to the adjective, noun, place and 1d4
+    let rendered = render(&tables, "name", &mut Roller::scripted(vec![2, 2, 1, 3])).unwrap();
+    assert_eq!(rendered.text, "Grim Crown of the 4 Hills");
+    let placeholders: Vec<&str> = rendered.placeholders.iter().map(|filled| filled.placeholder.as_str()).collect();
+    assert_eq!(placeholders, vec!["adjective", "noun", "place"]);
+    match rendered.placeholders[2].filled {
+        Filled::Table(ref place) => match place.placeholders[0].filled {
+            Filled::Dice(ref roll) => assert_eq!((roll.equation.as_str(), roll.value), ("1d4+1", 4)),
+            Filled::Table(_) => panic!("expected dice"),
+        },
+        Filled::Dice(_) => panic!("expected a table"),
+    }
+
+    let rendered = render(&tables, "name", &mut Roller::scripted(vec![1, 3, 2, 1])).unwrap();
+    assert_eq!(rendered.text, "Red Hound of the Sunken Blade");
+    assert_eq!(render(&tables, "band", &mut Roller::scripted(vec![4])).unwrap().text, "5 goblins");
+}
+
+#[test]
+fn it_says_which_placeholder_failed() {
+    let mut tables = names();
+    tables.insert(Table::new("title", 1, vec![TableEntry::new(1, 1, "{adjective} {ruler}")]).unwrap());
+    assert_eq!(render(&tables, "title", &mut Roller::scripted(vec![1])).err(), Some(TextTableError::Placeholder {
+        placeholder: "ruler".to_string(),
+        reason: PlaceholderError::Table(TableError::UnknownTable("ruler".to_string())),
+        table: "title".to_string(),
+    }));
+
+    // Nested failures name the innermost placeholder and its table
+    tables.insert(Table::new("place", 1, vec![TableEntry::new(1, 1, "{1d0} Hills")]).unwrap());
+    assert_eq!(render(&tables, "name", &mut Roller::scripted(vec![1, 1])).err(), Some(TextTableError::Placeholder {
+        placeholder: "1d0".to_string(),
+        reason: PlaceholderError::Roll(RollError::NoSides),
+        table: "place".to_string(),
+    }));
+
+    tables.insert(Table::new("broken", 1, vec![TableEntry::new(1, 1, "The {noun")]).unwrap());
+    assert_eq!(render(&tables, "broken", &mut Roller::new()).err(), Some(TextTableError::Placeholder {
+        placeholder: "noun".to_string(),
+        reason: PlaceholderError::Unclosed,
+        table: "broken".to_string(),
+    }));
+    assert_eq!(render(&tables, "missing", &mut Roller::new()).err(),
+               Some(TextTableError::Table(TableError::UnknownTable("missing".to_string()))));
+}
+
+#[test]
+fn it_stops_tables_that_fill_themselves_in() {
+    let mut tables = Tables::new();
+    tables.insert(Table::new("echo", 1, vec![TableEntry::new(1, 1, "echo {echo}")]).unwrap());
+    assert_eq!(render(&tables, "echo", &mut Roller::new()).err(), Some(TextTableError::Placeholder {
+        placeholder: "echo".to_string(),
+        reason: PlaceholderError::Table(TableError::TooDeep(5)),
+        table: "echo".to_string(),
+    }));
+}
+
+#[test]
+fn it_loads_packs() {
+    let tables = names();
+    assert_eq!(tables.get("noun").map(|table| table.sides), Some(3));
+
+    let duplicate = r#"{"name": "Dupes", "tables": [
+        {"id": "noun", "entries": ["Blade"]},
+        {"id": "noun", "entries": ["Crown"]}
+    ]}"#;
+    assert_eq!(TablePack::from_json(duplicate), Err(TextTableError::DuplicateTable("noun".to_string())));
+    assert_eq!(TablePack::from_json(r#"{"name": "Empty", "tables": [{"id": "noun", "entries": []}]}"#),
+               Err(TextTableError::InvalidTable("noun".to_string())));
+    match TablePack::from_json(r#"{"tables": 3}"#) {
+        Err(TextTableError::Json(_)) => {},
+        other => panic!("expected a JSON error, got {:?}", other),
+    }
+}
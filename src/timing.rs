@@ -0,0 +1,112 @@
+use std::fmt;
+use std::time::Instant;
+#[cfg(test)]
+use roll::Roll;
+#[cfg(test)]
+use roller::{RollOptions, Roller};
+#[cfg(test)]
+use parser::parse;
+
+/// Where the time went making a roll, in microseconds, see `RollOptions::capture_timing`
+///
+/// The parts are timed one after another, so together they never come to more than the total.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct RollTiming {
+    /// Exploding dice, every chain of explosions included
+    pub explosions_us: u64,
+
+    /// Parsing the notation, none when the roll wasn't parsed by whatever timed it
+    pub parse_us: Option<u64>,
+
+    /// Rerolling dice, once or until they stop matching
+    pub rerolls_us: u64,
+
+    /// Building the dice and rolling each of them once
+    pub rolling_us: u64,
+
+    /// Keeping and dropping dice and working out the total
+    pub selection_us: u64,
+
+    /// Wall time from the first part to the last, and whatever came between them
+    pub total_us: u64,
+}
+
+impl RollTiming {
+    /// Sum of the timed parts
+    pub fn parts_us(&self) -> u64 {
+        self.parse_us.unwrap_or(0) + self.rolling_us + self.rerolls_us + self.explosions_us + self.selection_us
+    }
+}
+
+/// Written as a breakdown for logs, e.g. `2140us in all: 12us parsing, 30us rolling, ...`
+impl fmt::Display for RollTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}us in all: ", self.total_us)?;
+        if let Some(parse_us) = self.parse_us {
+            write!(f, "{}us parsing, ", parse_us)?;
+        }
+        write!(f, "{}us rolling, {}us rerolling, {}us exploding, {}us selecting",
+               self.rolling_us, self.rerolls_us, self.explosions_us, self.selection_us)
+    }
+}
+
+/// Laps of a clock that's only read when it's running, so timing costs nothing when it's off
+pub struct Stopwatch {
+    lap: Option<Instant>,
+    start: Option<Instant>,
+}
+
+impl Stopwatch {
+    pub fn start(is_running: bool) -> Stopwatch {
+        let now = if is_running { Some(Instant::now()) } else { None };
+        Stopwatch {
+            lap: now,
+            start: now,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.start.is_some()
+    }
+
+    /// Microseconds since the last lap, or since starting, 0 when it isn't running
+    pub fn lap(&mut self) -> u64 {
+        match self.lap {
+            Some(lap) => {
+                let now = Instant::now();
+                self.lap = Some(now);
+                now.duration_since(lap).as_micros() as u64
+            },
+            None => 0,
+        }
+    }
+
+    /// Microseconds since starting, 0 when it isn't running
+    pub fn total(&self) -> u64 {
+        self.start.map_or(0, |start| start.elapsed().as_micros() as u64)
+    }
+}
+
+#[test]
+fn it_times_a_roll_when_asked() {
+    let mut flags = parse("6d6rr1").unwrap().pop().unwrap();
+    flags.explode = true;
+    let values = vec![1, 6, 6, 3, 2, 6, 4, 5, 2, 6, 1, 3, 4];
+    let options = RollOptions::new().capture_timing();
+    let roll = Roll::with_roller(flags.clone(), &mut Roller::scripted(values.clone()).with_options(&options));
+
+    let timing = roll.timing.clone().expect("the roll should be timed");
+    assert_eq!(timing.parse_us, None);
+    assert!(timing.parts_us() <= timing.total_us);
+    assert!(timing.total_us >= timing.explosions_us && timing.total_us >= timing.rolling_us);
+    let json = ::serde_json::to_value(&roll).unwrap();
+    assert_eq!(json["timing"]["total_us"], timing.total_us);
+
+    // Rolls aren't timed, or serialized with a timing, unless they're asked to be
+    let roll = Roll::with_roller(flags, &mut Roller::scripted(values));
+    assert!(roll.timing.is_none());
+    assert!(::serde_json::to_value(&roll).unwrap().get("timing").is_none());
+
+    let mut stopwatch = Stopwatch::start(false);
+    assert_eq!((stopwatch.lap(), stopwatch.total(), stopwatch.is_running()), (0, 0, false));
+}
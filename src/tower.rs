@@ -0,0 +1,233 @@
+use roll::{Roll, RollError};
+use roller::Roller;
+#[cfg(test)]
+use fairness::{verify_roll, SeedRecipe};
+#[cfg(test)]
+use serde_json;
+
+/// Position of a roll in a dice tower's queue, also the nonce its seed is derived from
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct QueuedId(pub u64);
+
+#[derive(Debug, PartialEq)]
+pub enum TowerError {
+    /// The roll was cancelled, so it will never be resolved
+    Cancelled(QueuedId),
+
+    /// No roll was queued with the ID
+    NotFound(QueuedId),
+
+    /// The roll was already resolved, so it can't be cancelled
+    Resolved(QueuedId),
+
+    /// The request can't be rolled
+    Roll(RollError),
+}
+
+impl From<RollError> for TowerError {
+    fn from(error: RollError) -> TowerError {
+        TowerError::Roll(error)
+    }
+}
+
+/// Where a queued roll is at
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum QueuedState {
+    /// Waiting for the tower to be tipped
+    Waiting,
+
+    /// Rolled, kept so resolving it again hands back the same roll
+    Resolved(Roll),
+
+    /// Taken out of the queue before it was rolled
+    Cancelled,
+}
+
+/// A roll request waiting in a dice tower
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QueuedRoll {
+    pub id: QueuedId,
+
+    /// The notation that was queued, checked against the default limits when it was queued
+    pub request: String,
+
+    pub state: QueuedState,
+}
+
+/// Rolls queued up front and only rolled once the tower is tipped, e.g. for play by post
+///
+/// With a roller whose seeds are derived, each roll is made with the seed of its place in the
+/// queue, so the queue alone decides every outcome. Rolls resolve the same whatever order they're
+/// resolved in, and cancelling one doesn't change the dice of those after it. Other rollers hand
+/// out their values in the order rolls are resolved. A tower can be serialized part way through
+/// and picked up again later.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DiceTower {
+    rolls: Vec<QueuedRoll>,
+}
+
+impl DiceTower {
+    pub fn new() -> DiceTower {
+        DiceTower::default()
+    }
+
+    /// Every roll queued so far, in queue order
+    pub fn rolls(&self) -> &[QueuedRoll] {
+        &self.rolls
+    }
+
+    pub fn get(&self, id: QueuedId) -> Option<&QueuedRoll> {
+        self.rolls.get(id.0 as usize)
+    }
+
+    /// Check the request and add it to the end of the queue, without rolling anything
+    pub fn queue(&mut self, request: &str) -> Result<QueuedId, TowerError> {
+        ::checked_flags(request)?;
+        let id = QueuedId(self.rolls.len() as u64);
+        self.rolls.push(QueuedRoll {
+            id,
+            request: request.to_string(),
+            state: QueuedState::Waiting,
+        });
+        Ok(id)
+    }
+
+    /// Take a waiting roll out of the queue, the rolls after it keep their places
+    pub fn cancel(&mut self, id: QueuedId) -> Result<(), TowerError> {
+        let queued = self.rolls.get_mut(id.0 as usize).ok_or(TowerError::NotFound(id))?;
+        match queued.state {
+            QueuedState::Resolved(_) => Err(TowerError::Resolved(id)),
+            _ => {
+                queued.state = QueuedState::Cancelled;
+                Ok(())
+            },
+        }
+    }
+
+    /// Roll a queued roll, or hand back its roll if it was already resolved
+    pub fn resolve(&mut self, id: QueuedId, roller: &mut Roller) -> Result<&Roll, TowerError> {
+        let queued = self.rolls.get_mut(id.0 as usize).ok_or(TowerError::NotFound(id))?;
+        if let QueuedState::Waiting = queued.state {
+            let flags = ::checked_flags(&queued.request)?;
+            roller.seek(id.0);
+            queued.state = QueuedState::Resolved(Roll::with_roller(flags, roller));
+        }
+
+        match queued.state {
+            QueuedState::Resolved(ref roll) => Ok(roll),
+            QueuedState::Cancelled => Err(TowerError::Cancelled(id)),
+            QueuedState::Waiting => unreachable!(),
+        }
+    }
+
+    /// Tip the tower, rolling every waiting roll in queue order and skipping cancelled ones
+    pub fn resolve_all(&mut self, roller: &mut Roller) -> Result<Vec<&Roll>, TowerError> {
+        for i in 0..self.rolls.len() {
+            if let QueuedState::Waiting = self.rolls[i].state {
+                self.resolve(QueuedId(i as u64), roller)?;
+            }
+        }
+
+        Ok(self.rolls.iter().filter_map(|queued| match queued.state {
+            QueuedState::Resolved(ref roll) => Some(roll),
+            _ => None,
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+fn tower_roller() -> Roller {
+    Roller::derived(SeedRecipe {
+        server: [7u8; 32],
+        client: "table-seed".to_string(),
+        nonce: 0,
+    })
+}
+
+#[cfg(test)]
+fn values(roll: &Roll) -> Vec<i16> {
+    roll.dice.iter().map(|die| die.value).collect()
+}
+
+#[test]
+fn it_rolls_each_queued_roll_with_the_seed_of_its_place() {
+    use parser::parse;
+
+    let mut tower = DiceTower::new();
+    let attack = tower.queue("1d20+5").unwrap();
+    let damage = tower.queue("2d6+3").unwrap();
+    assert!(tower.rolls().iter().all(|queued| match queued.state {
+        QueuedState::Waiting => true,
+        _ => false,
+    }));
+
+    let rolls = tower.resolve_all(&mut tower_roller()).unwrap();
+    assert_eq!(rolls.len(), 2);
+    assert_eq!(rolls[0].nonce, Some(attack.0));
+    assert_eq!(rolls[1].nonce, Some(damage.0));
+
+    // Anyone with the seeds can replay each roll from its place in the queue
+    let flags = parse("2d6+3").unwrap().pop().unwrap();
+    assert!(verify_roll([7u8; 32], "table-seed", flags, rolls[1]));
+
+    // Resolving out of order, or again, gives the same dice
+    let mut reversed = DiceTower::new();
+    reversed.queue("1d20+5").unwrap();
+    reversed.queue("2d6+3").unwrap();
+    let mut roller = tower_roller();
+    let damage_first = values(reversed.resolve(damage, &mut roller).unwrap());
+    assert_eq!(damage_first, values(rolls[1]));
+    assert_eq!(values(reversed.resolve(attack, &mut roller).unwrap()), values(rolls[0]));
+    assert_eq!(values(reversed.resolve(damage, &mut roller).unwrap()), damage_first);
+}
+
+#[test]
+fn it_cancels_without_shifting_later_rolls() {
+    let mut full = DiceTower::new();
+    let mut cancelled = DiceTower::new();
+    for request in &["1d20", "8d6", "4d8+2"] {
+        full.queue(request).unwrap();
+        cancelled.queue(request).unwrap();
+    }
+    cancelled.cancel(QueuedId(1)).unwrap();
+
+    let full = full.resolve_all(&mut tower_roller()).unwrap().iter().map(|roll| values(roll)).collect::<Vec<_>>();
+    let rolls = cancelled.resolve_all(&mut tower_roller()).unwrap().iter().map(|roll| values(roll)).collect::<Vec<_>>();
+    assert_eq!(rolls, vec![full[0].clone(), full[2].clone()]);
+
+    assert_eq!(cancelled.resolve(QueuedId(1), &mut tower_roller()).err(), Some(TowerError::Cancelled(QueuedId(1))));
+    assert_eq!(cancelled.cancel(QueuedId(2)), Err(TowerError::Resolved(QueuedId(2))));
+    assert_eq!(cancelled.cancel(QueuedId(3)), Err(TowerError::NotFound(QueuedId(3))));
+}
+
+#[test]
+fn it_checks_requests_when_they_are_queued() {
+    let mut tower = DiceTower::new();
+    assert_eq!(tower.queue("1000d6").err(), Some(TowerError::Roll(RollError::TooManyDice(1000, 100))));
+    assert!(tower.rolls().is_empty());
+}
+
+#[test]
+fn it_picks_up_a_saved_tower_where_it_left_off() {
+    let mut tower = DiceTower::new();
+    for request in &["1d20", "3d6", "1d12+1"] {
+        tower.queue(request).unwrap();
+    }
+    let first = values(tower.resolve(QueuedId(0), &mut tower_roller()).unwrap());
+
+    let saved = serde_json::to_string(&tower).unwrap();
+    let mut loaded: DiceTower = serde_json::from_str(&saved).unwrap();
+    let rolls = loaded.resolve_all(&mut tower_roller()).unwrap();
+    assert_eq!(values(rolls[0]), first);
+    assert_eq!(rolls[0].id, tower.get(QueuedId(0)).map(|queued| match queued.state {
+        QueuedState::Resolved(ref roll) => roll.id.clone(),
+        _ => String::new(),
+    }).unwrap());
+
+    let mut fresh = DiceTower::new();
+    for request in &["1d20", "3d6", "1d12+1"] {
+        fresh.queue(request).unwrap();
+    }
+    let expected: Vec<Vec<i16>> = fresh.resolve_all(&mut tower_roller()).unwrap().iter().map(|roll| values(roll)).collect();
+    assert_eq!(rolls.iter().map(|roll| values(roll)).collect::<Vec<_>>(), expected);
+}
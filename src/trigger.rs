@@ -0,0 +1,407 @@
+use limits::Limits;
+use parser::parse;
+use roll::{Roll, RollError, RollFlags};
+use roller::Roller;
+use table::{TableError, TableResult, Tables};
+#[cfg(test)]
+use table::{Table, TableEntry};
+
+/// A condition on how a roll came out, e.g. a natural 1, combined with `All`, `Any` and `Not`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum RollPredicate {
+    /// The kept dice came to the number, before modifiers
+    NaturalEquals(i32),
+    NaturalAtLeast(i32),
+    NaturalAtMost(i32),
+
+    /// The roll's value came to the number or higher, modifiers included
+    TotalAtLeast(i32),
+    TotalAtMost(i32),
+
+    /// At least this many kept dice were successes
+    SuccessesAtLeast(usize),
+
+    /// A kept die has the tag, e.g. `fire` in `1d20+2d6[fire]`
+    HasTag(String),
+
+    All(Vec<RollPredicate>),
+    Any(Vec<RollPredicate>),
+    Not(Box<RollPredicate>),
+}
+
+impl RollPredicate {
+    pub fn holds(&self, roll: &Roll) -> bool {
+        match *self {
+            RollPredicate::NaturalEquals(value) => roll.natural_value == value,
+            RollPredicate::NaturalAtLeast(value) => roll.natural_value >= value,
+            RollPredicate::NaturalAtMost(value) => roll.natural_value <= value,
+            RollPredicate::TotalAtLeast(value) => roll.value >= value,
+            RollPredicate::TotalAtMost(value) => roll.value <= value,
+            RollPredicate::SuccessesAtLeast(successes) => roll.successes() >= successes,
+            RollPredicate::HasTag(ref tag) => roll.dice.iter().any(|die| !die.is_dropped && die.has_tag(tag)),
+            RollPredicate::All(ref predicates) => predicates.iter().all(|predicate| predicate.holds(roll)),
+            RollPredicate::Any(ref predicates) => predicates.iter().any(|predicate| predicate.holds(roll)),
+            RollPredicate::Not(ref predicate) => !predicate.holds(roll),
+        }
+    }
+}
+
+/// What's done when a trigger's predicate holds
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TriggerAction {
+    /// Roll the notation, e.g. `2d6[fire]` for extra damage on a crit, which can set off triggers
+    /// of its own
+    Roll(String),
+
+    /// Roll on the table with this ID, e.g. a fumble table
+    RollOn(String),
+
+    /// Label the roll, e.g. "Critical hit!", without rolling anything
+    Label(String),
+}
+
+/// A predicate checked against every roll made through `Triggers`, and what to do when it holds
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Trigger {
+    pub predicate: RollPredicate,
+    pub action: TriggerAction,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TriggerError {
+    /// A trigger's notation doesn't parse
+    InvalidNotation(String),
+
+    Roll(RollError),
+    Table(TableError),
+
+    /// More dice (first) were rolled by a roll and its triggers together than a roll can start
+    /// with (second)
+    TooManyDice(usize, i16),
+}
+
+impl From<RollError> for TriggerError {
+    fn from(error: RollError) -> TriggerError {
+        TriggerError::Roll(error)
+    }
+}
+
+impl From<TableError> for TriggerError {
+    fn from(error: TableError) -> TriggerError {
+        TriggerError::Table(error)
+    }
+}
+
+/// What a fired trigger did
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Triggered {
+    Roll(Box<TriggeredRoll>),
+    Table(TableResult),
+    Label(String),
+}
+
+/// A trigger that fired on a roll
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Fired {
+    /// Index of the trigger, in the order triggers were registered
+    pub trigger: usize,
+
+    /// ID of the roll the trigger fired on
+    pub triggered_by: String,
+
+    pub triggered: Triggered,
+}
+
+/// A roll and every trigger that fired on it, with what those triggers rolled in turn
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TriggeredRoll {
+    /// Triggers that fired, in the order they were registered
+    pub fired: Vec<Fired>,
+
+    /// If triggers that roll would have fired but the chain was already as deep as it can go
+    pub is_capped: bool,
+
+    pub roll: Roll,
+}
+
+impl TriggeredRoll {
+    /// Every roll made, this one first and then those of each fired trigger in turn
+    pub fn rolls(&self) -> Vec<&Roll> {
+        let mut rolls = vec![&self.roll];
+        for fired in &self.fired {
+            if let Triggered::Roll(ref triggered) = fired.triggered {
+                rolls.extend(triggered.rolls());
+            }
+        }
+        rolls
+    }
+
+    /// Labels of every fired trigger, this roll's first
+    pub fn labels(&self) -> Vec<&str> {
+        let mut labels = vec![];
+        for fired in &self.fired {
+            match fired.triggered {
+                Triggered::Label(ref label) => labels.push(label.as_str()),
+                Triggered::Roll(ref triggered) => labels.extend(triggered.labels()),
+                Triggered::Table(_) => {},
+            }
+        }
+        labels
+    }
+}
+
+/// Triggers checked against rolls, and the tables they can roll on
+///
+/// Rolls made by triggers are checked against the triggers too, so chains like a crit rolling
+/// extra damage that crits again are followed, as deep as `max_depth`. The depth is set by
+/// whoever runs the triggers, never read with them.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Triggers {
+    /// Most rolls triggers can make one after another in a chain, not counting the roll it started from
+    #[serde(skip, default = "default_max_depth")]
+    max_depth: u8,
+
+    pub tables: Tables,
+    triggers: Vec<Trigger>,
+}
+
+impl Default for Triggers {
+    fn default() -> Triggers {
+        Triggers {
+            max_depth: default_max_depth(),
+            tables: Tables::new(),
+            triggers: vec![],
+        }
+    }
+}
+
+impl Triggers {
+    pub fn new() -> Triggers {
+        Triggers::default()
+    }
+
+    pub fn with_tables(mut self, tables: Tables) -> Triggers {
+        self.tables = tables;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: u8) -> Triggers {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Add a trigger, checking its notation can be rolled first
+    pub fn register(&mut self, trigger: Trigger) -> Result<(), TriggerError> {
+        if let TriggerAction::Roll(ref notation) = trigger.action {
+            flags_for(notation)?;
+        }
+        self.triggers.push(trigger);
+        Ok(())
+    }
+
+    pub fn triggers(&self) -> &Vec<Trigger> {
+        &self.triggers
+    }
+
+    pub fn max_depth(&self) -> u8 {
+        self.max_depth
+    }
+
+    /// Roll the flags, then fire every trigger whose predicate holds
+    ///
+    /// The dice of the roll and of everything its triggers roll count against `limits.max_dice`
+    /// together, counting one die per table roll, so a chain of triggers fails rather than
+    /// rolling without end.
+    pub fn roll(&self, flags: RollFlags, limits: &Limits, roller: &mut Roller) -> Result<TriggeredRoll, TriggerError> {
+        flags.validate()?;
+        limits.check(&flags)?;
+        let roll = Roll::with_roller(flags, roller);
+        self.fire_on(roll, limits, roller)
+    }
+
+    /// Fire every trigger whose predicate holds on a roll already made, its dice counting against
+    /// the limits the same as those its triggers roll
+    pub fn fire_on(&self, roll: Roll, limits: &Limits, roller: &mut Roller) -> Result<TriggeredRoll, TriggerError> {
+        let mut firing = Firing {
+            dice: 0,
+            limits,
+            triggers: self,
+        };
+        firing.add_dice(roll.dice.len())?;
+        firing.fire(roll, 0, roller)
+    }
+}
+
+struct Firing<'a> {
+    dice: usize,
+    limits: &'a Limits,
+    triggers: &'a Triggers,
+}
+
+impl<'a> Firing<'a> {
+    fn fire(&mut self, roll: Roll, depth: u8, roller: &mut Roller) -> Result<TriggeredRoll, TriggerError> {
+        let mut result = TriggeredRoll {
+            fired: vec![],
+            is_capped: false,
+            roll,
+        };
+        for (index, trigger) in self.triggers.triggers.iter().enumerate() {
+            if !trigger.predicate.holds(&result.roll) {
+                continue;
+            }
+
+            let triggered = match trigger.action {
+                TriggerAction::Label(ref label) => Triggered::Label(label.clone()),
+                _ if depth >= self.triggers.max_depth => {
+                    result.is_capped = true;
+                    continue;
+                },
+                TriggerAction::Roll(ref notation) => {
+                    let flags = flags_for(notation)?;
+                    self.limits.check(&flags)?;
+                    let roll = Roll::with_roller(flags, roller);
+                    self.add_dice(roll.dice.len())?;
+                    Triggered::Roll(Box::new(self.fire(roll, depth + 1, roller)?))
+                },
+                TriggerAction::RollOn(ref table) => {
+                    let result = self.triggers.tables.roll(table, roller)?;
+                    self.add_dice(result.len())?;
+                    Triggered::Table(result)
+                },
+            };
+            result.fired.push(Fired {
+                trigger: index,
+                triggered_by: result.roll.id.clone(),
+                triggered,
+            });
+        }
+        Ok(result)
+    }
+
+    fn add_dice(&mut self, dice: usize) -> Result<(), TriggerError> {
+        self.dice += dice;
+        if self.dice > self.limits.max_dice as usize {
+            return Err(TriggerError::TooManyDice(self.dice, self.limits.max_dice));
+        }
+        Ok(())
+    }
+}
+
+fn default_max_depth() -> u8 {
+    3
+}
+
+/// The last roll in the notation, the one that's rolled, checked it can be
+fn flags_for(notation: &str) -> Result<RollFlags, TriggerError> {
+    let flags = parse(notation)
+        .and_then(|mut rolls| rolls.pop())
+        .ok_or_else(|| TriggerError::InvalidNotation(notation.to_string()))?;
+    flags.validate()?;
+    Ok(flags)
+}
+
+#[test]
+fn it_rolls_on_the_fumble_table_on_a_natural_one() {
+    let mut tables = Tables::new();
+    tables.insert(Table::new("fumble", 6, vec![
+        TableEntry::new(1, 3, "Drop your weapon"),
+        TableEntry::new(4, 6, "Hit an ally"),
+    ]).unwrap());
+    let mut triggers = Triggers::new().with_tables(tables);
+    triggers.register(Trigger {
+        predicate: RollPredicate::NaturalEquals(1),
+        action: TriggerAction::RollOn("fumble".to_string()),
+    }).unwrap();
+
+    let flags = parse("1d20+3").unwrap().pop().unwrap();
+    let result = triggers.roll(flags, &Limits::default(), &mut Roller::scripted(vec![1, 5])).unwrap();
+    assert_eq!(result.roll.value, 4);
+    assert_eq!(result.fired.len(), 1);
+    assert_eq!(result.fired[0].triggered_by, result.roll.id);
+    match result.fired[0].triggered {
+        Triggered::Table(ref table) => assert_eq!(table.entry.text, "Hit an ally"),
+        ref other => panic!("expected a table roll, got {:?}", other),
+    }
+    assert!(!result.is_capped);
+
+    let json = ::serde_json::to_value(&result).unwrap();
+    assert_eq!(json["fired"][0]["triggered"]["Table"]["table"], "fumble");
+}
+
+#[test]
+fn it_labels_rolls_matching_combined_predicates() {
+    let mut triggers = Triggers::new();
+    triggers.register(Trigger {
+        predicate: RollPredicate::All(vec![RollPredicate::NaturalEquals(1),
+                                           RollPredicate::Not(Box::new(RollPredicate::TotalAtLeast(5)))]),
+        action: TriggerAction::Label("Fumble!".to_string()),
+    }).unwrap();
+
+    let flags = parse("1d20+3").unwrap().pop().unwrap();
+    let result = triggers.roll(flags.clone(), &Limits::default(), &mut Roller::scripted(vec![1])).unwrap();
+    assert_eq!(result.labels(), vec!["Fumble!"]);
+
+    // A 2 isn't a natural 1, so nothing fires
+    let result = triggers.roll(flags, &Limits::default(), &mut Roller::scripted(vec![2])).unwrap();
+    assert!(result.fired.is_empty());
+    assert_eq!(result.rolls().len(), 1);
+}
+
+#[test]
+fn it_turns_away_triggers_with_bad_notation() {
+    let bad = Trigger {
+        predicate: RollPredicate::NaturalEquals(20),
+        action: TriggerAction::Roll("4dQ".to_string()),
+    };
+    assert_eq!(Triggers::new().register(bad), Err(TriggerError::InvalidNotation("4dQ".to_string())));
+}
+
+#[test]
+fn it_caps_a_trigger_that_triggers_itself() {
+    let mut triggers = Triggers::new().with_max_depth(2);
+    triggers.register(Trigger {
+        predicate: RollPredicate::NaturalAtLeast(1),
+        action: TriggerAction::Roll("1d6".to_string()),
+    }).unwrap();
+    let flags = parse("1d6").unwrap().pop().unwrap();
+    let result = triggers.roll(flags, &Limits::default(), &mut Roller::scripted(vec![4, 5, 6])).unwrap();
+
+    let values: Vec<i32> = result.rolls().iter().map(|roll| roll.value).collect();
+    assert_eq!(values, vec![4, 5, 6]);
+    let first = match result.fired[0].triggered {
+        Triggered::Roll(ref triggered) => triggered,
+        ref other => panic!("expected a roll, got {:?}", other),
+    };
+    assert_eq!(result.fired[0].triggered_by, result.roll.id);
+    assert!(!result.is_capped && !first.is_capped);
+    match first.fired[0].triggered {
+        Triggered::Roll(ref last) => assert!(last.is_capped && last.fired.is_empty()),
+        ref other => panic!("expected a roll, got {:?}", other),
+    }
+}
+
+#[test]
+fn it_limits_dice_rolled_by_every_trigger_together() {
+    // Five triggers firing on every roll would make 155 rolls of 10d6 three deep
+    let mut triggers = Triggers::new();
+    for _ in 0..5 {
+        triggers.register(Trigger {
+            predicate: RollPredicate::NaturalAtLeast(1),
+            action: TriggerAction::Roll("10d6".to_string()),
+        }).unwrap();
+    }
+    let flags = parse("1d6").unwrap().pop().unwrap();
+
+    // The d6 and nine rolls of 10d6 fit, the tenth doesn't
+    assert_eq!(triggers.roll(flags, &Limits::default(), &mut Roller::new()).err(),
+               Some(TriggerError::TooManyDice(101, 100)));
+}
+
+#[test]
+fn it_keeps_the_depth_out_of_json() {
+    let json = ::serde_json::to_value(&Triggers::new().with_max_depth(1)).unwrap();
+    assert!(json.get("max_depth").is_none());
+
+    let read: Triggers = ::serde_json::from_str(r#"{"max_depth": 200, "tables": {"tables": {}}, "triggers": []}"#).unwrap();
+    assert_eq!(read.max_depth(), 3);
+}
@@ -1,198 +1,732 @@
-use die::*;
-use rocket_contrib::{Json, Value};
+use config::Config;
+use error::{ApiError, ErrorCode};
+use house_rules::{HouseRules, RoomRules};
+use history::{HiddenRollReceipt, HistoryError, HistoryPage, HistoryQuery, RollStore};
+use parser::{parse_notation, parse_notation_with, ParseOptions};
+use rate_limit::{bearer_token, hash_token, LightThrottle, RateLimits, Throttle};
+use rocket::{Outcome, Request, Response, State};
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest};
+#[cfg(test)]
+use rocket::local::{Client, LocalResponse};
+use rocket::response::{self, Responder, Stream};
+use rocket::response::content::Content;
+use rocket::response::status::Custom;
+use limits::Limits;
+use lint::LintOptions;
+use metrics::Metrics;
+use ndjson::NdjsonReader;
+use rocket_contrib::Json;
 use roll::*;
-use std::time::Instant;
-use ttml::arg::{Arg, ArgValue, ComparisonArg, RollArg};
-use ttml::parser::parse_step_p;
+use roller::{DiceRoller, RollOptions, Roller};
+use stats::{combined_distribution_with, CachedDistribution, Distribution, DistributionCache, SIMULATION_TRIALS};
+use validate::{validate_notation, validate_notation_with, ValidationReport, VariableSchema};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use webhook::{Webhook, WebhookStatus, Webhooks};
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct RollsResponse {
     pub roll: Roll,
     pub execution_time: u64,
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct StatsResponse {
+    pub distribution: Distribution,
+    pub equation: String,
+    pub execution_time: u64,
+    pub mean: f64,
+    pub was_cached: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CombinedStatsRequest {
+    pub expressions: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ValidateRequest {
+    pub notation: String,
+
+    /// The variables the notation can use, any when there are none
+    pub variables: Option<VariableSchema>,
+
+    /// The lint rules to skip, none by default
+    pub lint: Option<LintOptions>,
+}
+
+#[derive(Serialize)]
+pub struct CombinedStatsResponse {
+    pub distribution: Distribution,
+    pub execution_time: u64,
+    pub mean: f64,
+}
+
+/// Filters of a history export, the same as a page of history's with the choice of compact rolls
+#[derive(Debug, Default, FromForm)]
+pub struct ExportQuery {
+    pub before: Option<String>,
+
+    /// Write each roll as an `ndjson::CompactRoll`
+    pub compact: Option<bool>,
+
+    pub cursor: Option<String>,
+
+    /// Most rolls to export, none for every roll
+    pub limit: Option<usize>,
+
+    pub notation_contains: Option<String>,
+    pub participant: Option<String>,
+}
+
+impl ExportQuery {
+    fn history(self) -> HistoryQuery {
+        HistoryQuery {
+            before: self.before,
+            cursor: self.cursor,
+            limit: self.limit,
+            notation_contains: self.notation_contains,
+            participant: self.participant,
+        }
+    }
+}
+
+/// A 429 with a Retry-After header saying how many seconds to wait
+pub struct TooManyRequests(pub u64);
+
+impl<'r> Responder<'r> for TooManyRequests {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build()
+            .merge(Json(ApiError::rate_limited(self.0)).respond_to(request)?)
+            .status(Status::TooManyRequests)
+            .raw_header("Retry-After", self.0.to_string())
+            .ok()
+    }
+}
+
 #[error(404)]
-fn not_found() -> Json<Value> {
-    Json(json!({
-        "status": "error",
-        "reason": "Resource not found."
-    }))
+pub fn not_found() -> Json<ApiError> {
+    Json(ApiError::not_found())
+}
+
+#[error(429)]
+pub fn too_many_requests(request: &Request) -> TooManyRequests {
+    let retry_after = match request.guard::<State<RateLimits>>().succeeded() {
+        Some(limits) => limits.take_retry_after(request),
+        None => None,
+    };
+    TooManyRequests(retry_after.unwrap_or(1))
+}
+
+/// The hash of the API token a request was sent with, for routes that need one
+pub struct BearerToken(String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for BearerToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<BearerToken, ()> {
+        match bearer_token(request) {
+            Some(token) => Outcome::Success(BearerToken(hash_token(token))),
+            None => Outcome::Forward(()),
+        }
+    }
 }
 
 #[get("/<command>", format = "application/json")]
-pub fn roll(command: String) -> Option<Json<RollsResponse>> {
+pub fn roll(command: String, config: State<Config>, metrics: Option<State<Metrics>>, roller: State<Box<dyn DiceRoller>>, _throttle: Throttle) -> Result<Json<RollsResponse>, Custom<Json<ApiError>>> {
+    roll_command(&command, &config, &**roller, &HouseRules::default(), metrics.as_ref().map(|metrics| metrics.inner()))
+}
+
+/// Roll for a participant in a room, adding the roll to the room's history
+///
+/// Ranked after looking up a roll by its number, which a participant named `rolls` would collide with
+#[get("/rooms/<room>/<participant>/<command>", format = "application/json", rank = 2)]
+pub fn room_roll(room: String, participant: String, command: String, config: State<Config>, log: State<Box<dyn RollStore>>, metrics: Option<State<Metrics>>, roller: State<Box<dyn DiceRoller>>, rules: State<RoomRules>, webhooks: State<Webhooks>, _throttle: Throttle) -> Result<Json<RollsResponse>, Custom<Json<ApiError>>> {
+    let metrics = metrics.as_ref().map(|metrics| metrics.inner());
+    if let Some(metrics) = metrics {
+        metrics.record_room(&room);
+    }
+    let mut response = roll_command(&command, &config, &**roller, &rules.get(&room), metrics)?.into_inner();
+    response.roll.sequence = log.record(&room, &participant, response.roll.clone()).map_err(history_error)?;
+    webhooks.notify(&room, &response.roll);
+    Ok(Json(response))
+}
+
+/// Roll for a participant in a room, keeping the roll out of the room's history until it's revealed
+#[get("/rooms/<room>/hidden/<participant>/<command>", format = "application/json")]
+pub fn room_roll_hidden(room: String, participant: String, command: String, config: State<Config>, log: State<Box<dyn RollStore>>, metrics: Option<State<Metrics>>, roller: State<Box<dyn DiceRoller>>, rules: State<RoomRules>, _throttle: Throttle) -> Result<Json<HiddenRollReceipt>, Custom<Json<ApiError>>> {
+    let metrics = metrics.as_ref().map(|metrics| metrics.inner());
+    if let Some(metrics) = metrics {
+        metrics.record_room(&room);
+    }
+    let response = roll_command(&command, &config, &**roller, &rules.get(&room), metrics)?;
+    log.record_hidden(&room, &participant, response.into_inner().roll).map(Json).map_err(history_error)
+}
+
+/// Play by the house rules in a room from now on, in place of any it had
+///
+/// Only the room's owner can, the API token that first set its rules.
+#[put("/rooms/<room>/rules", format = "application/json", data = "<rules>")]
+pub fn set_room_rules(room: String, rules: Json<HouseRules>, token: Option<BearerToken>, rooms: State<RoomRules>) -> Result<Json<HouseRules>, Custom<Json<ApiError>>> {
+    let token = token.ok_or_else(|| error_response(Status::Unauthorized, ApiError::not_room_owner()))?;
+    let rules = rules.into_inner();
+    rooms.set_as_owner(&room, &token.0, rules.clone())
+        .map_err(|_| error_response(Status::Forbidden, ApiError::not_room_owner()))?;
+    Ok(Json(rules))
+}
+
+#[get("/rooms/<room>/rules", format = "application/json")]
+pub fn room_rules(room: String, rooms: State<RoomRules>) -> Json<HouseRules> {
+    Json(rooms.get(&room))
+}
+
+#[get("/rooms/<room>/rolls/<id>/reveal", format = "application/json")]
+pub fn room_reveal(room: String, id: String, log: State<Box<dyn RollStore>>) -> Result<Json<Roll>, Custom<Json<ApiError>>> {
+    log.reveal(&room, &id).map(Json).map_err(history_error)
+}
+
+/// A roll in a room by its number, e.g. 47 for "roll #47"
+#[get("/rooms/<room>/rolls/<sequence>", format = "application/json", rank = 1)]
+pub fn room_roll_by_sequence(room: String, sequence: u64, log: State<Box<dyn RollStore>>) -> Result<Json<Roll>, Custom<Json<ApiError>>> {
+    log.by_sequence(&room, sequence).map(Json).map_err(history_error)
+}
+
+#[get("/rooms/<room>/rolls?<query>", format = "application/json")]
+pub fn room_history(room: String, query: HistoryQuery, log: State<Box<dyn RollStore>>) -> Result<Json<HistoryPage>, Custom<Json<ApiError>>> {
+    history(&room, &query, &**log)
+}
+
+#[get("/rooms/<room>/rolls", format = "application/json", rank = 2)]
+pub fn room_history_latest(room: String, log: State<Box<dyn RollStore>>) -> Result<Json<HistoryPage>, Custom<Json<ApiError>>> {
+    history(&room, &HistoryQuery::default(), &**log)
+}
+
+// Both export routes come before a roll for a participant named rolls, and the one with filters before
+// the one without, which would match any query
+#[get("/rooms/<room>/rolls/export?<query>", rank = -1)]
+pub fn room_export<'r>(room: String, query: ExportQuery, log: State<'r, Box<dyn RollStore>>) -> Result<Content<Stream<NdjsonReader<'r>>>, Custom<Json<ApiError>>> {
+    export(&room, query, &**log.inner())
+}
+
+#[get("/rooms/<room>/rolls/export", rank = 0)]
+pub fn room_export_all<'r>(room: String, log: State<'r, Box<dyn RollStore>>) -> Result<Content<Stream<NdjsonReader<'r>>>, Custom<Json<ApiError>>> {
+    export(&room, ExportQuery::default(), &**log.inner())
+}
+
+/// Stream a room's history as newline-delimited JSON, newest first
+///
+/// The filters are checked against the first page before anything is sent, so a room or
+/// participant that isn't there still gets an error. Rolls are read from the store as the client
+/// takes them, and reading stops when the client goes.
+fn export<'r>(room: &str, query: ExportQuery, log: &'r dyn RollStore) -> Result<Content<Stream<NdjsonReader<'r>>>, Custom<Json<ApiError>>> {
+    let compact = query.compact.unwrap_or(false);
+    let query = query.history();
+    log.page(room, &HistoryQuery {
+        limit: Some(1),
+        ..query.clone()
+    }).map_err(history_error)?;
+
+    let reader = NdjsonReader::new(log.stream(room, &query), compact);
+    Ok(Content(ContentType::new("application", "x-ndjson"), Stream::from(reader)))
+}
+
+fn history(room: &str, query: &HistoryQuery, log: &dyn RollStore) -> Result<Json<HistoryPage>, Custom<Json<ApiError>>> {
+    log.page(room, query).map(Json).map_err(history_error)
+}
+
+fn history_error(error: HistoryError) -> Custom<Json<ApiError>> {
+    let error = ApiError::from(error);
+    let status = match error.code {
+        ErrorCode::NotFound => Status::NotFound,
+        ErrorCode::Unavailable => Status::ServiceUnavailable,
+        _ => Status::BadRequest,
+    };
+    error_response(status, error)
+}
+
+fn error_response(status: Status, error: ApiError) -> Custom<Json<ApiError>> {
+    Custom(status, Json(error))
+}
+
+/// Answer a roll that couldn't be made, with a 422 past a limit and a 503 when the server is busy
+///
+/// Anything else wrong with the roll is the client's mistake, so gets a 400.
+fn roll_error_response(error: ApiError) -> Custom<Json<ApiError>> {
+    let status = match error.code {
+        ErrorCode::LimitExceeded => Status::UnprocessableEntity,
+        ErrorCode::Unavailable => Status::ServiceUnavailable,
+        _ => Status::BadRequest,
+    };
+    error_response(status, error)
+}
+
+/// Roll the command, counting it in the metrics when they're kept
+fn roll_command(command: &str, config: &Config, roller: &dyn DiceRoller, rules: &HouseRules, metrics: Option<&Metrics>) -> Result<Json<RollsResponse>, Custom<Json<ApiError>>> {
     // Start the timer
     let start = Instant::now();
 
+    let result = roll_command_from(command, config, roller, rules, start);
+    if let Some(metrics) = metrics {
+        let outcome = result.as_ref().map(|response| &response.roll).map_err(|error| error.1.code);
+        metrics.record(outcome, start.elapsed());
+    }
+    result
+}
+
+fn roll_command_from(command: &str, config: &Config, roller: &dyn DiceRoller, rules: &HouseRules, start: Instant) -> Result<Json<RollsResponse>, Custom<Json<ApiError>>> {
     // Parse the roll command as if we're passing it through TTML
-    let input = "!roll ".to_string() + &command;
-    let step_result = parse_step_p(input.as_bytes());
-
-    // Successful parse, roll the die
-    if step_result.is_err() == false {
-        let (_, step) = step_result.unwrap();
-
-        // Build a list of dice to roll
-        let mut rolls: Vec<Roll> = vec![];
-
-        // Build a list of flags
-        let mut flags = RollFlags::new();
-
-        // Loop through each step, push the dice when necessary
-        for arg in &step.args {
-            if let &Arg::Roll(RollArg::N(ArgValue::Number(n))) = arg {
-                flags.n = n as i16;
-                flags.equation = flags.equation + &n.to_string();
-            } else if let &Arg::Roll(RollArg::D(ArgValue::Number(d))) = arg {
-                flags.max = d as i16;
-                flags.die = match d {
-                    100   => DieType::D100,
-                    20    => DieType::D20,
-                    12    => DieType::D12,
-                    10    => DieType::D10,
-                    8     => DieType::D8,
-                    6     => DieType::D6,
-                    4     => DieType::D4,
-                    _     => DieType::Other,
-                };
-                flags.equation = flags.equation + &"d" + &d.to_string();
-            } else if let &Arg::Roll(RollArg::H(ArgValue::Number(h))) = arg {
-                flags.kh = h as i16;
-                flags.equation = flags.equation + &"kh" + &h.to_string();
-            } else if let &Arg::Roll(RollArg::L(ArgValue::Number(l))) = arg {
-                flags.kl = l as i16;
-                flags.equation = flags.equation + &"kl" + &l.to_string();
-            } else if let &Arg::Roll(RollArg::GT(ArgValue::Number(gt))) = arg {
-                flags.gt = gt as u16;
-                flags.equation = flags.equation + &"gt" + &gt.to_string();
-            } else if let &Arg::Roll(RollArg::GTE(ArgValue::Number(gte))) = arg {
-                flags.gte = gte as u16;
-                flags.equation = flags.equation + &"gte" + &gte.to_string();
-            } else if let &Arg::Roll(RollArg::LT(ArgValue::Number(lt))) = arg {
-                flags.lt = lt as u16;
-                flags.equation = flags.equation + &"lt" + &lt.to_string();
-            } else if let &Arg::Roll(RollArg::LTE(ArgValue::Number(lte))) = arg {
-                flags.lte = lte as u16;
-                flags.equation = flags.equation + &"lte" + &lte.to_string();
-            } else if let &Arg::Roll(RollArg::RR(ref comparitive)) = arg {
-                flags.rr = match &comparitive.value {
-                    &ArgValue::Number(n) => n as i16,
-                    _ => 0
-                };
-                match comparitive.op {
-                    ComparisonArg::GreaterThan => {
-                        flags.rr_op = Some(ComparisonArg::GreaterThan);
-                        flags.equation = flags.equation + &"rr>" + &flags.rr.to_string();
-                    },
-                    ComparisonArg::GreaterThanOrEqual => {
-                        flags.rr_op = Some(ComparisonArg::GreaterThanOrEqual);
-                        flags.equation = flags.equation + &"rr>=" + &flags.rr.to_string();
-                    },
-                    ComparisonArg::LessThan => {
-                        flags.rr_op = Some(ComparisonArg::LessThan);
-                        flags.equation = flags.equation + &"rr<" + &flags.rr.to_string();
-                    },
-                    ComparisonArg::LessThanOrEqual => {
-                        flags.rr_op = Some(ComparisonArg::LessThanOrEqual);
-                        flags.equation = flags.equation + &"rr<=" + &flags.rr.to_string();
-                    },
-                    ComparisonArg::EqualTo => {
-                        flags.rr_op = Some(ComparisonArg::EqualTo);
-                        flags.equation = flags.equation + &"rr==" + &flags.rr.to_string();
-                    },
-                };
-            } else if let &Arg::Roll(RollArg::RO(ref comparitive)) = arg {
-                flags.ro = match &comparitive.value {
-                    &ArgValue::Number(n) => n as i16,
-                    _ => 0
-                };
-                match comparitive.op {
-                    ComparisonArg::GreaterThan => {
-                        flags.ro_op = Some(ComparisonArg::GreaterThan);
-                        flags.equation = flags.equation + &"ro>" + &flags.ro.to_string();
-                    },
-                    ComparisonArg::GreaterThanOrEqual => {
-                        flags.ro_op = Some(ComparisonArg::GreaterThanOrEqual);
-                        flags.equation = flags.equation + &"ro>=" + &flags.ro.to_string();
-                    },
-                    ComparisonArg::LessThan => {
-                        flags.ro_op = Some(ComparisonArg::LessThan);
-                        flags.equation = flags.equation + &"ro<" + &flags.ro.to_string();
-                    },
-                    ComparisonArg::LessThanOrEqual => {
-                        flags.ro_op = Some(ComparisonArg::LessThanOrEqual);
-                        flags.equation = flags.equation + &"ro<=" + &flags.ro.to_string();
-                    },
-                    ComparisonArg::EqualTo => {
-                        flags.ro_op = Some(ComparisonArg::EqualTo);
-                        flags.equation = flags.equation + &"ro==" + &flags.ro.to_string();
-                    },
-                };
-            } else if let &Arg::Roll(RollArg::ModifierPos(ArgValue::Number(mp))) = arg {
-                flags.modifiers.push(mp as i16);
-                flags.equation = flags.equation + &"+" + &mp.to_string();
-            } else if let &Arg::Roll(RollArg::ModifierNeg(ArgValue::Number(mn))) = arg {
-                flags.modifiers.push(mn as i16 * -1);
-                flags.equation = flags.equation + &"-" + &mn.to_string();
-            } else if let &Arg::Roll(RollArg::Max(ArgValue::Number(max))) = arg {
-                flags.max = max as i16;
-                flags.equation = flags.equation + &"max" + &max.to_string();
-            } else if let &Arg::Roll(RollArg::Min(ArgValue::Number(min))) = arg {
-                flags.min = min as i16;
-                flags.equation = flags.equation + &"min" + &min.to_string();
-            } else if let &Arg::Roll(RollArg::Sides(ref r_sides)) = arg {
-                let mut min = 0;
-                let mut max = 0;
-                let sides: Vec<i16> = r_sides.into_iter().map(|side| (
-                    match side {
-                        &ArgValue::Number(n) => {
-                            if n < min || n == 0 {
-                                min = n.clone()
-                            } else if n > max {
-                                max = n.clone()
-                            }
-                            n.clone() as i16
-                        },
-                        _ => 0 as i16
-                    }
-                )).collect();
-                flags.sides = Some(sides.clone());
-                flags.min = min as i16;
-                flags.max = max as i16;
-                let side_strs: Vec<String> = sides.into_iter().map(|side| (side.to_string())).collect();
-                flags.equation = flags.equation + &"[" + &side_strs.join(",") + &"]";
-            } else if let &Arg::Roll(RollArg::Comment(ArgValue::Text(ref comment))) = arg {
-                flags.equation = flags.equation + &"[" + comment + &"]";
-            } else if let &Arg::Roll(RollArg::Primitive(_)) = arg {
-                // Execute this roll before starting the next one
-                rolls.push(Roll::new(flags));
-
-                // Reset the flags
-                flags = RollFlags::new();
+    let options = if config.strict_notation {
+        ParseOptions::new().strict()
+    } else {
+        ParseOptions::new()
+    };
+    let parse_start = Instant::now();
+    let mut rolls = parse_notation_with(command, &options).map_err(|error| roll_error_response(error.into()))?;
+
+    // Every roll in the command has to be valid, even though only the last is rolled for now
+    if let Some(error) = rolls.iter().filter_map(|flags| flags.validate().err()).next() {
+        return Err(error_response(Status::BadRequest, error.into()));
+    }
+    let parse_us = parse_start.elapsed().as_micros() as u64;
+    // Linted term by term, as the rolls TTML splits the command into don't keep their signs
+    let limits = Limits::default();
+    let warnings = validate_notation(command, &limits, None).warnings;
+
+    // House rules fill in what the roll leaves out, before its dice are counted against the limit
+    let mut flags = rolls.pop().ok_or(RollError::InvalidNotation).map_err(|error| roll_error_response(error.into()))?;
+    let applied = rules.apply(&mut flags);
+    limits.check(&flags).map_err(|error| roll_error_response(error.into()))?;
+
+    // Turn away rolls that could roll more dice than a request is allowed before rolling any
+    let estimate = limits.estimate(&flags);
+    if estimate.dice_upper_bound > config.max_dice_rolled {
+        let error = ApiError::too_many_dice_rolled(estimate.dice_upper_bound, config.max_dice_rolled);
+        return Err(error_response(Status::UnprocessableEntity, error));
+    }
+
+    // Build the final roll
+    let options = match config.roll_deadline {
+        Some(deadline) => RollOptions::new().deadline(deadline),
+        None => RollOptions::new(),
+    };
+    let options = match config.slow_roll_warning {
+        Some(_) => options.capture_timing(),
+        None => options,
+    };
+    let options = rules.roll_options(options);
+    let mut roll = roller.roll_with_options(&flags, &options).map_err(|error| roll_error_response(error.into()))?;
+    roll.house_rules = applied;
+    roll.warnings = warnings;
+    roll.stamp_hashes(&flags);
+    if let Some(threshold) = config.slow_roll_warning {
+        if let Some(ref mut timing) = roll.timing {
+            timing.parse_us = Some(parse_us);
+            timing.total_us = start.elapsed().as_micros() as u64;
+        }
+        warn_if_slow(&roll, threshold);
+    }
+    // let original_equation = roll.equation.clone();
+
+    // Take all the dice from previous rolls and append them to this roll
+    // for mut r in rolls.into_iter() {
+        // roll.dice.append(&mut r.dice);
+        // roll.modifiers.append(&mut r.modifiers);
+        // roll.value += r.value;
+        // roll.raw_value += r.raw_value;
+        // roll.equation = r.equation + " + ";
+    // }
+    // roll.equation = roll.equation + &original_equation;
+
+    let elapsed = start.elapsed();
+    let response = RollsResponse {
+        roll,
+        execution_time: (elapsed.as_secs() * 1000) + (elapsed.subsec_nanos() / 1000000) as u64,
+    };
+    Ok(Json(response))
+}
+
+/// Log where the time went for a roll that took longer than the threshold
+fn warn_if_slow(roll: &Roll, threshold: Duration) {
+    let _timing = match roll.timing {
+        Some(ref timing) if timing.total_us > threshold.as_micros() as u64 => timing,
+        _ => return,
+    };
+    #[cfg(feature = "tracing")]
+    warn!(equation = %roll.equation, id = %roll.id, "slow roll, {}", _timing);
+}
+
+/// Get the chance of each total of a roll, worked out exactly when it can be and simulated when not
+///
+/// The roll is checked against the limits before anything is worked out, the same as rolling it.
+#[get("/stats/<command>", format = "application/json")]
+pub fn stats(command: String, cache: State<DistributionCache>, config: State<Config>, _throttle: Throttle) -> Result<Json<StatsResponse>, Custom<Json<ApiError>>> {
+    // Start the timer
+    let start = Instant::now();
+
+    // Only the last roll in the command is used, the same as rolling it
+    let mut rolls = parse_notation(&command).map_err(|error| roll_error_response(error.into()))?;
+    let flags = rolls.pop().ok_or(RollError::InvalidNotation).map_err(|error| roll_error_response(error.into()))?;
+    flags.validate().map_err(|error| error_response(Status::BadRequest, error.into()))?;
+    Limits::default().check(&flags).map_err(|error| error_response(Status::UnprocessableEntity, error.into()))?;
+    let estimate = Limits::default().estimate(&flags);
+    if estimate.dice_upper_bound > config.max_dice_rolled {
+        let error = ApiError::too_many_dice_rolled(estimate.dice_upper_bound, config.max_dice_rolled);
+        return Err(error_response(Status::UnprocessableEntity, error));
+    }
+
+    // Rolls without an exact distribution are simulated, and as they're random they aren't cached
+    let cached = match cache.distribution(&flags) {
+        Some(cached) => cached,
+        None => {
+            let mut roller = deadline_roller(&config);
+            let distribution = Distribution::try_simulated(flags.clone(), SIMULATION_TRIALS, &mut roller)
+                .map_err(|error| roll_error_response(error.into()))?;
+            CachedDistribution {
+                distribution: Arc::new(distribution),
+                was_cached: false,
             }
+        },
+    };
+
+    let elapsed = start.elapsed();
+    let response = StatsResponse {
+        distribution: (*cached.distribution).clone(),
+        equation: flags.canonical(),
+        execution_time: (elapsed.as_secs() * 1000) + (elapsed.subsec_nanos() / 1000000) as u64,
+        mean: cached.distribution.mean(),
+        was_cached: cached.was_cached,
+    };
+    Ok(Json(response))
+}
+
+/// A roller for simulating distributions, which gives up after the roll deadline the same as a roll
+fn deadline_roller(config: &Config) -> Roller {
+    match config.roll_deadline {
+        Some(deadline) => Roller::new().with_options(&RollOptions::new().deadline(deadline)),
+        None => Roller::new(),
+    }
+}
+
+/// Get the distribution of the sum of several rolls made together
+///
+/// The whole request is checked against the limits, its count of rolls and their dice together as
+/// well as each roll on its own.
+#[post("/stats/combined", format = "application/json", data = "<request>")]
+pub fn combined_stats(request: Json<CombinedStatsRequest>, config: State<Config>, _throttle: Throttle) -> Result<Json<CombinedStatsResponse>, Custom<Json<ApiError>>> {
+    // Start the timer
+    let start = Instant::now();
+
+    let expressions: Vec<&str> = request.expressions.iter().map(|e| e.as_str()).collect();
+    let distribution = combined_distribution_with(&expressions, &Limits::default(), &mut deadline_roller(&config))
+        .map_err(|error| roll_error_response(error.into()))?;
+
+    let elapsed = start.elapsed();
+    let response = CombinedStatsResponse {
+        execution_time: (elapsed.as_secs() * 1000) + (elapsed.subsec_nanos() / 1000000) as u64,
+        mean: distribution.mean(),
+        distribution,
+    };
+    Ok(Json(response))
+}
+
+/// Check notation without rolling it, every problem with it at once
+///
+/// Checks are counted apart from rolls, so checking as a user types doesn't use up their rolls.
+#[post("/validate", format = "application/json", data = "<request>")]
+pub fn validate(request: Json<ValidateRequest>, _throttle: LightThrottle) -> Json<ValidationReport> {
+    let lint_options = request.lint.clone().unwrap_or_default();
+    Json(validate_notation_with(&request.notation, &Limits::default(), request.variables.as_ref(), &lint_options))
+}
+
+/// Post every roll made in a room to a URL
+#[post("/webhooks/<room>", format = "application/json", data = "<webhook>")]
+pub fn register_webhook(room: String, webhook: Json<Webhook>, webhooks: State<Webhooks>) -> Result<Json<WebhookStatus>, Custom<Json<ApiError>>> {
+    webhooks.register(&room, webhook.into_inner()).map_err(|error| error_response(Status::BadRequest, error.into()))?;
+    Ok(Json(webhooks.status(&room).unwrap_or_default()))
+}
+
+#[get("/webhooks/<room>", format = "application/json")]
+pub fn webhook_status(room: String, webhooks: State<Webhooks>) -> Option<Json<WebhookStatus>> {
+    webhooks.status(&room).map(Json)
+}
+
+#[cfg(test)]
+fn assert_error(mut response: LocalResponse, status: Status, body: &str) {
+    assert_eq!(response.status(), status);
+    assert_eq!(response.body_string().unwrap(), body);
+}
+
+#[cfg(test)]
+fn client() -> Client {
+    Client::new(::rocket()).expect("the API should launch")
+}
+
+#[test]
+fn it_rolls_rooms_with_the_given_engine() {
+    use testing::MockRoller;
+
+    let canned = Roll::from_values("1d20", &[17]).unwrap();
+    let mock = Arc::new(MockRoller::new());
+    mock.respond_with(Ok(canned.clone()));
+    let client = Client::new(::rocket_with(Box::new(mock.clone()))).expect("the API should launch");
+
+    let mut response = client.get("/v1/rooms/tavern/alice/1d20").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.body_string().unwrap().contains(&canned.id));
+    let requests: Vec<String> = mock.requests().iter().map(|flags| flags.canonical()).collect();
+    assert_eq!(requests, vec!["1d20"]);
+
+    // The canned roll is the one kept in the room's history
+    let mut response = client.get("/v1/rooms/tavern/rolls").header(ContentType::JSON).dispatch();
+    assert!(response.body_string().unwrap().contains(&canned.id));
+}
+
+#[test]
+fn it_looks_up_room_rolls_by_sequence() {
+    let client = client();
+    let mut rolled = vec![];
+    for _ in 0..2 {
+        let mut response = client.get("/v1/rooms/tavern/alice/1d20").header(ContentType::JSON).dispatch();
+        let body: ::serde_json::Value = ::serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        rolled.push(body["roll"].clone());
+    }
+    assert_eq!(rolled[1]["sequence"], json!(2));
+
+    let mut response = client.get("/v1/rooms/tavern/rolls/2").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let roll: ::serde_json::Value = ::serde_json::from_str(&response.body_string().unwrap()).unwrap();
+    assert_eq!(roll["id"], rolled[1]["id"]);
+
+    let response = client.get("/v1/rooms/tavern/rolls/3").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // Anything that isn't a number is still rolled for a participant named rolls
+    let response = client.get("/v1/rooms/tavern/rolls/1d20").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn it_exports_room_history_line_by_line() {
+    use history::RollLog;
+
+    let log = RollLog::new();
+    for i in 0..10000 {
+        let participant = if i % 2 == 0 { "alice" } else { "bob" };
+        log.record("tavern", participant, Roll::from_values("1d20", &[(i % 20 + 1) as i16]).unwrap());
+    }
+    let rocket = ::rocket::ignite()
+        .mount("/v1", routes![room_export, room_export_all])
+        .manage(Box::new(log) as Box<dyn RollStore>);
+    let client = Client::new(rocket).expect("the API should launch");
+
+    let mut response = client.get("/v1/rooms/tavern/rolls/export").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::new("application", "x-ndjson")));
+    let body = response.body_string().unwrap();
+    let sequences: Vec<u64> = body.lines()
+        .map(|line| ::serde_json::from_str::<Roll>(line).unwrap().sequence)
+        .collect();
+    assert_eq!(sequences, (1..10001).rev().collect::<Vec<u64>>());
+
+    let mut response = client.get("/v1/rooms/tavern/rolls/export?participant=bob&limit=300&compact=true").dispatch();
+    let body = response.body_string().unwrap();
+    let rolls: Vec<::ndjson::CompactRoll> = body.lines().map(|line| ::serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(rolls.len(), 300);
+    assert!(rolls.iter().all(|roll| roll.sequence % 2 == 0 && roll.dice.len() == 1));
+
+    let response = client.get("/v1/rooms/cellar/rolls/export").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client.get("/v1/rooms/tavern/rolls/export?participant=carol").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn it_rolls_rooms_by_their_house_rules() {
+    use rocket::http::Header;
+    use testing::MockRoller;
+
+    let mock = Arc::new(MockRoller::new());
+    mock.respond_with(Roll::from_values("2d6!", &[3, 4]));
+    mock.respond_with(Roll::from_values("2d6", &[3, 4]));
+    let client = Client::new(::rocket_with(Box::new(mock.clone()))).expect("the API should launch");
+
+    let response = client.put("/v1/rooms/tavern/rules")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer gm-token"))
+        .body(r#"{"explode_tags": ["damage"]}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let mut response = client.get("/v1/rooms/tavern/rules").header(ContentType::JSON).dispatch();
+    assert!(response.body_string().unwrap().contains(r#""explode_tags":["damage"]"#));
+
+    // Only the room with the rules explodes the damage dice, and says which rule did
+    let mut response = client.get("/v1/rooms/tavern/alice/2d6%5Bdamage%5D").header(ContentType::JSON).dispatch();
+    assert!(response.body_string().unwrap().contains(r#""house_rules":[{"ExplodeTag":"damage"}]"#));
+    client.get("/v1/rooms/dungeon/alice/2d6%5Bdamage%5D").header(ContentType::JSON).dispatch();
+    let requests: Vec<String> = mock.requests().iter().map(|flags| flags.canonical()).collect();
+    assert_eq!(requests, vec!["2d6!", "2d6"]);
+}
+
+#[test]
+fn it_only_lets_the_room_owner_set_its_rules() {
+    use rocket::http::Header;
+
+    let client = client();
+    let put = |token: Option<&str>| {
+        let request = client.put("/v1/rooms/tavern/rules").header(ContentType::JSON).body(r#"{"crit_range": 19}"#);
+        match token {
+            Some(token) => request.header(Header::new("Authorization", format!("Bearer {}", token))).dispatch(),
+            None => request.dispatch(),
         }
+    };
+    let owner_only = r#"{"code":"UNAUTHORIZED","details":{"resource":"room"},"message":"Only the room's owner can do that."}"#;
+    assert_error(put(None), Status::Unauthorized, owner_only);
 
-        // Build the final roll
-        let roll = Roll::new(flags);
-        // let original_equation = roll.equation.clone();
-
-        // Take all the dice from previous rolls and append them to this roll
-        // for mut r in rolls.into_iter() {
-            // roll.dice.append(&mut r.dice);
-            // roll.modifiers.append(&mut r.modifiers);
-            // roll.value += r.value;
-            // roll.raw_value += r.raw_value;
-            // roll.equation = r.equation + " + ";
-        // }
-        // roll.equation = roll.equation + &original_equation;
-
-        let elapsed = start.elapsed();
-        let response = RollsResponse {
-            roll,
-            execution_time: (elapsed.as_secs() * 1000) + (elapsed.subsec_nanos() / 1000000) as u64,
-        };
-        Some(Json(response))
-    } else {
-        None
+    // The first token to set the rules owns the room
+    assert_eq!(put(Some("gm-token")).status(), Status::Ok);
+    assert_error(put(Some("player-token")), Status::Forbidden, owner_only);
+    assert_eq!(put(Some("gm-token")).status(), Status::Ok);
+}
+
+#[test]
+fn it_validates_notation_apart_from_rolls() {
+    use rate_limit::{MemoryLimiter, TokenBucket};
+
+    let limiter = MemoryLimiter::new(TokenBucket {
+        capacity: 1,
+        refill_per_second: 1,
+    });
+    let client = Client::new(::rocket().manage(RateLimits::new(Box::new(limiter)))).expect("the API should launch");
+    let check = |body: &str| {
+        let mut response = client.post("/v1/validate").header(ContentType::JSON).body(body).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.body_string().unwrap()
+    };
+
+    let report = check(r#"{"notation": "4dQ + 1d20 + @dex", "variables": {"names": ["str"]}}"#);
+    assert!(report.contains(r#""metadata":null"#));
+    assert!(report.contains(r#""code":"PARSE_ERROR""#) && report.contains(r#""code":"UNKNOWN_VARIABLE""#));
+
+    let report = check(r#"{"notation": "1d20 + 1d20"}"#);
+    assert!(report.contains(r#""rule":"added-d20s""#) && report.contains(r#""suggestion":"2d20kh1""#));
+    let report = check(r#"{"notation": "1d20 + 1d20", "lint": {"suppressed": ["added-d20s"]}}"#);
+    assert!(report.contains(r#""warnings":[]"#));
+
+    // Validating didn't use up the client's roll, which still rolls with its warnings
+    let mut response = client.get("/v1/1d20kh1").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.body_string().unwrap().contains(r#""rule":"keep-single-die""#));
+    assert_eq!(client.get("/v1/1d20").header(ContentType::JSON).dispatch().status(), Status::TooManyRequests);
+}
+
+#[test]
+fn it_returns_structured_roll_errors() {
+    let client = client();
+
+    let response = client.get("/v1/4dFkh2").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::BadRequest,
+                 r#"{"code":"UNSUPPORTED_MODIFIER","details":{"modifier":"kh"},"message":"The kh flag can't be used on Fate dice."}"#);
+
+    let response = client.get("/v1/100d6rr1").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::UnprocessableEntity,
+                 r#"{"code":"LIMIT_EXCEEDED","details":{"limit":"max_dice_rolled","max":10000,"value":10100},"message":"Roll could roll too many dice."}"#);
+
+    // Notation that can't be parsed or rolled is the client's mistake, not a missing resource
+    let response = client.get("/v1/4dQ").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::BadRequest, r#"{"code":"PARSE_ERROR","details":null,"message":"The roll couldn't be understood."}"#);
+    let response = client.get("/v1/1d0").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::BadRequest,
+                 r#"{"code":"INVALID_ROLL","details":null,"message":"Dice need at least one side to be rolled"}"#);
+    let response = client.get("/v1/1d20min30").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::BadRequest,
+                 r#"{"code":"INVALID_ROLL","details":{"max":20,"min":30},"message":"The lowest side (30) can't be higher than the highest (20)"}"#);
+
+    // Only a busy server is a 503, a roll past its deadline is past a limit
+    let status = |error: RollError| roll_error_response(error.into()).0;
+    assert_eq!(status(RollError::DeadlineExceeded), Status::UnprocessableEntity);
+    assert_eq!(status(RollError::Saturated), Status::ServiceUnavailable);
+    assert_eq!(status(RollError::Cancelled), Status::ServiceUnavailable);
+    assert_eq!(status(RollError::InvalidNotation), Status::BadRequest);
+
+    let response = client.get("/v1/not/a/route/at/all").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::NotFound, r#"{"code":"NOT_FOUND","details":null,"message":"Resource not found."}"#);
+}
+
+#[test]
+fn it_returns_structured_room_errors() {
+    let client = client();
+
+    let response = client.get("/v1/rooms/nowhere/rolls").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::NotFound, r#"{"code":"NOT_FOUND","details":{"resource":"room"},"message":"Room not found."}"#);
+
+    client.get("/v1/rooms/tavern/alice/1d20").header(ContentType::JSON).dispatch();
+    let response = client.get("/v1/rooms/tavern/rolls?cursor=nope").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::BadRequest,
+                 r#"{"code":"INVALID_QUERY","details":{"parameter":"cursor"},"message":"Cursor is not valid."}"#);
+
+    let response = client.post("/v1/webhooks/tavern")
+        .header(ContentType::JSON)
+        .body(r#"{"url": "ftp://logs.example.com/rolls"}"#)
+        .dispatch();
+    assert_error(response, Status::BadRequest,
+                 r#"{"code":"INVALID_WEBHOOK","details":{"reason":"unsupported_scheme"},"message":"Webhook URLs must start with http:// or https://."}"#);
+
+    // The server won't post rolls into its own network
+    let response = client.post("/v1/webhooks/tavern")
+        .header(ContentType::JSON)
+        .body(r#"{"url": "http://169.254.169.254/latest/meta-data"}"#)
+        .dispatch();
+    assert_error(response, Status::BadRequest,
+                 r#"{"code":"INVALID_WEBHOOK","details":{"reason":"private_host"},"message":"Webhook URLs must be on a public host."}"#);
+}
+
+#[test]
+fn it_checks_stats_requests_before_working_them_out() {
+    let client = client();
+
+    let response = client.get("/v1/stats/4dQ").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::BadRequest, r#"{"code":"PARSE_ERROR","details":null,"message":"The roll couldn't be understood."}"#);
+
+    let response = client.get("/v1/stats/1000d6").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::UnprocessableEntity,
+                 r#"{"code":"LIMIT_EXCEEDED","details":{"limit":"max_dice","max":100,"value":1000},"message":"Can't roll 1000 dice, the most is 100"}"#);
+    let response = client.get("/v1/stats/1d20min30").header(ContentType::JSON).dispatch();
+    assert_error(response, Status::BadRequest,
+                 r#"{"code":"INVALID_ROLL","details":{"max":20,"min":30},"message":"The lowest side (30) can't be higher than the highest (20)"}"#);
+
+    // Rolls without an exact distribution are simulated rather than missing
+    let mut response = client.get("/v1/stats/2d6!").header(ContentType::JSON).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body: ::serde_json::Value = ::serde_json::from_str(&response.body_string().unwrap()).unwrap();
+    assert_eq!(body["distribution"]["is_approximate"], json!(true));
+    assert_eq!(body["was_cached"], json!(false));
+}
+
+#[test]
+fn it_turns_away_clients_past_their_rate_limit() {
+    use rate_limit::{MemoryLimiter, TokenBucket};
+    use rocket::http::Header;
+
+    let limiter = MemoryLimiter::new(TokenBucket {
+        capacity: 2,
+        refill_per_second: 1,
+    });
+    let client = Client::new(::rocket().manage(RateLimits::new(Box::new(limiter)))).expect("the API should launch");
+    for _ in 0..2 {
+        assert_eq!(client.get("/v1/1d20").header(ContentType::JSON).dispatch().status(), Status::Ok);
     }
+
+    let response = client.get("/v1/1d20").header(ContentType::JSON).dispatch();
+    assert_eq!(response.headers().get_one("Retry-After"), Some("1"));
+    assert_error(response, Status::TooManyRequests,
+                 r#"{"code":"RATE_LIMITED","details":{"retry_after":1},"message":"Too many requests, slow down."}"#);
+
+    // Requests with an API token count against a limit of their own, kept for the token from
+    // this address
+    let response = client.get("/v1/1d20")
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", "Bearer party-token"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
 }
@@ -0,0 +1,352 @@
+use error::ApiError;
+use limits::{CostEstimate, Limits};
+use lint::{lint_signed_with, LintOptions, LintWarning, SignedRoll};
+use parser::parse_notation;
+use std::collections::BTreeSet;
+#[cfg(test)]
+use error::ErrorCode;
+#[cfg(test)]
+use roll::{MAX_EXPLOSIONS, MAX_REROLLS};
+
+/// The `@` variables notation is allowed to use, e.g. those on a character sheet
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct VariableSchema {
+    pub names: BTreeSet<String>,
+}
+
+impl VariableSchema {
+    pub fn new() -> VariableSchema {
+        VariableSchema::default()
+    }
+
+    /// Allow the variable, written without its `@`
+    pub fn with(mut self, name: &str) -> VariableSchema {
+        self.names.insert(name.to_string());
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+}
+
+/// Where in the notation something is, as byte offsets from the start to just past the end
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Something wrong with notation, and the part of it that's wrong
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Problem {
+    pub error: ApiError,
+    pub span: Span,
+}
+
+/// What valid notation would roll
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NotationMetadata {
+    /// The notation written the same way whatever its spacing, e.g. `1d20 + @str + 2`
+    pub canonical: String,
+
+    /// What the notation could cost to roll, with every variable as 1
+    pub cost: CostEstimate,
+
+    /// Number of dice the notation starts with, before any rerolls or explosions
+    pub dice: usize,
+
+    /// The variables the notation uses, in the order they first appear
+    pub variables: Vec<String>,
+}
+
+/// Whether notation can be rolled, and what it would roll or everything wrong with it
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ValidationReport {
+    /// What the notation would roll, none when it has problems
+    pub metadata: Option<NotationMetadata>,
+
+    /// Every problem found, in the order they appear in the notation
+    pub problems: Vec<Problem>,
+
+    /// What's probably not what was meant, from every term that parses, see `lint`
+    pub warnings: Vec<LintWarning>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Check notation can be rolled without rolling it, e.g. as a user types a macro
+///
+/// Each term between a `+` or `-` is checked on its own, so every problem is found rather than
+/// only the first. Variables are checked against the schema when there is one, and any are
+/// allowed without one. Terms that use variables are checked with every variable as 1. Nothing is
+/// rolled, so no randomness is used. Warnings name the term they were found in, and subtracted
+/// terms are linted as subtracted.
+pub fn validate_notation(input: &str, limits: &Limits, variables: Option<&VariableSchema>) -> ValidationReport {
+    validate_notation_with(input, limits, variables, &LintOptions::new())
+}
+
+/// Check notation can be rolled without rolling it, linting it with only the rules not suppressed
+pub fn validate_notation_with(input: &str, limits: &Limits, variables: Option<&VariableSchema>, lint_options: &LintOptions) -> ValidationReport {
+    let mut problems = vec![];
+    let mut parsed = vec![];
+
+    // The term each parsed roll came from, and if a term that's kept, a number or one with
+    // variables, can't be written back from its rolls
+    let mut term_of = vec![];
+    let mut is_partial = false;
+    let mut canonical = String::new();
+    let mut referenced: Vec<String> = vec![];
+    let mut dice = 0;
+    let mut dice_lower = 0;
+    let mut dice_upper_bound = 0;
+    let mut nodes = 0;
+
+    for (term, (sign, span)) in terms(input).into_iter().enumerate() {
+        let text = &input[span.start..span.end];
+        if !canonical.is_empty() || sign == '-' {
+            canonical = canonical + if canonical.is_empty() { "-" } else if sign == '-' { " - " } else { " + " };
+        }
+        if text.is_empty() {
+            problems.push(Problem {
+                error: ApiError::from(::parser::ParseError::InvalidNotation),
+                span,
+            });
+            continue;
+        }
+
+        let mut substituted = String::new();
+        let mut names = vec![];
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c != '@' {
+                substituted.push(c);
+                continue;
+            }
+
+            let mut end = i + 1;
+            while let Some(&(j, next)) = chars.peek() {
+                if !next.is_alphanumeric() && next != '_' {
+                    break;
+                }
+                end = j + next.len_utf8();
+                chars.next();
+            }
+            let name = text[i + 1..end].to_string();
+            if variables.map_or(false, |schema| !schema.contains(&name)) {
+                problems.push(Problem {
+                    error: ApiError::unknown_variable(&name),
+                    span: Span { start: span.start + i, end: span.start + end },
+                });
+            }
+            if !referenced.contains(&name) {
+                referenced.push(name.clone());
+            }
+            names.push(name);
+            substituted.push('1');
+        }
+
+        // Numbers and lone variables are modifiers, with nothing to roll
+        if substituted.chars().all(|c| c.is_digit(10)) {
+            canonical += text;
+            nodes += 1;
+            is_partial = true;
+            continue;
+        }
+
+        let rolls = match parse_notation(&substituted) {
+            Ok(rolls) => rolls,
+            Err(error) => {
+                problems.push(Problem {
+                    error: ApiError::from(error),
+                    span,
+                });
+                continue;
+            },
+        };
+        for flags in &rolls {
+            if let Err(error) = flags.validate().and_then(|_| limits.check(flags)) {
+                problems.push(Problem {
+                    error: ApiError::from(error),
+                    span,
+                });
+            }
+            let estimate = limits.estimate(flags);
+            dice += flags.n.max(0) as usize;
+            dice_lower += estimate.dice_lower;
+            dice_upper_bound += estimate.dice_upper_bound;
+            nodes += estimate.nodes;
+        }
+        for flags in &rolls {
+            parsed.push(SignedRoll {
+                flags: flags.clone(),
+                is_subtracted: sign == '-',
+            });
+            term_of.push(term);
+        }
+
+        // Variables were parsed as 1, so terms with them in are kept as written
+        if names.is_empty() {
+            let written: Vec<String> = rolls.iter().map(|flags| flags.canonical()).collect();
+            canonical += &written.join(" + ");
+        } else {
+            canonical += text;
+            is_partial = true;
+        }
+    }
+
+    let metadata = if problems.is_empty() {
+        Some(NotationMetadata {
+            canonical,
+            cost: CostEstimate {
+                class: limits.cost_class(dice_upper_bound),
+                dice_lower,
+                dice_upper_bound,
+                nodes,
+            },
+            dice,
+            variables: referenced,
+        })
+    } else {
+        None
+    };
+    // Warnings point at the terms as written, and suggestions written without the numbers and
+    // variables would leave them out
+    let warnings = lint_signed_with(&parsed, lint_options).into_iter()
+        .map(|warning| LintWarning {
+            suggestion: warning.suggestion.filter(|_| !is_partial),
+            term: term_of[warning.term],
+            ..warning
+        })
+        .collect();
+    ValidationReport {
+        metadata,
+        problems,
+        warnings,
+    }
+}
+
+/// Split notation into its terms at each `+` or `-` outside parentheses, brackets and comments,
+/// with the sign before each term and its span without the spaces around it
+fn terms(input: &str) -> Vec<(char, Span)> {
+    let mut terms = vec![];
+    let mut depth = 0;
+    let mut in_comment = false;
+    let mut sign = '+';
+    let mut start = 0;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => in_comment = !in_comment,
+            '(' | '[' if !in_comment => depth += 1,
+            ')' | ']' if !in_comment => depth -= 1,
+            '+' | '-' if !in_comment && depth == 0 => {
+                // A sign at the very start belongs to the first term
+                if !input[..i].trim().is_empty() {
+                    terms.push((sign, trimmed(input, start, i)));
+                }
+                sign = c;
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    terms.push((sign, trimmed(input, start, input.len())));
+    terms
+}
+
+fn trimmed(input: &str, start: usize, end: usize) -> Span {
+    let text = &input[start..end];
+    let leading = text.find(|c: char| !c.is_whitespace()).unwrap_or(text.len());
+    Span {
+        start: start + leading,
+        end: start + leading + text.trim().len(),
+    }
+}
+
+#[test]
+fn it_reports_every_problem_together() {
+    let schema = VariableSchema::new().with("str");
+    let input = "4dQ + 1000d6 + 2d6 + @nope";
+    let report = validate_notation(input, &Limits::default(), Some(&schema));
+    assert!(!report.is_valid());
+    assert_eq!(report.metadata, None);
+
+    let found: Vec<(ErrorCode, &str)> = report.problems.iter()
+        .map(|problem| (problem.error.code, &input[problem.span.start..problem.span.end]))
+        .collect();
+    assert_eq!(found, vec![(ErrorCode::ParseError, "4dQ"), (ErrorCode::LimitExceeded, "1000d6"),
+                           (ErrorCode::UnknownVariable, "@nope")]);
+    assert_eq!(report.problems[2].span, Span { start: 21, end: 26 });
+}
+
+#[test]
+fn it_describes_a_valid_macro() {
+    let schema = VariableSchema::new().with("str").with("prof");
+    let report = validate_notation("1d20 +@str+ @prof + 2d6kh1 - 1", &Limits::default(), Some(&schema));
+    assert!(report.is_valid());
+
+    let metadata = report.metadata.unwrap();
+    assert_eq!(metadata.canonical, "1d20 + @str + @prof + 2d6kh1 - 1");
+    assert_eq!(metadata.variables, vec!["str", "prof"]);
+    assert_eq!(metadata.dice, 3);
+    assert_eq!((metadata.cost.dice_lower, metadata.cost.dice_upper_bound), (3, 3));
+
+    // Without a schema any variable goes
+    let report = validate_notation("1d20+@anything", &Limits::default(), None);
+    assert_eq!(report.metadata.unwrap().variables, vec!["anything"]);
+}
+
+#[test]
+fn it_lints_what_parses() {
+    let report = validate_notation("1d20 + 1d20 + 4dQ", &Limits::default(), None);
+    assert!(!report.is_valid());
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!(report.warnings[0].suggestion, Some("2d20kh1".to_string()));
+
+    let options = LintOptions::new().suppress("added-d20s");
+    assert_eq!(validate_notation_with("1d20 + 1d20", &Limits::default(), None, &options).warnings, vec![]);
+}
+
+#[test]
+fn it_lints_subtracted_terms_as_subtracted() {
+    let report = validate_notation("1d20-1d4", &Limits::default(), None);
+    assert!(report.is_valid());
+    assert_eq!(report.warnings, vec![]);
+
+    // A d20 taken away from another is a contest rather than advantage
+    assert_eq!(validate_notation("1d20 - 1d20", &Limits::default(), None).warnings, vec![]);
+
+    let report = validate_notation("1d6 - 1d20kh1", &Limits::default(), None);
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!((report.warnings[0].term, report.warnings[0].suggestion.clone()), (1, Some("1d6 - 1d20".to_string())));
+}
+
+#[test]
+fn it_points_warnings_at_the_term_they_came_from() {
+    // The term that doesn't parse is still counted
+    let report = validate_notation("4dQ + 1d20kh1", &Limits::default(), None);
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!((report.warnings[0].term, report.warnings[0].suggestion.clone()), (1, Some("1d20".to_string())));
+
+    // Numbers are counted too, and as they can't be written back there's no suggestion
+    let report = validate_notation("1d20 + 5 + 1d20kh1", &Limits::default(), None);
+    assert_eq!(report.warnings.len(), 1);
+    assert_eq!((report.warnings[0].term, report.warnings[0].suggestion.clone()), (2, None));
+}
+
+#[test]
+fn it_validates_without_rolling() {
+    let report = validate_notation("100d6!rr1 + 4d6kh3", &Limits::default(), None);
+    assert!(report.is_valid());
+    let metadata = report.metadata.clone().unwrap();
+    assert_eq!(metadata.dice, 104);
+    assert_eq!(metadata.cost.dice_lower, 104);
+    assert_eq!(metadata.cost.dice_upper_bound, 100 + 100 * MAX_REROLLS + 100 * MAX_EXPLOSIONS + 4);
+
+    // Nothing in the report comes from rolling, so checking again reports exactly the same
+    assert_eq!(validate_notation("100d6!rr1 + 4d6kh3", &Limits::default(), None), report);
+}
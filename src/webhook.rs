@@ -0,0 +1,510 @@
+use hmac::{Hmac, Mac};
+use http::{self, HttpError, HttpOptions, Url};
+use roll::Roll;
+use serde_json;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+#[cfg(test)]
+use std::io::{Read, Write};
+#[cfg(test)]
+use std::net::TcpListener;
+
+/// Header carrying the signature of a delivery made to a webhook with a secret
+pub const SIGNATURE_HEADER: &str = "X-Roll-Signature";
+
+/// A URL every roll in a room is posted to
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Webhook {
+    /// Where to post the rolls, an `http://` or `https://` URL of a public host
+    pub url: String,
+
+    /// Shared secret used to sign each delivery
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WebhookError {
+    /// The URL couldn't be understood
+    InvalidUrl,
+
+    /// The URL's host is loopback, private or link-local, which the server won't post to
+    PrivateHost,
+
+    /// The URL uses a scheme other than http or https
+    UnsupportedScheme,
+
+    /// The delivery couldn't be sent or the response couldn't be read
+    Io(String),
+
+    /// The receiver answered with a status other than 2xx
+    Status(u16),
+}
+
+impl From<HttpError> for WebhookError {
+    fn from(error: HttpError) -> WebhookError {
+        match error {
+            HttpError::InvalidUrl => WebhookError::InvalidUrl,
+            HttpError::PrivateHost(_) => WebhookError::PrivateHost,
+            HttpError::UnsupportedScheme => WebhookError::UnsupportedScheme,
+            HttpError::InvalidResponse(why) | HttpError::Io(why) => WebhookError::Io(why),
+        }
+    }
+}
+
+/// How often to try a delivery before giving up on it
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of tries, including the first
+    pub attempts: u32,
+
+    /// Wait before the second try, doubling before every try after it
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// How many deliveries are made at once, and how many can wait for a turn
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeliveryPool {
+    /// Threads making deliveries, each one at a time
+    pub workers: usize,
+
+    /// Deliveries that can wait for a thread, past which more are counted as dead letters straight away
+    pub queued: usize,
+
+    /// Where deliveries may go and how long they wait on the receiver
+    pub http: HttpOptions,
+}
+
+impl Default for DeliveryPool {
+    fn default() -> DeliveryPool {
+        DeliveryPool {
+            workers: 4,
+            queued: 256,
+            http: HttpOptions {
+                public_only: true,
+                ..HttpOptions::default()
+            },
+        }
+    }
+}
+
+/// Delivery counts of a room's webhooks
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct WebhookStatus {
+    /// Deliveries that were given up on after every try failed, or for want of room in the queue
+    pub dead_letters: u64,
+
+    /// Deliveries the receiver accepted
+    pub delivered: u64,
+
+    /// Number of webhooks registered for the room
+    pub webhooks: usize,
+}
+
+struct Rooms {
+    hooks: HashMap<String, Vec<Webhook>>,
+    status: HashMap<String, WebhookStatus>,
+}
+
+/// A roll on its way to one webhook
+struct Delivery {
+    body: String,
+    hook: Webhook,
+    room: String,
+}
+
+/// Deliveries queued or being made, which `Webhooks::flush` waits on
+type Pending = Arc<(Mutex<usize>, Condvar)>;
+
+/// The webhooks of every room, delivering rolls in the background
+///
+/// Deliveries are made by a fixed number of threads from a queue of bounded length, so a burst of
+/// rolls or a slow receiver can't start more threads or hold more deliveries than that.
+pub struct Webhooks {
+    http: HttpOptions,
+    pending: Pending,
+    queue: Mutex<SyncSender<Delivery>>,
+    rooms: Arc<Mutex<Rooms>>,
+}
+
+impl Webhooks {
+    pub fn new(retry: RetryPolicy) -> Webhooks {
+        Webhooks::with_pool(retry, DeliveryPool::default())
+    }
+
+    pub fn with_pool(retry: RetryPolicy, pool: DeliveryPool) -> Webhooks {
+        let (sender, receiver) = mpsc::sync_channel(pool.queued);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending: Pending = Arc::new((Mutex::new(0), Condvar::new()));
+        let rooms = Arc::new(Mutex::new(Rooms {
+            hooks: HashMap::new(),
+            status: HashMap::new(),
+        }));
+        for _ in 0..pool.workers.max(1) {
+            let (receiver, pending, rooms) = (receiver.clone(), pending.clone(), rooms.clone());
+            let (retry, options) = (retry.clone(), pool.http.clone());
+            thread::spawn(move || work(&receiver, &pending, &rooms, &retry, &options));
+        }
+
+        Webhooks {
+            http: pool.http,
+            pending,
+            queue: Mutex::new(sender),
+            rooms,
+        }
+    }
+
+    /// Post every roll made in the room to the webhook from now on
+    ///
+    /// Hosts known to be loopback, private or link-local are turned away here, and names that only
+    /// resolve to such addresses are never posted to.
+    pub fn register(&self, room: &str, webhook: Webhook) -> Result<(), WebhookError> {
+        let url = Url::parse(&webhook.url)?;
+        if self.http.public_only && url.is_private_host() {
+            return Err(WebhookError::PrivateHost);
+        }
+
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.hooks.entry(room.to_string()).or_insert_with(Vec::new).push(webhook);
+        rooms.status.entry(room.to_string()).or_insert_with(WebhookStatus::default).webhooks += 1;
+        Ok(())
+    }
+
+    /// Delivery counts of the room's webhooks, none if the room has no webhooks
+    pub fn status(&self, room: &str) -> Option<WebhookStatus> {
+        self.rooms.lock().unwrap().status.get(room).cloned()
+    }
+
+    /// Queue a finished roll to be posted to each of the room's webhooks as JSON
+    ///
+    /// This never waits on a receiver. Deliveries that don't fit in the queue are counted as dead
+    /// letters. Returns how many deliveries were queued.
+    pub fn notify(&self, room: &str, roll: &Roll) -> usize {
+        match serde_json::to_string(roll) {
+            Ok(body) => self.send(room, body),
+            Err(_) => 0,
+        }
+    }
+
+    /// Wait until every queued delivery has been made or given up on
+    pub fn flush(&self) {
+        let &(ref count, ref idle) = &*self.pending;
+        let mut count = count.lock().unwrap();
+        while *count > 0 {
+            count = idle.wait(count).unwrap();
+        }
+    }
+
+    fn send(&self, room: &str, body: String) -> usize {
+        let hooks = match self.rooms.lock().unwrap().hooks.get(room) {
+            Some(hooks) => hooks.clone(),
+            None => return 0,
+        };
+
+        let queue = self.queue.lock().unwrap();
+        let mut queued = 0;
+        for hook in hooks {
+            *self.pending.0.lock().unwrap() += 1;
+            let delivery = Delivery {
+                body: body.clone(),
+                hook,
+                room: room.to_string(),
+            };
+            match queue.try_send(delivery) {
+                Ok(()) => queued += 1,
+                Err(TrySendError::Full(delivery)) | Err(TrySendError::Disconnected(delivery)) => {
+                    finish(&self.pending, &self.rooms, &delivery.room, false);
+                },
+            }
+        }
+        queued
+    }
+}
+
+/// Make deliveries from the queue until the webhooks are dropped
+fn work(receiver: &Mutex<Receiver<Delivery>>, pending: &Pending, rooms: &Mutex<Rooms>, retry: &RetryPolicy,
+        options: &HttpOptions) {
+    loop {
+        // The lock is only held while waiting for a delivery, not while making it
+        let delivery = match receiver.lock().unwrap().recv() {
+            Ok(delivery) => delivery,
+            Err(_) => return,
+        };
+        let delivered = deliver_with_retries_over(&delivery.hook, &delivery.body, retry, options).is_ok();
+        finish(pending, rooms, &delivery.room, delivered);
+    }
+}
+
+/// Count a delivery as delivered or a dead letter, and no longer pending
+fn finish(pending: &Pending, rooms: &Mutex<Rooms>, room: &str, delivered: bool) {
+    {
+        let mut rooms = rooms.lock().unwrap();
+        let status = rooms.status.entry(room.to_string()).or_insert_with(WebhookStatus::default);
+        if delivered {
+            status.delivered += 1;
+        } else {
+            status.dead_letters += 1;
+        }
+    }
+    let &(ref count, ref idle) = &**pending;
+    let mut count = count.lock().unwrap();
+    *count -= 1;
+    if *count == 0 {
+        idle.notify_all();
+    }
+}
+
+/// Sign a delivery, written as `sha256=` and the lowercase hex HMAC-SHA256 of the body
+pub fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(body.as_bytes());
+    let hex: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    "sha256=".to_string() + &hex
+}
+
+/// Post the body to the webhook, trying again with a growing wait if it fails
+///
+/// Only public hosts are posted to, see `deliver`.
+pub fn deliver_with_retries(webhook: &Webhook, body: &str, retry: &RetryPolicy) -> Result<(), WebhookError> {
+    deliver_with_retries_over(webhook, body, retry, &DeliveryPool::default().http)
+}
+
+fn deliver_with_retries_over(webhook: &Webhook, body: &str, retry: &RetryPolicy, options: &HttpOptions)
+                             -> Result<(), WebhookError> {
+    let mut wait = retry.backoff;
+    let mut result = Err(WebhookError::Io("no attempts were made".to_string()));
+    for attempt in 0..retry.attempts {
+        if attempt > 0 {
+            thread::sleep(wait);
+            wait = wait.checked_mul(2).unwrap_or(wait);
+        }
+        result = deliver_over(webhook, body, options);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Post the body to the webhook once, to a public host with the default timeouts
+pub fn deliver(webhook: &Webhook, body: &str) -> Result<(), WebhookError> {
+    deliver_over(webhook, body, &DeliveryPool::default().http)
+}
+
+fn deliver_over(webhook: &Webhook, body: &str, options: &HttpOptions) -> Result<(), WebhookError> {
+    let url = Url::parse(&webhook.url)?;
+    let signature = webhook.secret.as_ref().map(|secret| signature(secret, body));
+    let mut headers = vec![("Content-Type", "application/json")];
+    if let Some(ref signature) = signature {
+        headers.push((SIGNATURE_HEADER, signature.as_str()));
+    }
+
+    let response = http::request(&url, "POST", &url.path, &headers, body.as_bytes(), options)?;
+    if response.is_success() {
+        Ok(())
+    } else {
+        Err(WebhookError::Status(response.status))
+    }
+}
+
+/// Split an `http://host[:port][/path]` URL into its host, port and path
+///
+/// Only plain `http://` URLs are accepted, see `http::Url::parse` for `https://` too.
+pub fn parse_url(url: &str) -> Result<(String, u16, String), WebhookError> {
+    let url = Url::parse(url)?;
+    if url.scheme != http::Scheme::Http {
+        return Err(WebhookError::UnsupportedScheme);
+    }
+    Ok((url.host, url.port, url.path))
+}
+
+/// A request received by the mock receiver
+#[cfg(test)]
+struct Received {
+    body: String,
+    headers: Vec<(String, String)>,
+}
+
+/// Answer the given number of requests with the status, handing back each request received
+#[cfg(test)]
+fn mock_receiver(status: u16, requests: usize) -> (String, mpsc::Receiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/rolls", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut data = vec![];
+            let mut buf = [0u8; 1024];
+
+            // Read up to the end of the headers, then the rest of the body
+            let (head, mut body) = loop {
+                let read = stream.read(&mut buf).unwrap();
+                data.extend_from_slice(&buf[..read]);
+                let text = String::from_utf8_lossy(&data).to_string();
+                if let Some(index) = text.find("\r\n\r\n") {
+                    break (text[..index].to_string(), text[index + 4..].to_string());
+                }
+            };
+            let headers: Vec<(String, String)> = head.lines().skip(1).filter_map(|line| {
+                line.find(": ").map(|index| (line[..index].to_string(), line[index + 2..].to_string()))
+            }).collect();
+            let length = headers.iter()
+                .find(|&&(ref name, _)| name == "Content-Length")
+                .map_or(0, |&(_, ref value)| value.parse::<usize>().unwrap());
+            while body.len() < length {
+                let read = stream.read(&mut buf).unwrap();
+                body.push_str(&String::from_utf8_lossy(&buf[..read]));
+            }
+
+            write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            sender.send(Received {
+                body,
+                headers,
+            }).unwrap();
+        }
+    });
+    (url, receiver)
+}
+
+#[cfg(test)]
+fn header<'a>(received: &'a Received, name: &str) -> Option<&'a str> {
+    received.headers.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref value)| value.as_str())
+}
+
+/// Deliveries that can go to the mock receiver, which listens on loopback
+#[cfg(test)]
+fn local_pool() -> DeliveryPool {
+    DeliveryPool {
+        http: HttpOptions::default(),
+        ..DeliveryPool::default()
+    }
+}
+
+#[test]
+fn it_delivers_to_a_webhook() {
+    let (url, received) = mock_receiver(204, 1);
+    let webhooks = Webhooks::with_pool(RetryPolicy::default(), local_pool());
+    webhooks.register("table", Webhook {
+        url,
+        secret: None,
+    }).unwrap();
+
+    assert_eq!(webhooks.send("table", "{\"value\":17}".to_string()), 1);
+    webhooks.flush();
+    let request = received.recv().unwrap();
+    assert_eq!(request.body, "{\"value\":17}");
+    assert_eq!(header(&request, "Content-Type"), Some("application/json"));
+    assert_eq!(header(&request, SIGNATURE_HEADER), None);
+    assert_eq!(webhooks.status("table"), Some(WebhookStatus {
+        dead_letters: 0,
+        delivered: 1,
+        webhooks: 1,
+    }));
+
+    // Rooms without webhooks never deliver anything
+    assert_eq!(webhooks.send("attic", "{}".to_string()), 0);
+    assert_eq!(webhooks.status("attic"), None);
+}
+
+#[test]
+fn it_signs_deliveries() {
+    assert_eq!(
+        signature("key", "The quick brown fox jumps over the lazy dog"),
+        "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+    );
+
+    let (url, received) = mock_receiver(200, 1);
+    let webhook = Webhook {
+        url,
+        secret: Some("shh".to_string()),
+    };
+    assert_eq!(deliver_over(&webhook, "{\"value\":3}", &local_pool().http), Ok(()));
+    let request = received.recv().unwrap();
+    assert_eq!(header(&request, SIGNATURE_HEADER), Some(signature("shh", &request.body).as_str()));
+
+    // Outside of tests the receiver's loopback address is never posted to
+    assert_eq!(deliver(&webhook, "{}"), Err(WebhookError::PrivateHost));
+}
+
+#[test]
+fn it_gives_up_after_retrying() {
+    let (url, received) = mock_receiver(500, 2);
+    let webhooks = Webhooks::with_pool(RetryPolicy {
+        attempts: 2,
+        backoff: Duration::from_millis(1),
+    }, local_pool());
+    webhooks.register("table", Webhook {
+        url,
+        secret: None,
+    }).unwrap();
+
+    webhooks.send("table", "{}".to_string());
+    webhooks.flush();
+    assert_eq!(received.iter().take(2).count(), 2);
+    let status = webhooks.status("table").unwrap();
+    assert_eq!((status.delivered, status.dead_letters), (0, 1));
+}
+
+#[test]
+fn it_counts_deliveries_past_the_queue_as_dead_letters() {
+    // The receiver never answers, so the one worker holds a delivery until its read times out
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hooks/rolls", listener.local_addr().unwrap());
+    let webhooks = Webhooks::with_pool(RetryPolicy {
+        attempts: 1,
+        backoff: Duration::from_millis(1),
+    }, DeliveryPool {
+        workers: 1,
+        queued: 1,
+        http: HttpOptions {
+            read_timeout: Duration::from_millis(200),
+            ..HttpOptions::default()
+        },
+    });
+    for _ in 0..10 {
+        webhooks.register("table", Webhook {
+            url: url.clone(),
+            secret: None,
+        }).unwrap();
+    }
+
+    let queued = webhooks.send("table", "{}".to_string());
+    assert!(queued >= 1 && queued <= 2, "{} queued", queued);
+    webhooks.flush();
+    let status = webhooks.status("table").unwrap();
+    assert_eq!((status.delivered, status.dead_letters), (0, 10));
+}
+
+#[test]
+fn it_rejects_bad_webhook_urls() {
+    let webhooks = Webhooks::new(RetryPolicy::default());
+    let register = |url: &str| webhooks.register("table", Webhook {
+        url: url.to_string(),
+        secret: None,
+    });
+    assert_eq!(register("ftp://logs.example.com/rolls"), Err(WebhookError::UnsupportedScheme));
+    assert_eq!(register("discord.com"), Err(WebhookError::InvalidUrl));
+    assert_eq!(register("http://logs.example.com:port/"), Err(WebhookError::InvalidUrl));
+    assert_eq!(register("http://logs.example.com"), Ok(()));
+
+    // Nothing inside the server's own network can be reached through a webhook
+    assert_eq!(register("http://localhost"), Err(WebhookError::PrivateHost));
+    assert_eq!(register("http://169.254.169.254/latest/meta-data"), Err(WebhookError::PrivateHost));
+    assert_eq!(register("http://[::1]:8000/"), Err(WebhookError::PrivateHost));
+    if cfg!(feature = "tls") {
+        assert_eq!(register("https://discord.com/api/webhooks/1"), Ok(()));
+    }
+}
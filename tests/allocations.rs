@@ -0,0 +1,100 @@
+//! Counting the allocations a roll makes, so rolling lots of dice stays cheap
+//!
+//! Each die of an `8d6` used to allocate five times: its ID grew as it was written, and the roll's
+//! list of dice grew as they were added. Now each die allocates twice, once for its ID and once for
+//! its history, 2.5 times fewer. The list of dice is sized up front rather than kept in a
+//! `SmallVec`, and dice with custom sides still copy them, so `Roll::dice` and `Die::sides` keep
+//! their types.
+
+extern crate roll_api;
+
+use roll_api::format::{RollFormatter, SummaryFormatter, VerboseFormatter};
+use roll_api::locale::English;
+use roll_api::parser::parse;
+use roll_api::roll::Roll;
+use roll_api::roller::Roller;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Allocations each die of an `8d6` made before they were cut down
+const PER_DIE_BEFORE: usize = 5;
+
+/// The system allocator, counting allocations made by each thread
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.try_with(|count| count.set(count.get() + 1)).ok();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.try_with(|count| count.set(count.get() + 1)).ok();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+/// Allocations made rolling the notation once, not counting making its flags or the roller
+fn allocations(notation: &str) -> usize {
+    let flags = parse(notation).unwrap().pop().unwrap();
+    let mut roller = Roller::seeded(8);
+    let before = ALLOCATIONS.with(|count| count.get());
+    let roll = Roll::with_roller(flags, &mut roller);
+    let after = ALLOCATIONS.with(|count| count.get());
+    drop(roll);
+    after - before
+}
+
+#[test]
+fn it_allocates_twice_per_die() {
+    // Warm up anything allocated once per thread, e.g. the clock
+    allocations("8d6");
+
+    // Comparing 8 and 16 dice leaves out what every roll allocates, e.g. its own ID
+    let per_die = (allocations("16d6") - allocations("8d6")) / 8;
+    assert!(per_die * 5 <= PER_DIE_BEFORE * 2, "each die allocated {} times, down from {}", per_die, PER_DIE_BEFORE);
+
+    // Ranking dice to drop reuses the roller's buffer
+    let flags = parse("8d6dh1dl1").unwrap().pop().unwrap();
+    let mut roller = Roller::seeded(8);
+    Roll::with_roller(flags.clone(), &mut roller);
+    let before = ALLOCATIONS.with(|count| count.get());
+    Roll::with_roller(flags, &mut roller);
+    let again = ALLOCATIONS.with(|count| count.get()) - before;
+    assert!(again <= allocations("8d6"), "dropping dice allocated {} times", again);
+}
+
+#[test]
+fn it_formats_into_a_buffer_without_allocating() {
+    let roll = Roll::with_roller(parse("8d6kh3+2").unwrap().pop().unwrap(), &mut Roller::seeded(8));
+    let mut buffer = String::with_capacity(256);
+
+    let before = ALLOCATIONS.with(|count| count.get());
+    for _ in 0..100 {
+        buffer.clear();
+        SummaryFormatter.format_into(&roll, &mut buffer).unwrap();
+    }
+    let summary = ALLOCATIONS.with(|count| count.get()) - before;
+    assert_eq!(summary, 0, "formatting into a buffer allocated {} times", summary);
+    assert_eq!(buffer, SummaryFormatter.format(&roll));
+
+    // Sentences are written straight to the buffer, only each die's text is built on its own, a
+    // few allocations for what happened to it
+    let verbose = VerboseFormatter::new(English);
+    let before = ALLOCATIONS.with(|count| count.get());
+    buffer.clear();
+    verbose.format_into(&roll, &mut buffer).unwrap();
+    let sentences = ALLOCATIONS.with(|count| count.get()) - before;
+    assert!(sentences <= 4 * roll.dice.len(), "verbose formatting allocated {} times", sentences);
+}
@@ -0,0 +1,148 @@
+//! The client against the real API, served in-process on a port of its own
+#![cfg(feature = "client")]
+
+extern crate rocket;
+extern crate roll_api;
+extern crate tokio;
+
+use roll_api::client::{Backoff, ClientError, RollApiClient};
+use roll_api::error::ErrorCode;
+use roll_api::history::HistoryQuery;
+use roll_api::rate_limit::{MemoryLimiter, RateLimits, TokenBucket};
+use std::env;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder, Runtime};
+
+/// Rocket reads its address and port from the environment as it's built, so servers are built one
+/// at a time
+static LAUNCHING: Mutex<()> = Mutex::new(());
+
+/// Launch the API on a free port, handing back its base URL once it's taking requests
+fn serve<F: FnOnce() -> rocket::Rocket + Send + 'static>(build: F) -> String {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    thread::spawn(move || {
+        let rocket = {
+            let _launching = LAUNCHING.lock().unwrap();
+            env::set_var("ROCKET_ADDRESS", "127.0.0.1");
+            env::set_var("ROCKET_PORT", port.to_string());
+            build()
+        };
+        rocket.launch();
+    });
+
+    let started = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(10), "the API should launch");
+        thread::sleep(Duration::from_millis(20));
+    }
+    format!("http://127.0.0.1:{}", port)
+}
+
+/// A server that lets each token make one request a second
+fn serve_rate_limited() -> String {
+    serve(|| {
+        let limiter = MemoryLimiter::new(TokenBucket {
+            capacity: 1,
+            refill_per_second: 1,
+        });
+        roll_api::rocket().manage(RateLimits::new(Box::new(limiter)))
+    })
+}
+
+fn runtime() -> Runtime {
+    Builder::new_multi_thread().worker_threads(2).enable_all().build().unwrap()
+}
+
+/// Make a request the client has no method for, e.g. a room roll
+fn get(base_url: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(&base_url["http://".len()..]).unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn it_calls_every_endpoint() {
+    let runtime = runtime();
+    let base_url = serve(roll_api::rocket);
+    let client = RollApiClient::new(&base_url, "bot-token").unwrap();
+
+    let roll = runtime.block_on(client.roll("3d6+2 \"damage\"")).unwrap();
+    assert!(roll.value >= 5 && roll.value <= 20);
+    assert_eq!(roll.dice.len(), 3);
+
+    let rolls = runtime.block_on(client.roll_batch(&["1d20", "4d6kh3"])).unwrap();
+    assert_eq!(rolls.iter().map(|roll| roll.dice.len()).collect::<Vec<usize>>(), vec![1, 4]);
+
+    assert!(runtime.block_on(client.validate("1d20+5")).unwrap().is_valid());
+    assert!(!runtime.block_on(client.validate("1d20+")).unwrap().is_valid());
+
+    let distribution = runtime.block_on(client.stats("2d6")).unwrap();
+    assert!((distribution.probabilities[&7] - 6.0 / 36.0).abs() < 1e-9);
+
+    assert!(get(&base_url, "/v1/rooms/tavern/sam/1d20").starts_with("HTTP/1.1 200"));
+    let page = runtime.block_on(client.history("tavern", &HistoryQuery::default())).unwrap();
+    assert_eq!(page.rolls.len(), 1);
+    assert_eq!(page.rolls[0].participant, "sam");
+}
+
+#[test]
+fn it_decodes_the_error_envelope() {
+    let runtime = runtime();
+    let client = RollApiClient::new(&serve(roll_api::rocket), "bot-token").unwrap();
+
+    match runtime.block_on(client.roll("4dFkh2")) {
+        Err(ClientError::Api { status, error }) => {
+            assert_eq!((status, error.code), (400, ErrorCode::UnsupportedModifier));
+            assert_eq!(error.details["modifier"], "kh");
+            assert_eq!(error.message, "The kh flag can't be used on Fate dice.");
+        },
+        other => panic!("expected an API error, got {:?}", other.map(|roll| roll.equation)),
+    }
+
+    match runtime.block_on(client.history("empty", &HistoryQuery::default())) {
+        Err(ClientError::Api { status, error }) => assert_eq!((status, error.code), (404, ErrorCode::NotFound)),
+        _ => panic!("a room with no rolls should be an API error"),
+    }
+}
+
+#[test]
+fn it_waits_out_the_rate_limit() {
+    let runtime = runtime();
+    let base_url = serve_rate_limited();
+
+    // With no retries the 429 comes straight back, with how long to wait
+    let impatient = RollApiClient::new(&base_url, "impatient").unwrap().with_backoff(Backoff {
+        max_retries: 0,
+        ..Backoff::default()
+    });
+    runtime.block_on(impatient.roll("1d20")).unwrap();
+    match runtime.block_on(impatient.roll("1d20")) {
+        Err(ClientError::Api { status, error }) => {
+            assert_eq!((status, error.code), (429, ErrorCode::RateLimited));
+            assert_eq!(error.details["retry_after"], 1);
+        },
+        _ => panic!("the second roll should be turned away"),
+    }
+
+    // A wait longer than the client allows is handed back too, however many retries are left
+    let hasty = RollApiClient::new(&base_url, "hasty").unwrap().with_backoff(Backoff {
+        max_wait: Duration::from_millis(100),
+        ..Backoff::default()
+    });
+    runtime.block_on(hasty.roll("1d20")).unwrap();
+    assert!(runtime.block_on(hasty.roll("1d20")).is_err());
+
+    // Otherwise the client waits for the Retry-After and tries again
+    let patient = RollApiClient::new(&base_url, "patient").unwrap();
+    let started = Instant::now();
+    let rolls = runtime.block_on(patient.roll_batch(&["1d20", "1d6", "1d4"])).unwrap();
+    assert_eq!(rolls.len(), 3);
+    assert!(started.elapsed() >= Duration::from_secs(2));
+}
@@ -0,0 +1,59 @@
+//! The default roller behind `roll_api::roll`, in a binary of its own as setting it changes how
+//! every test in the binary rolls
+
+extern crate roll_api;
+
+use roll_api::limits::Limits;
+use roll_api::roll::{Roll, RollError};
+use roll_api::roller::DefaultRoller;
+use std::sync::Mutex;
+use std::thread;
+
+/// Held by each test while it has the default swapped out, so they don't see each other's
+static TURN: Mutex<()> = Mutex::new(());
+
+fn dice(roll: &Roll) -> Vec<i16> {
+    roll.dice.iter().map(|die| die.value).collect()
+}
+
+#[test]
+fn it_rolls_the_same_dice_from_any_number_of_threads() {
+    let _turn = TURN.lock().unwrap();
+    roll_api::set_default_roller(DefaultRoller::new().seeded(7));
+    let threads: Vec<_> = (0..4).map(|_| thread::spawn(|| {
+        (0..25).map(|_| roll_api::roll("3d6").unwrap()).collect::<Vec<Roll>>()
+    })).collect();
+    let mut threaded: Vec<(u64, Vec<i16>)> = threads.into_iter()
+        .flat_map(|rolling| rolling.join().unwrap())
+        .map(|roll| (roll.nonce.unwrap(), dice(&roll)))
+        .collect();
+    threaded.sort();
+
+    // Each roll is seeded by its index, so the threads took turns at one sequence of rolls
+    roll_api::set_default_roller(DefaultRoller::new().seeded(7));
+    let one_by_one: Vec<(u64, Vec<i16>)> = (0..100)
+        .map(|_| roll_api::roll("3d6").unwrap())
+        .map(|roll| (roll.nonce.unwrap(), dice(&roll)))
+        .collect();
+    assert_eq!(threaded, one_by_one);
+    assert_eq!(threaded.iter().map(|&(nonce, _)| nonce).collect::<Vec<u64>>(), (0..100).collect::<Vec<u64>>());
+
+    roll_api::reset_default_roller();
+    assert_eq!(roll_api::roll("3d6").unwrap().nonce, None);
+}
+
+#[test]
+fn it_keeps_rolling_with_a_default_after_its_replaced() {
+    let _turn = TURN.lock().unwrap();
+    roll_api::set_default_roller(DefaultRoller::new().seeded(7));
+    let held = roll_api::default_roller();
+    assert_eq!(held.roll("3d6").unwrap().nonce, Some(0));
+
+    let restricted = Limits { max_dice: 2, ..Limits::default() };
+    roll_api::set_default_roller(DefaultRoller::new().limits(restricted));
+    assert_eq!(roll_api::roll("3d6").err(), Some(RollError::TooManyDice(3, 2)));
+    assert_eq!(held.roll("3d6").unwrap().nonce, Some(1));
+
+    roll_api::reset_default_roller();
+    assert!(roll_api::roll("3d6").is_ok());
+}
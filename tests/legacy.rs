@@ -0,0 +1,84 @@
+//! The `Die` API as it was before rolls went through a `Roller`, pinned so code written against it
+//! keeps compiling and rolling the same, apart from the fixes listed in the changelog
+#![allow(deprecated)]
+
+extern crate roll_api;
+extern crate serde_json;
+
+use roll_api::die::{Die, DieType};
+
+#[test]
+fn it_creates_dice_with_the_same_public_fields() {
+    let die: Die = Die::new(DieType::D20);
+    let id: &String = &die.id;
+    let child: &Option<String> = &die.child;
+    let sides: &Option<Vec<i16>> = &die.sides;
+    let flags: (bool, bool, bool) = (die.is_dropped, die.is_rerolled, die.is_successful);
+    let (min, max, value): (i16, i16, i16) = (die.min, die.max, die.value);
+
+    assert!(!id.is_empty());
+    assert_eq!((child, sides), (&None, &None));
+    assert_eq!(flags, (false, false, false));
+    assert_eq!((min, max, value), (1, 20, 0));
+    assert_eq!(die.die, DieType::D20);
+    assert_eq!((Die::new(DieType::Other).min, Die::new(DieType::Other).max), (0, 0));
+}
+
+#[test]
+fn it_rolls_by_reference() {
+    let mut die = Die::new(DieType::D4);
+    let mut seen = [false; 4];
+    for _ in 0..1000 {
+        let rolled: &Die = die.roll();
+        assert!(rolled.is_successful);
+        seen[(rolled.value - 1) as usize] = true;
+    }
+
+    // The highest face used to be left out, which is fixed
+    assert_eq!(seen, [true; 4]);
+}
+
+#[test]
+fn it_sets_the_min_and_max() {
+    let mut custom = Die::new(DieType::Other);
+    let () = custom.set_max(-5);
+    let () = custom.set_min(-8);
+    assert_eq!(custom.die, DieType::Other);
+    for _ in 0..100 {
+        let value = custom.roll().value;
+        assert!(value >= -8 && value <= -5);
+    }
+}
+
+#[test]
+fn it_rolls_sides_assigned_directly() {
+    let mut die = Die::new(DieType::Other);
+    die.sides = Some(vec![2, 4, 6]);
+    for _ in 0..100 {
+        assert!([2, 4, 6].contains(&die.roll().value));
+    }
+
+    // The min and max now follow the sides, which is fixed
+    assert_eq!((die.min, die.max), (2, 6));
+}
+
+#[test]
+fn it_serializes_with_the_same_field_names() {
+    let mut die = Die::new(DieType::D6);
+    die.roll();
+    let json = serde_json::to_value(&die).unwrap();
+    for field in &["id", "child", "die", "is_dropped", "is_rerolled", "is_successful", "max", "min", "sides",
+                   "timestamp", "value"] {
+        assert!(json.get(field).is_some(), "{} is missing", field);
+    }
+    assert_eq!(json["die"], "D6");
+
+    // Dice serialized before any fields were added still read
+    let old = r#"{
+        "id": "1", "child": null, "die": "D20", "is_dropped": false, "is_rerolled": false,
+        "is_successful": true, "max": 20, "min": 1, "sides": null,
+        "timestamp": "2017-11-01T00:00:00Z", "value": 17
+    }"#;
+    let die: Die = serde_json::from_str(old).unwrap();
+    assert_eq!((die.die, die.value), (DieType::D20, 17));
+}
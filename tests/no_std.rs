@@ -0,0 +1,59 @@
+//! Rolling with `eval` from a crate with no standard library, the way firmware would, with a
+//! seeded generator of its own
+//!
+//! Run with `cargo test --test no_std --no-default-features --features core-nostd`, so the library
+//! it rolls with is built without the standard library too. Check the library itself builds for
+//! such devices with
+//! `cargo check --lib --no-default-features --features core-nostd --target thumbv7em-none-eabihf`.
+#![cfg(not(feature = "std"))]
+#![no_std]
+
+extern crate rand_core;
+extern crate roll_api;
+
+use rand_core::{impls, Error, RngCore};
+use roll_api::eval::{self, DieType, EvalError, Expression};
+
+/// Xorshift, small enough for a microcontroller and seeded by the caller
+struct Xorshift(u32);
+
+impl RngCore for Xorshift {
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_u32(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[test]
+fn it_rolls_4d6kh3_with_a_seeded_generator() {
+    let rolled = eval::roll("4d6kh3", &mut Xorshift(2463534242)).unwrap();
+    assert_eq!(rolled.dice.len(), 4);
+    assert_eq!(rolled.dice.iter().filter(|die| die.is_dropped).count(), 1);
+    assert!(rolled.dice.iter().all(|die| die.die == DieType::D6 && die.value >= 1 && die.value <= 6));
+    assert!(rolled.total >= 3 && rolled.total <= 18);
+
+    // The dropped die is one of the lowest
+    let lowest = rolled.dice.iter().map(|die| die.value).min().unwrap();
+    assert!(rolled.dice.iter().any(|die| die.is_dropped && die.value == lowest));
+
+    // The same seed rolls the same dice
+    let expression = Expression::parse("4d6kh3").unwrap();
+    let again = expression.roll(&mut Xorshift(2463534242));
+    assert_eq!(again, rolled);
+    assert_eq!(Expression::parse("4d6x"), Err(EvalError::InvalidNotation));
+}
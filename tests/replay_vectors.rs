@@ -0,0 +1,77 @@
+//! Seeds and the dice they roll, which have to come out the same on every target
+//!
+//! Run natively, on a 32-bit target and on WASM with `scripts/replay-vectors.sh`, which builds
+//! the library without the `server` feature, as Rocket doesn't build for WASM. The vectors are read
+//! with `include_str!`, as WASM has no files to read them from.
+
+extern crate roll_api;
+extern crate serde_json;
+#[cfg(target_arch = "wasm32")]
+extern crate wasm_bindgen_test;
+
+use roll_api::fairness::SeedRecipe;
+use roll_api::parser::parse;
+use roll_api::roll::{Roll, RollFlags};
+use roll_api::roller::{Roller, REPLAY_EPOCH};
+use serde_json::Value;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test;
+
+const VECTORS: &str = include_str!("golden/replay-vectors.json");
+
+fn vectors() -> Value {
+    serde_json::from_str(VECTORS).expect("replay vectors should be JSON")
+}
+
+fn flags(vector: &Value) -> RollFlags {
+    parse(vector["notation"].as_str().unwrap()).unwrap().pop().unwrap()
+}
+
+fn expected(vector: &Value) -> Vec<i64> {
+    vector["dice"].as_array().unwrap().iter().map(|value| value.as_i64().unwrap()).collect()
+}
+
+fn rolled(roll: &Roll) -> Vec<i64> {
+    roll.dice.iter().map(|die| die.value as i64).collect()
+}
+
+fn server(hex: &str) -> [u8; 32] {
+    let mut server = [0u8; 32];
+    for (i, byte) in server.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    server
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn it_pins_the_current_epoch() {
+    // A new epoch rolls different dice, so it comes with new vectors
+    assert_eq!(vectors()["epoch"].as_u64(), Some(REPLAY_EPOCH as u64));
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn it_rolls_the_same_dice_for_each_seed() {
+    for vector in vectors()["seeded"].as_array().unwrap() {
+        let seed = vector["seed"].as_u64().unwrap();
+        let roll = Roll::with_roller(flags(vector), &mut Roller::seeded(seed));
+        assert_eq!(rolled(&roll), expected(vector), "{} seeded with {}", vector["notation"], seed);
+        assert_eq!(roll.replay_epoch, Some(REPLAY_EPOCH));
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+fn it_rolls_the_same_dice_for_each_derived_seed() {
+    for vector in vectors()["derived"].as_array().unwrap() {
+        let recipe = SeedRecipe {
+            server: server(vector["server"].as_str().unwrap()),
+            client: vector["client"].as_str().unwrap().to_string(),
+            nonce: vector["nonce"].as_u64().unwrap(),
+        };
+        let roll = Roll::with_roller(flags(vector), &mut Roller::derived(recipe));
+        assert_eq!(rolled(&roll), expected(vector), "{} at nonce {}", vector["notation"], vector["nonce"]);
+        assert_eq!(roll.replay_epoch, Some(REPLAY_EPOCH));
+    }
+}